@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use runwasi_bench::{runtime_name, Bench, Sample, DEFAULT_ADDRESS, DEFAULT_IMAGE};
+use tokio::runtime::Runtime;
+
+/*
+    Runs the tasks of the runtimes of `RUNWASI_BENCH_RUNTIMES`, e.g., `wasmtime,wasmedge`, on the
+    containerd of `RUNWASI_BENCH_ADDRESS`, with the image of `RUNWASI_BENCH_IMAGE` in its default
+    namespace. The shims must be installed, and the image imported, e.g., with `make install load`.
+
+    Each measure of a task is a benchmark, e.g., `wasmtime/create`, so that criterion compares it
+    to the previous run. The benchmarks are skipped when containerd isn't reachable.
+*/
+
+// a measure of a task, in milliseconds, if the task measured it
+type Measure = fn(&Sample) -> Option<f64>;
+
+fn env_or(var: &str, default: &str) -> String {
+    std::env::var(var).unwrap_or_else(|_| default.to_string())
+}
+
+fn benchmark_shims(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let address = env_or("RUNWASI_BENCH_ADDRESS", DEFAULT_ADDRESS);
+    let bench = match rt.block_on(Bench::connect(address, "default")) {
+        Ok(bench) => bench,
+        Err(err) => {
+            eprintln!("skipping the benchmarks of the shims: {err:#}");
+            return;
+        }
+    };
+    let image = env_or("RUNWASI_BENCH_IMAGE", DEFAULT_IMAGE);
+    let args = ["/wasi-demo-app.wasm", "echo", "hello"].map(String::from);
+
+    let measures: [(&str, Measure); 4] = [
+        ("create", |s| Some(s.create_ms)),
+        ("start", |s| Some(s.start_ms)),
+        ("first-byte", |s| s.first_byte_ms),
+        ("exit", |s| Some(s.exit_ms)),
+    ];
+
+    let mut group = c.benchmark_group("shims");
+    group.sample_size(10);
+    for name in env_or("RUNWASI_BENCH_RUNTIMES", "wasmtime").split(',') {
+        let runtime = runtime_name(name.trim());
+        for (measure, value) in measures {
+            group.bench_function(format!("{name}/{measure}"), |b| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|i| {
+                            let id = format!("runwasi-bench-{}-{i}", std::process::id());
+                            let sample = rt
+                                .block_on(bench.run(&id, &runtime, &image, &args))
+                                .unwrap();
+                            let millis = value(&sample).expect("no output from the task");
+                            Duration::from_secs_f64(millis / 1000.0)
+                        })
+                        .sum()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(30));
+    targets = benchmark_shims
+}
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use runwasi_bench::{regressions, runtime_name, Bench, Report, DEFAULT_ADDRESS, DEFAULT_IMAGE};
+
+/// Measures the latencies and the memory of the tasks of the shims on a local containerd.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// The socket of containerd
+    #[arg(short, long, default_value = DEFAULT_ADDRESS)]
+    address: PathBuf,
+
+    /// The namespace the image is in
+    #[arg(short, long, default_value = "default")]
+    namespace: String,
+
+    /// The runtimes to compare, e.g., `wasmtime` or `io.containerd.wasmtime.v1`
+    #[arg(short, long, default_values = ["wasmtime", "wasmedge", "wasmer"])]
+    runtime: Vec<String>,
+
+    /// The image of the tasks, which must be pulled or imported
+    #[arg(short, long, default_value = DEFAULT_IMAGE)]
+    image: String,
+
+    /// The number of tasks run with each runtime
+    #[arg(short = 'c', long, default_value_t = 10)]
+    iterations: usize,
+
+    /// Writes the reports to this JSON file rather than to stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// A JSON file with the reports of a previous run to compare the medians to
+    #[arg(short, long)]
+    baseline: Option<PathBuf>,
+
+    /// The growth of a median over the baseline that fails the run, e.g., 0.1 for 10%
+    #[arg(short, long, default_value_t = 0.1)]
+    threshold: f64,
+
+    /// The command of the tasks
+    #[arg(last = true, default_values = ["/wasi-demo-app.wasm", "echo", "hello"])]
+    args: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let bench = Bench::connect(&args.address, &args.namespace).await?;
+    let mut reports = vec![];
+    for runtime in args.runtime.iter().map(|runtime| runtime_name(runtime)) {
+        let mut samples = vec![];
+        for i in 0..args.iterations {
+            let id = format!("runwasi-bench-{}-{i}", std::process::id());
+            match bench.run(&id, &runtime, &args.image, &args.args).await {
+                Ok(sample) => samples.push(sample),
+                Err(err) => eprintln!("{runtime}: {err:#}"),
+            }
+        }
+        eprintln!(
+            "{runtime}: {} of {} tasks ran",
+            samples.len(),
+            args.iterations
+        );
+        reports.push(Report::new(runtime, &args.image, samples));
+    }
+
+    match &args.output {
+        Some(path) => serde_json::to_writer_pretty(File::create(path)?, &reports)?,
+        None => println!("{}", serde_json::to_string_pretty(&reports)?),
+    }
+
+    if let Some(path) = &args.baseline {
+        let file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+        let baseline: Vec<Report> = serde_json::from_reader(file)?;
+        let regressions = regressions(&baseline, &reports, args.threshold);
+        for regression in &regressions {
+            eprintln!("regression: {regression}");
+        }
+        if !regressions.is_empty() {
+            bail!("{} regressions from {path:?}", regressions.len());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,461 @@
+//! Benchmarks of the shims against a local containerd, to compare the engines and to catch the
+//! regressions of the sandbox code they share.
+//!
+//! For each runtime, e.g., `io.containerd.wasmtime.v1`, a container of the image is created with
+//! `ctr`, and its task is created, started, waited for and deleted with the Task API of
+//! containerd, as for the containers of a pod. Each run measures:
+//!  - `create`: the creation of the task, i.e., starting the shim and creating the container.
+//!  - `start`: the start of the task.
+//!  - `first_byte`: the time from the start of the task to the first byte of its stdout.
+//!  - `exit`: the time from the start of the task to its exit.
+//!  - `delete`: the deletion of the task, i.e., the deletion of the container and the shim.
+//!  - `peak_rss_kib`: the peak resident memory of the process of the task, when it runs long
+//!    enough to be sampled.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use containerd_client::services::v1::containers_client::ContainersClient;
+use containerd_client::services::v1::snapshots::snapshots_client::SnapshotsClient;
+use containerd_client::services::v1::snapshots::MountsRequest;
+use containerd_client::services::v1::tasks_client::TasksClient;
+use containerd_client::services::v1::{
+    CreateTaskRequest, DeleteTaskRequest, GetContainerRequest, StartRequest, WaitRequest,
+};
+use containerd_client::tonic::transport::Channel;
+use containerd_client::tonic::Request;
+use containerd_client::with_namespace;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_ADDRESS: &str = "/run/containerd/containerd.sock";
+pub const DEFAULT_IMAGE: &str = "ghcr.io/containerd/runwasi/wasi-demo-app:latest";
+
+// How often the memory of the process of a task is sampled
+const RSS_SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The measures of a run of a task, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub create_ms: f64,
+    pub start_ms: f64,
+    pub first_byte_ms: Option<f64>,
+    pub exit_ms: f64,
+    pub delete_ms: f64,
+    pub exit_status: u32,
+    pub peak_rss_kib: Option<u64>,
+}
+
+/// The distribution of a measure over the runs of a runtime
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    /// Returns the distribution of `values`, if there are any
+    pub fn of(values: impl IntoIterator<Item = f64>) -> Option<Self> {
+        let mut values: Vec<f64> = values.into_iter().collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let percentile = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+        Some(Self {
+            min: values[0],
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+/// The results of the runs of a runtime
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub runtime: String,
+    pub image: String,
+    pub create_ms: Option<Stats>,
+    pub start_ms: Option<Stats>,
+    pub first_byte_ms: Option<Stats>,
+    pub exit_ms: Option<Stats>,
+    pub delete_ms: Option<Stats>,
+    pub peak_rss_kib: Option<Stats>,
+    pub samples: Vec<Sample>,
+}
+
+impl Report {
+    pub fn new(runtime: impl Into<String>, image: impl Into<String>, samples: Vec<Sample>) -> Self {
+        let stats =
+            |measure: fn(&Sample) -> Option<f64>| Stats::of(samples.iter().filter_map(measure));
+        Self {
+            runtime: runtime.into(),
+            image: image.into(),
+            create_ms: stats(|s| Some(s.create_ms)),
+            start_ms: stats(|s| Some(s.start_ms)),
+            first_byte_ms: stats(|s| s.first_byte_ms),
+            exit_ms: stats(|s| Some(s.exit_ms)),
+            delete_ms: stats(|s| Some(s.delete_ms)),
+            peak_rss_kib: stats(|s| s.peak_rss_kib.map(|rss| rss as f64)),
+            samples,
+        }
+    }
+
+    fn measures(&self) -> [(&'static str, Option<Stats>); 6] {
+        [
+            ("create_ms", self.create_ms),
+            ("start_ms", self.start_ms),
+            ("first_byte_ms", self.first_byte_ms),
+            ("exit_ms", self.exit_ms),
+            ("delete_ms", self.delete_ms),
+            ("peak_rss_kib", self.peak_rss_kib),
+        ]
+    }
+}
+
+/// Returns the regressions of `reports` from `baseline`, the measures whose median grew by more
+/// than `threshold`, e.g., `0.1` for 10%, for the runtimes in both.
+pub fn regressions(baseline: &[Report], reports: &[Report], threshold: f64) -> Vec<String> {
+    let mut regressions = vec![];
+    for report in reports {
+        let Some(base) = baseline.iter().find(|base| base.runtime == report.runtime) else {
+            continue;
+        };
+        for ((name, stats), (_, base)) in report.measures().into_iter().zip(base.measures()) {
+            let (Some(stats), Some(base)) = (stats, base) else {
+                continue;
+            };
+            if stats.p50 > base.p50 * (1.0 + threshold) {
+                regressions.push(format!(
+                    "{}: the median {name} went from {:.2} to {:.2}",
+                    report.runtime, base.p50, stats.p50
+                ));
+            }
+        }
+    }
+    regressions
+}
+
+/// Returns the name of the runtime of the shim `name`, e.g., `io.containerd.wasmtime.v1` for
+/// `wasmtime`
+pub fn runtime_name(name: &str) -> String {
+    match name.contains('.') {
+        true => name.to_string(),
+        false => format!("io.containerd.{name}.v1"),
+    }
+}
+
+/// Runs the tasks of the benchmarks on a containerd
+pub struct Bench {
+    channel: Channel,
+    address: PathBuf,
+    namespace: String,
+    dir: PathBuf,
+}
+
+impl Bench {
+    pub async fn connect(address: impl AsRef<Path>, namespace: impl Into<String>) -> Result<Self> {
+        let address = address.as_ref().to_path_buf();
+        let channel = containerd_client::connect(&address)
+            .await
+            .with_context(|| format!("failed to connect to containerd at {address:?}"))?;
+        let dir = std::env::temp_dir().join(format!("runwasi-bench-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            channel,
+            address,
+            namespace: namespace.into(),
+            dir,
+        })
+    }
+
+    /// Runs a task of `image` with `args` with `runtime`, e.g., `io.containerd.wasmtime.v1`.
+    pub async fn run(
+        &self,
+        id: &str,
+        runtime: &str,
+        image: &str,
+        args: &[String],
+    ) -> Result<Sample> {
+        let created = self
+            .ctr(["containers", "create", "--runtime", runtime, image, id])
+            .args(args)
+            .status()
+            .context("failed to run ctr")?;
+        if !created.success() {
+            bail!("failed to create container {id}");
+        }
+
+        let sample = self.run_task(id).await;
+
+        if !self.ctr(["containers", "delete", id]).status()?.success() {
+            eprintln!("failed to delete container {id}");
+        }
+        sample
+    }
+
+    async fn run_task(&self, id: &str) -> Result<Sample> {
+        let ns = self.namespace.as_str();
+        let mut tasks = TasksClient::new(self.channel.clone());
+
+        let stdout = self.dir.join(format!("{id}.stdout"));
+        let first_byte = watch_stdout(&stdout)?;
+        let rootfs = self.rootfs(id).await?;
+
+        let req = CreateTaskRequest {
+            container_id: id.to_string(),
+            rootfs,
+            stdout: stdout.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let created = tasks.create(with_namespace!(req, ns)).await;
+        let create = started.elapsed();
+        if let Err(err) = created {
+            // the stdout of the task was never opened
+            let _ = OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&stdout);
+            let _ = fs::remove_file(&stdout);
+            bail!("failed to create task {id}: {}", err.message());
+        }
+
+        let req = StartRequest {
+            container_id: id.to_string(),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let pid = tasks
+            .start(with_namespace!(req, ns))
+            .await?
+            .into_inner()
+            .pid;
+        let start = started.elapsed();
+        let rss = sample_rss(pid);
+
+        let req = WaitRequest {
+            container_id: id.to_string(),
+            ..Default::default()
+        };
+        let exit_status = tasks
+            .wait(with_namespace!(req, ns))
+            .await?
+            .into_inner()
+            .exit_status;
+        let exit = started.elapsed();
+        let peak_rss_kib = rss.stop();
+        let first_byte = first_byte
+            .recv_timeout(Duration::from_secs(1))
+            .ok()
+            .map(|at| at.duration_since(started));
+
+        let req = DeleteTaskRequest {
+            container_id: id.to_string(),
+        };
+        let deleted = Instant::now();
+        tasks.delete(with_namespace!(req, ns)).await?;
+        let delete = deleted.elapsed();
+        let _ = fs::remove_file(&stdout);
+
+        Ok(Sample {
+            create_ms: millis(create),
+            start_ms: millis(start),
+            first_byte_ms: first_byte.map(millis),
+            exit_ms: millis(exit),
+            delete_ms: millis(delete),
+            exit_status,
+            peak_rss_kib,
+        })
+    }
+
+    // Returns the mounts of the snapshot of the container, i.e., its rootfs
+    async fn rootfs(&self, id: &str) -> Result<Vec<containerd_client::types::Mount>> {
+        let ns = self.namespace.as_str();
+        let req = GetContainerRequest { id: id.to_string() };
+        let container = ContainersClient::new(self.channel.clone())
+            .get(with_namespace!(req, ns))
+            .await?
+            .into_inner()
+            .container
+            .with_context(|| format!("container {id} not found"))?;
+        let req = MountsRequest {
+            snapshotter: container.snapshotter,
+            key: container.snapshot_key,
+        };
+        let mounts = SnapshotsClient::new(self.channel.clone())
+            .mounts(with_namespace!(req, ns))
+            .await?
+            .into_inner()
+            .mounts;
+        Ok(mounts)
+    }
+
+    fn ctr<const N: usize>(&self, args: [&str; N]) -> Command {
+        let mut cmd = Command::new("ctr");
+        cmd.arg("--address")
+            .arg(&self.address)
+            .args(["--namespace", &self.namespace])
+            .args(args);
+        cmd
+    }
+}
+
+impl Drop for Bench {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Creates the FIFO the task writes its stdout to, and returns when the first byte is written
+fn watch_stdout(path: &Path) -> Result<mpsc::Receiver<Instant>> {
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to create the stdout fifo");
+    }
+    let (tx, rx) = mpsc::channel();
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        // blocks until the shim opens the stdout of the task
+        let Ok(mut fifo) = File::open(path) else {
+            return;
+        };
+        let mut buf = [0; 4096];
+        let mut first = true;
+        while let Ok(1..) = fifo.read(&mut buf) {
+            if first {
+                let _ = tx.send(Instant::now());
+                first = false;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+struct RssSampler {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<Option<u64>>,
+}
+
+impl RssSampler {
+    fn stop(self) -> Option<u64> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.join().ok().flatten()
+    }
+}
+
+// Samples the peak resident memory of the process `pid`, until it exits or the sampler stops
+fn sample_rss(pid: u32) -> RssSampler {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handle = thread::spawn({
+        let stop = stop.clone();
+        move || {
+            let mut peak = None;
+            while !stop.load(Ordering::SeqCst) {
+                let Ok(status) = fs::read_to_string(format!("/proc/{pid}/status")) else {
+                    break;
+                };
+                peak = peak.max(peak_rss_kib(&status));
+                thread::sleep(RSS_SAMPLE_INTERVAL);
+            }
+            peak
+        }
+    });
+    RssSampler { stop, handle }
+}
+
+// Returns the peak resident memory in /proc/<pid>/status
+fn peak_rss_kib(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(create_ms: f64, first_byte_ms: Option<f64>) -> Sample {
+        Sample {
+            create_ms,
+            start_ms: 1.0,
+            first_byte_ms,
+            exit_ms: 2.0,
+            delete_ms: 1.0,
+            exit_status: 0,
+            peak_rss_kib: None,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(Stats::of([]), None);
+        let stats = Stats::of([4.0, 1.0, 3.0, 2.0, 10.0]).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                min: 1.0,
+                mean: 4.0,
+                p50: 3.0,
+                p95: 10.0,
+                max: 10.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_regressions() {
+        let samples = |create_ms| vec![sample(create_ms, Some(1.0)), sample(create_ms, None)];
+        let baseline = [Report::new(
+            "io.containerd.wasmtime.v1",
+            "image",
+            samples(10.0),
+        )];
+        assert_eq!(baseline[0].first_byte_ms.unwrap().p50, 1.0);
+        assert_eq!(baseline[0].peak_rss_kib, None);
+
+        let reports = [Report::new(
+            "io.containerd.wasmtime.v1",
+            "image",
+            samples(10.5),
+        )];
+        assert!(regressions(&baseline, &reports, 0.1).is_empty());
+
+        let reports = [
+            Report::new("io.containerd.wasmtime.v1", "image", samples(12.0)),
+            Report::new("io.containerd.wasmedge.v1", "image", samples(100.0)),
+        ];
+        assert_eq!(
+            regressions(&baseline, &reports, 0.1),
+            ["io.containerd.wasmtime.v1: the median create_ms went from 10.00 to 12.00"]
+        );
+    }
+
+    #[test]
+    fn test_peak_rss() {
+        let status = "Name:\twasi-demo-app\nVmPeak:\t  12345 kB\nVmHWM:\t    2048 kB\n";
+        assert_eq!(peak_rss_kib(status), Some(2048));
+        assert_eq!(peak_rss_kib("Name:\tzombie\n"), None);
+        assert_eq!(runtime_name("wasmtime"), "io.containerd.wasmtime.v1");
+        assert_eq!(
+            runtime_name("io.containerd.spin.v2"),
+            "io.containerd.spin.v2"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::container::{
+    Engine, Instance, PathResolve, PrecompileTarget, RuntimeContext, Stdio,
+};
+use containerd_shim_wasm::sandbox::{LayerContent, WasmLayer};
+use containerd_shim_wasmtime::instance::{DefaultConfig, WasmtimeEngine};
+use oci_spec::image::{Descriptor, MediaType};
+
+use crate::language::{JavaScript, Language, Python};
+use crate::script::{Script, ScriptContext};
+
+pub type JsEngine = ScriptEngine<JavaScript>;
+pub type JsInstance = Instance<JsEngine>;
+
+pub type PythonEngine = ScriptEngine<Python>;
+pub type PythonInstance = Instance<PythonEngine>;
+
+/// An engine running the scripts of a language with an engine compiled to wasm, e.g., QuickJS
+/// or StarlingMonkey for JavaScript, or CPython for Python, on wasmtime.
+/// Containers without a script run their modules and components as with the wasmtime shim.
+#[derive(Clone)]
+pub struct ScriptEngine<L: Language> {
+    wasmtime: WasmtimeEngine<DefaultConfig>,
+    engine: Option<WasmLayer>,
+    language: PhantomData<L>,
+}
+
+impl<L: Language> Default for ScriptEngine<L> {
+    fn default() -> Self {
+        Self {
+            wasmtime: WasmtimeEngine::default(),
+            engine: load_engine::<L>(),
+            language: PhantomData,
+        }
+    }
+}
+
+/// Loads the engine of the language, in the shim before it creates the containers, as its path
+/// is usually not visible in the file system of the container.
+fn load_engine<L: Language>() -> Option<WasmLayer> {
+    let path = std::env::var_os(L::ENGINE_ENV)
+        .map(PathBuf::from)
+        .or_else(|| {
+            let shim = std::env::current_exe().ok()?;
+            Some(shim.with_file_name(L::ENGINE_FILE))
+        })?;
+    match std::fs::read(&path) {
+        Ok(module) => Some(WasmLayer {
+            config: Descriptor::new(
+                MediaType::Other(L::LAYER_TYPES[0].to_string()),
+                module.len() as i64,
+                "",
+            ),
+            layer: LayerContent::from(module),
+        }),
+        Err(err) => {
+            log::warn!(
+                "failed to load the {} engine from {path:?}, containers can't run scripts: {err}",
+                L::NAME
+            );
+            None
+        }
+    }
+}
+
+impl<L: Language> Engine for ScriptEngine<L> {
+    fn name() -> &'static str {
+        L::NAME
+    }
+
+    fn run_wasi(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
+        let Some(script) = Script::from_source::<L>(&ctx.entrypoint().source) else {
+            return self.wasmtime.run_wasi(ctx, stdio);
+        };
+        let engine = self.engine.as_ref().with_context(|| {
+            format!(
+                "no {} engine to run the script, set {} in the environment of the shim",
+                L::NAME,
+                L::ENGINE_ENV
+            )
+        })?;
+        let ctx = ScriptContext {
+            language: PhantomData::<L>,
+            ctx,
+            args: script.engine_args::<L>(ctx.args())?,
+            engine: std::slice::from_ref(engine),
+        };
+        self.wasmtime.run_wasi(&ctx, stdio)
+    }
+
+    fn run_reactor(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
+        self.wasmtime.run_reactor(ctx, stdio)
+    }
+
+    fn can_handle(&self, ctx: &impl RuntimeContext) -> Result<()> {
+        match Script::from_source::<L>(&ctx.entrypoint().source) {
+            Some(Script::File(path)) => {
+                PathResolve::resolve_in_path_or_cwd(&path)
+                    .next()
+                    .with_context(|| format!("script {path:?} not found"))?;
+                Ok(())
+            }
+            Some(Script::Layer(_)) => Ok(()),
+            None => self.wasmtime.can_handle(ctx),
+        }
+    }
+
+    fn supported_layers_types() -> &'static [&'static str] {
+        L::LAYER_TYPES
+    }
+
+    fn precompile(&self, layers: &[WasmLayer]) -> Result<Vec<Option<Vec<u8>>>> {
+        // the modules of the image are precompiled, its scripts are skipped as invalid wasm
+        self.wasmtime.precompile(layers)
+    }
+
+    fn can_precompile(&self) -> Option<String> {
+        self.wasmtime.can_precompile()
+    }
+
+    fn precompile_target(&self) -> PrecompileTarget {
+        self.wasmtime.precompile_target()
+    }
+}
@@ -0,0 +1,49 @@
+//! The languages of the scripts run by the shims.
+
+/// A scripting language, run by an engine compiled to wasm
+pub trait Language: Clone + Send + Sync + 'static {
+    /// The name of the shim and of its engine, also the `argv[0]` of the engine
+    const NAME: &'static str;
+    /// Media type of the layers with the script of the container
+    const MEDIA_TYPE: &'static str;
+    /// The extensions of the script files
+    const EXTENSIONS: &'static [&'static str];
+    /// The flag of the engine running the script that follows it
+    const EVAL_FLAG: &'static str;
+    /// Environment variable with the path of the engine, in the environment of the shim
+    const ENGINE_ENV: &'static str;
+    /// The file name of the engine bundled next to the shim binary
+    const ENGINE_FILE: &'static str;
+    /// The layer types of the images of the shim, the modules and the scripts
+    const LAYER_TYPES: &'static [&'static str];
+}
+
+const WASM_LAYER_MEDIA_TYPE: &str = "application/vnd.bytecodealliance.wasm.component.layer.v0+wasm";
+
+/// JavaScript, run by an engine like QuickJS or StarlingMonkey
+#[derive(Clone)]
+pub struct JavaScript;
+
+impl Language for JavaScript {
+    const NAME: &'static str = "js";
+    const MEDIA_TYPE: &'static str = "application/javascript";
+    const EXTENSIONS: &'static [&'static str] = &["js", "mjs"];
+    const EVAL_FLAG: &'static str = "-e";
+    const ENGINE_ENV: &'static str = "RUNWASI_JS_ENGINE";
+    const ENGINE_FILE: &'static str = "containerd-shim-js-engine.wasm";
+    const LAYER_TYPES: &'static [&'static str] = &[WASM_LAYER_MEDIA_TYPE, Self::MEDIA_TYPE];
+}
+
+/// Python, run by CPython compiled to WASI
+#[derive(Clone)]
+pub struct Python;
+
+impl Language for Python {
+    const NAME: &'static str = "python";
+    const MEDIA_TYPE: &'static str = "text/x-python";
+    const EXTENSIONS: &'static [&'static str] = &["py"];
+    const EVAL_FLAG: &'static str = "-c";
+    const ENGINE_ENV: &'static str = "RUNWASI_PYTHON_ENGINE";
+    const ENGINE_FILE: &'static str = "containerd-shim-python-engine.wasm";
+    const LAYER_TYPES: &'static [&'static str] = &[WASM_LAYER_MEDIA_TYPE, Self::MEDIA_TYPE];
+}
@@ -0,0 +1,5 @@
+pub mod instance;
+pub mod language;
+pub mod script;
+
+pub use instance::{JsInstance, PythonInstance};
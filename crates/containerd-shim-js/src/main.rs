@@ -0,0 +1,6 @@
+use containerd_shim_js::JsInstance;
+use containerd_shim_wasm::sandbox::cli::{revision, shim_main, version};
+
+fn main() {
+    shim_main::<JsInstance>("js", version!(), revision!(), "v1", None);
+}
@@ -0,0 +1,6 @@
+use containerd_shim_js::PythonInstance;
+use containerd_shim_wasm::sandbox::cli::{revision, shim_main, version};
+
+fn main() {
+    shim_main::<PythonInstance>("python", version!(), revision!(), "v1", None);
+}
@@ -0,0 +1,185 @@
+//! Script entrypoints, run by the engine module of the language of the shim.
+//!
+//! The script of a container is either:
+//!  - an image layer with the media type of the language, e.g., `application/javascript`,
+//!    passed to the engine with its eval flag, e.g., `-e <script>`, as the layer is not in the
+//!    file system of the container.
+//!  - a file of the container with an extension of the language, e.g., `.js`, passed to the
+//!    engine with its path.
+//!
+//! The arguments of the container follow the script, e.g., `js -e <script> <args>...`, the
+//! command line of `qjs` and `python`, and of the engines built from them.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::container::{
+    Entrypoint, PathResolve, RecordLog, RuntimeContext, Source, Volume,
+};
+use containerd_shim_wasm::sandbox::WasmLayer;
+use oci_spec::image::Platform;
+use oci_spec::runtime::LinuxResources;
+
+use crate::language::Language;
+
+/// The script of a container
+#[derive(Debug, PartialEq)]
+pub enum Script<'a> {
+    Layer(&'a [u8]),
+    File(PathBuf),
+}
+
+impl<'a> Script<'a> {
+    /// Returns the script of the entrypoint, if it is one.
+    pub fn from_source<L: Language>(source: &Source<'a>) -> Option<Self> {
+        match source {
+            Source::Oci(layers) => layers
+                .iter()
+                .find(|layer| layer.config.media_type().to_string() == L::MEDIA_TYPE)
+                .map(|layer| Self::Layer(&layer.layer)),
+            Source::File(path) => path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| L::EXTENSIONS.contains(&ext))
+                .then(|| Self::File(path.clone())),
+        }
+    }
+
+    /// The arguments of the engine running the script, with the arguments of the container.
+    pub fn engine_args<L: Language>(&self, args: &[String]) -> Result<Vec<String>> {
+        let mut engine_args = vec![L::NAME.to_string()];
+        match self {
+            Self::Layer(script) => {
+                let script = std::str::from_utf8(script).context("the script is not UTF-8")?;
+                engine_args.extend([L::EVAL_FLAG.to_string(), script.to_string()]);
+            }
+            Self::File(path) => {
+                let path = path
+                    .resolve_in_path_or_cwd()
+                    .next()
+                    .with_context(|| format!("script {path:?} not found"))?;
+                engine_args.push(path.to_string_lossy().into_owned());
+            }
+        }
+        engine_args.extend(args.iter().skip(1).cloned());
+        Ok(engine_args)
+    }
+}
+
+/// The context of a container running a script, with the engine as its entrypoint.
+pub(crate) struct ScriptContext<'a, L, C> {
+    pub language: PhantomData<L>,
+    pub ctx: &'a C,
+    pub args: Vec<String>,
+    pub engine: &'a [WasmLayer],
+}
+
+impl<L: Language, C: RuntimeContext> RuntimeContext for ScriptContext<'_, L, C> {
+    fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    fn entrypoint(&self) -> Entrypoint {
+        let Entrypoint { name, arg0, .. } = self.ctx.entrypoint();
+        Entrypoint::new(
+            "_start",
+            name,
+            arg0.map(|_| Path::new(L::NAME)),
+            Source::Oci(self.engine),
+        )
+    }
+
+    fn platform(&self) -> &Platform {
+        self.ctx.platform()
+    }
+
+    fn annotations(&self) -> &HashMap<String, String> {
+        self.ctx.annotations()
+    }
+
+    fn resources(&self) -> Option<&LinuxResources> {
+        self.ctx.resources()
+    }
+
+    fn volumes(&self) -> Vec<Volume> {
+        self.ctx.volumes()
+    }
+
+    fn readonly_rootfs(&self) -> bool {
+        self.ctx.readonly_rootfs()
+    }
+
+    fn record_log(&self) -> Result<Option<RecordLog>> {
+        self.ctx.record_log()
+    }
+
+    fn coredump_file(&self) -> Option<PathBuf> {
+        self.ctx.coredump_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_shim_wasm::sandbox::LayerContent;
+    use oci_spec::image::{Descriptor, MediaType};
+
+    use super::*;
+    use crate::language::{JavaScript, Python};
+
+    fn layer(media_type: &str, content: &[u8]) -> WasmLayer {
+        WasmLayer {
+            config: Descriptor::new(MediaType::Other(media_type.to_string()), 0, ""),
+            layer: LayerContent::from(content.to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_script_layer() -> Result<()> {
+        let layers = [
+            layer("application/vnd.wasm.content.layer.v1+wasm", b"\0asm"),
+            layer(JavaScript::MEDIA_TYPE, b"console.log('hello')"),
+        ];
+        let script = Script::from_source::<JavaScript>(&Source::Oci(&layers)).unwrap();
+        assert_eq!(script, Script::Layer(b"console.log('hello')"));
+
+        let args = script.engine_args::<JavaScript>(&["".to_string(), "world".to_string()])?;
+        assert_eq!(args, ["js", "-e", "console.log('hello')", "world"]);
+
+        // the layers of another language are not scripts of the shim
+        assert_eq!(Script::from_source::<Python>(&Source::Oci(&layers)), None);
+
+        let layers = [layer(Python::MEDIA_TYPE, b"print('hello')")];
+        let script = Script::from_source::<Python>(&Source::Oci(&layers)).unwrap();
+        let args = script.engine_args::<Python>(&[])?;
+        assert_eq!(args, ["python", "-c", "print('hello')"]);
+
+        let layers = [layer(JavaScript::MEDIA_TYPE, &[0xff])];
+        let script = Script::from_source::<JavaScript>(&Source::Oci(&layers)).unwrap();
+        assert!(script.engine_args::<JavaScript>(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.js");
+        std::fs::write(&path, "console.log('hello')")?;
+
+        let script = Script::from_source::<JavaScript>(&Source::File(path.clone())).unwrap();
+        let args = script
+            .engine_args::<JavaScript>(&[path.to_string_lossy().into_owned(), "world".into()])?;
+        assert_eq!(args, ["js", &*path.to_string_lossy(), "world"]);
+
+        let source = Source::File("app.py".into());
+        assert!(Script::from_source::<Python>(&source).is_some());
+        assert_eq!(Script::from_source::<JavaScript>(&source), None);
+
+        // modules are run by the engine of the shim rather than by the engine of the language
+        let source = Source::File("app.wasm".into());
+        assert_eq!(Script::from_source::<JavaScript>(&source), None);
+        assert_eq!(Script::from_source::<JavaScript>(&Source::Oci(&[])), None);
+        Ok(())
+    }
+}
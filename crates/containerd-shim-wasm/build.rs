@@ -4,8 +4,14 @@ use std::path::Path;
 use ttrpc_codegen::{Codegen, ProtobufCustomize};
 
 fn main() {
-    let protos = ["protos/sandbox.proto"];
+    let protos = [
+        "protos/sandbox.proto",
+        "protos/events.proto",
+        "protos/runtimeoptions.proto",
+    ];
     println!("cargo:rerun-if-changed=protos/sandbox.proto");
+    println!("cargo:rerun-if-changed=protos/events.proto");
+    println!("cargo:rerun-if-changed=protos/runtimeoptions.proto");
 
     let out_dir = var_os("OUT_DIR").unwrap();
     let out_dir = Path::new(&out_dir);
@@ -21,6 +27,8 @@ fn main() {
 
     let sanbox_rs = out_dir.join("sandbox.rs");
     let sanbox_ttrpc_rs = out_dir.join("sandbox_ttrpc.rs");
+    let events_rs = out_dir.join("events.rs");
+    let runtimeoptions_rs = out_dir.join("runtimeoptions.rs");
 
     std::fs::write(
         out_dir.join("mod.rs"),
@@ -28,6 +36,8 @@ fn main() {
             r#"
 #[path = {sanbox_rs:?}] pub mod sandbox;
 #[path = {sanbox_ttrpc_rs:?}] pub mod sandbox_ttrpc;
+#[path = {events_rs:?}] pub mod events;
+#[path = {runtimeoptions_rs:?}] pub mod runtimeoptions;
 "#,
         ),
     )
@@ -0,0 +1,281 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use oci_spec::runtime::Spec;
+use wasmparser::{Parser, Payload};
+
+use crate::container::WasmBinaryType;
+use crate::sandbox::oci::WasmLayer;
+
+/// The imports of a wasm module or component, and the WASI worlds and host capabilities they
+/// need, inspected without instantiating it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapabilityReport {
+    /// The WASI worlds the binary targets, e.g., `wasi:preview1` for modules, or
+    /// `wasi:cli/command` and `wasi:http/proxy` for components.
+    pub worlds: BTreeSet<String>,
+    /// The host capabilities the imports need, e.g., `filesystem`, `sockets` or `http`.
+    pub capabilities: BTreeSet<String>,
+    /// The imports of the binary: the import modules of a module, e.g., `wasi_snapshot_preview1`,
+    /// or the imported interfaces of a component, e.g., `wasi:filesystem/types@0.2.0`.
+    pub imports: BTreeSet<String>,
+}
+
+impl CapabilityReport {
+    /// Inspects the imports, and exports, of a wasm module or component.
+    /// Returns `None` if the bytes are not wasm, e.g., a precompiled module.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+        let Some(binary_type) = WasmBinaryType::from_bytes(bytes) else {
+            return Ok(None);
+        };
+
+        let mut report = Self::default();
+        // only the imports of the outer binary are provided by the host, the nested modules of a
+        // component are instantiated by the component itself
+        let mut depth = 0;
+        for payload in Parser::new(0).parse_all(bytes) {
+            match payload.context("invalid wasm binary")? {
+                Payload::ModuleSection { .. } | Payload::ComponentSection { .. } => depth += 1,
+                Payload::End(_) => depth -= 1,
+                Payload::ImportSection(imports) if depth == 0 => {
+                    for import in imports {
+                        let import = import.context("invalid import")?;
+                        report.add_module_import(import.module, import.name);
+                    }
+                }
+                Payload::ComponentImportSection(imports) if depth == 0 => {
+                    for import in imports {
+                        let import = import.context("invalid import")?;
+                        report.add_component_import(import.name.0);
+                    }
+                }
+                Payload::ComponentExportSection(exports) if depth == 0 => {
+                    for export in exports {
+                        let export = export.context("invalid export")?;
+                        report.add_component_export(export.name.0);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let (WasmBinaryType::Component, true) = (binary_type, report.worlds.is_empty()) {
+            report.worlds.insert("wasi:preview2".to_string());
+        }
+        Ok(Some(report))
+    }
+
+    /// Merges the report of another binary of the same container, e.g., of another layer.
+    pub fn merge(&mut self, other: Self) {
+        self.worlds.extend(other.worlds);
+        self.capabilities.extend(other.capabilities);
+        self.imports.extend(other.imports);
+    }
+
+    /// Returns the imports that don't start with any of the `supported` prefixes.
+    pub fn unsupported_imports<'a>(&'a self, supported: &[&str]) -> Vec<&'a str> {
+        self.imports
+            .iter()
+            .map(String::as_str)
+            .filter(|import| !supported.iter().any(|prefix| import.starts_with(prefix)))
+            .collect()
+    }
+
+    fn add_module_import(&mut self, module: &str, name: &str) {
+        self.imports.insert(module.to_string());
+        let world = match module {
+            "wasi_snapshot_preview1" => "wasi:preview1",
+            "wasi_unstable" => "wasi:unstable",
+            _ => return,
+        };
+        self.worlds.insert(world.to_string());
+        let capability = match name.split('_').next().unwrap_or_default() {
+            "fd" | "path" => "filesystem",
+            "sock" => "sockets",
+            "clock" => "clocks",
+            "random" => "random",
+            "environ" => "environment",
+            "args" => "arguments",
+            "poll" => "poll",
+            "proc" | "sched" => "process",
+            _ => return,
+        };
+        self.capabilities.insert(capability.to_string());
+    }
+
+    fn add_component_import(&mut self, name: &str) {
+        self.imports.insert(name.to_string());
+        // interfaces are named `<namespace>:<package>/<interface>[@<version>]`
+        if let Some(package) = name
+            .strip_prefix("wasi:")
+            .and_then(|name| name.split('/').next())
+        {
+            self.capabilities.insert(package.to_string());
+        }
+    }
+
+    fn add_component_export(&mut self, name: &str) {
+        let interface = name.split('@').next().unwrap_or_default();
+        let world = match interface {
+            "wasi:cli/run" => "wasi:cli/command",
+            "wasi:http/incoming-handler" => "wasi:http/proxy",
+            _ => return,
+        };
+        self.worlds.insert(world.to_string());
+    }
+}
+
+/// Inspects the wasm layers of a container, or else the module of its rootfs named by the first
+/// argument of its process, before the container is created.
+/// The layers that are not wasm, e.g., precompiled layers, are inspected from their `original`
+/// content.
+/// Returns `None` when there is no wasm binary to inspect, e.g., a module found in the `PATH`.
+pub(crate) fn inspect_container(
+    modules: &[WasmLayer],
+    spec: &Spec,
+    bundle: &Path,
+    original: impl Fn(&WasmLayer) -> Result<Vec<u8>>,
+) -> Result<Option<CapabilityReport>> {
+    if !modules.is_empty() {
+        let mut report: Option<CapabilityReport> = None;
+        for module in modules {
+            let layer = match CapabilityReport::from_bytes(&module.layer)? {
+                Some(layer) => Some(layer),
+                None => CapabilityReport::from_bytes(&original(module)?)?,
+            };
+            if let Some(layer) = layer {
+                report.get_or_insert_with(Default::default).merge(layer);
+            }
+        }
+        return Ok(report);
+    }
+
+    let Some(path) = rootfs_module(spec, bundle) else {
+        return Ok(None);
+    };
+    let bytes = std::fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+    CapabilityReport::from_bytes(&bytes)
+}
+
+// the path on the host of the module named by the first argument of the process, as a path in
+// the rootfs, or relative to the working directory of the process, if it exists
+fn rootfs_module(spec: &Spec, bundle: &Path) -> Option<PathBuf> {
+    let process = spec.process().as_ref()?;
+    let arg0 = process.args().as_ref()?.first()?;
+    let arg0 = Path::new(arg0.split('#').next()?);
+    let rootfs = bundle.join(spec.root().as_ref()?.path());
+    let in_rootfs = |path: &Path| rootfs.join(path.strip_prefix("/").unwrap_or(path));
+    let path = match arg0.is_absolute() {
+        true => in_rootfs(arg0),
+        false => in_rootfs(process.cwd()).join(arg0),
+    };
+    path.is_file().then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_report() -> Result<()> {
+        let module = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "random_get" (func (param i32 i32) (result i32)))
+                (import "env" "host_log" (func (param i32)))
+            )"#,
+        )?;
+        let report = CapabilityReport::from_bytes(&module)?.unwrap();
+
+        assert_eq!(report.worlds, BTreeSet::from(["wasi:preview1".to_string()]));
+        assert_eq!(
+            report.capabilities,
+            BTreeSet::from(["filesystem".to_string(), "random".to_string()])
+        );
+        assert_eq!(
+            report.unsupported_imports(&["wasi_snapshot_preview1"]),
+            ["env"]
+        );
+        assert!(report
+            .unsupported_imports(&["wasi_snapshot_preview1", "env"])
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_component_report() -> Result<()> {
+        let component = wat::parse_str(
+            r#"(component
+                (import "wasi:filesystem/types@0.2.0" (instance))
+                (import "wasi:sockets/tcp@0.2.0" (instance))
+                (core module (import "env" "inner" (func)))
+            )"#,
+        )?;
+        let report = CapabilityReport::from_bytes(&component)?.unwrap();
+
+        // the imports of the nested module are not provided by the host
+        assert_eq!(
+            report.imports,
+            BTreeSet::from([
+                "wasi:filesystem/types@0.2.0".to_string(),
+                "wasi:sockets/tcp@0.2.0".to_string(),
+            ])
+        );
+        assert_eq!(
+            report.capabilities,
+            BTreeSet::from(["filesystem".to_string(), "sockets".to_string()])
+        );
+        assert_eq!(report.worlds, BTreeSet::from(["wasi:preview2".to_string()]));
+        assert_eq!(
+            report
+                .unsupported_imports(&["wasi_snapshot_preview1"])
+                .len(),
+            2
+        );
+
+        assert!(CapabilityReport::from_bytes(b"not wasm")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_precompiled_layer() -> Result<()> {
+        let module = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "sock_accept" (func (param i32 i32 i32) (result i32)))
+            )"#,
+        )?;
+        let config = oci_spec::image::DescriptorBuilder::default()
+            .media_type("application/vnd.w3c.wasm.module.v1+wasm")
+            .digest("sha256:1234")
+            .size(module.len() as i64)
+            .build()?;
+        let precompiled = WasmLayer {
+            config,
+            layer: b"precompiled".to_vec().into(),
+        };
+
+        // the precompiled layer is inspected from its original content
+        let report = inspect_container(
+            &[precompiled.clone()],
+            &Spec::default(),
+            Path::new("/"),
+            |_| Ok(module.clone()),
+        )?
+        .unwrap();
+        assert_eq!(report.capabilities, BTreeSet::from(["sockets".to_string()]));
+
+        let report = inspect_container(
+            &[precompiled.clone()],
+            &Spec::default(),
+            Path::new("/"),
+            |_| Ok(b"script".to_vec()),
+        )?;
+        assert_eq!(report, None);
+
+        let report = inspect_container(&[precompiled], &Spec::default(), Path::new("/"), |_| {
+            anyhow::bail!("content not found")
+        });
+        assert!(report.is_err());
+        Ok(())
+    }
+}
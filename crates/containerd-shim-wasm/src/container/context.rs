@@ -1,12 +1,19 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
 
 use anyhow::{bail, Context};
-use oci_spec::image::Platform;
-use oci_spec::runtime::Spec;
+use oci_spec::image::{Platform, ANNOTATION_TITLE};
+use oci_spec::runtime::{LinuxResources, Spec};
 
-use crate::container::path::PathResolve;
-use crate::sandbox::oci::WasmLayer;
+use crate::container::path::{wasm_wrapper_target, PathResolve};
+use crate::container::{ErrorCode, PoolStats};
+use crate::sandbox::oci::{
+    WasmLayer, COREDUMP_PATH, ENTRYPOINT_FUNCTION_ANNOTATION, RECORD_LOG_PATH,
+};
 
 pub trait RuntimeContext {
     // ctx.args() returns arguments from the runtime spec process field, including the
@@ -18,6 +25,8 @@ pub trait RuntimeContext {
     //   - `name` - provided as the file name of the module in the entrypoint without the extension
     //   - `func` - name of the exported function to call, obtained from the
     // arguments on process OCI spec.
+    //   - `func_args()` - arguments of the exported function, obtained from the
+    // `io.containerd.wasm.entrypoint` annotation.
     //  - `Source` - either a `File(PathBuf)` or `Oci(WasmLayer)`. When a `File` source the `PathBuf`` is provided by entrypoint in OCI spec.
     //     If the image contains custom OCI Wasm layers, the source is provided as an array of `WasmLayer` structs.
     //
@@ -26,11 +35,248 @@ pub trait RuntimeContext {
     //   "/app/app.wasm#entry" -> { source: File("/app/app.wasm"), func: "entry", name: "Some(app)", arg0: "/app/app.wasm#entry" }
     //   "my_module.wat" -> { source: File("my_module.wat"), func: "_start", name: "Some(my_module)", arg0: "my_module.wat" }
     //   "#init" -> { source: File(""), func: "init", name: None, arg0: "#init" }
+    //
+    // When the entrypoint is a wrapper script starting with `#!wasm <path>#func`, the module and
+    // function are the ones of the script, resolved relative to the script, e.g.:
+    //   "/usr/bin/app" -> { source: File("/app/app.wasm"), func: "main", name: "Some(app)", arg0: "/usr/bin/app" }
+    //
+    // The `io.containerd.wasm.entrypoint` annotation overrides the function, and sets its
+    // arguments, as `func#arg1,arg2`, e.g.:
+    //   "handle#GET,/index.html" -> { func: "handle", func_args: ["GET", "/index.html"] }
+    //   "#hello" -> { func: <the function of the arguments>, func_args: ["hello"] }
     fn entrypoint(&self) -> Entrypoint;
 
     // the platform for the container using the struct defined on the OCI spec definition
     // https://github.com/opencontainers/image-spec/blob/v1.1.0-rc5/image-index.md
     fn platform(&self) -> &Platform;
+
+    // ctx.annotations() returns the annotations from the runtime spec, e.g., the
+    // annotations added by the OCI image or by the CRI plugin.
+    // Runtimes can use them for per-container configuration.
+    fn annotations(&self) -> &HashMap<String, String> {
+        static EMPTY: OnceLock<HashMap<String, String>> = OnceLock::new();
+        EMPTY.get_or_init(HashMap::new)
+    }
+
+    // ctx.resources() returns the resources from the linux section of the runtime spec, e.g.,
+    // the memory and cpu limits of the container.
+    // Runtimes can use them to enforce the limits inside the engine.
+    fn resources(&self) -> Option<&LinuxResources>;
+
+    // ctx.volumes() returns the directories bind mounted in the container by the `mounts`
+    // of the runtime spec, e.g., the hostPath, emptyDir and configMap volumes of a Kubernetes pod.
+    // Runtimes can preopen them for the guest, with the read-only flag of the mount.
+    // The in-memory tmpfs mounts are included too, e.g., the `/tmp` of a read-only root.
+    fn volumes(&self) -> Vec<Volume> {
+        vec![]
+    }
+
+    // ctx.readonly_rootfs() returns whether the root of the container is read-only,
+    // from the `root.readonly` of the runtime spec.
+    // Runtimes should then preopen the root for the guest without write permissions.
+    fn readonly_rootfs(&self) -> bool {
+        false
+    }
+
+    // ctx.record_log() returns the log the engine records the results of the nondeterministic
+    // WASI calls in, e.g., reading the clocks, or replays them from, with the
+    // `runwasi.io/record-mode` annotation. The log is a file of the bundle mounted in the container.
+    fn record_log(&self) -> anyhow::Result<Option<RecordLog>> {
+        Ok(None)
+    }
+
+    // ctx.coredump_file() returns the file the engine writes the core dump of the guest to when it
+    // traps, with the `runwasi.io/coredump-on-trap` annotation. The shim then publishes its path
+    // on the host in a `/tasks/coredumped` event.
+    fn coredump_file(&self) -> Option<PathBuf> {
+        None
+    }
+
+    // ctx.pool_stats() returns the counters the engine updates when it serves the requests of the
+    // container with a pool of instances, and when it creates wasm instances and grows their
+    // linear memory, see `PoolStats::instance_created`. The shim reports them in the stats of the
+    // task.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    // ctx.threads() returns the number of threads the engine runs the guest with, e.g., the
+    // workers of its async runtime, from the `threads` runtime option of the shim.
+    // Without one, engines use a thread per CPU the container can run on.
+    fn threads(&self) -> Option<NonZeroUsize> {
+        None
+    }
+
+    // ctx.keyvalue() returns the key-value store of the `wasi:keyvalue` interfaces, from the
+    // `keyvalue` runtime option of the shim, with the scope of the buckets of the container in it.
+    // Without one, engines don't provide the interfaces.
+    fn keyvalue(&self) -> Option<&KeyValue> {
+        None
+    }
+
+    // ctx.wasi_threads() returns whether the engine provides wasi-threads to the guest, i.e.,
+    // shared memories and the `wasi::thread-spawn` import, with the `runwasi.io/wasi-threads`
+    // annotation. Engines without wasi-threads ignore it.
+    fn wasi_threads(&self) -> anyhow::Result<bool> {
+        match self
+            .annotations()
+            .get(WASI_THREADS_ANNOTATION)
+            .map(String::as_str)
+        {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(value) => bail!(
+                "invalid {WASI_THREADS_ANNOTATION} annotation {value:?}, expected `true` or `false`"
+            ),
+        }
+    }
+
+    // ctx.max_threads() returns the maximum number of threads the guest can spawn with
+    // wasi-threads, from the `linux.resources.pids.limit` of the runtime spec.
+    // Without a limit, the guest can spawn as many threads as the engine allows.
+    fn max_threads(&self) -> Option<NonZeroUsize> {
+        let limit = self.resources()?.pids().as_ref()?.limit();
+        usize::try_from(limit).ok().and_then(NonZeroUsize::new)
+    }
+
+    // ctx.execution_mode() returns how the engine runs the entrypoint, selected with the
+    // `runwasi.io/execution-mode` annotation, and defaults to `ExecutionMode::Command`.
+    fn execution_mode(&self) -> anyhow::Result<ExecutionMode> {
+        match self.annotations().get(EXECUTION_MODE_ANNOTATION) {
+            None => Ok(ExecutionMode::default()),
+            Some(mode) => mode.parse(),
+        }
+    }
+
+    // ctx.allows_capability(capability) returns whether the engine may provide a WASI
+    // capability, e.g., `filesystem`, `sockets`, `http` or `nn`, to the container. The shim denies
+    // the capabilities of the node's capability policy with the `runwasi.io/denied-capabilities`
+    // annotation.
+    fn allows_capability(&self, capability: &str) -> bool {
+        self.annotations()
+            .get(DENIED_CAPABILITIES_ANNOTATION)
+            .map_or(true, |denied| {
+                !denied.split(',').any(|denied| denied.trim() == capability)
+            })
+    }
+}
+
+/// A directory mounted in the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    // The path of the directory, as seen by the engine.
+    pub host_path: PathBuf,
+    // The path of the directory in the guest.
+    pub guest_path: PathBuf,
+    pub readonly: bool,
+}
+
+/// The log of a container run with the `runwasi.io/record-mode` annotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordLog {
+    pub mode: RecordMode,
+    // The path of the log, as seen by the engine.
+    pub path: PathBuf,
+}
+
+/// The key-value store of a container, from the `keyvalue` runtime option of the shim.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyValue {
+    // The store, e.g., `memory`, `file:<directory>` or `redis://<host>`.
+    pub store: String,
+    // The prefix of the names of the buckets of the container, e.g., `k8s.io/default/web/app`,
+    // so that the containers sharing a store don't share their buckets.
+    pub scope: String,
+}
+
+impl KeyValue {
+    /// Returns the store of the container `id` of the containerd `namespace`.
+    /// The buckets of the containers of a pod are scoped to the Kubernetes namespace, pod and
+    /// container names of the CRI annotations, so that they outlive the restarts of the
+    /// container, and the buckets of the other containers are scoped to their id.
+    pub(crate) fn new(
+        store: String,
+        namespace: &str,
+        id: &str,
+        annotations: &HashMap<String, String>,
+    ) -> Self {
+        let names = [CRI_SANDBOX_NAMESPACE, CRI_SANDBOX_NAME, CRI_CONTAINER_NAME]
+            .map(|key| annotations.get(key).filter(|name| is_scope_name(name)));
+        let scope = match names {
+            [Some(pod_namespace), Some(pod), Some(container)] => {
+                format!("{namespace}/{pod_namespace}/{pod}/{container}")
+            }
+            _ => format!("{namespace}/{id}"),
+        };
+        Self { store, scope }
+    }
+}
+
+// the names of the scope are path components, so that the scopes of different depths never overlap
+fn is_scope_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/')
+}
+
+/// Whether the engine writes the record log, or reads it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    Record,
+    Replay,
+}
+
+impl FromStr for RecordMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> anyhow::Result<Self> {
+        match mode {
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            _ => bail!("invalid {RECORD_MODE_ANNOTATION} annotation {mode:?}, expected `record` or `replay`"),
+        }
+    }
+}
+
+/// Annotation with the exported function called by the engine, and its arguments, as `func#arg1,arg2`
+pub const ENTRYPOINT_ANNOTATION: &str = "io.containerd.wasm.entrypoint";
+
+/// Annotation with the execution mode of the container, `command` or `reactor`
+pub const EXECUTION_MODE_ANNOTATION: &str = "runwasi.io/execution-mode";
+
+/// Annotation with the record mode of the container, `record` or `replay`
+pub const RECORD_MODE_ANNOTATION: &str = "runwasi.io/record-mode";
+
+const CRI_SANDBOX_NAMESPACE: &str = "io.kubernetes.cri.sandbox-namespace";
+const CRI_SANDBOX_NAME: &str = "io.kubernetes.cri.sandbox-name";
+const CRI_CONTAINER_NAME: &str = "io.kubernetes.cri.container-name";
+
+/// Annotation enabling wasi-threads for the container, `true` or `false`
+pub const WASI_THREADS_ANNOTATION: &str = "runwasi.io/wasi-threads";
+
+/// Annotation with the comma separated capabilities denied to the container by the node's
+/// capability policy, set by the shim
+pub const DENIED_CAPABILITIES_ANNOTATION: &str = "runwasi.io/denied-capabilities";
+
+/// How the engine runs the entrypoint of a container.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    // The entrypoint function is called, and the container exits when it returns.
+    #[default]
+    Command,
+    // The module is initialized, and the instance is kept alive servicing invocations
+    // until the container is killed, see `Engine::run_reactor`.
+    Reactor,
+}
+
+impl FromStr for ExecutionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> anyhow::Result<Self> {
+        match mode {
+            "command" => Ok(Self::Command),
+            "reactor" => Ok(Self::Reactor),
+            _ => bail!("invalid {EXECUTION_MODE_ANNOTATION} annotation {mode:?}, expected `command` or `reactor`"),
+        }
+    }
 }
 
 /// The source for a WASI module / components.
@@ -55,7 +301,8 @@ impl<'a> Source<'a> {
                 let path = path
                     .resolve_in_path_or_cwd()
                     .next()
-                    .context("module not found")?;
+                    .with_context(|| format!("module {path:?} not found"))
+                    .context(ErrorCode::ModuleNotFound)?;
                 Ok(Cow::Owned(std::fs::read(path)?))
             }
             Source::Oci([module]) => Ok(Cow::Borrowed(&module.layer)),
@@ -73,12 +320,46 @@ pub struct Entrypoint<'a> {
     pub name: Option<String>,
     pub arg0: Option<&'a Path>,
     pub source: Source<'a>,
+    func_args: Vec<String>,
+}
+
+impl<'a> Entrypoint<'a> {
+    /// An entrypoint calling `func` without arguments.
+    pub fn new(
+        func: impl Into<String>,
+        name: Option<String>,
+        arg0: Option<&'a Path>,
+        source: Source<'a>,
+    ) -> Self {
+        Self {
+            func: func.into(),
+            name,
+            arg0,
+            source,
+            func_args: vec![],
+        }
+    }
+
+    /// Sets the arguments `func` is called with.
+    pub fn with_func_args(mut self, func_args: Vec<String>) -> Self {
+        self.func_args = func_args;
+        self
+    }
+
+    /// The arguments `func` is called with, as set by the `io.containerd.wasm.entrypoint`
+    /// annotation. Engines parse them according to the parameters of `func`.
+    pub fn func_args(&self) -> &[String] {
+        &self.func_args
+    }
 }
 
 pub(crate) struct WasiContext<'a> {
     pub spec: &'a Spec,
     pub wasm_layers: &'a [WasmLayer],
     pub platform: &'a Platform,
+    pub pool_stats: Option<&'a PoolStats>,
+    pub threads: Option<NonZeroUsize>,
+    pub keyvalue: Option<&'a KeyValue>,
 }
 
 impl RuntimeContext for WasiContext<'_> {
@@ -94,10 +375,54 @@ impl RuntimeContext for WasiContext<'_> {
     fn entrypoint(&self) -> Entrypoint {
         let arg0 = self.args().first();
 
+        // wasm artifacts can select the function, and name the module, in their layers
+        let layer_annotation = |key: &str| {
+            self.wasm_layers.iter().find_map(|layer| {
+                layer
+                    .config
+                    .annotations()
+                    .as_ref()?
+                    .get(key)
+                    .map(String::as_str)
+            })
+        };
+
         let entry_point = arg0.map(String::as_str).unwrap_or("");
-        let (path, func) = entry_point
-            .split_once('#')
-            .unwrap_or((entry_point, "_start"));
+
+        // a `#!wasm` wrapper script of the rootfs runs the module, and function, it names
+        let script = entry_point.split('#').next().unwrap_or_default();
+        let wrapped = match script {
+            "" => None,
+            _ if !self.wasm_layers.is_empty() => None,
+            script => wasm_wrapper_target(script),
+        };
+        let entry_point = wrapped.as_deref().unwrap_or(entry_point);
+
+        let (path, func) = entry_point.split_once('#').unwrap_or((
+            entry_point,
+            layer_annotation(ENTRYPOINT_FUNCTION_ANNOTATION).unwrap_or("_start"),
+        ));
+        let path = match path {
+            "" => layer_annotation(ANNOTATION_TITLE).unwrap_or_default(),
+            path => path,
+        };
+
+        // the annotation takes precedence over the function in the arguments
+        let (func, func_args) = match self.annotations().get(ENTRYPOINT_ANNOTATION) {
+            Some(entrypoint) => {
+                let (annotated, args) = entrypoint.split_once('#').unwrap_or((entrypoint, ""));
+                let func = match annotated {
+                    "" => func,
+                    annotated => annotated,
+                };
+                let args = match args {
+                    "" => vec![],
+                    args => args.split(',').map(String::from).collect(),
+                };
+                (func, args)
+            }
+            None => (func, vec![]),
+        };
 
         let source = if self.wasm_layers.is_empty() {
             Source::File(PathBuf::from(path))
@@ -109,24 +434,126 @@ impl RuntimeContext for WasiContext<'_> {
             .file_stem()
             .map(|name| name.to_string_lossy().to_string());
 
-        Entrypoint {
-            func: func.to_string(),
-            arg0: arg0.map(Path::new),
-            source,
-            name: module_name,
-        }
+        Entrypoint::new(func, module_name, arg0.map(Path::new), source).with_func_args(func_args)
     }
 
     fn platform(&self) -> &Platform {
         self.platform
     }
+
+    fn annotations(&self) -> &HashMap<String, String> {
+        static EMPTY: OnceLock<HashMap<String, String>> = OnceLock::new();
+        self.spec
+            .annotations()
+            .as_ref()
+            .unwrap_or_else(|| EMPTY.get_or_init(HashMap::new))
+    }
+
+    fn resources(&self) -> Option<&LinuxResources> {
+        self.spec.linux().as_ref()?.resources().as_ref()
+    }
+
+    fn volumes(&self) -> Vec<Volume> {
+        self.spec
+            .mounts()
+            .iter()
+            .flatten()
+            .filter_map(|mount| {
+                let options = mount.options().as_deref().unwrap_or_default();
+                let is_bind = mount.typ().as_deref() == Some("bind")
+                    || options.iter().any(|o| o == "bind" || o == "rbind");
+                let is_tmpfs = mount.typ().as_deref() == Some("tmpfs")
+                    && !mount.destination().starts_with("/dev");
+                if !is_bind && !is_tmpfs {
+                    return None;
+                }
+                // on Linux, libcontainer sets up the mounts of the container before the engine
+                // runs, elsewhere the engine opens the source of the mount
+                let host_path = if cfg!(unix) {
+                    mount.destination().clone()
+                } else {
+                    mount.source().clone()?
+                };
+                // files, e.g. /etc/hosts, can't be preopened
+                if !host_path.is_dir() {
+                    return None;
+                }
+                Some(Volume {
+                    host_path,
+                    guest_path: mount.destination().clone(),
+                    readonly: options.iter().any(|o| o == "ro"),
+                })
+            })
+            .collect()
+    }
+
+    fn readonly_rootfs(&self) -> bool {
+        self.spec
+            .root()
+            .as_ref()
+            .and_then(|root| root.readonly())
+            .unwrap_or(false)
+    }
+
+    fn record_log(&self) -> anyhow::Result<Option<RecordLog>> {
+        let Some(mode) = self.annotations().get(RECORD_MODE_ANNOTATION) else {
+            return Ok(None);
+        };
+        let mode = mode.parse()?;
+        let mount = self
+            .spec
+            .mounts()
+            .iter()
+            .flatten()
+            .find(|mount| mount.destination() == Path::new(RECORD_LOG_PATH))
+            .context("the record log is not mounted in the container")?;
+        // as for volumes, libcontainer mounts the log on Linux
+        let path = if cfg!(unix) {
+            mount.destination().clone()
+        } else {
+            mount
+                .source()
+                .clone()
+                .context("the record log has no source")?
+        };
+        Ok(Some(RecordLog { mode, path }))
+    }
+
+    fn coredump_file(&self) -> Option<PathBuf> {
+        let mount = self
+            .spec
+            .mounts()
+            .iter()
+            .flatten()
+            .find(|mount| mount.destination() == Path::new(COREDUMP_PATH))?;
+        if cfg!(unix) {
+            Some(mount.destination().clone())
+        } else {
+            mount.source().clone()
+        }
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.pool_stats.cloned()
+    }
+
+    fn threads(&self) -> Option<NonZeroUsize> {
+        self.threads
+    }
+
+    fn keyvalue(&self) -> Option<&KeyValue> {
+        self.keyvalue
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
     use oci_spec::image::Descriptor;
-    use oci_spec::runtime::{ProcessBuilder, RootBuilder, SpecBuilder};
+    use oci_spec::runtime::{
+        LinuxBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResourcesBuilder, MountBuilder,
+        ProcessBuilder, RootBuilder, SpecBuilder,
+    };
 
     use super::*;
 
@@ -146,6 +573,9 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         let args = ctx.args();
@@ -166,6 +596,9 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         let args = ctx.args();
@@ -174,6 +607,284 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_annotations() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .annotations(HashMap::from([("foo".to_string(), "bar".to_string())]))
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        assert_eq!(
+            ctx.annotations().get("foo").map(String::as_str),
+            Some("bar")
+        );
+
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        assert!(ctx.annotations().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_volumes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = dir.path().join("config");
+        let data = dir.path().join("data");
+        let hosts = dir.path().join("hosts");
+        let tmp = dir.path().join("tmp");
+        std::fs::create_dir(&config)?;
+        std::fs::create_dir(&data)?;
+        std::fs::create_dir(&tmp)?;
+        std::fs::write(&hosts, "")?;
+
+        let bind = |path: &Path, options: &[&str]| {
+            MountBuilder::default()
+                .destination(path)
+                .source(path)
+                .typ("bind")
+                .options(options.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+                .build()
+        };
+        let spec = SpecBuilder::default()
+            .root(
+                RootBuilder::default()
+                    .path("rootfs")
+                    .readonly(false)
+                    .build()?,
+            )
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .mounts(vec![
+                MountBuilder::default()
+                    .destination("/proc")
+                    .source("proc")
+                    .typ("proc")
+                    .build()?,
+                bind(&config, &["rbind", "ro"])?,
+                bind(&data, &["rbind", "rw"])?,
+                bind(&hosts, &["rbind", "ro"])?,
+                MountBuilder::default()
+                    .destination("/dev/shm")
+                    .source("shm")
+                    .typ("tmpfs")
+                    .build()?,
+                MountBuilder::default()
+                    .destination(&tmp)
+                    .source("tmpfs")
+                    .typ("tmpfs")
+                    .build()?,
+            ])
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        assert_eq!(
+            ctx.volumes(),
+            [
+                Volume {
+                    host_path: config.clone(),
+                    guest_path: config,
+                    readonly: true,
+                },
+                Volume {
+                    host_path: data.clone(),
+                    guest_path: data,
+                    readonly: false,
+                },
+                Volume {
+                    host_path: tmp.clone(),
+                    guest_path: tmp,
+                    readonly: false,
+                },
+            ]
+        );
+        assert!(!ctx.readonly_rootfs());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_execution_mode() -> Result<()> {
+        let spec = |mode: Option<&str>| {
+            let annotations = mode
+                .map(|mode| HashMap::from([(EXECUTION_MODE_ANNOTATION.to_string(), mode.into())]));
+            SpecBuilder::default()
+                .root(RootBuilder::default().path("rootfs").build()?)
+                .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+                .annotations(annotations.unwrap_or_default())
+                .build()
+        };
+        let mode = |mode: Option<&str>| -> Result<ExecutionMode> {
+            let spec = spec(mode)?;
+            let ctx = WasiContext {
+                spec: &spec,
+                wasm_layers: &[],
+                platform: &Platform::default(),
+                pool_stats: None,
+                threads: None,
+                keyvalue: None,
+            };
+            ctx.execution_mode()
+        };
+
+        assert_eq!(mode(None)?, ExecutionMode::Command);
+        assert_eq!(mode(Some("command"))?, ExecutionMode::Command);
+        assert_eq!(mode(Some("reactor"))?, ExecutionMode::Reactor);
+        assert!(mode(Some("daemon")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allows_capability() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .annotations(HashMap::from([(
+                DENIED_CAPABILITIES_ANNOTATION.to_string(),
+                "sockets, http".to_string(),
+            )]))
+            .build()?;
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        assert!(ctx.allows_capability("filesystem"));
+        assert!(!ctx.allows_capability("sockets"));
+        assert!(!ctx.allows_capability("http"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wasi_threads() -> Result<()> {
+        let spec = |enabled: &str, pids: i64| {
+            SpecBuilder::default()
+                .root(RootBuilder::default().path("rootfs").build()?)
+                .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+                .annotations(HashMap::from([(
+                    WASI_THREADS_ANNOTATION.to_string(),
+                    enabled.to_string(),
+                )]))
+                .linux(
+                    LinuxBuilder::default()
+                        .resources(
+                            LinuxResourcesBuilder::default()
+                                .pids(LinuxPidsBuilder::default().limit(pids).build()?)
+                                .build()?,
+                        )
+                        .build()?,
+                )
+                .build()
+        };
+        let threads = |enabled: &str, pids: i64| -> Result<(bool, Option<usize>)> {
+            let spec = spec(enabled, pids)?;
+            let ctx = WasiContext {
+                spec: &spec,
+                wasm_layers: &[],
+                platform: &Platform::default(),
+                pool_stats: None,
+                threads: None,
+                keyvalue: None,
+            };
+            Ok((
+                ctx.wasi_threads()?,
+                ctx.max_threads().map(NonZeroUsize::get),
+            ))
+        };
+
+        assert_eq!(threads("true", 16)?, (true, Some(16)));
+        assert_eq!(threads("false", 16)?, (false, Some(16)));
+        // a limit of 0 or -1 is no limit
+        assert_eq!(threads("true", 0)?, (true, None));
+        assert_eq!(threads("true", -1)?, (true, None));
+        assert!(threads("yes", 16).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_resources() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .linux(
+                LinuxBuilder::default()
+                    .resources(
+                        LinuxResourcesBuilder::default()
+                            .memory(LinuxMemoryBuilder::default().limit(1024).build()?)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        let memory = ctx.resources().and_then(|r| r.memory().as_ref());
+        assert_eq!(memory.and_then(|m| m.limit()), Some(1024));
+
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .linux(LinuxBuilder::default().build()?)
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        assert!(ctx.resources().and_then(|r| r.memory().as_ref()).is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn test_get_args_returns_all() -> Result<()> {
         let spec = SpecBuilder::default()
@@ -194,6 +905,9 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         let args = ctx.args();
@@ -216,6 +930,9 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         let path = ctx.entrypoint().source;
@@ -247,15 +964,21 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         let expected_path = PathBuf::from("hello.wat");
+        let entrypoint = ctx.entrypoint();
+        assert!(entrypoint.func_args().is_empty());
         let Entrypoint {
             name,
             func,
             arg0,
             source,
-        } = ctx.entrypoint();
+            ..
+        } = entrypoint;
         assert_eq!(name, Some("hello".to_string()));
         assert_eq!(func, "foo");
         assert_eq!(arg0, Some(Path::new("hello.wat#foo")));
@@ -287,6 +1010,9 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         let expected_path = PathBuf::from("/root/hello.wat");
@@ -295,6 +1021,7 @@ mod tests {
             func,
             arg0,
             source,
+            ..
         } = ctx.entrypoint();
         assert_eq!(name, Some("hello".to_string()));
         assert_eq!(func, "_start");
@@ -327,6 +1054,9 @@ mod tests {
             spec: &spec,
             wasm_layers: &[],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         let expected_path = PathBuf::from("/root/hello.wat");
@@ -338,6 +1068,54 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_entrypoint_from_wasm_wrapper() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        let script = dir.join("app");
+        std::fs::write(&script, "#!wasm modules/app.wasm#main\n")?;
+
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(
+                ProcessBuilder::default()
+                    .cwd("/")
+                    .args(vec![
+                        script.to_string_lossy().to_string(),
+                        "hello".to_string(),
+                    ])
+                    .build()?,
+            )
+            .build()?;
+
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        let Entrypoint {
+            name,
+            func,
+            arg0,
+            source,
+            ..
+        } = ctx.entrypoint();
+        let expected_path = dir.join("modules/app.wasm");
+        assert_eq!(name, Some("app".to_string()));
+        assert_eq!(func, "main");
+        assert_eq!(arg0, Some(script.as_path()));
+        assert!(matches!(
+            source,
+            Source::File(p) if p == expected_path
+        ));
+
+        Ok(())
+    }
+
     #[test]
     fn test_loading_strategy_is_oci_when_layers_present() -> Result<()> {
         let spec = SpecBuilder::default()
@@ -357,14 +1135,156 @@ mod tests {
         let ctx = WasiContext {
             spec: &spec,
             wasm_layers: &[WasmLayer {
-                layer: vec![],
+                layer: vec![].into(),
                 config: Descriptor::new(oci_spec::image::MediaType::Other("".to_string()), 10, ""),
             }],
             platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
         };
 
         assert!(matches!(ctx.entrypoint().source, Source::Oci(_)));
 
         Ok(())
     }
+
+    #[test]
+    fn test_entrypoint_from_wasm_artifact_layer() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .build()?;
+
+        let mut config = Descriptor::new(
+            oci_spec::image::MediaType::Other("application/wasm".to_string()),
+            10,
+            "",
+        );
+        config.set_annotations(Some(HashMap::from([
+            (ANNOTATION_TITLE.to_string(), "module.wasm".to_string()),
+            (
+                ENTRYPOINT_FUNCTION_ANNOTATION.to_string(),
+                "handle".to_string(),
+            ),
+        ])));
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[WasmLayer {
+                layer: vec![].into(),
+                config,
+            }],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        let entrypoint = ctx.entrypoint();
+        assert_eq!(entrypoint.name, Some("module".to_string()));
+        assert_eq!(entrypoint.func, "handle");
+        assert!(entrypoint.arg0.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_entrypoint_from_annotation() -> Result<()> {
+        let entrypoint = |annotation: &str| -> Result<(String, Vec<String>)> {
+            let spec = SpecBuilder::default()
+                .root(RootBuilder::default().path("rootfs").build()?)
+                .process(
+                    ProcessBuilder::default()
+                        .cwd("/")
+                        .args(vec!["hello.wat#foo".to_string()])
+                        .build()?,
+                )
+                .annotations(HashMap::from([(
+                    ENTRYPOINT_ANNOTATION.to_string(),
+                    annotation.to_string(),
+                )]))
+                .build()?;
+            let ctx = WasiContext {
+                spec: &spec,
+                wasm_layers: &[],
+                platform: &Platform::default(),
+                pool_stats: None,
+                threads: None,
+                keyvalue: None,
+            };
+            let entrypoint = ctx.entrypoint();
+            Ok((entrypoint.func.clone(), entrypoint.func_args().to_vec()))
+        };
+
+        assert_eq!(
+            entrypoint("handle#GET,/index.html")?,
+            ("handle".into(), vec!["GET".into(), "/index.html".into()])
+        );
+        assert_eq!(entrypoint("handle")?, ("handle".into(), vec![]));
+        assert_eq!(entrypoint("#hello")?, ("foo".into(), vec!["hello".into()]));
+        assert_eq!(
+            entrypoint("handle#a,,b")?,
+            ("handle".into(), vec!["a".into(), "".into(), "b".into()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyvalue_scope() {
+        let pod = HashMap::from([
+            (CRI_SANDBOX_NAMESPACE.to_string(), "default".to_string()),
+            (CRI_SANDBOX_NAME.to_string(), "web".to_string()),
+            (CRI_CONTAINER_NAME.to_string(), "app".to_string()),
+        ]);
+        let keyvalue = KeyValue::new("memory".into(), "k8s.io", "abc", &pod);
+        assert_eq!(keyvalue.store, "memory");
+        assert_eq!(keyvalue.scope, "k8s.io/default/web/app");
+
+        let keyvalue = KeyValue::new("memory".into(), "default", "abc", &HashMap::new());
+        assert_eq!(keyvalue.scope, "default/abc");
+
+        let mut pod = pod;
+        pod.insert(CRI_SANDBOX_NAME.to_string(), "web/other".to_string());
+        let keyvalue = KeyValue::new("memory".into(), "k8s.io", "abc", &pod);
+        assert_eq!(keyvalue.scope, "k8s.io/abc");
+    }
+
+    #[test]
+    fn test_default_context() -> Result<()> {
+        // a context of another shim, implementing the required methods only
+        struct MinimalContext(Platform);
+
+        impl RuntimeContext for MinimalContext {
+            fn args(&self) -> &[String] {
+                &[]
+            }
+
+            fn entrypoint(&self) -> Entrypoint {
+                Entrypoint::new(
+                    "_start",
+                    None,
+                    None,
+                    Source::File(PathBuf::from("app.wasm")),
+                )
+            }
+
+            fn platform(&self) -> &Platform {
+                &self.0
+            }
+
+            fn resources(&self) -> Option<&LinuxResources> {
+                None
+            }
+        }
+
+        let ctx = MinimalContext(Platform::default());
+        assert!(ctx.annotations().is_empty());
+        assert!(ctx.volumes().is_empty());
+        assert!(!ctx.readonly_rootfs());
+        assert!(ctx.record_log()?.is_none());
+        assert!(ctx.coredump_file().is_none());
+        assert_eq!(ctx.execution_mode()?, ExecutionMode::Command);
+        Ok(())
+    }
 }
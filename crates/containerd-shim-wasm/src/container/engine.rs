@@ -4,10 +4,34 @@ use std::io::Read;
 use anyhow::{bail, Context, Result};
 
 use super::Source;
-use crate::container::{PathResolve, RuntimeContext};
+use crate::container::{ExecutionMode, PathResolve, RuntimeContext};
 use crate::sandbox::oci::WasmLayer;
 use crate::sandbox::Stdio;
 
+/// What the layers precompiled by an engine can run on.
+/// It is recorded in the `runwasi.io/precompiled/engine-version`, `runwasi.io/precompiled/target`
+/// and `runwasi.io/precompiled/target-features` labels of the precompiled layers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrecompileTarget {
+    /// The version of the engine, e.g., the version of the shim bundling the runtime
+    pub version: String,
+    /// The architecture and OS the layers are compiled for, e.g., `x86_64-linux`
+    pub target: String,
+    /// The CPU features the layers are compiled for, e.g., `["avx2", "bmi2"]`
+    pub features: Vec<String>,
+}
+
+impl PrecompileTarget {
+    /// A target with the architecture and OS of the shim
+    pub fn host(version: impl Into<String>) -> Self {
+        Self {
+            version: version.into(),
+            target: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+            features: vec![],
+        }
+    }
+}
+
 pub trait Engine: Clone + Send + Sync + 'static {
     /// The name to use for this engine
     fn name() -> &'static str;
@@ -15,6 +39,20 @@ pub trait Engine: Clone + Send + Sync + 'static {
     /// Run a WebAssembly container
     fn run_wasi(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32>;
 
+    /// Run a WebAssembly container as a reactor.
+    /// This is called instead of `run_wasi` when the execution mode of the container is
+    /// `ExecutionMode::Reactor`.
+    /// Rather than calling the entrypoint and exiting, the engine instantiates the module,
+    /// calls its `_initialize` or `init` export, and keeps the instance alive servicing
+    /// invocations until the container is killed.
+    /// The default implementation returns an error, as reactors are not supported.
+    fn run_reactor(&self, _ctx: &impl RuntimeContext, _stdio: Stdio) -> Result<i32> {
+        bail!(
+            "the {} engine does not support reactor modules",
+            Self::name()
+        );
+    }
+
     /// Check that the runtime can run the container.
     /// This checks runs after the container creation and before the container starts.
     /// By it checks that the wasi_entrypoint is either:
@@ -54,6 +92,16 @@ pub trait Engine: Clone + Send + Sync + 'static {
         &["application/vnd.bytecodealliance.wasm.component.layer.v0+wasm"]
     }
 
+    /// Return the imports the engine provides to the guests, as prefixes of the import modules of
+    /// a module, e.g., `wasi_snapshot_preview1`, or of the imported interfaces of a component,
+    /// e.g., `wasi:`.
+    /// The shim inspects the imports of the module when the container is created, and fails
+    /// with the imports the engine can't provide, rather than when the container starts.
+    /// The default implementation returns `None`, and the imports are not checked.
+    fn supported_imports() -> Option<&'static [&'static str]> {
+        None
+    }
+
     /// Precompile passes supported OCI layers to engine for compilation
     /// This is used to precompile the layers before they are run and will be called if `can_precompile` returns `true`.
     /// It is called only the first time a module is run and the resulting bytes will be cached in the containerd content store.  
@@ -78,4 +126,22 @@ pub trait Engine: Clone + Send + Sync + 'static {
     fn can_precompile(&self) -> Option<String> {
         None
     }
+
+    /// The target of the layers precompiled by the engine.
+    /// The shim records it in the labels of the precompiled layers, and precompiles a layer again
+    /// when its labels don't match the target of the engine, e.g., after the shim is upgraded,
+    /// rather than failing to load the stale layer when the container starts.
+    /// The default implementation returns the architecture and OS of the shim, without a version.
+    fn precompile_target(&self) -> PrecompileTarget {
+        PrecompileTarget::host("")
+    }
+
+    /// Run a WebAssembly container in its execution mode,
+    /// with either `run_wasi` or `run_reactor`.
+    fn run(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
+        match ctx.execution_mode()? {
+            ExecutionMode::Command => self.run_wasi(ctx, stdio),
+            ExecutionMode::Reactor => self.run_reactor(ctx, stdio),
+        }
+    }
 }
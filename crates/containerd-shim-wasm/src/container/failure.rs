@@ -0,0 +1,191 @@
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+/// A failure of the guest that the shim publishes as an event, for monitoring systems to alert on.
+///
+/// Engines return it from `run_wasi`, e.g., as the context of the error of the guest, and the shim
+/// publishes a `/tasks/trapped` event for traps, or a `/tasks/oom` event when the guest failed
+/// after reaching its memory limit.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+pub enum GuestFailure {
+    /// The guest trapped, `code` identifies the trap, e.g., `UnreachableCodeReached`, and
+    /// `backtrace` has the frames of the guest when it trapped, innermost first, if the engine
+    /// captured them
+    #[error("guest trapped with {code}: {message}")]
+    Trap {
+        code: String,
+        message: String,
+        #[serde(default)]
+        backtrace: Vec<BacktraceFrame>,
+    },
+    /// The guest failed after its memory limit refused to grow its memory
+    #[error("guest ran out of memory: {message}")]
+    OutOfMemory { message: String },
+}
+
+impl GuestFailure {
+    /// Returns the code of the failure
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Trap { .. } => ErrorCode::Trap,
+            Self::OutOfMemory { .. } => ErrorCode::OutOfMemory,
+        }
+    }
+}
+
+/// A frame of the wasm backtrace of a trap, symbolicated with the name section of the module, or
+/// with its DWARF when it has some.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BacktraceFrame {
+    /// The name of the module of the function, from its name section
+    pub module: Option<String>,
+    /// The index of the function in its module
+    pub func_index: u32,
+    /// The demangled name of the function, from the DWARF or the name section of the module
+    pub func_name: Option<String>,
+    /// The offset of the instruction in the module
+    pub offset: Option<usize>,
+    /// The source file of the instruction, from the DWARF of the module
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+/// Formats the wasm backtrace of a trap, like wasmtime does, e.g.,
+///
+/// ```text
+/// wasm backtrace:
+///     0:   0x1f - hello.wasm!hello::main
+///                     at src/main.rs:3:5
+/// ```
+pub(crate) fn format_backtrace(backtrace: &[BacktraceFrame]) -> String {
+    let mut out = String::from("wasm backtrace:");
+    for (i, frame) in backtrace.iter().enumerate() {
+        let _ = write!(out, "\n  {i:>3}: ");
+        if let Some(offset) = frame.offset {
+            let _ = write!(out, "{offset:#6x} - ");
+        }
+        let module = frame.module.as_deref().unwrap_or("<unknown>");
+        let _ = match &frame.func_name {
+            Some(name) => write!(out, "{module}!{name}"),
+            None => write!(out, "{module}!<wasm function {}>", frame.func_index),
+        };
+        if let Some(file) = &frame.file {
+            let _ = write!(out, "\n                    at {file}");
+            if let Some(line) = frame.line {
+                let _ = write!(out, ":{line}");
+                if let Some(column) = frame.column {
+                    let _ = write!(out, ":{column}");
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The kind of a failure of a task, surfaced to containerd, and to `kubectl describe pod`, with a
+/// distinct ttrpc status code when the task can't be created, or a distinct exit status when its
+/// process fails, rather than a generic `137`.
+///
+/// Engines attach it as the context of the errors of `run_wasi`, e.g.,
+/// `Module::from_binary(&engine, bytes).context(ErrorCode::CompileFailed)`. The failures of the
+/// guest, see [`GuestFailure`], have the `trap` and `oom` codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+pub enum ErrorCode {
+    /// The wasm module of the task can't be found, exits with `127`
+    #[error("module-not-found")]
+    ModuleNotFound,
+    /// The wasm module of the task isn't a module, a component or a precompiled artifact the
+    /// engine can run, exits with `126`
+    #[error("invalid-wasm")]
+    InvalidWasm,
+    /// The engine can't provide an import of the module, exits with `125`
+    #[error("unsupported-import")]
+    UnsupportedImport,
+    /// The engine failed to compile the module, exits with `124`
+    #[error("compile-failed")]
+    CompileFailed,
+    /// The guest trapped, exits with `123`
+    #[error("trap")]
+    Trap,
+    /// The guest ran out of memory, exits with `122`
+    #[error("oom")]
+    OutOfMemory,
+}
+
+impl ErrorCode {
+    /// Returns the exit status of a process that failed with this code
+    /// The exit statuses are under 128, so that they aren't mistaken for a signal.
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            Self::ModuleNotFound => 127,
+            Self::InvalidWasm => 126,
+            Self::UnsupportedImport => 125,
+            Self::CompileFailed => 124,
+            Self::Trap => 123,
+            Self::OutOfMemory => 122,
+        }
+    }
+
+    /// Returns the code attached to `err`, or the code of the failure of the guest it carries
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        err.downcast_ref::<Self>()
+            .copied()
+            .or_else(|| err.downcast_ref::<GuestFailure>().map(GuestFailure::code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_error_code() {
+        let err = anyhow::anyhow!("unexpected end").context(ErrorCode::CompileFailed);
+        let err = Err::<(), _>(err).context("failed to run").unwrap_err();
+        assert_eq!(ErrorCode::of(&err), Some(ErrorCode::CompileFailed));
+        assert_eq!(
+            format!("{:#}", err),
+            "failed to run: compile-failed: unexpected end"
+        );
+
+        let err = anyhow::Error::new(GuestFailure::OutOfMemory {
+            message: "memory.grow failed".to_string(),
+        });
+        assert_eq!(ErrorCode::of(&err), Some(ErrorCode::OutOfMemory));
+        assert_eq!(ErrorCode::of(&anyhow::anyhow!("failed")), None);
+
+        assert_eq!(ErrorCode::ModuleNotFound.exit_status(), 127);
+        assert!(ErrorCode::OutOfMemory.exit_status() < 128);
+    }
+
+    #[test]
+    fn test_format_backtrace() {
+        let backtrace = [
+            BacktraceFrame {
+                module: Some("hello.wasm".to_string()),
+                func_index: 12,
+                func_name: Some("hello::divide".to_string()),
+                offset: Some(0x1f3),
+                file: Some("src/main.rs".to_string()),
+                line: Some(3),
+                column: Some(5),
+            },
+            BacktraceFrame {
+                func_index: 7,
+                offset: Some(0x42),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            format_backtrace(&backtrace),
+            "wasm backtrace:\n    \
+               0:  0x1f3 - hello.wasm!hello::divide\n                    \
+               at src/main.rs:3:5\n    \
+               1:   0x42 - <unknown>!<wasm function 7>"
+        );
+    }
+}
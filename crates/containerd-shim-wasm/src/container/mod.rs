@@ -10,16 +10,29 @@
 //! * Less customizable
 //! * Currently only works on Linux
 
+mod capabilities;
 mod context;
 mod engine;
+mod failure;
 mod path;
+mod pool;
 mod wasm;
 
+pub(crate) use capabilities::inspect_container;
+pub use capabilities::CapabilityReport;
 pub(crate) use context::WasiContext;
-pub use context::{Entrypoint, RuntimeContext, Source};
-pub use engine::Engine;
+pub use context::{
+    Entrypoint, ExecutionMode, KeyValue, RecordLog, RecordMode, RuntimeContext, Source, Volume,
+    DENIED_CAPABILITIES_ANNOTATION, ENTRYPOINT_ANNOTATION, EXECUTION_MODE_ANNOTATION,
+    RECORD_MODE_ANNOTATION, WASI_THREADS_ANNOTATION,
+};
+pub use engine::{Engine, PrecompileTarget};
+pub(crate) use failure::format_backtrace;
+pub use failure::{BacktraceFrame, ErrorCode, GuestFailure};
 pub use instance::Instance;
-pub use path::PathResolve;
+pub(crate) use path::wasm_wrapper_target;
+pub use path::{PathResolve, WASM_SHEBANG};
+pub use pool::{InstanceStats, PoolMetrics, PoolStats, POOL_METRICS_FIELD};
 pub use wasm::WasmBinaryType;
 
 pub use crate::sandbox::stdio::Stdio;
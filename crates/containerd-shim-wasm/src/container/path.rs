@@ -1,5 +1,12 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
 
+/// The interpreter of the wrapper scripts running a wasm module of the rootfs, e.g., a
+/// `/usr/bin/app` script with `#!wasm /app/app.wasm#main` as its first line.
+/// A relative module is resolved relative to the directory of the script.
+pub const WASM_SHEBANG: &str = "#!wasm";
+
 pub trait PathResolve {
     // Resolve the path of a file give a set of directories as the `which` unix
     // command would do with components of the `PATH` environment variable, and
@@ -71,3 +78,71 @@ impl<T: AsRef<Path>> PathResolve for T {
         self.resolve_in_dirs(paths().chain(std::env::current_dir().ok()))
     }
 }
+
+// Returns the module, with its optional `#func` suffix, run by a `#!wasm` wrapper script, or
+// `None` if `script` is not one.
+pub(crate) fn wasm_wrapper_target(script: impl AsRef<Path>) -> Option<String> {
+    let script = script.as_ref().resolve_in_path_or_cwd().next()?;
+    let mut line = String::new();
+    // binary files, e.g., modules, are not valid utf-8
+    BufReader::new(File::open(&script).ok()?)
+        .take(4096)
+        .read_line(&mut line)
+        .ok()?;
+    let target = line.strip_prefix(WASM_SHEBANG)?;
+    if !target.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let target = target.trim();
+    let (module, func) = match target.split_once('#') {
+        Some((module, func)) => (module, Some(func)),
+        None => (target, None),
+    };
+    if module.is_empty() {
+        return None;
+    }
+    // the script is canonical, and an absolute module replaces its directory
+    let module = script.parent()?.join(module);
+    Some(match func {
+        Some(func) => format!("{}#{func}", module.display()),
+        None => module.display().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wasm_wrapper_target() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path().canonicalize()?;
+        std::fs::create_dir(dir.join("bin"))?;
+        std::fs::write(dir.join("bin/relative"), "#!wasm ../app/app.wasm\n")?;
+        std::fs::write(dir.join("bin/absolute"), "#!wasm /app/app.wasm#main\n")?;
+        std::fs::write(dir.join("bin/native"), "#!/bin/sh\necho hello\n")?;
+        std::fs::write(dir.join("bin/wasmtime"), "#!wasmtime app.wasm\n")?;
+        std::fs::write(dir.join("bin/module.wasm"), b"\0asm\x01\0\0\0\xff\xfe")?;
+        std::os::unix::fs::symlink(dir.join("bin/relative"), dir.join("link"))?;
+
+        assert_eq!(
+            wasm_wrapper_target(dir.join("bin/relative")),
+            Some(format!("{}/bin/../app/app.wasm", dir.display()))
+        );
+        assert_eq!(
+            wasm_wrapper_target(dir.join("bin/absolute")),
+            Some("/app/app.wasm#main".to_string())
+        );
+        // symlinks are followed to the script
+        assert_eq!(
+            wasm_wrapper_target(dir.join("link")),
+            Some(format!("{}/bin/../app/app.wasm", dir.display()))
+        );
+        assert_eq!(wasm_wrapper_target(dir.join("bin/native")), None);
+        assert_eq!(wasm_wrapper_target(dir.join("bin/wasmtime")), None);
+        assert_eq!(wasm_wrapper_target(dir.join("bin/module.wasm")), None);
+        assert_eq!(wasm_wrapper_target(dir.join("missing")), None);
+        Ok(())
+    }
+}
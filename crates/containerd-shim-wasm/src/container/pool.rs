@@ -0,0 +1,363 @@
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use protobuf::well_known_types::empty::Empty;
+use protobuf::Message;
+use serde::{Deserialize, Serialize};
+
+/// Field of the cgroup `Metrics` message of the task stats carrying the [`PoolMetrics`] of the
+/// container, as an extension that the consumers of the standard fields, e.g., `ctr task metrics`
+/// or the CRI, ignore.
+pub const POOL_METRICS_FIELD: u32 = 1000;
+
+/// The counters of the pool of ready instances an engine keeps to serve the requests of a
+/// container, e.g., with the `runwasi.io/concurrency` annotation of the wasmtime shim, and of the
+/// wasm instances of the container, in or out of a pool, with their linear memory.
+///
+/// The counters are shared by the process of the container, where the engine updates them, and
+/// the shim, which reports them in the stats of the task.
+#[derive(Clone)]
+pub struct PoolStats {
+    counters: Arc<Counters>,
+}
+
+/// A snapshot of the [`PoolStats`] of a container
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolMetrics {
+    /// The number of instances of the pool
+    pub size: u64,
+    /// The instances ready to handle a request
+    pub ready: u64,
+    /// The instances handling a request
+    pub busy: u64,
+    /// The requests waiting for a ready instance
+    pub queued: u64,
+    /// The requests handled by the pool
+    pub requests: u64,
+    /// The instances replaced after failing to handle a request
+    pub recycled: u64,
+    /// The wasm instances alive in the container, e.g., the stores of the engine
+    pub instances: u64,
+    /// The bytes of linear memory of the wasm instances
+    pub memory: u64,
+}
+
+/// A wasm instance counted in the [`PoolStats`] of its container, until it is dropped
+pub struct InstanceStats {
+    stats: PoolStats,
+    memory: AtomicU64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SharedCounters {
+    size: AtomicU64,
+    ready: AtomicU64,
+    busy: AtomicU64,
+    queued: AtomicU64,
+    requests: AtomicU64,
+    recycled: AtomicU64,
+    instances: AtomicU64,
+    memory: AtomicU64,
+}
+
+enum Counters {
+    Local(SharedCounters),
+    // mapped in the shim, and inherited by the process of the container
+    #[cfg(unix)]
+    Mapped(NonNull<SharedCounters>),
+}
+
+// the mapping is only accessed through atomics
+unsafe impl Send for Counters {}
+unsafe impl Sync for Counters {}
+
+impl Default for PoolStats {
+    /// Counters local to the process, e.g., for tests
+    fn default() -> Self {
+        Self {
+            counters: Arc::new(Counters::Local(SharedCounters::default())),
+        }
+    }
+}
+
+impl std::fmt::Debug for PoolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PoolStats").field(&self.metrics()).finish()
+    }
+}
+
+impl PoolStats {
+    /// Counters in a shared memory mapping, inherited by the processes the shim forks
+    #[cfg(unix)]
+    pub(crate) fn shared() -> io::Result<Self> {
+        let len = std::mem::size_of::<SharedCounters>();
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // anonymous mappings are zeroed, which are valid counters
+        let counters = NonNull::new(addr.cast()).ok_or_else(|| io::Error::other("null mapping"))?;
+        Ok(Self {
+            counters: Arc::new(Counters::Mapped(counters)),
+        })
+    }
+
+    fn counters(&self) -> &SharedCounters {
+        match self.counters.as_ref() {
+            Counters::Local(counters) => counters,
+            #[cfg(unix)]
+            Counters::Mapped(counters) => unsafe { counters.as_ref() },
+        }
+    }
+
+    /// Clears the counters, e.g., when the process of the container is replaced
+    pub(crate) fn reset(&self) {
+        let counters = self.counters();
+        for counter in [
+            &counters.size,
+            &counters.ready,
+            &counters.busy,
+            &counters.queued,
+            &counters.requests,
+            &counters.recycled,
+            &counters.instances,
+            &counters.memory,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Sets the number of instances of the pool
+    pub fn set_size(&self, size: u64) {
+        self.counters().size.store(size, Ordering::Relaxed);
+    }
+
+    /// An instance of the pool is ready to handle a request
+    pub fn instance_ready(&self) {
+        self.counters().ready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request is waiting for a ready instance
+    pub fn request_queued(&self) {
+        self.counters().queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A ready instance started handling a queued request
+    pub fn request_started(&self) {
+        let counters = self.counters();
+        counters.queued.fetch_sub(1, Ordering::Relaxed);
+        counters.ready.fetch_sub(1, Ordering::Relaxed);
+        counters.busy.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An instance finished handling a request, and is recycled if it failed
+    pub fn request_finished(&self, failed: bool) {
+        let counters = self.counters();
+        counters.busy.fetch_sub(1, Ordering::Relaxed);
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            counters.recycled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a new wasm instance of the container
+    pub fn instance_created(&self) -> InstanceStats {
+        self.counters().instances.fetch_add(1, Ordering::Relaxed);
+        InstanceStats {
+            stats: self.clone(),
+            memory: AtomicU64::new(0),
+        }
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        let counters = self.counters();
+        PoolMetrics {
+            size: counters.size.load(Ordering::Relaxed),
+            ready: counters.ready.load(Ordering::Relaxed),
+            busy: counters.busy.load(Ordering::Relaxed),
+            queued: counters.queued.load(Ordering::Relaxed),
+            requests: counters.requests.load(Ordering::Relaxed),
+            recycled: counters.recycled.load(Ordering::Relaxed),
+            instances: counters.instances.load(Ordering::Relaxed),
+            memory: counters.memory.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl InstanceStats {
+    /// The linear memory of the instance grew by `bytes`
+    pub fn memory_grown(&self, bytes: u64) {
+        self.memory.fetch_add(bytes, Ordering::Relaxed);
+        self.stats
+            .counters()
+            .memory
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+impl Drop for InstanceStats {
+    fn drop(&mut self) {
+        let counters = self.stats.counters();
+        counters.instances.fetch_sub(1, Ordering::Relaxed);
+        counters
+            .memory
+            .fetch_sub(self.memory.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Debug for InstanceStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstanceStats")
+            .field("memory", &self.memory)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Counters {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Counters::Mapped(counters) = self {
+            let len = std::mem::size_of::<SharedCounters>();
+            unsafe { libc::munmap(counters.as_ptr().cast(), len) };
+        }
+    }
+}
+
+impl PoolMetrics {
+    /// Encodes the metrics as the [`POOL_METRICS_FIELD`] of the cgroup `Metrics`, a message with
+    /// the counters as `uint64` fields, in the order of the struct.
+    pub fn encode(&self) -> protobuf::Result<Vec<u8>> {
+        let mut message = Empty::new();
+        let fields = message.special_fields.mut_unknown_fields();
+        for (number, value) in (1..).zip(self.values()) {
+            fields.add_varint(number, value);
+        }
+        message.write_to_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> protobuf::Result<Self> {
+        let message = Empty::parse_from_bytes(bytes)?;
+        let fields = message.special_fields.unknown_fields();
+        let value = |number| match fields.get(number) {
+            Some(protobuf::UnknownValueRef::Varint(value)) => value,
+            _ => 0,
+        };
+        Ok(Self {
+            size: value(1),
+            ready: value(2),
+            busy: value(3),
+            queued: value(4),
+            requests: value(5),
+            recycled: value(6),
+            instances: value(7),
+            memory: value(8),
+        })
+    }
+
+    fn values(&self) -> [u64; 8] {
+        [
+            self.size,
+            self.ready,
+            self.busy,
+            self.queued,
+            self.requests,
+            self.recycled,
+            self.instances,
+            self.memory,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_stats() {
+        let stats = PoolStats::default();
+        stats.set_size(2);
+        stats.instance_ready();
+        stats.instance_ready();
+        stats.request_queued();
+        stats.request_started();
+        assert_eq!(
+            stats.metrics(),
+            PoolMetrics {
+                size: 2,
+                ready: 1,
+                busy: 1,
+                ..Default::default()
+            }
+        );
+        stats.request_finished(true);
+        assert_eq!(stats.metrics().requests, 1);
+        assert_eq!(stats.metrics().recycled, 1);
+    }
+
+    #[test]
+    fn test_instance_stats() {
+        let stats = PoolStats::default();
+        let first = stats.instance_created();
+        let second = stats.instance_created();
+        first.memory_grown(65536);
+        second.memory_grown(2 * 65536);
+        first.memory_grown(65536);
+        assert_eq!(stats.metrics().instances, 2);
+        assert_eq!(stats.metrics().memory, 4 * 65536);
+
+        // the memory of an instance is freed with it
+        drop(second);
+        assert_eq!(stats.metrics().instances, 1);
+        assert_eq!(stats.metrics().memory, 2 * 65536);
+        drop(first);
+        assert_eq!(stats.metrics(), PoolMetrics::default());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_pool_stats() -> io::Result<()> {
+        let stats = PoolStats::shared()?;
+        match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {
+                stats.set_size(4);
+                unsafe { libc::_exit(0) };
+            }
+            pid => unsafe {
+                libc::waitpid(pid, std::ptr::null_mut(), 0);
+            },
+        }
+        assert_eq!(stats.metrics().size, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_pool_metrics() -> protobuf::Result<()> {
+        let metrics = PoolMetrics {
+            size: 8,
+            ready: 5,
+            busy: 3,
+            queued: 0,
+            requests: 42,
+            recycled: 1,
+            instances: 8,
+            memory: 8 << 16,
+        };
+        assert_eq!(PoolMetrics::decode(&metrics.encode()?)?, metrics);
+        Ok(())
+    }
+}
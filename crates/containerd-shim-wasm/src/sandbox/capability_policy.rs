@@ -0,0 +1,145 @@
+//! Node-level policy of the WASI capabilities allowed to the containers.
+//!
+//! When [`CAPABILITY_POLICY_ENV`] is set to the path of a TOML policy file, the shim denies the
+//! capabilities the policy denies to a container, by its namespace or the labels of the container,
+//! which include the labels of its image, e.g.:
+//!
+//! ```toml
+//! # denied to every container, unless a rule allows them
+//! deny = ["sockets", "http", "nn"]
+//!
+//! [[rules]]
+//! namespace = "trusted"
+//! allow = ["sockets", "http"]
+//!
+//! [[rules]]
+//! labels = { "runwasi.io/tenant" = "payments" }
+//! deny = ["filesystem"]
+//! ```
+//!
+//! The rules matching a container apply in order, and a rule matches when its namespace, if any,
+//! and all its labels match. The capabilities are the ones of the capability report of the
+//! module, e.g., `filesystem`, `sockets`, `http`, `nn`, `random` or `clocks`.
+//! A container whose module imports a denied capability fails to be created, as does a container
+//! denied some capabilities whose module can't be inspected. The engines also leave the denied
+//! capabilities they provide out of the WASI context, see
+//! [`RuntimeContext::allows_capability`](crate::container::RuntimeContext::allows_capability).
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::sandbox::error::{Error as ShimError, Result};
+use crate::sandbox::node_config;
+
+/// Environment variable with the path of the capability policy
+pub const CAPABILITY_POLICY_ENV: &str = "RUNWASI_CAPABILITY_POLICY";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CapabilityPolicy {
+    /// The capabilities denied to every container, unless a rule allows them
+    #[serde(default)]
+    deny: Vec<String>,
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    namespace: Option<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl Rule {
+    fn matches(&self, namespace: &str, labels: &HashMap<String, String>) -> bool {
+        self.namespace.as_deref().map_or(true, |ns| ns == namespace)
+            && self
+                .labels
+                .iter()
+                .all(|(key, value)| labels.get(key) == Some(value))
+    }
+}
+
+impl CapabilityPolicy {
+    /// Loads the policy from [`CAPABILITY_POLICY_ENV`], if it is set
+    pub fn from_env() -> Result<Option<Self>> {
+        match node_config::var_os(CAPABILITY_POLICY_ENV) {
+            Some(path) if !path.is_empty() => Self::load(Path::new(&path)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let policy = fs::read_to_string(path).map_err(|err| {
+            ShimError::FailedPrecondition(format!(
+                "failed to read capability policy {path:?}: {err}"
+            ))
+        })?;
+        toml::from_str(&policy).map_err(|err| {
+            ShimError::InvalidArgument(format!("invalid capability policy {path:?}: {err}"))
+        })
+    }
+
+    /// Returns the capabilities denied to a container of `namespace` with `labels`
+    pub fn denied(&self, namespace: &str, labels: &HashMap<String, String>) -> BTreeSet<String> {
+        let mut denied: BTreeSet<String> = self.deny.iter().cloned().collect();
+        for rule in self.rules.iter().filter(|r| r.matches(namespace, labels)) {
+            for capability in &rule.allow {
+                denied.remove(capability);
+            }
+            denied.extend(rule.deny.iter().cloned());
+        }
+        denied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denied_capabilities() {
+        let policy: CapabilityPolicy = toml::from_str(
+            r#"
+            deny = ["sockets", "http", "nn"]
+
+            [[rules]]
+            namespace = "trusted"
+            allow = ["sockets", "http"]
+
+            [[rules]]
+            labels = { "runwasi.io/tenant" = "payments" }
+            deny = ["filesystem"]
+            "#,
+        )
+        .unwrap();
+
+        let labels =
+            |tenant: &str| HashMap::from([("runwasi.io/tenant".to_string(), tenant.to_string())]);
+        let denied = |namespace, labels| {
+            policy
+                .denied(namespace, &labels)
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(denied("default", HashMap::new()), ["http", "nn", "sockets"]);
+        assert_eq!(denied("trusted", labels("search")), ["nn"]);
+        assert_eq!(denied("trusted", labels("payments")), ["filesystem", "nn"]);
+        assert_eq!(
+            denied("k8s.io", labels("payments")),
+            ["filesystem", "http", "nn", "sockets"]
+        );
+
+        assert!(toml::from_str::<CapabilityPolicy>("allow = [\"http\"]").is_err());
+    }
+}
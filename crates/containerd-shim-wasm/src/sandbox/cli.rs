@@ -1,15 +1,19 @@
+use std::ffi::OsString;
 use std::path::PathBuf;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 
-use containerd_shim::{parse, run, Config};
+use containerd_shim::{parse, run, Config, Flags};
 use ttrpc::Server;
 
+use crate::sandbox::install::{self, InstallOptions};
 use crate::sandbox::manager::Shim;
 use crate::sandbox::shim::Local;
 #[cfg(feature = "opentelemetry")]
-use crate::sandbox::shim::{otel_traces_enabled, OTLPConfig};
-use crate::sandbox::{Instance, ManagerService, ShimCli};
+use crate::sandbox::shim::{
+    otel_logs_enabled, otel_metrics_enabled, otel_traces_enabled, OTLPConfig, PrometheusConfig,
+};
+use crate::sandbox::{Instance, InstanceConfig, ManagerService};
 use crate::services::sandbox_ttrpc::{create_manager, Manager};
 
 pub mod r#impl {
@@ -18,6 +22,10 @@ pub mod r#impl {
 
 pub use crate::{revision, version};
 
+// defaults of the `precompile`, `pull` and `gc` commands, the same as `ctr`
+const DEFAULT_NAMESPACE: &str = "default";
+const DEFAULT_CONTAINERD_ADDRESS: &str = "/run/containerd/containerd.sock";
+
 #[macro_export]
 macro_rules! version {
     () => {
@@ -43,6 +51,7 @@ macro_rules! revision {
 /// If the `opentelemetry` feature is enabled, this function will start the shim with OpenTelemetry tracing.
 ///
 /// It parses OTLP configuration from the environment and initializes the OpenTelemetry SDK.
+/// If Prometheus metrics are enabled, it also initializes the meter provider.
 pub fn shim_main<'a, I>(
     name: &str,
     version: &str,
@@ -54,15 +63,23 @@ pub fn shim_main<'a, I>(
     I::Engine: Default,
 {
     #[cfg(feature = "opentelemetry")]
-    if otel_traces_enabled() {
+    if otel_traces_enabled() || otel_logs_enabled() || otel_metrics_enabled() {
         // opentelemetry uses tokio, so we need to initialize a runtime
         use tokio::runtime::Runtime;
         let rt = Runtime::new().unwrap();
         rt.block_on(async {
-            let _guard = OTLPConfig::build_from_env()
-                .expect("Failed to build OtelConfig.")
-                .init()
-                .expect("Failed to initialize OpenTelemetry.");
+            let _guard = (otel_traces_enabled() || otel_logs_enabled()).then(|| {
+                OTLPConfig::build_from_env()
+                    .expect("Failed to build OtelConfig.")
+                    .init()
+                    .expect("Failed to initialize OpenTelemetry.")
+            });
+            let _metrics_guard = otel_metrics_enabled().then(|| {
+                PrometheusConfig::build_from_env()
+                    .expect("Failed to build PrometheusConfig.")
+                    .init()
+                    .expect("Failed to initialize OpenTelemetry metrics.")
+            });
             shim_main_inner::<I>(name, version, revision, shim_version, config);
         });
     } else {
@@ -75,6 +92,129 @@ pub fn shim_main<'a, I>(
     }
 }
 
+// Returns the image argument of the `precompile` and `pull` actions
+fn image_arg<'a>(action: &str, args: &'a [OsString]) -> Option<&'a str> {
+    args.iter()
+        .skip_while(|arg| *arg != action)
+        .nth(1)
+        .and_then(|image| image.to_str())
+}
+
+// Returns the config of the `precompile`, `pull` and `gc` commands, with the namespace and address of
+// containerd from the flags, or their defaults
+fn command_config<I>(flags: &Flags) -> InstanceConfig<I::Engine>
+where
+    I: Instance,
+    I::Engine: Default,
+{
+    let namespace = match flags.namespace.as_str() {
+        "" => DEFAULT_NAMESPACE,
+        namespace => namespace,
+    };
+    let address = match flags.address.as_str() {
+        "" => DEFAULT_CONTAINERD_ADDRESS,
+        address => address,
+    };
+    InstanceConfig::new(I::Engine::default(), namespace, address)
+}
+
+/// Precompiles the image following the `precompile` action, e.g.,
+/// `containerd-shim-wasmtime-v1 -namespace k8s.io precompile ghcr.io/containerd/runwasi/wasi-demo-app:latest`.
+/// Returns the exit code of the command.
+fn precompile<I>(flags: &Flags, args: &[OsString]) -> i32
+where
+    I: Instance,
+    I::Engine: Default,
+{
+    let Some(image) = image_arg("precompile", args) else {
+        eprintln!("error: missing image, usage: precompile <image>");
+        return 1;
+    };
+
+    let cfg = command_config::<I>(flags);
+    match I::precompile(image, &cfg) {
+        Ok(layers) => {
+            for (original, precompiled) in layers {
+                println!("{original}: {precompiled}");
+            }
+            0
+        }
+        Err(err) => {
+            eprintln!("error: failed to precompile {image}: {err}");
+            1
+        }
+    }
+}
+
+/// Pulls the image following the `pull` action with only the content the engine runs, e.g.,
+/// `containerd-shim-wasmtime-v1 -namespace k8s.io pull ghcr.io/containerd/runwasi/wasi-demo-app:latest`.
+/// Returns the exit code of the command.
+fn pull<I>(flags: &Flags, args: &[OsString]) -> i32
+where
+    I: Instance,
+    I::Engine: Default,
+{
+    let Some(image) = image_arg("pull", args) else {
+        eprintln!("error: missing image, usage: pull <image>");
+        return 1;
+    };
+
+    let cfg = command_config::<I>(flags);
+    match I::pull(image, &cfg) {
+        Ok(digests) => {
+            for digest in digests {
+                println!("{digest}");
+            }
+            0
+        }
+        Err(err) => {
+            eprintln!("error: failed to pull {image}: {err}");
+            1
+        }
+    }
+}
+
+/// Drops the precompiled layers of the removed images following the `gc` action, e.g.,
+/// `containerd-shim-wasmtime-v1 -namespace k8s.io gc`.
+/// Returns the exit code of the command.
+fn gc<I>(flags: &Flags) -> i32
+where
+    I: Instance,
+    I::Engine: Default,
+{
+    let cfg = command_config::<I>(flags);
+    match I::gc(&cfg) {
+        Ok(collected) => {
+            for collected in collected {
+                println!("{collected}");
+            }
+            0
+        }
+        Err(err) => {
+            eprintln!("error: failed to collect the precompiled layers: {err}");
+            1
+        }
+    }
+}
+
+/// Installs or uninstalls the shim binary `shim_cli`, the runtime handler of its `shim_id` and
+/// its RuntimeClass following the `install` or `uninstall` action, e.g.,
+/// `containerd-shim-wasmtime-v1 install -handler wasm -runtime-class`.
+/// Returns the exit code of the command.
+fn install(action: &str, args: &[OsString], runtime: &str, shim_cli: &str, shim_id: &str) -> i32 {
+    let installed = InstallOptions::parse(action, args, runtime).and_then(|options| match action {
+        "install" => install::install(shim_cli, shim_id, &options),
+        _ => install::uninstall(shim_cli, &options),
+    });
+    match installed {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("error: failed to {action} {shim_cli}: {err:#}");
+            1
+        }
+    }
+}
+
 #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
 fn shim_main_inner<'a, I>(
     name: &str,
@@ -96,21 +236,37 @@ fn shim_main_inner<'a, I>(
             std::env::set_var("TRACECONTEXT", ctx);
         }
     }
+    if let Some(exit_code) = I::run_container_process(I::Engine::default) {
+        std::process::exit(exit_code);
+    }
+
     let os_args: Vec<_> = std::env::args_os().collect();
 
     let flags = parse(&os_args[1..]).unwrap();
     let argv0 = PathBuf::from(&os_args[0]);
     let argv0 = argv0.file_stem().unwrap_or_default().to_string_lossy();
 
+    let revision = revision.into();
     if flags.version {
         println!("{argv0}:");
         println!("  Runtime: {name}");
         println!("  Version: {version}");
-        println!("  Revision: {}", revision.into().unwrap_or("<none>"));
+        println!("  Revision: {}", revision.unwrap_or("<none>"));
         println!();
 
         std::process::exit(0);
     }
+    if flags.action == "precompile" {
+        std::process::exit(precompile::<I>(&flags, &os_args));
+    }
+    if flags.action == "pull" {
+        std::process::exit(pull::<I>(&flags, &os_args));
+    }
+    if flags.action == "gc" {
+        std::process::exit(gc::<I>(&flags));
+    }
+    #[cfg(unix)]
+    crate::sandbox::shim::introspection::set_runtime(name, version, revision);
 
     let shim_version = shim_version.into().unwrap_or("v1");
 
@@ -120,15 +276,31 @@ fn shim_main_inner<'a, I>(
     let shim_daemon = format!("containerd-{lower_name}d");
     let shim_id = format!("io.containerd.{lower_name}.{shim_version}");
 
+    if flags.action == "install" || flags.action == "uninstall" {
+        std::process::exit(install(
+            &flags.action,
+            &os_args,
+            &lower_name,
+            &shim_cli,
+            &shim_id,
+        ));
+    }
+
     match argv0.to_lowercase() {
         s if s == shim_cli => {
-            run::<ShimCli<I>>(&shim_id, config);
+            // the task server of the shim also serves the Sandbox API of containerd
+            #[cfg(unix)]
+            crate::sandbox::shim::run::<I>(&shim_id, config);
+            #[cfg(not(unix))]
+            run::<crate::sandbox::ShimCli<I>>(&shim_id, config);
         }
         s if s == shim_client => {
             run::<Shim>(&shim_client, config);
         }
         s if s == shim_daemon => {
             log::info!("starting up!");
+            #[cfg(unix)]
+            let notifier = crate::sandbox::shim::sd_notify::Notifier::take(true);
             let s: ManagerService<Local<I>> = Default::default();
             let s = Arc::new(Box::new(s) as Box<dyn Manager + Send + Sync>);
             let service = create_manager(s);
@@ -140,6 +312,11 @@ fn shim_main_inner<'a, I>(
 
             server.start().expect("failed to start daemon");
             log::info!("server started!");
+            // the daemon holds no state to probe, so the watchdog only watches its process
+            #[cfg(unix)]
+            if let Some(notifier) = &notifier {
+                notifier.ready(|| true);
+            }
             let (_tx, rx) = channel::<()>();
             rx.recv().unwrap();
         }
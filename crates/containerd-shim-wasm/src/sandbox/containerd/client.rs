@@ -1,46 +1,89 @@
-#![cfg(unix)]
-
 use std::collections::HashMap;
-use std::path::Path;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use containerd_client;
 use containerd_client::services::v1::containers_client::ContainersClient;
 use containerd_client::services::v1::content_client::ContentClient;
 use containerd_client::services::v1::images_client::ImagesClient;
 use containerd_client::services::v1::leases_client::LeasesClient;
+use containerd_client::services::v1::namespaces_client::NamespacesClient;
 use containerd_client::services::v1::{
-    Container, DeleteContentRequest, GetContainerRequest, GetImageRequest, Image, Info,
-    InfoRequest, ReadContentRequest, UpdateRequest, WriteAction, WriteContentRequest,
-    WriteContentResponse,
+    AddResourceRequest, Container, CreateImageRequest, DeleteContentRequest, GetContainerRequest,
+    GetImageRequest, Image, Info, InfoRequest, ListNamespacesRequest, ListRequest,
+    ReadContentRequest, Resource, UpdateImageRequest, UpdateRequest, WriteAction,
+    WriteContentRequest, WriteContentResponse,
 };
 use containerd_client::tonic::transport::Channel;
 use containerd_client::tonic::Streaming;
-use containerd_client::{tonic, with_namespace};
+use containerd_client::{self, tonic, with_namespace};
 use futures::TryStreamExt;
-use oci_spec::image::{Arch, ImageManifest, MediaType, Platform};
+use oci_distribution::manifest::OciDescriptor;
+use oci_distribution::secrets::RegistryAuth;
+use oci_distribution::Reference;
+use oci_spec::image::{Arch, Descriptor, ImageIndex, ImageManifest, MediaType, Os, Platform};
 use sha256::digest;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Code, Request};
 
+use super::compression::{self, Compression};
+use super::layer_cache::LayerCache;
 use super::lease::LeaseGuard;
-use crate::container::Engine;
+use super::precompile_cache::PrecompileCache;
+use super::signature::{
+    signature_image_name, Signature, SignaturePolicy, SIGNATURE_ANNOTATION,
+    SIMPLE_SIGNING_MEDIA_TYPE,
+};
+use crate::container::{Engine, PrecompileTarget};
 use crate::sandbox::error::{Error as ShimError, Result};
-use crate::sandbox::oci::{self, WasmLayer};
+use crate::sandbox::instance::{CompileEvent, CompileObserver};
+use crate::sandbox::node_config;
+use crate::sandbox::oci::{
+    self, LayerContent, WasmArtifactConfig, WasmLayer, ENGINE_ANNOTATION,
+    ENTRYPOINT_FUNCTION_ANNOTATION, WASM_ARTIFACT_CONFIG_MEDIA_TYPE,
+    WASM_ARTIFACT_LAYER_MEDIA_TYPE, WASM_PRECOMPILED_LAYER_MEDIA_TYPE,
+};
 use crate::with_lease;
 
 static PRECOMPILE_PREFIX: &str = "runwasi.io/precompiled";
+// Label of the leases of the precompiled layers, with the digest of the layer they were compiled
+// from, which the garbage collection drops once the layer is removed from the content store
+const PRECOMPILED_SOURCE_LABEL: &str = "runwasi.io/precompiled/source";
+// How often the shims collect the precompiled layers of the removed images
+const PRECOMPILE_GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
 // 16MB is the default maximum gRPC message size for gRPC in containerd:
 // https://github.com/containerd/containerd/blob/main/defaults/defaults.go
 // Conservatively set the max to 15MB to leave room for message overhead
 static MAX_WRITE_CHUNK_SIZE_BYTES: i64 = 1024 * 1024 * 15;
+// Environment variable with the directory of the containerd content store,
+// for containerd installations that don't use the default root directory
+const CONTENT_STORE_DIR_ENV: &str = "RUNWASI_CONTENT_STORE_DIR";
+const DEFAULT_CONTENT_STORE_DIR: &str = "/var/lib/containerd/io.containerd.content.v1.content";
+// Environment variables with the credentials of the registries the `pull` command pulls from,
+// e.g., a token as the password, the same as for oci-tar-builder
+const REGISTRY_USERNAME_ENV: &str = "OCI_REGISTRY_USERNAME";
+const REGISTRY_PASSWORD_ENV: &str = "OCI_REGISTRY_PASSWORD";
+// The media types of the manifests accepted from the registries
+const PULL_MANIFEST_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+// Media type of the multi-platform images built by docker
+const DOCKER_MANIFEST_LIST_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
 
 pub struct Client {
     inner: Channel,
     rt: Runtime,
     namespace: String,
     address: String,
+    compile_observer: Option<CompileObserver>,
+    precompile_cache: Option<PrecompileCache>,
 }
 
 #[derive(Debug)]
@@ -62,7 +105,7 @@ impl Client {
             .build()?;
 
         let inner = rt
-            .block_on(containerd_client::connect(address.as_ref()))
+            .block_on(connect(address.as_ref()))
             .map_err(|err| ShimError::Containerd(err.to_string()))?;
 
         Ok(Client {
@@ -70,9 +113,55 @@ impl Client {
             rt,
             namespace: namespace.to_string(),
             address: address.to_string(),
+            compile_observer: None,
+            precompile_cache: PrecompileCache::from_env(),
         })
     }
 
+    /// Set the observer notified when `load_modules` precompiles the wasm layers
+    pub fn with_compile_observer(mut self, observer: Option<CompileObserver>) -> Self {
+        self.compile_observer = observer;
+        self
+    }
+
+    /// Set the directory of the node-level precompilation cache, rather than the one of
+    /// `RUNWASI_PRECOMPILE_CACHE_DIR`
+    pub fn with_precompile_cache_dir(mut self, dir: Option<PathBuf>) -> Self {
+        if let Some(dir) = dir {
+            self.precompile_cache = Some(PrecompileCache::new(dir));
+        }
+        self
+    }
+
+    fn notify_compile(&self, event: CompileEvent) {
+        if let Some(observer) = &self.compile_observer {
+            observer(event);
+        }
+    }
+
+    // maps a blob of the content store into memory, if the content store is accessible from the shim.
+    // returns None when the blob can't be mapped so the caller can fall back to reading it over gRPC.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn map_content(&self, digest: &str, size: i64) -> Option<LayerContent> {
+        let path = content_path(digest)?;
+        let file = File::open(&path).ok()?;
+        // don't map blobs that are still being written, or that are not the one we expect
+        if file.metadata().ok()?.len() != u64::try_from(size).ok()? {
+            log::debug!("size of content {path:?} doesn't match {size}");
+            return None;
+        }
+        match LayerContent::map(file) {
+            Ok(content) => {
+                log::info!("mapped content {digest} from {path:?}");
+                Some(content)
+            }
+            Err(err) => {
+                log::warn!("failed to map content {path:?}: {err}");
+                None
+            }
+        }
+    }
+
     // wrapper around read that will read the entire content file
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn read_content(&self, digest: impl ToString) -> Result<Vec<u8>> {
@@ -144,16 +233,201 @@ impl Client {
         })
     }
 
+    // takes a lease, without expiry, on the layer `artifact` precompiled from the layer `source`,
+    // so that it is kept until the garbage collection finds `source` removed
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn lease_precompiled(&self, source: &str, artifact: &str) -> Result<()> {
+        // lease ids are at most 76 characters
+        let hash = artifact.rsplit(':').next().unwrap_or(artifact);
+        let id = format!("runwasi-precompiled-{}", hash.get(..48).unwrap_or(hash));
+        self.rt.block_on(async {
+            let mut leases_client = LeasesClient::new(self.inner.clone());
+            let lease_request = containerd_client::services::v1::CreateRequest {
+                id: id.clone(),
+                labels: HashMap::from([(PRECOMPILED_SOURCE_LABEL.to_string(), source.to_string())]),
+            };
+            match leases_client
+                .create(with_namespace!(lease_request, self.namespace))
+                .await
+            {
+                Ok(_) => {}
+                Err(status) if status.code() == Code::AlreadyExists => {}
+                Err(status) => return Err(ShimError::Containerd(status.to_string())),
+            }
+            let resource_request = AddResourceRequest {
+                id,
+                resource: Some(Resource {
+                    id: artifact.to_string(),
+                    r#type: "content".to_string(),
+                }),
+            };
+            leases_client
+                .add_resource(with_namespace!(resource_request, self.namespace))
+                .await
+                .map_err(|err| ShimError::Containerd(err.to_string()))?;
+            Ok(())
+        })
+    }
+
+    // returns whether the content store has the content `digest` in the namespace of the client
+    fn content_exists(&self, digest: &str) -> Result<bool> {
+        self.content_exists_in(&self.namespace, digest)
+    }
+
+    // returns whether the content store has the content `digest` in `namespace`
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn content_exists_in(&self, namespace: &str, digest: &str) -> Result<bool> {
+        self.rt.block_on(async {
+            let req = InfoRequest {
+                digest: digest.to_string(),
+            };
+            let req = with_namespace!(req, namespace);
+            match ContentClient::new(self.inner.clone()).info(req).await {
+                Ok(_) => Ok(true),
+                Err(status) if status.code() == Code::NotFound => Ok(false),
+                Err(status) => Err(ShimError::Containerd(status.to_string())),
+            }
+        })
+    }
+
+    // returns the namespaces of containerd
+    fn namespaces(&self) -> Result<Vec<String>> {
+        let namespaces = self.rt.block_on(async {
+            NamespacesClient::new(self.inner.clone())
+                .list(ListNamespacesRequest::default())
+                .await
+                .map_err(|err| ShimError::Containerd(err.to_string()))
+        })?;
+        Ok(namespaces
+            .into_inner()
+            .namespaces
+            .into_iter()
+            .map(|namespace| namespace.name)
+            .collect())
+    }
+
+    /// Drops the precompiled layers of the layers removed from the content store, e.g., with their
+    /// image: their leases in the namespace of the client, so that containerd collects them, and
+    /// the artifacts of the node-level precompilation cache, which is shared by the namespaces,
+    /// whose layers are removed from every namespace. This is called by the `gc` command of the
+    /// shim. Returns the dropped leases and artifacts.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn collect_precompiled(&self) -> Result<Vec<String>> {
+        let mut collected = vec![];
+        let leases = self.rt.block_on(async {
+            let req = ListRequest {
+                filters: vec![format!("labels.\"{PRECOMPILED_SOURCE_LABEL}\"")],
+            };
+            LeasesClient::new(self.inner.clone())
+                .list(with_namespace!(req, self.namespace))
+                .await
+                .map_err(|err| ShimError::Containerd(err.to_string()))
+        })?;
+        for lease in leases.into_inner().leases {
+            let Some(source) = lease.labels.get(PRECOMPILED_SOURCE_LABEL) else {
+                continue;
+            };
+            if self.content_exists(source)? {
+                continue;
+            }
+            self.rt.block_on(async {
+                let req = containerd_client::services::v1::DeleteRequest {
+                    id: lease.id.clone(),
+                    sync: false,
+                };
+                LeasesClient::new(self.inner.clone())
+                    .delete(with_namespace!(req, self.namespace))
+                    .await
+                    .map_err(|err| ShimError::Containerd(err.to_string()))
+            })?;
+            log::info!(
+                "removed lease {} of the precompiled layer of {source}",
+                lease.id
+            );
+            collected.push(format!("lease {}", lease.id));
+        }
+
+        if let Some(cache) = &self.precompile_cache {
+            let namespaces = self.namespaces()?;
+            for entry in cache.entries() {
+                let mut exists = false;
+                for namespace in &namespaces {
+                    if self.content_exists_in(namespace, &entry.digest)? {
+                        exists = true;
+                        break;
+                    }
+                }
+                if exists {
+                    continue;
+                }
+                match cache.remove(&entry) {
+                    Ok(()) => collected.push(entry.path.display().to_string()),
+                    Err(err) => log::warn!("failed to remove {:?}: {err}", entry.path),
+                }
+            }
+        }
+        Ok(collected)
+    }
+
+    /// Runs `collect_precompiled` in a background thread when it is due: once per
+    /// `PRECOMPILE_GC_INTERVAL` on the node with a precompilation cache, or else once per shim,
+    /// so that it doesn't delay the start of the containers. Failures are only logged.
+    pub fn collect_precompiled_if_due(&self) {
+        static COLLECTED: AtomicBool = AtomicBool::new(false);
+        let due = match &self.precompile_cache {
+            Some(cache) => cache.gc_due(PRECOMPILE_GC_INTERVAL),
+            None => !COLLECTED.swap(true, Ordering::Relaxed),
+        };
+        if !due {
+            return;
+        }
+        let address = self.address.clone();
+        let namespace = self.namespace.clone();
+        let precompile_cache = self.precompile_cache.clone();
+        let spawned = std::thread::Builder::new()
+            .name("precompile-gc".to_string())
+            .spawn(move || {
+                let collected = Client::connect(address, namespace).and_then(|mut client| {
+                    client.precompile_cache = precompile_cache;
+                    client.collect_precompiled()
+                });
+                match collected {
+                    Ok(collected) if !collected.is_empty() => log::info!(
+                        "collected {} precompiled layers of removed images",
+                        collected.len()
+                    ),
+                    Ok(_) => {}
+                    Err(err) => log::warn!("failed to collect the precompiled layers: {err}"),
+                }
+            });
+        if let Err(err) = spawned {
+            log::warn!("failed to start collecting the precompiled layers: {err}");
+        }
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn save_content(
         &self,
         data: Vec<u8>,
         unique_id: &str,
         labels: HashMap<String, String>,
+    ) -> Result<WriteContent> {
+        self.write_content(data, format!("precompile-{}", unique_id), labels)
+    }
+
+    // writes content to the content store under the lease `reference`, held until the returned
+    // `WriteContent` is dropped
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn write_content(
+        &self,
+        data: Vec<u8>,
+        reference: String,
+        labels: HashMap<String, String>,
     ) -> Result<WriteContent> {
         let expected = format!("sha256:{}", digest(data.clone()));
-        let reference = format!("precompile-{}", unique_id);
         let lease = self.lease(reference.clone())?;
+        #[cfg(any(test, feature = "fault-injection"))]
+        crate::sandbox::fault::lease_loss(&reference)?;
 
         let digest = self.rt.block_on(async {
             // create a channel to feed the stream; only sending one message at a time so we can set this to one
@@ -343,6 +617,38 @@ impl Client {
         })
     }
 
+    // creates the image `name`, or updates its target if it exists
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn create_image(&self, name: &str, target: containerd_client::types::Descriptor) -> Result<()> {
+        self.rt.block_on(async {
+            let image = Image {
+                name: name.to_string(),
+                target: Some(target),
+                ..Default::default()
+            };
+            let mut client = ImagesClient::new(self.inner.clone());
+            let req = CreateImageRequest {
+                image: Some(image.clone()),
+                ..Default::default()
+            };
+            match client.create(with_namespace!(req, self.namespace)).await {
+                Ok(_) => Ok(()),
+                Err(err) if err.code() == Code::AlreadyExists => {
+                    let req = UpdateImageRequest {
+                        image: Some(image),
+                        ..Default::default()
+                    };
+                    client
+                        .update(with_namespace!(req, self.namespace))
+                        .await
+                        .map_err(|err| ShimError::Containerd(err.to_string()))?;
+                    Ok(())
+                }
+                Err(err) => Err(ShimError::Containerd(err.to_string())),
+            }
+        })
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn extract_image_content_sha(&self, image: &Image) -> Result<String> {
         let digest = image
@@ -382,11 +688,124 @@ impl Client {
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
-    fn get_image_manifest_and_digest(&self, image_name: &str) -> Result<(ImageManifest, String)> {
+    fn get_image_manifest_and_digest(
+        &self,
+        image_name: &str,
+        engine_name: Option<&str>,
+    ) -> Result<(ImageManifest, String)> {
         let image = self.get_image(image_name)?;
         let image_digest = self.extract_image_content_sha(&image)?;
-        let manifest = ImageManifest::from_reader(self.read_content(&image_digest)?.as_slice())?;
-        Ok((manifest, image_digest))
+        let is_index = image
+            .target
+            .as_ref()
+            .is_some_and(|target| is_image_index(&target.media_type));
+        if !is_index {
+            let manifest =
+                ImageManifest::from_reader(self.read_content(&image_digest)?.as_slice())?;
+            return Ok((manifest, image_digest));
+        }
+
+        // the variants of an image index that were not pulled are not in the content store,
+        // select the first one that was
+        let index = ImageIndex::from_reader(self.read_content(&image_digest)?.as_slice())?;
+        for descriptor in select_manifests(&index, engine_name) {
+            match self.read_content(descriptor.digest()) {
+                Ok(manifest) => {
+                    log::info!(
+                        "selected manifest {} of image index {image_digest}",
+                        descriptor.digest()
+                    );
+                    let manifest = ImageManifest::from_reader(manifest.as_slice())?;
+                    return Ok((manifest, descriptor.digest().clone()));
+                }
+                Err(err) => log::debug!("manifest {} not pulled: {err}", descriptor.digest()),
+            }
+        }
+        Err(ShimError::FailedPrecondition(format!(
+            "no wasm or dual-format manifest of image index {image_name} for the {} engine",
+            engine_name.unwrap_or_default()
+        )))
+    }
+
+    /// Returns the original content of a layer returned by [`Client::load_modules`], decompressed,
+    /// rather than the precompiled content the engine runs, e.g., to inspect its imports.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn read_original_layer(&self, layer: &WasmLayer) -> Result<Vec<u8>> {
+        let content = self.read_content(layer.config.digest())?;
+        match compression::detect(&content) {
+            Some(compression) => {
+                compression::decompress(&content, compression, compression::MAX_DECOMPRESSED_SIZE)
+            }
+            None => Ok(content),
+        }
+    }
+
+    /// Returns the labels of a container, along with the labels of its image, which the labels
+    /// of the container take precedence over.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn container_labels(
+        &self,
+        containerd_id: impl ToString,
+    ) -> Result<HashMap<String, String>> {
+        let container = self.get_container(containerd_id.to_string())?;
+        let mut labels = match self.get_image(&container.image) {
+            Ok(image) => image.labels,
+            Err(err) => {
+                log::debug!("no labels for image {}: {err}", container.image);
+                HashMap::new()
+            }
+        };
+        labels.extend(container.labels);
+        Ok(labels)
+    }
+
+    /// Returns the image of a container and the digest of its target, which changes when the image
+    /// is updated, e.g., pulled or imported again.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn container_image(&self, containerd_id: impl ToString) -> Result<(String, String)> {
+        let container = self.get_container(containerd_id.to_string())?;
+        let image = self.get_image(&container.image)?;
+        let digest = self.extract_image_content_sha(&image)?;
+        Ok((container.image, digest))
+    }
+
+    /// Verifies the signature of the image of a container against `policy`.
+    /// The signatures are read from the cosign signature image, if it was pulled.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn verify_signature(
+        &self,
+        containerd_id: impl ToString,
+        policy: &SignaturePolicy,
+    ) -> Result<()> {
+        let container = self.get_container(containerd_id.to_string())?;
+        let image = self.get_image(&container.image)?;
+        let digest = self.extract_image_content_sha(&image)?;
+
+        let signature_image = signature_image_name(&container.image, &digest);
+        let mut signatures = vec![];
+        match self.get_image_manifest_and_digest(&signature_image, None) {
+            Ok((manifest, _)) => {
+                for layer in manifest.layers() {
+                    if layer.media_type().to_string() != SIMPLE_SIGNING_MEDIA_TYPE {
+                        continue;
+                    }
+                    let Some(signature) = layer
+                        .annotations()
+                        .as_ref()
+                        .and_then(|a| a.get(SIGNATURE_ANNOTATION))
+                    else {
+                        continue;
+                    };
+                    signatures.push(Signature {
+                        payload: self.read_content(layer.digest())?,
+                        signature: signature.clone(),
+                    });
+                }
+            }
+            Err(err) => log::debug!("no signatures for image {}: {err}", container.image),
+        }
+
+        policy.verify(&container.image, &digest, &signatures)
     }
 
     // load module will query the containerd store to find an image that has an OS of type 'wasm'
@@ -399,39 +818,244 @@ impl Client {
         engine: &T,
     ) -> Result<(Vec<oci::WasmLayer>, Platform)> {
         let container = self.get_container(containerd_id.to_string())?;
-        let (manifest, image_digest) = self.get_image_manifest_and_digest(&container.image)?;
+        self.load_image_modules(&container.image, engine)
+    }
+
+    /// Precompiles the wasm layers of `image` ahead of time, so that the containers running it
+    /// load the precompiled layers rather than compiling the image when they start.
+    /// Returns the digests of the original and precompiled layers.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn precompile_image<T: Engine>(
+        &self,
+        image: &str,
+        engine: &T,
+    ) -> Result<Vec<(String, String)>> {
+        let Some(engine_precompile_id) = engine.can_precompile() else {
+            return Err(ShimError::FailedPrecondition(format!(
+                "the {} engine doesn't support precompilation",
+                T::name()
+            )));
+        };
+        let precompile_id = precompile_label(T::name(), &engine_precompile_id);
+
+        let (layers, _) = self.load_image_modules(image, engine)?;
+        let mut precompiled = vec![];
+        for layer in layers {
+            let original = layer.config.digest();
+            if let Some(digest) = self.get_info(original)?.labels.remove(&precompile_id) {
+                precompiled.push((original.to_string(), digest));
+            }
+        }
+        if precompiled.is_empty() {
+            return Err(ShimError::FailedPrecondition(format!(
+                "no wasm layers of image {image} were precompiled"
+            )));
+        }
+        Ok(precompiled)
+    }
+
+    /// Pulls `image` from its registry into the content store, fetching only the content that
+    /// the engine runs: the manifest of an image index selected for the engine, as when the image
+    /// is loaded, its config, the layers the engine supports, and the rootfs layers containerd
+    /// unpacks. The other manifests of the index, e.g., the `linux/amd64` fallback of a
+    /// dual-format image, and the layers of other engines are not fetched.
+    /// The image is created, or updated, with the selected manifest as its target.
+    /// Returns the digests of the fetched content.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn pull_image<T: Engine>(&self, image: &str) -> Result<Vec<String>> {
+        let reference: Reference = image.parse().map_err(|err| {
+            ShimError::InvalidArgument(format!("invalid image reference {image}: {err}"))
+        })?;
+        let auth = match (
+            std::env::var(REGISTRY_USERNAME_ENV),
+            std::env::var(REGISTRY_PASSWORD_ENV),
+        ) {
+            (Ok(username), Ok(password)) => RegistryAuth::Basic(username, password),
+            _ => RegistryAuth::Anonymous,
+        };
+        let registry = oci_distribution::Client::default();
+        let pull_err = |err: oci_distribution::errors::OciDistributionError| {
+            ShimError::Containerd(format!("failed to pull {image}: {err}"))
+        };
+
+        // the blobs are fetched before they are written, as the content store is written from
+        // the runtime of the client
+        let (manifest_digest, manifest, blobs) = self.rt.block_on(async {
+            let (mut manifest, mut manifest_digest) = registry
+                .pull_manifest_raw(&reference, &auth, PULL_MANIFEST_MEDIA_TYPES)
+                .await
+                .map_err(pull_err)?;
+            if is_index_content(&manifest) {
+                let index = ImageIndex::from_reader(manifest.as_slice())?;
+                let descriptor = select_manifests(&index, Some(T::name()))
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| {
+                        ShimError::FailedPrecondition(format!(
+                            "no wasm manifest of image index {image} for the {} engine",
+                            T::name()
+                        ))
+                    })?;
+                log::info!("selected manifest {} of {image}", descriptor.digest());
+                let reference = Reference::with_digest(
+                    reference.registry().to_string(),
+                    reference.repository().to_string(),
+                    descriptor.digest().clone(),
+                );
+                (manifest, manifest_digest) = registry
+                    .pull_manifest_raw(&reference, &auth, PULL_MANIFEST_MEDIA_TYPES)
+                    .await
+                    .map_err(pull_err)?;
+            }
+
+            let parsed = ImageManifest::from_reader(manifest.as_slice())?;
+            let descriptors =
+                std::iter::once(parsed.config()).chain(parsed.layers().iter().filter(|layer| {
+                    is_pulled_layer(layer.media_type(), T::supported_layers_types())
+                }));
+            let mut blobs = vec![];
+            for descriptor in descriptors {
+                let oci_descriptor = OciDescriptor {
+                    media_type: descriptor.media_type().to_string(),
+                    digest: descriptor.digest().clone(),
+                    size: descriptor.size(),
+                    ..Default::default()
+                };
+                let mut blob = vec![];
+                registry
+                    .pull_blob(&reference, &oci_descriptor, &mut blob)
+                    .await
+                    .map_err(pull_err)?;
+                if format!("sha256:{}", digest(blob.as_slice())) != *descriptor.digest() {
+                    return Err(ShimError::Containerd(format!(
+                        "digest of blob {} of {image} doesn't match",
+                        descriptor.digest()
+                    )));
+                }
+                blobs.push((descriptor.digest().clone(), blob));
+            }
+            Ok::<_, ShimError>((manifest_digest, manifest, blobs))
+        })?;
+
+        // the distribution source lets containerd fetch the content it is missing from the
+        // registry, and the manifest references the content it keeps from garbage collection
+        let source = HashMap::from([(
+            format!("containerd.io/distribution.source.{}", reference.registry()),
+            reference.repository().to_string(),
+        )]);
+        let mut manifest_labels = source.clone();
+        let mut pulled = vec![];
+        let mut leases = vec![];
+        for (i, (blob_digest, blob)) in blobs.into_iter().enumerate() {
+            let gc_ref = match i {
+                0 => "containerd.io/gc.ref.content.config".to_string(),
+                i => format!("containerd.io/gc.ref.content.l.{}", i - 1),
+            };
+            manifest_labels.insert(gc_ref, blob_digest.clone());
+            leases.push(self.write_content(blob, format!("pull-{blob_digest}"), source.clone())?);
+            pulled.push(blob_digest);
+        }
+        let size = manifest.len() as i64;
+        let media_type = serde_json::from_slice::<serde_json::Value>(&manifest)?
+            .get("mediaType")
+            .and_then(|media_type| media_type.as_str().map(str::to_string))
+            .unwrap_or_else(|| MediaType::ImageManifest.to_string());
+        leases.push(self.write_content(
+            manifest,
+            format!("pull-{manifest_digest}"),
+            manifest_labels,
+        )?);
+        pulled.push(manifest_digest.clone());
+
+        self.create_image(
+            &reference.whole(),
+            containerd_client::types::Descriptor {
+                media_type,
+                digest: manifest_digest,
+                size,
+                ..Default::default()
+            },
+        )?;
+        Ok(pulled)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn load_image_modules<T: Engine>(
+        &self,
+        image: &str,
+        engine: &T,
+    ) -> Result<(Vec<oci::WasmLayer>, Platform)> {
+        let (manifest, image_digest) =
+            self.get_image_manifest_and_digest(image, Some(T::name()))?;
 
         let image_config_descriptor = manifest.config();
         let image_config = self.read_content(image_config_descriptor.digest())?;
         let image_config = image_config.as_slice();
 
-        // the only part we care about here is the platform values
-        let platform: Platform = serde_json::from_slice(image_config)?;
-        let Arch::Wasm = platform.architecture() else {
+        // wasm artifacts have their own config, for images the only part we care about
+        // here is the platform values
+        let artifact = match image_config_descriptor.media_type().to_string().as_str() {
+            WASM_ARTIFACT_CONFIG_MEDIA_TYPE => {
+                Some(serde_json::from_slice::<WasmArtifactConfig>(image_config)?)
+            }
+            _ => None,
+        };
+        let platform: Platform = match &artifact {
+            Some(artifact) => artifact.platform(),
+            None => serde_json::from_slice(image_config)?,
+        };
+        // dual-format images have a native rootfs, run by runc, and wasm layers preferred here
+        if platform.architecture() == &Arch::Wasm {
+            log::info!("found manifest with WASM OCI image format");
+        } else if is_dual_format(&manifest, T::supported_layers_types()) {
+            log::info!("found dual-format manifest, preferring its WASM layers to the rootfs");
+        } else {
             log::info!("manifest is not in WASM OCI image format");
             return Ok((vec![], platform));
-        };
+        }
 
-        log::info!("found manifest with WASM OCI image format");
         // This label is unique across runtimes and version of the shim running
         // a precompiled component/module will not work across different runtimes or versions
-        let (can_precompile, precompile_id) = match engine.can_precompile() {
-            Some(precompile_id) => (true, precompile_label(T::name(), &precompile_id)),
-            None => (false, "".to_string()),
+        let engine_precompile_id = engine.can_precompile().unwrap_or_default();
+        let (can_precompile, precompile_id) = match engine_precompile_id.as_str() {
+            "" => (false, "".to_string()),
+            id => (true, precompile_label(T::name(), id)),
         };
+        let target_labels = precompile_target_labels(T::name(), &engine.precompile_target());
 
+        // the containers of a pod created together with the same image compile it once,
+        // the others wait and load the precompiled layers
+        let _compiling =
+            can_precompile.then(|| LayerCache::shared().lock_compilation(&image_digest));
         let image_info = self.get_info(&image_digest)?;
         let mut needs_precompile =
             can_precompile && !image_info.labels.contains_key(&precompile_id);
         let layers = manifest
             .layers()
             .iter()
-            .filter(|x| is_wasm_layer(x.media_type(), T::supported_layers_types()))
+            .filter(|x| {
+                let media_type = x.media_type().to_string();
+                let (media_type, _) = compression::uncompressed_media_type(&media_type);
+                is_wasm_layer(media_type, T::supported_layers_types())
+                    || (artifact.is_some() && media_type == WASM_ARTIFACT_LAYER_MEDIA_TYPE)
+            })
             .map(|original_config| {
+                // the engines get the content of the compressed layers, with its media type
+                let (original_config, compression) =
+                    compression::uncompressed_descriptor(original_config);
+                let func = artifact
+                    .as_ref()
+                    .and_then(WasmArtifactConfig::entrypoint_function);
+                let original_config = match func {
+                    Some(func) => with_entrypoint_function(&original_config, func),
+                    None => original_config,
+                };
                 self.read_wasm_layer(
-                    original_config,
+                    &original_config,
+                    compression,
                     can_precompile,
                     &precompile_id,
+                    &target_labels,
                     &mut needs_precompile,
                 )
             })
@@ -443,8 +1067,25 @@ impl Client {
         }
 
         if needs_precompile {
-            log::info!("precompiling layers for image: {}", container.image);
-            let compiled_layers = match engine.precompile(&layers) {
+            log::info!("precompiling layers for image: {}", image);
+            self.notify_compile(CompileEvent::Started {
+                runtime: T::name().to_string(),
+            });
+            let started = std::time::Instant::now();
+            let compiled_layers = precompile_layers(
+                engine,
+                &layers,
+                &engine_precompile_id,
+                self.precompile_cache.as_ref(),
+            );
+            #[cfg(feature = "opentelemetry")]
+            crate::sandbox::shim::record_compile_duration(T::name(), started.elapsed());
+            self.notify_compile(CompileEvent::Finished {
+                runtime: T::name().to_string(),
+                duration: started.elapsed(),
+                error: compiled_layers.as_ref().err().map(|e| e.to_string()),
+            });
+            let compiled_layers = match compiled_layers {
                 Ok(compiled_layers) => {
                     if compiled_layers.len() != layers.len() {
                         return Err(ShimError::FailedPrecondition(
@@ -469,12 +1110,18 @@ impl Client {
 
                 let compiled_layer = compiled_layer.as_ref().unwrap();
                 let original_config = &layers[i].config;
-                let labels = HashMap::from([(
+                let mut labels = target_labels.clone();
+                labels.insert(
                     format!("{precompile_id}/original"),
                     original_config.digest().to_string(),
-                )]);
+                );
+                labels.insert(
+                    format!("{PRECOMPILE_PREFIX}/media-type"),
+                    WASM_PRECOMPILED_LAYER_MEDIA_TYPE.to_string(),
+                );
                 let precompiled_content =
                     self.save_content(compiled_layer.clone(), &precompile_id, labels)?;
+                let size = compiled_layer.len() as i64;
 
                 log::debug!(
                     "updating original layer {} with compiled layer {}",
@@ -504,16 +1151,24 @@ impl Client {
                 let mut image_content = self.get_info(&image_digest)?;
                 image_content.labels.insert(
                     format!("containerd.io/gc.ref.content.precompile.{}", i),
-                    precompiled_content.digest,
+                    precompiled_content.digest.clone(),
                 );
                 image_content
                     .labels
                     .insert(precompile_id.clone(), "true".to_string());
                 self.update_info(image_content)?;
 
+                // the lease keeps the precompiled layer even if the labels of the image are
+                // mutated, until the original layer is removed
+                self.lease_precompiled(original_config.digest(), &precompiled_content.digest)?;
+
+                let layer = self
+                    .map_content(&precompiled_content.digest, size)
+                    .unwrap_or_else(|| compiled_layer.clone().into());
+                LayerCache::shared().insert(&precompiled_content.digest, layer.clone());
                 layers_for_runtime.push(WasmLayer {
                     config: original_config.clone(),
-                    layer: compiled_layer.clone(),
+                    layer,
                 });
             }
             return Ok((layers_for_runtime, platform));
@@ -527,58 +1182,303 @@ impl Client {
     fn read_wasm_layer(
         &self,
         original_config: &oci_spec::image::Descriptor,
+        compression: Option<Compression>,
         can_precompile: bool,
         precompile_id: &String,
+        target_labels: &HashMap<String, String>,
         needs_precompile: &mut bool,
     ) -> std::prelude::v1::Result<WasmLayer, ShimError> {
         let mut digest_to_load = original_config.digest().clone();
         if can_precompile {
             let info = self.get_info(&digest_to_load)?;
             if let Some(label) = info.labels.get(precompile_id) {
-                // Safe to unwrap here since we already checked for the label's existence
-                digest_to_load.clone_from(label);
-                log::info!(
-                    "layer {} has pre-compiled content: {} ",
-                    info.digest,
-                    &digest_to_load
-                );
+                // a layer precompiled for another version or target is compiled again, rather
+                // than failing to load when the container starts
+                match self.get_info(label) {
+                    Ok(precompiled) if is_stale(&precompiled.labels, target_labels) => {
+                        log::info!(
+                            "layer {} has stale pre-compiled content: {}, marking for recompile",
+                            info.digest,
+                            label
+                        );
+                        *needs_precompile = true;
+                    }
+                    _ => {
+                        digest_to_load.clone_from(label);
+                        log::info!(
+                            "layer {} has pre-compiled content: {} ",
+                            info.digest,
+                            &digest_to_load
+                        );
+                    }
+                }
             }
         }
         log::debug!("loading digest: {} ", &digest_to_load);
-        self.read_content(&digest_to_load)
-            .map(|module| WasmLayer {
-                config: original_config.clone(),
-                layer: module,
-            })
-            .or_else(|e| {
+        if digest_to_load != *original_config.digest() {
+            if let Some(layer) = LayerCache::shared().get(&digest_to_load) {
+                log::info!("using precompiled layer {digest_to_load} loaded by the shim");
+                return Ok(WasmLayer {
+                    config: original_config.clone(),
+                    layer,
+                });
+            }
+            // precompiled artifacts are mapped rather than read, so that the containers
+            // running the same image share the pages of the artifact
+            let mapped = self
+                .get_info(&digest_to_load)
+                .ok()
+                .and_then(|info| self.map_content(&digest_to_load, info.size));
+            let layer = match mapped {
+                Some(layer) => Ok(layer),
+                None => self.read_content(&digest_to_load).map(LayerContent::from),
+            };
+            match layer {
+                Ok(layer) => {
+                    // the other containers of the pod running this image reuse the layer
+                    LayerCache::shared().insert(&digest_to_load, layer.clone());
+                    return Ok(WasmLayer {
+                        config: original_config.clone(),
+                        layer,
+                    });
+                }
                 // handle content being removed from the content store out of band
-                if digest_to_load != *original_config.digest() {
+                Err(e) => {
                     log::error!("failed to load precompiled layer: {}", e);
                     log::error!("falling back to original layer and marking for recompile");
                     *needs_precompile = can_precompile; // only mark for recompile if engine is capable
-                    self.read_content(original_config.digest())
-                        .map(|module| WasmLayer {
-                            config: original_config.clone(),
-                            layer: module,
-                        })
-                } else {
-                    Err(e)
                 }
-            })
+            }
+        }
+        let mut module = self.read_content(original_config.digest())?;
+        if let Some(compression) = compression {
+            log::debug!(
+                "decompressing {compression:?} layer {}",
+                original_config.digest()
+            );
+            module =
+                compression::decompress(&module, compression, compression::MAX_DECOMPRESSED_SIZE)?;
+        }
+        Ok(WasmLayer {
+            config: original_config.clone(),
+            layer: module.into(),
+        })
     }
 }
 
+// connects to the unix socket of containerd
+#[cfg(unix)]
+pub(super) async fn connect(
+    address: &Path,
+) -> std::result::Result<Channel, tonic::transport::Error> {
+    containerd_client::connect(address).await
+}
+
+// connects to the named pipe of containerd, e.g. `\\.\pipe\containerd-containerd`
+#[cfg(windows)]
+pub(super) async fn connect(
+    address: &Path,
+) -> std::result::Result<Channel, tonic::transport::Error> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+    use windows_sys::Win32::Foundation::ERROR_PIPE_BUSY;
+
+    let address = address.to_path_buf();
+    tonic::transport::Endpoint::from_static("https://[::]")
+        .connect_with_connector(tower::service_fn(move |_| {
+            let address = address.clone();
+            async move {
+                // all the instances of the pipe are busy while containerd accepts other clients
+                loop {
+                    match ClientOptions::new().open(&address) {
+                        Err(err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {}
+                        result => return result,
+                    }
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            }
+        }))
+        .await
+}
+
+// path of the blob with `digest` in the content store, e.g. `<dir>/blobs/sha256/<hex>`
+fn content_path(digest: &str) -> Option<PathBuf> {
+    let (algorithm, hex) = digest.split_once(':')?;
+    if algorithm.is_empty() || hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let dir = node_config::var_os(CONTENT_STORE_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONTENT_STORE_DIR));
+    Some(dir.join("blobs").join(algorithm).join(hex))
+}
+
 fn precompile_label(name: &str, version: &str) -> String {
     format!("{}/{}/{}", PRECOMPILE_PREFIX, name, version)
 }
 
-fn is_wasm_layer(media_type: &MediaType, supported_layer_types: &[&str]) -> bool {
-    let supported = supported_layer_types.contains(&media_type.to_string().as_str());
-    log::debug!(
-        "layer type {} is supported: {}",
-        media_type.to_string().as_str(),
-        supported
-    );
+// labels of the precompiled content with the engine and target it was compiled for, the unknown ones are omitted
+fn precompile_target_labels(name: &str, target: &PrecompileTarget) -> HashMap<String, String> {
+    let labels = HashMap::from([
+        (format!("{PRECOMPILE_PREFIX}/engine"), name.to_string()),
+        (
+            format!("{PRECOMPILE_PREFIX}/engine-version"),
+            target.version.clone(),
+        ),
+        (format!("{PRECOMPILE_PREFIX}/target"), target.target.clone()),
+        (
+            format!("{PRECOMPILE_PREFIX}/target-features"),
+            target.features.join(","),
+        ),
+    ]);
+    labels
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect()
+}
+
+// Returns true if precompiled content was compiled for another engine or target.
+// Content precompiled before the target labels were recorded is not stale.
+fn is_stale(labels: &HashMap<String, String>, target_labels: &HashMap<String, String>) -> bool {
+    target_labels
+        .iter()
+        .any(|(key, value)| labels.get(key).is_some_and(|label| label != value))
+}
+
+// Precompiles the layers, reusing the artifacts in the node-level precompile `cache` when one is configured.
+// Only the layers missing from the cache are passed to the engine.
+#[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+fn precompile_layers<T: Engine>(
+    engine: &T,
+    layers: &[WasmLayer],
+    precompile_id: &str,
+    cache: Option<&PrecompileCache>,
+) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+    #[cfg(any(test, feature = "fault-injection"))]
+    crate::sandbox::fault::compile_delay();
+
+    let Some(cache) = cache else {
+        return engine.precompile(layers);
+    };
+
+    let mut compiled_layers: Vec<_> = layers
+        .iter()
+        .map(|layer| cache.get(T::name(), precompile_id, layer.config.digest()))
+        .collect();
+    let missing: Vec<_> = (0..layers.len())
+        .filter(|i| compiled_layers[*i].is_none())
+        .collect();
+    if missing.is_empty() {
+        return Ok(compiled_layers);
+    }
+
+    let to_compile: Vec<_> = missing.iter().map(|i| layers[*i].clone()).collect();
+    let compiled = engine.precompile(&to_compile)?;
+    if compiled.len() != to_compile.len() {
+        // let the caller report the mismatch
+        return Ok(compiled);
+    }
+
+    for (i, compiled_layer) in missing.into_iter().zip(compiled) {
+        if let Some(compiled_layer) = &compiled_layer {
+            let digest = layers[i].config.digest();
+            if let Err(err) = cache.put(T::name(), precompile_id, digest, compiled_layer) {
+                log::warn!("failed to cache precompiled layer {digest}: {err}");
+            }
+        }
+        compiled_layers[i] = compiled_layer;
+    }
+    Ok(compiled_layers)
+}
+
+// Returns a copy of the layer descriptor with the entrypoint function selected by the artifact config
+fn with_entrypoint_function(
+    descriptor: &oci_spec::image::Descriptor,
+    func: &str,
+) -> oci_spec::image::Descriptor {
+    let mut descriptor = descriptor.clone();
+    let mut annotations = descriptor.annotations().clone().unwrap_or_default();
+    annotations.insert(ENTRYPOINT_FUNCTION_ANNOTATION.to_string(), func.to_string());
+    descriptor.set_annotations(Some(annotations));
+    descriptor
+}
+
+// Returns whether a manifest pulled from a registry is an image index
+fn is_index_content(manifest: &[u8]) -> bool {
+    let Ok(manifest) = serde_json::from_slice::<serde_json::Value>(manifest) else {
+        return false;
+    };
+    match manifest
+        .get("mediaType")
+        .and_then(|media_type| media_type.as_str())
+    {
+        Some(media_type) => is_image_index(media_type),
+        None => manifest.get("manifests").is_some(),
+    }
+}
+
+// Returns whether the `pull` command fetches a layer: the layers the engine supports, and the
+// rootfs layers containerd unpacks when it creates the containers
+fn is_pulled_layer(media_type: &MediaType, supported_layer_types: &[&str]) -> bool {
+    let rootfs = matches!(
+        media_type,
+        MediaType::ImageLayer | MediaType::ImageLayerGzip | MediaType::ImageLayerZstd
+    ) || media_type
+        .to_string()
+        .starts_with("application/vnd.docker.image.rootfs.diff.tar");
+    let media_type = media_type.to_string();
+    let (media_type, _) = compression::uncompressed_media_type(&media_type);
+    rootfs
+        || is_wasm_layer(media_type, supported_layer_types)
+        || media_type == WASM_ARTIFACT_LAYER_MEDIA_TYPE
+}
+
+fn is_image_index(media_type: &str) -> bool {
+    media_type == MediaType::ImageIndex.to_string() || media_type == DOCKER_MANIFEST_LIST_MEDIA_TYPE
+}
+
+// Returns the manifests of an image index, in order of preference for the engine:
+// the wasm manifests annotated with the engine, then the wasm manifests without an engine
+// annotation, then the native manifests of the platform of the node, which may be dual-format
+fn select_manifests<'a>(index: &'a ImageIndex, engine_name: Option<&str>) -> Vec<&'a Descriptor> {
+    let wasm = index.manifests().iter().filter(|descriptor| {
+        descriptor
+            .platform()
+            .as_ref()
+            .is_some_and(|platform| platform.architecture() == &Arch::Wasm)
+    });
+    let engine = |descriptor: &Descriptor| {
+        descriptor
+            .annotations()
+            .as_ref()
+            .and_then(|annotations| annotations.get(ENGINE_ANNOTATION).cloned())
+    };
+    let for_engine = wasm
+        .clone()
+        .filter(|descriptor| engine_name.is_some() && engine(descriptor).as_deref() == engine_name);
+    let for_any = wasm.filter(|descriptor| engine(descriptor).is_none());
+    // the native manifests of the platform of the node, which may be dual-format
+    let native = index.manifests().iter().filter(|descriptor| {
+        descriptor.platform().as_ref().is_some_and(|platform| {
+            platform.os() == &Os::Linux && platform.architecture() == &Arch::default()
+        })
+    });
+    for_engine.chain(for_any).chain(native).collect()
+}
+
+// Returns whether the manifest of a native image has wasm layers the engine supports, besides
+// the rootfs layers containerd unpacks for runc
+fn is_dual_format(manifest: &ImageManifest, supported_layer_types: &[&str]) -> bool {
+    manifest.layers().iter().any(|layer| {
+        let media_type = layer.media_type().to_string();
+        let (media_type, _) = compression::uncompressed_media_type(&media_type);
+        is_wasm_layer(media_type, supported_layer_types)
+    })
+}
+
+fn is_wasm_layer(media_type: &str, supported_layer_types: &[&str]) -> bool {
+    let supported = supported_layer_types.contains(&media_type);
+    log::debug!("layer type {} is supported: {}", media_type, supported);
     supported
 }
 
@@ -736,7 +1636,9 @@ mod tests {
         assert_eq!(layers.len(), 1);
         assert_eq!(layers[0].layer, fake_precompiled_bytes.bytes);
 
-        let (manifest, _) = client.get_image_manifest_and_digest(&image_name).unwrap();
+        let (manifest, _) = client
+            .get_image_manifest_and_digest(&image_name, None)
+            .unwrap();
         let original_config = manifest.layers().first().unwrap();
         let info = client.get_info(original_config.digest()).unwrap();
 
@@ -747,6 +1649,288 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stale_precompiled_content() {
+        let target = PrecompileTarget {
+            version: "0.5.0".to_string(),
+            target: "x86_64-linux".to_string(),
+            features: vec!["avx2".to_string(), "bmi2".to_string()],
+        };
+        let labels = precompile_target_labels("wasmtime", &target);
+        assert_eq!(
+            labels["runwasi.io/precompiled/target-features"],
+            "avx2,bmi2"
+        );
+        assert!(!is_stale(&labels, &labels));
+
+        // content precompiled before the labels were recorded
+        assert!(!is_stale(&HashMap::new(), &labels));
+
+        let upgraded = PrecompileTarget {
+            version: "0.6.0".to_string(),
+            ..target.clone()
+        };
+        assert!(is_stale(
+            &labels,
+            &precompile_target_labels("wasmtime", &upgraded)
+        ));
+        let other_cpu = PrecompileTarget {
+            features: vec!["avx2".to_string()],
+            ..target
+        };
+        assert!(is_stale(
+            &labels,
+            &precompile_target_labels("wasmtime", &other_cpu)
+        ));
+
+        // unknown values are not recorded
+        let labels = precompile_target_labels("wasmtime", &PrecompileTarget::host(""));
+        assert!(!labels.contains_key("runwasi.io/precompiled/engine-version"));
+        assert!(!labels.contains_key("runwasi.io/precompiled/target-features"));
+    }
+
+    #[test]
+    fn test_select_manifests() {
+        use oci_spec::image::{DescriptorBuilder, ImageIndexBuilder, PlatformBuilder};
+
+        let manifest = |digest: &str, arch: Arch, engine: Option<&str>| {
+            let platform = PlatformBuilder::default()
+                .architecture(arch)
+                .os("wasip1")
+                .build()
+                .unwrap();
+            let annotations = engine
+                .map(|engine| HashMap::from([(ENGINE_ANNOTATION.to_string(), engine.to_string())]));
+            DescriptorBuilder::default()
+                .media_type(MediaType::ImageManifest)
+                .digest(digest)
+                .size(0)
+                .platform(platform)
+                .annotations(annotations.unwrap_or_default())
+                .build()
+                .unwrap()
+        };
+        let index = ImageIndexBuilder::default()
+            .schema_version(2u32)
+            .manifests(vec![
+                manifest("sha256:native", Arch::Amd64, None),
+                manifest("sha256:wasmedge", Arch::Wasm, Some("wasmedge")),
+                manifest("sha256:any", Arch::Wasm, None),
+                manifest("sha256:wasmtime", Arch::Wasm, Some("wasmtime")),
+            ])
+            .build()
+            .unwrap();
+        let digests = |engine| {
+            select_manifests(&index, engine)
+                .into_iter()
+                .map(|descriptor| descriptor.digest().as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(digests(Some("wasmtime")), ["sha256:wasmtime", "sha256:any"]);
+        assert_eq!(digests(Some("wasmer")), ["sha256:any"]);
+        assert_eq!(digests(None), ["sha256:any"]);
+        assert!(is_image_index(&MediaType::ImageIndex.to_string()));
+        assert!(!is_image_index(&MediaType::ImageManifest.to_string()));
+    }
+
+    #[test]
+    fn test_dual_format_image() {
+        use oci_spec::image::{
+            DescriptorBuilder, ImageIndexBuilder, ImageManifestBuilder, PlatformBuilder,
+        };
+
+        let descriptor = |media_type: MediaType, digest: &str| {
+            DescriptorBuilder::default()
+                .media_type(media_type)
+                .digest(digest)
+                .size(0)
+                .build()
+                .unwrap()
+        };
+        let manifest = |layers| {
+            ImageManifestBuilder::default()
+                .schema_version(2u32)
+                .config(descriptor(MediaType::ImageConfig, "sha256:config"))
+                .layers(layers)
+                .build()
+                .unwrap()
+        };
+        let supported = &[WASM_LAYER_MEDIA_TYPE];
+        let rootfs = descriptor(MediaType::ImageLayerGzip, "sha256:rootfs");
+        let wasm =
+            |media_type: &str| descriptor(MediaType::Other(media_type.to_string()), "sha256:wasm");
+
+        // the native rootfs and the wasm layer
+        assert!(is_dual_format(
+            &manifest(vec![rootfs.clone(), wasm(WASM_LAYER_MEDIA_TYPE)]),
+            supported
+        ));
+        assert!(is_dual_format(
+            &manifest(vec![
+                rootfs.clone(),
+                wasm(&format!("{WASM_LAYER_MEDIA_TYPE}+zstd"))
+            ]),
+            supported
+        ));
+        // a native image, or the wasm layer of another engine
+        assert!(!is_dual_format(&manifest(vec![rootfs.clone()]), supported));
+        assert!(!is_dual_format(
+            &manifest(vec![rootfs, wasm("application/javascript")]),
+            supported
+        ));
+
+        // the native manifest of the node follows the wasm manifests of an index
+        let platform = |os: Os, arch: Arch| {
+            PlatformBuilder::default()
+                .os(os)
+                .architecture(arch)
+                .build()
+                .unwrap()
+        };
+        let mut native = descriptor(MediaType::ImageManifest, "sha256:native");
+        native.set_platform(Some(platform(Os::Linux, Arch::default())));
+        let mut wasm = descriptor(MediaType::ImageManifest, "sha256:wasm");
+        wasm.set_platform(Some(platform(Os::Other("wasip1".to_string()), Arch::Wasm)));
+        let mut other = descriptor(MediaType::ImageManifest, "sha256:other");
+        other.set_platform(Some(platform(Os::Linux, Arch::Mips64)));
+        let index = ImageIndexBuilder::default()
+            .schema_version(2u32)
+            .manifests(vec![native, other, wasm])
+            .build()
+            .unwrap();
+        let digests = select_manifests(&index, Some("wasmtime"))
+            .into_iter()
+            .map(|descriptor| descriptor.digest().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(digests, ["sha256:wasm", "sha256:native"]);
+    }
+
+    #[test]
+    fn test_pulled_layers() {
+        let supported = &[WASM_LAYER_MEDIA_TYPE];
+        let other = "application/vnd.example.wasm.layer.v1+wasm";
+
+        assert!(is_pulled_layer(
+            &MediaType::Other(WASM_LAYER_MEDIA_TYPE.to_string()),
+            supported
+        ));
+        assert!(is_pulled_layer(
+            &MediaType::Other(format!("{WASM_LAYER_MEDIA_TYPE}+zstd")),
+            supported
+        ));
+        assert!(is_pulled_layer(
+            &MediaType::Other(WASM_ARTIFACT_LAYER_MEDIA_TYPE.to_string()),
+            supported
+        ));
+        assert!(is_pulled_layer(&MediaType::ImageLayerGzip, supported));
+        assert!(is_pulled_layer(
+            &MediaType::Other("application/vnd.docker.image.rootfs.diff.tar.gzip".to_string()),
+            supported
+        ));
+        assert!(!is_pulled_layer(
+            &MediaType::Other(other.to_string()),
+            supported
+        ));
+        assert!(!is_pulled_layer(
+            &MediaType::Other(format!("{other}+gzip")),
+            supported
+        ));
+
+        assert!(is_index_content(
+            br#"{"mediaType":"application/vnd.oci.image.index.v1+json","manifests":[]}"#
+        ));
+        assert!(is_index_content(br#"{"schemaVersion":2,"manifests":[]}"#));
+        assert!(!is_index_content(
+            br#"{"mediaType":"application/vnd.oci.image.manifest.v1+json","layers":[]}"#
+        ));
+        assert!(!is_index_content(b"not json"));
+    }
+
+    #[test]
+    fn test_precompile_image() {
+        let path = PathBuf::from("/run/containerd/containerd.sock");
+        let path = path.to_str().unwrap();
+        let client = Client::connect(path, crate::testing::TEST_NAMESPACE).unwrap();
+
+        let fake_bytes = generate_content("original", WASM_LAYER_MEDIA_TYPE);
+        let (image_name, container_name, _cleanup) = generate_test_container(None, &[&fake_bytes]);
+
+        let fake_precompiled_bytes = generate_content("precompiled", WASM_LAYER_MEDIA_TYPE);
+        let mut engine = FakePrecomiplerEngine::new(Some(()));
+        engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
+
+        let layers = client.precompile_image(&image_name, &engine).unwrap();
+        assert_eq!(engine.precompile_called.load(Ordering::SeqCst), 1);
+        assert_eq!(layers.len(), 1);
+        let (_, precompiled) = &layers[0];
+        assert_eq!(
+            *precompiled,
+            format!("sha256:{}", &digest(fake_precompiled_bytes.bytes.clone()))
+        );
+
+        let info = client.get_info(precompiled).unwrap();
+        assert_eq!(
+            info.labels
+                .get("runwasi.io/precompiled/media-type")
+                .unwrap(),
+            WASM_PRECOMPILED_LAYER_MEDIA_TYPE
+        );
+
+        // the containers running the image use the precompiled layers
+        let (layers, _) = client.load_modules(container_name, &engine).unwrap();
+        assert_eq!(engine.precompile_called.load(Ordering::SeqCst), 1);
+        assert_eq!(layers[0].layer, fake_precompiled_bytes.bytes);
+    }
+
+    #[test]
+    fn test_precompiled_layers_are_collected() {
+        let path = PathBuf::from("/run/containerd/containerd.sock");
+        let path = path.to_str().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let client = Client::connect(path, crate::testing::TEST_NAMESPACE)
+            .unwrap()
+            .with_precompile_cache_dir(Some(cache_dir.path().to_path_buf()));
+
+        let fake_bytes = generate_content("original", WASM_LAYER_MEDIA_TYPE);
+        let (_, container_name, _cleanup) = generate_test_container(None, &[&fake_bytes]);
+
+        let fake_precompiled_bytes = generate_content("precompiled", WASM_LAYER_MEDIA_TYPE);
+        let mut engine = FakePrecomiplerEngine::new(Some(()));
+        engine.add_precompiled_bits(fake_bytes.bytes.clone(), &fake_precompiled_bytes);
+        client.load_modules(container_name, &engine).unwrap();
+
+        // the artifact of a layer removed from the content store is dropped
+        let cache = PrecompileCache::new(cache_dir.path());
+        let removed = format!("sha256:{}", digest(b"removed layer".to_vec()));
+        cache.put("fake", "1", &removed, b"compiled").unwrap();
+
+        let collected = client.collect_precompiled().unwrap();
+        let removed_path = cache_dir
+            .path()
+            .join("fake/1")
+            .join(removed.replace(':', "-"));
+        assert!(collected.contains(&removed_path.display().to_string()));
+        assert_eq!(cache.get("fake", "1", &removed), None);
+
+        // the precompiled layer of the image is kept, with its lease
+        let original = format!("sha256:{}", digest(fake_bytes.bytes.clone()));
+        assert!(cache.entries().iter().any(|e| e.digest == original));
+        let req = ListRequest { filters: vec![] };
+        let leases = client
+            .rt
+            .block_on(
+                LeasesClient::new(client.inner.clone())
+                    .list(with_namespace!(req, client.namespace)),
+            )
+            .unwrap()
+            .into_inner()
+            .leases;
+        assert!(leases
+            .iter()
+            .any(|lease| lease.labels.get(PRECOMPILED_SOURCE_LABEL) == Some(&original)));
+    }
+
     #[test]
     fn test_layers_are_precompiled_but_not_for_all_layers() {
         let path = PathBuf::from("/run/containerd/containerd.sock");
@@ -877,7 +2061,9 @@ mod tests {
         assert_eq!(layers[0].layer, fake_precompiled_bytes.bytes);
         assert_eq!(layers[1].layer, fake_precompiled_bytes2.bytes);
 
-        let (manifest, _) = client.get_image_manifest_and_digest(&image_name).unwrap();
+        let (manifest, _) = client
+            .get_image_manifest_and_digest(&image_name, None)
+            .unwrap();
 
         let original_config1 = manifest.layers().first().unwrap();
         let info1 = client.get_info(original_config1.digest()).unwrap();
@@ -896,6 +2082,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_content_path() {
+        temp_env::with_var(CONTENT_STORE_DIR_ENV, Some("/content"), || {
+            assert_eq!(
+                content_path("sha256:abcd01"),
+                Some(PathBuf::from("/content/blobs/sha256/abcd01"))
+            );
+            assert_eq!(content_path("sha256:../../etc/passwd"), None);
+            assert_eq!(content_path("abcd01"), None);
+        });
+    }
+
     fn generate_test_container(
         name: Option<String>,
         original: &[&oci_helpers::ImageContent],
@@ -1000,7 +2198,7 @@ mod tests {
                     continue;
                 }
 
-                let key = digest(layer.layer.clone());
+                let key = digest(layer.layer.to_vec());
                 if self.precompiled_layers.values().any(|l| digest(l) == key) {
                     // simulate scenario were one of the layers is already compiled
                     compiled_layers.push(None);
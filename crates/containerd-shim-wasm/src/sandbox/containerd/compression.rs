@@ -0,0 +1,140 @@
+//! Decompression of the wasm layers compressed with gzip or zstd.
+//!
+//! A compressed layer has the media type of its content with a `+gzip` or `+zstd` suffix, e.g.,
+//! `application/vnd.bytecodealliance.wasm.component.layer.v0+wasm+zstd`, as built by
+//! `oci-tar-builder --compression`. The shim matches the media type of the content against the
+//! layer types of its engine, and passes the decompressed content to the engine.
+
+use std::io::Read;
+
+use oci_spec::image::{Descriptor, MediaType};
+
+use crate::sandbox::error::{Error as ShimError, Result};
+
+/// The compression of a layer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// Returns the media type of the content of a layer, and its compression, if it is compressed.
+pub(crate) fn uncompressed_media_type(media_type: &str) -> (&str, Option<Compression>) {
+    if let Some(media_type) = media_type.strip_suffix("+gzip") {
+        (media_type, Some(Compression::Gzip))
+    } else if let Some(media_type) = media_type.strip_suffix("+zstd") {
+        (media_type, Some(Compression::Zstd))
+    } else {
+        (media_type, None)
+    }
+}
+
+/// Returns the descriptor of a layer with the media type of its content, if it is compressed.
+pub(crate) fn uncompressed_descriptor(
+    descriptor: &Descriptor,
+) -> (Descriptor, Option<Compression>) {
+    let media_type = descriptor.media_type().to_string();
+    let (media_type, compression) = uncompressed_media_type(&media_type);
+    let mut descriptor = descriptor.clone();
+    if compression.is_some() {
+        descriptor.set_media_type(MediaType::from(media_type));
+    }
+    (descriptor, compression)
+}
+
+/// Returns the compression of the content of a layer from its magic number, e.g., for a layer
+/// whose descriptor no longer has the media type it was pulled with.
+pub(crate) fn detect(content: &[u8]) -> Option<Compression> {
+    if content.starts_with(&[0x1f, 0x8b]) {
+        Some(Compression::Gzip)
+    } else if content.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(Compression::Zstd)
+    } else {
+        None
+    }
+}
+
+/// The maximum size of the decompressed content of a layer, so that a small layer can't exhaust
+/// the memory of the shim once decompressed.
+pub(crate) const MAX_DECOMPRESSED_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Decompresses the content of a layer, failing if it is larger than `limit` bytes.
+pub(crate) fn decompress(content: &[u8], compression: Compression, limit: u64) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    // read one more byte than the limit, to tell content of the limit from larger content
+    let res = match compression {
+        Compression::Gzip => flate2::read::GzDecoder::new(content)
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut decompressed),
+        Compression::Zstd => zstd::stream::read::Decoder::new(content).and_then(|decoder| {
+            decoder
+                .take(limit.saturating_add(1))
+                .read_to_end(&mut decompressed)
+        }),
+    };
+    res.map_err(|err| {
+        ShimError::InvalidArgument(format!("failed to decompress {compression:?} layer: {err}"))
+    })?;
+    if decompressed.len() as u64 > limit {
+        return Err(ShimError::InvalidArgument(format!(
+            "{compression:?} layer is larger than {limit} bytes once decompressed"
+        )));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use oci_spec::image::DescriptorBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_uncompressed_media_type() {
+        let wasm = "application/vnd.bytecodealliance.wasm.component.layer.v0+wasm";
+        assert_eq!(uncompressed_media_type(wasm), (wasm, None));
+        assert_eq!(
+            uncompressed_media_type(&format!("{wasm}+gzip")),
+            (wasm, Some(Compression::Gzip))
+        );
+        assert_eq!(
+            uncompressed_media_type(&format!("{wasm}+zstd")),
+            (wasm, Some(Compression::Zstd))
+        );
+
+        let descriptor = DescriptorBuilder::default()
+            .media_type(MediaType::Other(format!("{wasm}+zstd")))
+            .digest("sha256:1234")
+            .size(4)
+            .build()
+            .unwrap();
+        let (descriptor, compression) = uncompressed_descriptor(&descriptor);
+        assert_eq!(descriptor.media_type().to_string(), wasm);
+        assert_eq!(descriptor.digest(), "sha256:1234");
+        assert_eq!(compression, Some(Compression::Zstd));
+    }
+
+    #[test]
+    fn test_decompress() -> Result<()> {
+        let module = b"\0asm\x01\0\0\0";
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(module)?;
+        let gzip = gzip.finish()?;
+        assert_eq!(decompress(&gzip, Compression::Gzip, 8)?, module);
+        assert!(decompress(&gzip, Compression::Gzip, 7).is_err());
+
+        let zstd = zstd::encode_all(module.as_slice(), 0)?;
+        assert_eq!(decompress(&zstd, Compression::Zstd, 8)?, module);
+        assert!(decompress(&zstd, Compression::Zstd, 7).is_err());
+
+        assert!(decompress(module, Compression::Zstd, 8).is_err());
+
+        assert_eq!(detect(&gzip), Some(Compression::Gzip));
+        assert_eq!(detect(&zstd), Some(Compression::Zstd));
+        assert_eq!(detect(module), None);
+        Ok(())
+    }
+}
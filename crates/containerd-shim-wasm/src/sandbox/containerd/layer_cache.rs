@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+use crate::sandbox::oci::LayerContent;
+
+/// The total size of the layers kept by the cache of the shim process
+const MAX_CACHED_BYTES: usize = 1 << 30;
+
+/// The precompiled layers loaded by the shim process.
+///
+/// The containers of a Kubernetes pod share one shim process, so the containers running the
+/// same image reuse the layers loaded by the first one, and their memory, rather than loading
+/// them from the content store again.
+/// Layers are keyed by the digest of their content. The least recently used layers are evicted
+/// once the cache exceeds its size, and are loaded again by the next container using them.
+pub(crate) struct LayerCache {
+    layers: Mutex<Layers>,
+    max_bytes: usize,
+    compiling: Mutex<HashSet<String>>,
+    compiled: Condvar,
+}
+
+#[derive(Default)]
+struct Layers {
+    entries: HashMap<String, Entry>,
+    bytes: usize,
+    clock: u64,
+}
+
+struct Entry {
+    layer: LayerContent,
+    last_used: u64,
+}
+
+/// Holds the compilation of an image, until dropped.
+pub(crate) struct CompilationLock<'a> {
+    cache: &'a LayerCache,
+    image_digest: String,
+}
+
+impl Drop for CompilationLock<'_> {
+    fn drop(&mut self) {
+        let mut compiling = self
+            .cache
+            .compiling
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        compiling.remove(&self.image_digest);
+        self.cache.compiled.notify_all();
+    }
+}
+
+impl Default for LayerCache {
+    fn default() -> Self {
+        Self::with_max_bytes(MAX_CACHED_BYTES)
+    }
+}
+
+impl LayerCache {
+    fn with_max_bytes(max_bytes: usize) -> Self {
+        Self {
+            layers: Mutex::default(),
+            max_bytes,
+            compiling: Mutex::default(),
+            compiled: Condvar::new(),
+        }
+    }
+
+    /// Returns the cache of the shim process.
+    pub fn shared() -> &'static Self {
+        static CACHE: OnceLock<LayerCache> = OnceLock::new();
+        CACHE.get_or_init(Self::default)
+    }
+
+    /// Returns the content with `digest`, if the process loaded it.
+    pub fn get(&self, digest: &str) -> Option<LayerContent> {
+        let mut layers = self.layers.lock().unwrap();
+        layers.clock += 1;
+        let clock = layers.clock;
+        let entry = layers.entries.get_mut(digest)?;
+        entry.last_used = clock;
+        Some(entry.layer.clone())
+    }
+
+    pub fn insert(&self, digest: &str, layer: LayerContent) {
+        let mut layers = self.layers.lock().unwrap();
+        layers.clock += 1;
+        let entry = Entry {
+            last_used: layers.clock,
+            layer,
+        };
+        layers.bytes += entry.layer.len();
+        if let Some(replaced) = layers.entries.insert(digest.to_string(), entry) {
+            layers.bytes -= replaced.layer.len();
+        }
+        // the containers using an evicted layer keep it until they exit
+        while layers.bytes > self.max_bytes && layers.entries.len() > 1 {
+            let Some(lru) = layers
+                .entries
+                .iter()
+                .filter(|(key, _)| key.as_str() != digest)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            let evicted = layers.entries.remove(&lru).unwrap();
+            layers.bytes -= evicted.layer.len();
+            log::debug!("evicted layer {lru} from the layer cache");
+        }
+    }
+
+    /// Waits for the other containers of the process to finish precompiling the image with
+    /// `image_digest`, so that containers created together with the same image only compile
+    /// it once. The containers of other images compile theirs concurrently.
+    pub fn lock_compilation(&self, image_digest: &str) -> CompilationLock<'_> {
+        let compiling = self
+            .compiling
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut compiling = self
+            .compiled
+            .wait_while(compiling, |compiling| compiling.contains(image_digest))
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        compiling.insert(image_digest.to_string());
+        CompilationLock {
+            cache: self,
+            image_digest: image_digest.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_layer_cache() {
+        let cache = LayerCache::default();
+        assert_eq!(cache.get("sha256:abcd"), None);
+
+        let layer = LayerContent::from(b"compiled".to_vec());
+        cache.insert("sha256:abcd", layer.clone());
+        let cached = cache.get("sha256:abcd").unwrap();
+        assert_eq!(cached, layer);
+        // the containers share the memory of the layer
+        assert_eq!(cached.as_ptr(), layer.as_ptr());
+    }
+
+    #[test]
+    fn test_layer_cache_evicts_least_recently_used() {
+        let cache = LayerCache::with_max_bytes(8);
+        cache.insert("sha256:a", LayerContent::from(vec![0; 4]));
+        cache.insert("sha256:b", LayerContent::from(vec![0; 4]));
+        assert!(cache.get("sha256:a").is_some());
+
+        cache.insert("sha256:c", LayerContent::from(vec![0; 4]));
+        assert!(cache.get("sha256:a").is_some());
+        assert_eq!(cache.get("sha256:b"), None);
+        assert!(cache.get("sha256:c").is_some());
+
+        // a layer larger than the cache is kept until the next one
+        cache.insert("sha256:d", LayerContent::from(vec![0; 16]));
+        assert!(cache.get("sha256:d").is_some());
+        assert_eq!(cache.get("sha256:a"), None);
+    }
+
+    #[test]
+    fn test_lock_compilation_per_image() {
+        let cache = LayerCache::default();
+        let lock = cache.lock_compilation("sha256:image1");
+        // another image compiles concurrently
+        drop(cache.lock_compilation("sha256:image2"));
+
+        std::thread::scope(|s| {
+            let (tx, rx) = mpsc::channel();
+            let cache = &cache;
+            s.spawn(move || {
+                let _lock = cache.lock_compilation("sha256:image1");
+                tx.send(()).unwrap();
+            });
+            // the same image waits for the first compilation
+            assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(lock);
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        });
+    }
+}
@@ -1,9 +1,9 @@
-#![cfg(unix)]
-
 use containerd_client::services::v1::leases_client::LeasesClient;
 use containerd_client::{tonic, with_namespace};
 use tonic::Request;
 
+use super::client::connect;
+
 // Adds lease info to grpc header
 // https://github.com/containerd/containerd/blob/8459273f806e068e1a6bacfaf1355bbbad738d5e/docs/garbage-collection.md#using-grpc
 #[macro_export]
@@ -34,7 +34,7 @@ impl Drop for LeaseGuard {
             .build()
             .unwrap();
 
-        let client = rt.block_on(containerd_client::connect(self.address.clone()));
+        let client = rt.block_on(connect(self.address.as_ref()));
 
         let channel = match client {
             Ok(channel) => channel,
@@ -1,6 +1,12 @@
-#![cfg(unix)]
-
 mod client;
+mod compression;
+mod layer_cache;
 mod lease;
+mod precompile_cache;
+mod signature;
 
 pub(crate) use client::Client;
+#[cfg(unix)]
+pub(crate) use precompile_cache::{stats as precompile_cache_stats, CacheStats};
+#[cfg(unix)]
+pub(crate) use signature::SignaturePolicy;
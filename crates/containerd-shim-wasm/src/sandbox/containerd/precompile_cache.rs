@@ -0,0 +1,233 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tempfile::NamedTempFile;
+
+use crate::sandbox::node_config;
+
+/// Environment variable with the directory of the node-level precompilation cache
+pub(crate) const PRECOMPILE_CACHE_DIR_ENV: &str = "RUNWASI_PRECOMPILE_CACHE_DIR";
+
+// the file whose modification time is the last garbage collection of the cache
+const GC_MARKER: &str = ".gc";
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static WRITES: AtomicU64 = AtomicU64::new(0);
+
+/// The lookups and writes of the precompilation cache by the shim process
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub(crate) struct CacheStats {
+    pub dir: Option<PathBuf>,
+    pub hits: u64,
+    pub misses: u64,
+    pub writes: u64,
+}
+
+pub(crate) fn stats() -> CacheStats {
+    CacheStats {
+        dir: PrecompileCache::from_env().map(|cache| cache.dir),
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        writes: WRITES.load(Ordering::Relaxed),
+    }
+}
+
+/// A directory based cache of precompiled layers shared by all the containers on a node.
+///
+/// Artifacts are stored as `<dir>/<engine name>/<precompile id>/<layer digest>`, so
+/// an artifact is only reused by the same engine name and version that produced it.
+/// Unlike the content store cache, it doesn't depend on the image labels and survives
+/// the removal of the image, until the garbage collection of the shim drops the artifacts of
+/// the layers that are no longer in the content store.
+#[derive(Debug, Clone)]
+pub(crate) struct PrecompileCache {
+    dir: PathBuf,
+}
+
+/// A precompiled artifact of the cache
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CacheEntry {
+    pub path: PathBuf,
+    /// The digest of the layer the artifact was compiled from
+    pub digest: String,
+}
+
+impl PrecompileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns the cache configured with `RUNWASI_PRECOMPILE_CACHE_DIR`, if any.
+    pub fn from_env() -> Option<Self> {
+        node_config::var_os(PRECOMPILE_CACHE_DIR_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(Self::new)
+    }
+
+    fn path(&self, engine: &str, precompile_id: &str, digest: &str) -> PathBuf {
+        self.dir
+            .join(engine)
+            .join(precompile_id)
+            .join(digest.replace(':', "-"))
+    }
+
+    /// Returns the precompiled artifact for the layer with `digest`, if it is cached.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn get(&self, engine: &str, precompile_id: &str, digest: &str) -> Option<Vec<u8>> {
+        let path = self.path(engine, precompile_id, digest);
+        match fs::read(&path) {
+            Ok(artifact) => {
+                log::info!("using cached precompiled layer {path:?}");
+                HITS.fetch_add(1, Ordering::Relaxed);
+                Some(artifact)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                MISSES.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(err) => {
+                log::warn!("failed to read cached precompiled layer {path:?}: {err}");
+                MISSES.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Stores the precompiled artifact for the layer with `digest`.
+    /// The artifact is written to a temporary file of its own first, and synced before it is
+    /// renamed, so concurrent readers and writers, or a crash, never leave partial content.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn put(
+        &self,
+        engine: &str,
+        precompile_id: &str,
+        digest: &str,
+        artifact: &[u8],
+    ) -> std::io::Result<()> {
+        let path = self.path(engine, precompile_id, digest);
+        let parent = path.parent().expect("cache path has a parent");
+        fs::create_dir_all(parent)?;
+
+        // the temporary files are hidden from `entries`, with a `.tmp` prefix
+        let mut tmp = NamedTempFile::new_in(parent)?;
+        tmp.write_all(artifact)?;
+        tmp.as_file().sync_all()?;
+        tmp.persist(&path).map_err(|err| err.error)?;
+        log::info!("cached precompiled layer {path:?}");
+        WRITES.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the artifacts of the cache, of every engine and version.
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let read_dir = |dir: PathBuf| {
+            fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        };
+        read_dir(self.dir.clone())
+            .flat_map(|engine| read_dir(engine.path()))
+            .flat_map(|precompile_id| read_dir(precompile_id.path()))
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+            .map(|entry| CacheEntry {
+                path: entry.path(),
+                digest: entry.file_name().to_string_lossy().replacen('-', ":", 1),
+            })
+            .collect()
+    }
+
+    /// Removes an artifact from the cache, with the directory of its engine version once empty.
+    pub fn remove(&self, entry: &CacheEntry) -> std::io::Result<()> {
+        fs::remove_file(&entry.path)?;
+        if let Some(parent) = entry.path.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+        log::info!("removed cached precompiled layer {:?}", entry.path);
+        Ok(())
+    }
+
+    /// Returns whether the garbage collection of the cache last ran more than `interval` ago,
+    /// and records that it runs now if so, so that the shims of a node take turns running it.
+    pub fn gc_due(&self, interval: Duration) -> bool {
+        let marker = self.dir.join(GC_MARKER);
+        let last = fs::metadata(&marker).and_then(|metadata| metadata.modified());
+        if last.is_ok_and(|last| last.elapsed().is_ok_and(|elapsed| elapsed < interval)) {
+            return false;
+        }
+        let recorded = fs::create_dir_all(&self.dir)
+            .and_then(|_| File::options().create(true).append(true).open(&marker))
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(err) = recorded {
+            log::warn!("failed to record the garbage collection of {marker:?}: {err}");
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_roundtrip() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let cache = PrecompileCache::new(dir.path());
+
+        assert_eq!(cache.get("wasmtime", "1234", "sha256:abcd"), None);
+
+        cache.put("wasmtime", "1234", "sha256:abcd", b"compiled")?;
+        assert_eq!(
+            cache.get("wasmtime", "1234", "sha256:abcd"),
+            Some(b"compiled".to_vec())
+        );
+
+        // the artifact is replaced, without leaving temporary files behind
+        cache.put("wasmtime", "1234", "sha256:abcd", b"recompiled")?;
+        assert_eq!(
+            cache.get("wasmtime", "1234", "sha256:abcd"),
+            Some(b"recompiled".to_vec())
+        );
+        assert_eq!(fs::read_dir(dir.path().join("wasmtime/1234"))?.count(), 1);
+
+        // artifacts are not shared across engines or engine versions
+        assert_eq!(cache.get("wasmtime", "5678", "sha256:abcd"), None);
+        assert_eq!(cache.get("wasmer", "1234", "sha256:abcd"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_entries() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let cache = PrecompileCache::new(dir.path());
+        cache.put("wasmtime", "1234", "sha256:abcd", b"compiled")?;
+        cache.put("wasmer", "5678", "sha256:ef01", b"compiled")?;
+
+        let mut entries = cache.entries();
+        entries.sort_by(|a, b| a.digest.cmp(&b.digest));
+        let digests: Vec<_> = entries.iter().map(|e| e.digest.as_str()).collect();
+        assert_eq!(digests, ["sha256:abcd", "sha256:ef01"]);
+
+        cache.remove(&entries[0])?;
+        assert_eq!(cache.get("wasmtime", "1234", "sha256:abcd"), None);
+        assert!(!dir.path().join("wasmtime/1234").exists());
+        assert_eq!(cache.entries(), entries[1..]);
+
+        // the collection runs once per interval, and its marker isn't an entry
+        assert!(cache.gc_due(Duration::from_secs(60)));
+        assert!(!cache.gc_due(Duration::from_secs(60)));
+        assert!(cache.gc_due(Duration::ZERO));
+        assert_eq!(cache.entries().len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+//! Verification of the cosign signatures of images.
+//!
+//! When [`SIGNATURE_POLICY_ENV`] is set to the path of a policy file, the shim refuses to run
+//! containers whose image doesn't have a signature made by one of the keys the policy trusts
+//! for that image, e.g.:
+//!
+//! ```json
+//! {
+//!   "allowUnmatched": false,
+//!   "rules": [
+//!     { "images": "ghcr.io/containerd/runwasi/*", "keys": ["/etc/runwasi/cosign.pub"] }
+//!   ]
+//! }
+//! ```
+//!
+//! The signatures are read from the `<repository>:sha256-<digest>.sig` image that `cosign sign`
+//! pushes next to the image, which must have been pulled into containerd along with it.
+//! Keys are PEM encoded ECDSA P-256 or Ed25519 public keys, as generated by `cosign generate-key-pair`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use ring::signature::{UnparsedPublicKey, VerificationAlgorithm, ECDSA_P256_SHA256_ASN1, ED25519};
+use serde::Deserialize;
+
+use crate::sandbox::error::{Error as ShimError, Result};
+use crate::sandbox::node_config;
+
+/// Environment variable with the path of the signature policy
+pub const SIGNATURE_POLICY_ENV: &str = "RUNWASI_SIGNATURE_POLICY";
+
+/// Media type of the cosign signature layers, whose content is the signed payload
+pub(crate) const SIMPLE_SIGNING_MEDIA_TYPE: &str =
+    "application/vnd.dev.cosign.simplesigning.v1+json";
+
+/// Annotation of the cosign signature layers with the base64 encoded signature of the payload
+pub(crate) const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+// SubjectPublicKeyInfo headers of the supported keys, followed by the raw public key
+const P256_SPKI_HEADER: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+const ED25519_SPKI_HEADER: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// A signature of an image: the signed payload and its base64 encoded signature
+pub(crate) struct Signature {
+    pub payload: Vec<u8>,
+    pub signature: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignaturePolicy {
+    /// Whether images that match no rule can run without a signature
+    #[serde(default)]
+    allow_unmatched: bool,
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rule {
+    /// The image name, or a prefix of the name followed by `*`
+    images: String,
+    /// Paths of the keys trusted to sign the matching images
+    keys: Vec<PathBuf>,
+}
+
+impl Rule {
+    fn matches(&self, image_name: &str) -> bool {
+        match self.images.strip_suffix('*') {
+            Some(prefix) => image_name.starts_with(prefix),
+            None => image_name == self.images,
+        }
+    }
+}
+
+impl SignaturePolicy {
+    /// Loads the policy from [`SIGNATURE_POLICY_ENV`], if it is set
+    pub fn from_env() -> Result<Option<Self>> {
+        match node_config::var_os(SIGNATURE_POLICY_ENV) {
+            Some(path) if !path.is_empty() => Self::load(Path::new(&path)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let policy = fs::read(path).map_err(|err| {
+            ShimError::FailedPrecondition(format!(
+                "failed to read signature policy {path:?}: {err}"
+            ))
+        })?;
+        Ok(serde_json::from_slice(&policy)?)
+    }
+
+    /// Checks that one of the `signatures` of the image, whose manifest has `manifest_digest`,
+    /// is made by a key the first rule matching `image_name` trusts.
+    pub(crate) fn verify(
+        &self,
+        image_name: &str,
+        manifest_digest: &str,
+        signatures: &[Signature],
+    ) -> Result<()> {
+        let Some(rule) = self.rules.iter().find(|rule| rule.matches(image_name)) else {
+            if self.allow_unmatched {
+                return Ok(());
+            }
+            return Err(ShimError::FailedPrecondition(format!(
+                "image {image_name} is not allowed by the signature policy"
+            )));
+        };
+
+        let keys = rule
+            .keys
+            .iter()
+            .map(|path| load_key(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        let verified = signatures.iter().any(|signature| {
+            signs_digest(&signature.payload, manifest_digest)
+                && BASE64.decode(signature.signature.trim()).is_ok_and(|sig| {
+                    keys.iter()
+                        .any(|key| key.verify(&signature.payload, &sig).is_ok())
+                })
+        });
+        if !verified {
+            return Err(ShimError::FailedPrecondition(format!(
+                "image {image_name} has no signature from a trusted key"
+            )));
+        }
+        log::info!("verified signature of image {image_name}");
+        Ok(())
+    }
+}
+
+// Returns whether the cosign payload is the signature of the manifest with `digest`
+fn signs_digest(payload: &[u8], digest: &str) -> bool {
+    serde_json::from_slice::<serde_json::Value>(payload)
+        .ok()
+        .and_then(|payload| {
+            payload
+                .pointer("/critical/image/docker-manifest-digest")?
+                .as_str()
+                .map(|signed| signed == digest)
+        })
+        .unwrap_or(false)
+}
+
+fn load_key(path: &Path) -> Result<UnparsedPublicKey<Vec<u8>>> {
+    let invalid =
+        |reason: &str| ShimError::FailedPrecondition(format!("invalid key {path:?}: {reason}"));
+
+    let pem = fs::read_to_string(path).map_err(|err| invalid(&err.to_string()))?;
+    let der: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = BASE64
+        .decode(der.trim())
+        .map_err(|err| invalid(&err.to_string()))?;
+
+    let (algorithm, key): (&'static dyn VerificationAlgorithm, _) =
+        if let Some(key) = der.strip_prefix(P256_SPKI_HEADER) {
+            (&ECDSA_P256_SHA256_ASN1, key)
+        } else if let Some(key) = der.strip_prefix(ED25519_SPKI_HEADER) {
+            (&ED25519, key)
+        } else {
+            return Err(invalid(
+                "only ECDSA P-256 and Ed25519 public keys are supported",
+            ));
+        };
+    Ok(UnparsedPublicKey::new(algorithm, key.to_vec()))
+}
+
+/// Name of the image cosign stores the signatures of the manifest with `digest` in
+pub(crate) fn signature_image_name(image_name: &str, digest: &str) -> String {
+    let repository = image_name.split('@').next().unwrap_or(image_name);
+    let repository = match repository.rsplit_once(':') {
+        // a colon after the last slash separates the tag, otherwise it is the port of the registry
+        Some((name, tag)) if !tag.contains('/') => name,
+        _ => repository,
+    };
+    format!("{repository}:{}.sig", digest.replace(':', "-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const DIGEST: &str = "sha256:6c48b431d29a1ea1ece13fa50e9f33e4d164e07f6a501dbed668aed947002c5c";
+
+    fn payload(digest: &str) -> Vec<u8> {
+        format!(
+            r#"{{"critical":{{"identity":{{"docker-reference":"ghcr.io/containerd/runwasi/app"}},"image":{{"docker-manifest-digest":"{digest}"}},"type":"cosign container image signature"}},"optional":null}}"#
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_verify() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+
+        let spki = [P256_SPKI_HEADER, key.public_key().as_ref()].concat();
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            BASE64.encode(spki)
+        );
+        let key_path = dir.path().join("cosign.pub");
+        fs::write(&key_path, pem)?;
+
+        let policy_path = dir.path().join("policy.json");
+        fs::write(
+            &policy_path,
+            format!(
+                r#"{{"rules": [{{"images": "ghcr.io/containerd/runwasi/*", "keys": [{key_path:?}]}}]}}"#
+            ),
+        )?;
+        let policy = SignaturePolicy::load(&policy_path)?;
+
+        let sign = |payload: Vec<u8>| -> anyhow::Result<Signature> {
+            let signature = BASE64.encode(key.sign(&rng, &payload).unwrap());
+            Ok(Signature { payload, signature })
+        };
+
+        let image = "ghcr.io/containerd/runwasi/app:latest";
+        policy.verify(image, DIGEST, &[sign(payload(DIGEST))?])?;
+
+        // unsigned images, and signatures of other images, are refused
+        assert!(policy.verify(image, DIGEST, &[]).is_err());
+        let other = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+        assert!(policy
+            .verify(image, DIGEST, &[sign(payload(other))?])
+            .is_err());
+
+        // so are tampered payloads
+        let mut signature = sign(payload(DIGEST))?;
+        signature.payload.push(b'\n');
+        assert!(policy.verify(image, DIGEST, &[signature]).is_err());
+
+        // images that match no rule are refused unless allowed
+        assert!(policy.verify("docker.io/library/app", DIGEST, &[]).is_err());
+        let policy = SignaturePolicy {
+            allow_unmatched: true,
+            ..policy
+        };
+        policy.verify("docker.io/library/app", DIGEST, &[])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_image_name() {
+        assert_eq!(
+            signature_image_name("ghcr.io/containerd/runwasi/app:latest", "sha256:abc"),
+            "ghcr.io/containerd/runwasi/app:sha256-abc.sig"
+        );
+        assert_eq!(
+            signature_image_name("localhost:5000/app", "sha256:abc"),
+            "localhost:5000/app:sha256-abc.sig"
+        );
+        assert_eq!(
+            signature_image_name("localhost:5000/app@sha256:abc", "sha256:abc"),
+            "localhost:5000/app:sha256-abc.sig"
+        );
+    }
+}
@@ -7,6 +7,8 @@ use oci_spec::OciSpecError;
 use thiserror::Error;
 use ttrpc;
 
+use crate::container::ErrorCode;
+
 #[derive(Debug, Error)]
 pub enum Error {
     /// An error occurred while parsing the OCI spec
@@ -48,10 +50,25 @@ pub enum Error {
     Libcontainer(#[from] libcontainer::error::LibcontainerError),
     #[error("{0}")]
     Containerd(String),
+    /// A failure of the wasm module of a task, with its code
+    #[error("{0}: {1}")]
+    Wasm(ErrorCode, String),
 }
 
 pub type Result<T, E = Error> = ::std::result::Result<T, E>;
 
+/// Returns the ttrpc status code of a failure of a task with `code`
+fn ttrpc_code(code: ErrorCode) -> ttrpc::Code {
+    match code {
+        ErrorCode::ModuleNotFound => ttrpc::Code::NOT_FOUND,
+        ErrorCode::InvalidWasm => ttrpc::Code::INVALID_ARGUMENT,
+        ErrorCode::UnsupportedImport => ttrpc::Code::UNIMPLEMENTED,
+        ErrorCode::CompileFailed => ttrpc::Code::INTERNAL,
+        ErrorCode::Trap => ttrpc::Code::ABORTED,
+        ErrorCode::OutOfMemory => ttrpc::Code::RESOURCE_EXHAUSTED,
+    }
+}
+
 impl From<Error> for ttrpc::Error {
     fn from(e: Error) -> Self {
         match e {
@@ -79,9 +96,15 @@ impl From<Error> for ttrpc::Error {
             Error::Oci(ref _s) => {
                 ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::UNKNOWN, e.to_string()))
             }
-            Error::Any(ref s) => {
-                ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::UNKNOWN, s))
+            Error::Wasm(code, _) => {
+                ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc_code(code), e.to_string()))
             }
+            Error::Any(ref s) => match ErrorCode::of(s) {
+                Some(code) => {
+                    ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc_code(code), format!("{s:#}")))
+                }
+                None => ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::UNKNOWN, s)),
+            },
             _ => ttrpc::Error::Others(e.to_string()),
         }
     }
@@ -160,5 +183,25 @@ mod tests {
             }
             _ => panic!("unexpected error"),
         }
+
+        let e = Error::Wasm(ErrorCode::UnsupportedImport, "wasi:http".to_string());
+        let t: ttrpc::Error = e.into();
+        match t {
+            ttrpc::Error::RpcStatus(s) => {
+                assert_eq!(s.code(), ttrpc::Code::UNIMPLEMENTED);
+                assert_eq!(s.message, "unsupported-import: wasi:http");
+            }
+            _ => panic!("unexpected error"),
+        }
+
+        let e = Error::Any(AnyError::msg("bad magic").context(ErrorCode::InvalidWasm));
+        let t: ttrpc::Error = e.into();
+        match t {
+            ttrpc::Error::RpcStatus(s) => {
+                assert_eq!(s.code(), ttrpc::Code::INVALID_ARGUMENT);
+                assert_eq!(s.message, "invalid-wasm: bad magic");
+            }
+            _ => panic!("unexpected error"),
+        }
     }
 }
@@ -0,0 +1,156 @@
+//! Fault injection, for the tests of the recovery and error paths of the shims.
+//!
+//! With the `fault-injection` feature, the faults are injected by the tests with [`inject`], or
+//! in the environment of the shim with [`FAULTS_ENV`], a comma-separated list of faults, e.g.,
+//! `RUNWASI_FAULTS=stdio_open,compile_delay_ms=500,lease_loss`:
+//!  - `stdio_open`: opening the stdio of a container fails.
+//!  - `compile_delay_ms=<ms>`: the precompilation of the layers of an image is delayed.
+//!  - `lease_loss`: the leases of containerd are lost, so that writing content fails.
+//!
+//! The faults are global to the process, so the tests injecting them shouldn't run in parallel
+//! with the tests they could break.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+/// Environment variable with the faults injected in the shim
+pub const FAULTS_ENV: &str = "RUNWASI_FAULTS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Opening the stdio of a container fails
+    StdioOpen,
+    /// The precompilation of the layers of an image is delayed
+    CompileDelay(Duration),
+    /// The leases of containerd are lost
+    LeaseLoss,
+}
+
+impl std::str::FromStr for Fault {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.trim().split_once('=') {
+            None if s.trim() == "stdio_open" => Self::StdioOpen,
+            None if s.trim() == "lease_loss" => Self::LeaseLoss,
+            Some(("compile_delay_ms", ms)) => {
+                let ms = ms
+                    .parse()
+                    .with_context(|| format!("invalid delay {ms:?}"))?;
+                Self::CompileDelay(Duration::from_millis(ms))
+            }
+            _ => bail!("unknown fault {s:?}"),
+        })
+    }
+}
+
+fn faults() -> &'static Mutex<Vec<Fault>> {
+    static FAULTS: OnceLock<Mutex<Vec<Fault>>> = OnceLock::new();
+    FAULTS.get_or_init(|| {
+        let faults = std::env::var(FAULTS_ENV).unwrap_or_default();
+        let faults = parse(&faults).unwrap_or_else(|err| {
+            log::error!("ignoring {FAULTS_ENV}: {err:#}");
+            vec![]
+        });
+        Mutex::new(faults)
+    })
+}
+
+fn parse(faults: &str) -> Result<Vec<Fault>> {
+    faults
+        .split(',')
+        .filter(|fault| !fault.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+fn injected(matches: impl Fn(&Fault) -> bool) -> Option<Fault> {
+    faults().lock().unwrap().iter().copied().find(matches)
+}
+
+/// Injects `fault` until the returned guard is dropped.
+#[must_use = "the fault is removed when the guard is dropped"]
+pub fn inject(fault: Fault) -> FaultGuard {
+    log::warn!("injecting fault {fault:?}");
+    faults().lock().unwrap().push(fault);
+    FaultGuard(fault)
+}
+
+/// Removes all the faults, including the ones of [`FAULTS_ENV`].
+pub fn clear() {
+    faults().lock().unwrap().clear();
+}
+
+pub struct FaultGuard(Fault);
+
+impl Drop for FaultGuard {
+    fn drop(&mut self) {
+        let mut faults = faults().lock().unwrap();
+        if let Some(i) = faults.iter().position(|fault| *fault == self.0) {
+            faults.remove(i);
+        }
+    }
+}
+
+pub(crate) fn stdio_open(path: &std::path::Path) -> std::io::Result<()> {
+    match injected(|fault| *fault == Fault::StdioOpen) {
+        Some(_) => Err(std::io::Error::other(format!(
+            "injected fault: failed to open {path:?}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn compile_delay() {
+    if let Some(Fault::CompileDelay(delay)) =
+        injected(|fault| matches!(fault, Fault::CompileDelay(_)))
+    {
+        log::warn!("injected fault: delaying the precompilation by {delay:?}");
+        std::thread::sleep(delay);
+    }
+}
+
+pub(crate) fn lease_loss(reference: &str) -> Result<(), super::Error> {
+    match injected(|fault| *fault == Fault::LeaseLoss) {
+        Some(_) => Err(super::Error::Containerd(format!(
+            "injected fault: lease {reference} not found"
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(
+            parse("stdio_open, compile_delay_ms=500,lease_loss,")?,
+            vec![
+                Fault::StdioOpen,
+                Fault::CompileDelay(Duration::from_millis(500)),
+                Fault::LeaseLoss
+            ]
+        );
+        assert_eq!(parse("")?, vec![]);
+        assert!(parse("compile_delay_ms=soon").is_err());
+        assert!(parse("oom").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inject() {
+        // a fault without effect, not to break the tests running in parallel
+        let fault = Fault::CompileDelay(Duration::ZERO);
+        assert_eq!(injected(|f| *f == fault), None);
+        {
+            let _guard = inject(fault);
+            assert_eq!(injected(|f| *f == fault), Some(fault));
+            assert!(stdio_open(std::path::Path::new("/run/stdout")).is_ok());
+        }
+        assert_eq!(injected(|f| *f == fault), None);
+    }
+}
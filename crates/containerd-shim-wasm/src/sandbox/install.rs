@@ -0,0 +1,433 @@
+//! The `install` and `uninstall` commands of the shims, which provision a node for a runtime.
+//!
+//! `install` copies the shim binary to the directory containerd finds its shims in, adds the
+//! runtime handler to the configuration of containerd, and optionally applies the RuntimeClass
+//! of the handler with `kubectl`. `uninstall` reverts them. containerd has to be restarted to
+//! load its new configuration.
+//!
+//! ```bash
+//! sudo containerd-shim-wasmtime-v1 install -handler wasm -runtime-class
+//! sudo systemctl restart containerd
+//! ```
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, ensure, Context, Result};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+const DEFAULT_BIN_DIR: &str = "/usr/local/bin";
+const DEFAULT_CONTAINERD_CONFIG: &str = "/etc/containerd/config.toml";
+
+/// What the `install` and `uninstall` commands provision
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InstallOptions {
+    /// The directory the shim binary is copied to, `-bin-dir`
+    pub bin_dir: PathBuf,
+    /// The configuration of containerd, `-config`
+    pub config: PathBuf,
+    /// The name of the runtime handler, and of its RuntimeClass, `-handler`
+    pub handler: String,
+    /// Whether the RuntimeClass is applied with `kubectl`, `-runtime-class`
+    pub runtime_class: bool,
+}
+
+impl InstallOptions {
+    /// Parses the flags following the `install` or `uninstall` `action` in `args`, the handler
+    /// defaulting to the name of the runtime.
+    pub fn parse(action: &str, args: &[OsString], runtime: &str) -> Result<Self> {
+        let mut options = Self {
+            bin_dir: PathBuf::from(DEFAULT_BIN_DIR),
+            config: PathBuf::from(DEFAULT_CONTAINERD_CONFIG),
+            handler: runtime.to_string(),
+            runtime_class: false,
+        };
+        let mut args = args
+            .iter()
+            .skip_while(|arg| *arg != action)
+            .skip(1)
+            .map(|arg| arg.to_string_lossy());
+        while let Some(arg) = args.next() {
+            let flag = arg.trim_start_matches('-');
+            let (flag, inline) = match flag.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let mut next_value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next().map(|arg| arg.to_string()))
+                    .with_context(|| format!("missing value of -{flag}"))
+            };
+            match flag {
+                "bin-dir" => options.bin_dir = next_value()?.into(),
+                "config" => options.config = next_value()?.into(),
+                "handler" => options.handler = next_value()?,
+                "runtime-class" => options.runtime_class = true,
+                _ => bail!("unknown flag {arg:?}, usage: {action} [-bin-dir <dir>] [-config <path>] [-handler <name>] [-runtime-class]"),
+            }
+        }
+        ensure!(
+            is_dns_label(&options.handler),
+            "invalid handler {:?}, expected a lowercase DNS label",
+            options.handler
+        );
+        Ok(options)
+    }
+}
+
+// RuntimeClasses are named after their handler, which has to be a DNS label
+fn is_dns_label(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 63
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Installs the shim `binary`, e.g., `containerd-shim-wasmtime-v1`, with the runtime type
+/// containerd starts it for, e.g., `io.containerd.wasmtime.v1`.
+pub(crate) fn install(binary: &str, runtime_type: &str, options: &InstallOptions) -> Result<()> {
+    let exe = std::env::current_exe().context("failed to find the shim binary")?;
+    let target = options.bin_dir.join(binary);
+    if fs::canonicalize(&exe).ok() != fs::canonicalize(&target).ok() {
+        copy_binary(&exe, &target)?;
+        println!("installed {}", target.display());
+    }
+
+    let mut config = read_config(&options.config)?;
+    add_runtime(&mut config, &options.handler, runtime_type)?;
+    write_config(&options.config, &config)?;
+    println!(
+        "added the {} runtime handler to {}, restart containerd to load it",
+        options.handler,
+        options.config.display()
+    );
+
+    if options.runtime_class {
+        kubectl(
+            &["apply", "-f", "-"],
+            Some(&runtime_class(&options.handler)),
+        )?;
+        println!("applied the {} RuntimeClass", options.handler);
+    }
+    Ok(())
+}
+
+/// Uninstalls the shim `binary`, its runtime handler and its RuntimeClass.
+pub(crate) fn uninstall(binary: &str, options: &InstallOptions) -> Result<()> {
+    if options.runtime_class {
+        let name = format!("runtimeclass/{}", options.handler);
+        kubectl(&["delete", &name, "--ignore-not-found"], None)?;
+        println!("deleted the {} RuntimeClass", options.handler);
+    }
+
+    if options.config.exists() {
+        let mut config = read_config(&options.config)?;
+        if remove_runtime(&mut config, &options.handler)? {
+            write_config(&options.config, &config)?;
+            println!(
+                "removed the {} runtime handler from {}, restart containerd to unload it",
+                options.handler,
+                options.config.display()
+            );
+        }
+    }
+
+    let target = options.bin_dir.join(binary);
+    match fs::remove_file(&target) {
+        Ok(()) => println!("removed {}", target.display()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("failed to remove {target:?}")),
+    }
+    Ok(())
+}
+
+// Copies the binary next to the target first, so that a running shim is never overwritten with a
+// partial binary
+fn copy_binary(exe: &Path, target: &Path) -> Result<()> {
+    let dir = target.parent().context("invalid binary path")?;
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {dir:?}"))?;
+    let tmp = dir.join(format!(".{}.tmp", std::process::id()));
+    fs::copy(exe, &tmp).with_context(|| format!("failed to copy the shim to {dir:?}"))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+    }
+    fs::rename(&tmp, target).with_context(|| format!("failed to install {target:?}"))
+}
+
+// Reads the configuration of containerd, an empty one of the current version if there is none
+fn read_config(path: &Path) -> Result<DocumentMut> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .parse()
+            .with_context(|| format!("invalid configuration {path:?}")),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok("version = 2\n".parse()?),
+        Err(err) => Err(err).with_context(|| format!("failed to read {path:?}")),
+    }
+}
+
+// Writes the configuration next to it first, synced, and renames it over the configuration, so
+// that containerd never reads a partial configuration
+fn write_config(path: &Path, config: &DocumentMut) -> Result<()> {
+    // the target of a symlink is replaced, rather than the symlink
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = path.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {dir:?}"))?;
+    let tmp = dir.join(format!(".config.{}.tmp", std::process::id()));
+    let written = fs::File::create(&tmp).and_then(|mut file| {
+        file.write_all(config.to_string().as_bytes())?;
+        // the configuration keeps its permissions
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()
+    });
+    if let Err(err) = written.and_then(|_| fs::rename(&tmp, &path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(err).with_context(|| format!("failed to write {path:?}"));
+    }
+    Ok(())
+}
+
+// The keys of the table of the runtime handlers of the CRI plugin, for the version of the
+// configuration
+fn runtimes_path(config: &DocumentMut) -> Result<&'static [&'static str]> {
+    match config
+        .get("version")
+        .and_then(Item::as_integer)
+        .unwrap_or(1)
+    {
+        1 => Ok(&["plugins", "cri", "containerd", "runtimes"]),
+        2 => Ok(&[
+            "plugins",
+            "io.containerd.grpc.v1.cri",
+            "containerd",
+            "runtimes",
+        ]),
+        3 => Ok(&[
+            "plugins",
+            "io.containerd.cri.v1.runtime",
+            "containerd",
+            "runtimes",
+        ]),
+        version => bail!("unsupported containerd configuration version {version}"),
+    }
+}
+
+// Adds the runtime handler, or updates its runtime type, keeping its other settings, e.g., its
+// options
+fn add_runtime(config: &mut DocumentMut, handler: &str, runtime_type: &str) -> Result<()> {
+    let path = runtimes_path(config)?;
+    let mut item = config.as_item_mut();
+    for key in path {
+        item = table_mut(item, key)?;
+    }
+    let runtime = table_mut(item, handler)?;
+    if let Some(runtime) = runtime.as_table_mut() {
+        runtime.set_implicit(false);
+    }
+    runtime["runtime_type"] = value(runtime_type);
+    Ok(())
+}
+
+// Removes the runtime handler, returns whether the configuration had it
+fn remove_runtime(config: &mut DocumentMut, handler: &str) -> Result<bool> {
+    let path = runtimes_path(config)?;
+    let mut item = config.as_item_mut();
+    for key in path {
+        match item.get_mut(key) {
+            Some(next) => item = next,
+            None => return Ok(false),
+        }
+    }
+    Ok(item
+        .as_table_like_mut()
+        .and_then(|runtimes| runtimes.remove(handler))
+        .is_some())
+}
+
+// Returns the table of `key`, created as an implicit table when missing, so that only the
+// tables with settings are written, e.g., `[plugins."io.containerd.grpc.v1.cri".containerd.runtimes.wasm]`
+fn table_mut<'a>(item: &'a mut Item, key: &str) -> Result<&'a mut Item> {
+    let parent = item
+        .as_table_like_mut()
+        .with_context(|| format!("{key:?} is not in a table"))?;
+    if parent.get(key).is_none() {
+        let mut implicit = Table::new();
+        implicit.set_implicit(true);
+        parent.insert(key, Item::Table(implicit));
+    }
+    let entry = parent.get_mut(key).expect("the table was inserted");
+    ensure!(entry.is_table_like(), "{key:?} is not a table");
+    Ok(entry)
+}
+
+fn runtime_class(handler: &str) -> String {
+    format!(
+        "apiVersion: node.k8s.io/v1\nkind: RuntimeClass\nmetadata:\n  name: {handler}\nhandler: {handler}\n"
+    )
+}
+
+fn kubectl(args: &[&str], stdin: Option<&str>) -> Result<()> {
+    let mut child = Command::new("kubectl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("failed to run kubectl")?;
+    let mut input = child.stdin.take().expect("stdin is piped");
+    if let Some(stdin) = stdin {
+        input.write_all(stdin.as_bytes())?;
+    }
+    drop(input);
+    let status = child.wait()?;
+    ensure!(
+        status.success(),
+        "kubectl {} failed: {status}",
+        args.join(" ")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse_options() -> Result<()> {
+        let options = InstallOptions::parse("install", &args(&["install"]), "wasmtime")?;
+        assert_eq!(options.handler, "wasmtime");
+        assert_eq!(options.config, Path::new(DEFAULT_CONTAINERD_CONFIG));
+        assert!(!options.runtime_class);
+
+        let options = InstallOptions::parse(
+            "install",
+            &args(&[
+                "-namespace",
+                "k8s.io",
+                "install",
+                "-handler",
+                "wasm",
+                "--bin-dir=/opt/bin",
+                "-runtime-class",
+            ]),
+            "wasmtime",
+        )?;
+        assert_eq!(options.handler, "wasm");
+        assert_eq!(options.bin_dir, Path::new("/opt/bin"));
+        assert!(options.runtime_class);
+
+        assert!(
+            InstallOptions::parse("install", &args(&["install", "-handler"]), "wasmtime").is_err()
+        );
+        assert!(
+            InstallOptions::parse("install", &args(&["install", "-force"]), "wasmtime").is_err()
+        );
+        assert!(InstallOptions::parse(
+            "install",
+            &args(&["install", "-handler", "Wasm"]),
+            "wasmtime"
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_runtime() -> Result<()> {
+        let mut config: DocumentMut = r#"version = 2
+
+# the runtimes of the node
+[plugins."io.containerd.grpc.v1.cri".containerd.runtimes.runc]
+  runtime_type = "io.containerd.runc.v2"
+"#
+        .parse()?;
+        add_runtime(&mut config, "wasm", "io.containerd.wasmtime.v1")?;
+        let content = config.to_string();
+        assert!(content.contains("# the runtimes of the node"));
+        assert!(content.contains(
+            "[plugins.\"io.containerd.grpc.v1.cri\".containerd.runtimes.wasm]\nruntime_type = \"io.containerd.wasmtime.v1\"\n"
+        ));
+
+        // installing again updates the runtime type, and keeps the options of the runtime
+        let mut config: DocumentMut = format!(
+            "{content}[plugins.\"io.containerd.grpc.v1.cri\".containerd.runtimes.wasm.options]\nConfigPath = \"/etc/runwasi/wasmtime.toml\"\n"
+        )
+        .parse()?;
+        add_runtime(&mut config, "wasm", "io.containerd.wasmtime.v2")?;
+        let content = config.to_string();
+        assert_eq!(content.matches("runtimes.wasm]").count(), 1);
+        assert!(content.contains("runtime_type = \"io.containerd.wasmtime.v2\""));
+        assert!(content.contains("ConfigPath"));
+
+        assert!(remove_runtime(&mut config, "wasm")?);
+        assert!(!remove_runtime(&mut config, "wasm")?);
+        assert!(!config.to_string().contains("runtimes.wasm"));
+        assert!(config.to_string().contains("runtimes.runc"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_versions() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+
+        // a missing configuration is created
+        let mut config = read_config(&path)?;
+        add_runtime(&mut config, "wasm", "io.containerd.wasmtime.v1")?;
+        write_config(&path, &config)?;
+        let table: toml::Table = toml::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!(
+            table["plugins"]["io.containerd.grpc.v1.cri"]["containerd"]["runtimes"]["wasm"]
+                ["runtime_type"]
+                .as_str(),
+            Some("io.containerd.wasmtime.v1")
+        );
+
+        let mut config: DocumentMut = "version = 3\n".parse()?;
+        add_runtime(&mut config, "wasm", "io.containerd.wasmtime.v1")?;
+        assert!(config
+            .to_string()
+            .contains("[plugins.\"io.containerd.cri.v1.runtime\".containerd.runtimes.wasm]"));
+
+        let mut config: DocumentMut = "version = 4\n".parse()?;
+        assert!(add_runtime(&mut config, "wasm", "io.containerd.wasmtime.v1").is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_config() -> Result<()> {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "version = 2\n")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        let link = dir.path().join("link.toml");
+        symlink(&path, &link)?;
+
+        let mut config = read_config(&link)?;
+        add_runtime(&mut config, "wasm", "io.containerd.wasmtime.v1")?;
+        write_config(&link, &config)?;
+
+        // the target of the symlink is replaced with its permissions, without a temporary file left
+        assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+        assert!(fs::read_to_string(&path)?.contains("runtimes.wasm"));
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+        Ok(())
+    }
+}
@@ -1,14 +1,53 @@
 //! Abstractions for running/managing a wasm/wasi instance.
 
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
+use containerd_shim::Error as ShimError;
+use oci_spec::runtime::Process;
 
 use super::error::Error;
 use super::sync::WaitableCell;
+use crate::container::{CapabilityReport, GuestFailure, PoolMetrics};
 use crate::sys::signals::*;
 
+/// Progress of the precompilation of the wasm layers of an instance.
+#[derive(Clone, Debug)]
+pub enum CompileEvent {
+    /// The runtime started precompiling the layers.
+    Started { runtime: String },
+    /// The runtime finished precompiling the layers.
+    /// `error` is set if the compilation failed, in which case the original layers are used.
+    Finished {
+        runtime: String,
+        duration: Duration,
+        error: Option<String>,
+    },
+}
+
+/// Callback notified of the precompilation progress of an instance.
+pub type CompileObserver = Arc<dyn Fn(CompileEvent) + Send + Sync>;
+
+/// Callback notified of the capabilities the module of an instance needs, when it is created.
+pub type CapabilityObserver = Arc<dyn Fn(&CapabilityReport) + Send + Sync>;
+
+/// The module of an instance was reloaded from an update of its image, with the
+/// `runwasi.io/hot-reload` annotation.
+#[derive(Clone, Debug)]
+pub struct ReloadEvent {
+    /// The image of the instance
+    pub image: String,
+    /// The digest the image was updated to
+    pub digest: String,
+    /// The pid of the process running the new module
+    pub pid: u32,
+}
+
+/// Callback notified when the module of an instance is reloaded.
+pub type ReloadObserver = Arc<dyn Fn(&ReloadEvent) + Send + Sync>;
+
 /// Generic options builder for creating a wasm instance.
 /// This is passed to the `Instance::new` method.
 #[derive(Clone)]
@@ -22,12 +61,20 @@ pub struct InstanceConfig<Engine: Send + Sync + Clone> {
     stdout: PathBuf,
     /// Optional stderr named pipe path.
     stderr: PathBuf,
+    /// Whether the instance runs with a terminal attached to its stdio
+    terminal: bool,
     /// Path to the OCI bundle directory.
     bundle: PathBuf,
     /// Namespace for containerd
     namespace: String,
     // /// GRPC address back to main containerd
     containerd_address: String,
+    /// Optional observer of the precompilation of the wasm layers
+    compile_observer: Option<CompileObserver>,
+    /// Optional observer of the capabilities the module needs
+    capability_observer: Option<CapabilityObserver>,
+    /// Optional observer of the reloads of the module
+    reload_observer: Option<ReloadObserver>,
 }
 
 impl<Engine: Send + Sync + Clone> InstanceConfig<Engine> {
@@ -45,7 +92,11 @@ impl<Engine: Send + Sync + Clone> InstanceConfig<Engine> {
             stdin: PathBuf::default(),
             stdout: PathBuf::default(),
             stderr: PathBuf::default(),
+            terminal: false,
             bundle: PathBuf::default(),
+            compile_observer: None,
+            capability_observer: None,
+            reload_observer: None,
         }
     }
 
@@ -107,6 +158,60 @@ impl<Engine: Send + Sync + Clone> InstanceConfig<Engine> {
     pub fn get_containerd_address(&self) -> String {
         self.containerd_address.clone()
     }
+
+    /// set whether the instance runs with a terminal
+    /// With a terminal, stdin is copied to the terminal and its output is copied to stdout.
+    pub fn set_terminal(&mut self, terminal: bool) -> &mut Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// get whether the instance runs with a terminal
+    pub fn get_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// set the observer notified when the wasm layers of the instance are precompiled
+    pub fn set_compile_observer(
+        &mut self,
+        observer: impl Fn(CompileEvent) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.compile_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// get the observer notified when the wasm layers of the instance are precompiled
+    pub fn get_compile_observer(&self) -> Option<CompileObserver> {
+        self.compile_observer.clone()
+    }
+
+    /// set the observer notified of the capabilities the module of the instance needs
+    pub fn set_capability_observer(
+        &mut self,
+        observer: impl Fn(&CapabilityReport) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.capability_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// get the observer notified of the capabilities the module of the instance needs
+    pub fn get_capability_observer(&self) -> Option<CapabilityObserver> {
+        self.capability_observer.clone()
+    }
+
+    /// set the observer notified when the module of the instance is reloaded
+    pub fn set_reload_observer(
+        &mut self,
+        observer: impl Fn(&ReloadEvent) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.reload_observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// get the observer notified when the module of the instance is reloaded
+    pub fn get_reload_observer(&self) -> Option<ReloadObserver> {
+        self.reload_observer.clone()
+    }
 }
 
 /// Represents a WASI module(s).
@@ -134,6 +239,151 @@ pub trait Instance: 'static {
     /// This is called after the instance has exited.
     fn delete(&self) -> Result<(), Error>;
 
+    /// Create an additional process that runs inside this instance
+    /// `cfg` holds the stdio named pipes of the new process and `process` describes what to run.
+    /// The returned instance represents the new process and follows the same start/kill/wait/delete lifecycle.
+    /// The default implementation returns an `Unimplemented` error.
+    fn exec(
+        &self,
+        _exec_id: String,
+        _process: &Process,
+        _cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        Err(ShimError::Unimplemented("exec is not supported".to_string()).into())
+    }
+
+    /// Re-attach to the running process `pid` of the instance `id`, started by a previous shim,
+    /// e.g., after the shim was restarted.
+    /// The returned instance is running, and follows the same kill/wait/delete lifecycle.
+    /// The default implementation returns an `Unimplemented` error.
+    fn recover(_id: String, _pid: u32, _cfg: &InstanceConfig<Self::Engine>) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        Err(ShimError::Unimplemented("recovering instances is not supported".to_string()).into())
+    }
+
+    /// Pause the running instance
+    /// The default implementation returns an `Unimplemented` error.
+    fn pause(&self) -> Result<(), Error> {
+        Err(ShimError::Unimplemented("pause is not supported".to_string()).into())
+    }
+
+    /// Resume the paused instance
+    /// The default implementation returns an `Unimplemented` error.
+    fn resume(&self) -> Result<(), Error> {
+        Err(ShimError::Unimplemented("resume is not supported".to_string()).into())
+    }
+
+    /// Checkpoint the running instance into the `path` directory
+    /// The instance keeps running after the checkpoint is taken.
+    /// The default implementation returns an `Unimplemented` error.
+    fn checkpoint(&self, _path: &Path) -> Result<(), Error> {
+        Err(ShimError::Unimplemented("checkpoint is not supported".to_string()).into())
+    }
+
+    /// Restore the instance `id` from the checkpoint in the `checkpoint` directory, taken with
+    /// [`Instance::checkpoint`].
+    /// The returned instance is running, with the returned pid, and follows the same
+    /// kill/wait/delete lifecycle.
+    /// The default implementation returns an `Unimplemented` error.
+    fn restore(
+        _id: String,
+        _checkpoint: &Path,
+        _cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<(Self, u32), Error>
+    where
+        Self: Sized,
+    {
+        Err(
+            ShimError::Unimplemented("restoring from a checkpoint is not supported".to_string())
+                .into(),
+        )
+    }
+
+    /// Resize the terminal of the instance, in characters
+    /// The default implementation returns an `Unimplemented` error.
+    fn resize_pty(&self, _width: u32, _height: u32) -> Result<(), Error> {
+        Err(ShimError::Unimplemented("terminal is not supported".to_string()).into())
+    }
+
+    /// Close the stdin of the instance, so it reads an EOF once it has consumed its input
+    /// The default implementation returns an `Unimplemented` error.
+    fn close_stdin(&self) -> Result<(), Error> {
+        Err(ShimError::Unimplemented("closing stdin is not supported".to_string()).into())
+    }
+
+    /// Run the container in the current process, if the shim binary was started to host a container.
+    /// This is called by `shim_main` before anything else, and lets instances that can't fork the
+    /// shim, e.g., on Windows, run their containers in a child process spawned from the shim binary.
+    /// Returns the exit code of the container, or `None` if the process is not hosting a container.
+    /// The default implementation returns `None`.
+    fn run_container_process(_engine: impl FnOnce() -> Self::Engine) -> Option<i32>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Precompile the wasm layers of `image` in the content store of containerd ahead of time,
+    /// with the engine and the containerd namespace and address of `cfg`.
+    /// This is called by the `precompile` command of the shim.
+    /// Returns the digests of the original and precompiled layers.
+    /// The default implementation returns an `Unimplemented` error.
+    fn precompile(
+        _image: &str,
+        _cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<Vec<(String, String)>, Error>
+    where
+        Self: Sized,
+    {
+        Err(ShimError::Unimplemented("precompiling images is not supported".to_string()).into())
+    }
+
+    /// Pull `image` into the content store of containerd with only the content the engine runs,
+    /// with the containerd namespace and address of `cfg`.
+    /// This is called by the `pull` command of the shim.
+    /// Returns the digests of the pulled content.
+    /// The default implementation returns an `Unimplemented` error.
+    fn pull(_image: &str, _cfg: &InstanceConfig<Self::Engine>) -> Result<Vec<String>, Error>
+    where
+        Self: Sized,
+    {
+        Err(ShimError::Unimplemented("pulling images is not supported".to_string()).into())
+    }
+
+    /// Drop the precompiled layers of the images removed from the content store of containerd,
+    /// with the containerd namespace and address of `cfg`.
+    /// This is called by the `gc` command of the shim.
+    /// Returns the dropped leases and cached artifacts.
+    /// The default implementation returns an `Unimplemented` error.
+    fn gc(_cfg: &InstanceConfig<Self::Engine>) -> Result<Vec<String>, Error>
+    where
+        Self: Sized,
+    {
+        Err(
+            ShimError::Unimplemented("collecting precompiled layers is not supported".to_string())
+                .into(),
+        )
+    }
+
+    /// Returns the failure of the guest reported by the engine, once the instance has exited
+    /// The default implementation returns `None`.
+    fn take_failure(&self) -> Option<GuestFailure> {
+        None
+    }
+
+    /// Returns the metrics of the pool of instances the engine serves the requests of the
+    /// container with, if it has one, and of the wasm instances of the container, if the engine
+    /// counts them.
+    /// The default implementation returns `None`.
+    fn pool_metrics(&self) -> Option<PoolMetrics> {
+        None
+    }
+
     /// Waits for the instance to finish and retunrs its exit code
     /// This is a blocking call.
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), parent = tracing::Span::current(), level = "Info"))]
@@ -154,6 +404,16 @@ pub struct Nop {
     exit_code: WaitableCell<(u32, DateTime<Utc>)>,
 }
 
+impl Nop {
+    /// Returns an instance that already exited with `exit`, e.g., a task that exited while its
+    /// shim was down.
+    pub(crate) fn exited(exit: (u32, DateTime<Utc>)) -> Self {
+        let exit_code = WaitableCell::new();
+        let _ = exit_code.set(exit);
+        Nop { exit_code }
+    }
+}
+
 impl Instance for Nop {
     type Engine = ();
     fn new(_id: String, _cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self, Error> {
@@ -180,6 +440,12 @@ impl Instance for Nop {
     fn delete(&self) -> Result<(), Error> {
         Ok(())
     }
+    fn pause(&self) -> Result<(), Error> {
+        Ok(())
+    }
+    fn resume(&self) -> Result<(), Error> {
+        Ok(())
+    }
     fn wait_timeout(&self, t: impl Into<Option<Duration>>) -> Option<(u32, DateTime<Utc>)> {
         self.exit_code.wait_timeout(t).copied()
     }
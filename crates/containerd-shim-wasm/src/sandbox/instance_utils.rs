@@ -1,12 +1,22 @@
 //! Common utilities for the containerd shims.
-use std::fs::File;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::io::ErrorKind;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
+use protobuf::well_known_types::any::Any;
+use protobuf::Message;
 use serde::{Deserialize, Serialize};
 
+use super::stdio::LogDriverKind;
 use super::Error;
+use crate::services::runtimeoptions::Options as RuntimeOptions;
+
+/// The type of the options containerd passes to the runtimes other than runc, with the config of
+/// the runtime
+const RUNTIME_OPTIONS_TYPE_URL: &str = "runtimeoptions.v1.Options";
 
 /// Return the root path for the instance.
 ///
@@ -32,9 +42,98 @@ pub fn instance_exists<P: AsRef<Path>>(root_path: P, container_id: &str) -> Resu
     Ok(instance_root.exists())
 }
 
-#[derive(Serialize, Deserialize)]
-struct Options {
-    root: Option<PathBuf>,
+/// The runtime options of a container, from the `options.json` of its bundle.
+///
+/// The shim writes them to the bundle when containerd passes the `options` of the runtime in its
+/// configuration, see [`Options::from_runtime_options`].
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Options {
+    pub root: Option<PathBuf>,
+    /// Where the stdout and stderr of the container go
+    #[serde(default)]
+    pub log_driver: LogDriverKind,
+    /// The file of the `file` log driver
+    pub log_path: Option<PathBuf>,
+    /// The size in bytes at which the log files are rotated
+    pub log_max_size: Option<u64>,
+    /// The number of log files kept, including the current one, 1 by default
+    pub log_max_files: Option<u32>,
+    /// The size in bytes of the last output of the container the shim keeps to replay to the
+    /// readers attaching to its fifos, 64 KiB by default, or 0 to write to the fifos directly
+    pub log_buffer_size: Option<usize>,
+    /// Whether the engine is confined with Landlock to the files of the container, on Linux
+    #[serde(default)]
+    pub landlock: bool,
+    /// Additional paths of the container the engine can read and write when confined with
+    /// Landlock, e.g., a cache directory
+    #[serde(default)]
+    pub landlock_paths: Vec<PathBuf>,
+    /// The number of threads the engine runs the guests with, e.g., the workers of its async
+    /// runtime, by default one per CPU the container can run on
+    pub threads: Option<NonZeroUsize>,
+    /// The key-value store of the `wasi:keyvalue` interfaces of the engines providing them, e.g.,
+    /// `memory`, `file:<directory>` or `redis://<host>`, shared by the containers of the runtime
+    /// in buckets scoped to each container
+    pub keyvalue: Option<String>,
+    /// The directory of the node-level precompilation cache, rather than the one of
+    /// `RUNWASI_PRECOMPILE_CACHE_DIR`
+    pub precompile_cache_dir: Option<PathBuf>,
+    /// The capability policy, rather than the one of `RUNWASI_CAPABILITY_POLICY`
+    pub capability_policy: Option<PathBuf>,
+    /// The signature policy, rather than the one of `RUNWASI_SIGNATURE_POLICY`
+    pub signature_policy: Option<PathBuf>,
+    /// The patterns of the environment variables removed from the environment of the guests, next
+    /// to the ones of the `io.containerd.wasm.env-strip` annotation, e.g., `["KUBERNETES_*"]`
+    #[serde(default)]
+    pub env_strip: Vec<String>,
+    /// The default annotations of the containers, e.g., to enable a feature of the engine, which
+    /// the annotations of a container override
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+impl Options {
+    /// Reads the runtime options of the container, the defaults when the bundle has none.
+    pub(crate) fn read(bundle: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = match File::open(bundle.as_ref().join("options.json")) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Writes the runtime options to the bundle of the container.
+    pub(crate) fn write(&self, bundle: impl AsRef<Path>) -> Result<(), Error> {
+        let file = File::create(bundle.as_ref().join("options.json"))?;
+        Ok(serde_json::to_writer(file, self)?)
+    }
+
+    /// Parses the `options` containerd passes to a task, from the `options` of the runtime in its
+    /// configuration, i.e., a `runtimeoptions.v1.Options` with the path of a TOML config, in
+    /// `ConfigPath`, or the config, in `ConfigBody`.
+    /// Returns `None` for the options of other types, e.g., the options of runc.
+    pub(crate) fn from_runtime_options(options: &Any) -> Result<Option<Self>, Error> {
+        // the type url may be prefixed, e.g., with `type.googleapis.com/`
+        let type_url = options.type_url.rsplit('/').next().unwrap_or_default();
+        if type_url != RUNTIME_OPTIONS_TYPE_URL {
+            log::warn!("ignoring runtime options of type {:?}", options.type_url);
+            return Ok(None);
+        }
+        let options = RuntimeOptions::parse_from_bytes(&options.value)
+            .map_err(|err| Error::InvalidArgument(format!("invalid runtime options: {err}")))?;
+        let config = match options.config_path.as_str() {
+            "" => options.config_body,
+            path => fs::read(path).map_err(|err| {
+                Error::FailedPrecondition(format!("failed to read runtime config {path:?}: {err}"))
+            })?,
+        };
+        let config = String::from_utf8(config)
+            .map_err(|err| Error::InvalidArgument(format!("invalid runtime config: {err}")))?;
+        toml::from_str(&config)
+            .map(Some)
+            .map_err(|err| Error::InvalidArgument(format!("invalid runtime config: {err}")))
+    }
 }
 
 #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
@@ -43,15 +142,10 @@ pub fn determine_rootdir(
     namespace: &str,
     rootdir: impl AsRef<Path>,
 ) -> Result<PathBuf, Error> {
-    let file = match File::open(bundle.as_ref().join("options.json")) {
-        Ok(f) => f,
-        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(rootdir.as_ref().join(namespace)),
-        Err(e) => return Err(e.into()),
+    let Some(root) = Options::read(bundle)?.root else {
+        return Ok(rootdir.as_ref().join(namespace));
     };
-    let path = serde_json::from_reader::<_, Options>(file)?
-        .root
-        .unwrap_or_else(|| rootdir.as_ref().to_owned())
-        .join(namespace);
+    let path = root.join(namespace);
     log::info!("container runtime root path is {path:?}");
     Ok(path)
 }
@@ -82,6 +176,7 @@ mod tests {
         let rootdir = dir.path().join("runwasi");
         let opts = Options {
             root: Some(rootdir.clone()),
+            ..Default::default()
         };
         std::fs::write(
             dir.path().join("options.json"),
@@ -104,6 +199,62 @@ mod tests {
         );
         Ok(())
     }
+
+    fn runtime_options(type_url: &str, options: RuntimeOptions) -> Any {
+        let mut any = Any::new();
+        any.type_url = type_url.to_string();
+        any.value = options.write_to_bytes().unwrap();
+        any
+    }
+
+    #[test]
+    fn test_options_from_runtime_options() -> Result<(), Error> {
+        let config = r#"
+            threads = 2
+            keyvalue = "redis://redis.default.svc"
+            precompile_cache_dir = "/var/cache/runwasi"
+
+            [annotations]
+            "runwasi.io/wasi-threads" = "true"
+        "#;
+        let mut options = RuntimeOptions::new();
+        options.config_body = config.as_bytes().to_vec();
+        let options = runtime_options(RUNTIME_OPTIONS_TYPE_URL, options);
+        let options = Options::from_runtime_options(&options)?.expect("missing options");
+        assert_eq!(options.threads, NonZeroUsize::new(2));
+        assert_eq!(
+            options.keyvalue.as_deref(),
+            Some("redis://redis.default.svc")
+        );
+        assert_eq!(
+            options.precompile_cache_dir,
+            Some(PathBuf::from("/var/cache/runwasi"))
+        );
+        assert_eq!(options.annotations["runwasi.io/wasi-threads"], "true");
+        assert!(!options.landlock);
+
+        // the config file takes precedence over the config body
+        let dir = tempdir()?;
+        let config_path = dir.path().join("runwasi.toml");
+        std::fs::write(&config_path, "landlock = true")?;
+        let mut options = RuntimeOptions::new();
+        options.config_path = config_path.to_string_lossy().to_string();
+        options.config_body = config.as_bytes().to_vec();
+        let options = runtime_options("type.googleapis.com/runtimeoptions.v1.Options", options);
+        let options = Options::from_runtime_options(&options)?.expect("missing options");
+        assert!(options.landlock);
+        assert_eq!(options.threads, None);
+
+        // the options of runc are ignored
+        let options = runtime_options("containerd.runc.v1.Options", RuntimeOptions::new());
+        assert!(Options::from_runtime_options(&options)?.is_none());
+
+        let mut options = RuntimeOptions::new();
+        options.config_body = b"threads = 0".to_vec();
+        let options = runtime_options(RUNTIME_OPTIONS_TYPE_URL, options);
+        assert!(Options::from_runtime_options(&options).is_err());
+        Ok(())
+    }
 }
 
 #[cfg(test)]
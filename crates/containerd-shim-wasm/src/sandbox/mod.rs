@@ -2,21 +2,30 @@
 
 use crate::services::sandbox;
 
+#[cfg(unix)]
+pub(crate) mod capability_policy;
 pub mod cli;
 pub mod error;
+#[cfg(any(test, feature = "fault-injection"))]
+pub mod fault;
 pub mod instance;
 pub mod instance_utils;
 pub mod manager;
+pub mod node_config;
 pub mod shim;
 pub mod stdio;
 pub mod sync;
 
 pub use error::{Error, Result};
-pub use instance::{Instance, InstanceConfig};
+pub use instance::{CompileEvent, Instance, InstanceConfig, ReloadEvent};
 pub use manager::{Sandbox as SandboxService, Service as ManagerService};
 pub use shim::Cli as ShimCli;
 pub use stdio::Stdio;
 
 pub(crate) mod containerd;
+pub(crate) mod install;
 pub(crate) mod oci;
-pub use oci::WasmLayer;
+pub use oci::{LayerContent, WasmLayer};
+
+#[cfg(unix)]
+pub use crate::sys::metrics::CGROUP_PATH_FIELD;
@@ -0,0 +1,192 @@
+//! Per-node configuration of the shims, shared by all the shims and engines of a node.
+//!
+//! The configuration file, `/etc/runwasi/config.toml` or the file of [`CONFIG_FILE_ENV`], sets
+//! the defaults of the environment variables of the shim, e.g., the OpenTelemetry exporter, the
+//! caches, the limits of the engine or the log format:
+//!
+//! ```toml
+//! log_format = "json"                  # RUNWASI_LOG_FORMAT
+//!
+//! [otel]
+//! exporter_otlp_endpoint = "http://localhost:4318"    # OTEL_EXPORTER_OTLP_ENDPOINT
+//!
+//! [cache]
+//! precompile_cache_dir = "/var/cache/runwasi"         # RUNWASI_PRECOMPILE_CACHE_DIR
+//!
+//! [limits]
+//! wasmtime_pooling_max_instances = 100                # RUNWASI_WASMTIME_POOLING_MAX_INSTANCES
+//! ```
+//!
+//! A setting is the variable named after its key in upper case, prefixed with `OTEL_` in the
+//! `otel` section, and with `RUNWASI_` elsewhere. The other sections only group the settings.
+//!
+//! The variables of the environment of the shim take precedence over the file, and the
+//! annotations and runtime options of a container over both.
+//! The file is loaded when the shim starts, and loaded again when the shim receives a SIGHUP.
+//! The settings read when a task is created, e.g., the caches and the policies, apply to the
+//! tasks created after the reload, and the others, e.g., the OpenTelemetry exporter and the
+//! log format, to the shims started after it.
+
+use std::collections::HashMap;
+use std::env::VarError;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+
+use anyhow::{bail, Context, Result};
+
+/// Environment variable with the path of the configuration file of the node
+pub const CONFIG_FILE_ENV: &str = "RUNWASI_CONFIG";
+
+/// The configuration file of the node, when [`CONFIG_FILE_ENV`] isn't set
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/runwasi/config.toml";
+
+fn vars() -> &'static RwLock<HashMap<String, String>> {
+    static VARS: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+    VARS.get_or_init(|| {
+        let vars = load().unwrap_or_else(|err| {
+            log::error!("ignoring the configuration of the node: {err:#}");
+            HashMap::new()
+        });
+        RwLock::new(vars)
+    })
+}
+
+fn config_file() -> PathBuf {
+    std::env::var_os(CONFIG_FILE_ENV)
+        .filter(|path| !path.is_empty())
+        .map_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE), PathBuf::from)
+}
+
+// Loads the variables set by the configuration file, none if there is no file
+fn load() -> Result<HashMap<String, String>> {
+    let path = config_file();
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {path:?}")),
+    };
+    parse(&content).with_context(|| format!("invalid configuration {path:?}"))
+}
+
+fn parse(content: &str) -> Result<HashMap<String, String>> {
+    let table: toml::Table = toml::from_str(content)?;
+    let mut vars = HashMap::new();
+    for (key, value) in table {
+        let toml::Value::Table(section) = value else {
+            vars.insert(var_name("", &key), setting(&key, value)?);
+            continue;
+        };
+        for (name, value) in section {
+            vars.insert(var_name(&key, &name), setting(&name, value)?);
+        }
+    }
+    Ok(vars)
+}
+
+fn var_name(section: &str, key: &str) -> String {
+    let prefix = match section {
+        "otel" => "OTEL_",
+        _ => "RUNWASI_",
+    };
+    format!("{prefix}{}", key.to_uppercase())
+}
+
+fn setting(key: &str, value: toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        value => bail!("invalid value {value} for {key}, expected a string, a number or a boolean"),
+    }
+}
+
+/// Loads the configuration file again, keeping the current configuration if it is invalid.
+pub fn reload() {
+    match load() {
+        Ok(config) => {
+            log::info!(
+                "loaded the configuration of the node from {:?}",
+                config_file()
+            );
+            *vars().write().unwrap() = config;
+        }
+        Err(err) => log::error!("keeping the current configuration of the node: {err:#}"),
+    }
+}
+
+/// Returns the variable `key` of the environment of the shim, or the one set by the
+/// configuration file of the node, like [`std::env::var`].
+pub fn var(key: &str) -> Result<String, VarError> {
+    match std::env::var(key) {
+        Err(VarError::NotPresent) => vars()
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or(VarError::NotPresent),
+        res => res,
+    }
+}
+
+/// Returns the variable `key` of the environment of the shim, or the one set by the
+/// configuration file of the node, like [`std::env::var_os`].
+pub fn var_os(key: &str) -> Option<OsString> {
+    std::env::var_os(key).or_else(|| vars().read().unwrap().get(key).map(OsString::from))
+}
+
+/// Loads the configuration file again when the shim receives a SIGHUP.
+#[cfg(unix)]
+pub(crate) fn watch() -> std::io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    std::thread::Builder::new()
+        .name("node-config".to_string())
+        .spawn(move || {
+            for _ in signals.forever() {
+                reload();
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let vars = parse(
+            r#"
+            log_format = "json"
+
+            [otel]
+            exporter_otlp_endpoint = "http://localhost:4318"
+
+            [cache]
+            precompile_cache_dir = "/var/cache/runwasi"
+
+            [limits]
+            wasmtime_pooling_max_instances = 100
+            wasmtime_debug = true
+            "#,
+        )?;
+        assert_eq!(
+            vars,
+            HashMap::from(
+                [
+                    ("RUNWASI_LOG_FORMAT", "json"),
+                    ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://localhost:4318"),
+                    ("RUNWASI_PRECOMPILE_CACHE_DIR", "/var/cache/runwasi"),
+                    ("RUNWASI_WASMTIME_POOLING_MAX_INSTANCES", "100"),
+                    ("RUNWASI_WASMTIME_DEBUG", "true"),
+                ]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+            )
+        );
+
+        assert!(parse("[limits]\nwasmtime_pooling = [1, 2]").is_err());
+        assert!(parse("log_format = ").is_err());
+        Ok(())
+    }
+}
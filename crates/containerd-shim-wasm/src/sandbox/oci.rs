@@ -1,20 +1,292 @@
 //! Generic helpers for working with OCI specs that can be consumed by any runtime.
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
 use std::io::{ErrorKind, Write};
+use std::ops::Deref;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
 #[cfg(unix)]
 use std::os::unix::process::CommandExt;
+use std::path::{Component, Path, PathBuf};
 use std::process;
+use std::sync::Arc;
 
 use anyhow::Context;
-use oci_spec::image::Descriptor;
+use memmap2::Mmap;
+use oci_spec::image::{Arch, Descriptor, Os, Platform};
+#[cfg(unix)]
+use oci_spec::runtime::{Arch as SeccompArch, LinuxSeccompBuilder};
+use oci_spec::runtime::{
+    LinuxIdMapping, LinuxNamespaceType, LinuxSeccompAction, LinuxSeccompArgBuilder,
+    LinuxSeccompOperator, LinuxSyscall, LinuxSyscallBuilder, MountBuilder, Spec,
+};
+use serde::Deserialize;
 
-use super::error::Result;
+use super::error::{Error, Result};
+use crate::container::{RecordMode, DENIED_CAPABILITIES_ANNOTATION, RECORD_MODE_ANNOTATION};
+use crate::sandbox::node_config;
 
 #[derive(Clone, Debug)]
 pub struct WasmLayer {
     pub config: Descriptor,
-    pub layer: Vec<u8>,
+    pub layer: LayerContent,
+}
+
+/// The content of a wasm layer.
+///
+/// The content is either read into memory, or mapped from a file in the containerd
+/// content store, in which case the pages are shared by all the containers using the layer.
+#[derive(Clone)]
+pub struct LayerContent(Content);
+
+// the content is shared by the clones of a layer, e.g., by the containers running the same image
+#[derive(Clone)]
+enum Content {
+    Bytes(Arc<Vec<u8>>),
+    Mapped { file: Arc<File>, mmap: Arc<Mmap> },
+}
+
+impl LayerContent {
+    /// Maps the content of `file` into memory.
+    ///
+    /// The file must not be modified while it is mapped, which is always the case
+    /// for committed blobs in the containerd content store.
+    pub fn map(file: File) -> std::io::Result<Self> {
+        let mmap = unsafe { Mmap::map(&file) }?;
+        Ok(Self(Content::Mapped {
+            file: Arc::new(file),
+            mmap: Arc::new(mmap),
+        }))
+    }
+
+    /// Returns true if the content is mapped from a file
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.0, Content::Mapped { .. })
+    }
+
+    /// Returns a path to the mapped file, if the content is mapped.
+    /// The path refers to a file descriptor owned by the process, so it can be
+    /// opened from the container, where the content store is not visible.
+    #[cfg(unix)]
+    pub fn mapped_path(&self) -> Option<PathBuf> {
+        match &self.0 {
+            Content::Mapped { file, .. } => {
+                Some(PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd())))
+            }
+            Content::Bytes(_) => None,
+        }
+    }
+
+    /// Returns a path to the mapped file, if the content is mapped.
+    /// There is no path to an open file on this platform.
+    #[cfg(not(unix))]
+    pub fn mapped_path(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+impl Deref for LayerContent {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Content::Bytes(bytes) => bytes,
+            Content::Mapped { mmap, .. } => mmap,
+        }
+    }
+}
+
+impl AsRef<[u8]> for LayerContent {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for LayerContent {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(Content::Bytes(Arc::new(bytes)))
+    }
+}
+
+impl PartialEq for LayerContent {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl PartialEq<Vec<u8>> for LayerContent {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        **self == **other
+    }
+}
+
+impl std::fmt::Debug for LayerContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LayerContent")
+            .field("len", &self.len())
+            .field("mapped", &self.is_mapped())
+            .finish()
+    }
+}
+
+/// Media type of the config of wasm artifacts packaged following the CNCF wasm OCI artifact layout
+pub const WASM_ARTIFACT_CONFIG_MEDIA_TYPE: &str = "application/vnd.wasm.config.v0+json";
+
+/// Media type of the module and component layers of wasm artifacts
+pub const WASM_ARTIFACT_LAYER_MEDIA_TYPE: &str = "application/wasm";
+
+/// Media type of the layers precompiled by an engine, recorded in the `runwasi.io/precompiled/media-type`
+/// label of the precompiled content, next to the name and version of the engine that compiled it
+pub const WASM_PRECOMPILED_LAYER_MEDIA_TYPE: &str = "application/vnd.runwasi.wasm.precompiled.v1";
+
+/// Layer annotation with the function to call when the container doesn't set one,
+/// added by the shim to the layers of wasm artifacts whose config selects the function
+pub const ENTRYPOINT_FUNCTION_ANNOTATION: &str = "runwasi.io/entrypoint-function";
+
+/// Annotation of the manifests of an image index with the engine of the shim they are built for,
+/// e.g., with layers precompiled by the engine, the shims select the manifest of their engine,
+/// or else a manifest without the annotation
+pub const ENGINE_ANNOTATION: &str = "runwasi.io/engine";
+
+/// Annotation with the file name of the record log, in the directory of `RUNWASI_RECORD_LOG_DIR`
+/// or else in the bundle, defaults to a file of the bundle
+pub const RECORD_LOG_ANNOTATION: &str = "runwasi.io/record-log";
+
+/// Environment variable with the directory of the record logs named by the annotation of a container
+pub const RECORD_LOG_DIR_ENV: &str = "RUNWASI_RECORD_LOG_DIR";
+
+/// Path of the record log in the container
+pub const RECORD_LOG_PATH: &str = "/run/runwasi/record.log";
+
+/// Name of the record log in the bundle
+const RECORD_LOG_FILE: &str = "wasi-record.log";
+
+/// Annotation enabling the core dumps of the guests that trap, `true` or `false`
+pub const COREDUMP_ANNOTATION: &str = "runwasi.io/coredump-on-trap";
+
+/// Path of the core dump in the container
+pub const COREDUMP_PATH: &str = "/run/runwasi/wasm.coredump";
+
+/// Annotation enabling the built-in seccomp profile for the containers whose spec has none,
+/// `true` or `false`, defaults to `false`
+pub const DEFAULT_SECCOMP_ANNOTATION: &str = "runwasi.io/default-seccomp";
+
+/// Annotation enabling the hot reload of the module of the container when its image is updated,
+/// `true` or `false`, defaults to `false`
+pub const HOT_RELOAD_ANNOTATION: &str = "runwasi.io/hot-reload";
+
+/// Syscalls the built-in seccomp profile denies with `EPERM`: the ones that change the system, or
+/// the namespaces, mounts, or other processes, which an engine never needs to run a guest.
+/// `clone` is denied with the flags creating namespaces, and `clone3`, whose flags seccomp can't
+/// read, fails with `ENOSYS` so that the C libraries fall back to `clone`.
+const DEFAULT_SECCOMP_DENIED_SYSCALLS: &[&str] = &[
+    "acct",
+    "add_key",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "init_module",
+    "kcmp",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mount",
+    "move_mount",
+    "name_to_handle_at",
+    "open_by_handle_at",
+    "open_tree",
+    "perf_event_open",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "setns",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "syslog",
+    "umount2",
+    "unshare",
+    "userfaultfd",
+];
+
+/// Prefix of the annotations setting an environment variable to the content of a file,
+/// e.g., `io.containerd.wasm.env-from-file.MY_TOKEN: /run/secrets/token`
+pub const ENV_FROM_FILE_ANNOTATION_PREFIX: &str = "io.containerd.wasm.env-from-file.";
+
+/// Prefix of the annotations setting an environment variable to a field of the pod, like the
+/// downward API of Kubernetes, e.g., `io.containerd.wasm.env-from-field.POD_IP: status.podIP`
+pub const ENV_FROM_FIELD_ANNOTATION_PREFIX: &str = "io.containerd.wasm.env-from-field.";
+
+/// Annotation with the patterns of the environment variables removed from the environment of the
+/// guest, separated by commas, where `*` matches any characters, e.g., `KUBERNETES_*,*_PORT_*`
+pub const ENV_STRIP_ANNOTATION: &str = "io.containerd.wasm.env-strip";
+
+/// Annotation with the filesystem backing the root of the container: `host`, the default, or
+/// `memory`, a tmpfs seeded with the rootfs
+pub const FS_ANNOTATION: &str = "io.containerd.wasm.fs";
+
+/// The fields of the pod of the `io.containerd.wasm.env-from-field.<NAME>` annotations, with the
+/// annotation of the CRI they are read from, or `None` for the ones of the host
+const ENV_FIELDS: &[(&str, Option<&str>)] = &[
+    ("metadata.name", Some("io.kubernetes.cri.sandbox-name")),
+    (
+        "metadata.namespace",
+        Some("io.kubernetes.cri.sandbox-namespace"),
+    ),
+    ("metadata.uid", Some("io.kubernetes.cri.sandbox-uid")),
+    ("spec.nodeName", None),
+    ("status.podIP", None),
+];
+
+/// The config of a wasm artifact, as defined by the CNCF wasm OCI artifact layout.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WasmArtifactConfig {
+    #[serde(default)]
+    pub architecture: String,
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub component: Option<WasmComponentMetadata>,
+}
+
+/// The exports of a component packaged as a wasm artifact
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct WasmComponentMetadata {
+    #[serde(default)]
+    pub exports: Vec<String>,
+}
+
+impl WasmArtifactConfig {
+    /// The platform of the artifact, e.g., `wasm/wasip2`
+    pub fn platform(&self) -> Platform {
+        let mut platform = Platform::default();
+        platform.set_architecture(Arch::from(self.architecture.as_str()));
+        platform.set_os(Os::from(self.os.as_str()));
+        platform
+    }
+
+    /// The function to call in the artifact, when the component exports a single function
+    /// rather than interfaces such as `wasi:cli/run`
+    pub fn entrypoint_function(&self) -> Option<&str> {
+        let exports = &self.component.as_ref()?.exports;
+        match exports.as_slice() {
+            [export] if !export.contains([':', '/']) => Some(export),
+            _ => None,
+        }
+    }
 }
 
 fn parse_env(envs: &[String]) -> HashMap<String, String> {
@@ -31,6 +303,554 @@ fn parse_env(envs: &[String]) -> HashMap<String, String> {
         .collect()
 }
 
+/// Adds an in-memory `/tmp` to a container with a read-only root, unless the spec already
+/// mounts something there. Returns whether the spec was changed.
+pub(crate) fn mount_tmp_on_readonly_root(spec: &mut Spec) -> Result<bool> {
+    let readonly = spec
+        .root()
+        .as_ref()
+        .and_then(|root| root.readonly())
+        .unwrap_or(false);
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    if !readonly || mounts.iter().any(|m| m.destination() == Path::new("/tmp")) {
+        return Ok(false);
+    }
+
+    mounts.push(
+        MountBuilder::default()
+            .destination("/tmp")
+            .typ("tmpfs")
+            .source("tmpfs")
+            .options(["nosuid", "nodev", "mode=1777"].map(String::from).to_vec())
+            .build()?,
+    );
+    spec.set_mounts(Some(mounts));
+    Ok(true)
+}
+
+/// The flags of `clone` creating namespaces, denied by the built-in seccomp profile
+#[cfg(unix)]
+const CLONE_NAMESPACE_FLAGS: &[libc::c_int] = &[
+    libc::CLONE_NEWNS,
+    libc::CLONE_NEWCGROUP,
+    libc::CLONE_NEWUTS,
+    libc::CLONE_NEWIPC,
+    libc::CLONE_NEWUSER,
+    libc::CLONE_NEWPID,
+    libc::CLONE_NEWNET,
+];
+
+/// The architectures of the built-in seccomp profile, the native one and its compat ones, so that
+/// the 32-bit syscalls are filtered too.
+#[cfg(unix)]
+fn seccomp_architectures() -> Vec<SeccompArch> {
+    if cfg!(target_arch = "x86_64") {
+        vec![
+            SeccompArch::ScmpArchX86_64,
+            SeccompArch::ScmpArchX86,
+            SeccompArch::ScmpArchX32,
+        ]
+    } else if cfg!(target_arch = "aarch64") {
+        vec![SeccompArch::ScmpArchAarch64, SeccompArch::ScmpArchArm]
+    } else {
+        vec![SeccompArch::ScmpArchNative]
+    }
+}
+
+/// Adds the built-in seccomp profile to a container whose spec has no seccomp profile, when it is
+/// enabled with the `runwasi.io/default-seccomp` annotation.
+/// The profile of the spec, or the built-in one, filters the syscalls of the process running the
+/// engine, as for native containers. Returns whether the spec was changed.
+///
+/// The built-in profile allows every syscall but the ones of `DEFAULT_SECCOMP_DENIED_SYSCALLS`:
+/// it reduces what an escape from the engine can do, but, unlike the default profile of
+/// containerd, it isn't an allowlist, so it is not a security boundary.
+#[cfg(unix)]
+pub(crate) fn apply_default_seccomp(spec: &mut Spec) -> Result<bool> {
+    let enabled = match spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(DEFAULT_SECCOMP_ANNOTATION))
+    {
+        Some(enabled) => enabled.parse().map_err(|_| {
+            Error::InvalidArgument(format!(
+                "invalid {DEFAULT_SECCOMP_ANNOTATION} annotation {enabled:?}, expected `true` or `false`"
+            ))
+        })?,
+        None => false,
+    };
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    if !enabled || linux.seccomp().is_some() {
+        return Ok(false);
+    }
+
+    let denied = LinuxSyscallBuilder::default()
+        .names(
+            DEFAULT_SECCOMP_DENIED_SYSCALLS
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .action(LinuxSeccompAction::ScmpActErrno)
+        .errno_ret(libc::EPERM as u32)
+        .build()?;
+    let mut syscalls = vec![denied];
+    // the rules of a syscall match when all their arguments match, so each flag has its own rule
+    for flag in CLONE_NAMESPACE_FLAGS {
+        syscalls.push(clone_flag_rule(*flag as u64)?);
+    }
+    syscalls.push(
+        LinuxSyscallBuilder::default()
+            .names(vec!["clone3".to_string()])
+            .action(LinuxSeccompAction::ScmpActErrno)
+            .errno_ret(libc::ENOSYS as u32)
+            .build()?,
+    );
+    linux.set_seccomp(Some(
+        LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .architectures(seccomp_architectures())
+            .syscalls(syscalls)
+            .build()?,
+    ));
+    spec.set_linux(Some(linux));
+    Ok(true)
+}
+
+/// Denies `clone` with `flag` with `EPERM`.
+fn clone_flag_rule(flag: u64) -> Result<LinuxSyscall> {
+    // the flags are the second argument of `clone` on s390x, and the first one elsewhere
+    let index: usize = if cfg!(target_arch = "s390x") { 1 } else { 0 };
+    Ok(LinuxSyscallBuilder::default()
+        .names(vec!["clone".to_string()])
+        .action(LinuxSeccompAction::ScmpActErrno)
+        .errno_ret(libc::EPERM as u32)
+        .args(vec![LinuxSeccompArgBuilder::default()
+            .index(index)
+            .value(flag)
+            .value_two(flag)
+            .op(LinuxSeccompOperator::ScmpCmpMaskedEq)
+            .build()?])
+        .build()?)
+}
+
+/// Removes the environment variables matching the patterns of the `io.containerd.wasm.env-strip`
+/// annotation, or the `patterns` of the runtime options, e.g., the variables Kubernetes sets for
+/// the services of the namespace, which leak the addresses of the cluster to the guest.
+/// The variables of the `env-from-*` annotations are set after, so they are never removed.
+/// Returns whether the spec was changed.
+pub(crate) fn strip_env(spec: &mut Spec, patterns: &[String]) -> Result<bool> {
+    let annotation = spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(ENV_STRIP_ANNOTATION))
+        .map(String::as_str)
+        .unwrap_or_default();
+    let patterns: Vec<_> = patterns
+        .iter()
+        .map(String::as_str)
+        .chain(annotation.split(','))
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .collect();
+    if patterns.is_empty() {
+        return Ok(false);
+    }
+
+    let Some(mut env) = spec.process().as_ref().and_then(|p| p.env().clone()) else {
+        return Ok(false);
+    };
+    let len = env.len();
+    env.retain(|var| {
+        let name = env_name(var);
+        !patterns.iter().any(|pattern| glob_match(pattern, name))
+    });
+    if env.len() == len {
+        return Ok(false);
+    }
+    log::debug!("removed {} variables from the environment", len - env.len());
+
+    let mut process = spec.process().clone().unwrap_or_default();
+    process.set_env(Some(env));
+    spec.set_process(Some(process));
+    Ok(true)
+}
+
+/// Sets the environment variables of the `io.containerd.wasm.env-from-field.<NAME>` annotations
+/// to the field of the pod they name, like the downward API: `metadata.name`,
+/// `metadata.namespace` and `metadata.uid` are read from the annotations of the CRI, and
+/// `spec.nodeName` and `status.podIP` from `host_field`.
+/// They override the variables of the spec, and are overridden by the ones of the
+/// `io.containerd.wasm.env-from-file.<NAME>` annotations.
+/// Returns whether the spec was changed.
+pub(crate) fn resolve_env_from_fields(
+    spec: &mut Spec,
+    host_field: impl Fn(&str) -> Option<String>,
+) -> Result<bool> {
+    let Some(annotations) = spec.annotations() else {
+        return Ok(false);
+    };
+    let mut vars: Vec<_> = annotations
+        .iter()
+        .filter_map(|(key, field)| {
+            Some((key.strip_prefix(ENV_FROM_FIELD_ANNOTATION_PREFIX)?, field))
+        })
+        .collect();
+    if vars.is_empty() {
+        return Ok(false);
+    }
+    vars.sort();
+
+    let mut env = spec
+        .process()
+        .as_ref()
+        .and_then(|p| p.env().clone())
+        .unwrap_or_default();
+    for (name, field) in vars {
+        check_env_name(name, ENV_FROM_FIELD_ANNOTATION_PREFIX)?;
+        let Some((_, annotation)) = ENV_FIELDS.iter().find(|(f, _)| *f == field.as_str()) else {
+            return Err(Error::InvalidArgument(format!(
+                "unknown field {field:?} in {ENV_FROM_FIELD_ANNOTATION_PREFIX}{name}, expected one of {:?}",
+                ENV_FIELDS.iter().map(|(f, _)| f).collect::<Vec<_>>()
+            )));
+        };
+        let value = match annotation {
+            Some(annotation) => annotations.get(*annotation).cloned(),
+            None => host_field(field),
+        };
+        let value = value.ok_or_else(|| {
+            Error::FailedPrecondition(format!("the pod has no {field} for {name}"))
+        })?;
+        set_env(&mut env, name, &value);
+    }
+
+    let mut process = spec.process().clone().unwrap_or_default();
+    process.set_env(Some(env));
+    spec.set_process(Some(process));
+    Ok(true)
+}
+
+// The `io.containerd.wasm.env-from-file.<NAME>` annotations, sorted by name, with the path of
+// their file in the container, which is absolute and has no `..`.
+fn env_files(spec: &Spec) -> Result<Vec<(&str, &Path)>> {
+    let Some(annotations) = spec.annotations() else {
+        return Ok(vec![]);
+    };
+    let mut vars = annotations
+        .iter()
+        .filter_map(|(key, path)| Some((key.strip_prefix(ENV_FROM_FILE_ANNOTATION_PREFIX)?, path)))
+        .map(|(name, path)| {
+            check_env_name(name, ENV_FROM_FILE_ANNOTATION_PREFIX)?;
+            let path = Path::new(path);
+            if !path.has_root() || path.components().any(|c| c == Component::ParentDir) {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid path {path:?} in {ENV_FROM_FILE_ANNOTATION_PREFIX}{name}, expected an absolute path without `..`"
+                )));
+            }
+            Ok((name, path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    vars.sort();
+    Ok(vars)
+}
+
+/// Checks the `io.containerd.wasm.env-from-file.<NAME>` annotations when the container is created,
+/// before their files are read by [`resolve_env_from_files`].
+pub(crate) fn check_env_from_files(spec: &Spec) -> Result<()> {
+    env_files(spec).map(|_| ())
+}
+
+/// Sets the environment variables of the `io.containerd.wasm.env-from-file.<NAME>` annotations
+/// to the content of their file, e.g., a Kubernetes secret mounted in the container.
+/// The path of the file is in the container, and is read with `read`, which must not leave the
+/// root of the container, e.g., in the process of the container once it pivoted to its root.
+/// The values are secrets, so the spec with them is only kept in memory, and never saved.
+/// A trailing newline in the file is not part of the value.
+/// Returns whether the spec was changed.
+pub(crate) fn resolve_env_from_files(
+    spec: &mut Spec,
+    read: impl Fn(&Path) -> std::io::Result<String>,
+) -> Result<bool> {
+    let mut values = vec![];
+    for (name, path) in env_files(spec)? {
+        let value = read(path).map_err(|err| {
+            Error::FailedPrecondition(format!("failed to read {name} from {path:?}: {err}"))
+        })?;
+        let value = value
+            .strip_suffix('\n')
+            .map(|value| value.strip_suffix('\r').unwrap_or(value))
+            .unwrap_or(&value)
+            .to_string();
+        values.push((name.to_string(), value));
+    }
+    if values.is_empty() {
+        return Ok(false);
+    }
+
+    let mut env = spec
+        .process()
+        .as_ref()
+        .and_then(|p| p.env().clone())
+        .unwrap_or_default();
+    for (name, value) in values {
+        set_env(&mut env, &name, &value);
+    }
+
+    let mut process = spec.process().clone().unwrap_or_default();
+    process.set_env(Some(env));
+    spec.set_process(Some(process));
+    Ok(true)
+}
+
+fn env_name(var: &str) -> &str {
+    var.split_once('=').map_or(var, |(name, _)| name)
+}
+
+fn set_env(env: &mut Vec<String>, name: &str, value: &str) {
+    env.retain(|var| env_name(var) != name);
+    env.push(format!("{name}={value}"));
+}
+
+fn check_env_name(name: &str, prefix: &str) -> Result<()> {
+    if name.is_empty() || name.contains('=') {
+        return Err(Error::InvalidArgument(format!(
+            "invalid environment variable name {name:?} in {prefix}{name}"
+        )));
+    }
+    Ok(())
+}
+
+// Whether `name` matches `pattern`, where `*` matches any characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = name.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // the pattern has no `*`
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+// The directory of the record logs named by the annotation of a container
+fn record_log_dir(bundle: &Path) -> PathBuf {
+    node_config::var_os(RECORD_LOG_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| bundle.to_path_buf())
+}
+
+// The name of the record log of the annotation, which can't be a path, so that the annotation
+// can't create or mount any other file of the host
+fn record_log_name(name: &str) -> Result<&Path> {
+    let path = Path::new(name);
+    match path.components().collect::<Vec<_>>()[..] {
+        [Component::Normal(_)] => Ok(path),
+        _ => Err(Error::InvalidArgument(format!(
+            "invalid {RECORD_LOG_ANNOTATION} annotation {name:?}, expected a file name"
+        ))),
+    }
+}
+
+/// Mounts the record log of a container run with the `runwasi.io/record-mode` annotation.
+/// The log is created, or truncated, to be recorded, and must exist to be replayed.
+/// Returns whether the spec was changed.
+pub(crate) fn mount_record_log(spec: &mut Spec, bundle: &Path) -> Result<bool> {
+    let annotations = spec.annotations().clone().unwrap_or_default();
+    let Some(mode) = annotations.get(RECORD_MODE_ANNOTATION) else {
+        return Ok(false);
+    };
+    let mode: RecordMode = mode
+        .parse()
+        .map_err(|err: anyhow::Error| Error::InvalidArgument(err.to_string()))?;
+    let log = match annotations.get(RECORD_LOG_ANNOTATION) {
+        Some(name) => record_log_dir(bundle).join(record_log_name(name)?),
+        None => bundle.join(RECORD_LOG_FILE),
+    };
+
+    let access = match mode {
+        RecordMode::Record => {
+            File::create(&log)?;
+            chown_to_container_user(spec, &log);
+            "rw"
+        }
+        RecordMode::Replay if log.is_file() => "ro",
+        RecordMode::Replay => {
+            return Err(Error::FailedPrecondition(format!(
+                "record log {log:?} to replay doesn't exist"
+            )))
+        }
+    };
+    log::info!("{mode:?} log: {log:?}");
+
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    mounts.retain(|mount| mount.destination() != Path::new(RECORD_LOG_PATH));
+    mounts.push(
+        MountBuilder::default()
+            .destination(RECORD_LOG_PATH)
+            .typ("bind")
+            .source(log)
+            .options(["rbind", access].map(String::from).to_vec())
+            .build()?,
+    );
+    spec.set_mounts(Some(mounts));
+    Ok(true)
+}
+
+/// Returns the uid and gid on the host of the user of the container process, mapped through the
+/// `linux.uidMappings` and `linux.gidMappings` of its user namespace, if it has one.
+/// Returns `None` if the user is not mapped.
+pub(crate) fn host_user(spec: &Spec) -> Option<(u32, u32)> {
+    let user = spec.process().as_ref()?.user();
+    let linux = spec.linux().as_ref();
+    let uid = host_id(user.uid(), linux.and_then(|l| l.uid_mappings().as_deref()))?;
+    let gid = host_id(user.gid(), linux.and_then(|l| l.gid_mappings().as_deref()))?;
+    Some((uid, gid))
+}
+
+fn host_id(id: u32, mappings: Option<&[LinuxIdMapping]>) -> Option<u32> {
+    match mappings {
+        None | Some([]) => Some(id),
+        Some(mappings) => mappings.iter().find_map(|mapping| {
+            let offset = id.checked_sub(mapping.container_id())?;
+            (offset < mapping.size()).then(|| mapping.host_id() + offset)
+        }),
+    }
+}
+
+/// Gives a file created by the shim for the container to the user of the container process,
+/// e.g., in a user namespace, so that the engine can write it.
+/// Failing to do so, e.g., with rootless containerd, is only logged.
+#[cfg(unix)]
+fn chown_to_container_user(spec: &Spec, path: &Path) {
+    let Some((uid, gid)) = host_user(spec) else {
+        log::warn!(
+            "the user of the container is not mapped to a host user, {path:?} keeps its owner"
+        );
+        return;
+    };
+    if let Err(err) = std::os::unix::fs::chown(path, Some(uid), Some(gid)) {
+        log::warn!("failed to give {path:?} to the user {uid}:{gid} of the container: {err}");
+    }
+}
+
+#[cfg(not(unix))]
+fn chown_to_container_user(_spec: &Spec, _path: &Path) {}
+
+/// Path of the core dump of a container on the host.
+/// The dump is next to the bundle rather than in it, so that it outlives the container.
+pub fn coredump_path(bundle: &Path) -> PathBuf {
+    let name = bundle.file_name().unwrap_or_default().to_string_lossy();
+    bundle.with_file_name(format!("{name}.coredump"))
+}
+
+/// Mounts the core dump of the container, if enabled with the `runwasi.io/coredump-on-trap`
+/// annotation, so that the engine can write it from the container.
+/// The dump is created empty, and is only written when the guest traps.
+pub(crate) fn mount_coredump(spec: &mut Spec, bundle: &Path) -> Result<bool> {
+    let enabled = match spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(COREDUMP_ANNOTATION))
+    {
+        Some(enabled) => enabled.parse().map_err(|_| {
+            Error::InvalidArgument(format!(
+                "invalid {COREDUMP_ANNOTATION} annotation {enabled:?}, expected `true` or `false`"
+            ))
+        })?,
+        None => false,
+    };
+    if !enabled {
+        return Ok(false);
+    }
+
+    let coredump = coredump_path(bundle);
+    File::create(&coredump)?;
+    chown_to_container_user(spec, &coredump);
+
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    mounts.retain(|mount| mount.destination() != Path::new(COREDUMP_PATH));
+    mounts.push(
+        MountBuilder::default()
+            .destination(COREDUMP_PATH)
+            .typ("bind")
+            .source(coredump)
+            .options(["rbind", "rw"].map(String::from).to_vec())
+            .build()?,
+    );
+    spec.set_mounts(Some(mounts));
+    Ok(true)
+}
+
+/// Returns the core dump written for the container, if its guest trapped.
+/// The empty dump left when it didn't is removed.
+pub(crate) fn take_coredump(bundle: &Path) -> Option<PathBuf> {
+    let coredump = coredump_path(bundle);
+    match std::fs::metadata(&coredump) {
+        Ok(metadata) if metadata.len() > 0 => Some(coredump),
+        Ok(_) => {
+            let _ = std::fs::remove_file(&coredump);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Sets the `runwasi.io/denied-capabilities` annotation to the capabilities the capability policy
+/// denies to the container, replacing any annotation set by the user.
+/// Returns whether the annotations changed.
+pub(crate) fn set_denied_capabilities(spec: &mut Spec, denied: &BTreeSet<String>) -> bool {
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    let previous = match denied.is_empty() {
+        true => annotations.remove(DENIED_CAPABILITIES_ANNOTATION),
+        false => {
+            let denied = denied.iter().cloned().collect::<Vec<_>>().join(",");
+            annotations.insert(DENIED_CAPABILITIES_ANNOTATION.to_string(), denied)
+        }
+    };
+    let changed = previous.as_ref() != annotations.get(DENIED_CAPABILITIES_ANNOTATION);
+    spec.set_annotations(Some(annotations));
+    changed
+}
+
+/// Returns the network namespace of the spec, and its path when the container joins an existing
+/// one, e.g., the network namespace CNI set up for the pod, rather than a new one.
+/// Returns `None` when the container shares the network namespace of the shim.
+pub(crate) fn network_namespace(spec: &Spec) -> Option<Option<&Path>> {
+    spec.linux()
+        .as_ref()?
+        .namespaces()
+        .as_ref()?
+        .iter()
+        .find(|ns| ns.typ() == LinuxNamespaceType::Network)
+        .map(|ns| ns.path().as_deref())
+}
+
+/// Returns whether the module of the container is reloaded when its image is updated, with the
+/// `runwasi.io/hot-reload` annotation.
+pub(crate) fn hot_reload(spec: &Spec) -> Result<bool> {
+    match spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(HOT_RELOAD_ANNOTATION))
+    {
+        Some(enabled) => enabled.parse().map_err(|_| {
+            Error::InvalidArgument(format!(
+                "invalid {HOT_RELOAD_ANNOTATION} annotation {enabled:?}, expected `true` or `false`"
+            ))
+        }),
+        None => Ok(false),
+    }
+}
+
 pub(crate) fn setup_prestart_hooks(hooks: &Option<oci_spec::runtime::Hooks>) -> Result<()> {
     if let Some(hooks) = hooks {
         let prestart_hooks = hooks.prestart().as_ref().unwrap();
@@ -101,3 +921,518 @@ pub(crate) fn setup_prestart_hooks(hooks: &Option<oci_spec::runtime::Hooks>) ->
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_mapped_layer_content() -> std::io::Result<()> {
+        let mut file = tempfile::tempfile()?;
+        file.write_all(b"precompiled")?;
+
+        let content = LayerContent::map(file)?;
+        assert!(content.is_mapped());
+        assert_eq!(content, b"precompiled".to_vec());
+        assert_eq!(content, LayerContent::from(b"precompiled".to_vec()));
+
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read(content.mapped_path().unwrap())?,
+            b"precompiled"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_tmp_on_readonly_root() -> anyhow::Result<()> {
+        use oci_spec::runtime::{RootBuilder, SpecBuilder};
+
+        let spec = |readonly: bool| {
+            SpecBuilder::default()
+                .root(
+                    RootBuilder::default()
+                        .path("rootfs")
+                        .readonly(readonly)
+                        .build()?,
+                )
+                .mounts(vec![])
+                .build()
+        };
+
+        let mut writable = spec(false)?;
+        assert!(!mount_tmp_on_readonly_root(&mut writable)?);
+
+        let mut readonly = spec(true)?;
+        assert!(mount_tmp_on_readonly_root(&mut readonly)?);
+        let mounts = readonly.mounts().clone().unwrap();
+        assert_eq!(mounts[0].destination(), Path::new("/tmp"));
+        assert_eq!(mounts[0].typ().as_deref(), Some("tmpfs"));
+
+        // the mount is only added once
+        assert!(!mount_tmp_on_readonly_root(&mut readonly)?);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_apply_default_seccomp() -> anyhow::Result<()> {
+        use oci_spec::runtime::SpecBuilder;
+
+        let spec = |enabled: Option<&str>| {
+            let annotations = enabled
+                .map(|e| HashMap::from([(DEFAULT_SECCOMP_ANNOTATION.to_string(), e.to_string())]));
+            SpecBuilder::default()
+                .annotations(annotations.unwrap_or_default())
+                .build()
+        };
+
+        // the built-in profile is opt-in
+        assert!(!apply_default_seccomp(&mut spec(None)?)?);
+        assert!(!apply_default_seccomp(&mut spec(Some("false"))?)?);
+        assert!(apply_default_seccomp(&mut spec(Some("no"))?).is_err());
+
+        let mut spec = spec(Some("true"))?;
+        assert!(apply_default_seccomp(&mut spec)?);
+        let seccomp = spec.linux().as_ref().unwrap().seccomp().clone().unwrap();
+        assert_eq!(seccomp.default_action(), LinuxSeccompAction::ScmpActAllow);
+        assert_eq!(seccomp.architectures(), &Some(seccomp_architectures()));
+        let syscalls = seccomp.syscalls().as_ref().unwrap();
+        let denied = &syscalls[0];
+        assert!(denied.names().contains(&"mount".to_string()));
+        assert_eq!(denied.errno_ret(), Some(libc::EPERM as u32));
+
+        // clone is denied with each of the namespace flags, and clone3 altogether
+        let clone_flags: Vec<_> = syscalls
+            .iter()
+            .filter(|syscall| syscall.names() == &["clone"])
+            .map(|syscall| syscall.args().as_ref().unwrap()[0].value())
+            .collect();
+        assert_eq!(clone_flags.len(), CLONE_NAMESPACE_FLAGS.len());
+        assert!(clone_flags.contains(&(libc::CLONE_NEWUSER as u64)));
+        let clone3 = syscalls.last().unwrap();
+        assert_eq!(clone3.names(), &["clone3"]);
+        assert_eq!(clone3.errno_ret(), Some(libc::ENOSYS as u32));
+
+        // the profile of the spec is kept
+        assert!(!apply_default_seccomp(&mut spec)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_env_from_files() -> anyhow::Result<()> {
+        use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir_all(root.path().join("etc"))?;
+        std::fs::create_dir_all(root.path().join("run/secrets"))?;
+        std::fs::write(root.path().join("etc/region"), "eu-west")?;
+        std::fs::write(root.path().join("run/secrets/token"), "s3cr3t=\n")?;
+        let read = |path: &Path| {
+            std::fs::read_to_string(root.path().join(path.strip_prefix("/").unwrap()))
+        };
+
+        let mut spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .env(vec!["PATH=/bin".to_string(), "TOKEN=old".to_string()])
+                    .build()?,
+            )
+            .annotations(HashMap::from([
+                (
+                    format!("{ENV_FROM_FILE_ANNOTATION_PREFIX}TOKEN"),
+                    "/run/secrets/token".to_string(),
+                ),
+                (
+                    format!("{ENV_FROM_FILE_ANNOTATION_PREFIX}REGION"),
+                    "/etc/region".to_string(),
+                ),
+            ]))
+            .build()?;
+
+        check_env_from_files(&spec)?;
+        assert!(resolve_env_from_files(&mut spec, read)?);
+        let env = spec.process().as_ref().unwrap().env().clone().unwrap();
+        assert_eq!(env, ["PATH=/bin", "REGION=eu-west", "TOKEN=s3cr3t="]);
+
+        // missing files fail the start of the container
+        let with_file = |path: &str| {
+            let mut spec = spec.clone();
+            let mut annotations = spec.annotations().clone().unwrap();
+            annotations.insert(
+                format!("{ENV_FROM_FILE_ANNOTATION_PREFIX}OTHER"),
+                path.to_string(),
+            );
+            spec.set_annotations(Some(annotations));
+            spec
+        };
+        assert!(check_env_from_files(&with_file("/run/secrets/missing")).is_ok());
+        assert!(resolve_env_from_files(&mut with_file("/run/secrets/missing"), read).is_err());
+
+        // paths that could leave the root of the container fail its creation
+        assert!(check_env_from_files(&with_file("/run/../../etc/shadow")).is_err());
+        assert!(check_env_from_files(&with_file("run/secrets/token")).is_err());
+
+        // specs without the annotations are left alone
+        let mut spec = SpecBuilder::default().build()?;
+        assert!(!resolve_env_from_files(&mut spec, read)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("HOME", "HOME"));
+        assert!(!glob_match("HOME", "HOMEDIR"));
+        assert!(glob_match("KUBERNETES_*", "KUBERNETES_SERVICE_HOST"));
+        assert!(!glob_match("KUBERNETES_*", "MY_KUBERNETES_HOST"));
+        assert!(glob_match("*_SERVICE_HOST", "REDIS_SERVICE_HOST"));
+        assert!(glob_match("*_PORT_*_TCP", "REDIS_PORT_6379_TCP"));
+        assert!(!glob_match("*_PORT_*_TCP", "REDIS_PORT_6379_UDP"));
+        assert!(!glob_match("A*A", "A"));
+        assert!(glob_match("*", "ANYTHING"));
+    }
+
+    #[test]
+    fn test_env_precedence() -> anyhow::Result<()> {
+        use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+        let mut spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .env(vec![
+                        "PATH=/bin".to_string(),
+                        "HOSTNAME=web-0".to_string(),
+                        "KUBERNETES_SERVICE_HOST=10.96.0.1".to_string(),
+                        "REDIS_SERVICE_HOST=10.96.0.2".to_string(),
+                        "POD_IP=from-image".to_string(),
+                    ])
+                    .build()?,
+            )
+            .annotations(HashMap::from([
+                (
+                    ENV_STRIP_ANNOTATION.to_string(),
+                    "*_SERVICE_HOST, POD_*".to_string(),
+                ),
+                (
+                    "io.kubernetes.cri.sandbox-name".to_string(),
+                    "web-0".to_string(),
+                ),
+                (
+                    "io.kubernetes.cri.sandbox-namespace".to_string(),
+                    "default".to_string(),
+                ),
+                (
+                    format!("{ENV_FROM_FIELD_ANNOTATION_PREFIX}POD_NAME"),
+                    "metadata.name".to_string(),
+                ),
+                (
+                    format!("{ENV_FROM_FIELD_ANNOTATION_PREFIX}POD_NAMESPACE"),
+                    "metadata.namespace".to_string(),
+                ),
+                (
+                    format!("{ENV_FROM_FIELD_ANNOTATION_PREFIX}POD_IP"),
+                    "status.podIP".to_string(),
+                ),
+                (
+                    format!("{ENV_FROM_FIELD_ANNOTATION_PREFIX}NODE_NAME"),
+                    "spec.nodeName".to_string(),
+                ),
+                (
+                    format!("{ENV_FROM_FILE_ANNOTATION_PREFIX}POD_NAMESPACE"),
+                    "/etc/pod".to_string(),
+                ),
+            ]))
+            .build()?;
+        let host_field = |field: &str| match field {
+            "spec.nodeName" => Some("node-1".to_string()),
+            "status.podIP" => Some("10.244.0.7".to_string()),
+            _ => None,
+        };
+
+        // the runtime options and the annotation strip the variables of the spec, then the
+        // fields of the pod are set, then the files, which override them
+        assert!(strip_env(&mut spec, &["HOSTNAME".to_string()])?);
+        assert!(resolve_env_from_fields(&mut spec, host_field)?);
+        assert!(resolve_env_from_files(&mut spec, |_| Ok(
+            "from-file\n".to_string()
+        ))?);
+        let env = spec.process().as_ref().unwrap().env().clone().unwrap();
+        assert_eq!(
+            env,
+            [
+                "PATH=/bin",
+                "NODE_NAME=node-1",
+                "POD_IP=10.244.0.7",
+                "POD_NAME=web-0",
+                "POD_NAMESPACE=from-file",
+            ]
+        );
+
+        // fields the pod doesn't have fail the creation of the container
+        let mut annotations = spec.annotations().clone().unwrap();
+        annotations.insert(
+            format!("{ENV_FROM_FIELD_ANNOTATION_PREFIX}POD_UID"),
+            "metadata.uid".to_string(),
+        );
+        spec.set_annotations(Some(annotations.clone()));
+        assert!(resolve_env_from_fields(&mut spec, host_field).is_err());
+
+        annotations.insert(
+            format!("{ENV_FROM_FIELD_ANNOTATION_PREFIX}POD_UID"),
+            "metadata.labels".to_string(),
+        );
+        spec.set_annotations(Some(annotations));
+        assert!(resolve_env_from_fields(&mut spec, host_field).is_err());
+
+        // specs without the annotations are left alone
+        let mut spec = SpecBuilder::default().build()?;
+        assert!(!strip_env(&mut spec, &[])?);
+        assert!(!resolve_env_from_fields(&mut spec, host_field)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_user() -> anyhow::Result<()> {
+        use oci_spec::runtime::{
+            LinuxBuilder, LinuxIdMappingBuilder, ProcessBuilder, SpecBuilder, UserBuilder,
+        };
+
+        let mapping = |container_id: u32, host_id: u32, size: u32| {
+            LinuxIdMappingBuilder::default()
+                .container_id(container_id)
+                .host_id(host_id)
+                .size(size)
+                .build()
+        };
+        let spec = |uid: u32, gid: u32, linux| {
+            SpecBuilder::default()
+                .process(
+                    ProcessBuilder::default()
+                        .user(UserBuilder::default().uid(uid).gid(gid).build()?)
+                        .build()?,
+                )
+                .linux(linux)
+                .build()
+        };
+
+        // without a user namespace, the ids are the same on the host
+        let linux = LinuxBuilder::default().build()?;
+        assert_eq!(host_user(&spec(1000, 1000, linux)?), Some((1000, 1000)));
+
+        let linux = LinuxBuilder::default()
+            .uid_mappings(vec![mapping(0, 100000, 1000)?, mapping(1000, 1000, 1)?])
+            .gid_mappings(vec![mapping(0, 200000, 65536)?])
+            .build()?;
+        assert_eq!(
+            host_user(&spec(0, 0, linux.clone())?),
+            Some((100000, 200000))
+        );
+        assert_eq!(
+            host_user(&spec(42, 42, linux.clone())?),
+            Some((100042, 200042))
+        );
+        assert_eq!(
+            host_user(&spec(1000, 5, linux.clone())?),
+            Some((1000, 200005))
+        );
+        assert_eq!(host_user(&spec(1001, 0, linux)?), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_record_log() -> anyhow::Result<()> {
+        use oci_spec::runtime::SpecBuilder;
+
+        let bundle = tempfile::tempdir()?;
+        let spec = |mode: Option<&str>| {
+            let annotations = mode.map(|mode| {
+                HashMap::from([(RECORD_MODE_ANNOTATION.to_string(), mode.to_string())])
+            });
+            let mut spec = SpecBuilder::default().mounts(vec![]).build()?;
+            spec.set_annotations(annotations);
+            anyhow::Ok(spec)
+        };
+
+        let mut spec_without_mode = spec(None)?;
+        assert!(!mount_record_log(&mut spec_without_mode, bundle.path())?);
+
+        // there is nothing to replay yet
+        assert!(mount_record_log(&mut spec(Some("replay"))?, bundle.path()).is_err());
+        assert!(mount_record_log(&mut spec(Some("rewind"))?, bundle.path()).is_err());
+
+        let mut recorded = spec(Some("record"))?;
+        assert!(mount_record_log(&mut recorded, bundle.path())?);
+        let log = bundle.path().join(RECORD_LOG_FILE);
+        assert!(log.is_file());
+        let mounts = recorded.mounts().clone().unwrap();
+        assert_eq!(mounts[0].destination(), Path::new(RECORD_LOG_PATH));
+        assert_eq!(mounts[0].source().as_deref(), Some(log.as_path()));
+
+        let mut replayed = spec(Some("replay"))?;
+        assert!(mount_record_log(&mut replayed, bundle.path())?);
+        let options = replayed.mounts().clone().unwrap()[0].options().clone();
+        assert_eq!(options.unwrap(), ["rbind", "ro"]);
+
+        // the annotation names a log, which can't be anywhere else on the host
+        let named = |log: &str| {
+            let mut spec = spec(Some("record"))?;
+            let mut annotations = spec.annotations().clone().unwrap();
+            annotations.insert(RECORD_LOG_ANNOTATION.to_string(), log.to_string());
+            spec.set_annotations(Some(annotations));
+            anyhow::Ok(spec)
+        };
+        assert!(mount_record_log(
+            &mut named("failed-run.log")?,
+            bundle.path()
+        )?);
+        assert!(bundle.path().join("failed-run.log").is_file());
+        assert!(mount_record_log(&mut named("/etc/passwd")?, bundle.path()).is_err());
+        assert!(mount_record_log(&mut named("../failed-run.log")?, bundle.path()).is_err());
+        assert!(mount_record_log(&mut named("logs/failed-run.log")?, bundle.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_coredump() -> anyhow::Result<()> {
+        use oci_spec::runtime::SpecBuilder;
+
+        let dir = tempfile::tempdir()?;
+        let bundle = dir.path().join("container");
+        let spec = |enabled: &str| {
+            let mut spec = SpecBuilder::default().mounts(vec![]).build()?;
+            spec.set_annotations(Some(HashMap::from([(
+                COREDUMP_ANNOTATION.to_string(),
+                enabled.to_string(),
+            )])));
+            anyhow::Ok(spec)
+        };
+
+        assert!(!mount_coredump(&mut spec("false")?, &bundle)?);
+        assert!(mount_coredump(&mut spec("yes")?, &bundle).is_err());
+
+        let mut spec = spec("true")?;
+        assert!(mount_coredump(&mut spec, &bundle)?);
+        let coredump = dir.path().join("container.coredump");
+        let mounts = spec.mounts().clone().unwrap();
+        assert_eq!(mounts[0].destination(), Path::new(COREDUMP_PATH));
+        assert_eq!(mounts[0].source().as_deref(), Some(coredump.as_path()));
+
+        // the guest didn't trap
+        assert_eq!(take_coredump(&bundle), None);
+        assert!(!coredump.exists());
+
+        std::fs::write(&coredump, b"\0asm")?;
+        assert_eq!(take_coredump(&bundle), Some(coredump));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_denied_capabilities() -> anyhow::Result<()> {
+        use oci_spec::runtime::SpecBuilder;
+
+        let mut spec = SpecBuilder::default().build()?;
+        let denied = |capabilities: &[&str]| capabilities.iter().map(|c| c.to_string()).collect();
+        let annotation = |spec: &Spec| {
+            spec.annotations()
+                .as_ref()
+                .and_then(|a| a.get(DENIED_CAPABILITIES_ANNOTATION).cloned())
+        };
+
+        assert!(!set_denied_capabilities(&mut spec, &denied(&[])));
+        assert!(set_denied_capabilities(
+            &mut spec,
+            &denied(&["sockets", "http"])
+        ));
+        assert_eq!(annotation(&spec).as_deref(), Some("http,sockets"));
+        assert!(!set_denied_capabilities(
+            &mut spec,
+            &denied(&["http", "sockets"])
+        ));
+
+        // the annotation set by the user is removed when the policy denies nothing
+        assert!(set_denied_capabilities(&mut spec, &denied(&[])));
+        assert_eq!(annotation(&spec), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_namespace() -> anyhow::Result<()> {
+        use oci_spec::runtime::{LinuxBuilder, LinuxNamespace, LinuxNamespaceBuilder, SpecBuilder};
+
+        let spec = |namespace: Option<LinuxNamespace>| {
+            SpecBuilder::default()
+                .linux(
+                    LinuxBuilder::default()
+                        .namespaces(namespace.into_iter().collect::<Vec<_>>())
+                        .build()?,
+                )
+                .build()
+        };
+        let netns = || LinuxNamespaceBuilder::default().typ(LinuxNamespaceType::Network);
+
+        let host = spec(None)?;
+        assert_eq!(network_namespace(&host), None);
+        let new = spec(Some(netns().build()?))?;
+        assert_eq!(network_namespace(&new), Some(None));
+        let pod = spec(Some(netns().path("/var/run/netns/cni-1234").build()?))?;
+        assert_eq!(
+            network_namespace(&pod),
+            Some(Some(Path::new("/var/run/netns/cni-1234")))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hot_reload() -> anyhow::Result<()> {
+        use oci_spec::runtime::SpecBuilder;
+
+        let spec = |enabled: &str| {
+            SpecBuilder::default()
+                .annotations(HashMap::from([(
+                    HOT_RELOAD_ANNOTATION.to_string(),
+                    enabled.to_string(),
+                )]))
+                .build()
+        };
+
+        assert!(!hot_reload(&SpecBuilder::default().build()?)?);
+        assert!(hot_reload(&spec("true")?)?);
+        assert!(!hot_reload(&spec("false")?)?);
+        assert!(hot_reload(&spec("yes")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wasm_artifact_config() -> anyhow::Result<()> {
+        let config: WasmArtifactConfig = serde_json::from_str(
+            r#"{
+                "created": "2024-06-01T00:00:00Z",
+                "architecture": "wasm",
+                "os": "wasip2",
+                "layerDigests": ["sha256:0000"],
+                "component": {
+                    "exports": ["wasi:cli/run@0.2.0"],
+                    "imports": ["wasi:cli/environment@0.2.0"],
+                    "target": "wasi:cli/command@0.2.0"
+                }
+            }"#,
+        )?;
+        assert_eq!(config.platform().architecture(), &Arch::Wasm);
+        assert_eq!(config.platform().os(), &Os::Other("wasip2".to_string()));
+        assert_eq!(config.entrypoint_function(), None);
+
+        let config: WasmArtifactConfig = serde_json::from_str(
+            r#"{"architecture": "wasm", "os": "wasip2", "component": {"exports": ["handle"]}}"#,
+        )?;
+        assert_eq!(config.entrypoint_function(), Some("handle"));
+
+        // modules have no component metadata
+        let config: WasmArtifactConfig =
+            serde_json::from_str(r#"{"architecture": "wasm", "os": "wasip1"}"#)?;
+        assert_eq!(config.entrypoint_function(), None);
+        Ok(())
+    }
+}
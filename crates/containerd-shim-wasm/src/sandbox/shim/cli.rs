@@ -11,9 +11,21 @@ use oci_spec::runtime::Spec;
 use shim::Flags;
 
 use crate::sandbox::instance::Instance;
+#[cfg(unix)]
+use crate::sandbox::node_config;
+#[cfg(unix)]
+use crate::sandbox::shim::drain::DrainMode;
 use crate::sandbox::shim::events::{RemoteEventSender, ToTimestamp};
+#[cfg(unix)]
+use crate::sandbox::shim::introspection;
 use crate::sandbox::shim::local::Local;
+use crate::sandbox::shim::logger;
+#[cfg(unix)]
+use crate::sandbox::shim::recovery;
+#[cfg(feature = "opentelemetry")]
+use crate::sandbox::shim::{otel_metrics_enabled, PrometheusConfig};
 use crate::sys::networking::setup_namespaces;
+
 /// Cli implements the containerd-shim cli interface using `Local<T>` as the task service.
 pub struct Cli<T: Instance + Sync + Send> {
     engine: T::Engine,
@@ -45,7 +57,17 @@ where
     type T = Local<I>;
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
-    fn new(_runtime_id: &str, args: &Flags, _config: &mut shim::Config) -> Self {
+    fn new(_runtime_id: &str, args: &Flags, config: &mut shim::Config) -> Self {
+        // the logger is only set up for the task service, which runs without an action
+        if args.action.is_empty() && logger::enabled() && !config.no_setup_logger {
+            match logger::init(args, &config.default_log_level) {
+                Ok(()) => config.no_setup_logger = true,
+                Err(err) => {
+                    eprintln!("failed to set up the logger, using the default logger: {err:#}")
+                }
+            }
+        }
+
         Cli {
             engine: Default::default(),
             namespace: args.namespace.to_string(),
@@ -58,21 +80,29 @@ where
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn start_shim(&mut self, opts: containerd_shim::StartOpts) -> shim::Result<String> {
         let dir = current_dir().map_err(|err| ShimError::Other(err.to_string()))?;
-        let spec = Spec::load(dir.join("config.json")).map_err(|err| {
-            shim::Error::InvalidArgument(format!("error loading runtime spec: {}", err))
-        })?;
-
         let id = opts.id.clone();
-        let grouping = spec
-            .annotations()
-            .as_ref()
-            .and_then(|a| a.get("io.kubernetes.cri.sandbox-id"))
-            .unwrap_or(&id);
 
-        setup_namespaces(&spec)
-            .map_err(|e| shim::Error::Other(format!("failed to setup namespaces: {}", e)))?;
+        // the bundle of a pod sandbox created with the Sandbox API has no runtime spec, its
+        // containers join the shim of the sandbox and set up their own namespaces
+        let config = dir.join("config.json");
+        let grouping = if config.exists() {
+            let spec = Spec::load(config).map_err(|err| {
+                shim::Error::InvalidArgument(format!("error loading runtime spec: {}", err))
+            })?;
+
+            setup_namespaces(&spec)
+                .map_err(|e| shim::Error::Other(format!("failed to setup namespaces: {}", e)))?;
+
+            spec.annotations()
+                .as_ref()
+                .and_then(|a| a.get("io.kubernetes.cri.sandbox-id"))
+                .cloned()
+                .unwrap_or(id)
+        } else {
+            id
+        };
 
-        let (_child, address) = shim::spawn(opts, grouping, vec![])?;
+        let (_child, address) = shim::spawn(opts, &grouping, vec![])?;
 
         write_address(&address)?;
 
@@ -82,24 +112,71 @@ where
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn wait(&mut self) {
         self.exit.wait();
+        #[cfg(unix)]
+        if let Some(path) = introspection::socket_path(&self.namespace, &self._id) {
+            let _ = std::fs::remove_file(path);
+        }
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn create_task_service(&self, publisher: RemotePublisher) -> Self::T {
+        #[cfg(feature = "opentelemetry")]
+        if otel_metrics_enabled() {
+            // only the long running shim process serves the metrics endpoint
+            if let Err(err) = PrometheusConfig::build_from_env().and_then(|config| config.serve()) {
+                log::warn!("failed to start prometheus endpoint: {err:#}");
+            }
+        }
+
         let events = RemoteEventSender::new(&self.namespace, publisher);
         let exit = self.exit.clone();
         let engine = self.engine.clone();
-        Local::<I>::new(
+        let local = Local::<I>::new(
             engine,
             events,
             exit,
             &self.namespace,
             &self.containerd_address,
-        )
+        );
+
+        #[cfg(unix)]
+        if let Some(path) = introspection::socket_path(&self.namespace, &self._id) {
+            if let Err(err) = introspection::serve(path, local.instances.clone()) {
+                log::warn!("failed to start introspection endpoint: {err:#}");
+            }
+        }
+
+        // a shim started again in the bundle of a running task re-attaches to it
+        if let Ok(bundle) = current_dir() {
+            if let Err(err) = local.recover(&bundle) {
+                log::warn!("failed to recover the task of bundle {bundle:?}: {err}");
+            }
+        }
+
+        // on a SIGTERM, e.g., when containerd is restarted, the shim drains rather than
+        // exiting with its tasks
+        #[cfg(unix)]
+        if let Err(err) = DrainMode::from_env().and_then(|mode| Ok(local.drain.watch(mode)?)) {
+            log::warn!("failed to set up the drain of the shim on SIGTERM: {err:#}");
+        }
+
+        // on a SIGHUP, the configuration of the node is loaded again
+        #[cfg(unix)]
+        if let Err(err) = node_config::watch() {
+            log::warn!("failed to set up the reload of the node configuration on SIGHUP: {err}");
+        }
+
+        local
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn delete_shim(&mut self) -> shim::Result<api::DeleteResponse> {
+        // the processes of a task left running by a shim that is gone are deleted with the task
+        #[cfg(unix)]
+        if let Ok(bundle) = current_dir() {
+            recovery::cleanup(&bundle);
+        }
+
         Ok(api::DeleteResponse {
             exit_status: 137,
             exited_at: Some(Utc::now().to_timestamp()).into(),
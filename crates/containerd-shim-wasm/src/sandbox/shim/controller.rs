@@ -0,0 +1,465 @@
+//! The Sandbox API of containerd, with which the shim is the sandbox controller of a pod.
+//!
+//! With the `shim` sandboxer of containerd, CRI creates one sandbox per pod with the Sandbox
+//! service of the shim, and then the containers of the pod as tasks of the same shim, so that
+//! they run as instances of a single process rather than with a shim each.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use containerd_shim::api::Status;
+use containerd_shim::protos::sandbox_api::{
+    CreateSandboxRequest, CreateSandboxResponse, PingRequest, PingResponse, PlatformRequest,
+    PlatformResponse, SandboxStatusRequest, SandboxStatusResponse, ShutdownSandboxRequest,
+    ShutdownSandboxResponse, StartSandboxRequest, StartSandboxResponse, StopSandboxRequest,
+    StopSandboxResponse, WaitSandboxRequest, WaitSandboxResponse,
+};
+use containerd_shim::protos::sandbox_sync::Sandbox;
+use containerd_shim::protos::types::platform::Platform;
+use containerd_shim::{ExitSignal, TtrpcContext, TtrpcResult};
+use log::debug;
+
+use crate::sandbox::instance::Instance;
+use crate::sandbox::shim::events::{EventSender, ToTimestamp};
+use crate::sandbox::shim::local::{Local, LocalInstances};
+use crate::sandbox::{Error, Result};
+use crate::sys::signals::{SIGKILL, SIGTERM};
+
+const SANDBOX_READY: &str = "SANDBOX_READY";
+const SANDBOX_NOTREADY: &str = "SANDBOX_NOTREADY";
+
+struct SandboxData {
+    id: String,
+    bundle: String,
+    netns_path: String,
+    created_at: DateTime<Utc>,
+    ready: bool,
+    exit: Option<(u32, DateTime<Utc>)>,
+}
+
+/// The pod sandbox of the shim, shared by its Task and Sandbox services
+#[derive(Default)]
+pub(super) struct PodSandbox {
+    data: Mutex<Option<SandboxData>>,
+    stopped: Condvar,
+}
+
+impl PodSandbox {
+    /// Returns `true` if the shim is the sandbox controller of a pod, in which case it keeps
+    /// running without tasks until the sandbox is shut down.
+    pub(super) fn is_created(&self) -> bool {
+        self.data.lock().unwrap().is_some()
+    }
+
+    fn with<R>(&self, id: &str, f: impl FnOnce(&mut SandboxData) -> Result<R>) -> Result<R> {
+        match self.data.lock().unwrap().as_mut() {
+            Some(data) if data.id == id => f(data),
+            _ => Err(Error::NotFound(id.to_string())),
+        }
+    }
+}
+
+/// SandboxController implements the Sandbox service of containerd for the pod of the shim.
+/// The containers of the pod are the instances of the Task service it shares the shim with.
+pub(super) struct SandboxController<T: Instance + Send + Sync> {
+    sandbox: Arc<PodSandbox>,
+    instances: LocalInstances<T>,
+    exit: Arc<ExitSignal>,
+}
+
+impl<T: Instance + Send + Sync> SandboxController<T> {
+    /// Creates the sandbox controller of the tasks of `local`.
+    pub(super) fn new<E: EventSender>(local: &Local<T, E>) -> Self {
+        Self {
+            sandbox: local.pod_sandbox.clone(),
+            instances: local.instances.clone(),
+            exit: local.exit.clone(),
+        }
+    }
+
+    fn sandbox_create(&self, req: CreateSandboxRequest) -> Result<CreateSandboxResponse> {
+        let mut data = self.sandbox.data.lock().unwrap();
+        if let Some(data) = data.as_ref() {
+            return Err(Error::AlreadyExists(format!(
+                "the shim is already the controller of sandbox {}",
+                data.id
+            )));
+        }
+
+        // the containers of the pod join its network namespace with their own runtime spec
+        *data = Some(SandboxData {
+            id: req.sandbox_id,
+            bundle: req.bundle_path,
+            netns_path: req.netns_path,
+            created_at: Utc::now(),
+            ready: false,
+            exit: None,
+        });
+        Ok(CreateSandboxResponse::new())
+    }
+
+    fn sandbox_start(&self, req: StartSandboxRequest) -> Result<StartSandboxResponse> {
+        self.sandbox.with(&req.sandbox_id, |data| {
+            if data.exit.is_some() {
+                return Err(Error::FailedPrecondition(format!(
+                    "sandbox {} is stopped",
+                    data.id
+                )));
+            }
+            data.ready = true;
+            Ok(StartSandboxResponse {
+                pid: std::process::id(),
+                created_at: Some(data.created_at.to_timestamp()).into(),
+                ..Default::default()
+            })
+        })
+    }
+
+    fn sandbox_platform(&self, req: PlatformRequest) -> Result<PlatformResponse> {
+        self.sandbox.with(&req.sandbox_id, |_| {
+            Ok(PlatformResponse {
+                platform: Some(platform()).into(),
+                ..Default::default()
+            })
+        })
+    }
+
+    fn sandbox_stop(&self, req: StopSandboxRequest) -> Result<StopSandboxResponse> {
+        self.sandbox.with(&req.sandbox_id, |_| Ok(()))?;
+
+        // without a timeout, each container has its own grace period
+        let timeout = match req.timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs.into())),
+        };
+        self.stop_containers(timeout);
+
+        self.sandbox.with(&req.sandbox_id, |data| {
+            data.ready = false;
+            data.exit.get_or_insert_with(|| (0, Utc::now()));
+            Ok(())
+        })?;
+        self.sandbox.stopped.notify_all();
+        Ok(StopSandboxResponse::new())
+    }
+
+    fn sandbox_wait(&self, req: WaitSandboxRequest) -> Result<WaitSandboxResponse> {
+        let data = self.sandbox.data.lock().unwrap();
+        let data = self
+            .sandbox
+            .stopped
+            .wait_while(data, |data| {
+                data.as_ref()
+                    .is_some_and(|data| data.id == req.sandbox_id && data.exit.is_none())
+            })
+            .unwrap();
+
+        match data.as_ref() {
+            Some(SandboxData {
+                id,
+                exit: Some((exit_status, exited_at)),
+                ..
+            }) if *id == req.sandbox_id => Ok(WaitSandboxResponse {
+                exit_status: *exit_status,
+                exited_at: Some(exited_at.to_timestamp()).into(),
+                ..Default::default()
+            }),
+            _ => Err(Error::NotFound(req.sandbox_id)),
+        }
+    }
+
+    fn sandbox_state(&self, req: SandboxStatusRequest) -> Result<SandboxStatusResponse> {
+        let containers = self.instances.read().unwrap().len();
+        self.sandbox.with(&req.sandbox_id, |data| {
+            let mut info = HashMap::new();
+            if req.verbose {
+                info.insert("bundle".to_string(), data.bundle.clone());
+                info.insert("netns_path".to_string(), data.netns_path.clone());
+                info.insert("containers".to_string(), containers.to_string());
+            }
+            let state = match data.ready {
+                true => SANDBOX_READY,
+                false => SANDBOX_NOTREADY,
+            };
+            Ok(SandboxStatusResponse {
+                sandbox_id: data.id.clone(),
+                pid: std::process::id(),
+                state: state.to_string(),
+                info,
+                created_at: Some(data.created_at.to_timestamp()).into(),
+                exited_at: data.exit.map(|(_, at)| at.to_timestamp()).into(),
+                ..Default::default()
+            })
+        })
+    }
+
+    fn sandbox_ping(&self, req: PingRequest) -> Result<PingResponse> {
+        self.sandbox
+            .with(&req.sandbox_id, |_| Ok(PingResponse::new()))
+    }
+
+    fn sandbox_shutdown(&self, req: ShutdownSandboxRequest) -> Result<ShutdownSandboxResponse> {
+        self.sandbox.with(&req.sandbox_id, |_| Ok(()))?;
+
+        // the containers left running get no grace period
+        self.stop_containers(Some(Duration::ZERO));
+
+        *self.sandbox.data.lock().unwrap() = None;
+        self.sandbox.stopped.notify_all();
+        self.exit.signal();
+        Ok(ShutdownSandboxResponse::new())
+    }
+
+    /// Sends a SIGTERM to the running containers of the pod, and kills the ones that didn't exit
+    /// after `timeout`, or after their own grace period, if they have one.
+    fn stop_containers(&self, timeout: Option<Duration>) {
+        let running: Vec<_> = self
+            .instances
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, i)| matches!(i.status().0, Status::RUNNING | Status::PAUSED))
+            .map(|(id, i)| (id.clone(), i.clone()))
+            .collect();
+
+        let start = Instant::now();
+        for (id, i) in &running {
+            if let Err(err) = i.kill(SIGTERM as u32) {
+                log::warn!("failed to stop container {id}: {err}");
+            }
+        }
+
+        for (id, i) in running {
+            let Some(grace_period) = timeout.or(i.shutdown_grace_period()) else {
+                // without a grace period, the container is never killed
+                i.wait();
+                continue;
+            };
+            let remaining = grace_period.saturating_sub(start.elapsed());
+            if i.wait_timeout(remaining).is_some() {
+                continue;
+            }
+            log::info!("container {id} did not exit within {grace_period:?}, killing it");
+            match i.kill(SIGKILL as u32) {
+                Ok(()) => {
+                    i.wait();
+                }
+                Err(err) => log::error!("failed to kill container {id}: {err}"),
+            }
+        }
+    }
+}
+
+// The platform of the shim, with the names of the architectures of Go, like containerd
+fn platform() -> Platform {
+    let architecture = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64le",
+        "loongarch64" => "loong64",
+        arch => arch,
+    };
+    Platform {
+        os: std::env::consts::OS.to_string(),
+        architecture: architecture.to_string(),
+        ..Default::default()
+    }
+}
+
+impl<T: Instance + Send + Sync> Sandbox for SandboxController<T> {
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn create_sandbox(
+        &self,
+        _: &TtrpcContext,
+        req: CreateSandboxRequest,
+    ) -> TtrpcResult<CreateSandboxResponse> {
+        debug!("create_sandbox: {:?}", req);
+        Ok(self.sandbox_create(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn start_sandbox(
+        &self,
+        _: &TtrpcContext,
+        req: StartSandboxRequest,
+    ) -> TtrpcResult<StartSandboxResponse> {
+        debug!("start_sandbox: {:?}", req);
+        Ok(self.sandbox_start(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn platform(&self, _: &TtrpcContext, req: PlatformRequest) -> TtrpcResult<PlatformResponse> {
+        debug!("platform: {:?}", req);
+        Ok(self.sandbox_platform(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn stop_sandbox(
+        &self,
+        _: &TtrpcContext,
+        req: StopSandboxRequest,
+    ) -> TtrpcResult<StopSandboxResponse> {
+        debug!("stop_sandbox: {:?}", req);
+        Ok(self.sandbox_stop(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn wait_sandbox(
+        &self,
+        _: &TtrpcContext,
+        req: WaitSandboxRequest,
+    ) -> TtrpcResult<WaitSandboxResponse> {
+        debug!("wait_sandbox: {:?}", req);
+        Ok(self.sandbox_wait(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn sandbox_status(
+        &self,
+        _: &TtrpcContext,
+        req: SandboxStatusRequest,
+    ) -> TtrpcResult<SandboxStatusResponse> {
+        debug!("sandbox_status: {:?}", req);
+        Ok(self.sandbox_state(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn ping_sandbox(&self, _: &TtrpcContext, req: PingRequest) -> TtrpcResult<PingResponse> {
+        debug!("ping_sandbox: {:?}", req);
+        Ok(self.sandbox_ping(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn shutdown_sandbox(
+        &self,
+        _: &TtrpcContext,
+        req: ShutdownSandboxRequest,
+    ) -> TtrpcResult<ShutdownSandboxResponse> {
+        debug!("shutdown_sandbox: {:?}", req);
+        Ok(self.sandbox_shutdown(req)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    use super::*;
+    use crate::sandbox::instance::{InstanceConfig, Nop};
+    use crate::sandbox::shim::instance_data::InstanceData;
+
+    #[test]
+    fn test_sandbox_lifecycle() -> Result<()> {
+        let (tx, _rx) = channel();
+        let exit = Arc::new(ExitSignal::default());
+        let local = Local::<Nop, _>::new((), tx, exit.clone(), "test_namespace", "/test/address");
+        let controller = SandboxController::new(&local);
+        let id = "test-sandbox".to_string();
+
+        controller.sandbox_create(CreateSandboxRequest {
+            sandbox_id: id.clone(),
+            bundle_path: "/run/test-sandbox".to_string(),
+            netns_path: "/var/run/netns/test".to_string(),
+            ..Default::default()
+        })?;
+        assert!(local.pod_sandbox.is_created());
+        match controller.sandbox_create(CreateSandboxRequest {
+            sandbox_id: "other".to_string(),
+            ..Default::default()
+        }) {
+            Err(Error::AlreadyExists(_)) => {}
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+        match controller.sandbox_ping(PingRequest {
+            sandbox_id: "other".to_string(),
+            ..Default::default()
+        }) {
+            Err(Error::NotFound(_)) => {}
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+
+        let started = controller.sandbox_start(StartSandboxRequest {
+            sandbox_id: id.clone(),
+            ..Default::default()
+        })?;
+        assert_eq!(started.pid, std::process::id());
+
+        // a container of the pod
+        let container = InstanceData::<Nop>::new_instance(
+            "container",
+            InstanceConfig::new((), "test_namespace", "/test/address"),
+        )?;
+        container.start()?;
+        let container = Arc::new(container);
+        local
+            .instances
+            .write()
+            .unwrap()
+            .insert("container".to_string(), container.clone());
+
+        let status = controller.sandbox_state(SandboxStatusRequest {
+            sandbox_id: id.clone(),
+            verbose: true,
+            ..Default::default()
+        })?;
+        assert_eq!(status.state, SANDBOX_READY);
+        assert_eq!(status.info["netns_path"], "/var/run/netns/test");
+        assert_eq!(status.info["containers"], "1");
+        assert!(status.exited_at.is_none());
+
+        thread::scope(|s| -> Result<()> {
+            let waiter = s.spawn(|| {
+                controller.sandbox_wait(WaitSandboxRequest {
+                    sandbox_id: id.clone(),
+                    ..Default::default()
+                })
+            });
+
+            controller.sandbox_stop(StopSandboxRequest {
+                sandbox_id: id.clone(),
+                timeout_secs: 1,
+                ..Default::default()
+            })?;
+            assert_eq!(container.wait_timeout(Duration::ZERO).unwrap().0, 0);
+            assert_eq!(waiter.join().unwrap()?.exit_status, 0);
+            Ok(())
+        })?;
+
+        let status = controller.sandbox_state(SandboxStatusRequest {
+            sandbox_id: id.clone(),
+            ..Default::default()
+        })?;
+        assert_eq!(status.state, SANDBOX_NOTREADY);
+        assert!(status.info.is_empty());
+        assert!(status.exited_at.is_some());
+        match controller.sandbox_start(StartSandboxRequest {
+            sandbox_id: id.clone(),
+            ..Default::default()
+        }) {
+            Err(Error::FailedPrecondition(_)) => {}
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+
+        controller.sandbox_shutdown(ShutdownSandboxRequest {
+            sandbox_id: id.clone(),
+            ..Default::default()
+        })?;
+        assert!(!local.pod_sandbox.is_created());
+        // returns right away, as the shim was asked to exit
+        exit.wait();
+        Ok(())
+    }
+
+    #[test]
+    fn test_platform() {
+        let platform = platform();
+        assert_eq!(platform.os, std::env::consts::OS);
+        assert!(!platform.architecture.is_empty());
+        assert_ne!(platform.architecture, "x86_64");
+        assert_ne!(platform.architecture, "aarch64");
+    }
+}
@@ -0,0 +1,166 @@
+//! Graceful shutdown of the shim, e.g., when containerd is restarted or stopped.
+//!
+//! On a SIGTERM, the shim drains: it stops accepting new tasks and exec processes, and persists
+//! the state of its running tasks to their bundle, rather than killing them.
+//! The drain mode is set with `RUNWASI_DRAIN_MODE` in the environment of the shim:
+//!  - `keep`, the default: the tasks keep running, and the shim keeps serving them so that
+//!    containerd re-attaches to them when it comes back, until they are deleted.
+//!  - `checkpoint`: the running tasks are checkpointed to the `checkpoint` directory of their
+//!    bundle first, and the shim exits. The tasks keep running, and a shim started again in their
+//!    bundle re-attaches to them, or restores them from their checkpoint if they are gone, e.g.,
+//!    after the node rebooted.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::bail;
+use chrono::Utc;
+use containerd_shim::ExitSignal;
+
+use crate::sandbox::shim::local::LocalInstances;
+use crate::sandbox::shim::recovery::TaskRecord;
+use crate::sandbox::{node_config, Instance};
+
+/// Environment variable with the drain mode of the shim, `keep` or `checkpoint`
+pub const DRAIN_MODE_ENV: &str = "RUNWASI_DRAIN_MODE";
+
+// The directory of the bundle of a task the task is checkpointed to
+const CHECKPOINT_DIR: &str = "checkpoint";
+
+/// What the shim does with its running tasks when it drains.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DrainMode {
+    // The tasks keep running, and the shim keeps serving them.
+    #[default]
+    Keep,
+    // The tasks are checkpointed, and the shim exits.
+    Checkpoint,
+}
+
+impl DrainMode {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match node_config::var(DRAIN_MODE_ENV) {
+            Ok(mode) => mode.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+impl FromStr for DrainMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> anyhow::Result<Self> {
+        match mode.trim() {
+            "keep" => Ok(Self::Keep),
+            "checkpoint" => Ok(Self::Checkpoint),
+            mode => bail!("invalid {DRAIN_MODE_ENV} {mode:?}, expected `keep` or `checkpoint`"),
+        }
+    }
+}
+
+/// Drains the tasks of a shim, shared by its task service and the thread handling its signals.
+pub(crate) struct Drain<T: Instance + Send + Sync> {
+    instances: LocalInstances<T>,
+    draining: Arc<AtomicBool>,
+    exit: Arc<ExitSignal>,
+}
+
+impl<T: Instance + Send + Sync> Clone for Drain<T> {
+    fn clone(&self) -> Self {
+        Self {
+            instances: self.instances.clone(),
+            draining: self.draining.clone(),
+            exit: self.exit.clone(),
+        }
+    }
+}
+
+impl<T: Instance + Send + Sync> fmt::Debug for Drain<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Drain {{ draining: {:?} }}", self.draining)
+    }
+}
+
+impl<T: Instance + Send + Sync> Drain<T> {
+    pub fn new(instances: LocalInstances<T>, exit: Arc<ExitSignal>) -> Self {
+        Self {
+            instances,
+            draining: Arc::default(),
+            exit,
+        }
+    }
+
+    /// Whether the shim is draining, and refuses new tasks
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting new tasks, and persists the state of the running tasks.
+    pub fn drain(&self, mode: DrainMode) {
+        if self.draining.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        log::info!("draining the shim with the {mode:?} mode");
+
+        let instances: Vec<_> = self
+            .instances
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, instance)| (id.clone(), instance.clone()))
+            .collect();
+        for (id, instance) in instances {
+            let Some(mut record) = TaskRecord::of(&id, &instance) else {
+                continue;
+            };
+            let bundle = instance.config().get_bundle();
+            if mode == DrainMode::Checkpoint {
+                let path = bundle.join(CHECKPOINT_DIR);
+                match instance.checkpoint(&path) {
+                    Ok(()) => record.checkpoint = Some(path),
+                    Err(err) => log::error!("failed to checkpoint task {id}: {err}"),
+                }
+            }
+            record.drained_at = Some(Utc::now().to_rfc3339());
+            match record.write(bundle) {
+                Ok(()) => log::info!("persisted the state of task {id} to {bundle:?}"),
+                Err(err) => log::error!("failed to persist the state of task {id}: {err:#}"),
+            }
+        }
+
+        if mode == DrainMode::Checkpoint {
+            self.exit.signal();
+        }
+    }
+
+    /// Drains the shim when it receives a SIGTERM.
+    #[cfg(unix)]
+    pub fn watch(&self, mode: DrainMode) -> std::io::Result<()> {
+        let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGTERM])?;
+        let drain = self.clone();
+        std::thread::Builder::new()
+            .name("drain".to_string())
+            .spawn(move || {
+                if signals.forever().next().is_some() {
+                    drain.drain(mode);
+                }
+            })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain_mode() -> anyhow::Result<()> {
+        assert_eq!(DrainMode::default(), DrainMode::Keep);
+        assert_eq!("keep".parse::<DrainMode>()?, DrainMode::Keep);
+        assert_eq!("checkpoint".parse::<DrainMode>()?, DrainMode::Checkpoint);
+        assert!("kill".parse::<DrainMode>().is_err());
+        Ok(())
+    }
+}
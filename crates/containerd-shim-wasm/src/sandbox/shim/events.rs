@@ -6,6 +6,11 @@ use containerd_shim::publisher::RemotePublisher;
 use log::warn;
 use protobuf::well_known_types::timestamp::Timestamp;
 
+use crate::services::events::{
+    TaskCapabilities, TaskCompileFinished, TaskCompileStarted, TaskCoreDumped, TaskReloaded,
+    TaskTrapped,
+};
+
 pub trait EventSender: Clone + Send + Sync + 'static {
     fn send(&self, event: impl Event);
 }
@@ -45,6 +50,42 @@ impl EventSender for RemoteEventSender {
     }
 }
 
+impl Event for TaskCompileStarted {
+    fn topic(&self) -> String {
+        "/tasks/compile-started".to_string()
+    }
+}
+
+impl Event for TaskCompileFinished {
+    fn topic(&self) -> String {
+        "/tasks/compile-finished".to_string()
+    }
+}
+
+impl Event for TaskCapabilities {
+    fn topic(&self) -> String {
+        "/tasks/capabilities".to_string()
+    }
+}
+
+impl Event for TaskCoreDumped {
+    fn topic(&self) -> String {
+        "/tasks/coredumped".to_string()
+    }
+}
+
+impl Event for TaskTrapped {
+    fn topic(&self) -> String {
+        "/tasks/trapped".to_string()
+    }
+}
+
+impl Event for TaskReloaded {
+    fn topic(&self) -> String {
+        "/tasks/reloaded".to_string()
+    }
+}
+
 pub(super) trait ToTimestamp {
     fn to_timestamp(self) -> Timestamp;
 }
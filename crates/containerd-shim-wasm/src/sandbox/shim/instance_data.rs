@@ -1,18 +1,30 @@
-use std::sync::{Arc, OnceLock, RwLock};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
+use containerd_shim::protos::types::task::Status;
+use oci_spec::runtime::Process;
 
+use crate::container::{GuestFailure, PoolMetrics};
 use crate::sandbox::instance::Nop;
 use crate::sandbox::shim::instance_option::InstanceOption;
 use crate::sandbox::shim::task_state::TaskState;
-use crate::sandbox::{Instance, InstanceConfig, Result};
+use crate::sandbox::{Error, Instance, InstanceConfig, Result};
 
 pub(super) struct InstanceData<T: Instance> {
     pub instance: InstanceOption<T>,
     cfg: InstanceConfig<T::Engine>,
-    pid: OnceLock<u32>,
+    pid: RwLock<Option<u32>>,
     state: Arc<RwLock<TaskState>>,
+    execs: RwLock<HashMap<String, Arc<InstanceData<T>>>>,
+    shutdown_grace_period: Option<Duration>,
+    escalating: AtomicBool,
+    sandbox_id: Option<String>,
+    // the pid of the process restored from a checkpoint, which is running before the task starts
+    restored_pid: Option<u32>,
 }
 
 impl<T: Instance> InstanceData<T> {
@@ -20,29 +32,174 @@ impl<T: Instance> InstanceData<T> {
     pub fn new_instance(id: impl AsRef<str>, cfg: InstanceConfig<T::Engine>) -> Result<Self> {
         let id = id.as_ref().to_string();
         let instance = InstanceOption::Instance(T::new(id, Some(&cfg))?);
-        Ok(Self {
-            instance,
-            cfg,
-            pid: OnceLock::default(),
-            state: Arc::new(RwLock::new(TaskState::Created)),
-        })
+        Ok(Self::with_instance(instance, cfg))
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     pub fn new_base(id: impl AsRef<str>, cfg: InstanceConfig<T::Engine>) -> Result<Self> {
         let id = id.as_ref().to_string();
         let instance = InstanceOption::Nop(Nop::new(id, None)?);
-        Ok(Self {
+        Ok(Self::with_instance(instance, cfg))
+    }
+
+    /// Re-attaches to the running process `pid` of the instance `id`, started by a previous shim
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn recover(
+        id: impl AsRef<str>,
+        cfg: InstanceConfig<T::Engine>,
+        pid: u32,
+        paused: bool,
+    ) -> Result<Self> {
+        let id = id.as_ref().to_string();
+        let instance = InstanceOption::Instance(T::recover(id, pid, &cfg)?);
+        let data = Self::with_instance(instance, cfg);
+        *data.pid.write().unwrap() = Some(pid);
+        *data.state.write().unwrap() = match paused {
+            true => TaskState::Paused,
+            false => TaskState::Started,
+        };
+        Ok(data)
+    }
+
+    /// Restores the instance `id` from the checkpoint in the `checkpoint` directory.
+    /// The task is created, and starting it returns the pid of the restored process.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn restore(
+        id: impl AsRef<str>,
+        cfg: InstanceConfig<T::Engine>,
+        checkpoint: &Path,
+    ) -> Result<Self> {
+        let id = id.as_ref().to_string();
+        let (instance, pid) = T::restore(id, checkpoint, &cfg)?;
+        let mut data = Self::with_instance(InstanceOption::Instance(instance), cfg);
+        data.restored_pid = Some(pid);
+        Ok(data)
+    }
+
+    /// Returns the task whose process `pid` exited with `exit` while its shim was down
+    pub fn exited(cfg: InstanceConfig<T::Engine>, pid: u32, exit: (u32, DateTime<Utc>)) -> Self {
+        let data = Self::with_instance(InstanceOption::Nop(Nop::exited(exit)), cfg);
+        *data.pid.write().unwrap() = Some(pid);
+        *data.state.write().unwrap() = TaskState::Exited;
+        data
+    }
+
+    fn with_instance(instance: InstanceOption<T>, cfg: InstanceConfig<T::Engine>) -> Self {
+        Self {
             instance,
             cfg,
-            pid: OnceLock::default(),
+            pid: RwLock::default(),
             state: Arc::new(RwLock::new(TaskState::Created)),
-        })
+            execs: RwLock::default(),
+            shutdown_grace_period: None,
+            escalating: AtomicBool::new(false),
+            sandbox_id: None,
+            restored_pid: None,
+        }
+    }
+
+    /// Sets the time the instance, and its exec processes, have to exit after a SIGTERM before
+    /// they are killed. Without a grace period, a SIGTERM is never escalated.
+    pub fn with_shutdown_grace_period(mut self, grace_period: Option<Duration>) -> Self {
+        self.shutdown_grace_period = grace_period;
+        self
+    }
+
+    pub fn shutdown_grace_period(&self) -> Option<Duration> {
+        self.shutdown_grace_period
+    }
+
+    /// Returns the grace period to escalate a SIGTERM to a SIGKILL after, to the first caller
+    /// only, so that the process has a single waiter however many SIGTERMs it gets.
+    pub fn start_escalation(&self) -> Option<Duration> {
+        let grace_period = self.shutdown_grace_period?;
+        let started = self.escalating.swap(true, Ordering::SeqCst);
+        (!started).then_some(grace_period)
+    }
+
+    /// Sets the id of the pod sandbox the instance is part of
+    pub fn with_sandbox_id(mut self, sandbox_id: Option<String>) -> Self {
+        self.sandbox_id = sandbox_id;
+        self
+    }
+
+    pub fn sandbox_id(&self) -> Option<&str> {
+        self.sandbox_id.as_deref()
+    }
+
+    /// Creates a new exec process inside this running instance
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn exec(
+        &self,
+        exec_id: impl AsRef<str>,
+        process: &Process,
+        cfg: InstanceConfig<T::Engine>,
+    ) -> Result<()> {
+        let exec_id = exec_id.as_ref().to_string();
+        let s = self.state.read().unwrap();
+        if !matches!(*s, TaskState::Started) {
+            return Err(Error::FailedPrecondition(format!(
+                "cannot exec in a task in the {:?} state",
+                *s
+            )));
+        }
+
+        let mut execs = self.execs.write().unwrap();
+        if execs.contains_key(&exec_id) {
+            return Err(Error::AlreadyExists(exec_id));
+        }
+
+        let instance = self.instance.exec_process(exec_id.clone(), process, &cfg)?;
+        let exec = Self::with_instance(instance, cfg)
+            .with_shutdown_grace_period(self.shutdown_grace_period);
+        execs.insert(exec_id, Arc::new(exec));
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn get_exec(&self, exec_id: &str) -> Result<Arc<Self>> {
+        let exec = self.execs.read().unwrap().get(exec_id).cloned();
+        exec.ok_or_else(|| Error::NotFound(exec_id.to_string()))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn remove_exec(&self, exec_id: &str) {
+        self.execs.write().unwrap().remove(exec_id);
+    }
+
+    /// Returns the exec processes of the instance, by exec id
+    pub fn execs(&self) -> Vec<(String, Arc<Self>)> {
+        let execs = self.execs.read().unwrap();
+        execs
+            .iter()
+            .map(|(id, e)| (id.clone(), e.clone()))
+            .collect()
+    }
+
+    /// Returns the status of the task, and its exit code and time once it has exited
+    pub fn status(&self) -> (Status, Option<(u32, DateTime<Utc>)>) {
+        let exit = self.wait_timeout(Duration::ZERO);
+        let status = if self.pid().is_none() {
+            Status::CREATED
+        } else if exit.is_none() && self.is_paused() {
+            Status::PAUSED
+        } else if exit.is_none() {
+            Status::RUNNING
+        } else {
+            Status::STOPPED
+        };
+        (status, exit)
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     pub fn pid(&self) -> Option<u32> {
-        self.pid.get().copied()
+        *self.pid.read().unwrap()
+    }
+
+    /// Sets the pid of the process the instance runs after it started a new one, e.g., when its
+    /// module was reloaded
+    pub fn set_pid(&self, pid: u32) {
+        *self.pid.write().unwrap() = Some(pid);
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
@@ -55,13 +212,16 @@ impl<T: Instance> InstanceData<T> {
         let mut s = self.state.write().unwrap();
         s.start()?;
 
-        let res = self.instance.start();
+        let res = match self.restored_pid {
+            Some(pid) => Ok(pid),
+            None => self.instance.start(),
+        };
 
         // These state transitions are always `Ok(())` because
         // we hold the lock since `s.start()`
         let _ = match res {
             Ok(pid) => {
-                let _ = self.pid.set(pid);
+                *self.pid.write().unwrap() = Some(pid);
                 s.started()
             }
             Err(_) => s.stop(),
@@ -78,6 +238,67 @@ impl<T: Instance> InstanceData<T> {
         self.instance.kill(signal)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn pause(&self) -> Result<()> {
+        let mut s = self.state.write().unwrap();
+        s.pause()?;
+
+        let res = self.instance.pause();
+
+        if res.is_err() {
+            // Always `Ok(())` because we hold the lock since `s.pause()`
+            let _ = s.resume();
+        }
+
+        res
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn resume(&self) -> Result<()> {
+        let mut s = self.state.write().unwrap();
+        s.resume()?;
+
+        let res = self.instance.resume();
+
+        if res.is_err() {
+            // Always `Ok(())` because we hold the lock since `s.resume()`
+            let _ = s.pause();
+        }
+
+        res
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn is_paused(&self) -> bool {
+        matches!(*self.state.read().unwrap(), TaskState::Paused)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn checkpoint(&self, path: &Path) -> Result<()> {
+        let mut s = self.state.write().unwrap();
+        s.checkpoint()?;
+
+        self.instance.checkpoint(path)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn resize_pty(&self, width: u32, height: u32) -> Result<()> {
+        self.instance.resize_pty(width, height)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn close_stdin(&self) -> Result<()> {
+        self.instance.close_stdin()
+    }
+
+    pub fn take_failure(&self) -> Option<GuestFailure> {
+        self.instance.take_failure()
+    }
+
+    pub fn pool_metrics(&self) -> Option<PoolMetrics> {
+        self.instance.pool_metrics()
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     pub fn delete(&self) -> Result<()> {
         let mut s = self.state.write().unwrap();
@@ -1,7 +1,11 @@
+use std::path::Path;
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
+use containerd_shim::Error as ShimError;
+use oci_spec::runtime::Process;
 
+use crate::container::{GuestFailure, PoolMetrics};
 use crate::sandbox::instance::Nop;
 use crate::sandbox::{Instance, InstanceConfig, Result};
 
@@ -10,6 +14,24 @@ pub(super) enum InstanceOption<I: Instance> {
     Nop(Nop),
 }
 
+impl<I: Instance> InstanceOption<I> {
+    /// Creates an exec process inside the wrapped instance
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn exec_process(
+        &self,
+        exec_id: String,
+        process: &Process,
+        cfg: &InstanceConfig<I::Engine>,
+    ) -> Result<Self> {
+        match self {
+            Self::Instance(i) => Ok(Self::Instance(i.exec(exec_id, process, cfg)?)),
+            Self::Nop(_) => {
+                Err(ShimError::Unimplemented("exec is not supported".to_string()).into())
+            }
+        }
+    }
+}
+
 impl<I: Instance> Instance for InstanceOption<I> {
     type Engine = ();
 
@@ -43,6 +65,60 @@ impl<I: Instance> Instance for InstanceOption<I> {
         }
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn pause(&self) -> Result<()> {
+        match self {
+            Self::Instance(i) => i.pause(),
+            Self::Nop(i) => i.pause(),
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn resume(&self) -> Result<()> {
+        match self {
+            Self::Instance(i) => i.resume(),
+            Self::Nop(i) => i.resume(),
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        match self {
+            Self::Instance(i) => i.checkpoint(path),
+            Self::Nop(i) => i.checkpoint(path),
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn resize_pty(&self, width: u32, height: u32) -> Result<()> {
+        match self {
+            Self::Instance(i) => i.resize_pty(width, height),
+            Self::Nop(i) => i.resize_pty(width, height),
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn close_stdin(&self) -> Result<()> {
+        match self {
+            Self::Instance(i) => i.close_stdin(),
+            Self::Nop(i) => i.close_stdin(),
+        }
+    }
+
+    fn take_failure(&self) -> Option<GuestFailure> {
+        match self {
+            Self::Instance(i) => i.take_failure(),
+            Self::Nop(i) => i.take_failure(),
+        }
+    }
+
+    fn pool_metrics(&self) -> Option<PoolMetrics> {
+        match self {
+            Self::Instance(i) => i.pool_metrics(),
+            Self::Nop(i) => i.pool_metrics(),
+        }
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip(self, t), level = "Info"))]
     fn wait_timeout(&self, t: impl Into<Option<Duration>>) -> Option<(u32, DateTime<Utc>)> {
         match self {
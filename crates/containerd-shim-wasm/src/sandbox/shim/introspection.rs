@@ -0,0 +1,266 @@
+//! Introspection endpoint of the shim.
+//!
+//! With `RUNWASI_INTROSPECTION_DIR` in the environment of the shim, the long running shim process
+//! serves HTTP on the `<dir>/<namespace>/<id>.sock` unix socket, so that node agents can debug a
+//! stuck shim without attaching a debugger, e.g.,
+//! `curl --unix-socket /run/runwasi/k8s.io/<id>.sock http://localhost/state`:
+//!  - `/health`: `{"status":"ok"}`, or a `503` if the task service has been busy for a second.
+//!  - `/state`: the health, the runtime and version of the shim, the stats of the
+//!    precompilation cache, and the state of each container and exec process, with the pod
+//!    sandbox of the containers of Kubernetes pods.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLockReadGuard, TryLockError};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::sandbox::containerd::{precompile_cache_stats, CacheStats};
+use crate::sandbox::shim::instance_data::InstanceData;
+use crate::sandbox::shim::local::LocalInstances;
+use crate::sandbox::{node_config, Instance};
+
+const INTROSPECTION_DIR_ENV: &str = "RUNWASI_INTROSPECTION_DIR";
+
+// Time the task service can hold the instances before the shim is reported unhealthy
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(1);
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+#[derive(Clone, Debug, Serialize)]
+struct Runtime {
+    name: String,
+    version: String,
+    revision: Option<String>,
+}
+
+/// Records the runtime and version of the shim, as printed by `--version`.
+pub(crate) fn set_runtime(name: &str, version: &str, revision: Option<&str>) {
+    let _ = RUNTIME.set(Runtime {
+        name: name.to_string(),
+        version: version.to_string(),
+        revision: revision.map(str::to_string),
+    });
+}
+
+/// Returns the socket of the shim, if the endpoint is enabled.
+pub(super) fn socket_path(namespace: &str, id: &str) -> Option<PathBuf> {
+    let dir = node_config::var_os(INTROSPECTION_DIR_ENV).filter(|dir| !dir.is_empty())?;
+    Some(
+        PathBuf::from(dir)
+            .join(namespace)
+            .join(format!("{id}.sock")),
+    )
+}
+
+/// Starts serving the endpoint on `path` from a background thread.
+pub(super) fn serve<T: Instance + Send + Sync>(
+    path: PathBuf,
+    instances: LocalInstances<T>,
+) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // the socket of a previous shim with the same id
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind introspection endpoint to {path:?}"))?;
+    log::info!("serving introspection endpoint on {path:?}");
+
+    let started = Instant::now();
+    thread::Builder::new()
+        .name("introspection".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(err) = handle_request(stream, &instances, started) {
+                    log::debug!("failed to serve introspection request: {err}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn handle_request<T: Instance + Send + Sync>(
+    mut stream: UnixStream,
+    instances: &LocalInstances<T>,
+    started: Instant,
+) -> anyhow::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/health") => match read(instances) {
+            Some(_) => ("200 OK", json!({ "status": "ok" })),
+            None => ("503 Service Unavailable", json!({ "status": "busy" })),
+        },
+        Some("/state") | Some("/") => match read(instances) {
+            Some(instances) => ("200 OK", state(&instances, started)?),
+            None => ("503 Service Unavailable", json!({ "status": "busy" })),
+        },
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    };
+
+    let body = serde_json::to_vec(&body)?;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
+
+/// Returns `true` unless the task service holds the instances for longer than `HEALTH_TIMEOUT`.
+pub(super) fn healthy<T: Instance + Send + Sync>(instances: &LocalInstances<T>) -> bool {
+    read(instances).is_some()
+}
+
+type Instances<T> = HashMap<String, Arc<InstanceData<T>>>;
+
+/// Reads the instances, unless the task service holds them for longer than `HEALTH_TIMEOUT`.
+fn read<T: Instance + Send + Sync>(
+    instances: &LocalInstances<T>,
+) -> Option<RwLockReadGuard<'_, Instances<T>>> {
+    let deadline = Instant::now() + HEALTH_TIMEOUT;
+    loop {
+        match instances.try_read() {
+            Ok(instances) => return Some(instances),
+            Err(TryLockError::Poisoned(err)) => return Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(TryLockError::WouldBlock) => return None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ShimState {
+    status: &'static str,
+    pid: u32,
+    uptime_seconds: u64,
+    runtime: Option<Runtime>,
+    precompile_cache: CacheStats,
+    containers: Vec<ProcessState>,
+}
+
+#[derive(Serialize)]
+struct ProcessState {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sandbox_id: Option<String>,
+    status: String,
+    pid: Option<u32>,
+    bundle: PathBuf,
+    exit_status: Option<u32>,
+    exited_at: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    execs: Vec<ProcessState>,
+}
+
+impl ProcessState {
+    fn new<T: Instance>(id: String, instance: &InstanceData<T>) -> Self {
+        let (status, exit) = instance.status();
+        let (exit_status, exited_at) = exit.unzip();
+        let mut execs: Vec<_> = instance
+            .execs()
+            .into_iter()
+            .map(|(id, exec)| Self::new(id, &exec))
+            .collect();
+        execs.sort_by(|a, b| a.id.cmp(&b.id));
+        Self {
+            id,
+            sandbox_id: instance.sandbox_id().map(str::to_string),
+            status: format!("{status:?}").to_lowercase(),
+            pid: instance.pid(),
+            bundle: instance.config().get_bundle().to_path_buf(),
+            exit_status,
+            exited_at: exited_at.map(|time| time.to_rfc3339()),
+            execs,
+        }
+    }
+}
+
+fn state<T: Instance>(
+    instances: &Instances<T>,
+    started: Instant,
+) -> anyhow::Result<serde_json::Value> {
+    let mut containers: Vec<_> = instances
+        .iter()
+        .map(|(id, instance)| ProcessState::new(id.clone(), instance))
+        .collect();
+    containers.sort_by(|a, b| a.id.cmp(&b.id));
+    let state = ShimState {
+        status: "ok",
+        pid: std::process::id(),
+        uptime_seconds: started.elapsed().as_secs(),
+        runtime: RUNTIME.get().cloned(),
+        precompile_cache: precompile_cache_stats(),
+        containers,
+    };
+    Ok(serde_json::to_value(state)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::sandbox::instance::Nop;
+    use crate::sandbox::InstanceConfig;
+
+    fn get(path: &std::path::Path, target: &str) -> anyhow::Result<(String, serde_json::Value)> {
+        let mut stream = UnixStream::connect(path)?;
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .context("invalid response")?;
+        let status = head.lines().next().unwrap_or_default().to_string();
+        Ok((status, serde_json::from_str(body)?))
+    }
+
+    #[test]
+    fn test_introspection() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("ns").join("shim.sock");
+        let instances = LocalInstances::<Nop>::default();
+        let cfg = InstanceConfig::new((), "ns", "/run/containerd/containerd.sock");
+        instances.write().unwrap().insert(
+            "sandbox".to_string(),
+            Arc::new(InstanceData::new_base("sandbox", cfg)?),
+        );
+        serve(path.clone(), instances.clone())?;
+
+        let (status, health) = get(&path, "/health")?;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(health, json!({ "status": "ok" }));
+
+        let (_, state) = get(&path, "/state")?;
+        assert_eq!(state["status"], "ok");
+        assert_eq!(state["pid"], std::process::id());
+        assert_eq!(state["containers"][0]["id"], "sandbox");
+        assert_eq!(state["containers"][0]["status"], "created");
+        assert!(state["precompile_cache"]["hits"].is_u64());
+
+        // a task service stuck with the instances makes the shim unhealthy
+        let guard = instances.write().unwrap();
+        let (status, health) = get(&path, "/health")?;
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+        assert_eq!(health["status"], "busy");
+        drop(guard);
+
+        let (status, _) = get(&path, "/containers")?;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Logging to the systemd journal, with the [native protocol] of journald.
+//!
+//! Each record is sent as a datagram to the socket of journald, with its `MESSAGE`, `PRIORITY`,
+//! `TARGET`, source location, and the fields of the shim in upper case, e.g., `CONTAINER_ID`
+//! and `POD`, e.g., `journalctl SYSLOG_IDENTIFIER=containerd-shim-wasmtime-v1 POD=hello`.
+//!
+//! [native protocol]: https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+use log::{Level, Record};
+
+use crate::sandbox::node_config;
+
+const JOURNALD_ENV: &str = "RUNWASI_LOG_JOURNALD";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Returns `true` if the logs are sent to the journal, from the environment of the shim.
+pub(crate) fn enabled() -> bool {
+    node_config::var(JOURNALD_ENV)
+        .is_ok_and(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true"))
+}
+
+pub(crate) struct Journald {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl Journald {
+    pub(crate) fn connect() -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        let identifier = std::env::args_os()
+            .next()
+            .as_deref()
+            .map(std::path::Path::new)
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "containerd-shim-wasm".to_string());
+        Ok(Self { socket, identifier })
+    }
+
+    /// Sends a record to the journal, dropping it if the journal doesn't accept it.
+    pub(crate) fn send(&self, record: &Record, fields: &[(&str, String)]) {
+        let _ = self.socket.send(&encode(record, &self.identifier, fields));
+    }
+}
+
+/// The syslog priority of a level
+fn priority(level: Level) -> &'static str {
+    match level {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    }
+}
+
+fn encode(record: &Record, identifier: &str, fields: &[(&str, String)]) -> Vec<u8> {
+    let mut entry = vec![];
+    let mut field = |name: &str, value: &str| {
+        entry.extend_from_slice(name.as_bytes());
+        // values with a newline are sent with their size rather than as `NAME=value`
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    };
+
+    field("MESSAGE", &record.args().to_string());
+    field("PRIORITY", priority(record.level()));
+    field("SYSLOG_IDENTIFIER", identifier);
+    field("TARGET", record.target());
+    if let Some(file) = record.file() {
+        field("CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        field("CODE_LINE", &line.to_string());
+    }
+    for (name, value) in fields {
+        field(&name.to_uppercase(), value);
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("shim")
+            .args(format_args!("task exited\nwith 1"))
+            .build();
+        let entry = encode(&record, "shim-v1", &[("container_id", "abc".to_string())]);
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&18u64.to_le_bytes());
+        expected.extend_from_slice(b"task exited\nwith 1\n");
+        expected.extend_from_slice(b"PRIORITY=4\nSYSLOG_IDENTIFIER=shim-v1\nTARGET=shim\n");
+        expected.extend_from_slice(b"CONTAINER_ID=abc\n");
+        assert_eq!(entry, expected);
+    }
+}
@@ -1,6 +1,5 @@
 use std::collections::HashMap;
 use std::fs::create_dir_all;
-use std::ops::Not;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
 use std::thread;
@@ -8,38 +7,61 @@ use std::time::Duration;
 
 use anyhow::Context as AnyhowContext;
 use containerd_shim::api::{
-    ConnectRequest, ConnectResponse, CreateTaskRequest, CreateTaskResponse, DeleteRequest, Empty,
-    KillRequest, ShutdownRequest, StartRequest, StartResponse, StateRequest, StateResponse,
-    StatsRequest, StatsResponse, WaitRequest, WaitResponse,
+    CheckpointTaskRequest, CloseIORequest, ConnectRequest, ConnectResponse, CreateTaskRequest,
+    CreateTaskResponse, DeleteRequest, Empty, ExecProcessRequest, KillRequest, PauseRequest,
+    ResizePtyRequest, ResumeRequest, ShutdownRequest, StartRequest, StartResponse, StateRequest,
+    StateResponse, StatsRequest, StatsResponse, WaitRequest, WaitResponse,
 };
 use containerd_shim::error::Error as ShimError;
-use containerd_shim::protos::events::task::{TaskCreate, TaskDelete, TaskExit, TaskIO, TaskStart};
+use containerd_shim::protos::events::task::{
+    TaskCheckpointed, TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskExit, TaskIO,
+    TaskOOM, TaskPaused, TaskResumed, TaskStart,
+};
 use containerd_shim::protos::shim::shim_ttrpc::Task;
-use containerd_shim::protos::types::task::Status;
 use containerd_shim::publisher::RemotePublisher;
 use containerd_shim::util::IntoOption;
 use containerd_shim::{DeleteResponse, ExitSignal, TtrpcContext, TtrpcResult};
 use log::debug;
-use oci_spec::runtime::Spec;
-
-use crate::sandbox::instance::{Instance, InstanceConfig};
+use oci_spec::runtime::{Process, Spec};
+use protobuf::well_known_types::any::Any;
+
+use crate::container::{format_backtrace, GuestFailure};
+use crate::sandbox::instance::{CompileEvent, Instance, InstanceConfig};
+use crate::sandbox::instance_utils::Options;
+use crate::sandbox::shim::controller::PodSandbox;
+use crate::sandbox::shim::drain::Drain;
 use crate::sandbox::shim::events::{EventSender, RemoteEventSender, ToTimestamp};
 use crate::sandbox::shim::instance_data::InstanceData;
+use crate::sandbox::shim::recovery::{self, ExitRecord, TaskRecord};
 use crate::sandbox::{oci, Error, Result, SandboxService};
+use crate::services::events::{
+    TaskCapabilities, TaskCompileFinished, TaskCompileStarted, TaskCoreDumped, TaskReloaded,
+    TaskTrapped,
+};
 use crate::sys::metrics::get_metrics;
+use crate::sys::signals::{SIGKILL, SIGTERM};
 
 #[cfg(test)]
 mod tests;
 
-type LocalInstances<T> = RwLock<HashMap<String, Arc<InstanceData<T>>>>;
+pub(super) type LocalInstances<T> = Arc<RwLock<HashMap<String, Arc<InstanceData<T>>>>>;
+
+/// Annotation with the time, in seconds, a task has to exit after a SIGTERM before it is killed.
+/// Without it, a SIGTERM is never escalated to a SIGKILL.
+const SHUTDOWN_GRACE_PERIOD_ANNOTATION: &str = "runwasi.io/shutdown-grace-seconds";
+
+const CRI_SANDBOX_ID_ANNOTATION: &str = "io.kubernetes.cri.sandbox-id";
+const CRI_CONTAINER_TYPE_ANNOTATION: &str = "io.kubernetes.cri.container-type";
 
 /// Local implements the Task service for a containerd shim.
 /// It defers all task operations to the `Instance` implementation.
 pub struct Local<T: Instance + Send + Sync, E: EventSender = RemoteEventSender> {
     pub engine: T::Engine,
     pub(super) instances: LocalInstances<T>,
+    pub(super) drain: Drain<T>,
+    pub(super) pod_sandbox: Arc<PodSandbox>,
     events: E,
-    exit: Arc<ExitSignal>,
+    pub(super) exit: Arc<ExitSignal>,
     namespace: String,
     containerd_address: String,
 }
@@ -54,12 +76,15 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         namespace: impl AsRef<str>,
         containerd_address: impl AsRef<str>,
     ) -> Self {
-        let instances = RwLock::default();
+        let instances = LocalInstances::default();
+        let drain = Drain::new(instances.clone(), exit.clone());
         let namespace = namespace.as_ref().to_string();
         let containerd_address = containerd_address.as_ref().to_string();
         Self {
             engine,
             instances,
+            drain,
+            pod_sandbox: Arc::default(),
             events,
             exit,
             namespace,
@@ -73,6 +98,17 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         instance.ok_or_else(|| Error::NotFound(id.to_string()))
     }
 
+    /// Returns the instance with `id`, or its exec process if `exec_id` is not empty.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub(super) fn get_process(&self, id: &str, exec_id: &str) -> Result<Arc<InstanceData<T>>> {
+        let instance = self.get_instance(id)?;
+        if exec_id.is_empty() {
+            Ok(instance)
+        } else {
+            instance.get_exec(exec_id)
+        }
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn has_instance(&self, id: &str) -> bool {
         self.instances.read().unwrap().contains_key(id)
@@ -91,26 +127,233 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             &self.containerd_address,
         )
     }
+
+    /// Re-attaches to the task of `bundle` left running by a previous shim, if any, e.g., when
+    /// the shim is started again after it crashed or drained.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub(super) fn recover(&self, bundle: &Path) -> Result<()> {
+        let Some(record) = TaskRecord::read(bundle)? else {
+            return Ok(());
+        };
+        let id = record.id.clone();
+        log::info!("recovering task {id} running with pid {}", record.pid);
+
+        let spec = Spec::load(bundle.join("config.json"))
+            .map_err(|err| Error::InvalidArgument(format!("could not load runtime spec: {err}")))?;
+        let mut cfg = self.instance_config();
+        cfg.set_bundle(bundle)
+            .set_stdin(&record.stdin)
+            .set_stdout(&record.stdout)
+            .set_stderr(&record.stderr)
+            .set_terminal(record.terminal);
+
+        // a task that exited while no shim was running returns its persisted exit status
+        let exit = match ExitRecord::read(bundle)? {
+            Some(exit) => Some((exit.pid, exit.exit()?)),
+            None => None,
+        };
+        let paused = record.status == "paused";
+        let instance = match (exit, &record.checkpoint) {
+            (Some((pid, exit)), _) => Ok(InstanceData::exited(cfg, pid, exit)),
+            (None, None) => InstanceData::recover(&id, cfg, record.pid, paused),
+            // a task checkpointed when its shim drained is restored if its process is gone
+            (None, Some(checkpoint)) => InstanceData::recover(&id, cfg.clone(), record.pid, paused)
+                .or_else(|err| {
+                    log::info!(
+                        "restoring task {id} from {checkpoint:?}, as it can't be recovered: {err}"
+                    );
+                    let instance = InstanceData::restore(&id, cfg, checkpoint)?;
+                    instance.start()?;
+                    Ok(instance)
+                }),
+        };
+        let instance = match instance {
+            Ok(instance) => instance
+                .with_shutdown_grace_period(shutdown_grace_period(&spec)?)
+                .with_sandbox_id(cri_sandbox_id(&spec)),
+            Err(err) => {
+                // the task is gone, or can't be re-attached to
+                TaskRecord::remove(bundle);
+                return Err(err);
+            }
+        };
+        // the exec processes are killed with the container when the task is deleted
+        for (exec_id, pid) in &record.execs {
+            log::warn!("exec process {exec_id} of task {id}, with pid {pid}, is not recovered");
+        }
+
+        let instance = Arc::new(instance);
+        self.instances
+            .write()
+            .unwrap()
+            .insert(id.clone(), instance.clone());
+        if exit.is_none() {
+            recovery::persist(&id, &instance);
+        }
+        let pid = instance.pid().unwrap_or(record.pid);
+        self.wait_exit(&id, "", instance, pid)
+    }
+
+    /// Publishes the exit of the task `container_id`, or of its exec process `exec_id` if it is
+    /// not empty, once it exits.
+    fn wait_exit(
+        &self,
+        container_id: &str,
+        exec_id: &str,
+        i: Arc<InstanceData<T>>,
+        pid: u32,
+    ) -> Result<()> {
+        let events = self.events.clone();
+        let instances = self.instances.clone();
+        let container_id = container_id.to_string();
+        let exec_id = exec_id.to_string();
+        let id = match exec_id.as_str() {
+            "" => container_id.clone(),
+            exec_id => exec_id.to_string(),
+        };
+
+        thread::Builder::new()
+            .name(format!("{id}-wait"))
+            .spawn(move || {
+                let (exit_code, timestamp) = i.wait();
+                // the exit of the task is persisted first, so that it isn't lost if the shim
+                // crashes before publishing it
+                if exec_id.is_empty() {
+                    let bundle = i.config().get_bundle();
+                    recovery::persist_exit(bundle, i.pid().unwrap_or(pid), exit_code, timestamp);
+                } else if let Some(task) = instances.read().unwrap().get(&container_id) {
+                    recovery::persist(&container_id, task);
+                }
+                match i.take_failure() {
+                    Some(GuestFailure::Trap {
+                        code,
+                        message,
+                        backtrace,
+                    }) => {
+                        log::info!("task {id} trapped with {code}: {message}");
+                        let backtrace = match backtrace.is_empty() {
+                            true => String::new(),
+                            false => format_backtrace(&backtrace),
+                        };
+                        events.send(TaskTrapped {
+                            container_id: container_id.clone(),
+                            id: id.clone(),
+                            code,
+                            message,
+                            backtrace,
+                            ..Default::default()
+                        });
+                    }
+                    Some(GuestFailure::OutOfMemory { message }) => {
+                        log::info!("task {id} ran out of memory: {message}");
+                        events.send(TaskOOM {
+                            container_id: container_id.clone(),
+                            ..Default::default()
+                        });
+                    }
+                    None => {}
+                }
+                if let Some(coredump) = oci::take_coredump(i.config().get_bundle()) {
+                    log::info!("task {id} trapped, core dump written to {coredump:?}");
+                    events.send(TaskCoreDumped {
+                        container_id: container_id.clone(),
+                        id: id.clone(),
+                        path: coredump.to_string_lossy().into_owned(),
+                        ..Default::default()
+                    });
+                }
+                events.send(TaskExit {
+                    container_id,
+                    exit_status: exit_code,
+                    exited_at: Some(timestamp.to_timestamp()).into(),
+                    pid: i.pid().unwrap_or(pid),
+                    id,
+                    ..Default::default()
+                });
+            })
+            .context("could not spawn thread to wait exit")
+            .map_err(Error::from)?;
+        Ok(())
+    }
 }
 
+/// Returns the id of the pod sandbox of a cri container.
+/// The containers of a pod are grouped in the same shim, see `start_shim`.
 #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
-fn is_cri_container(spec: &Spec) -> bool {
+fn cri_sandbox_id(spec: &Spec) -> Option<String> {
     spec.annotations()
         .as_ref()
-        .is_some_and(|annotations| annotations.contains_key("io.kubernetes.cri.sandbox-id"))
+        .and_then(|annotations| annotations.get(CRI_SANDBOX_ID_ANNOTATION))
+        .cloned()
+}
+
+/// Returns true if a cri container is the "pause" container of its pod.
+fn is_cri_sandbox(spec: &Spec, is_first: bool) -> bool {
+    match spec
+        .annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(CRI_CONTAINER_TYPE_ANNOTATION))
+    {
+        Some(container_type) => container_type == "sandbox",
+        // without the type of the container, the sandbox is the first container of the pod
+        None => is_first,
+    }
+}
+
+fn shutdown_grace_period(spec: &Spec) -> Result<Option<Duration>> {
+    let Some(seconds) = spec
+        .annotations()
+        .as_ref()
+        .and_then(|annotations| annotations.get(SHUTDOWN_GRACE_PERIOD_ANNOTATION))
+    else {
+        return Ok(None);
+    };
+    seconds
+        .trim()
+        .parse()
+        .ok()
+        .filter(|s: &f64| s.is_finite() && *s >= 0.0)
+        .map(|s| Some(Duration::from_secs_f64(s)))
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "invalid value {seconds:?} for {SHUTDOWN_GRACE_PERIOD_ANNOTATION}"
+            ))
+        })
+}
+
+// Writes the options of the runtime in the configuration of containerd to the bundle, where the
+// instance reads them, and adds their default annotations to the spec.
+fn apply_runtime_options(bundle: &Path, spec: &mut Spec, options: &Any) -> Result<()> {
+    let Some(options) = Options::from_runtime_options(options)? else {
+        return Ok(());
+    };
+    options.write(bundle)?;
+    if options.annotations.is_empty() {
+        return Ok(());
+    }
+    let mut annotations = spec.annotations().clone().unwrap_or_default();
+    for (key, value) in options.annotations {
+        annotations.entry(key).or_insert(value);
+    }
+    spec.set_annotations(Some(annotations));
+    spec.save(bundle.join("config.json"))
+        .map_err(|err| Error::Others(format!("could not save runtime spec: {err}")))
 }
 
 // These are the same functions as in Task, but without the TtrcpContext, which is useful for testing
 impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn task_create(&self, req: CreateTaskRequest) -> Result<CreateTaskResponse> {
-        if !req.checkpoint().is_empty() || !req.parent_checkpoint().is_empty() {
-            return Err(ShimError::Unimplemented("checkpoint is not supported".to_string()).into());
+        if !req.parent_checkpoint().is_empty() {
+            return Err(ShimError::Unimplemented(
+                "restoring from a parent checkpoint is not supported".to_string(),
+            )
+            .into());
         }
 
-        if req.terminal {
-            return Err(Error::InvalidArgument(
-                "terminal is not supported".to_string(),
+        if self.drain.is_draining() {
+            return Err(Error::FailedPrecondition(
+                "the shim is shutting down, it doesn't accept new tasks".to_string(),
             ));
         }
 
@@ -120,6 +363,10 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
         let mut spec = Spec::load(Path::new(&req.bundle).join("config.json"))
             .map_err(|err| Error::InvalidArgument(format!("could not load runtime spec: {err}")))?;
+        if let Some(options) = req.options.as_ref() {
+            apply_runtime_options(Path::new(&req.bundle), &mut spec, options)?;
+        }
+        let shutdown_grace_period = shutdown_grace_period(&spec)?;
 
         spec.canonicalize_rootfs(req.bundle()).map_err(|err| {
             ShimError::InvalidArgument(format!("could not canonicalize rootfs: {}", err))
@@ -152,16 +399,77 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         cfg.set_bundle(&req.bundle)
             .set_stdin(&req.stdin)
             .set_stdout(&req.stdout)
-            .set_stderr(&req.stderr);
+            .set_stderr(&req.stderr)
+            .set_terminal(req.terminal);
+
+        let events = self.events.clone();
+        let container_id = req.id().to_string();
+        cfg.set_compile_observer(move |event| match event {
+            CompileEvent::Started { runtime } => events.send(TaskCompileStarted {
+                container_id: container_id.clone(),
+                runtime,
+                ..Default::default()
+            }),
+            CompileEvent::Finished {
+                runtime,
+                duration,
+                error,
+            } => events.send(TaskCompileFinished {
+                container_id: container_id.clone(),
+                runtime,
+                duration_ms: duration.as_millis() as u64,
+                error: error.unwrap_or_default(),
+                ..Default::default()
+            }),
+        });
+        let events = self.events.clone();
+        let container_id = req.id().to_string();
+        cfg.set_capability_observer(move |report| {
+            events.send(TaskCapabilities {
+                container_id: container_id.clone(),
+                worlds: report.worlds.iter().cloned().collect(),
+                capabilities: report.capabilities.iter().cloned().collect(),
+                imports: report.imports.iter().cloned().collect(),
+                ..Default::default()
+            })
+        });
+        let events = self.events.clone();
+        let instances = self.instances.clone();
+        let container_id = req.id().to_string();
+        cfg.set_reload_observer(move |event| {
+            log::info!(
+                "task {container_id} reloaded image {} at {}",
+                event.image,
+                event.digest
+            );
+            // the task now runs the process of the new module
+            if let Some(instance) = instances.read().unwrap().get(&container_id) {
+                instance.set_pid(event.pid);
+                recovery::persist(&container_id, instance);
+            }
+            events.send(TaskReloaded {
+                container_id: container_id.clone(),
+                image: event.image.clone(),
+                digest: event.digest.clone(),
+                pid: event.pid,
+                ..Default::default()
+            })
+        });
 
         // Check if this is a cri container
-        let instance = if self.is_empty() && is_cri_container(&spec) {
+        let sandbox_id = cri_sandbox_id(&spec);
+        let instance = if sandbox_id.is_some() && is_cri_sandbox(&spec, self.is_empty()) {
             // If it is cri, then this is the "pause" container, which we don't need to deal with.
             // TODO: maybe we can just go ahead and execute the actual container with runc?
             InstanceData::new_base(req.id(), cfg)?
+        } else if !req.checkpoint().is_empty() {
+            // the process is restored now, and the task reports it once it is started
+            InstanceData::restore(req.id(), cfg, Path::new(req.checkpoint()))?
         } else {
             InstanceData::new_instance(req.id(), cfg)?
-        };
+        }
+        .with_shutdown_grace_period(shutdown_grace_period)
+        .with_sandbox_id(sandbox_id);
 
         self.instances
             .write()
@@ -196,38 +504,29 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn task_start(&self, req: StartRequest) -> Result<StartResponse> {
-        if req.exec_id().is_empty().not() {
-            return Err(ShimError::Unimplemented("exec is not supported".to_string()).into());
-        }
-
-        let i = self.get_instance(req.id())?;
+        let i = self.get_process(req.id(), req.exec_id())?;
         let pid = i.start()?;
 
-        self.events.send(TaskStart {
-            container_id: req.id().into(),
-            pid,
-            ..Default::default()
-        });
+        if req.exec_id().is_empty() {
+            #[cfg(feature = "opentelemetry")]
+            crate::sandbox::shim::record_container_start();
 
-        let events = self.events.clone();
+            self.events.send(TaskStart {
+                container_id: req.id().into(),
+                pid,
+                ..Default::default()
+            });
+        } else {
+            self.events.send(TaskExecStarted {
+                container_id: req.id().into(),
+                exec_id: req.exec_id().into(),
+                pid,
+                ..Default::default()
+            });
+        }
 
-        let id = req.id().to_string();
-
-        thread::Builder::new()
-            .name(format!("{id}-wait"))
-            .spawn(move || {
-                let (exit_code, timestamp) = i.wait();
-                events.send(TaskExit {
-                    container_id: id.clone(),
-                    exit_status: exit_code,
-                    exited_at: Some(timestamp.to_timestamp()).into(),
-                    pid,
-                    id,
-                    ..Default::default()
-                });
-            })
-            .context("could not spawn thread to wait exit")
-            .map_err(Error::from)?;
+        recovery::persist(req.id(), &*self.get_instance(req.id())?);
+        self.wait_exit(req.id(), req.exec_id(), i, pid)?;
 
         debug!("started: {:?}", req);
 
@@ -239,20 +538,121 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn task_kill(&self, req: KillRequest) -> Result<Empty> {
-        if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
+        let process = self.get_process(req.id(), req.exec_id())?;
+        process.kill(req.signal())?;
+
+        // give the process its grace period to exit, then kill it, with a single waiter
+        let escalation = match req.signal() == SIGTERM as u32 {
+            true => process.start_escalation(),
+            false => None,
+        };
+        if let Some(grace_period) = escalation {
+            let id = req.id().to_string();
+            thread::spawn(move || {
+                if process.wait_timeout(grace_period).is_none() {
+                    log::info!("task {id} did not exit within {grace_period:?}, killing it");
+                    if let Err(err) = process.kill(SIGKILL as u32) {
+                        log::error!("failed to kill task {id}: {err}");
+                    }
+                }
+            });
         }
-        self.get_instance(req.id())?.kill(req.signal())?;
         Ok(Empty::new())
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
-    fn task_delete(&self, req: DeleteRequest) -> Result<DeleteResponse> {
-        if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
+    fn task_exec(&self, req: ExecProcessRequest) -> Result<Empty> {
+        if self.drain.is_draining() {
+            return Err(Error::FailedPrecondition(
+                "the shim is shutting down, it doesn't accept new exec processes".to_string(),
+            ));
+        }
+        let spec = req
+            .spec
+            .as_ref()
+            .ok_or_else(|| Error::InvalidArgument("exec process spec is not set".to_string()))?;
+        let process: Process = serde_json::from_slice(&spec.value)?;
+
+        let i = self.get_instance(req.id())?;
+        let mut cfg = i.config().clone();
+        cfg.set_stdin(&req.stdin)
+            .set_stdout(&req.stdout)
+            .set_stderr(&req.stderr)
+            .set_terminal(req.terminal);
+
+        i.exec(req.exec_id(), &process, cfg)?;
+
+        self.events.send(TaskExecAdded {
+            container_id: req.id().into(),
+            exec_id: req.exec_id().into(),
+            ..Default::default()
+        });
+
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn task_pause(&self, req: PauseRequest) -> Result<Empty> {
+        let i = self.get_instance(req.id())?;
+        i.pause()?;
+        recovery::persist(req.id(), &i);
+        self.events.send(TaskPaused {
+            container_id: req.id().into(),
+            ..Default::default()
+        });
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn task_resume(&self, req: ResumeRequest) -> Result<Empty> {
+        let i = self.get_instance(req.id())?;
+        i.resume()?;
+        recovery::persist(req.id(), &i);
+        self.events.send(TaskResumed {
+            container_id: req.id().into(),
+            ..Default::default()
+        });
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn task_resize_pty(&self, req: ResizePtyRequest) -> Result<Empty> {
+        self.get_process(req.id(), req.exec_id())?
+            .resize_pty(req.width, req.height)?;
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn task_close_io(&self, req: CloseIORequest) -> Result<Empty> {
+        if req.stdin {
+            self.get_process(req.id(), req.exec_id())?.close_stdin()?;
+        }
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn task_checkpoint(&self, req: CheckpointTaskRequest) -> Result<Empty> {
+        if req.path().is_empty() {
+            return Err(Error::InvalidArgument(
+                "checkpoint path is not set".to_string(),
+            ));
         }
 
         let i = self.get_instance(req.id())?;
+        i.checkpoint(Path::new(req.path()))?;
+
+        self.events.send(TaskCheckpointed {
+            container_id: req.id().into(),
+            checkpoint: req.path().into(),
+            ..Default::default()
+        });
+
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn task_delete(&self, req: DeleteRequest) -> Result<DeleteResponse> {
+        let i = self.get_process(req.id(), req.exec_id())?;
 
         i.delete()?;
 
@@ -260,7 +660,22 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         let (exit_code, timestamp) = i.wait_timeout(Duration::ZERO).unzip();
         let timestamp = timestamp.map(ToTimestamp::to_timestamp);
 
+        if !req.exec_id().is_empty() {
+            let task = self.get_instance(req.id())?;
+            task.remove_exec(req.exec_id());
+            recovery::persist(req.id(), &task);
+
+            return Ok(DeleteResponse {
+                pid,
+                exit_status: exit_code.unwrap_or_default(),
+                exited_at: timestamp.into(),
+                ..Default::default()
+            });
+        }
+
         self.instances.write().unwrap().remove(req.id());
+        TaskRecord::remove(i.config().get_bundle());
+        ExitRecord::remove(i.config().get_bundle());
 
         self.events.send(TaskDelete {
             container_id: req.id().into(),
@@ -280,11 +695,7 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn task_wait(&self, req: WaitRequest) -> Result<WaitResponse> {
-        if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
-        }
-
-        let i = self.get_instance(req.id())?;
+        let i = self.get_process(req.id(), req.exec_id())?;
         let (exit_code, timestamp) = i.wait();
 
         debug!("wait finishes");
@@ -297,24 +708,15 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn task_state(&self, req: StateRequest) -> Result<StateResponse> {
-        if !req.exec_id().is_empty() {
-            return Err(Error::InvalidArgument("exec is not supported".to_string()));
-        }
-
-        let i = self.get_instance(req.id())?;
+        let i = self.get_process(req.id(), req.exec_id())?;
         let pid = i.pid();
-        let (exit_code, timestamp) = i.wait_timeout(Duration::ZERO).unzip();
+        let (status, exit) = i.status();
+        let (exit_code, timestamp) = exit.unzip();
         let timestamp = timestamp.map(ToTimestamp::to_timestamp);
 
-        let status = if pid.is_none() {
-            Status::CREATED
-        } else if exit_code.is_none() {
-            Status::RUNNING
-        } else {
-            Status::STOPPED
-        };
-
         Ok(StateResponse {
+            id: req.id().to_string(),
+            exec_id: req.exec_id().to_string(),
             bundle: i.config().get_bundle().to_string_lossy().to_string(),
             stdin: i.config().get_stdin().to_string_lossy().to_string(),
             stdout: i.config().get_stdout().to_string_lossy().to_string(),
@@ -334,7 +736,7 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             .pid()
             .ok_or_else(|| Error::InvalidArgument("task is not running".to_string()))?;
 
-        let metrics = get_metrics(pid)?;
+        let metrics = get_metrics(pid, i.pool_metrics())?;
 
         Ok(StatsResponse {
             stats: Some(metrics).into(),
@@ -378,6 +780,42 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
         Ok(self.task_kill(req)?)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn pause(&self, _: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        debug!("pause: {:?}", req);
+        Ok(self.task_pause(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn resume(&self, _: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        debug!("resume: {:?}", req);
+        Ok(self.task_resume(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn resize_pty(&self, _: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
+        debug!("resize_pty: {:?}", req);
+        Ok(self.task_resize_pty(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn close_io(&self, _: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        debug!("close_io: {:?}", req);
+        Ok(self.task_close_io(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn checkpoint(&self, _: &TtrpcContext, req: CheckpointTaskRequest) -> TtrpcResult<Empty> {
+        debug!("checkpoint: {:?}", req);
+        Ok(self.task_checkpoint(req)?)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn exec(&self, _: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
+        debug!("exec: {:?}", req);
+        Ok(self.task_exec(req)?)
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn delete(&self, _: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
         debug!("delete: {:?}", req);
@@ -412,7 +850,8 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn shutdown(&self, _: &TtrpcContext, _: ShutdownRequest) -> TtrpcResult<Empty> {
         debug!("shutdown");
-        if self.is_empty() {
+        // the shim of a pod sandbox exits when the sandbox is shut down
+        if self.is_empty() && !self.pod_sandbox.is_created() {
             self.exit.signal();
         }
         Ok(Empty::new())
@@ -11,6 +11,7 @@ use serde_json as json;
 use tempfile::tempdir;
 
 use super::*;
+use crate::container::BacktraceFrame;
 use crate::sandbox::instance::Nop;
 use crate::sandbox::shim::events::EventSender;
 use crate::sandbox::shim::instance_option::InstanceOption;
@@ -101,6 +102,641 @@ fn test_delete_after_create() {
         .unwrap();
 }
 
+#[test]
+fn test_pause_resume() -> Result<()> {
+    let dir = tempdir().unwrap();
+    let id = "test-pause-resume";
+    create_bundle(dir.path(), None).unwrap();
+
+    let (tx, _rx) = channel();
+    let local = Arc::new(Local::<Nop, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    let state = || {
+        local.task_state(StateRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })
+    };
+    let pause = || {
+        local.task_pause(PauseRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })
+    };
+    let resume = || {
+        local.task_resume(ResumeRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })
+    };
+
+    local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+
+    // a task can only be paused while running
+    match pause().unwrap_err() {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    local.task_start(StartRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(state()?.status(), Status::RUNNING);
+
+    pause()?;
+    assert_eq!(state()?.status(), Status::PAUSED);
+
+    match pause().unwrap_err() {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    resume()?;
+    assert_eq!(state()?.status(), Status::RUNNING);
+
+    match resume().unwrap_err() {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    pause()?;
+    local.task_kill(KillRequest {
+        id: id.to_string(),
+        signal: 9,
+        ..Default::default()
+    })?;
+    local.task_wait(WaitRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(state()?.status(), Status::STOPPED);
+
+    Ok(())
+}
+
+#[test]
+fn test_checkpoint_not_supported() {
+    let dir = tempdir().unwrap();
+    let id = "test-checkpoint-not-supported";
+    create_bundle(dir.path(), None).unwrap();
+
+    let (tx, _rx) = channel();
+    let local = Arc::new(Local::<Nop, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    local
+        .task_create(CreateTaskRequest {
+            id: id.to_string(),
+            bundle: dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+    let checkpoint = |path: &std::path::Path| {
+        local.task_checkpoint(CheckpointTaskRequest {
+            id: id.to_string(),
+            path: path.to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+    };
+
+    // a task can only be checkpointed while running
+    let res = checkpoint(dir.path());
+    assert!(matches!(res, Err(Error::FailedPrecondition(_))));
+
+    local
+        .task_start(StartRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+    let res = checkpoint(dir.path());
+    assert!(matches!(res, Err(Error::Shim(ShimError::Unimplemented(_)))));
+
+    local
+        .task_kill(KillRequest {
+            id: id.to_string(),
+            signal: 9,
+            ..Default::default()
+        })
+        .unwrap();
+    local
+        .task_wait(WaitRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_exec_not_supported() -> Result<()> {
+    let dir = tempdir().unwrap();
+    let id = "test-exec-not-supported";
+    create_bundle(dir.path(), None).unwrap();
+
+    let (tx, _rx) = channel();
+    let local = Arc::new(Local::<Nop, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    let exec = || {
+        let mut spec = protobuf::well_known_types::any::Any::new();
+        spec.value = json::to_vec(&Process::default()).unwrap();
+        local.task_exec(ExecProcessRequest {
+            id: id.to_string(),
+            exec_id: "exec".to_string(),
+            spec: Some(spec).into(),
+            ..Default::default()
+        })
+    };
+
+    local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+
+    // a task can only run exec processes while running
+    match exec().unwrap_err() {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    local.task_start(StartRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+
+    match exec().unwrap_err() {
+        Error::Shim(ShimError::Unimplemented(_)) => {}
+        e => return Err(e),
+    }
+
+    // the exec process was never added
+    match local
+        .task_state(StateRequest {
+            id: id.to_string(),
+            exec_id: "exec".to_string(),
+            ..Default::default()
+        })
+        .unwrap_err()
+    {
+        Error::NotFound(_) => {}
+        e => return Err(e),
+    }
+
+    local.task_kill(KillRequest {
+        id: id.to_string(),
+        signal: 9,
+        ..Default::default()
+    })?;
+    local.task_wait(WaitRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+// An instance that reports a precompilation when created
+struct CompilingInstance(Nop);
+
+impl Instance for CompilingInstance {
+    type Engine = ();
+
+    fn new(id: String, cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self> {
+        if let Some(observer) = cfg.and_then(|cfg| cfg.get_compile_observer()) {
+            let runtime = "test".to_string();
+            observer(CompileEvent::Started {
+                runtime: runtime.clone(),
+            });
+            observer(CompileEvent::Finished {
+                runtime,
+                duration: Duration::from_millis(42),
+                error: None,
+            });
+        }
+        Ok(Self(Nop::new(id, None)?))
+    }
+
+    fn start(&self) -> Result<u32> {
+        self.0.start()
+    }
+
+    fn kill(&self, signal: u32) -> Result<()> {
+        self.0.kill(signal)
+    }
+
+    fn delete(&self) -> Result<()> {
+        self.0.delete()
+    }
+
+    fn wait_timeout(
+        &self,
+        t: impl Into<Option<Duration>>,
+    ) -> Option<(u32, chrono::DateTime<chrono::Utc>)> {
+        self.0.wait_timeout(t)
+    }
+}
+
+#[test]
+fn test_compile_events() -> Result<()> {
+    let dir = tempdir().unwrap();
+    let id = "test-compile-events";
+    create_bundle(dir.path(), None).unwrap();
+
+    let (tx, rx) = channel();
+    let local = Arc::new(Local::<CompilingInstance, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+
+    let (topic, _) = rx.try_recv().unwrap();
+    assert_eq!(topic, "/tasks/compile-started");
+
+    let (topic, event) = rx.try_recv().unwrap();
+    assert_eq!(topic, "/tasks/compile-finished");
+    let event = event
+        .downcast_ref::<crate::services::events::TaskCompileFinished>()
+        .unwrap();
+    assert_eq!(event.container_id, id);
+    assert_eq!(event.runtime, "test");
+    assert_eq!(event.duration_ms, 42);
+    assert!(event.error.is_empty());
+
+    let (topic, _) = rx.try_recv().unwrap();
+    assert_eq!(topic, "/tasks/create");
+
+    Ok(())
+}
+
+// An instance that reports the capabilities of its module when created
+struct InspectingInstance(Nop);
+
+impl Instance for InspectingInstance {
+    type Engine = ();
+
+    fn new(id: String, cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self> {
+        if let Some(observer) = cfg.and_then(|cfg| cfg.get_capability_observer()) {
+            observer(&crate::container::CapabilityReport {
+                worlds: ["wasi:preview1".to_string()].into(),
+                capabilities: ["filesystem".to_string()].into(),
+                imports: ["wasi_snapshot_preview1".to_string()].into(),
+            });
+        }
+        Ok(Self(Nop::new(id, None)?))
+    }
+
+    fn start(&self) -> Result<u32> {
+        self.0.start()
+    }
+
+    fn kill(&self, signal: u32) -> Result<()> {
+        self.0.kill(signal)
+    }
+
+    fn delete(&self) -> Result<()> {
+        self.0.delete()
+    }
+
+    fn wait_timeout(
+        &self,
+        t: impl Into<Option<Duration>>,
+    ) -> Option<(u32, chrono::DateTime<chrono::Utc>)> {
+        self.0.wait_timeout(t)
+    }
+}
+
+#[test]
+fn test_capability_events() -> Result<()> {
+    let dir = tempdir().unwrap();
+    let id = "test-capability-events";
+    create_bundle(dir.path(), None).unwrap();
+
+    let (tx, rx) = channel();
+    let local = Arc::new(Local::<InspectingInstance, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+
+    let (topic, event) = rx.try_recv().unwrap();
+    assert_eq!(topic, "/tasks/capabilities");
+    let event = event
+        .downcast_ref::<crate::services::events::TaskCapabilities>()
+        .unwrap();
+    assert_eq!(event.container_id, id);
+    assert_eq!(event.worlds, ["wasi:preview1"]);
+    assert_eq!(event.capabilities, ["filesystem"]);
+    assert_eq!(event.imports, ["wasi_snapshot_preview1"]);
+
+    let (topic, _) = rx.try_recv().unwrap();
+    assert_eq!(topic, "/tasks/create");
+
+    Ok(())
+}
+
+// An instance that keeps running when it receives a SIGTERM
+struct IgnoreSigtermInstance(Nop);
+
+impl Instance for IgnoreSigtermInstance {
+    type Engine = ();
+
+    fn new(id: String, _cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self> {
+        Ok(Self(Nop::new(id, None)?))
+    }
+
+    fn start(&self) -> Result<u32> {
+        self.0.start()
+    }
+
+    fn kill(&self, signal: u32) -> Result<()> {
+        match signal as i32 {
+            SIGTERM => Ok(()),
+            _ => self.0.kill(signal),
+        }
+    }
+
+    fn delete(&self) -> Result<()> {
+        self.0.delete()
+    }
+
+    fn wait_timeout(
+        &self,
+        t: impl Into<Option<Duration>>,
+    ) -> Option<(u32, chrono::DateTime<chrono::Utc>)> {
+        self.0.wait_timeout(t)
+    }
+}
+
+#[test]
+fn test_kill_after_grace_period() -> Result<()> {
+    let (etx, _erx) = channel();
+    let local = Arc::new(Local::<IgnoreSigtermInstance, _>::new(
+        (),
+        etx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    let with_grace_period = |seconds: &str| {
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(HashMap::from([(
+            SHUTDOWN_GRACE_PERIOD_ANNOTATION.to_string(),
+            seconds.to_string(),
+        )])));
+        spec
+    };
+
+    let temp = tempdir().unwrap();
+    create_bundle(temp.path(), Some(with_grace_period("soon")))?;
+    match local
+        .task_create(CreateTaskRequest {
+            id: "invalid".to_string(),
+            bundle: temp.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap_err()
+    {
+        Error::InvalidArgument(_) => {}
+        e => return Err(e),
+    }
+
+    let temp = tempdir().unwrap();
+    create_bundle(temp.path(), Some(with_grace_period("0.1")))?;
+    local.task_create(CreateTaskRequest {
+        id: "test".to_string(),
+        bundle: temp.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+    local.task_start(StartRequest {
+        id: "test".to_string(),
+        ..Default::default()
+    })?;
+
+    local.task_kill(KillRequest {
+        id: "test".to_string(),
+        signal: SIGTERM as u32,
+        ..Default::default()
+    })?;
+
+    // the instance ignores the SIGTERM, and is killed once the grace period is over
+    let (tx, rx) = channel();
+    let waiter = local.clone();
+    thread::spawn(move || {
+        let res = waiter.task_wait(WaitRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        });
+        tx.send(res).unwrap();
+    });
+    let res = rx.recv_timeout(Duration::from_secs(5)).unwrap()?;
+    assert_eq!(res.exit_status, 137);
+
+    Ok(())
+}
+
+#[test]
+fn test_no_kill_without_grace_period() -> Result<()> {
+    let (etx, _erx) = channel();
+    let local = Arc::new(Local::<IgnoreSigtermInstance, _>::new(
+        (),
+        etx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    create_bundle(temp.path(), None)?;
+    local.task_create(CreateTaskRequest {
+        id: "test".to_string(),
+        bundle: temp.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+    local.task_start(StartRequest {
+        id: "test".to_string(),
+        ..Default::default()
+    })?;
+
+    for _ in 0..2 {
+        local.task_kill(KillRequest {
+            id: "test".to_string(),
+            signal: SIGTERM as u32,
+            ..Default::default()
+        })?;
+    }
+
+    // the instance ignores the SIGTERM, and is only killed by a SIGKILL
+    let (tx, rx) = channel();
+    let waiter = local.clone();
+    thread::spawn(move || {
+        let res = waiter.task_wait(WaitRequest {
+            id: "test".to_string(),
+            ..Default::default()
+        });
+        tx.send(res).unwrap();
+    });
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+
+    local.task_kill(KillRequest {
+        id: "test".to_string(),
+        signal: SIGKILL as u32,
+        ..Default::default()
+    })?;
+    let res = rx.recv_timeout(Duration::from_secs(5)).unwrap()?;
+    assert_eq!(res.exit_status, 137);
+
+    Ok(())
+}
+
+// An instance whose guest fails with the failure in its id once killed
+struct FailingInstance(Nop, String);
+
+impl Instance for FailingInstance {
+    type Engine = ();
+
+    fn new(id: String, _cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self> {
+        Ok(Self(Nop::new(id.clone(), None)?, id))
+    }
+
+    fn start(&self) -> Result<u32> {
+        self.0.start()
+    }
+
+    fn kill(&self, signal: u32) -> Result<()> {
+        self.0.kill(signal)
+    }
+
+    fn delete(&self) -> Result<()> {
+        self.0.delete()
+    }
+
+    fn take_failure(&self) -> Option<GuestFailure> {
+        let message = "failed".to_string();
+        match self.1.as_str() {
+            "trap" => Some(GuestFailure::Trap {
+                code: "UnreachableCodeReached".to_string(),
+                message,
+                backtrace: vec![BacktraceFrame {
+                    module: Some("hello.wasm".to_string()),
+                    func_name: Some("hello::main".to_string()),
+                    ..Default::default()
+                }],
+            }),
+            "oom" => Some(GuestFailure::OutOfMemory { message }),
+            _ => None,
+        }
+    }
+
+    fn wait_timeout(
+        &self,
+        t: impl Into<Option<Duration>>,
+    ) -> Option<(u32, chrono::DateTime<chrono::Utc>)> {
+        self.0.wait_timeout(t)
+    }
+}
+
+#[test]
+fn test_failure_events() -> Result<()> {
+    let (etx, erx) = channel();
+    let local = Arc::new(Local::<FailingInstance, _>::new(
+        (),
+        etx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    for (id, topic) in [("trap", "/tasks/trapped"), ("oom", "/tasks/oom")] {
+        let temp = tempdir().unwrap();
+        create_bundle(temp.path(), None)?;
+        local.task_create(CreateTaskRequest {
+            id: id.to_string(),
+            bundle: temp.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })?;
+        local.task_start(StartRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })?;
+        local.task_kill(KillRequest {
+            id: id.to_string(),
+            signal: SIGKILL as u32,
+            ..Default::default()
+        })?;
+
+        // the failure is published before the exit of the task
+        let events: Vec<_> = erx
+            .iter()
+            .take_while(|(topic, _)| topic != "/tasks/exit")
+            .collect();
+        let (_, event) = events.iter().find(|(t, _)| t == topic).unwrap();
+        match id {
+            "trap" => {
+                let event = event.downcast_ref::<TaskTrapped>().unwrap();
+                assert_eq!(event.container_id, id);
+                assert_eq!(event.code, "UnreachableCodeReached");
+                assert_eq!(event.message, "failed");
+                assert!(event.backtrace.contains("hello.wasm!hello::main"));
+            }
+            _ => {
+                let event = event.downcast_ref::<TaskOOM>().unwrap();
+                assert_eq!(event.container_id, id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_cri_task() -> Result<()> {
     // Currently the relationship between the "base" container and the "instances" are pretty weak.
@@ -276,6 +912,59 @@ fn test_cri_task() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_cri_pod() -> Result<()> {
+    // The containers of a pod share the shim, the sandbox is found with the type of the containers
+    let (etx, _erx) = channel();
+    let exit_signal = Arc::new(ExitSignal::default());
+    let local = Arc::new(Local::<Nop, _>::new(
+        (),
+        etx,
+        exit_signal,
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    let sandbox_id = "test-cri-pod".to_string();
+    let mut bundles = vec![];
+    // the first container of the shim isn't the sandbox, e.g., after the sandbox was deleted
+    for (id, container_type) in [
+        ("container1", "container"),
+        ("sandbox", "sandbox"),
+        ("container2", "container"),
+    ] {
+        let mut spec = with_cri_sandbox(None, sandbox_id.clone());
+        let mut annotations = spec.annotations().clone().unwrap_or_default();
+        annotations.insert(
+            CRI_CONTAINER_TYPE_ANNOTATION.to_string(),
+            container_type.to_string(),
+        );
+        spec.set_annotations(Some(annotations));
+
+        let temp = tempdir()?;
+        create_bundle(temp.path(), Some(spec))?;
+        local.task_create(CreateTaskRequest {
+            id: id.to_string(),
+            bundle: temp.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })?;
+        bundles.push(temp);
+    }
+
+    let sandbox = local.get_instance("sandbox")?;
+    assert!(matches!(sandbox.instance, InstanceOption::Nop(_)));
+    for id in ["container1", "container2"] {
+        let container = local.get_instance(id)?;
+        assert!(matches!(container.instance, InstanceOption::Instance(_)));
+        assert_eq!(container.sandbox_id(), Some(sandbox_id.as_str()));
+    }
+    assert_eq!(local.instances.read().unwrap().len(), 3);
+
+    Ok(())
+}
+
 #[test]
 fn test_task_lifecycle() -> Result<()> {
     let (etx, _erx) = channel(); // TODO: check events
@@ -392,3 +1081,460 @@ fn test_task_lifecycle() -> Result<()> {
 
     Ok(())
 }
+
+struct RecoveringInstance(Nop);
+
+impl Instance for RecoveringInstance {
+    type Engine = ();
+
+    fn new(id: String, _cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self> {
+        Ok(Self(Nop::new(id, None)?))
+    }
+
+    fn recover(id: String, _pid: u32, _cfg: &InstanceConfig<Self::Engine>) -> Result<Self> {
+        Ok(Self(Nop::new(id, None)?))
+    }
+
+    fn start(&self) -> Result<u32> {
+        self.0.start()
+    }
+
+    fn kill(&self, signal: u32) -> Result<()> {
+        self.0.kill(signal)
+    }
+
+    fn delete(&self) -> Result<()> {
+        self.0.delete()
+    }
+
+    fn wait_timeout(
+        &self,
+        t: impl Into<Option<Duration>>,
+    ) -> Option<(u32, chrono::DateTime<chrono::Utc>)> {
+        self.0.wait_timeout(t)
+    }
+}
+
+#[test]
+fn test_recover_task() -> Result<()> {
+    let dir = tempdir()?;
+    let id = "test-recover";
+    create_bundle(dir.path(), None)?;
+
+    let (tx, _rx) = channel();
+    let local = Local::<RecoveringInstance, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    );
+    local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        stdout: "/run/stdout".to_string(),
+        ..Default::default()
+    })?;
+    let pid = local
+        .task_start(StartRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })?
+        .pid;
+
+    // the state of the running task is persisted to its bundle
+    let record = TaskRecord::read(dir.path())?.context("no state in the bundle")?;
+    assert_eq!((record.id.as_str(), record.pid), (id, pid));
+    assert_eq!(record.status, "running");
+
+    // a restarted shim re-attaches to the task
+    let (tx, rx) = channel();
+    let restarted = Local::<RecoveringInstance, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    );
+    restarted.recover(dir.path())?;
+
+    let state = restarted.task_state(StateRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(state.status(), Status::RUNNING);
+    assert_eq!((state.pid, state.stdout.as_str()), (pid, "/run/stdout"));
+
+    restarted.task_kill(KillRequest {
+        id: id.to_string(),
+        signal: 9,
+        ..Default::default()
+    })?;
+    let exit = restarted.task_wait(WaitRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(exit.exit_status, 137);
+
+    let (topic, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(topic, "/tasks/exit");
+
+    restarted.task_delete(DeleteRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(TaskRecord::read(dir.path())?, None);
+
+    Ok(())
+}
+
+// The pid of the process of a CheckpointingInstance, and of the process restored from its checkpoint
+const CHECKPOINTED_PID: u32 = 4242;
+const RESTORED_PID: u32 = 4343;
+
+struct CheckpointingInstance(Nop);
+
+impl Instance for CheckpointingInstance {
+    type Engine = ();
+
+    fn new(id: String, _cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self> {
+        Ok(Self(Nop::new(id, None)?))
+    }
+
+    fn restore(
+        id: String,
+        checkpoint: &Path,
+        _cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<(Self, u32)> {
+        let pid: u32 = std::fs::read_to_string(checkpoint.join("pid"))?
+            .parse()
+            .map_err(|_| Error::InvalidArgument("invalid checkpoint".to_string()))?;
+        assert_eq!(pid, CHECKPOINTED_PID);
+        Ok((Self(Nop::new(id, None)?), RESTORED_PID))
+    }
+
+    fn start(&self) -> Result<u32> {
+        Ok(CHECKPOINTED_PID)
+    }
+
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        std::fs::write(path.join("pid"), CHECKPOINTED_PID.to_string())?;
+        Ok(())
+    }
+
+    fn kill(&self, signal: u32) -> Result<()> {
+        self.0.kill(signal)
+    }
+
+    fn delete(&self) -> Result<()> {
+        self.0.delete()
+    }
+
+    fn wait_timeout(
+        &self,
+        t: impl Into<Option<Duration>>,
+    ) -> Option<(u32, chrono::DateTime<chrono::Utc>)> {
+        self.0.wait_timeout(t)
+    }
+}
+
+#[test]
+fn test_checkpoint_restore() -> Result<()> {
+    let dir = tempdir()?;
+    let checkpoint = tempdir()?;
+    let id = "test-checkpoint-restore";
+    create_bundle(dir.path(), None)?;
+
+    let (tx, _rx) = channel();
+    let local = Local::<CheckpointingInstance, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    );
+    let create = |checkpoint: &str| {
+        local.task_create(CreateTaskRequest {
+            id: id.to_string(),
+            bundle: dir.path().to_str().unwrap().to_string(),
+            checkpoint: checkpoint.to_string(),
+            ..Default::default()
+        })
+    };
+    let start = || {
+        local.task_start(StartRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })
+    };
+    let stop = || -> Result<()> {
+        local.task_kill(KillRequest {
+            id: id.to_string(),
+            signal: 9,
+            ..Default::default()
+        })?;
+        local.task_wait(WaitRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })?;
+        local.task_delete(DeleteRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })?;
+        Ok(())
+    };
+
+    create("")?;
+    assert_eq!(start()?.pid, CHECKPOINTED_PID);
+    local.task_checkpoint(CheckpointTaskRequest {
+        id: id.to_string(),
+        path: checkpoint.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+    stop()?;
+
+    // the task created from the checkpoint runs the restored process once started
+    create(checkpoint.path().to_str().unwrap())?;
+    let state = local.task_state(StateRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(state.status(), Status::CREATED);
+    assert_eq!(start()?.pid, RESTORED_PID);
+    stop()?;
+
+    // restoring from a parent checkpoint is not supported
+    let res = local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        parent_checkpoint: checkpoint.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    });
+    assert!(matches!(res, Err(Error::Shim(ShimError::Unimplemented(_)))));
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_not_supported() -> Result<()> {
+    let dir = tempdir()?;
+    create_bundle(dir.path(), None)?;
+
+    let (tx, _rx) = channel();
+    let local = Local::<Nop, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    );
+
+    // nothing to recover without a state in the bundle
+    local.recover(dir.path())?;
+
+    let record = TaskRecord {
+        id: "test".to_string(),
+        pid: 42,
+        status: "running".to_string(),
+        stdin: Default::default(),
+        stdout: Default::default(),
+        stderr: Default::default(),
+        terminal: false,
+        execs: vec![],
+        checkpoint: None,
+        drained_at: None,
+        start_times: vec![],
+    };
+    record.write(dir.path())?;
+    assert!(local.recover(dir.path()).is_err());
+
+    // the stale state is removed
+    assert_eq!(TaskRecord::read(dir.path())?, None);
+    assert!(!local.has_instance("test"));
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_from_checkpoint() -> Result<()> {
+    let dir = tempdir()?;
+    let id = "test-recover-checkpoint";
+    create_bundle(dir.path(), None)?;
+    let checkpoint = dir.path().join("checkpoint");
+    create_dir(&checkpoint)?;
+    std::fs::write(checkpoint.join("pid"), CHECKPOINTED_PID.to_string())?;
+
+    // the process of the task drained with the `checkpoint` mode is gone
+    let record = TaskRecord {
+        id: id.to_string(),
+        pid: CHECKPOINTED_PID,
+        status: "running".to_string(),
+        stdin: Default::default(),
+        stdout: Default::default(),
+        stderr: Default::default(),
+        terminal: false,
+        execs: vec![],
+        checkpoint: Some(checkpoint),
+        drained_at: None,
+        start_times: vec![],
+    };
+    record.write(dir.path())?;
+
+    let (tx, _rx) = channel();
+    let local = Local::<CheckpointingInstance, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    );
+    local.recover(dir.path())?;
+
+    // the task runs the process restored from its checkpoint
+    let state = local.task_state(StateRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(state.status(), Status::RUNNING);
+    assert_eq!(state.pid, RESTORED_PID);
+    let record = TaskRecord::read(dir.path())?.context("no state in the bundle")?;
+    assert_eq!(record.pid, RESTORED_PID);
+
+    local.task_kill(KillRequest {
+        id: id.to_string(),
+        signal: 9,
+        ..Default::default()
+    })?;
+    local.task_wait(WaitRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    local.task_delete(DeleteRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_exited_task() -> Result<()> {
+    let dir = tempdir()?;
+    let id = "test-recover-exited";
+    create_bundle(dir.path(), None)?;
+
+    let (tx, rx) = channel();
+    let local = Local::<Nop, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    );
+    local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+    let pid = local
+        .task_start(StartRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })?
+        .pid;
+    local.task_kill(KillRequest {
+        id: id.to_string(),
+        signal: 9,
+        ..Default::default()
+    })?;
+
+    // the exit is persisted before it is published
+    let _ = rx
+        .iter()
+        .take_while(|(topic, _)| topic != "/tasks/exit")
+        .count();
+    let exit = ExitRecord::read(dir.path())?.context("no exit in the bundle")?;
+    assert_eq!((exit.pid, exit.exit_status), (pid, 137));
+
+    // a shim restarted after the task exited returns its exit, and publishes it again
+    let (tx, rx) = channel();
+    let restarted = Local::<Nop, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    );
+    restarted.recover(dir.path())?;
+
+    let state = restarted.task_state(StateRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(state.status(), Status::STOPPED);
+    assert_eq!((state.pid, state.exit_status), (pid, 137));
+
+    let (topic, _) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(topic, "/tasks/exit");
+
+    let deleted = restarted.task_delete(DeleteRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(deleted.exit_status, 137);
+    assert_eq!(ExitRecord::read(dir.path())?, None);
+    assert_eq!(TaskRecord::read(dir.path())?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_runtime_options() -> Result<()> {
+    let dir = tempdir()?;
+    let mut spec = Spec::default();
+    spec.set_annotations(Some(HashMap::from([(
+        "runwasi.io/wasi-threads".to_string(),
+        "false".to_string(),
+    )])));
+    create_bundle(dir.path(), Some(spec))?;
+
+    let (tx, _rx) = channel();
+    let local = Arc::new(Local::<Nop, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDescrutor::new(local.clone());
+
+    let mut options = crate::services::runtimeoptions::Options::new();
+    options.config_body = br#"
+        threads = 2
+
+        [annotations]
+        "runwasi.io/wasi-threads" = "true"
+        "runwasi.io/coredump-on-trap" = "true"
+    "#
+    .to_vec();
+    let mut any = Any::new();
+    any.type_url = "runtimeoptions.v1.Options".to_string();
+    any.value = protobuf::Message::write_to_bytes(&options).unwrap();
+    local.task_create(CreateTaskRequest {
+        id: "test".to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        options: Some(any).into(),
+        ..Default::default()
+    })?;
+
+    // the options are written to the bundle, and the annotations of the container override
+    // the default ones
+    let options = Options::read(dir.path())?;
+    assert_eq!(options.threads, std::num::NonZeroUsize::new(2));
+    let spec = Spec::load(dir.path().join("config.json"))?;
+    let annotations = spec.annotations().clone().unwrap_or_default();
+    assert_eq!(annotations["runwasi.io/wasi-threads"], "false");
+    assert_eq!(annotations["runwasi.io/coredump-on-trap"], "true");
+
+    Ok(())
+}
@@ -0,0 +1,249 @@
+//! Logging of the shim.
+//!
+//! By default, `containerd-shim` writes the logs of the shim to the containerd log fifo, in
+//! containerd's `key=value` text format. The shim sets up its own logger instead when:
+//!  - `RUNWASI_LOG_FORMAT=json`: the logs are written to the fifo as a JSON object per line,
+//!    so that the log pipelines of the node can parse them.
+//!  - `RUNWASI_LOG_JOURNALD=1`, with the `journald` feature: the logs are also sent to the
+//!    systemd journal, and still reach the journal when the fifo can't be opened.
+//!
+//! JSON logs and journal entries have the `time`, `level`, `target` and `msg` of the log, and
+//! the `container_id` of the shim, the `containerd_namespace`, and, for the containers of
+//! Kubernetes pods, the `pod` and its `namespace`.
+//!
+//! This is only supported on Linux, where the shim opens the fifo created by containerd.
+
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use containerd_shim::Flags;
+use log::{LevelFilter, Log, Metadata, Record};
+use oci_spec::runtime::Spec;
+use serde_json::{Map, Value};
+
+use crate::sandbox::node_config;
+#[cfg(all(unix, feature = "journald"))]
+use crate::sandbox::shim::journald::{self, Journald};
+
+const LOG_FORMAT_ENV: &str = "RUNWASI_LOG_FORMAT";
+const CRI_SANDBOX_NAME: &str = "io.kubernetes.cri.sandbox-name";
+const CRI_SANDBOX_NAMESPACE: &str = "io.kubernetes.cri.sandbox-namespace";
+
+/// Returns `true` if the logs are written as JSON, from the environment of the shim.
+pub(crate) fn json_enabled() -> bool {
+    node_config::var(LOG_FORMAT_ENV).is_ok_and(|format| format.eq_ignore_ascii_case("json"))
+}
+
+/// Returns `true` if the shim sets up its own logger, rather than the logger of `containerd-shim`.
+pub(crate) fn enabled() -> bool {
+    #[cfg(all(unix, feature = "journald"))]
+    if journald::enabled() {
+        return true;
+    }
+    json_enabled()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+/// A logger writing each record to the fifo, and to the journal, with the fields of the shim.
+struct Logger<W> {
+    fifo: Option<Mutex<W>>,
+    format: Format,
+    fields: Vec<(&'static str, String)>,
+    #[cfg(all(unix, feature = "journald"))]
+    journald: Option<Journald>,
+}
+
+impl<W: Write + Send> Logger<W> {
+    fn new(fifo: Option<W>, format: Format, flags: &Flags, spec: Option<&Spec>) -> Self {
+        let mut fields = vec![
+            ("container_id", flags.id.clone()),
+            ("containerd_namespace", flags.namespace.clone()),
+        ];
+        let annotations = spec.and_then(|spec| spec.annotations().as_ref());
+        for (field, annotation) in [
+            ("pod", CRI_SANDBOX_NAME),
+            ("namespace", CRI_SANDBOX_NAMESPACE),
+        ] {
+            if let Some(value) = annotations.and_then(|a| a.get(annotation)) {
+                fields.push((field, value.clone()));
+            }
+        }
+        Self {
+            fifo: fifo.map(Mutex::new),
+            format,
+            fields,
+            #[cfg(all(unix, feature = "journald"))]
+            journald: None,
+        }
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let level = record.level().as_str().to_lowercase();
+        match self.format {
+            // the format of the logger of `containerd-shim`
+            Format::Text => format!("time=\"{time}\" level={level} msg=\"{}\"", record.args()),
+            Format::Json => {
+                let mut entry = Map::new();
+                entry.insert("time".into(), time.into());
+                entry.insert("level".into(), level.into());
+                entry.insert("target".into(), record.target().into());
+                entry.insert("msg".into(), record.args().to_string().into());
+                for (field, value) in &self.fields {
+                    entry.insert(field.to_string(), value.clone().into());
+                }
+                Value::Object(entry).to_string()
+            }
+        }
+    }
+}
+
+impl<W: Write + Send> Log for Logger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Some(fifo) = &self.fifo {
+            let line = self.format(record);
+            // as for the logger of `containerd-shim`, errors of a closed fifo are ignored
+            let _ = writeln!(fifo.lock().unwrap(), "{line}");
+        }
+        #[cfg(all(unix, feature = "journald"))]
+        if let Some(journald) = &self.journald {
+            journald.send(record, &self.fields);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(fifo) = &self.fifo {
+            let _ = fifo.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Sets the logger of the shim, in place of the logger of `containerd-shim`.
+#[cfg(unix)]
+pub(crate) fn init(flags: &Flags, default_log_level: &str) -> anyhow::Result<()> {
+    use std::fs::OpenOptions;
+
+    let format = match json_enabled() {
+        true => Format::Json,
+        false => Format::Text,
+    };
+    let fifo = OpenOptions::new().write(true).open("log");
+    // the shim runs in the bundle of the container
+    let spec = Spec::load("config.json").ok();
+
+    #[cfg(feature = "journald")]
+    let (logger, journald_err) = {
+        let (journald, journald_err) = match journald::enabled().then(Journald::connect) {
+            Some(Ok(journald)) => (Some(journald), None),
+            Some(Err(err)) => (None, Some(err)),
+            None => (None, None),
+        };
+        // without the fifo, the logs only go to the journal
+        let fifo = match (fifo, &journald) {
+            (Err(err), None) => return Err(err.into()),
+            (fifo, _) => fifo.ok(),
+        };
+        let mut logger = Logger::new(fifo, format, flags, spec.as_ref());
+        logger.journald = journald;
+        (logger, journald_err)
+    };
+    #[cfg(not(feature = "journald"))]
+    let logger = Logger::new(Some(fifo?), format, flags, spec.as_ref());
+
+    log::set_boxed_logger(Box::new(logger))?;
+
+    let level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| LevelFilter::from_str(&level).ok())
+        .or_else(|| LevelFilter::from_str(default_log_level).ok())
+        .unwrap_or(LevelFilter::Info);
+    log::set_max_level(match flags.debug {
+        true => level.max(LevelFilter::Debug),
+        false => level,
+    });
+
+    #[cfg(feature = "journald")]
+    if let Some(err) = journald_err {
+        log::warn!("failed to connect to the systemd journal: {err}");
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn init(_flags: &Flags, _default_log_level: &str) -> anyhow::Result<()> {
+    anyhow::bail!("the logger of the shim is only supported on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    fn flags() -> Flags {
+        Flags {
+            id: "container".into(),
+            namespace: "k8s.io".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_json_logger() -> anyhow::Result<()> {
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([
+                (CRI_SANDBOX_NAME.to_string(), "pod".to_string()),
+                (CRI_SANDBOX_NAMESPACE.to_string(), "default".to_string()),
+            ]))
+            .build()?;
+        let logger = Logger::<Vec<u8>>::new(None, Format::Json, &flags(), Some(&spec));
+
+        let line = logger.format(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .target("shim")
+                .args(format_args!("task \"container\" exited"))
+                .build(),
+        );
+        let entry: Map<String, Value> = serde_json::from_str(&line)?;
+        assert_eq!(entry["level"], "warn");
+        assert_eq!(entry["target"], "shim");
+        assert_eq!(entry["msg"], "task \"container\" exited");
+        assert_eq!(entry["container_id"], "container");
+        assert_eq!(entry["containerd_namespace"], "k8s.io");
+        assert_eq!(entry["pod"], "pod");
+        assert_eq!(entry["namespace"], "default");
+        assert!(entry["time"].as_str().is_some_and(|t| t.ends_with('Z')));
+
+        // outside of a pod
+        let logger = Logger::<Vec<u8>>::new(None, Format::Json, &flags(), None);
+        let line = logger.format(&Record::builder().args(format_args!("started")).build());
+        let entry: Map<String, Value> = serde_json::from_str(&line)?;
+        assert!(!entry.contains_key("pod"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_logger() {
+        let logger = Logger::<Vec<u8>>::new(None, Format::Text, &flags(), None);
+        let line = logger.format(&Record::builder().args(format_args!("started")).build());
+        assert!(line.starts_with("time=\""));
+        assert!(line.ends_with(" level=info msg=\"started\""));
+    }
+}
@@ -3,15 +3,35 @@
 //! the container/sandbox.
 
 mod cli;
+#[cfg_attr(not(unix), allow(dead_code))]
+mod controller;
+mod drain;
 mod events;
 mod instance_data;
 mod instance_option;
+#[cfg(unix)]
+pub(crate) mod introspection;
+#[cfg(all(unix, feature = "journald"))]
+mod journald;
 mod local;
+mod logger;
 #[cfg(feature = "opentelemetry")]
 mod otel;
+mod recovery;
+#[cfg(unix)]
+pub(crate) mod sd_notify;
+#[cfg(unix)]
+mod server;
 mod task_state;
 
 pub use cli::Cli;
 pub(crate) use local::Local;
 #[cfg(feature = "opentelemetry")]
-pub use otel::{traces_enabled as otel_traces_enabled, Config as OTLPConfig};
+pub use otel::{
+    logs_enabled as otel_logs_enabled, metrics_enabled as otel_metrics_enabled,
+    traces_enabled as otel_traces_enabled, Config as OTLPConfig, MetricsConfig as PrometheusConfig,
+};
+#[cfg(feature = "opentelemetry")]
+pub(crate) use otel::{record_compile_duration, record_container_start};
+#[cfg(unix)]
+pub(crate) use server::run;
@@ -4,13 +4,26 @@
 //! OpenTelemetry tracing with the OTLP exporter. The configuration can be set up via
 //! the `Config` struct and its builder pattern.
 //!
+//! When using the `grpc` protocol, the endpoint can be a Unix domain socket, e.g.
+//! `unix:///run/otel/collector.sock`.
+//!
+//! Telemetry is tagged with a `Resource` that carries the `service.name` of the shim binary,
+//! the container, pod and namespace the shim was started for, and any attributes set via
+//! `OTEL_RESOURCE_ATTRIBUTES`.
+//!
+//! If `OTEL_LOGS_EXPORTER` is set to `otlp`, tracing events are exported as OTLP logs, with or
+//! without traces, and correlated with the active trace when traces are enabled.
+//!
+//! Shim-level metrics (container starts, wasm compile time, memory usage) can be
+//! exposed on a Prometheus pull endpoint using the `MetricsConfig` struct.
+//!
 //! # Usage
 //!
 //! ```rust
 //! use containerd_shim_wasm::sandbox::shim::otel::Config;
 //!
 //! fn main() -> anyhow::Result<()> {
-//!     if traces_enabled() {
+//!     if traces_enabled() || logs_enabled() {
 //!         let otel_config = Config::build_from_env()?;
 //!    
 //!         let _guard = otel_config.init()?;
@@ -22,33 +35,107 @@
 //! ```
 
 use std::collections::HashMap;
-use std::env;
+use std::ffi::OsString;
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::{env, thread};
 
+use anyhow::Context as _;
+use oci_spec::runtime::Spec;
 use opentelemetry::global::{self, set_text_map_propagator};
+use opentelemetry::logs::LogError;
+use opentelemetry::metrics::{Counter, Histogram, Unit};
 use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
 use opentelemetry_otlp::{
-    Protocol, SpanExporterBuilder, WithExportConfig, OTEL_EXPORTER_OTLP_PROTOCOL_DEFAULT,
+    LogExporterBuilder, Protocol, SpanExporterBuilder, TonicExporterBuilder, WithExportConfig,
+    OTEL_EXPORTER_OTLP_PROTOCOL_DEFAULT,
 };
 pub use opentelemetry_otlp::{
-    OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_PROTOCOL, OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
+    OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_LOGS_ENDPOINT, OTEL_EXPORTER_OTLP_PROTOCOL,
+    OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
 };
+use opentelemetry_sdk::logs::LoggerProvider;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
 use opentelemetry_sdk::propagation::TraceContextPropagator;
-use opentelemetry_sdk::{runtime, trace as sdktrace};
+use opentelemetry_sdk::resource::{EnvResourceDetector, TelemetryResourceDetector};
+use opentelemetry_sdk::trace::Sampler;
+use opentelemetry_sdk::{logs as sdklogs, runtime, trace as sdktrace, Resource};
+use prometheus::{Encoder as _, Registry as PrometheusRegistry, TextEncoder};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(unix)]
+use tonic::transport::{Endpoint, Uri};
 use tracing::Span;
 use tracing_opentelemetry::OpenTelemetrySpanExt as _;
 use tracing_subscriber::layer::SubscriberExt as _;
-use tracing_subscriber::{EnvFilter, Registry};
+use tracing_subscriber::{EnvFilter, Layer as _, Registry};
+
+use crate::sandbox::node_config;
 
 const OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_JSON: &str = "http/json";
 const OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_PROTOBUF: &str = "http/protobuf";
 const OTEL_EXPORTER_OTLP_PROTOCOL_GRPC: &str = "grpc";
 const OTEL_EXPORTER_OTLP_TRACES_PROTOCOL: &str = "OTEL_EXPORTER_OTLP_TRACES_PROTOCOL";
+const OTEL_EXPORTER_OTLP_LOGS_PROTOCOL: &str = "OTEL_EXPORTER_OTLP_LOGS_PROTOCOL";
 const OTEL_SDK_DISABLED: &str = "OTEL_SDK_DISABLED";
+const UNIX_SOCKET_SCHEME: &str = "unix://";
+const OTEL_SERVICE_NAME: &str = "OTEL_SERVICE_NAME";
+const OTEL_TRACES_SAMPLER: &str = "OTEL_TRACES_SAMPLER";
+const OTEL_TRACES_SAMPLER_ARG: &str = "OTEL_TRACES_SAMPLER_ARG";
+const OTEL_LOGS_EXPORTER: &str = "OTEL_LOGS_EXPORTER";
+const OTEL_LOGS_EXPORTER_OTLP: &str = "otlp";
+const OTEL_METRICS_EXPORTER: &str = "OTEL_METRICS_EXPORTER";
+const OTEL_METRICS_EXPORTER_PROMETHEUS: &str = "prometheus";
+const OTEL_EXPORTER_PROMETHEUS_HOST: &str = "OTEL_EXPORTER_PROMETHEUS_HOST";
+const OTEL_EXPORTER_PROMETHEUS_PORT: &str = "OTEL_EXPORTER_PROMETHEUS_PORT";
+const OTEL_EXPORTER_PROMETHEUS_HOST_DEFAULT: &str = "localhost";
+// every shim process serves its own endpoint, so by default each binds an ephemeral port
+const OTEL_EXPORTER_PROMETHEUS_PORT_DEFAULT: u16 = 0;
+// a client that doesn't send its request in time is disconnected
+const METRICS_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const METER_NAME: &str = "containerd-shim-wasm";
+
+const SERVICE_NAME: &str = "service.name";
+const CONTAINER_ID: &str = "container.id";
+const CONTAINERD_NAMESPACE: &str = "containerd.namespace";
+const CONTAINERD_SANDBOX_ID: &str = "containerd.sandbox.id";
+const K8S_POD_NAME: &str = "k8s.pod.name";
+const K8S_POD_UID: &str = "k8s.pod.uid";
+const K8S_NAMESPACE_NAME: &str = "k8s.namespace.name";
+const K8S_CONTAINER_NAME: &str = "k8s.container.name";
+
+const CRI_SANDBOX_ID: &str = "io.kubernetes.cri.sandbox-id";
+const CRI_SANDBOX_NAME: &str = "io.kubernetes.cri.sandbox-name";
+const CRI_SANDBOX_UID: &str = "io.kubernetes.cri.sandbox-uid";
+const CRI_SANDBOX_NAMESPACE: &str = "io.kubernetes.cri.sandbox-namespace";
+const CRI_CONTAINER_NAME: &str = "io.kubernetes.cri.container-name";
+
+static PROMETHEUS_REGISTRY: OnceLock<PrometheusRegistry> = OnceLock::new();
 
 /// Configuration struct for OpenTelemetry setup.
 pub struct Config {
-    traces_endpoint: String,
-    traces_protocol: Protocol,
+    traces: Option<TracesConfig>,
+    logs: Option<LogsConfig>,
+}
+
+/// Configuration of the OTLP trace exporter, only present when traces are enabled.
+struct TracesConfig {
+    endpoint: String,
+    protocol: Protocol,
+    sampler: Sampler,
+    resource: Resource,
+}
+
+/// Configuration of the OTLP log exporter, only present when logs are enabled.
+struct LogsConfig {
+    endpoint: String,
+    protocol: Protocol,
+    resource: Resource,
 }
 
 /// Returns `true` if traces are enabled, `false` otherwise.
@@ -56,15 +143,37 @@ pub struct Config {
 /// Traces are enabled if either `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` or `OTEL_EXPORTER_OTLP_ENDPOINT` is set and not empty.
 /// `OTEL_SDK_DISABLED` can be set to `true` to disable traces.
 pub fn traces_enabled() -> bool {
-    let check_env_var = |var: &str| env::var_os(var).is_some_and(|val| !val.is_empty());
+    let check_env_var = |var: &str| node_config::var_os(var).is_some_and(|val| !val.is_empty());
     let traces_endpoint = check_env_var(OTEL_EXPORTER_OTLP_TRACES_ENDPOINT);
     let otlp_endpoint = check_env_var(OTEL_EXPORTER_OTLP_ENDPOINT);
 
     // https://opentelemetry.io/docs/specs/otel/configuration/sdk-environment-variables/#general-sdk-configuration
-    let sdk_disabled = env::var_os(OTEL_SDK_DISABLED).is_some_and(|val| val == "true");
+    let sdk_disabled = node_config::var_os(OTEL_SDK_DISABLED).is_some_and(|val| val == "true");
     (traces_endpoint || otlp_endpoint) && !sdk_disabled
 }
 
+/// Returns `true` if logs are enabled, `false` otherwise.
+///
+/// Logs are enabled if `OTEL_LOGS_EXPORTER` is set to `otlp`.
+/// `OTEL_SDK_DISABLED` can be set to `true` to disable logs.
+pub fn logs_enabled() -> bool {
+    let otlp =
+        node_config::var_os(OTEL_LOGS_EXPORTER).is_some_and(|val| val == OTEL_LOGS_EXPORTER_OTLP);
+    let sdk_disabled = node_config::var_os(OTEL_SDK_DISABLED).is_some_and(|val| val == "true");
+    otlp && !sdk_disabled
+}
+
+/// Returns `true` if metrics are enabled, `false` otherwise.
+///
+/// Metrics are enabled if `OTEL_METRICS_EXPORTER` is set to `prometheus`.
+/// `OTEL_SDK_DISABLED` can be set to `true` to disable metrics.
+pub fn metrics_enabled() -> bool {
+    let prometheus = node_config::var_os(OTEL_METRICS_EXPORTER)
+        .is_some_and(|val| val == OTEL_METRICS_EXPORTER_PROMETHEUS);
+    let sdk_disabled = node_config::var_os(OTEL_SDK_DISABLED).is_some_and(|val| val == "true");
+    prometheus && !sdk_disabled
+}
+
 /// Initializes a new OpenTelemetry tracer with the OTLP exporter.
 ///
 /// Returns a `Result` containing the initialized tracer or a `TraceError` if initialization fails.
@@ -72,28 +181,70 @@ pub fn traces_enabled() -> bool {
 /// https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/protocol/exporter.md#configuration-options
 impl Config {
     pub fn build_from_env() -> anyhow::Result<Self> {
-        let traces_endpoint = traces_endpoint_from_env()?;
-        let traces_protocol: Protocol = traces_protocol_from_env()?;
-        Ok(Self {
-            traces_endpoint,
-            traces_protocol,
-        })
+        let resource = resource_from_env();
+        let traces = if traces_enabled() {
+            let endpoint = traces_endpoint_from_env()?;
+            let protocol = traces_protocol_from_env()?;
+            check_unix_endpoint(&endpoint, &protocol)?;
+            Some(TracesConfig {
+                endpoint,
+                protocol,
+                sampler: traces_sampler_from_env(),
+                resource: resource.clone(),
+            })
+        } else {
+            None
+        };
+        let logs = if logs_enabled() {
+            let endpoint = logs_endpoint_from_env()?;
+            let protocol = logs_protocol_from_env()?;
+            check_unix_endpoint(&endpoint, &protocol)?;
+            Some(LogsConfig {
+                endpoint,
+                protocol,
+                resource: resource.clone(),
+            })
+        } else {
+            None
+        };
+        if traces.is_none() && logs.is_none() {
+            anyhow::bail!("neither OTLP traces nor OTLP logs are enabled");
+        }
+        Ok(Self { traces, logs })
     }
 
-    /// Initializes the tracer, sets up the telemetry and subscriber layers, and sets the global subscriber.
+    /// Initializes the tracer and the logger of the enabled signals, sets up the telemetry and
+    /// subscriber layers, and sets the global subscriber.
     ///
     /// Note: this function should be called only once and be called by the binary entry point.
     pub fn init(&self) -> anyhow::Result<impl Drop> {
-        let tracer = self.init_tracer()?;
-        let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-        set_text_map_propagator(TraceContextPropagator::new());
+        let telemetry = match &self.traces {
+            Some(traces) => {
+                let tracer = traces.init()?;
+                set_text_map_propagator(TraceContextPropagator::new());
+                Some(tracing_opentelemetry::layer().with_tracer(tracer))
+            }
+            None => None,
+        };
 
         let filter = EnvFilter::try_new("info,h2=off")?;
 
-        let subscriber = Registry::default().with(telemetry).with(filter);
+        let logger_provider = self.logs.as_ref().map(LogsConfig::init).transpose()?;
+        let logs = match &logger_provider {
+            Some(provider) => {
+                // don't export the logs emitted by the exporters themselves
+                let filter = EnvFilter::try_new(
+                    "info,h2=off,hyper=off,reqwest=off,tonic=off,opentelemetry=off",
+                )?;
+                Some(OpenTelemetryTracingBridge::new(provider).with_filter(filter))
+            }
+            None => None,
+        };
+
+        let subscriber = Registry::default().with(telemetry).with(logs).with(filter);
 
         tracing::subscriber::set_global_default(subscriber)?;
-        Ok(ShutdownGuard)
+        Ok(ShutdownGuard(logger_provider))
     }
 
     /// Returns the current trace context as a JSON string.
@@ -114,23 +265,22 @@ impl Config {
         Span::current().set_parent(context);
         Ok(())
     }
+}
 
+impl TracesConfig {
     fn init_tracer_http(&self) -> SpanExporterBuilder {
         opentelemetry_otlp::new_exporter()
             .http()
-            .with_endpoint(&self.traces_endpoint)
+            .with_endpoint(&self.endpoint)
             .into()
     }
 
     fn init_tracer_grpc(&self) -> SpanExporterBuilder {
-        opentelemetry_otlp::new_exporter()
-            .tonic()
-            .with_endpoint(&self.traces_endpoint)
-            .into()
+        grpc_exporter(&self.endpoint).into()
     }
 
-    fn init_tracer(&self) -> Result<opentelemetry_sdk::trace::Tracer, TraceError> {
-        let exporter = match self.traces_protocol {
+    fn init(&self) -> Result<opentelemetry_sdk::trace::Tracer, TraceError> {
+        let exporter = match self.protocol {
             Protocol::HttpBinary => self.init_tracer_http(),
             Protocol::HttpJson => self.init_tracer_http(),
             Protocol::Grpc => self.init_tracer_grpc(),
@@ -139,35 +289,303 @@ impl Config {
         opentelemetry_otlp::new_pipeline()
             .tracing()
             .with_exporter(exporter)
-            .with_trace_config(sdktrace::config())
+            .with_trace_config(
+                sdktrace::config()
+                    .with_sampler(self.sampler.clone())
+                    .with_resource(self.resource.clone()),
+            )
             .install_batch(runtime::Tokio)
     }
 }
 
+impl LogsConfig {
+    fn init(&self) -> Result<LoggerProvider, LogError> {
+        let exporter: LogExporterBuilder = match self.protocol {
+            Protocol::HttpBinary | Protocol::HttpJson => opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(&self.endpoint)
+                .into(),
+            Protocol::Grpc => grpc_exporter(&self.endpoint).into(),
+        };
+
+        opentelemetry_otlp::new_pipeline()
+            .logging()
+            .with_log_config(sdklogs::config().with_resource(self.resource.clone()))
+            .with_exporter(exporter)
+            .install_batch(runtime::Tokio)
+    }
+}
+
+/// Creates a gRPC exporter for `endpoint`, dialing a Unix domain socket for `unix://` endpoints.
+#[cfg(unix)]
+fn grpc_exporter(endpoint: &str) -> TonicExporterBuilder {
+    let exporter = opentelemetry_otlp::new_exporter().tonic();
+    let Some(path) = endpoint.strip_prefix(UNIX_SOCKET_SCHEME) else {
+        return exporter.with_endpoint(endpoint);
+    };
+
+    // tonic requires a valid http uri, but the connector ignores it and dials the socket instead
+    let path = path.to_owned();
+    let timeout = Duration::from_secs(opentelemetry_otlp::OTEL_EXPORTER_OTLP_TIMEOUT_DEFAULT);
+    let channel = Endpoint::from_static("http://[::]:50051")
+        .timeout(timeout)
+        .connect_with_connector_lazy(tower::service_fn(move |_: Uri| {
+            UnixStream::connect(path.clone())
+        }));
+    exporter.with_timeout(timeout).with_channel(channel)
+}
+
+#[cfg(windows)]
+fn grpc_exporter(endpoint: &str) -> TonicExporterBuilder {
+    opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint)
+}
+
+/// Unix domain socket endpoints are only supported with the `grpc` protocol.
+fn check_unix_endpoint(endpoint: &str, protocol: &Protocol) -> anyhow::Result<()> {
+    if endpoint.starts_with(UNIX_SOCKET_SCHEME) && *protocol != Protocol::Grpc {
+        Err(TraceError::from(
+            "unix:// endpoints require the grpc OTEL_EXPORTER_OTLP_PROTOCOL",
+        ))?;
+    }
+    Ok(())
+}
+
 /// Shutdown of the open telemetry services will automatically called when the OtelConfig instance goes out of scope.
 #[must_use]
-struct ShutdownGuard;
+struct ShutdownGuard(Option<LoggerProvider>);
 
 impl Drop for ShutdownGuard {
     fn drop(&mut self) {
         // Give tracer provider a chance to flush any pending traces.
         opentelemetry::global::shutdown_tracer_provider();
+        // Give logger provider a chance to flush any pending logs.
+        if let Some(provider) = self.0.take() {
+            let _ = provider.shutdown();
+        }
+    }
+}
+
+/// Configuration struct for the Prometheus metrics exporter.
+pub struct MetricsConfig {
+    host: String,
+    port: u16,
+    resource: Resource,
+}
+
+/// Builds the Prometheus exporter configuration.
+///
+/// https://opentelemetry.io/docs/specs/otel/configuration/sdk-environment-variables/#prometheus-exporter
+impl MetricsConfig {
+    pub fn build_from_env() -> anyhow::Result<Self> {
+        let host = node_config::var(OTEL_EXPORTER_PROMETHEUS_HOST)
+            .ok()
+            .filter(|host| !host.is_empty())
+            .unwrap_or(OTEL_EXPORTER_PROMETHEUS_HOST_DEFAULT.to_owned());
+        let port = prometheus_port_from_env()?;
+        let resource = resource_from_env();
+        Ok(Self {
+            host,
+            port,
+            resource,
+        })
+    }
+
+    /// Initializes the meter provider with the Prometheus exporter and sets it as the global meter provider.
+    ///
+    /// Note: this function should be called only once and be called by the binary entry point.
+    pub fn init(&self) -> anyhow::Result<impl Drop> {
+        let registry = PrometheusRegistry::new();
+        let exporter = opentelemetry_prometheus::exporter()
+            .with_registry(registry.clone())
+            .build()?;
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter)
+            .with_resource(self.resource.clone())
+            .build();
+        global::set_meter_provider(provider.clone());
+        let _ = PROMETHEUS_REGISTRY.set(registry);
+
+        register_memory_gauge();
+
+        Ok(MetricsShutdownGuard(provider))
+    }
+
+    /// Starts serving the metrics on `http://<host>:<port>/metrics` from a background thread,
+    /// and returns the address it is bound to.
+    /// Each request is handled on its own thread, so a slow client doesn't block the others.
+    ///
+    /// Note: this function should be called by the long running shim process, after `init`.
+    pub fn serve(&self) -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind((self.host.as_str(), self.port)).with_context(|| {
+            format!(
+                "failed to bind prometheus endpoint to {}:{}",
+                self.host, self.port
+            )
+        })?;
+        let address = listener.local_addr()?;
+        thread::Builder::new()
+            .name("prometheus".to_string())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let spawned = thread::Builder::new()
+                        .name("prometheus-request".to_string())
+                        .spawn(move || {
+                            if let Err(err) = handle_metrics_request(stream) {
+                                log::debug!("failed to serve metrics request: {err}");
+                            }
+                        });
+                    if let Err(err) = spawned {
+                        log::debug!("failed to serve metrics request: {err}");
+                    }
+                }
+            })?;
+        log::info!("serving prometheus metrics on http://{address}/metrics");
+        Ok(address)
+    }
+}
+
+/// Shutdown of the meter provider will automatically called when the guard goes out of scope.
+#[must_use]
+struct MetricsShutdownGuard(SdkMeterProvider);
+
+impl Drop for MetricsShutdownGuard {
+    fn drop(&mut self) {
+        if let Err(err) = self.0.shutdown() {
+            log::warn!("failed to shutdown meter provider: {err}");
+        }
+    }
+}
+
+/// Records a successfully started container.
+pub(crate) fn record_container_start() {
+    static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
+    COUNTER
+        .get_or_init(|| {
+            global::meter(METER_NAME)
+                .u64_counter("container_starts")
+                .with_description("Number of containers started by the shim")
+                .init()
+        })
+        .add(1, &[]);
+}
+
+/// Records the time it took for `engine` to precompile the wasm layers of an image.
+pub(crate) fn record_compile_duration(engine: &str, duration: Duration) {
+    static HISTOGRAM: OnceLock<Histogram<f64>> = OnceLock::new();
+    HISTOGRAM
+        .get_or_init(|| {
+            global::meter(METER_NAME)
+                .f64_histogram("wasm_compile_duration")
+                .with_description("Time spent precompiling wasm layers")
+                .with_unit(Unit::new("s"))
+                .init()
+        })
+        .record(
+            duration.as_secs_f64(),
+            &[KeyValue::new("engine", engine.to_owned())],
+        );
+}
+
+fn register_memory_gauge() {
+    let gauge = global::meter(METER_NAME)
+        .u64_observable_gauge("process_memory_usage")
+        .with_description("Resident set size of the shim process")
+        .with_unit(Unit::new("By"))
+        .with_callback(|observer| {
+            if let Some(rss) = resident_memory_bytes() {
+                observer.observe(rss, &[]);
+            }
+        })
+        .init();
+    // the callback stays registered with the meter provider
+    drop(gauge);
+}
+
+#[cfg(unix)]
+fn resident_memory_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    u64::try_from(page_size)
+        .ok()
+        .map(|page_size| pages * page_size)
+}
+
+#[cfg(windows)]
+fn resident_memory_bytes() -> Option<u64> {
+    None
+}
+
+fn handle_metrics_request(mut stream: TcpStream) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(METRICS_REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(METRICS_REQUEST_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let families = PROMETHEUS_REGISTRY
+                .get()
+                .map(|registry| registry.gather())
+                .unwrap_or_default();
+            let mut body = vec![];
+            TextEncoder::new().encode(&families, &mut body)?;
+            ("200 OK", body)
+        }
+        _ => ("404 Not Found", vec![]),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        TextEncoder::new().format_type(),
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
+
+/// Sets the Prometheus exporter port from environment variables.
+fn prometheus_port_from_env() -> anyhow::Result<u16> {
+    match node_config::var(OTEL_EXPORTER_PROMETHEUS_PORT) {
+        Ok(port) if !port.is_empty() => port
+            .parse()
+            .with_context(|| format!("Invalid {OTEL_EXPORTER_PROMETHEUS_PORT} value")),
+        _ => Ok(OTEL_EXPORTER_PROMETHEUS_PORT_DEFAULT),
     }
 }
 
 /// Sets the OTLP endpoint from environment variables.
 fn traces_endpoint_from_env() -> anyhow::Result<String> {
-    Ok(env::var(OTEL_EXPORTER_OTLP_TRACES_ENDPOINT)
-        .or_else(|_| env::var(OTEL_EXPORTER_OTLP_ENDPOINT))?)
+    Ok(node_config::var(OTEL_EXPORTER_OTLP_TRACES_ENDPOINT)
+        .or_else(|_| node_config::var(OTEL_EXPORTER_OTLP_ENDPOINT))?)
+}
+
+/// Sets the OTLP logs endpoint from environment variables.
+fn logs_endpoint_from_env() -> anyhow::Result<String> {
+    Ok(node_config::var(OTEL_EXPORTER_OTLP_LOGS_ENDPOINT)
+        .or_else(|_| node_config::var(OTEL_EXPORTER_OTLP_ENDPOINT))?)
 }
 
 /// Sets the OTLP protocol from environment variables.
 fn traces_protocol_from_env() -> anyhow::Result<Protocol> {
-    let traces_protocol = env::var(OTEL_EXPORTER_OTLP_TRACES_PROTOCOL).unwrap_or(
-        env::var(OTEL_EXPORTER_OTLP_PROTOCOL)
+    protocol_from_env(OTEL_EXPORTER_OTLP_TRACES_PROTOCOL)
+}
+
+/// Sets the OTLP logs protocol from environment variables.
+fn logs_protocol_from_env() -> anyhow::Result<Protocol> {
+    protocol_from_env(OTEL_EXPORTER_OTLP_LOGS_PROTOCOL)
+}
+
+/// Reads the protocol from the signal specific `var`, falling back to `OTEL_EXPORTER_OTLP_PROTOCOL`.
+fn protocol_from_env(var: &str) -> anyhow::Result<Protocol> {
+    let protocol = node_config::var(var).unwrap_or(
+        node_config::var(OTEL_EXPORTER_OTLP_PROTOCOL)
             .unwrap_or(OTEL_EXPORTER_OTLP_PROTOCOL_DEFAULT.to_owned()),
     );
-    let protocol = match traces_protocol.as_str() {
+    let protocol = match protocol.as_str() {
         OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_PROTOBUF => Protocol::HttpBinary,
         OTEL_EXPORTER_OTLP_PROTOCOL_GRPC => Protocol::Grpc,
         OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_JSON => Protocol::HttpJson,
@@ -178,6 +596,114 @@ fn traces_protocol_from_env() -> anyhow::Result<Protocol> {
     Ok(protocol)
 }
 
+/// Builds the resource describing this shim from the environment.
+///
+/// `service.name` defaults to the shim binary name, and can be overridden with
+/// `OTEL_SERVICE_NAME` or `OTEL_RESOURCE_ATTRIBUTES`.
+fn resource_from_env() -> Resource {
+    let args: Vec<_> = env::args_os().collect();
+    let spec = env::current_dir()
+        .ok()
+        .and_then(|dir| Spec::load(dir.join("config.json")).ok());
+
+    let mut resource =
+        Resource::new(task_attributes(&args, spec.as_ref())).merge(&Resource::from_detectors(
+            Duration::from_secs(0),
+            vec![
+                Box::new(TelemetryResourceDetector),
+                Box::new(EnvResourceDetector::new()),
+            ],
+        ));
+    if let Some(service_name) = node_config::var(OTEL_SERVICE_NAME)
+        .ok()
+        .filter(|name| !name.is_empty())
+    {
+        resource = resource.merge(&Resource::new([KeyValue::new(SERVICE_NAME, service_name)]));
+    }
+    resource
+}
+
+/// Returns the attributes identifying the task the shim was started for.
+///
+/// `args` are the shim command line arguments, and `spec` is the runtime spec of the bundle
+/// the shim runs in.
+fn task_attributes(args: &[OsString], spec: Option<&Spec>) -> Vec<KeyValue> {
+    let mut attributes = vec![];
+
+    if let Some(argv0) = args.first() {
+        let name = Path::new(argv0).file_stem().unwrap_or_default();
+        attributes.push(KeyValue::new(
+            SERVICE_NAME,
+            name.to_string_lossy().into_owned(),
+        ));
+    }
+
+    if let Ok(flags) = containerd_shim::parse(args.get(1..).unwrap_or_default()) {
+        if !flags.id.is_empty() {
+            attributes.push(KeyValue::new(CONTAINER_ID, flags.id));
+        }
+        if !flags.namespace.is_empty() {
+            attributes.push(KeyValue::new(CONTAINERD_NAMESPACE, flags.namespace));
+        }
+    }
+
+    let annotations = spec.and_then(|spec| spec.annotations().as_ref());
+    for (annotation, key) in [
+        (CRI_SANDBOX_ID, CONTAINERD_SANDBOX_ID),
+        (CRI_SANDBOX_NAME, K8S_POD_NAME),
+        (CRI_SANDBOX_UID, K8S_POD_UID),
+        (CRI_SANDBOX_NAMESPACE, K8S_NAMESPACE_NAME),
+        (CRI_CONTAINER_NAME, K8S_CONTAINER_NAME),
+    ] {
+        if let Some(value) = annotations.and_then(|a| a.get(annotation)) {
+            attributes.push(KeyValue::new(key, value.clone()));
+        }
+    }
+
+    attributes
+}
+
+/// Sets the trace sampler from environment variables.
+///
+/// An invalid sampler, or sampler argument, falls back to the default with a warning, as the
+/// specification requires, rather than failing the shim.
+///
+/// https://opentelemetry.io/docs/specs/otel/configuration/sdk-environment-variables/#general-sdk-configuration
+fn traces_sampler_from_env() -> Sampler {
+    let default = || Sampler::ParentBased(Box::new(Sampler::AlwaysOn));
+    let Ok(sampler) = node_config::var(OTEL_TRACES_SAMPLER) else {
+        return default();
+    };
+    let ratio = || -> f64 {
+        let Ok(arg) = node_config::var(OTEL_TRACES_SAMPLER_ARG) else {
+            return 1.0;
+        };
+        match arg.parse::<f64>() {
+            Ok(ratio) if (0.0..=1.0).contains(&ratio) => ratio,
+            _ => {
+                log::warn!("invalid {OTEL_TRACES_SAMPLER_ARG} value {arg:?}, using 1.0");
+                1.0
+            }
+        }
+    };
+    match sampler.as_str() {
+        "always_on" => Sampler::AlwaysOn,
+        "always_off" => Sampler::AlwaysOff,
+        "traceidratio" => Sampler::TraceIdRatioBased(ratio()),
+        "parentbased_always_on" => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+        "parentbased_always_off" => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+        "parentbased_traceidratio" => {
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio())))
+        }
+        _ => {
+            log::warn!(
+                "invalid {OTEL_TRACES_SAMPLER} value {sampler:?}, using parentbased_always_on"
+            );
+            default()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use temp_env::with_vars;
@@ -354,12 +880,400 @@ mod tests {
                 (OTEL_EXPORTER_OTLP_TRACES_PROTOCOL, Some("grpc")),
                 (OTEL_EXPORTER_OTLP_PROTOCOL, Some("http/protobuf")),
             ],
+            || {
+                let traces = Config::build_from_env().unwrap().traces.unwrap();
+                assert_eq!(traces.endpoint, "trace_endpoint".to_string());
+                assert_eq!(traces.protocol, Protocol::Grpc);
+            },
+        );
+    }
+
+    #[test]
+    fn test_traces_sampler_from_env_default() {
+        with_vars::<String, &str, _, _>([], || {
+            let sampler = traces_sampler_from_env();
+            assert_eq!(
+                format!("{sampler:?}"),
+                format!("{:?}", Sampler::ParentBased(Box::new(Sampler::AlwaysOn)))
+            );
+        });
+    }
+
+    #[test]
+    fn test_traces_sampler_from_env() {
+        with_vars(
+            [
+                (OTEL_TRACES_SAMPLER, Some("parentbased_traceidratio")),
+                (OTEL_TRACES_SAMPLER_ARG, Some("0.25")),
+            ],
+            || {
+                let sampler = traces_sampler_from_env();
+                assert_eq!(
+                    format!("{sampler:?}"),
+                    format!(
+                        "{:?}",
+                        Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(0.25)))
+                    )
+                );
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_TRACES_SAMPLER, Some("traceidratio")),
+                (OTEL_TRACES_SAMPLER_ARG, None::<&str>),
+            ],
+            || {
+                let sampler = traces_sampler_from_env();
+                assert_eq!(
+                    format!("{sampler:?}"),
+                    format!("{:?}", Sampler::TraceIdRatioBased(1.0))
+                );
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_TRACES_SAMPLER, Some("always_off")),
+                (OTEL_TRACES_SAMPLER_ARG, None::<&str>),
+            ],
+            || {
+                let sampler = traces_sampler_from_env();
+                assert_eq!(format!("{sampler:?}"), format!("{:?}", Sampler::AlwaysOff));
+            },
+        );
+    }
+
+    #[test]
+    fn test_traces_sampler_from_env_invalid() {
+        with_vars(
+            [
+                (OTEL_TRACES_SAMPLER, Some("something-else")),
+                (OTEL_TRACES_SAMPLER_ARG, None::<&str>),
+            ],
+            || {
+                let sampler = traces_sampler_from_env();
+                assert_eq!(
+                    format!("{sampler:?}"),
+                    format!("{:?}", Sampler::ParentBased(Box::new(Sampler::AlwaysOn)))
+                );
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_TRACES_SAMPLER, Some("traceidratio")),
+                (OTEL_TRACES_SAMPLER_ARG, Some("1.5")),
+            ],
+            || {
+                let sampler = traces_sampler_from_env();
+                assert_eq!(
+                    format!("{sampler:?}"),
+                    format!("{:?}", Sampler::TraceIdRatioBased(1.0))
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn test_task_attributes() {
+        use oci_spec::runtime::SpecBuilder;
+
+        let args: Vec<OsString> = [
+            "/usr/local/bin/containerd-shim-wasmtime-v1",
+            "-namespace",
+            "k8s.io",
+            "-id",
+            "container-id",
+            "start",
+        ]
+        .into_iter()
+        .map(Into::into)
+        .collect();
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([
+                (CRI_SANDBOX_ID.to_string(), "sandbox-id".to_string()),
+                (CRI_SANDBOX_NAME.to_string(), "pod-name".to_string()),
+                (CRI_SANDBOX_NAMESPACE.to_string(), "default".to_string()),
+                (CRI_CONTAINER_NAME.to_string(), "wasm".to_string()),
+            ]))
+            .build()
+            .unwrap();
+
+        let resource = Resource::new(task_attributes(&args, Some(&spec)));
+        let get = |key: &'static str| resource.get(key.into()).map(|v| v.to_string());
+
+        assert_eq!(get(SERVICE_NAME).unwrap(), "containerd-shim-wasmtime-v1");
+        assert_eq!(get(CONTAINER_ID).unwrap(), "container-id");
+        assert_eq!(get(CONTAINERD_NAMESPACE).unwrap(), "k8s.io");
+        assert_eq!(get(CONTAINERD_SANDBOX_ID).unwrap(), "sandbox-id");
+        assert_eq!(get(K8S_POD_NAME).unwrap(), "pod-name");
+        assert_eq!(get(K8S_NAMESPACE_NAME).unwrap(), "default");
+        assert_eq!(get(K8S_CONTAINER_NAME).unwrap(), "wasm");
+        assert!(get(K8S_POD_UID).is_none());
+    }
+
+    #[test]
+    fn test_resource_from_env_service_name() {
+        with_vars(
+            [
+                (OTEL_SERVICE_NAME, Some("my-shim")),
+                (
+                    "OTEL_RESOURCE_ATTRIBUTES",
+                    Some("service.name=other,team=wasm"),
+                ),
+            ],
+            || {
+                let resource = resource_from_env();
+                let get = |key: &'static str| resource.get(key.into()).map(|v| v.to_string());
+                assert_eq!(get(SERVICE_NAME).unwrap(), "my-shim");
+                assert_eq!(get("team").unwrap(), "wasm");
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_SERVICE_NAME, None::<&str>),
+                ("OTEL_RESOURCE_ATTRIBUTES", None::<&str>),
+            ],
+            || {
+                let resource = resource_from_env();
+                assert!(resource.get(SERVICE_NAME.into()).is_some());
+            },
+        );
+    }
+
+    #[test]
+    fn test_logs_enabled() {
+        with_vars(
+            [
+                (OTEL_LOGS_EXPORTER, Some("otlp")),
+                (OTEL_SDK_DISABLED, None::<&str>),
+            ],
+            || {
+                assert!(logs_enabled());
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_LOGS_EXPORTER, Some("none")),
+                (OTEL_SDK_DISABLED, None::<&str>),
+            ],
+            || {
+                assert!(!logs_enabled());
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_LOGS_EXPORTER, Some("otlp")),
+                (OTEL_SDK_DISABLED, Some("true")),
+            ],
+            || {
+                assert!(!logs_enabled());
+            },
+        );
+    }
+
+    #[test]
+    fn test_build_with_logs() {
+        with_vars(
+            [
+                (OTEL_EXPORTER_OTLP_ENDPOINT, Some("general_endpoint")),
+                (OTEL_EXPORTER_OTLP_LOGS_ENDPOINT, Some("logs_endpoint")),
+                (OTEL_EXPORTER_OTLP_LOGS_PROTOCOL, Some("grpc")),
+                (OTEL_LOGS_EXPORTER, Some("otlp")),
+            ],
             || {
                 let config = Config::build_from_env().unwrap();
-                assert_eq!(config.traces_endpoint, "trace_endpoint".to_string());
-                assert_eq!(config.traces_protocol, Protocol::Grpc);
+                let logs = config.logs.unwrap();
+                assert_eq!(logs.endpoint, "logs_endpoint".to_string());
+                assert_eq!(logs.protocol, Protocol::Grpc);
+                assert_eq!(config.traces.unwrap().protocol, Protocol::HttpBinary);
+            },
+        );
+    }
+
+    #[test]
+    fn test_build_logs_without_traces() {
+        with_vars(
+            [
+                (OTEL_EXPORTER_OTLP_TRACES_ENDPOINT, None),
+                (OTEL_EXPORTER_OTLP_ENDPOINT, None),
+                (OTEL_EXPORTER_OTLP_LOGS_ENDPOINT, Some("logs_endpoint")),
+                (OTEL_LOGS_EXPORTER, Some("otlp")),
+            ],
+            || {
+                assert!(!traces_enabled());
+                let config = Config::build_from_env().unwrap();
+                assert!(config.traces.is_none());
+                assert_eq!(config.logs.unwrap().endpoint, "logs_endpoint".to_string());
+            },
+        );
+    }
+
+    #[test]
+    fn test_build_without_logs() {
+        with_vars(
+            [
+                (OTEL_EXPORTER_OTLP_ENDPOINT, Some("general_endpoint")),
+                (OTEL_LOGS_EXPORTER, None::<&str>),
+            ],
+            || {
+                let config = Config::build_from_env().unwrap();
+                assert!(config.logs.is_none());
+            },
+        );
+    }
+
+    #[test]
+    fn test_metrics_enabled() {
+        with_vars(
+            [
+                (OTEL_METRICS_EXPORTER, Some("prometheus")),
+                (OTEL_SDK_DISABLED, None::<&str>),
+            ],
+            || {
+                assert!(metrics_enabled());
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_METRICS_EXPORTER, Some("otlp")),
+                (OTEL_SDK_DISABLED, None::<&str>),
+            ],
+            || {
+                assert!(!metrics_enabled());
+            },
+        );
+
+        with_vars(
+            [
+                (OTEL_METRICS_EXPORTER, None::<&str>),
+                (OTEL_SDK_DISABLED, None::<&str>),
+            ],
+            || {
+                assert!(!metrics_enabled());
             },
         );
+
+        with_vars(
+            [
+                (OTEL_METRICS_EXPORTER, Some("prometheus")),
+                (OTEL_SDK_DISABLED, Some("true")),
+            ],
+            || {
+                assert!(!metrics_enabled());
+            },
+        );
+    }
+
+    #[test]
+    fn test_metrics_config_from_env() {
+        with_vars(
+            [
+                (OTEL_EXPORTER_PROMETHEUS_HOST, Some("0.0.0.0")),
+                (OTEL_EXPORTER_PROMETHEUS_PORT, Some("9000")),
+            ],
+            || {
+                let config = MetricsConfig::build_from_env().unwrap();
+                assert_eq!(config.host, "0.0.0.0");
+                assert_eq!(config.port, 9000);
+            },
+        );
+    }
+
+    #[test]
+    fn test_metrics_config_default() {
+        with_vars::<String, &str, _, _>([], || {
+            let config = MetricsConfig::build_from_env().unwrap();
+            assert_eq!(config.host, OTEL_EXPORTER_PROMETHEUS_HOST_DEFAULT);
+            assert_eq!(config.port, OTEL_EXPORTER_PROMETHEUS_PORT_DEFAULT);
+        });
+    }
+
+    #[test]
+    fn test_serve_metrics() -> anyhow::Result<()> {
+        use std::io::Read as _;
+
+        let config = MetricsConfig {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            resource: Resource::empty(),
+        };
+        let address = config.serve()?;
+        assert_ne!(address.port(), 0);
+
+        // a client that never sends its request doesn't block the others
+        let _idle = TcpStream::connect(address)?;
+        let mut client = TcpStream::connect(address)?;
+        client.set_read_timeout(Some(METRICS_REQUEST_TIMEOUT / 2))?;
+        client.write_all(b"GET /metrics HTTP/1.1\r\n\r\n")?;
+        let mut response = String::new();
+        client.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics_config_invalid_port() {
+        with_vars(
+            [(OTEL_EXPORTER_PROMETHEUS_PORT, Some("not-a-port"))],
+            || {
+                let result = MetricsConfig::build_from_env();
+                assert!(result.is_err());
+            },
+        );
+    }
+
+    #[test]
+    fn test_build_with_unix_endpoint() {
+        with_vars(
+            [
+                (
+                    OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
+                    Some("unix:///run/otel/collector.sock"),
+                ),
+                (OTEL_EXPORTER_OTLP_TRACES_PROTOCOL, Some("grpc")),
+            ],
+            || {
+                let traces = Config::build_from_env().unwrap().traces.unwrap();
+                assert_eq!(traces.endpoint, "unix:///run/otel/collector.sock");
+                assert_eq!(traces.protocol, Protocol::Grpc);
+            },
+        );
+    }
+
+    #[test]
+    fn test_build_with_unix_endpoint_http_fail() {
+        with_vars(
+            [
+                (
+                    OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
+                    Some("unix:///run/otel/collector.sock"),
+                ),
+                (OTEL_EXPORTER_OTLP_TRACES_PROTOCOL, Some("http/protobuf")),
+            ],
+            || {
+                let result = Config::build_from_env();
+                assert!(result.is_err());
+            },
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_init_tracer_with_unix_endpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let traces = TracesConfig {
+            endpoint: format!("unix://{}", dir.path().join("otel.sock").display()),
+            protocol: Protocol::Grpc,
+            sampler: Sampler::AlwaysOn,
+            resource: Resource::empty(),
+        };
+        assert!(traces.init().is_ok());
     }
 
     #[test]
@@ -0,0 +1,341 @@
+//! Recovery of the tasks of a shim after the shim is restarted.
+//!
+//! The shim persists the state of its running tasks, their pid, stdio and exec processes, to
+//! [`STATE_FILE`] in their bundle, when they start and whenever it changes. A shim started again
+//! in the bundle of a task, e.g., after it crashed or drained, re-attaches to the process of the
+//! task if it is still running, and serves Wait, Kill and Delete for it again. The process writes
+//! its exit code to a FIFO of the bundle, since the new shim isn't its parent.
+//!
+//! Once the task exits, its exit status is persisted to [`EXIT_FILE`] before anything else, so
+//! that a shim restarted after the task exited, e.g., if the shim crashed before publishing the
+//! exit, still returns it from Wait and Delete.
+//!
+//! When containerd deletes the bundle of a task whose shim is gone, the processes recorded in the
+//! state of the task are killed with it, unless the task has exited. A process is only killed if
+//! its start time is the recorded one, so that a process that reused its pid is never killed.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use containerd_shim::protos::types::task::Status;
+use serde::{Deserialize, Serialize};
+
+use crate::sandbox::shim::instance_data::InstanceData;
+use crate::sandbox::Instance;
+
+/// The file of the bundle of a task the state of the task is persisted to
+pub const STATE_FILE: &str = "runwasi-state.json";
+
+/// The file of the bundle of a task the exit status of the task is persisted to
+pub const EXIT_FILE: &str = "runwasi-exit.json";
+
+/// The state of a running task, persisted to its bundle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskRecord {
+    pub id: String,
+    pub pid: u32,
+    pub status: String,
+    pub stdin: PathBuf,
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+    #[serde(default)]
+    pub terminal: bool,
+    // the pids of the exec processes of the task, by exec id
+    pub execs: Vec<(String, u32)>,
+    // the directory the task was checkpointed to, when the shim drained with the `checkpoint` mode
+    pub checkpoint: Option<PathBuf>,
+    // the time the shim drained, if it did
+    pub drained_at: Option<String>,
+    // the start times of the processes of the task, in clock ticks after boot, by pid
+    #[serde(default)]
+    pub start_times: Vec<(u32, u64)>,
+}
+
+impl TaskRecord {
+    /// Returns the state of the task `id`, if it is running
+    pub(super) fn of<T: Instance>(id: &str, instance: &InstanceData<T>) -> Option<Self> {
+        let (status, _) = instance.status();
+        let (Some(pid), Status::RUNNING | Status::PAUSED) = (instance.pid(), status) else {
+            return None;
+        };
+        let cfg = instance.config();
+        let execs: Vec<_> = instance
+            .execs()
+            .into_iter()
+            .filter(|(_, exec)| exec.status().0 == Status::RUNNING)
+            .filter_map(|(exec_id, exec)| Some((exec_id, exec.pid()?)))
+            .collect();
+        let start_times = execs
+            .iter()
+            .map(|(_, pid)| *pid)
+            .chain([pid])
+            .filter_map(|pid| Some((pid, process_start_time(pid)?)))
+            .collect();
+        Some(Self {
+            id: id.to_string(),
+            pid,
+            status: format!("{status:?}").to_lowercase(),
+            stdin: cfg.get_stdin().to_path_buf(),
+            stdout: cfg.get_stdout().to_path_buf(),
+            stderr: cfg.get_stderr().to_path_buf(),
+            terminal: cfg.get_terminal(),
+            execs,
+            checkpoint: None,
+            drained_at: None,
+            start_times,
+        })
+    }
+
+    pub fn write(&self, bundle: &Path) -> anyhow::Result<()> {
+        write_atomic(bundle, STATE_FILE, &serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn read(bundle: &Path) -> anyhow::Result<Option<Self>> {
+        read(bundle, STATE_FILE)
+    }
+
+    pub fn remove(bundle: &Path) {
+        let _ = fs::remove_file(bundle.join(STATE_FILE));
+    }
+}
+
+/// The exit status of a task, persisted to its bundle once it exits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExitRecord {
+    pub pid: u32,
+    pub exit_status: u32,
+    // RFC 3339, with nanoseconds
+    pub exited_at: String,
+}
+
+impl ExitRecord {
+    pub fn new(pid: u32, exit_status: u32, exited_at: DateTime<Utc>) -> Self {
+        Self {
+            pid,
+            exit_status,
+            exited_at: exited_at.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        }
+    }
+
+    /// Returns the exit status and time of the task
+    pub fn exit(&self) -> anyhow::Result<(u32, DateTime<Utc>)> {
+        let exited_at = DateTime::parse_from_rfc3339(&self.exited_at)
+            .with_context(|| format!("invalid exit time {:?}", self.exited_at))?;
+        Ok((self.exit_status, exited_at.with_timezone(&Utc)))
+    }
+
+    pub fn write(&self, bundle: &Path) -> anyhow::Result<()> {
+        write_atomic(bundle, EXIT_FILE, &serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn read(bundle: &Path) -> anyhow::Result<Option<Self>> {
+        read(bundle, EXIT_FILE)
+    }
+
+    pub fn remove(bundle: &Path) {
+        let _ = fs::remove_file(bundle.join(EXIT_FILE));
+    }
+}
+
+// Writes `file` in `bundle` so that it is either fully written or not at all, even if the shim
+// or the host crashes: the content is written to a temporary file, synced, and renamed.
+fn write_atomic(bundle: &Path, file: &str, content: &[u8]) -> anyhow::Result<()> {
+    let path = bundle.join(file);
+    let tmp = bundle.join(format!(".{file}.tmp"));
+    let res = File::create(&tmp)
+        .and_then(|mut f| f.write_all(content).and_then(|_| f.sync_all()))
+        .and_then(|_| fs::rename(&tmp, &path))
+        .and_then(|_| File::open(bundle)?.sync_all());
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res.with_context(|| format!("failed to write {path:?}"))
+}
+
+fn read<T: for<'de> Deserialize<'de>>(bundle: &Path, file: &str) -> anyhow::Result<Option<T>> {
+    let path = bundle.join(file);
+    match fs::read(&path) {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to read {path:?}")),
+    }
+}
+
+/// Persists the state of the task `id` to its bundle, or removes it once the task isn't running.
+pub(super) fn persist<T: Instance>(id: &str, instance: &InstanceData<T>) {
+    let bundle = instance.config().get_bundle();
+    let Some(record) = TaskRecord::of(id, instance) else {
+        TaskRecord::remove(bundle);
+        return;
+    };
+    if let Err(err) = record.write(bundle) {
+        log::warn!("failed to persist the state of task {id}: {err:#}");
+    }
+}
+
+/// Persists the exit status of the task whose process `pid` exited, logging any failure.
+pub(super) fn persist_exit(bundle: &Path, pid: u32, exit_status: u32, exited_at: DateTime<Utc>) {
+    if let Err(err) = ExitRecord::new(pid, exit_status, exited_at).write(bundle) {
+        log::warn!("failed to persist the exit of process {pid}: {err:#}");
+    }
+}
+
+/// Returns the start time of the process `pid`, in clock ticks after boot, from procfs.
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    parse_start_time(&stat)
+}
+
+fn parse_start_time(stat: &str) -> Option<u64> {
+    // the process name can contain spaces, the fields start after its closing parenthesis, and
+    // the start time is the 22nd field, i.e., the 20th after the name
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Kills the process `pid` if it is the one started at `start_time`, rather than a process that
+/// reused its pid. The process is held with a pidfd while its start time is checked, so that its
+/// pid can't be reused in between. Returns whether the process was killed.
+#[cfg(unix)]
+fn kill_started_at(pid: u32, start_time: u64) -> std::io::Result<bool> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        let err = std::io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ESRCH) => Ok(false),
+            _ => Err(err),
+        };
+    }
+    let pidfd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+    if process_start_time(pid) != Some(start_time) {
+        return Ok(false);
+    }
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            libc::SIGKILL,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+/// Kills the processes of the task of `bundle` left running by a shim that is gone, when
+/// containerd deletes the task without its shim.
+#[cfg(unix)]
+pub(crate) fn cleanup(bundle: &Path) {
+    // the pids of a task that exited may have been reused
+    if matches!(ExitRecord::read(bundle), Ok(Some(_))) {
+        ExitRecord::remove(bundle);
+        TaskRecord::remove(bundle);
+        return;
+    }
+    let record = match TaskRecord::read(bundle) {
+        Ok(Some(record)) => record,
+        Ok(None) => return,
+        Err(err) => {
+            log::warn!("invalid state in bundle {bundle:?}: {err:#}");
+            TaskRecord::remove(bundle);
+            return;
+        }
+    };
+    let pids = record.execs.iter().map(|(_, pid)| *pid);
+    for pid in pids.chain([record.pid]) {
+        let id = &record.id;
+        let start_time = record.start_times.iter().find(|(p, _)| *p == pid);
+        let Some((_, start_time)) = start_time else {
+            log::warn!("not killing process {pid} of task {id}, its start time is unknown");
+            continue;
+        };
+        match kill_started_at(pid, *start_time) {
+            Ok(true) => log::info!("killed process {pid} of task {id}"),
+            Ok(false) => log::info!("process {pid} of task {id} already exited"),
+            Err(err) => log::warn!("failed to kill process {pid} of task {id}: {err}"),
+        }
+    }
+    TaskRecord::remove(bundle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_record() -> anyhow::Result<()> {
+        let bundle = tempfile::tempdir()?;
+        assert_eq!(TaskRecord::read(bundle.path())?, None);
+
+        let record = TaskRecord {
+            id: "task".to_string(),
+            pid: 42,
+            status: "running".to_string(),
+            stdin: PathBuf::new(),
+            stdout: PathBuf::from("/run/stdout"),
+            stderr: PathBuf::from("/run/stderr"),
+            terminal: false,
+            execs: vec![("exec".to_string(), 43)],
+            checkpoint: Some(bundle.path().join("checkpoint")),
+            drained_at: None,
+            start_times: vec![(42, 1234)],
+        };
+        record.write(bundle.path())?;
+        assert_eq!(TaskRecord::read(bundle.path())?, Some(record));
+
+        TaskRecord::remove(bundle.path());
+        assert_eq!(TaskRecord::read(bundle.path())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_start_time() {
+        let stat = "42 (my (app)) S 1 42 42 0 -1 4194560 100 0 0 0 5 3 0 0 20 0 1 0 98765 1000";
+        assert_eq!(parse_start_time(stat), Some(98765));
+        assert_eq!(parse_start_time("42 (app) S 1"), None);
+        assert!(process_start_time(std::process::id()).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_kill_started_at() -> anyhow::Result<()> {
+        let mut child = std::process::Command::new("sleep").arg("60").spawn()?;
+        let start_time = process_start_time(child.id()).context("no start time")?;
+
+        // a process started at another time is left running
+        assert!(!kill_started_at(child.id(), start_time + 1)?);
+        assert!(child.try_wait()?.is_none());
+
+        assert!(kill_started_at(child.id(), start_time)?);
+        child.wait()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_record() -> anyhow::Result<()> {
+        let bundle = tempfile::tempdir()?;
+        assert_eq!(ExitRecord::read(bundle.path())?, None);
+
+        let exited_at = Utc::now();
+        persist_exit(bundle.path(), 42, 137, exited_at);
+        let record = ExitRecord::read(bundle.path())?.context("no exit in the bundle")?;
+        assert_eq!(record.pid, 42);
+        assert_eq!(record.exit()?, (137, exited_at));
+
+        // only the record is left in the bundle
+        let files: Vec<_> = fs::read_dir(bundle.path())?.collect::<Result<_, _>>()?;
+        assert_eq!(files.len(), 1);
+
+        ExitRecord::remove(bundle.path());
+        assert_eq!(ExitRecord::read(bundle.path())?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Notifications to systemd, for the shims supervised by systemd, e.g., the `containerd-{engine}d`
+//! daemon run as a service, or the shims started by a node service, with the [sd_notify] protocol.
+//!
+//! With `NOTIFY_SOCKET` in its environment, the shim sends `READY=1` once it serves its API, and
+//! `STOPPING=1` when it shuts down. With `WatchdogSec=` in the service, systemd also sets
+//! `WATCHDOG_USEC`, and the shim sends `WATCHDOG=1` twice per interval while it is healthy, so
+//! that systemd restarts a hung shim.
+//!
+//! The task shims inherit the environment of containerd, which may be a service notifying
+//! systemd itself, so they only notify systemd when `MAINPID` or `WATCHDOG_PID` is their pid.
+//! The variables are removed from the environment of the shim, so that the processes it starts,
+//! e.g., the containers, never notify systemd on its behalf.
+//!
+//! [sd_notify]: https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html
+
+use std::ffi::{OsStr, OsString};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+use std::{env, io, thread};
+
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+const MAINPID_ENV: &str = "MAINPID";
+
+/// The notification socket of systemd, for a shim supervised by systemd.
+#[derive(Debug, Clone)]
+pub(crate) struct Notifier {
+    socket: OsString,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Takes the notification socket, and the watchdog, of systemd out of the environment of the
+    /// shim. Returns `None` unless the shim is supervised by systemd: the `daemon`, or the process
+    /// of `MAINPID` or `WATCHDOG_PID`.
+    /// Must be called before the shim starts any thread, as it changes the environment.
+    pub(crate) fn take(daemon: bool) -> Option<Self> {
+        let socket = env::var_os(NOTIFY_SOCKET_ENV).filter(|socket| !socket.is_empty());
+        let usec = env::var(WATCHDOG_USEC_ENV).ok();
+        let watchdog_pid = env::var(WATCHDOG_PID_ENV).ok();
+        let main_pid = env::var(MAINPID_ENV).ok();
+        for var in [NOTIFY_SOCKET_ENV, WATCHDOG_USEC_ENV, WATCHDOG_PID_ENV] {
+            env::remove_var(var);
+        }
+
+        let supervised = daemon || [&watchdog_pid, &main_pid].into_iter().any(is_self);
+        if !supervised {
+            if socket.is_some() {
+                log::debug!("ignoring the notification socket of systemd of another process");
+            }
+            return None;
+        }
+        let watchdog = usec.and_then(|usec| watchdog_interval(&usec, watchdog_pid.as_deref()));
+        Some(Self {
+            socket: socket?,
+            watchdog,
+        })
+    }
+
+    /// Notifies systemd that the shim is ready, and pings the watchdog of systemd from a
+    /// background thread for as long as `healthy` returns `true`.
+    pub(crate) fn ready(&self, healthy: impl Fn() -> bool + Send + 'static) {
+        match send(&self.socket, "READY=1") {
+            Ok(()) => log::info!("notified systemd that the shim is ready"),
+            Err(err) => {
+                log::warn!("failed to notify systemd that the shim is ready: {err}");
+                return;
+            }
+        }
+
+        let Some(interval) = self.watchdog else {
+            return;
+        };
+        let socket = self.socket.clone();
+        let spawned = thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                if !healthy() {
+                    log::warn!("the shim is unhealthy, not pinging the watchdog of systemd");
+                    continue;
+                }
+                if let Err(err) = send(&socket, "WATCHDOG=1") {
+                    log::warn!("failed to ping the watchdog of systemd: {err}");
+                }
+            });
+        match spawned {
+            Ok(_) => log::info!("pinging the watchdog of systemd every {interval:?}"),
+            Err(err) => log::warn!("failed to start pinging the watchdog of systemd: {err}"),
+        }
+    }
+
+    /// Notifies systemd that the shim is shutting down.
+    pub(crate) fn stopping(&self) {
+        if let Err(err) = send(&self.socket, "STOPPING=1") {
+            log::warn!("failed to notify systemd that the shim is stopping: {err}");
+        }
+    }
+}
+
+/// Returns whether `pid` is the pid of the shim
+fn is_self(pid: &Option<String>) -> bool {
+    pid.as_deref()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id())
+}
+
+fn send(socket: &OsStr, state: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        // a socket in the abstract namespace
+        Some(name) => datagram.connect_addr(&SocketAddr::from_abstract_name(name)?)?,
+        None => datagram.connect(socket)?,
+    }
+    datagram.send(state.as_bytes())?;
+    Ok(())
+}
+
+/// Returns the interval the shim pings the watchdog at, half of its timeout, if the watchdog
+/// watches the shim rather than another process, e.g., the one that started the shim.
+fn watchdog_interval(usec: &str, pid: Option<&str>) -> Option<Duration> {
+    let usec = usec.trim().parse::<u64>().ok().filter(|usec| *usec > 0)?;
+    match pid.map(|pid| pid.trim().parse::<u32>()) {
+        Some(Ok(pid)) if pid == std::process::id() => {}
+        Some(_) => return None,
+        None => {}
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_send() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path)?;
+        send(path.as_os_str(), "READY=1")?;
+
+        let name = format!("runwasi-test-notify-{}", std::process::id());
+        let abstract_socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name)?)?;
+        send(OsStr::new(&format!("@{name}")), "WATCHDOG=1")?;
+
+        let mut buf = [0; 16];
+        let len = socket.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = abstract_socket.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        let pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_interval("10000000", None),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            watchdog_interval("10000000", Some(&pid)),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(watchdog_interval("10000000", Some("1")), None);
+        assert_eq!(watchdog_interval("0", None), None);
+        assert_eq!(watchdog_interval("infinity", None), None);
+    }
+
+    #[test]
+    fn test_take() {
+        let pid = std::process::id().to_string();
+        fn env<'a>(
+            watchdog_pid: Option<&'a str>,
+            main_pid: Option<&'a str>,
+        ) -> [(&'static str, Option<&'a str>); 4] {
+            [
+                (NOTIFY_SOCKET_ENV, Some("@notify")),
+                (WATCHDOG_USEC_ENV, Some("10000000")),
+                (WATCHDOG_PID_ENV, watchdog_pid),
+                (MAINPID_ENV, main_pid),
+            ]
+        }
+
+        // a task shim started by containerd doesn't notify systemd on its behalf
+        temp_env::with_vars(env(None, None), || {
+            assert!(Notifier::take(false).is_none());
+            assert!(env::var_os(NOTIFY_SOCKET_ENV).is_none());
+        });
+        temp_env::with_vars(env(Some("1"), Some("1")), || {
+            assert!(Notifier::take(false).is_none());
+        });
+
+        temp_env::with_vars(env(None, Some(&pid)), || {
+            let notifier = Notifier::take(false).expect("missing notifier");
+            assert_eq!(notifier.socket, "@notify");
+            assert_eq!(notifier.watchdog, Some(Duration::from_secs(5)));
+            assert!(env::var_os(NOTIFY_SOCKET_ENV).is_none());
+            assert!(env::var_os(WATCHDOG_USEC_ENV).is_none());
+        });
+        temp_env::with_vars(env(Some(&pid), None), || {
+            assert!(Notifier::take(false).is_some());
+        });
+        temp_env::with_vars(env(None, None), || {
+            assert!(Notifier::take(true).is_some());
+        });
+    }
+}
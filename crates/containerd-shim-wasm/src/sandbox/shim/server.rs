@@ -0,0 +1,143 @@
+//! The task server of the shim, serving the Sandbox API next to the Task API.
+//!
+//! `containerd_shim::run` only registers the Task service on the socket containerd connects to,
+//! so the shim serves both services itself, like `containerd-shim` does for the Task service.
+
+use std::env;
+use std::sync::Arc;
+
+use containerd_shim::protos::sandbox_sync::create_sandbox;
+use containerd_shim::protos::shim::shim_ttrpc::create_task;
+use containerd_shim::publisher::RemotePublisher;
+use containerd_shim::util::read_address;
+use containerd_shim::{monitor, parse, Config, Flags, Shim};
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use signal_hook::consts::{SIGCHLD, SIGINT, SIGPIPE, SIGTERM};
+use signal_hook::iterator::Signals;
+use ttrpc::Server;
+
+use crate::sandbox::instance::Instance;
+use crate::sandbox::shim::controller::SandboxController;
+use crate::sandbox::shim::{introspection, logger, sd_notify, Cli};
+
+// The file descriptor of the socket containerd connects to, passed by the shim that started
+// the task server, see `containerd_shim::spawn`
+const SOCKET_FD: i32 = 3;
+
+const TTRPC_ADDRESS: &str = "TTRPC_ADDRESS";
+
+/// Runs the shim of `runtime_id` with `Cli<I>`, like `containerd_shim::run`, with the Sandbox
+/// service next to the Task service in its task server.
+pub(crate) fn run<I>(runtime_id: &str, config: Option<Config>)
+where
+    I: 'static + Instance + Sync + Send,
+    I::Engine: Default,
+{
+    let os_args: Vec<_> = env::args_os().collect();
+    let flags = match parse(&os_args[1..]) {
+        Ok(flags) => flags,
+        Err(err) => {
+            eprintln!("{runtime_id}: {err:?}");
+            std::process::exit(1);
+        }
+    };
+
+    // the start and delete actions don't serve anything
+    if !flags.action.is_empty() {
+        return containerd_shim::run::<Cli<I>>(runtime_id, config);
+    }
+
+    if let Err(err) = serve::<I>(runtime_id, flags, config) {
+        eprintln!("{runtime_id}: {err:#}");
+        std::process::exit(1);
+    }
+}
+
+fn serve<I>(runtime_id: &str, flags: Flags, config: Option<Config>) -> anyhow::Result<()>
+where
+    I: 'static + Instance + Sync + Send,
+    I::Engine: Default,
+{
+    anyhow::ensure!(
+        !flags.namespace.is_empty(),
+        "shim namespace cannot be empty"
+    );
+    let ttrpc_address = env::var(TTRPC_ADDRESS)?;
+
+    let mut config = config.unwrap_or_default();
+
+    // the environment is changed, and the signals are registered, before any thread is started
+    let notifier = sd_notify::Notifier::take(false);
+    let signals = Signals::new([SIGTERM, SIGINT, SIGPIPE])?;
+    if !config.no_reaper {
+        signals.add_signal(SIGCHLD)?;
+    }
+    if !config.no_sub_reaper {
+        nix::sys::prctl::set_child_subreaper(true)?;
+    }
+
+    let mut cli = Cli::<I>::new(runtime_id, &flags, &mut config);
+    if !config.no_setup_logger {
+        logger::init(&flags, &config.default_log_level)?;
+    }
+
+    let publisher = RemotePublisher::new(ttrpc_address)?;
+    let local = cli.create_task_service(publisher);
+    let instances = local.instances.clone();
+    let controller = SandboxController::new(&local);
+
+    let mut server = Server::new()
+        .add_listener(SOCKET_FD)?
+        .register_service(create_task(Arc::new(Box::new(local))))
+        .register_service(create_sandbox(Arc::new(Box::new(controller))));
+    server.start()?;
+
+    log::info!("Shim successfully started, waiting for exit signal...");
+    std::thread::spawn(move || handle_signals(signals));
+    // a hung task service holds the instances, and stops the pings of the watchdog
+    if let Some(notifier) = &notifier {
+        notifier.ready(move || introspection::healthy(&instances));
+    }
+    cli.wait();
+
+    log::info!("Shutting down shim instance");
+    if let Some(notifier) = &notifier {
+        notifier.stopping();
+    }
+    server.shutdown();
+
+    // the socket is left behind if the shim is killed
+    let address = read_address()?;
+    let path = address.strip_prefix("unix://").unwrap_or(&address);
+    if let Err(err) = std::fs::remove_file(path) {
+        log::warn!("failed to remove socket {path}: {err}");
+    }
+    Ok(())
+}
+
+// Reaps the children of the shim, and notifies their exit to the instances waiting for them
+fn handle_signals(mut signals: Signals) {
+    for signal in signals.forever() {
+        if signal != SIGCHLD {
+            log::debug!("received signal {signal}");
+            continue;
+        }
+        loop {
+            let (pid, exit_code) = match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(pid, status)) => (pid, status),
+                Ok(WaitStatus::Signaled(pid, signal, _)) => (pid, 128 + signal as i32),
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+                Ok(_) => continue,
+                Err(err) => {
+                    log::warn!("failed to reap the children of the shim: {err}");
+                    break;
+                }
+            };
+            if let Err(err) = monitor::monitor_notify_by_pid(pid.as_raw(), exit_code) {
+                log::error!("failed to notify the exit of {pid}: {err}");
+            }
+        }
+    }
+}
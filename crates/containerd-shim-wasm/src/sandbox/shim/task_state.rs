@@ -6,6 +6,7 @@ pub(super) enum TaskState {
     Created,
     Starting,
     Started,
+    Paused,
     Exited,
     Deleting,
 }
@@ -24,11 +25,39 @@ impl TaskState {
     pub fn kill(&mut self) -> Result<()> {
         *self = match self {
             Self::Started => Ok(Self::Started),
+            Self::Paused => Ok(Self::Paused),
             _ => state_transition_error(*self, "Killing"),
         }?;
         Ok(())
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn pause(&mut self) -> Result<()> {
+        *self = match self {
+            Self::Started => Ok(Self::Paused),
+            _ => state_transition_error(*self, Self::Paused),
+        }?;
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn resume(&mut self) -> Result<()> {
+        *self = match self {
+            Self::Paused => Ok(Self::Started),
+            _ => state_transition_error(*self, Self::Started),
+        }?;
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    pub fn checkpoint(&mut self) -> Result<()> {
+        *self = match self {
+            Self::Started => Ok(Self::Started),
+            _ => state_transition_error(*self, "Checkpointing"),
+        }?;
+        Ok(())
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     pub fn delete(&mut self) -> Result<()> {
         *self = match self {
@@ -50,7 +79,7 @@ impl TaskState {
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     pub fn stop(&mut self) -> Result<()> {
         *self = match self {
-            Self::Started | Self::Starting => Ok(Self::Exited),
+            Self::Started | Self::Starting | Self::Paused => Ok(Self::Exited),
             // This is for potential failure cases where we want delete to be able to be retried.
             Self::Deleting => Ok(Self::Exited),
             _ => state_transition_error(*self, Self::Exited),
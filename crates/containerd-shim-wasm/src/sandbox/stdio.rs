@@ -1,11 +1,22 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
 use std::io::ErrorKind::NotFound;
-use std::io::{Error, Result};
-use std::path::Path;
-use std::sync::{Arc, OnceLock};
+use std::io::{Error, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 
+use serde::{Deserialize, Serialize};
+
+use super::instance_utils::Options;
 use super::InstanceConfig;
 use crate::sys::stdio::*;
 
+#[cfg(unix)]
+mod attach;
+
+/// The default size of the last output of a container kept to replay to the readers of its fifos
+const DEFAULT_LOG_BUFFER_SIZE: usize = 64 * 1024;
+
 #[derive(Default, Clone)]
 pub struct Stdio {
     pub stdin: Stdin,
@@ -91,19 +102,12 @@ impl<const FD: StdioRawFd> StdioStream<FD> {
 }
 
 impl<const FD: StdioRawFd> StdioStream<FD> {
-    fn try_from_path(path: impl AsRef<Path>) -> Result<Self> {
-        let path = path.as_ref();
-        if path.as_os_str().is_empty() {
-            return Ok(Self(Arc::default()));
-        }
-
-        let fd = match StdioOwnedFd::try_from_path(path) {
-            Err(err) if err.kind() == NotFound => Default::default(),
-            Err(err) => return Err(err),
-            Ok(fd) => fd,
-        };
+    pub(crate) fn from_owned_fd(fd: StdioOwnedFd) -> Self {
+        Self(Arc::new(fd))
+    }
 
-        Ok(Self(Arc::new(fd)))
+    pub(crate) fn try_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self(Arc::new(open_fifo(path.as_ref())?)))
     }
 }
 
@@ -111,9 +115,270 @@ pub type Stdin = StdioStream<STDIN_FILENO>;
 pub type Stdout = StdioStream<STDOUT_FILENO>;
 pub type Stderr = StdioStream<STDERR_FILENO>;
 
+fn open_fifo(path: &Path) -> Result<StdioOwnedFd> {
+    if path.as_os_str().is_empty() {
+        return Ok(StdioOwnedFd::default());
+    }
+    #[cfg(any(test, feature = "fault-injection"))]
+    super::fault::stdio_open(path)?;
+    match StdioOwnedFd::try_from_path(path) {
+        Err(err) if err.kind() == NotFound => Ok(StdioOwnedFd::default()),
+        res => res,
+    }
+}
+
+/// An output stream of a container
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Where the stdout and stderr of a container go.
+///
+/// The driver opens the file descriptors the container writes to, so that the executor doesn't
+/// depend on the destination of the logs.
+pub(crate) trait LogDriver: Send + Sync {
+    /// Opens the destination of a stream, `path` is the one containerd created for it, if any.
+    fn open(&self, stream: Stream, path: &Path) -> Result<StdioOwnedFd>;
+
+    fn stdout(&self, path: &Path) -> Result<Stdout> {
+        Ok(StdioStream::from_owned_fd(self.open(Stream::Stdout, path)?))
+    }
+
+    fn stderr(&self, path: &Path) -> Result<Stderr> {
+        Ok(StdioStream::from_owned_fd(self.open(Stream::Stderr, path)?))
+    }
+}
+
+/// The log driver of a container, from the `log_driver` of its runtime options
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogDriverKind {
+    /// The fifos created by containerd, read by its clients, e.g., the CRI plugin
+    #[default]
+    Fifo,
+    /// A file on the node, `log_path` or `<bundle>.log` next to the bundle
+    File,
+    /// The output is discarded
+    Null,
+}
+
+/// Size-based rotation of the log files, from the `log_max_size` and `log_max_files` of the
+/// runtime options of the container.
+///
+/// Once a file reaches `max_size` bytes, it is renamed to `<path>.1`, the previous `<path>.1` to
+/// `<path>.2`, and so on, keeping `max_files` files including the current one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Rotation {
+    pub max_size: Option<u64>,
+    pub max_files: u32,
+}
+
+impl Rotation {
+    fn from_options(options: &Options) -> Self {
+        Self {
+            max_size: options.log_max_size,
+            max_files: options.log_max_files.unwrap_or(1).max(1),
+        }
+    }
+}
+
+/// Returns the log driver of the container of `bundle`.
+pub(crate) fn log_driver(bundle: &Path) -> Result<Box<dyn LogDriver>> {
+    let options = Options::read(bundle).map_err(Error::other)?;
+    let rotation = Rotation::from_options(&options);
+    Ok(match options.log_driver {
+        LogDriverKind::Fifo => {
+            let buffer_size = options.log_buffer_size.unwrap_or(DEFAULT_LOG_BUFFER_SIZE);
+            Box::new(FifoDriver::new(rotation, buffer_size))
+        }
+        LogDriverKind::File => {
+            let path = options.log_path.unwrap_or_else(|| log_path(bundle));
+            Box::new(FileDriver::open(path, rotation)?)
+        }
+        LogDriverKind::Null => Box::new(NullDriver),
+    })
+}
+
+/// The default file of the `file` log driver, next to the bundle so that it outlives the container
+fn log_path(bundle: &Path) -> PathBuf {
+    let mut name = bundle.file_name().unwrap_or_default().to_os_string();
+    name.push(".log");
+    bundle.with_file_name(name)
+}
+
+/// Writes to the stdio paths given by containerd.
+///
+/// These are usually fifos, but can be files, e.g., with `ctr run --log-uri file:///var/log/app`.
+/// Files are written by the shim, so that they are rotated, and the streams sharing a file write
+/// to the same one.
+/// On Linux, the output is copied to the fifos by the shim, which keeps its last `buffer_size`
+/// bytes to replay to the readers opening them again, unless `buffer_size` is 0.
+pub(crate) struct FifoDriver {
+    rotation: Rotation,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    buffer_size: usize,
+    files: Mutex<HashMap<PathBuf, Arc<Mutex<LogFile>>>>,
+}
+
+impl FifoDriver {
+    pub(crate) fn new(rotation: Rotation, buffer_size: usize) -> Self {
+        Self {
+            rotation,
+            buffer_size,
+            files: Mutex::default(),
+        }
+    }
+
+    fn open_fifo(&self, path: &Path) -> Result<StdioOwnedFd> {
+        #[cfg(unix)]
+        if self.buffer_size > 0 && is_fifo(path) {
+            #[cfg(any(test, feature = "fault-injection"))]
+            super::fault::stdio_open(path)?;
+            return forward_to(attach::AttachableFifo::new(path, self.buffer_size));
+        }
+        open_fifo(path)
+    }
+}
+
+impl LogDriver for FifoDriver {
+    fn open(&self, _stream: Stream, path: &Path) -> Result<StdioOwnedFd> {
+        let file = match path.to_str().and_then(|p| p.strip_prefix("file://")) {
+            Some(file) => Path::new(file),
+            None if path.metadata().is_ok_and(|m| m.is_file()) => path,
+            None => return self.open_fifo(path),
+        };
+        let mut files = self.files.lock().unwrap();
+        let file = match files.get(file) {
+            Some(file) => file.clone(),
+            None => {
+                let log = Arc::new(Mutex::new(LogFile::open(file, self.rotation)?));
+                files.insert(file.to_path_buf(), log.clone());
+                log
+            }
+        };
+        forward_to(SharedFile(file))
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    path.metadata().is_ok_and(|m| m.file_type().is_fifo())
+}
+
+pub(crate) struct NullDriver;
+
+impl LogDriver for NullDriver {
+    fn open(&self, _stream: Stream, _path: &Path) -> Result<StdioOwnedFd> {
+        StdioOwnedFd::try_from(OpenOptions::new().write(true).open(NULL_DEVICE)?)
+    }
+}
+
+/// Appends the stdout and stderr of the container to a file.
+///
+/// The container writes to a socket whose other end is copied to the file by the shim.
+pub(crate) struct FileDriver {
+    file: Arc<Mutex<LogFile>>,
+}
+
+impl FileDriver {
+    pub(crate) fn open(path: impl AsRef<Path>, rotation: Rotation) -> Result<Self> {
+        Ok(Self {
+            file: Arc::new(Mutex::new(LogFile::open(path.as_ref(), rotation)?)),
+        })
+    }
+}
+
+impl LogDriver for FileDriver {
+    fn open(&self, _stream: Stream, _path: &Path) -> Result<StdioOwnedFd> {
+        forward_to(SharedFile(self.file.clone()))
+    }
+}
+
+/// A log file, rotated when it reaches its maximum size
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: Rotation,
+}
+
+impl LogFile {
+    fn open(path: &Path, rotation: Rotation) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            rotation,
+        })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    /// Moves the current file to `<path>.1`, or truncates it when only one file is kept.
+    fn rotate(&mut self) -> Result<()> {
+        for n in (1..self.rotation.max_files).rev() {
+            let from = match n {
+                1 => self.path.clone(),
+                n => self.rotated(n - 1),
+            };
+            match fs::rename(&from, self.rotated(n)) {
+                Err(err) if err.kind() != NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if let Some(max_size) = self.rotation.max_size {
+            if self.size > 0 && self.size + buf.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+struct SharedFile(Arc<Mutex<LogFile>>);
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::fs::File;
+    #[cfg(unix)]
+    use std::time::{Duration, Instant};
 
     use tempfile::tempdir;
 
@@ -143,4 +408,103 @@ mod test {
         assert!(s.0.take().as_raw_fd().is_some());
         Ok(())
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_log_drivers() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let bundle = dir.path().join("bundle");
+        std::fs::create_dir(&bundle)?;
+
+        // without runtime options, the fifos of containerd are used
+        let stdout = log_driver(&bundle)?.stdout(Path::new(""))?;
+        assert!(stdout.0.take().as_raw_fd().is_none());
+
+        let options = |driver: &str| {
+            std::fs::write(
+                bundle.join("options.json"),
+                format!(r#"{{"log_driver":"{driver}"}}"#),
+            )
+        };
+        options("null")?;
+        let stdout = log_driver(&bundle)?.stdout(Path::new(""))?;
+        assert!(stdout.0.take().as_raw_fd().is_some());
+
+        options("file")?;
+        let driver = log_driver(&bundle)?;
+        for (stream, line) in [(Stream::Stdout, "hello\n"), (Stream::Stderr, "world\n")] {
+            let fd = driver.open(stream, Path::new(""))?;
+            let written =
+                unsafe { libc::write(fd.as_raw_fd().unwrap(), line.as_ptr().cast(), line.len()) };
+            assert_eq!(written, line.len() as isize);
+        }
+
+        let path = dir.path().join("bundle.log");
+        wait_for_logs(&path, 12);
+        let logs = std::fs::read_to_string(&path)?;
+        assert!(logs.contains("hello\n") && logs.contains("world\n"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn wait_for_logs(path: &Path, len: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while std::fs::read_to_string(path).map_or(0, |logs| logs.len()) < len
+            && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_log_rotation() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("app.log");
+        let rotation = Rotation {
+            max_size: Some(8),
+            max_files: 3,
+        };
+        let mut file = LogFile::open(&path, rotation)?;
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            file.write_all(line.as_bytes())?;
+        }
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "five\n");
+        assert_eq!(read(&dir.path().join("app.log.1")), "four\n");
+        assert_eq!(read(&dir.path().join("app.log.2")), "three\n");
+        assert!(!dir.path().join("app.log.3").exists());
+
+        // with a single file, the file is truncated
+        let path = dir.path().join("single.log");
+        let rotation = Rotation {
+            max_size: Some(8),
+            max_files: 1,
+        };
+        let mut file = LogFile::open(&path, rotation)?;
+        file.write_all(b"one\ntwo\n")?;
+        file.write_all(b"three\n")?;
+        assert_eq!(read(&path), "three\n");
+        assert!(!dir.path().join("single.log.1").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_driver_with_file() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("app.log");
+        let driver = FifoDriver::new(Rotation::default(), DEFAULT_LOG_BUFFER_SIZE);
+        let uri = PathBuf::from(format!("file://{}", path.display()));
+        for line in ["hello\n", "world\n"] {
+            let fd = driver.open(Stream::Stdout, &uri)?;
+            let written =
+                unsafe { libc::write(fd.as_raw_fd().unwrap(), line.as_ptr().cast(), line.len()) };
+            assert_eq!(written, line.len() as isize);
+        }
+        // the streams sharing a file write to the same one
+        assert_eq!(driver.files.lock().unwrap().len(), 1);
+        wait_for_logs(&path, 12);
+        assert_eq!(std::fs::read_to_string(&path)?.len(), 12);
+        Ok(())
+    }
 }
@@ -0,0 +1,227 @@
+//! The fifos of the output of a container, which its clients can attach to again.
+//!
+//! The shim copies the output of the container to its fifo, and keeps its last bytes in a ring
+//! buffer. When the reader of the fifo goes away, e.g., containerd is restarted, the output is
+//! kept in the buffer rather than blocking the container, and replayed to the next reader that
+//! opens the fifo, so that `kubectl logs --follow` and `kubectl attach` get the output the
+//! container wrote in the meantime.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Result, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// The last bytes of the output of a container
+struct RingBuffer {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    // the number of bytes written to the buffer, including the ones it dropped
+    written: u64,
+}
+
+impl RingBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buf: VecDeque::with_capacity(capacity),
+            capacity,
+            written: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.written += data.len() as u64;
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        let overflow = (self.buf.len() + data.len()).saturating_sub(self.capacity);
+        self.buf.drain(..overflow);
+        self.buf.extend(data);
+    }
+
+    /// Returns the bytes written after the first `offset` ones, or the ones left in the buffer if
+    /// some of them were dropped.
+    fn since(&self, offset: u64) -> Vec<u8> {
+        let start = self.written - self.buf.len() as u64;
+        let skip = offset.saturating_sub(start) as usize;
+        self.buf.iter().skip(skip).copied().collect()
+    }
+}
+
+/// Writes the output of a container to a fifo, which readers can open again after its reader
+/// went away.
+pub(crate) struct AttachableFifo {
+    path: PathBuf,
+    fifo: Option<File>,
+    buffer: RingBuffer,
+    // the number of bytes of the output the readers of the fifo got
+    delivered: u64,
+}
+
+impl AttachableFifo {
+    /// Writes to the fifo of `path`, keeping the last `capacity` bytes of the output to replay to
+    /// the readers that attach to it.
+    pub(crate) fn new(path: impl AsRef<Path>, capacity: usize) -> Self {
+        let mut fifo = Self {
+            path: path.as_ref().to_path_buf(),
+            fifo: None,
+            buffer: RingBuffer::new(capacity),
+            delivered: 0,
+        };
+        fifo.attach();
+        fifo
+    }
+
+    // Opens the fifo if it has a reader, and replays the output the reader missed
+    fn attach(&mut self) {
+        let mut fifo = match open_writer(&self.path) {
+            Ok(fifo) => fifo,
+            // the fifo has no reader
+            Err(err) if err.raw_os_error() == Some(libc::ENXIO) => return,
+            Err(err) => {
+                log::warn!("failed to open {:?}: {err}", self.path);
+                return;
+            }
+        };
+
+        let missed = self.buffer.since(self.delivered);
+        if !missed.is_empty() {
+            log::info!(
+                "replaying {} bytes of output to the new reader of {:?}",
+                missed.len(),
+                self.path
+            );
+            if let Err(err) = fifo.write_all(&missed) {
+                log::warn!("failed to replay the output to {:?}: {err}", self.path);
+                return;
+            }
+        }
+        self.delivered = self.buffer.written;
+        self.fifo = Some(fifo);
+    }
+}
+
+// Opens a fifo for writing, or fails with `ENXIO` rather than blocking when it has no reader
+fn open_writer(path: &Path) -> Result<File> {
+    let fifo = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+
+    // the writes block while the reader catches up, as when the container writes to the fifo
+    let fd = fifo.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(fifo)
+}
+
+impl Write for AttachableFifo {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.fifo.is_none() {
+            self.attach();
+        }
+        self.buffer.push(buf);
+
+        let Some(fifo) = self.fifo.as_mut() else {
+            return Ok(buf.len());
+        };
+        match fifo.write_all(buf) {
+            Ok(()) => self.delivered = self.buffer.written,
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                log::info!(
+                    "the reader of {:?} went away, keeping the output until one attaches",
+                    self.path
+                );
+                self.fifo = None;
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn mkfifo(path: &Path) {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+    }
+
+    fn open_reader(path: &Path) -> File {
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .unwrap()
+    }
+
+    fn read_available(reader: &mut File) -> String {
+        let mut buf = vec![0; 1024];
+        match reader.read(&mut buf) {
+            Ok(n) => String::from_utf8(buf[..n].to_vec()).unwrap(),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => String::new(),
+            Err(err) => panic!("failed to read the fifo: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut buffer = RingBuffer::new(8);
+        buffer.push(b"hello ");
+        assert_eq!(buffer.since(0), b"hello ");
+        assert_eq!(buffer.since(2), b"llo ");
+        buffer.push(b"world");
+        assert_eq!(buffer.written, 11);
+        assert_eq!(buffer.since(0), b"lo world");
+        assert_eq!(buffer.since(6), b"world");
+        assert_eq!(buffer.since(11), b"");
+
+        buffer.push(b"0123456789");
+        assert_eq!(buffer.since(0), b"23456789");
+    }
+
+    #[test]
+    fn test_attach_again() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("stdout");
+        mkfifo(&path);
+
+        // the output is kept until a reader opens the fifo
+        let mut fifo = AttachableFifo::new(&path, 16);
+        fifo.write_all(b"early\n")?;
+
+        let mut reader = open_reader(&path);
+        fifo.write_all(b"hello\n")?;
+        assert_eq!(read_available(&mut reader), "early\nhello\n");
+
+        // the output written without a reader is replayed to the next one
+        drop(reader);
+        fifo.write_all(b"lost 1\n")?;
+        fifo.write_all(b"lost 2\n")?;
+
+        let mut reader = open_reader(&path);
+        fifo.write_all(b"live\n")?;
+        assert_eq!(read_available(&mut reader), "lost 1\nlost 2\nlive\n");
+
+        // only the last bytes of the output are replayed
+        drop(reader);
+        fifo.write_all(b"0123456789\n0123456789\n")?;
+        let mut reader = open_reader(&path);
+        fifo.write_all(b"end\n")?;
+        assert_eq!(read_available(&mut reader), "6789\n0123456789\nend\n");
+        Ok(())
+    }
+}
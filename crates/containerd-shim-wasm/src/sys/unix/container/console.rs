@@ -0,0 +1,203 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, IoSliceMut};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use nix::cmsg_space;
+use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags, UnixAddr};
+
+/// Socket on which libcontainer sends the master of the pty it creates for the container,
+/// as runc does with the `--console-socket` flag.
+pub(crate) struct ConsoleSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ConsoleSocket {
+    /// Creates a console socket in the temporary directory.
+    /// The bundle and root directories are not used, as their paths can exceed the maximum
+    /// length of a socket path.
+    pub fn new() -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("runwasi-{}-{n}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to bind console socket {path:?}"))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits for the container to send the master of its pty
+    pub fn receive_master(self, timeout: Duration) -> Result<OwnedFd> {
+        let deadline = Instant::now() + timeout;
+        let stream = loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(err) if err.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(err) => return Err(err).context("container did not connect to the console"),
+            }
+        };
+        stream.set_nonblocking(false)?;
+
+        let mut buf = [0u8; 64];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut cmsg = cmsg_space!([std::os::fd::RawFd; 1]);
+        let msg = recvmsg::<UnixAddr>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::empty(),
+        )
+        .context("failed to receive the console")?;
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                if let Some(fd) = fds.first() {
+                    return Ok(unsafe { OwnedFd::from_raw_fd(*fd) });
+                }
+            }
+        }
+        bail!("container did not send the console")
+    }
+}
+
+impl Drop for ConsoleSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The pty attached to the stdio of a container.
+///
+/// The stdin of the task is copied to the pty, and the output of the pty to the stdout of the task.
+pub(crate) struct Console {
+    master: File,
+}
+
+impl Console {
+    pub fn new(master: OwnedFd, stdin: &Path, stdout: &Path) -> Result<Self> {
+        let master = File::from(master);
+
+        if !stdin.as_os_str().is_empty() {
+            let mut master = master.try_clone()?;
+            let stdin = stdin.to_path_buf();
+            thread::spawn(move || {
+                let res =
+                    File::open(&stdin).and_then(|mut stdin| io::copy(&mut stdin, &mut master));
+                if let Err(err) = res {
+                    log::debug!("stopped copying {stdin:?} to the console: {err}");
+                }
+            });
+        }
+
+        if !stdout.as_os_str().is_empty() {
+            let mut master = master.try_clone()?;
+            let stdout = stdout.to_path_buf();
+            thread::spawn(move || {
+                let res = OpenOptions::new()
+                    .write(true)
+                    .open(&stdout)
+                    .and_then(|mut stdout| io::copy(&mut master, &mut stdout));
+                // reading the master fails with EIO once the container closes the pty
+                if let Err(err) = res {
+                    log::debug!("stopped copying the console to {stdout:?}: {err}");
+                }
+            });
+        }
+
+        Ok(Self { master })
+    }
+
+    pub fn resize(&self, width: u32, height: u32) -> io::Result<()> {
+        let size = libc::winsize {
+            ws_row: height as u16,
+            ws_col: width as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        match unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    use nix::pty::openpty;
+    use nix::sys::socket::{sendmsg, ControlMessage};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_console() -> Result<()> {
+        let dir = tempdir()?;
+        let socket = ConsoleSocket::new()?;
+
+        // send the master as libcontainer would
+        let pty = openpty(None, None)?;
+        let stream = UnixStream::connect(socket.path())?;
+        sendmsg::<UnixAddr>(
+            stream.as_raw_fd(),
+            &[io::IoSlice::new(b"/dev/ptmx")],
+            &[ControlMessage::ScmRights(&[pty.master.as_raw_fd()])],
+            MsgFlags::empty(),
+            None,
+        )?;
+        let master = socket.receive_master(Duration::from_secs(1))?;
+
+        let stdin = dir.path().join("stdin");
+        let stdout = dir.path().join("stdout");
+        fs::write(&stdin, "hello\n")?;
+        File::create(&stdout)?;
+        let console = Console::new(master, &stdin, &stdout)?;
+
+        // the input is read from the slave
+        let mut slave = File::from(pty.slave);
+        let mut buf = [0u8; 6];
+        slave.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"hello\n");
+
+        console.resize(120, 40)?;
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        assert_ne!(
+            unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCGWINSZ, &mut size) },
+            -1
+        );
+        assert_eq!((size.ws_col, size.ws_row), (120, 40));
+
+        // the output is written to stdout
+        slave.write_all(b"world")?;
+        drop(slave);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !fs::read_to_string(&stdout)?.contains("world") {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_console_timeout() -> Result<()> {
+        let socket = ConsoleSocket::new()?;
+        let path = socket.path().to_path_buf();
+        assert!(socket.receive_master(Duration::from_millis(50)).is_err());
+        assert!(!path.exists());
+        Ok(())
+    }
+}
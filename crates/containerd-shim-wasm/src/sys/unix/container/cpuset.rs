@@ -0,0 +1,110 @@
+//! Pinning of the engine to the cpuset of the container, `linux.resources.cpu.cpus`.
+//!
+//! The cgroup of the container already restricts it to its cpuset, but the engine also pins
+//! itself to the cores of the cpuset before running the guest, so that the cores are honored when
+//! the shim doesn't manage the cgroup of the container, e.g., rootless, and so that the thread
+//! pools sized from the CPUs the engine can run on, e.g., the workers of tokio, get one thread
+//! per core. The threads the engine starts inherit the affinity.
+
+use anyhow::{bail, ensure, Context, Result};
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+use oci_spec::runtime::Spec;
+
+/// The cores of the cpuset of a container
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Cpus(Vec<usize>);
+
+impl Cpus {
+    /// Returns the cores of the `linux.resources.cpu.cpus` of the spec, if any.
+    pub fn from_spec(spec: &Spec) -> Result<Option<Self>> {
+        let cpus = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref())
+            .and_then(|resources| resources.cpu().as_ref())
+            .and_then(|cpu| cpu.cpus().as_deref())
+            .filter(|cpus| !cpus.trim().is_empty());
+        cpus.map(|cpus| cpus.parse()).transpose()
+    }
+
+    /// Pins the calling thread, and the threads it starts, to the cores.
+    pub fn pin(&self) -> Result<()> {
+        let mut set = CpuSet::new();
+        for cpu in &self.0 {
+            set.set(*cpu)
+                .with_context(|| format!("cpu {cpu} is out of range"))?;
+        }
+        sched_setaffinity(Pid::from_raw(0), &set)
+            .with_context(|| format!("failed to pin the engine to cpus {self:?}"))?;
+        Ok(())
+    }
+}
+
+// Parses a cpuset in the list format of the kernel, e.g. `0-3,8`
+impl std::str::FromStr for Cpus {
+    type Err = anyhow::Error;
+
+    fn from_str(cpus: &str) -> Result<Self> {
+        let mut cores = vec![];
+        for range in cpus.split(',').map(str::trim) {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let (Ok(first), Ok(last)) = (first.trim().parse::<usize>(), last.trim().parse()) else {
+                bail!("invalid cpuset {cpus:?}");
+            };
+            ensure!(first <= last, "invalid cpuset {cpus:?}");
+            cores.extend(first..=last);
+        }
+        cores.sort_unstable();
+        cores.dedup();
+        Ok(Self(cores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{LinuxBuilder, LinuxCpuBuilder, LinuxResourcesBuilder, SpecBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_cpus() -> Result<()> {
+        assert_eq!("0".parse::<Cpus>()?, Cpus(vec![0]));
+        assert_eq!("0-3,8".parse::<Cpus>()?, Cpus(vec![0, 1, 2, 3, 8]));
+        assert_eq!(" 4-5, 1 ,5".parse::<Cpus>()?, Cpus(vec![1, 4, 5]));
+        assert!("3-1".parse::<Cpus>().is_err());
+        assert!("0,".parse::<Cpus>().is_err());
+        assert!("all".parse::<Cpus>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpus_from_spec() -> Result<()> {
+        let spec = |cpus: &str| -> Result<Spec> {
+            let cpu = LinuxCpuBuilder::default().cpus(cpus).build()?;
+            let resources = LinuxResourcesBuilder::default().cpu(cpu).build()?;
+            let linux = LinuxBuilder::default().resources(resources).build()?;
+            Ok(SpecBuilder::default().linux(linux).build()?)
+        };
+        assert_eq!(Cpus::from_spec(&spec("2-3")?)?, Some(Cpus(vec![2, 3])));
+        assert_eq!(Cpus::from_spec(&spec("")?)?, None);
+        assert_eq!(Cpus::from_spec(&SpecBuilder::default().build()?)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin() -> Result<()> {
+        // the thread only gets the first of the cores it can run on
+        let current = nix::sched::sched_getaffinity(Pid::from_raw(0))?;
+        let first = (0..CpuSet::count())
+            .find(|cpu| current.is_set(*cpu).unwrap_or(false))
+            .context("no cpu")?;
+        std::thread::spawn(move || {
+            Cpus(vec![first]).pin()?;
+            assert_eq!(std::thread::available_parallelism()?.get(), 1);
+            Ok(())
+        })
+        .join()
+        .unwrap()
+    }
+}
@@ -0,0 +1,151 @@
+//! Restore of the containers checkpointed with CRIU, which libcontainer only checkpoints.
+//!
+//! The container is restored in its bundle, with the bind mounts of its spec as external mounts,
+//! as libcontainer dumps them, and in the cgroup it was checkpointed in. CRIU doesn't return the
+//! pid of the restored process, which is in its own pid namespace, so the shim reads it from the
+//! cgroup of the container, and saves the state of the container for libcontainer to kill and
+//! delete it as any other container.
+
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use libcontainer::config::YoukiConfig;
+use libcontainer::container::{Container, ContainerStatus};
+use oci_spec::runtime::Spec;
+
+// the log of CRIU, in the checkpoint directory
+const CRIU_RESTORE_LOG_FILE: &str = "restore.log";
+
+// the root of the cgroup v2 hierarchy
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Restores the container `id` from the checkpoint in the `checkpoint` directory, and saves its
+/// state to `container_root`. Returns the pid of the restored process.
+pub(crate) fn restore(
+    id: &str,
+    spec: &Spec,
+    bundle: &Path,
+    checkpoint: &Path,
+    container_root: &Path,
+) -> Result<i32> {
+    let mut criu =
+        rust_criu::Criu::new().map_err(|err| anyhow::anyhow!("failed to start criu: {err}"))?;
+    for mount in spec.mounts().iter().flatten() {
+        if mount.typ().as_deref() != Some("bind") {
+            continue;
+        }
+        let (Some(source), Some(destination)) = (
+            mount.source().as_ref().and_then(|s| s.to_str()),
+            mount.destination().to_str(),
+        ) else {
+            continue;
+        };
+        criu.set_external_mount(destination.to_string(), source.to_string());
+    }
+    let images = File::open(checkpoint)
+        .with_context(|| format!("failed to open the checkpoint {checkpoint:?}"))?;
+    criu.set_images_dir_fd(images.as_raw_fd());
+    let root = spec
+        .root()
+        .as_ref()
+        .context("rootfs is not set in runtime spec")?;
+    let rootfs = bundle.join(root.path());
+    criu.set_root(rootfs.to_string_lossy().into_owned());
+    criu.set_log_file(CRIU_RESTORE_LOG_FILE.to_string());
+    criu.set_log_level(4);
+    criu.set_orphan_pts_master(true);
+    criu.set_manage_cgroups(true);
+    criu.restore().map_err(|err| {
+        anyhow::anyhow!(
+            "failed to restore container {id}, see {:?}: {err}",
+            checkpoint.join(CRIU_RESTORE_LOG_FILE)
+        )
+    })?;
+
+    let config = YoukiConfig::from_spec(spec, id)?;
+    let pid = restored_pid(&config.cgroup_path)
+        .with_context(|| format!("failed to find the restored process of container {id}"))?;
+    log::info!("restored container {id} with pid {pid}");
+
+    fs::create_dir_all(container_root)?;
+    let mut container = match Container::load(container_root.to_path_buf()) {
+        Ok(container) => container,
+        Err(_) => Container::new(
+            id,
+            ContainerStatus::Running,
+            Some(pid),
+            bundle,
+            container_root,
+        )?,
+    };
+    container
+        .set_pid(pid)
+        .set_status(ContainerStatus::Running)
+        .save()?;
+    config.save(container_root)?;
+    Ok(pid)
+}
+
+// the pid of the init process of the container, restored in its cgroup
+fn restored_pid(cgroup_path: &Path) -> Result<i32> {
+    if !cgroup_path.is_absolute() {
+        bail!("the cgroup {cgroup_path:?} is not a cgroupfs path");
+    }
+    let procs = cgroup_dir(cgroup_path).join("cgroup.procs");
+    let procs = fs::read_to_string(&procs).with_context(|| format!("failed to read {procs:?}"))?;
+    let pids: Vec<i32> = procs
+        .lines()
+        .filter_map(|pid| pid.trim().parse().ok())
+        .collect();
+    init_pid(&pids, parent_pid).context("no process in the cgroup of the container")
+}
+
+fn cgroup_dir(cgroup_path: &Path) -> PathBuf {
+    Path::new(CGROUP_ROOT).join(cgroup_path.strip_prefix("/").unwrap_or(cgroup_path))
+}
+
+// the process of `pids` whose parent isn't one of them, i.e., the init of the container
+fn init_pid(pids: &[i32], parent: impl Fn(i32) -> Option<i32>) -> Option<i32> {
+    pids.iter()
+        .copied()
+        .find(|pid| parent(*pid).map_or(true, |parent| !pids.contains(&parent)))
+}
+
+fn parent_pid(pid: i32) -> Option<i32> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // the name of the process is in parentheses, and may contain spaces
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_pid() {
+        let parents = |pid| match pid {
+            10 => Some(1),
+            11 | 12 => Some(10),
+            _ => None,
+        };
+        assert_eq!(init_pid(&[11, 10, 12], parents), Some(10));
+        assert_eq!(init_pid(&[], parents), None);
+    }
+
+    #[test]
+    fn test_parent_pid() {
+        let pid = std::process::id() as i32;
+        assert_eq!(parent_pid(pid), Some(nix::unistd::getppid().as_raw()));
+    }
+
+    #[test]
+    fn test_cgroup_dir() {
+        assert_eq!(
+            cgroup_dir(Path::new("/runwasi/test")),
+            Path::new("/sys/fs/cgroup/runwasi/test")
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use std::cell::OnceCell;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use libcontainer::workload::default::DefaultExecutor;
@@ -13,8 +15,16 @@ use libcontainer::workload::{
 use oci_spec::image::Platform;
 use oci_spec::runtime::Spec;
 
-use crate::container::{Engine, PathResolve, RuntimeContext, Source, Stdio, WasiContext};
-use crate::sandbox::oci::WasmLayer;
+use crate::container::{
+    format_backtrace, wasm_wrapper_target, Engine, ErrorCode, GuestFailure, KeyValue, PathResolve,
+    PoolStats, RuntimeContext, Source, Stdio, WasiContext,
+};
+use crate::sandbox::oci::{self, WasmLayer};
+use crate::sys::container::cpuset::Cpus;
+use crate::sys::container::exit_fifo::ExitFifo;
+use crate::sys::container::failure::FailureChannel;
+use crate::sys::container::landlock::Landlock;
+use crate::sys::container::mac::{MacLabels, Transition};
 
 #[derive(Clone)]
 enum InnerExecutor {
@@ -30,6 +40,13 @@ pub(crate) struct Executor<E: Engine> {
     inner: OnceCell<InnerExecutor>,
     wasm_layers: Vec<WasmLayer>,
     platform: Platform,
+    failures: Arc<FailureChannel>,
+    pool_stats: PoolStats,
+    landlock: Option<Landlock>,
+    cpus: Option<Cpus>,
+    threads: Option<NonZeroUsize>,
+    keyvalue: Option<KeyValue>,
+    exit_fifo: Option<Arc<ExitFifo>>,
 }
 
 impl<E: Engine> LibcontainerExecutor for Executor<E> {
@@ -44,40 +61,125 @@ impl<E: Engine> LibcontainerExecutor for Executor<E> {
 
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn exec(&self, spec: &Spec) -> Result<(), LibcontainerExecutorError> {
+        // the process runs in the root of the container, so that the files of the environment
+        // can't be outside of it, and their content is only kept in the environment of the process
+        let mut spec = spec.clone();
+        if oci::resolve_env_from_files(&mut spec, |path| fs::read_to_string(path))
+            .map_err(|err| LibcontainerExecutorError::Other(err.to_string()))?
+        {
+            let env = spec.process().as_ref().and_then(|p| p.env().as_ref());
+            for (name, value) in env
+                .into_iter()
+                .flatten()
+                .filter_map(|var| var.split_once('='))
+            {
+                std::env::set_var(name, value);
+            }
+        }
+        let spec = &spec;
+
         // If it looks like a linux container, run it as a linux container.
         // Otherwise, run it as a wasm container
         match self.inner(spec) {
             InnerExecutor::CantHandle => Err(LibcontainerExecutorError::CantHandle(E::name())),
             InnerExecutor::Linux => {
                 log::info!("executing linux container");
+                apply_mac_labels(spec, Transition::OnExec)?;
                 self.stdio.take().redirect().unwrap();
                 DefaultExecutor {}.exec(spec)
             }
             InnerExecutor::Wasm => {
+                let ctx = self.ctx(spec);
+                if let Some(cpus) = &self.cpus {
+                    cpus.pin()
+                        .map_err(|err| LibcontainerExecutorError::Other(format!("{err:#}")))?;
+                }
+                // the labels are applied before landlock, which denies writing their attributes
+                apply_mac_labels(spec, Transition::Now)?;
+                if let Some(landlock) = &self.landlock {
+                    landlock.restrict_self(&ctx).map_err(|err| {
+                        LibcontainerExecutorError::Other(format!(
+                            "failed to confine the engine with landlock: {err}"
+                        ))
+                    })?;
+                }
                 log::info!("calling start function");
-                match self.engine.run_wasi(&self.ctx(spec), self.stdio.take()) {
-                    Ok(code) => std::process::exit(code),
+                let code = match self.engine.run(&ctx, self.stdio.take()) {
+                    Ok(code) => code,
                     Err(err) => {
-                        log::info!("error running start function: {err}");
-                        std::process::exit(137)
+                        log::info!("error running start function: {err:#}");
+                        if let Some(failure) = err.downcast_ref::<GuestFailure>() {
+                            self.failures.report(failure);
+                            write_backtrace(failure);
+                        }
+                        match ErrorCode::of(&err) {
+                            Some(code) => {
+                                write_termination_message(spec, &format!("{err:#}"));
+                                code.exit_status()
+                            }
+                            None => 137,
+                        }
                     }
                 };
+                if let Some(exit_fifo) = &self.exit_fifo {
+                    exit_fifo.report(code);
+                }
+                std::process::exit(code)
             }
         }
     }
 }
 
 impl<E: Engine> Executor<E> {
-    pub fn new(engine: E, stdio: Stdio, wasm_layers: Vec<WasmLayer>, platform: Platform) -> Self {
+    pub fn new(
+        engine: E,
+        stdio: Stdio,
+        wasm_layers: Vec<WasmLayer>,
+        platform: Platform,
+        failures: Arc<FailureChannel>,
+        pool_stats: PoolStats,
+        landlock: Option<Landlock>,
+    ) -> Self {
         Self {
             engine,
             stdio,
             inner: Default::default(),
             wasm_layers,
             platform,
+            failures,
+            pool_stats,
+            landlock,
+            cpus: None,
+            threads: None,
+            keyvalue: None,
+            exit_fifo: None,
         }
     }
 
+    /// Pins the engine to the cores of the cpuset of the container
+    pub fn with_cpus(mut self, cpus: Option<Cpus>) -> Self {
+        self.cpus = cpus;
+        self
+    }
+
+    /// Sets the number of threads the engine runs the guest with
+    pub fn with_threads(mut self, threads: Option<NonZeroUsize>) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets the key-value store of the `wasi:keyvalue` interfaces of the guest
+    pub fn with_keyvalue(mut self, keyvalue: Option<KeyValue>) -> Self {
+        self.keyvalue = keyvalue;
+        self
+    }
+
+    /// Sets the FIFO the process of the container writes its exit code to
+    pub fn with_exit_fifo(mut self, exit_fifo: Option<Arc<ExitFifo>>) -> Self {
+        self.exit_fifo = exit_fifo;
+        self
+    }
+
     fn ctx<'a>(&'a self, spec: &'a Spec) -> WasiContext<'a> {
         let wasm_layers = &self.wasm_layers;
         let platform = &self.platform;
@@ -85,6 +187,9 @@ impl<E: Engine> Executor<E> {
             spec,
             wasm_layers,
             platform,
+            pool_stats: Some(&self.pool_stats),
+            threads: self.threads,
+            keyvalue: self.keyvalue.as_ref(),
         }
     }
 
@@ -109,6 +214,44 @@ impl<E: Engine> Executor<E> {
     }
 }
 
+// The termination message of a kubernetes container, shown by `kubectl describe pod`
+const TERMINATION_MESSAGE_PATH: &str = "/dev/termination-log";
+
+// Writes the failure of the task to its termination message, when kubernetes mounted one at the
+// default path
+fn write_termination_message(spec: &Spec, message: &str) {
+    let mounted = spec
+        .mounts()
+        .iter()
+        .flatten()
+        .any(|mount| mount.destination() == std::path::Path::new(TERMINATION_MESSAGE_PATH));
+    if !mounted {
+        return;
+    }
+    if let Err(err) = std::fs::write(TERMINATION_MESSAGE_PATH, message) {
+        log::warn!("failed to write the termination message: {err}");
+    }
+}
+
+// Writes the trap of the guest, with its backtrace, to the stderr of the container, which the engine
+// redirected to the stderr of the task
+fn write_backtrace(failure: &GuestFailure) {
+    let GuestFailure::Trap { backtrace, .. } = failure else {
+        return;
+    };
+    let mut message = failure.to_string();
+    if !backtrace.is_empty() {
+        message = format!("{message}\n{}", format_backtrace(backtrace));
+    }
+    let _ = writeln!(std::io::stderr(), "{message}");
+}
+
+fn apply_mac_labels(spec: &Spec, transition: Transition) -> Result<(), LibcontainerExecutorError> {
+    MacLabels::from_spec(spec)
+        .apply(transition)
+        .map_err(|err| LibcontainerExecutorError::Other(format!("{err:#}")))
+}
+
 fn is_linux_container(ctx: &impl RuntimeContext) -> Result<()> {
     if let Source::Oci(_) = ctx.entrypoint().source {
         bail!("the entry point contains wasm layers")
@@ -125,6 +268,11 @@ fn is_linux_container(ctx: &impl RuntimeContext) -> Result<()> {
         })
         .context("entrypoint not found")?;
 
+    // `#!wasm` wrapper scripts run a module of the rootfs
+    if wasm_wrapper_target(&executable).is_some() {
+        bail!("the entry point is a wasm wrapper script")
+    }
+
     // check the shebang and ELF magic number
     // https://en.wikipedia.org/wiki/Executable_and_Linkable_Format#File_header
     let mut buffer = [0; 4];
@@ -0,0 +1,89 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// The FIFO of the bundle of a task the process of the container writes its exit code to
+const EXIT_FIFO: &str = "runwasi-exit.fifo";
+
+/// Reports the exit code of the process of a container to a shim that isn't its parent.
+///
+/// The shim that starts the container gets the exit code of its child with `waitid`, but a shim
+/// restarted after it, which re-attaches to the running container, can't. The process of the
+/// container inherits the FIFO from the shim, and writes its exit code to it before exiting, so
+/// that a restarted shim holding the FIFO open reads it once the process is gone.
+pub(crate) struct ExitFifo(File);
+
+impl ExitFifo {
+    /// Creates the FIFO in `bundle`, or opens it if it exists.
+    pub fn create(bundle: &Path) -> io::Result<Self> {
+        let path = bundle.join(EXIT_FIFO);
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != ErrorKind::AlreadyExists {
+                return Err(err);
+            }
+        }
+        Self::open(bundle)
+    }
+
+    /// Opens the FIFO of `bundle`, without waiting for a writer.
+    pub fn open(bundle: &Path) -> io::Result<Self> {
+        // opened for both reading and writing, so that opening doesn't block and the buffered
+        // exit code is kept while the FIFO is open
+        let fifo = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(bundle.join(EXIT_FIFO))?;
+        Ok(Self(fifo))
+    }
+
+    /// Writes the exit code, called from the process of the container
+    pub fn report(&self, code: i32) {
+        if let Err(err) = (&self.0).write_all(format!("{code}\n").as_bytes()) {
+            log::warn!("failed to report the exit code of the container: {err}");
+        }
+    }
+
+    /// Returns the last exit code written to the FIFO, if any
+    pub fn take(&self) -> Option<u32> {
+        let mut buf = vec![0; 4096];
+        let len = match (&self.0).read(&mut buf) {
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return None,
+            Err(err) => {
+                log::warn!("failed to read the exit code of the container: {err}");
+                return None;
+            }
+        };
+        let codes = String::from_utf8_lossy(&buf[..len]);
+        codes.lines().filter_map(|code| code.parse().ok()).last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_fifo() -> io::Result<()> {
+        let bundle = tempfile::tempdir()?;
+        let fifo = ExitFifo::create(bundle.path())?;
+        assert_eq!(fifo.take(), None);
+
+        // a restarted shim opens the FIFO the process of the container writes to
+        let restarted = ExitFifo::open(bundle.path())?;
+        fifo.report(42);
+        drop(fifo);
+        assert_eq!(restarted.take(), Some(42));
+        assert_eq!(restarted.take(), None);
+
+        // the FIFO is reused when the container is recreated in the same bundle
+        assert!(ExitFifo::create(bundle.path()).is_ok());
+        Ok(())
+    }
+}
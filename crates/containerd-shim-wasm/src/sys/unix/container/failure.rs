@@ -0,0 +1,72 @@
+use std::io::{self, ErrorKind};
+use std::os::unix::net::UnixDatagram;
+
+use crate::container::GuestFailure;
+
+/// Reports the failure of the guest from the process of the container to the shim.
+///
+/// The process of the container inherits both ends of a datagram socket from the shim, and sends
+/// the failure of the guest before exiting. The shim reads it once the process has exited.
+pub(crate) struct FailureChannel {
+    sender: UnixDatagram,
+    receiver: UnixDatagram,
+}
+
+impl FailureChannel {
+    pub fn new() -> io::Result<Self> {
+        let (sender, receiver) = UnixDatagram::pair()?;
+        receiver.set_nonblocking(true)?;
+        Ok(Self { sender, receiver })
+    }
+
+    /// Sends the failure of the guest to the shim, called from the process of the container
+    pub fn report(&self, failure: &GuestFailure) {
+        let res = serde_json::to_vec(failure)
+            .map_err(io::Error::other)
+            .and_then(|failure| self.sender.send(&failure));
+        if let Err(err) = res {
+            log::warn!("failed to report the failure of the guest: {err}");
+        }
+    }
+
+    /// Returns the failure reported by the process of the container, if any
+    pub fn take(&self) -> Option<GuestFailure> {
+        let mut buf = vec![0; 64 * 1024];
+        match self.receiver.recv(&mut buf) {
+            Ok(len) => serde_json::from_slice(&buf[..len])
+                .inspect_err(|err| log::warn!("invalid failure of the guest: {err}"))
+                .ok(),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => None,
+            Err(err) => {
+                log::warn!("failed to read the failure of the guest: {err}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::BacktraceFrame;
+
+    #[test]
+    fn test_report_failure() -> io::Result<()> {
+        let channel = FailureChannel::new()?;
+        assert_eq!(channel.take(), None);
+
+        let failure = GuestFailure::Trap {
+            code: "UnreachableCodeReached".to_string(),
+            message: "wasm `unreachable` instruction executed".to_string(),
+            backtrace: vec![BacktraceFrame {
+                func_index: 3,
+                offset: Some(0x2a),
+                ..Default::default()
+            }],
+        };
+        channel.report(&failure);
+        assert_eq!(channel.take(), Some(failure));
+        assert_eq!(channel.take(), None);
+        Ok(())
+    }
+}
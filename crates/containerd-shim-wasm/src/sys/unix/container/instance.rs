@@ -1,34 +1,123 @@
-use std::marker::PhantomData;
+use std::collections::BTreeSet;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::thread;
+use std::sync::{mpsc, Arc, OnceLock};
 use std::time::Duration;
+use std::{fs, thread};
 
 use anyhow::Context;
 use chrono::{DateTime, Utc};
 use libcontainer::container::builder::ContainerBuilder;
-use libcontainer::container::Container;
+use libcontainer::container::{CheckpointOptions, Container};
 use libcontainer::signal::Signal;
 use libcontainer::syscall::syscall::SyscallType;
 use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal as NixSignal};
 use nix::sys::wait::{waitid, Id as WaitID, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use oci_spec::image::Platform;
+use oci_spec::runtime::{LinuxResources, Process, Spec};
 
-use crate::container::Engine;
-use crate::sandbox::instance_utils::{determine_rootdir, get_instance_root, instance_exists};
+use crate::container::{
+    inspect_container, Engine, ErrorCode, GuestFailure, KeyValue, PoolMetrics, PoolStats,
+};
+use crate::sandbox::capability_policy::CapabilityPolicy;
+use crate::sandbox::instance_utils::{
+    determine_rootdir, get_instance_root, instance_exists, Options,
+};
+use crate::sandbox::oci::{self, WasmLayer};
+use crate::sandbox::stdio::{self, Stdin, StdioStream};
 use crate::sandbox::sync::WaitableCell;
 use crate::sandbox::{
     containerd, Error as SandboxError, Instance as SandboxInstance, InstanceConfig, Stdio,
 };
+use crate::sys::container::console::{Console, ConsoleSocket};
+use crate::sys::container::cpuset::Cpus;
 use crate::sys::container::executor::Executor;
+use crate::sys::container::exit_fifo::ExitFifo;
+use crate::sys::container::failure::FailureChannel;
+use crate::sys::container::landlock::Landlock;
+use crate::sys::container::reload::{HotReload, Restart};
+use crate::sys::container::rootless::{self, CgroupMode};
+use crate::sys::container::stdin::StdinForwarder;
+use crate::sys::container::{criu, memfs, scope};
+use crate::sys::metrics::cgroup_path;
+use crate::sys::networking;
+use crate::sys::stdio::StdioOwnedFd;
 
 static DEFAULT_CONTAINER_ROOT_DIR: &str = "/run/containerd";
 
+// Time the container process has to send its console once it is created
+const CONSOLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Interval at which a recovered instance checks whether its process is still running
+const RECOVERED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct Instance<E: Engine> {
     exit_code: WaitableCell<(u32, DateTime<Utc>)>,
     rootdir: PathBuf,
     id: String,
-    _phantom: PhantomData<E>,
+    engine: E,
+    modules: Vec<WasmLayer>,
+    platform: Platform,
+    exec: Option<ExecProcess>,
+    console: OnceLock<Console>,
+    stdin: Option<StdinForwarder>,
+    failures: Arc<FailureChannel>,
+    pool_stats: PoolStats,
+    landlock: Option<Landlock>,
+    cpus: Option<Cpus>,
+    threads: Option<NonZeroUsize>,
+    keyvalue: Option<KeyValue>,
+    hot_reload: Option<HotReload>,
+    // the tmpfs of the root of a container with the `io.containerd.wasm.fs: memory` annotation
+    memory_root: Option<PathBuf>,
+}
+
+/// An additional process running inside the container, created through `exec`
+struct ExecProcess {
+    exec_id: String,
+    process: Process,
+    stdio: Stdio,
+    // the stdio paths, used to create the console when the process has a terminal
+    terminal: Option<(PathBuf, PathBuf)>,
+    pid: OnceLock<i32>,
+}
+
+// With a terminal, libcontainer connects the stdio of the container to a pty,
+// so the stdio of the task is copied to the console instead.
+// Otherwise stdin is forwarded, so it can be closed with `CloseIO`.
+fn init_stdio(
+    cfg: &InstanceConfig<impl Send + Sync + Clone>,
+) -> std::io::Result<(Stdio, Option<StdinForwarder>)> {
+    if cfg.get_terminal() {
+        return Ok((Stdio::default(), None));
+    }
+
+    let path = cfg.get_stdin();
+    let (stdin, forwarder) = match path.as_os_str().is_empty() || !path.exists() {
+        true => (Stdin::default(), None),
+        false => {
+            let (forwarder, reader) = StdinForwarder::new(path)?;
+            let stdin = StdioStream::from_owned_fd(StdioOwnedFd::try_from(reader)?);
+            (stdin, Some(forwarder))
+        }
+    };
+    let driver = stdio::log_driver(cfg.get_bundle())?;
+    let stdio = Stdio {
+        stdin,
+        stdout: driver.stdout(cfg.get_stdout())?,
+        stderr: driver.stderr(cfg.get_stderr())?,
+    };
+    Ok((stdio, forwarder))
+}
+
+// The key-value store of the `keyvalue` runtime option, with the buckets scoped to the container
+fn keyvalue(store: Option<String>, namespace: &str, id: &str, spec: &Spec) -> Option<KeyValue> {
+    let annotations = spec.annotations().clone().unwrap_or_default();
+    store
+        .filter(|store| !store.is_empty())
+        .map(|store| KeyValue::new(store, namespace, id, &annotations))
 }
 
 impl<E: Engine> SandboxInstance for Instance<E> {
@@ -42,28 +131,408 @@ impl<E: Engine> SandboxInstance for Instance<E> {
         let namespace = cfg.get_namespace();
         let rootdir = Path::new(DEFAULT_CONTAINER_ROOT_DIR).join(E::name());
         let rootdir = determine_rootdir(&bundle, &namespace, rootdir)?;
-        let stdio = Stdio::init_from_cfg(cfg)?;
+        let (stdio, stdin) = init_stdio(cfg)?;
+        let console_socket = cfg.get_terminal().then(ConsoleSocket::new).transpose()?;
+        let failures = Arc::new(FailureChannel::new()?);
+        let pool_stats = PoolStats::shared()?;
+        let exit_fifo = Arc::new(ExitFifo::create(&bundle)?);
+        let options = Options::read(&bundle)?;
+        let landlock = options
+            .landlock
+            .then(|| Landlock::new(options.landlock_paths));
+        let threads = options.threads;
+
+        let client =
+            containerd::Client::connect(cfg.get_containerd_address().as_str(), &namespace)?
+                .with_compile_observer(cfg.get_compile_observer())
+                .with_precompile_cache_dir(options.precompile_cache_dir);
+
+        // with a signature policy, refuse to run images that aren't signed by a trusted key
+        let signature_policy = match &options.signature_policy {
+            Some(path) => Some(containerd::SignaturePolicy::load(path)?),
+            None => containerd::SignaturePolicy::from_env()?,
+        };
+        if let Some(policy) = signature_policy {
+            client.verify_signature(&id, &policy)?;
+        }
+
+        // with a capability policy, deny the capabilities the policy denies to the container
+        let capability_policy = match &options.capability_policy {
+            Some(path) => Some(CapabilityPolicy::load(path)?),
+            None => CapabilityPolicy::from_env()?,
+        };
+        let denied = match capability_policy {
+            Some(policy) => policy.denied(&namespace, &client.container_labels(&id)?),
+            None => BTreeSet::new(),
+        };
 
         // check if container is OCI image with wasm layers and attempt to read the module
-        let (modules, platform) = containerd::Client::connect(cfg.get_containerd_address().as_str(), &namespace)?
+        let (modules, platform) = client
             .load_modules(&id, &engine)
             .unwrap_or_else(|e| {
                 log::warn!("Error obtaining wasm layers for container {id}.  Will attempt to use files inside container image. Error: {e}");
                 (vec![], Platform::default())
             });
+        client.collect_precompiled_if_due();
 
-        ContainerBuilder::new(id.clone(), SyscallType::Linux)
-            .with_executor(Executor::new(engine, stdio, modules, platform))
-            .with_root_path(rootdir.clone())?
-            .as_init(&bundle)
-            .with_systemd(false)
-            .build()?;
+        // libcontainer creates the mounts before making the root read-only
+        let spec_path = bundle.join("config.json");
+        let mut spec = Spec::load(&spec_path)?;
+        // the cpuset is read before a rootless shim drops the resources it can't enforce
+        let cpus = Cpus::from_spec(&spec)?;
+        let keyvalue = keyvalue(options.keyvalue.clone(), &namespace, &id, &spec);
+        let memory_root = memfs::mount_memory_root(&mut spec, &bundle)?;
+        let mounted_tmp = oci::mount_tmp_on_readonly_root(&mut spec)?;
+        let stripped_env = oci::strip_env(&mut spec, &options.env_strip)?;
+        let resolved_fields = oci::resolve_env_from_fields(&mut spec, networking::host_field)?;
+        // the files of the environment are read by the container process, in the root of the container
+        oci::check_env_from_files(&spec)?;
+        let mounted_log = oci::mount_record_log(&mut spec, &bundle)?;
+        let mounted_coredump = oci::mount_coredump(&mut spec, &bundle)?;
+        let denied_capabilities = oci::set_denied_capabilities(&mut spec, &denied);
+        // libcontainer applies the seccomp profile to the process running the engine
+        let default_seccomp = oci::apply_default_seccomp(&mut spec)?;
+        // a rootless shim can only create the cgroup of the container where it is delegated
+        let cgroup_mode = rootless::cgroup_mode();
+        // the limits of a scope are applied by the shim rather than libcontainer
+        let resources = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().clone());
+        let rootless_cgroup = rootless::adapt_spec(&mut spec, &id, cgroup_mode);
+        let rootless_sysfs = rootless::is_rootless() && rootless::bind_sysfs(&mut spec);
+        if memory_root.is_some()
+            || mounted_tmp
+            || stripped_env
+            || resolved_fields
+            || mounted_log
+            || mounted_coredump
+            || denied_capabilities
+            || default_seccomp
+            || rootless_cgroup
+            || rootless_sysfs
+        {
+            spec.save(&spec_path)?;
+        }
+
+        // libcontainer joins the network namespace of the pod before the engine starts, so that
+        // the sockets of the guest use the pod IP rather than the host network
+        if let Some(Some(netns)) = oci::network_namespace(&spec) {
+            if !netns.exists() {
+                return Err(SandboxError::FailedPrecondition(format!(
+                    "the network namespace {} of container {id} does not exist",
+                    netns.display()
+                )));
+            }
+            log::info!(
+                "container {id} joins the network namespace {}",
+                netns.display()
+            );
+        }
+
+        // fail fast when the engine can't provide the imports of the module, and refuse the
+        // modules the capability policy can't be checked against
+        let report = inspect_container(&modules, &spec, &bundle, |layer| {
+            Ok(client.read_original_layer(layer)?)
+        });
+        let policy_error = |reason: String| {
+            SandboxError::FailedPrecondition(format!(
+                "the capability policy denies the capabilities {} to container {id}, but {reason}",
+                denied.iter().cloned().collect::<Vec<_>>().join(", ")
+            ))
+        };
+        match report {
+            Ok(Some(report)) => {
+                log::debug!("capabilities of container {id}: {report:?}");
+                if let Some(observer) = cfg.get_capability_observer() {
+                    observer(&report);
+                }
+                let unsupported = E::supported_imports()
+                    .map(|supported| report.unsupported_imports(supported))
+                    .unwrap_or_default();
+                if !unsupported.is_empty() {
+                    return Err(SandboxError::Wasm(
+                        ErrorCode::UnsupportedImport,
+                        format!(
+                            "the {} engine can't provide the imports {} of container {id}",
+                            E::name(),
+                            unsupported.join(", ")
+                        ),
+                    ));
+                }
+                let denied: Vec<_> = report.capabilities.intersection(&denied).cloned().collect();
+                if !denied.is_empty() {
+                    return Err(SandboxError::FailedPrecondition(format!(
+                        "the capability policy denies the capabilities {} of container {id}",
+                        denied.join(", ")
+                    )));
+                }
+            }
+            Ok(None) if denied.is_empty() => {
+                log::debug!("no wasm module to inspect for container {id}")
+            }
+            Err(err) if denied.is_empty() => {
+                log::warn!("failed to inspect the wasm module of container {id}: {err}")
+            }
+            Ok(None) => {
+                return Err(policy_error("it has no wasm module to inspect".to_string()));
+            }
+            Err(err) => {
+                return Err(policy_error(format!(
+                    "its wasm module failed to be inspected: {err}"
+                )));
+            }
+        }
+
+        let hot_reload = match oci::hot_reload(&spec)? {
+            true if console_socket.is_some() => {
+                log::warn!("container {id} has a terminal, its module is not reloaded");
+                None
+            }
+            true => {
+                // the new process of the container runs with the stdio of the drained one
+                let restart: Restart = Arc::new({
+                    let id = id.clone();
+                    let rootdir = rootdir.clone();
+                    let bundle = bundle.clone();
+                    let engine = engine.clone();
+                    let stdio = stdio.clone();
+                    let failures = failures.clone();
+                    let pool_stats = pool_stats.clone();
+                    let landlock = landlock.clone();
+                    let cpus = cpus.clone();
+                    let keyvalue = keyvalue.clone();
+                    let exit_fifo = exit_fifo.clone();
+                    let resources = resources.clone();
+                    move |modules: Vec<WasmLayer>, platform: Platform| -> anyhow::Result<i32> {
+                        let mut container = Container::load(get_instance_root(&rootdir, &id)?)?;
+                        rootless::delete_container(&mut container)?;
+                        pool_stats.reset();
+                        let executor = Executor::new(
+                            engine.clone(),
+                            stdio.clone(),
+                            modules,
+                            platform,
+                            failures.clone(),
+                            pool_stats.clone(),
+                            landlock.clone(),
+                        )
+                        .with_cpus(cpus.clone())
+                        .with_threads(threads)
+                        .with_keyvalue(keyvalue.clone())
+                        .with_exit_fifo(Some(exit_fifo.clone()));
+                        create_container(
+                            &id,
+                            &rootdir,
+                            &bundle,
+                            executor,
+                            None,
+                            cgroup_mode,
+                            resources.as_ref(),
+                        )?;
+                        let mut container = Container::load(get_instance_root(&rootdir, &id)?)?;
+                        let pid = container.pid().context("failed to get pid")?.as_raw();
+                        container.start()?;
+                        Ok(pid)
+                    }
+                });
+                let address = cfg.get_containerd_address();
+                let observer = cfg.get_reload_observer();
+                Some(HotReload::new(
+                    &id, &client, &address, &namespace, restart, observer,
+                )?)
+            }
+            false => None,
+        };
+
+        let executor = Executor::new(
+            engine.clone(),
+            stdio,
+            modules.clone(),
+            platform.clone(),
+            failures.clone(),
+            pool_stats.clone(),
+            landlock.clone(),
+        )
+        .with_cpus(cpus.clone())
+        .with_threads(threads)
+        .with_keyvalue(keyvalue.clone())
+        .with_exit_fifo(Some(exit_fifo));
+        create_container(
+            &id,
+            &rootdir,
+            &bundle,
+            executor,
+            console_socket.as_ref(),
+            cgroup_mode,
+            resources.as_ref(),
+        )?;
+        // the tmpfs is unmounted when the container is deleted
+        let memory_root = memory_root.map(memfs::MemoryRoot::into_path);
+
+        let console = OnceLock::new();
+        if let Some(socket) = console_socket {
+            let master = socket.receive_master(CONSOLE_TIMEOUT)?;
+            let _ = console.set(Console::new(master, cfg.get_stdin(), cfg.get_stdout())?);
+        }
 
         Ok(Self {
             id,
             exit_code: WaitableCell::new(),
             rootdir,
-            _phantom: Default::default(),
+            engine,
+            modules,
+            platform,
+            exec: None,
+            console,
+            stdin,
+            failures,
+            pool_stats,
+            landlock,
+            cpus,
+            threads,
+            keyvalue,
+            hot_reload,
+            memory_root,
+        })
+    }
+
+    /// Re-attach to the process of the container, started by a previous shim
+    /// The shim isn't the parent of the process, so it reads its exit code from the exit FIFO
+    /// of the bundle. The stdin of the container, its terminal and its hot reload are not
+    /// recovered.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn recover(
+        id: String,
+        pid: u32,
+        cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<Self, SandboxError> {
+        let engine = cfg.get_engine();
+        let bundle = cfg.get_bundle().to_path_buf();
+        let namespace = cfg.get_namespace();
+        let rootdir = Path::new(DEFAULT_CONTAINER_ROOT_DIR).join(E::name());
+        let rootdir = determine_rootdir(&bundle, &namespace, rootdir)?;
+
+        // the FIFO is opened before checking the process, so that its exit code isn't missed
+        let exit_fifo = ExitFifo::open(&bundle)?;
+        if !instance_exists(&rootdir, &id)? || kill(Pid::from_raw(pid as i32), None).is_err() {
+            return Err(SandboxError::NotFound(format!(
+                "process {pid} of container {id} is not running"
+            )));
+        }
+        log::info!("recovering instance {id} with pid {pid}");
+
+        let client =
+            containerd::Client::connect(cfg.get_containerd_address().as_str(), &namespace)?;
+        let (modules, platform) = client.load_modules(&id, &engine).unwrap_or_else(|e| {
+            log::warn!("Error obtaining wasm layers for container {id}: {e}");
+            (vec![], Platform::default())
+        });
+        let options = Options::read(&bundle)?;
+        let landlock = options
+            .landlock
+            .then(|| Landlock::new(options.landlock_paths));
+        let spec = Spec::load(bundle.join("config.json"))?;
+        let cpus = Cpus::from_spec(&spec)?;
+        let keyvalue = keyvalue(options.keyvalue.clone(), &namespace, &id, &spec);
+
+        let exit_code = WaitableCell::new();
+        thread::spawn({
+            let exit_code = exit_code.clone();
+            move || {
+                let status = wait_recovered(pid as i32, &exit_fifo);
+                let _ = exit_code.set((status, Utc::now()));
+            }
+        });
+
+        Ok(Self {
+            id,
+            exit_code,
+            rootdir,
+            engine,
+            modules,
+            platform,
+            exec: None,
+            console: OnceLock::new(),
+            stdin: None,
+            failures: Arc::new(FailureChannel::new()?),
+            pool_stats: PoolStats::shared()?,
+            landlock,
+            cpus,
+            threads: options.threads,
+            keyvalue,
+            hot_reload: None,
+            // the tmpfs of the recovered container is only mounted in its own mount namespace, and
+            // is freed with it, see the `memfs` module
+            memory_root: None,
+        })
+    }
+
+    /// Restore the container from the checkpoint in the `checkpoint` directory using CRIU
+    /// The container is restored in its bundle, and the shim re-attaches to the restored process as
+    /// it does to a recovered one: the process writes its exit code to the exit FIFO of the
+    /// bundle, and its stdin, terminal and hot reload are not restored.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn restore(
+        id: String,
+        checkpoint: &Path,
+        cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<(Self, u32), SandboxError> {
+        let bundle = cfg.get_bundle().to_path_buf();
+        let namespace = cfg.get_namespace();
+        let rootdir = Path::new(DEFAULT_CONTAINER_ROOT_DIR).join(E::name());
+        let rootdir = determine_rootdir(&bundle, &namespace, rootdir)?;
+        log::info!("restoring instance {id} from {checkpoint:?}");
+
+        // the restored process reopens the exit FIFO of the bundle
+        let _exit_fifo = ExitFifo::create(&bundle)?;
+        let spec = Spec::load(bundle.join("config.json"))?;
+        let pid = criu::restore(&id, &spec, &bundle, checkpoint, &rootdir.join(&id))?;
+        let pid = u32::try_from(pid).context("invalid pid")?;
+        Ok((Self::recover(id, pid, cfg)?, pid))
+    }
+
+    /// Create an exec process that joins the namespaces and cgroup of this container
+    /// The process is only spawned when the returned instance is started.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn exec(
+        &self,
+        exec_id: String,
+        process: &Process,
+        cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<Self, SandboxError> {
+        let (stdio, stdin) = init_stdio(cfg)?;
+        let terminal = cfg.get_terminal().then(|| {
+            (
+                cfg.get_stdin().to_path_buf(),
+                cfg.get_stdout().to_path_buf(),
+            )
+        });
+
+        Ok(Self {
+            id: self.id.clone(),
+            exit_code: WaitableCell::new(),
+            rootdir: self.rootdir.clone(),
+            engine: self.engine.clone(),
+            modules: self.modules.clone(),
+            platform: self.platform.clone(),
+            exec: Some(ExecProcess {
+                exec_id,
+                process: process.clone(),
+                stdio,
+                terminal,
+                pid: OnceLock::new(),
+            }),
+            console: OnceLock::new(),
+            stdin,
+            failures: Arc::new(FailureChannel::new()?),
+            pool_stats: PoolStats::shared()?,
+            landlock: self.landlock.clone(),
+            cpus: self.cpus.clone(),
+            threads: self.threads,
+            keyvalue: self.keyvalue.clone(),
+            hot_reload: None,
+            memory_root: None,
         })
     }
 
@@ -76,30 +545,43 @@ impl<E: Engine> SandboxInstance for Instance<E> {
         // make sure we have an exit code by the time we finish (even if there's a panic)
         let guard = self.exit_code.set_guard_with(|| (137, Utc::now()));
 
-        let container_root = get_instance_root(&self.rootdir, &self.id)?;
-        let mut container = Container::load(container_root)?;
-        let pid = container.pid().context("failed to get pid")?.as_raw();
+        let pid = match &self.exec {
+            Some(exec) => self.start_exec(exec)?,
+            None => {
+                let container_root = get_instance_root(&self.rootdir, &self.id)?;
+                let mut container = Container::load(container_root)?;
+                let pid = container.pid().context("failed to get pid")?.as_raw();
 
-        container.start()?;
+                container.start()?;
+                if let Some(cgroup) = cgroup_path(pid as u32) {
+                    log::info!("instance {} running in cgroup {cgroup:?}", self.id);
+                }
+                pid
+            }
+        };
+
+        let reloads = match &self.hot_reload {
+            Some(hot_reload) => {
+                hot_reload.watch(self.engine.clone(), pid, self.exit_code.clone())?;
+                Some(hot_reload.reloads())
+            }
+            None => None,
+        };
 
         let exit_code = self.exit_code.clone();
         thread::spawn(move || {
             // move the exit code guard into this thread
             let _guard = guard;
 
-            let status = match waitid(WaitID::Pid(Pid::from_raw(pid)), WaitPidFlag::WEXITED) {
-                Ok(WaitStatus::Exited(_, status)) => status,
-                Ok(WaitStatus::Signaled(_, sig, _)) => sig as i32,
-                Ok(_) => 0,
-                Err(Errno::ECHILD) => {
-                    log::info!("no child process");
-                    0
+            let mut pid = pid;
+            let status = loop {
+                let status = wait_exit(pid);
+                // a reloaded container goes on with the process running the new module
+                match reloads.as_ref().and_then(|reloads| reloads.next_process()) {
+                    Some(next) => pid = next,
+                    None => break status,
                 }
-                Err(e) => {
-                    log::error!("waitpid failed: {e}");
-                    137
-                }
-            } as u32;
+            };
             let _ = exit_code.set((status, Utc::now()));
         });
 
@@ -110,6 +592,21 @@ impl<E: Engine> SandboxInstance for Instance<E> {
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn kill(&self, signal: u32) -> Result<(), SandboxError> {
         log::info!("sending signal {signal} to instance: {}", self.id);
+        if let Some(exec) = &self.exec {
+            let pid = exec.pid.get().ok_or_else(|| {
+                SandboxError::FailedPrecondition(format!(
+                    "exec process {} is not running",
+                    exec.exec_id
+                ))
+            })?;
+            let signal = NixSignal::try_from(signal as i32).map_err(|err| {
+                SandboxError::InvalidArgument(format!("invalid signal number: {}", err))
+            })?;
+            kill(Pid::from_raw(*pid), signal)
+                .with_context(|| format!("failed to signal exec process {}", exec.exec_id))?;
+            return Ok(());
+        }
+
         let signal = Signal::try_from(signal as i32).map_err(|err| {
             SandboxError::InvalidArgument(format!("invalid signal number: {}", err))
         })?;
@@ -117,6 +614,13 @@ impl<E: Engine> SandboxInstance for Instance<E> {
         let mut container = Container::load(container_root)
             .with_context(|| format!("could not load state for container {}", self.id))?;
 
+        // the other processes in the cgroup of the shim are the shim and the other containers
+        if rootless::in_shim_cgroup(&container) {
+            if container.can_kill() {
+                container.kill(signal, false)?;
+            }
+            return Ok(());
+        }
         container.kill(signal, true)?;
 
         Ok(())
@@ -127,6 +631,14 @@ impl<E: Engine> SandboxInstance for Instance<E> {
     #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn delete(&self) -> Result<(), SandboxError> {
         log::info!("deleting instance: {}", self.id);
+        if let Some(exec) = &self.exec {
+            // the exec process shares the container state, only its pid and process files belong
+            // to it
+            let _ = fs::remove_file(self.exec_pid_file(exec));
+            let _ = fs::remove_file(self.exec_process_file(exec));
+            return Ok(());
+        }
+
         match instance_exists(&self.rootdir, &self.id) {
             Ok(true) => {}
             Ok(false) => return Ok(()),
@@ -138,12 +650,99 @@ impl<E: Engine> SandboxInstance for Instance<E> {
         let container_root = get_instance_root(&self.rootdir, &self.id)?;
         match Container::load(container_root) {
             Ok(mut container) => {
-                container.delete(true)?;
+                rootless::delete_container(&mut container)?;
             }
             Err(err) => {
                 log::error!("could not find the container, skipping cleanup: {}", err);
             }
         }
+        if let Some(memory_root) = &self.memory_root {
+            memfs::unmount_memory_root(memory_root);
+        }
+        Ok(())
+    }
+
+    /// Pause the instance using the cgroup freezer
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn pause(&self) -> Result<(), SandboxError> {
+        log::info!("pausing instance: {}", self.id);
+        let container_root = get_instance_root(&self.rootdir, &self.id)?;
+        let mut container = Container::load(container_root)
+            .with_context(|| format!("could not load state for container {}", self.id))?;
+        if rootless::in_shim_cgroup(&container) {
+            return Err(SandboxError::FailedPrecondition(format!(
+                "container {} runs in the cgroup of the shim, which can't be frozen",
+                self.id
+            )));
+        }
+
+        container.pause()?;
+
+        Ok(())
+    }
+
+    /// Resume the instance by thawing its cgroup
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn resume(&self) -> Result<(), SandboxError> {
+        log::info!("resuming instance: {}", self.id);
+        let container_root = get_instance_root(&self.rootdir, &self.id)?;
+        let mut container = Container::load(container_root)
+            .with_context(|| format!("could not load state for container {}", self.id))?;
+        if rootless::in_shim_cgroup(&container) {
+            return Err(SandboxError::FailedPrecondition(format!(
+                "container {} runs in the cgroup of the shim, which can't be frozen",
+                self.id
+            )));
+        }
+
+        container.resume()?;
+
+        Ok(())
+    }
+
+    /// Checkpoint the running instance into the `path` directory using CRIU
+    /// The dump includes the whole wasm instance, i.e., linear memory, globals and open file descriptors.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn checkpoint(&self, path: &Path) -> Result<(), SandboxError> {
+        log::info!("checkpointing instance {} to {path:?}", self.id);
+        let container_root = get_instance_root(&self.rootdir, &self.id)?;
+        let mut container = Container::load(container_root)
+            .with_context(|| format!("could not load state for container {}", self.id))?;
+
+        container.checkpoint(&CheckpointOptions {
+            ext_unix_sk: false,
+            file_locks: false,
+            image_path: path.to_path_buf(),
+            leave_running: true,
+            shell_job: false,
+            tcp_established: false,
+            work_path: None,
+        })?;
+
+        Ok(())
+    }
+
+    /// Resize the terminal of the instance
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn resize_pty(&self, width: u32, height: u32) -> Result<(), SandboxError> {
+        let console = self.console.get().ok_or_else(|| {
+            SandboxError::FailedPrecondition(format!("instance {} has no terminal", self.id))
+        })?;
+        console
+            .resize(width, height)
+            .with_context(|| format!("failed to resize the terminal of instance {}", self.id))?;
+        Ok(())
+    }
+
+    /// Close the stdin of the instance
+    /// With a terminal, stdin is copied to the console and is left open.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn close_stdin(&self) -> Result<(), SandboxError> {
+        if let Some(stdin) = &self.stdin {
+            stdin
+                .close()
+                .with_context(|| format!("failed to close the stdin of instance {}", self.id))?;
+        }
         Ok(())
     }
 
@@ -154,4 +753,201 @@ impl<E: Engine> SandboxInstance for Instance<E> {
     fn wait_timeout(&self, t: impl Into<Option<Duration>>) -> Option<(u32, DateTime<Utc>)> {
         self.exit_code.wait_timeout(t).copied()
     }
+
+    fn take_failure(&self) -> Option<GuestFailure> {
+        self.failures.take()
+    }
+
+    fn pool_metrics(&self) -> Option<PoolMetrics> {
+        // the engine reports a pool, or the wasm instances of the container and their memory
+        let metrics = self.pool_stats.metrics();
+        (metrics != PoolMetrics::default()).then_some(metrics)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn precompile(
+        image: &str,
+        cfg: &InstanceConfig<Self::Engine>,
+    ) -> Result<Vec<(String, String)>, SandboxError> {
+        let client = containerd::Client::connect(
+            cfg.get_containerd_address().as_str(),
+            cfg.get_namespace(),
+        )?;
+        client.precompile_image(image, &cfg.get_engine())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn pull(image: &str, cfg: &InstanceConfig<Self::Engine>) -> Result<Vec<String>, SandboxError> {
+        let client = containerd::Client::connect(
+            cfg.get_containerd_address().as_str(),
+            cfg.get_namespace(),
+        )?;
+        client.pull_image::<E>(image)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn gc(cfg: &InstanceConfig<Self::Engine>) -> Result<Vec<String>, SandboxError> {
+        let client = containerd::Client::connect(
+            cfg.get_containerd_address().as_str(),
+            cfg.get_namespace(),
+        )?;
+        client.collect_precompiled()
+    }
+}
+
+// Creates the process of the container, which runs the engine once the container is started
+// The scope of a container the shim creates the cgroup of gets the limits of `resources`.
+fn create_container<E: Engine>(
+    id: &str,
+    rootdir: &Path,
+    bundle: &Path,
+    executor: Executor<E>,
+    console_socket: Option<&ConsoleSocket>,
+    cgroup_mode: CgroupMode,
+    resources: Option<&LinuxResources>,
+) -> Result<(), SandboxError> {
+    if cgroup_mode == CgroupMode::Scope {
+        scope::create(id, resources)?;
+    }
+    let created = ContainerBuilder::new(id.to_string(), SyscallType::Linux)
+        .with_executor(executor)
+        .with_root_path(rootdir)?
+        .with_console_socket(console_socket.map(ConsoleSocket::path))
+        .as_init(bundle)
+        .with_systemd(cgroup_mode == CgroupMode::Systemd)
+        .build();
+    if let Err(err) = created {
+        if cgroup_mode == CgroupMode::Scope {
+            scope::remove(id);
+        }
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+// Waits for the process `pid` to exit and returns its exit code
+fn wait_exit(pid: i32) -> u32 {
+    (match waitid(WaitID::Pid(Pid::from_raw(pid)), WaitPidFlag::WEXITED) {
+        Ok(WaitStatus::Exited(_, status)) => status,
+        Ok(WaitStatus::Signaled(_, sig, _)) => sig as i32,
+        Ok(_) => 0,
+        Err(Errno::ECHILD) => {
+            log::info!("no child process");
+            0
+        }
+        Err(e) => {
+            log::error!("waitpid failed: {e}");
+            137
+        }
+    }) as u32
+}
+
+// Waits for the process `pid`, which isn't a child of the shim, to exit and returns the exit
+// code it wrote to its exit FIFO
+fn wait_recovered(pid: i32, exit_fifo: &ExitFifo) -> u32 {
+    let mut code = None;
+    while kill(Pid::from_raw(pid), None).is_ok() {
+        code = exit_fifo.take().or(code);
+        thread::sleep(RECOVERED_POLL_INTERVAL);
+    }
+    // a process that didn't write its exit code was killed
+    exit_fifo.take().or(code).unwrap_or(137)
+}
+
+impl<E: Engine> Instance<E> {
+    fn exec_pid_file(&self, exec: &ExecProcess) -> PathBuf {
+        self.rootdir
+            .join(&self.id)
+            .join(format!("{}.pid", exec.exec_id))
+    }
+
+    fn exec_process_file(&self, exec: &ExecProcess) -> PathBuf {
+        self.rootdir
+            .join(&self.id)
+            .join(format!("{}.process.json", exec.exec_id))
+    }
+
+    /// Spawn the exec process as a tenant of the container and return its pid
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn start_exec(&self, exec: &ExecProcess) -> Result<i32, SandboxError> {
+        let pid_file = self.exec_pid_file(exec);
+        // libcontainer reads the whole process from a file, as `runc exec --process` does, so that
+        // its user, e.g., mapped in the user namespace of the container, is honored
+        let process_file = self.exec_process_file(exec);
+        let process = serde_json::to_vec(&exec.process).context("failed to encode the process")?;
+        fs::write(&process_file, process)
+            .with_context(|| format!("failed to write {process_file:?}"))?;
+
+        let id = self.id.clone();
+        let rootdir = self.rootdir.clone();
+        let executor = Executor::new(
+            self.engine.clone(),
+            exec.stdio.clone(),
+            self.modules.clone(),
+            self.platform.clone(),
+            self.failures.clone(),
+            self.pool_stats.clone(),
+            self.landlock.clone(),
+        )
+        .with_cpus(self.cpus.clone())
+        .with_threads(self.threads)
+        .with_keyvalue(self.keyvalue.clone());
+        let pid_path = pid_file.clone();
+        let console_socket = exec
+            .terminal
+            .as_ref()
+            .map(|_| ConsoleSocket::new())
+            .transpose()?;
+        let console_path = console_socket.as_ref().map(|s| s.path().to_path_buf());
+
+        // The tenant builder only returns once the exec process exits, as the wasm
+        // workload never calls `execve` and keeps the notification pipe open.
+        // Build it in the background and pick up the pid from the pid file instead.
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name(format!("{}-exec", exec.exec_id))
+            .spawn(move || {
+                let res = ContainerBuilder::new(id, SyscallType::Linux)
+                    .with_executor(executor)
+                    .with_console_socket(console_path)
+                    .with_root_path(rootdir)
+                    .and_then(|b| b.with_pid_file(Some(pid_path)))
+                    .and_then(|b| {
+                        b.as_tenant()
+                            .with_process(Some(process_file))
+                            .with_detach(true)
+                            .build()
+                    });
+                let _ = tx.send(res);
+            })
+            .context("failed to spawn exec thread")?;
+
+        let pid = loop {
+            if let Some(pid) = fs::read_to_string(&pid_file)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<i32>().ok())
+            {
+                break pid;
+            }
+            match rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(Ok(pid)) => break pid.as_raw(),
+                Ok(Err(err)) => return Err(err.into()),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(SandboxError::Others(format!(
+                        "exec process {} failed to start",
+                        exec.exec_id
+                    )));
+                }
+            }
+        };
+
+        if let (Some(socket), Some((stdin, stdout))) = (console_socket, &exec.terminal) {
+            let master = socket.receive_master(CONSOLE_TIMEOUT)?;
+            let _ = self.console.set(Console::new(master, stdin, stdout)?);
+        }
+
+        let _ = exec.pid.set(pid);
+        Ok(pid)
+    }
 }
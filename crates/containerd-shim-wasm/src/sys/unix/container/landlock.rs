@@ -0,0 +1,162 @@
+//! Landlock confinement of the container process running the engine.
+//!
+//! With the `landlock` runtime option, the executor restricts itself right before the engine runs,
+//! so that only the files of the bundle, i.e., the rootfs of the container, the volumes preopened
+//! for the guest, and the `landlock_paths` of the runtime options, e.g., a cache directory, can be
+//! opened, even by guest code that escapes the engine.
+//! The filesystems mounted at the root of the container, e.g., `/proc`, `/sys` or `/dev`, are not
+//! reachable, except for the few files an engine needs.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+    ABI,
+};
+
+use crate::container::RuntimeContext;
+
+// the newest ABI handled, the restrictions are best effort on kernels with an older one
+const LANDLOCK_ABI: ABI = ABI::V3;
+
+// files outside of the rootfs an engine uses, e.g., for its random numbers, or to read the layers
+// mapped from the content store
+const ENGINE_PATHS: &[&str] = &[
+    "/dev/null",
+    "/dev/zero",
+    "/dev/random",
+    "/dev/urandom",
+    "/proc/self/fd",
+];
+
+/// The files of the container the engine can open
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Landlock {
+    /// Additional paths of the container the engine can read and write
+    pub paths: Vec<PathBuf>,
+}
+
+impl Landlock {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self { paths }
+    }
+
+    /// Restricts the current process, and the processes it spawns, to the files of the container.
+    pub fn restrict_self(&self, ctx: &impl RuntimeContext) -> Result<()> {
+        let (read_only, read_write) = self.rules(ctx, Path::new("/"));
+        let status = Ruleset::default()
+            .handle_access(AccessFs::from_all(LANDLOCK_ABI))?
+            .create()?
+            // directories can be listed, e.g., to preopen the root, but only the files of the
+            // rules below can be opened
+            .add_rules(path_beneath_rules(["/"], AccessFs::ReadDir))?
+            .add_rules(path_beneath_rules(
+                read_only,
+                AccessFs::from_read(LANDLOCK_ABI),
+            ))?
+            .add_rules(path_beneath_rules(
+                read_write,
+                AccessFs::from_all(LANDLOCK_ABI),
+            ))?
+            .restrict_self()?;
+        match status.ruleset {
+            RulesetStatus::FullyEnforced => log::info!("landlock confinement enforced"),
+            RulesetStatus::PartiallyEnforced => {
+                log::info!("landlock confinement partially enforced by the kernel")
+            }
+            RulesetStatus::NotEnforced => log::warn!("landlock is not supported by the kernel"),
+        }
+        Ok(())
+    }
+
+    // the read-only and the writable paths of the container
+    fn rules(&self, ctx: &impl RuntimeContext, root: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut read_only = vec![];
+        let mut read_write: Vec<PathBuf> = ENGINE_PATHS.iter().map(PathBuf::from).collect();
+
+        let rootfs = rootfs_entries(root);
+        match ctx.readonly_rootfs() {
+            true => read_only.extend(rootfs),
+            false => read_write.extend(rootfs),
+        }
+        for volume in ctx.volumes() {
+            match volume.readonly {
+                true => read_only.push(volume.host_path),
+                false => read_write.push(volume.host_path),
+            }
+        }
+        if let Ok(Some(log)) = ctx.record_log() {
+            read_write.push(log.path);
+        }
+        read_write.extend(ctx.coredump_file());
+        read_write.extend(self.paths.iter().cloned());
+        (read_only, read_write)
+    }
+}
+
+// the entries of the root that are on the filesystem of the rootfs, rather than mounted on it
+fn rootfs_entries(root: &Path) -> Vec<PathBuf> {
+    let Ok(dev) = root.metadata().map(|m| m.dev()) else {
+        return vec![];
+    };
+    let Ok(entries) = root.read_dir() else {
+        return vec![];
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.symlink_metadata().is_ok_and(|m| m.dev() == dev))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use oci_spec::image::Platform;
+    use oci_spec::runtime::{ProcessBuilder, RootBuilder, SpecBuilder};
+
+    use super::*;
+    use crate::container::WasiContext;
+
+    #[test]
+    fn test_landlock_rules() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("app"))?;
+        std::fs::write(dir.path().join("app.wasm"), b"\0asm")?;
+
+        let spec = SpecBuilder::default()
+            .root(
+                RootBuilder::default()
+                    .path("rootfs")
+                    .readonly(true)
+                    .build()?,
+            )
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .annotations(HashMap::new())
+            .mounts(vec![])
+            .build()?;
+        let ctx = WasiContext {
+            spec: &spec,
+            wasm_layers: &[],
+            platform: &Platform::default(),
+            pool_stats: None,
+            threads: None,
+            keyvalue: None,
+        };
+
+        let landlock = Landlock::new(vec![PathBuf::from("/var/cache/app")]);
+        let (mut read_only, read_write) = landlock.rules(&ctx, dir.path());
+        read_only.sort();
+
+        assert_eq!(
+            read_only,
+            [dir.path().join("app"), dir.path().join("app.wasm")]
+        );
+        assert!(read_write.contains(&PathBuf::from("/dev/urandom")));
+        assert!(read_write.contains(&PathBuf::from("/var/cache/app")));
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Mandatory access control labels of the container process, i.e., the `process.selinuxLabel` and
+//! the `process.apparmorProfile` of the spec.
+//!
+//! libcontainer only sets the AppArmor profile of the next `execve`, which the container process
+//! running the engine never calls, and ignores the SELinux label. The executor applies both right
+//! before the engine starts, so that it runs confined as a runc container would be, and sets the
+//! SELinux label of the `execve` of a linux container.
+//!
+//! Linux sets the labels of the thread writing them only, and the threads inherit the labels of
+//! the thread creating them. The labels are only applied immediately to a single threaded
+//! process, before the engine starts its threads, so that the whole process is confined, and the
+//! executor fails with an unsupported error otherwise.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::Spec;
+
+const SELINUX_FS: &str = "/sys/fs/selinux";
+
+/// When the labels take effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transition {
+    /// On the next `execve` of the process
+    OnExec,
+    /// Immediately, for the process running the engine
+    Now,
+}
+
+/// The MAC labels of the container process
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MacLabels {
+    pub selinux_label: Option<String>,
+    pub apparmor_profile: Option<String>,
+}
+
+impl MacLabels {
+    pub fn from_spec(spec: &Spec) -> Self {
+        let process = spec.process().as_ref();
+        let non_empty = |label: &Option<String>| label.clone().filter(|label| !label.is_empty());
+        Self {
+            selinux_label: process.and_then(|p| non_empty(p.selinux_label())),
+            apparmor_profile: process.and_then(|p| non_empty(p.apparmor_profile())),
+        }
+    }
+
+    /// Applies the labels to the current process
+    pub fn apply(&self, transition: Transition) -> Result<()> {
+        let labeled = self.selinux_label.is_some() || self.apparmor_profile.is_some();
+        if labeled && transition == Transition::Now {
+            let threads = threads()?;
+            if threads > 1 {
+                bail!(
+                    "applying the mac labels to a process with {threads} threads is unsupported, \
+                     the other threads would run unconfined"
+                );
+            }
+        }
+
+        if let Some(label) = &self.selinux_label {
+            if !Path::new(SELINUX_FS).join("enforce").exists() {
+                log::warn!("selinux label {label} ignored, selinux is not enabled on this system");
+            } else {
+                let attr = match transition {
+                    Transition::OnExec => "exec",
+                    Transition::Now => "current",
+                };
+                write_attr("selinux", attr, label)
+                    .with_context(|| format!("failed to set the selinux label {label}"))?;
+            }
+        }
+
+        // libcontainer refuses to create a container with a profile when AppArmor is disabled
+        // and sets the profile of the next `execve`
+        if let (Some(profile), Transition::Now) = (&self.apparmor_profile, transition) {
+            write_attr("apparmor", "current", &format!("changeprofile {profile}"))
+                .with_context(|| format!("failed to change to the apparmor profile {profile}"))?;
+        }
+        Ok(())
+    }
+}
+
+// Returns the number of threads of the current process
+fn threads() -> Result<usize> {
+    let tasks = fs::read_dir("/proc/self/task").context("failed to list the threads")?;
+    Ok(tasks.count())
+}
+
+// Writes a LSM attribute of the current thread, with the LSM specific interface of Linux 5.1+ or
+// the legacy one.
+fn write_attr(lsm: &str, attr: &str, value: &str) -> Result<()> {
+    let path = Path::new("/proc/thread-self/attr").join(lsm).join(attr);
+    if fs::write(path, value).is_ok() {
+        return Ok(());
+    }
+    fs::write(Path::new("/proc/thread-self/attr").join(attr), value)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_labels_from_spec() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .selinux_label("system_u:system_r:container_t:s0:c1,c2")
+                    .apparmor_profile("")
+                    .build()?,
+            )
+            .build()?;
+        let labels = MacLabels::from_spec(&spec);
+        assert_eq!(
+            labels.selinux_label.as_deref(),
+            Some("system_u:system_r:container_t:s0:c1,c2")
+        );
+        assert_eq!(labels.apparmor_profile, None);
+
+        let spec = SpecBuilder::default().build()?;
+        assert_eq!(MacLabels::from_spec(&spec), MacLabels::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        MacLabels::default().apply(Transition::Now)?;
+
+        // the profile of the next `execve` is set by libcontainer
+        let labels = MacLabels {
+            selinux_label: None,
+            apparmor_profile: Some("unconfined".to_string()),
+        };
+        labels.apply(Transition::OnExec)?;
+
+        let (done, wait) = std::sync::mpsc::channel::<()>();
+        let other = std::thread::spawn(move || wait.recv());
+        assert!(threads()? > 1);
+        let err = labels.apply(Transition::Now).unwrap_err();
+        assert!(err.to_string().contains("unsupported"), "{err}");
+        drop(done);
+        let _ = other.join();
+        Ok(())
+    }
+}
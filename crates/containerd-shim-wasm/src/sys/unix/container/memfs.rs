@@ -0,0 +1,283 @@
+//! In-memory root of the containers run with the `io.containerd.wasm.fs: memory` annotation.
+//!
+//! The shim mounts a tmpfs in its mount namespace, copies the rootfs containerd mounted for the
+//! container, i.e., the layers of its image, into it, and runs the container on it instead, so
+//! that the guest reads and writes memory rather than the filesystem of the host, and its writes
+//! are dropped with the container. The tmpfs is as large as the memory limit of the container.
+//!
+//! The shim copies the rootfs before the cgroup of the container exists, so the copy is charged
+//! to the cgroup of the shim, and the memory limit of the container is lowered by its size, so
+//! that the container and its root stay within the limit together. The guest's own writes are
+//! charged to the cgroup of the container.
+//!
+//! The tmpfs is only mounted in the mount namespace of the shim, and the one of the container
+//! cloned from it. When the shim exits, e.g., before a new shim recovers the container, the tmpfs
+//! stays mounted in the mount namespace of the container only, and is freed when it exits.
+//!
+//! Mounting the tmpfs needs `CAP_SYS_ADMIN`, so a rootless shim refuses the annotation.
+
+use std::fs;
+use std::os::unix::fs::{lchown, symlink, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use oci_spec::runtime::Spec;
+
+use super::rootless;
+use crate::sandbox::error::{Error, Result};
+use crate::sandbox::oci::FS_ANNOTATION;
+
+// the directory of the bundle the tmpfs is mounted on
+const MEMFS_DIR: &str = "memfs";
+
+/// The filesystem the root of a container is backed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RootFs {
+    /// The rootfs containerd mounted on the host
+    Host,
+    /// A copy of the rootfs in memory
+    Memory,
+}
+
+fn root_fs(spec: &Spec) -> Result<RootFs> {
+    let annotations = spec.annotations().clone().unwrap_or_default();
+    match annotations.get(FS_ANNOTATION).map(String::as_str) {
+        None | Some("host") => Ok(RootFs::Host),
+        Some("memory") => Ok(RootFs::Memory),
+        Some(fs) => Err(Error::InvalidArgument(format!(
+            "invalid {FS_ANNOTATION} annotation {fs:?}, expected `host` or `memory`"
+        ))),
+    }
+}
+
+/// The tmpfs the root of a container is moved to, unmounted when dropped, e.g., when the
+/// container fails to be created, unless it is kept with [`MemoryRoot::into_path`].
+pub(crate) struct MemoryRoot(Option<PathBuf>);
+
+impl MemoryRoot {
+    /// Keeps the tmpfs mounted, for the container created on it.
+    /// Returns the tmpfs, to unmount when the container is deleted.
+    pub fn into_path(mut self) -> PathBuf {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for MemoryRoot {
+    fn drop(&mut self) {
+        if let Some(target) = self.0.take() {
+            unmount_memory_root(&target);
+        }
+    }
+}
+
+/// Moves the root of a container with the `io.containerd.wasm.fs: memory` annotation to a tmpfs
+/// seeded with its rootfs.
+pub(crate) fn mount_memory_root(spec: &mut Spec, bundle: &Path) -> Result<Option<MemoryRoot>> {
+    if root_fs(spec)? == RootFs::Host {
+        return Ok(None);
+    }
+    if rootless::is_rootless() {
+        return Err(Error::FailedPrecondition(format!(
+            "a rootless shim can't mount the tmpfs of the {FS_ANNOTATION}: memory annotation, \
+             which needs CAP_SYS_ADMIN"
+        )));
+    }
+    let mut root = spec.root().clone().unwrap_or_default();
+    let rootfs = bundle.join(root.path());
+    let target = bundle.join(MEMFS_DIR);
+    fs::create_dir_all(&target)?;
+
+    let metadata = fs::metadata(&rootfs)?;
+    let mut options = format!("mode={:o}", metadata.mode() & 0o7777);
+    if let Some(limit) = memory_limit(spec) {
+        options.push_str(&format!(",size={limit}"));
+    }
+    mount(
+        Some("tmpfs"),
+        &target,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(options.as_str()),
+    )?;
+    let memory_root = MemoryRoot(Some(target.clone()));
+    lchown(&target, Some(metadata.uid()), Some(metadata.gid()))?;
+    let size = copy_dir(&rootfs, &target)?;
+    lower_memory_limit(spec, size)?;
+    log::info!("copied the rootfs {rootfs:?} to the tmpfs {target:?}, {size} bytes");
+
+    root.set_path(target);
+    spec.set_root(Some(root));
+    Ok(Some(memory_root))
+}
+
+/// Unmounts the tmpfs of the root of a container, freeing its memory.
+pub(crate) fn unmount_memory_root(target: &Path) {
+    if let Err(err) = umount2(target, MntFlags::MNT_DETACH) {
+        log::warn!("failed to unmount the in-memory root {target:?}: {err}");
+    }
+}
+
+fn memory_limit(spec: &Spec) -> Option<i64> {
+    spec.linux()
+        .as_ref()?
+        .resources()
+        .as_ref()?
+        .memory()
+        .as_ref()?
+        .limit()
+        .filter(|limit| *limit > 0)
+}
+
+// Lowers the memory limit of the container by the `size` of the copy of its rootfs, charged to
+// the cgroup of the shim.
+fn lower_memory_limit(spec: &mut Spec, size: u64) -> Result<()> {
+    let Some(limit) = memory_limit(spec) else {
+        return Ok(());
+    };
+    let lowered = i64::try_from(size)
+        .ok()
+        .and_then(|size| limit.checked_sub(size))
+        .filter(|lowered| *lowered > 0)
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "the rootfs of {size} bytes doesn't fit in the memory limit of {limit} bytes"
+            ))
+        })?;
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    let mut resources = linux.resources().clone().unwrap_or_default();
+    // `LinuxMemory` has no setters, its other fields are kept as they are
+    let mut memory = serde_json::to_value(resources.memory().unwrap_or_default())?;
+    memory["limit"] = lowered.into();
+    resources.set_memory(Some(serde_json::from_value(memory)?));
+    linux.set_resources(Some(resources));
+    spec.set_linux(Some(linux));
+    Ok(())
+}
+
+// Copies the files, directories and symlinks of `src` to `dst`, with their permissions and
+// owners. Other files, e.g., devices, are skipped, as the container gets its own `/dev`.
+// Returns the size of the files copied.
+fn copy_dir(src: &Path, dst: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let (src, dst) = (entry.path(), dst.join(entry.file_name()));
+        let metadata = entry.metadata()?;
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            fs::create_dir(&dst)?;
+            size += copy_dir(&src, &dst)?;
+        } else if file_type.is_file() {
+            size += fs::copy(&src, &dst)?;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(&src)?, &dst)?;
+        } else {
+            log::debug!("skipping the special file {src:?}");
+            continue;
+        }
+        lchown(&dst, Some(metadata.uid()), Some(metadata.gid()))?;
+        if !file_type.is_symlink() {
+            fs::set_permissions(&dst, fs::Permissions::from_mode(metadata.mode() & 0o7777))?;
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use oci_spec::runtime::{
+        LinuxBuilder, LinuxMemoryBuilder, LinuxResourcesBuilder, RootBuilder, SpecBuilder,
+    };
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_root_fs() -> anyhow::Result<()> {
+        let spec = |fs: &str| {
+            SpecBuilder::default()
+                .annotations(HashMap::from([(FS_ANNOTATION.to_string(), fs.to_string())]))
+                .build()
+        };
+        assert_eq!(root_fs(&Spec::default())?, RootFs::Host);
+        assert_eq!(root_fs(&spec("host")?)?, RootFs::Host);
+        assert_eq!(root_fs(&spec("memory")?)?, RootFs::Memory);
+        assert!(root_fs(&spec("disk")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_dir() -> anyhow::Result<()> {
+        let (src, dst) = (tempdir()?, tempdir()?);
+        fs::create_dir_all(src.path().join("etc/app"))?;
+        fs::write(src.path().join("etc/app/config"), "key=value")?;
+        fs::set_permissions(
+            src.path().join("etc/app/config"),
+            fs::Permissions::from_mode(0o640),
+        )?;
+        symlink("app/config", src.path().join("etc/config"))?;
+
+        assert_eq!(copy_dir(src.path(), dst.path())?, 9);
+        let config = dst.path().join("etc/app/config");
+        assert_eq!(fs::read_to_string(&config)?, "key=value");
+        assert_eq!(fs::metadata(&config)?.mode() & 0o777, 0o640);
+        assert_eq!(
+            fs::read_link(dst.path().join("etc/config"))?,
+            Path::new("app/config")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unmount_memory_root_on_error() -> anyhow::Result<()> {
+        let bundle = tempdir()?;
+        fs::create_dir(bundle.path().join("rootfs"))?;
+        fs::write(bundle.path().join("rootfs/module.wasm"), [0; 1024])?;
+        // the rootfs doesn't fit in the memory limit
+        let memory = LinuxMemoryBuilder::default().limit(512).build()?;
+        let resources = LinuxResourcesBuilder::default().memory(memory).build()?;
+        let mut spec = SpecBuilder::default()
+            .annotations(HashMap::from([(
+                FS_ANNOTATION.to_string(),
+                "memory".to_string(),
+            )]))
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .linux(LinuxBuilder::default().resources(resources).build()?)
+            .build()?;
+
+        let err = mount_memory_root(&mut spec, bundle.path())
+            .err()
+            .expect("the rootfs doesn't fit in the tmpfs");
+        if rootless::is_rootless() {
+            assert!(matches!(err, Error::FailedPrecondition(_)), "{err}");
+            return Ok(());
+        }
+        assert!(matches!(err, Error::InvalidArgument(_)), "{err}");
+        // the tmpfs is unmounted, so its directory is on the filesystem of the bundle again
+        let target = fs::metadata(bundle.path().join(MEMFS_DIR))?;
+        assert_eq!(target.dev(), fs::metadata(bundle.path())?.dev());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lower_memory_limit() -> anyhow::Result<()> {
+        let spec = |limit: i64| {
+            let memory = LinuxMemoryBuilder::default().limit(limit).build()?;
+            let resources = LinuxResourcesBuilder::default().memory(memory).build()?;
+            let linux = LinuxBuilder::default().resources(resources).build()?;
+            SpecBuilder::default().linux(linux).build()
+        };
+
+        let mut limited = spec(1024)?;
+        lower_memory_limit(&mut limited, 1000)?;
+        assert_eq!(memory_limit(&limited), Some(24));
+        assert!(lower_memory_limit(&mut limited, 24).is_err());
+
+        let mut unlimited = Spec::default();
+        lower_memory_limit(&mut unlimited, 1000)?;
+        assert_eq!(memory_limit(&unlimited), None);
+        Ok(())
+    }
+}
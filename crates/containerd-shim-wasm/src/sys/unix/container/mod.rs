@@ -1,2 +1,14 @@
+mod console;
+mod cpuset;
+mod criu;
 mod executor;
+mod exit_fifo;
+mod failure;
 pub mod instance;
+mod landlock;
+mod mac;
+mod memfs;
+mod reload;
+mod rootless;
+mod scope;
+mod stdin;
@@ -0,0 +1,247 @@
+//! Hot reload of the module of a container when its image is updated, with the
+//! `runwasi.io/hot-reload` annotation, e.g., for the inner loop of development.
+//!
+//! The shim polls the digest of the image of the container in containerd. When the image is
+//! updated, e.g., pulled or imported again, the shim loads the wasm layers of the new image,
+//! drains the process of the container with a SIGTERM, or a SIGKILL after [`DRAIN_TIMEOUT`], and
+//! starts a new process running the new module in its place, without the task exiting.
+//! The module keeps running when the layers of the new image can't be loaded.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use oci_spec::image::Platform;
+
+use crate::container::Engine;
+use crate::sandbox::containerd::Client;
+use crate::sandbox::instance::{ReloadEvent, ReloadObserver};
+use crate::sandbox::oci::WasmLayer;
+use crate::sandbox::sync::WaitableCell;
+
+// Interval between two checks of the digest of the image
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Time the process of the container has to exit after a SIGTERM, and then after a SIGKILL
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Creates and starts the process of the container with new wasm layers, and returns its pid
+pub(crate) type Restart = Arc<dyn Fn(Vec<WasmLayer>, Platform) -> Result<i32> + Send + Sync>;
+
+/// Watches the image of a container, and replaces its process when the image is updated
+#[derive(Clone)]
+pub(crate) struct HotReload {
+    id: String,
+    address: String,
+    namespace: String,
+    digest: String,
+    reloads: Arc<Reloads>,
+    restart: Restart,
+    observer: Option<ReloadObserver>,
+}
+
+impl HotReload {
+    pub fn new(
+        id: &str,
+        client: &Client,
+        address: &str,
+        namespace: &str,
+        restart: Restart,
+        observer: Option<ReloadObserver>,
+    ) -> Result<Self> {
+        let (image, digest) = client.container_image(id)?;
+        log::info!("reloading container {id} when image {image} is updated from {digest}");
+        Ok(Self {
+            id: id.to_string(),
+            address: address.to_string(),
+            namespace: namespace.to_string(),
+            digest,
+            reloads: Arc::default(),
+            restart,
+            observer,
+        })
+    }
+
+    /// The reloads the thread waiting for the process of the container takes part in
+    pub fn reloads(&self) -> Arc<Reloads> {
+        self.reloads.clone()
+    }
+
+    /// Watches the image in the background while the container runs, from its process `pid`,
+    /// until `exit_code` is set.
+    pub fn watch<E: Engine>(
+        &self,
+        engine: E,
+        pid: i32,
+        exit_code: WaitableCell<(u32, DateTime<Utc>)>,
+    ) -> std::io::Result<()> {
+        let reload = self.clone();
+        thread::Builder::new()
+            .name(format!("{}-reload", self.id))
+            .spawn(move || reload.run(engine, pid, exit_code))?;
+        Ok(())
+    }
+
+    fn run<E: Engine>(
+        self,
+        engine: E,
+        mut pid: i32,
+        exit_code: WaitableCell<(u32, DateTime<Utc>)>,
+    ) {
+        let id = &self.id;
+        let client = match Client::connect(self.address.as_str(), &self.namespace) {
+            Ok(client) => client,
+            Err(err) => {
+                log::error!("failed to watch the image of container {id}: {err}");
+                return;
+            }
+        };
+
+        let mut digest = self.digest.clone();
+        while exit_code.wait_timeout(POLL_INTERVAL).is_none() {
+            let (image, current) = match client.container_image(id) {
+                Ok(image) => image,
+                Err(err) => {
+                    log::debug!("failed to get the image of container {id}: {err}");
+                    continue;
+                }
+            };
+            if current == digest {
+                continue;
+            }
+            log::info!("image {image} of container {id} was updated to {current}, reloading");
+            digest = current;
+
+            let (modules, platform) = match client.load_modules(id, &engine) {
+                Ok(layers) => layers,
+                Err(err) => {
+                    log::error!("failed to load the wasm layers of image {image}@{digest}: {err}");
+                    continue;
+                }
+            };
+            let Some(next) = self.replace(pid, modules, platform) else {
+                return;
+            };
+            pid = next;
+            if let Some(observer) = &self.observer {
+                observer(&ReloadEvent {
+                    image,
+                    digest: digest.clone(),
+                    pid: pid as u32,
+                });
+            }
+        }
+    }
+
+    // Drains the process `pid` and starts the new one, returns its pid, or `None` if the container
+    // exits instead.
+    fn replace(&self, pid: i32, modules: Vec<WasmLayer>, platform: Platform) -> Option<i32> {
+        let id = &self.id;
+        let pid = Pid::from_raw(pid);
+        self.reloads.drain();
+        let _ = kill(pid, Signal::SIGTERM);
+        if !self.reloads.wait_exited(DRAIN_TIMEOUT) {
+            log::warn!("container {id} didn't exit after a SIGTERM, killing it");
+            let _ = kill(pid, Signal::SIGKILL);
+            if !self.reloads.wait_exited(DRAIN_TIMEOUT) {
+                log::error!("container {id} didn't exit, abandoning the reload");
+                self.reloads.replaced(None);
+                return None;
+            }
+        }
+
+        let next = (self.restart)(modules, platform)
+            .map_err(|err| log::error!("failed to restart container {id}: {err:#}"))
+            .ok();
+        self.reloads.replaced(next);
+        next
+    }
+}
+
+/// Coordinates the thread waiting for the process of a container with the watcher replacing it
+#[derive(Default)]
+pub(crate) struct Reloads {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    // the process of the container is being replaced
+    draining: bool,
+    // the process being replaced exited
+    exited: bool,
+    // the process replacing it, or `None` if it couldn't be started
+    next: Option<Option<i32>>,
+}
+
+impl Reloads {
+    /// Called once the process of the container exited, returns the process replacing it when it
+    /// was drained for a reload.
+    /// This blocks until the new process is started.
+    pub fn next_process(&self) -> Option<i32> {
+        let mut state = self.state.lock().unwrap();
+        if !state.draining {
+            return None;
+        }
+        state.exited = true;
+        self.changed.notify_all();
+
+        let mut state = self
+            .changed
+            .wait_while(state, |state| state.next.is_none())
+            .unwrap();
+        let next = state.next.take().flatten();
+        *state = State::default();
+        next
+    }
+
+    fn drain(&self) {
+        self.state.lock().unwrap().draining = true;
+    }
+
+    // Returns whether the process being drained exited before `timeout`
+    fn wait_exited(&self, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(state, timeout, |state| !state.exited)
+            .unwrap();
+        state.exited
+    }
+
+    fn replaced(&self, pid: Option<i32>) {
+        self.state.lock().unwrap().next = Some(pid);
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reloads() {
+        let reloads = Arc::new(Reloads::default());
+
+        // the process exited on its own
+        assert_eq!(reloads.next_process(), None);
+
+        reloads.drain();
+        let waiter = thread::spawn({
+            let reloads = reloads.clone();
+            move || reloads.next_process()
+        });
+        assert!(reloads.wait_exited(Duration::from_secs(10)));
+        reloads.replaced(Some(42));
+        assert_eq!(waiter.join().unwrap(), Some(42));
+
+        // the new process isn't being drained
+        assert_eq!(reloads.next_process(), None);
+        assert!(!reloads.wait_exited(Duration::ZERO));
+    }
+}
@@ -0,0 +1,279 @@
+//! Rootless operation of the shim, e.g., under rootless containerd, where the shim runs in the user
+//! namespace of containerd, or as an unprivileged user.
+//!
+//! The cgroup of a rootless container is created by libcontainer with cgroupfs when the cgroup
+//! hierarchy is delegated to the user, e.g., with the cgroup namespace of `rootlesskit --cgroupns`,
+//! or else through the systemd user instance, which delegates the cgroups of the user's services,
+//! or else in a scope the shim creates in its own cgroup when it is delegated to it, see the
+//! `scope` module. Without any, the container is left in the cgroup of the shim and its resources are not
+//! limited, with a warning. libcontainer always adds the process of a container to a cgroup, but
+//! the cgroup of the shim is then shared with the shim and the other containers, so it is never
+//! managed: only the process of the container is signalled, the container can't be paused, and
+//! the cgroup isn't removed when the container is deleted.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libcontainer::container::Container;
+use libcontainer::error::LibcontainerError;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{access, AccessFlags, Pid};
+use oci_spec::runtime::{LinuxNamespaceType, Mount, MountBuilder, Spec};
+
+use crate::sys::container::scope;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// How the cgroup of a container is managed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CgroupMode {
+    /// With cgroupfs, as root or in a cgroup hierarchy delegated to the user
+    Cgroupfs,
+    /// Through the systemd user instance
+    Systemd,
+    /// In a scope created by the shim in its own cgroup, delegated to it
+    Scope,
+    /// In the cgroup of the shim, without resource limits
+    Unmanaged,
+}
+
+/// Returns whether the shim runs rootless, as an unprivileged user or in a user namespace
+pub(crate) fn is_rootless() -> bool {
+    libcontainer::utils::rootless_required().unwrap_or(false)
+}
+
+/// Returns how the cgroup of a container is managed by a rootless shim
+pub(crate) fn cgroup_mode() -> CgroupMode {
+    if !is_rootless() || is_writable(Path::new(CGROUP_ROOT)) {
+        CgroupMode::Cgroupfs
+    } else if has_user_bus() {
+        CgroupMode::Systemd
+    } else if scope::is_delegated() {
+        CgroupMode::Scope
+    } else {
+        CgroupMode::Unmanaged
+    }
+}
+
+/// Adapts the `linux.cgroupsPath` and `linux.resources` of the spec of a container to the cgroup
+/// mode. Returns whether the spec was changed.
+pub(crate) fn adapt_spec(spec: &mut Spec, id: &str, mode: CgroupMode) -> bool {
+    let Some(mut linux) = spec.linux().clone() else {
+        return false;
+    };
+    match mode {
+        CgroupMode::Cgroupfs => return false,
+        // a cgroupfs path, e.g., `/default/<id>`, is turned into a systemd scope of the user slice
+        CgroupMode::Systemd => {
+            if linux
+                .cgroups_path()
+                .as_ref()
+                .is_some_and(|path| is_systemd_path(path))
+            {
+                return false;
+            }
+            linux.set_cgroups_path(Some(PathBuf::from(format!(":runwasi:{id}"))));
+        }
+        // the shim applies the limits to the scope, as libcontainer can't enable the controllers
+        // from the root of the hierarchy
+        CgroupMode::Scope => {
+            linux.set_cgroups_path(scope::cgroup_path(id));
+            linux.set_resources(None);
+        }
+        CgroupMode::Unmanaged => {
+            log::warn!(
+                "the cgroup hierarchy is not delegated to the rootless shim, container {id} runs \
+                 in the cgroup of the shim without resource limits"
+            );
+            linux.set_cgroups_path(shim_cgroup());
+            linux.set_resources(None);
+        }
+    }
+    spec.set_linux(Some(linux));
+    true
+}
+
+/// Returns whether the container was left in the cgroup of the shim, which isn't managed.
+pub(crate) fn in_shim_cgroup(container: &Container) -> bool {
+    let Ok(config) = container.spec() else {
+        return false;
+    };
+    shim_cgroup().is_some_and(|cgroup| is_same_cgroup(&config.cgroup_path, &cgroup))
+}
+
+/// Deletes the container, without removing its cgroup when it is the cgroup of the shim, which
+/// libcontainer would kill and remove with the shim and the other containers in it.
+pub(crate) fn delete_container(container: &mut Container) -> Result<(), LibcontainerError> {
+    if !in_shim_cgroup(container) {
+        return container.delete(true);
+    }
+    // the status of the container is refreshed when it is loaded
+    if let Some(pid) = container.pid().filter(|_| container.can_kill()) {
+        let _ = kill(Pid::from_raw(pid.as_raw()), Signal::SIGKILL);
+    }
+    match fs::remove_dir_all(&container.root) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(LibcontainerError::OtherIO(err))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the `sysfs` mounts of the spec of a rootless container by a read-only bind mount of the
+/// `/sys` of the shim, as mounting sysfs needs to own the network namespace, which isn't the case
+/// for a container sharing the network of the host. Returns whether the spec was changed.
+pub(crate) fn bind_sysfs(spec: &mut Spec) -> bool {
+    let own_netns = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.namespaces().as_ref())
+        .is_some_and(|namespaces| {
+            namespaces
+                .iter()
+                .any(|ns| ns.typ() == LinuxNamespaceType::Network && ns.path().is_none())
+        });
+    let Some(mounts) = spec.mounts().as_ref() else {
+        return false;
+    };
+    if own_netns || !mounts.iter().any(is_sysfs) {
+        return false;
+    }
+    let mounts = mounts
+        .iter()
+        .map(|mount| match is_sysfs(mount) {
+            true => MountBuilder::default()
+                .destination(mount.destination())
+                .typ("none")
+                .source("/sys")
+                .options(vec![
+                    "rbind".to_string(),
+                    "nosuid".to_string(),
+                    "noexec".to_string(),
+                    "nodev".to_string(),
+                    "ro".to_string(),
+                ])
+                .build()
+                .expect("all the fields of the mount are set"),
+            false => mount.clone(),
+        })
+        .collect();
+    spec.set_mounts(Some(mounts));
+    true
+}
+
+fn is_sysfs(mount: &Mount) -> bool {
+    mount.typ().as_deref() == Some("sysfs")
+}
+
+// systemd cgroup paths are `<slice>:<prefix>:<name>`
+fn is_systemd_path(path: &Path) -> bool {
+    !path.is_absolute() && path.to_string_lossy().split(':').count() == 3
+}
+
+fn is_writable(path: &Path) -> bool {
+    access(path, AccessFlags::W_OK).is_ok()
+}
+
+// the session bus of the systemd user instance
+fn has_user_bus() -> bool {
+    if std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some() {
+        return true;
+    }
+    std::env::var_os("XDG_RUNTIME_DIR").is_some_and(|dir| Path::new(&dir).join("bus").exists())
+}
+
+fn is_same_cgroup(a: &Path, b: &Path) -> bool {
+    a.strip_prefix("/").unwrap_or(a) == b.strip_prefix("/").unwrap_or(b)
+}
+
+// the cgroup of the shim in the unified hierarchy
+fn shim_cgroup() -> Option<PathBuf> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{
+        LinuxBuilder, LinuxNamespaceBuilder, LinuxResourcesBuilder, SpecBuilder,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_adapt_spec() -> anyhow::Result<()> {
+        let spec = |cgroups_path: &str| {
+            SpecBuilder::default()
+                .linux(
+                    LinuxBuilder::default()
+                        .cgroups_path(cgroups_path)
+                        .resources(LinuxResourcesBuilder::default().build()?)
+                        .build()?,
+                )
+                .build()
+        };
+        let linux = |spec: &Spec| spec.linux().clone().unwrap();
+
+        let mut cgroupfs = spec("/default/app")?;
+        assert!(!adapt_spec(&mut cgroupfs, "app", CgroupMode::Cgroupfs));
+
+        let mut systemd = spec("/default/app")?;
+        assert!(adapt_spec(&mut systemd, "app", CgroupMode::Systemd));
+        assert_eq!(
+            linux(&systemd).cgroups_path().as_deref(),
+            Some(Path::new(":runwasi:app"))
+        );
+        assert!(linux(&systemd).resources().is_some());
+        let mut scope = spec("user.slice:nerdctl:app")?;
+        assert!(!adapt_spec(&mut scope, "app", CgroupMode::Systemd));
+
+        let mut shim_scope = spec("/default/app")?;
+        assert!(adapt_spec(&mut shim_scope, "app", CgroupMode::Scope));
+        assert!(linux(&shim_scope).resources().is_none());
+        assert_eq!(
+            linux(&shim_scope).cgroups_path(),
+            &scope::cgroup_path("app")
+        );
+
+        let mut unmanaged = spec("/default/app")?;
+        assert!(adapt_spec(&mut unmanaged, "app", CgroupMode::Unmanaged));
+        assert!(linux(&unmanaged).resources().is_none());
+        if let Some(cgroup) = shim_cgroup() {
+            let path = linux(&unmanaged).cgroups_path().clone().unwrap();
+            assert!(is_same_cgroup(&path, &cgroup));
+            assert!(!is_same_cgroup(Path::new("/default/app"), &cgroup));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_bind_sysfs() -> anyhow::Result<()> {
+        let sysfs = MountBuilder::default()
+            .destination("/sys")
+            .typ("sysfs")
+            .source("sysfs")
+            .build()?;
+        let spec = |namespaces| {
+            SpecBuilder::default()
+                .mounts(vec![sysfs.clone()])
+                .linux(LinuxBuilder::default().namespaces(namespaces).build()?)
+                .build()
+        };
+
+        let netns = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Network)
+            .build()?;
+        let mut own_netns = spec(vec![netns])?;
+        assert!(!bind_sysfs(&mut own_netns));
+
+        let mut host_network = spec(vec![])?;
+        assert!(bind_sysfs(&mut host_network));
+        let mount = &host_network.mounts().as_ref().unwrap()[0];
+        assert_eq!(mount.source().as_deref(), Some(Path::new("/sys")));
+        assert_eq!(mount.destination(), Path::new("/sys"));
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! The cgroup v2 scope a rootless shim creates for a container in its own cgroup.
+//!
+//! When the cgroup hierarchy isn't delegated to a rootless shim and the systemd user instance
+//! isn't reachable, but the cgroup of the shim is delegated to it, e.g., by a systemd service with
+//! `Delegate=yes`, the shim creates a `runwasi-<id>.scope` cgroup for each container in its own
+//! cgroup, and applies the `memory.max`, `cpu.max` and `pids.max` of the spec to it. The
+//! controllers can only be enabled for the children of a cgroup without processes, so the
+//! processes of the cgroup of the shim are first moved to a `runwasi-shim` leaf.
+//!
+//! libcontainer adds the process of the container to the existing scope, and manages it as any
+//! other cgroup: the scope is killed, frozen and removed with the container.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use nix::unistd::{access, AccessFlags};
+use oci_spec::runtime::LinuxResources;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// the leaf the processes of the cgroup of the shim are moved to
+const SHIM_LEAF: &str = "runwasi-shim";
+
+// the controllers of the limits applied to the scope
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+// the default `cpu.cfs_period_us`
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// Returns whether the cgroup of the shim is delegated to it, so that it can create the scopes of
+/// its containers.
+pub(crate) fn is_delegated() -> bool {
+    let is_unified = Path::new(CGROUP_ROOT).join("cgroup.controllers").exists();
+    is_unified
+        && scope_parent()
+            .is_some_and(|parent| access(&cgroup_dir(&parent), AccessFlags::W_OK).is_ok())
+}
+
+/// Returns the path of the scope of container `id`, relative to the root of the cgroup hierarchy.
+pub(crate) fn cgroup_path(id: &str) -> Option<PathBuf> {
+    scope_parent().map(|parent| parent.join(format!("runwasi-{id}.scope")))
+}
+
+/// Creates the scope of container `id`, with the limits of `resources`.
+pub(crate) fn create(id: &str, resources: Option<&LinuxResources>) -> Result<()> {
+    let parent = scope_parent().context("the shim doesn't run in a cgroup v2 hierarchy")?;
+    enable_controllers(&cgroup_dir(&parent))?;
+
+    let scope = cgroup_dir(&parent.join(format!("runwasi-{id}.scope")));
+    match fs::create_dir(&scope) {
+        Err(err) if err.kind() != ErrorKind::AlreadyExists => {
+            return Err(err).with_context(|| format!("failed to create the cgroup {scope:?}"));
+        }
+        _ => {}
+    }
+    for (file, limit) in resources.map(limits).unwrap_or_default() {
+        fs::write(scope.join(file), &limit)
+            .with_context(|| format!("failed to set {file} of the cgroup {scope:?} to {limit}"))?;
+    }
+    log::info!("created the cgroup {scope:?} of container {id}");
+    Ok(())
+}
+
+/// Removes the scope of container `id`, e.g., when it failed to be created.
+pub(crate) fn remove(id: &str) {
+    let Some(path) = cgroup_path(id) else {
+        return;
+    };
+    let scope = cgroup_dir(&path);
+    match fs::remove_dir(&scope) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            log::warn!("failed to remove the cgroup {scope:?} of container {id}: {err}");
+        }
+        _ => {}
+    }
+}
+
+// the cgroup the scopes are created in, i.e., the cgroup of the shim, or the parent of its leaf
+fn scope_parent() -> Option<PathBuf> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let cgroup = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from)?;
+    match cgroup.file_name() {
+        Some(name) if name == SHIM_LEAF => cgroup.parent().map(Path::to_path_buf),
+        _ => Some(cgroup),
+    }
+}
+
+fn cgroup_dir(cgroup_path: &Path) -> PathBuf {
+    Path::new(CGROUP_ROOT).join(cgroup_path.strip_prefix("/").unwrap_or(cgroup_path))
+}
+
+// enables the controllers of the limits for the children of the cgroup `dir`
+fn enable_controllers(dir: &Path) -> Result<()> {
+    let read = |file: &str| {
+        fs::read_to_string(dir.join(file))
+            .with_context(|| format!("failed to read {file} of {dir:?}"))
+    };
+    let (available, enabled) = (read("cgroup.controllers")?, read("cgroup.subtree_control")?);
+    let missing: Vec<_> = CONTROLLERS
+        .into_iter()
+        .filter(|controller| available.split_whitespace().any(|c| c == *controller))
+        .filter(|controller| !enabled.split_whitespace().any(|c| c == *controller))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let leaf = dir.join(SHIM_LEAF);
+    fs::create_dir_all(&leaf).with_context(|| format!("failed to create the cgroup {leaf:?}"))?;
+    for pid in read("cgroup.procs")?.lines() {
+        // the process may have exited since the cgroup was read
+        if let Err(err) = fs::write(leaf.join("cgroup.procs"), pid) {
+            log::debug!("failed to move process {pid} to the cgroup {leaf:?}: {err}");
+        }
+    }
+    for controller in missing {
+        fs::write(dir.join("cgroup.subtree_control"), format!("+{controller}"))
+            .with_context(|| format!("failed to enable the {controller} controller of {dir:?}"))?;
+    }
+    Ok(())
+}
+
+// the cgroup v2 files of the limits of `resources`
+fn limits(resources: &LinuxResources) -> Vec<(&'static str, String)> {
+    // a negative or zero limit is no limit
+    let max = |limit: i64| match limit > 0 {
+        true => limit.to_string(),
+        false => "max".to_string(),
+    };
+    let mut limits = vec![];
+    if let Some(limit) = resources
+        .memory()
+        .as_ref()
+        .and_then(|memory| memory.limit())
+    {
+        limits.push(("memory.max", max(limit)));
+    }
+    if let Some(cpu) = resources.cpu() {
+        if let Some(quota) = cpu.quota() {
+            let period = cpu.period().unwrap_or(DEFAULT_CPU_PERIOD);
+            limits.push(("cpu.max", format!("{} {period}", max(quota))));
+        }
+    }
+    if let Some(pids) = resources.pids() {
+        limits.push(("pids.max", max(pids.limit())));
+    }
+    limits
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResourcesBuilder,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_limits() -> anyhow::Result<()> {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(64 << 20).build()?)
+            .cpu(LinuxCpuBuilder::default().quota(50_000).build()?)
+            .pids(LinuxPidsBuilder::default().limit(-1).build()?)
+            .build()?;
+        assert_eq!(
+            limits(&resources),
+            vec![
+                ("memory.max", "67108864".to_string()),
+                ("cpu.max", "50000 100000".to_string()),
+                ("pids.max", "max".to_string()),
+            ]
+        );
+
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().shares(512u64).build()?)
+            .build()?;
+        assert!(limits(&resources).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroup_path() {
+        let Some(parent) = scope_parent() else {
+            return;
+        };
+        let path = cgroup_path("app").unwrap();
+        assert_eq!(path, parent.join("runwasi-app.scope"));
+    }
+}
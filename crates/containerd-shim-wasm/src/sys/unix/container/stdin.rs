@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+
+/// Forwards the stdin FIFO of a task to the stdin of the container.
+///
+/// The container reads its stdin from a socket, and the shim copies the FIFO into the other end.
+/// Unlike the FIFO, or a pipe, the socket can be closed for writing even if a copy of it leaked into
+/// another process, e.g., a container created later by the same shim, so the container always gets
+/// an EOF once the FIFO is closed or the stdin of the task is closed with `CloseIO`.
+pub(crate) struct StdinForwarder {
+    writer: UnixStream,
+}
+
+impl StdinForwarder {
+    /// Starts forwarding `fifo`, returns the forwarder and the socket to use as the stdin of the container
+    pub fn new(fifo: &Path) -> io::Result<(Self, UnixStream)> {
+        let (writer, reader) = UnixStream::pair()?;
+
+        let mut dst = writer.try_clone()?;
+        let fifo = fifo.to_path_buf();
+        thread::spawn(move || {
+            // opening the FIFO for reading blocks until the client opens it for writing
+            let res = File::open(&fifo).and_then(|mut src| io::copy(&mut src, &mut dst));
+            match res {
+                Ok(_) => log::debug!("stdin {fifo:?} closed"),
+                Err(err) if err.kind() == ErrorKind::BrokenPipe => {}
+                Err(err) => log::debug!("stopped forwarding stdin {fifo:?}: {err}"),
+            }
+            let _ = dst.shutdown(Shutdown::Write);
+        });
+
+        Ok((Self { writer }, reader))
+    }
+
+    /// Closes the stdin of the container
+    pub fn close(&self) -> io::Result<()> {
+        match self.writer.shutdown(Shutdown::Write) {
+            Err(err) if err.kind() == ErrorKind::NotConnected => Ok(()),
+            res => res,
+        }
+    }
+}
+
+impl Drop for StdinForwarder {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::io::{Read, Write};
+    use std::os::unix::ffi::OsStrExt;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_forward_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("stdin");
+        std::fs::write(&path, "hello")?;
+
+        let (_forwarder, mut reader) = StdinForwarder::new(&path)?;
+        let mut stdin = String::new();
+        reader.read_to_string(&mut stdin)?;
+        assert_eq!(stdin, "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_close() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("stdin");
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let (forwarder, mut reader) = StdinForwarder::new(&path)?;
+        let mut fifo = File::options().write(true).open(&path)?;
+        fifo.write_all(b"hello")?;
+
+        let mut buf = [0u8; 5];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"hello");
+
+        // the container gets an EOF even though the FIFO is still open
+        forwarder.close()?;
+        assert_eq!(reader.read(&mut buf)?, 0);
+        Ok(())
+    }
+}
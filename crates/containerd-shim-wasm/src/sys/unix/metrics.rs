@@ -1,12 +1,236 @@
-use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
 use containerd_shim::cgroup::collect_metrics;
+use containerd_shim::protos::cgroups::metrics::{
+    CPUStat, CPUUsage, MemoryEntry, MemoryStat, Metrics, PidsStat,
+};
 use containerd_shim::util::convert_to_any;
 use protobuf::well_known_types::any::Any;
 
+use crate::container::{PoolMetrics, POOL_METRICS_FIELD};
+
+/// Field of the cgroup `Metrics` message of the task stats carrying the path of the cgroup of the
+/// container in the unified hierarchy, as an extension that the consumers of the standard fields
+/// ignore.
+pub const CGROUP_PATH_FIELD: u32 = 1001;
+
 #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
-pub fn get_metrics(pid: u32) -> Result<Any> {
-    let metrics = collect_metrics(pid)?;
+pub fn get_metrics(pid: u32, pool: Option<PoolMetrics>) -> Result<Any> {
+    let mut metrics = collect_metrics(pid).or_else(|err| {
+        // e.g., when the container doesn't have its own cgroup, or cgroups are not available
+        log::debug!("failed to collect cgroup metrics for {pid}, falling back to procfs: {err}");
+        procfs_metrics(pid)
+    })?;
+    if let Some(pool) = pool {
+        add_pool_metrics(&mut metrics, &pool)?;
+    }
+    if let Some(cgroup) = cgroup_path(pid) {
+        add_cgroup_path(&mut metrics, &cgroup);
+    }
 
     let metrics = convert_to_any(Box::new(metrics))?;
     Ok(metrics)
 }
+
+// Adds the metrics of the instance pool of the container as an extension field of the message.
+fn add_pool_metrics(metrics: &mut Metrics, pool: &PoolMetrics) -> Result<()> {
+    metrics
+        .special_fields
+        .mut_unknown_fields()
+        .add_length_delimited(POOL_METRICS_FIELD, pool.encode()?);
+    Ok(())
+}
+
+// Adds the path of the cgroup of the container as an extension field of the message.
+fn add_cgroup_path(metrics: &mut Metrics, cgroup: &Path) {
+    metrics
+        .special_fields
+        .mut_unknown_fields()
+        .add_length_delimited(
+            CGROUP_PATH_FIELD,
+            cgroup.to_string_lossy().into_owned().into_bytes(),
+        );
+}
+
+/// Returns the cgroup of the process `pid` in the unified hierarchy, i.e., on cgroup v2.
+pub(crate) fn cgroup_path(pid: u32) -> Option<PathBuf> {
+    let cgroups = fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+    cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(PathBuf::from)
+}
+
+// Builds the cpu, memory and pids metrics of a single process from procfs.
+// The cpu time of the process includes the one of all its threads, e.g., the threads spawned by
+// a guest with wasi-threads, and the pids are the number of its threads.
+fn procfs_metrics(pid: u32) -> Result<Metrics> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))
+        .with_context(|| format!("failed to read stat of process {pid}"))?;
+    // the process name can contain spaces, the fields start after its closing parenthesis
+    let fields: Vec<_> = stat
+        .rsplit_once(')')
+        .map(|(_, fields)| fields.split_whitespace().collect())
+        .unwrap_or_default();
+    // utime and stime are the 14th and 15th fields, i.e., the 12th and 13th after the name
+    let parse_ticks = |idx: usize| -> Result<u64> {
+        fields
+            .get(idx)
+            .and_then(|f| f.parse().ok())
+            .with_context(|| format!("invalid stat of process {pid}"))
+    };
+    let (utime, stime) = (parse_ticks(11)?, parse_ticks(12)?);
+
+    let ticks_per_second = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    };
+    let to_nanos = |ticks: u64| ticks * 1_000_000_000 / ticks_per_second;
+
+    let (user, kernel) = (to_nanos(utime), to_nanos(stime));
+    let mut cpu_usage = CPUUsage::new();
+    cpu_usage.set_user(user);
+    cpu_usage.set_kernel(kernel);
+    cpu_usage.set_total(user + kernel);
+    let mut cpu = CPUStat::new();
+    cpu.set_usage(cpu_usage);
+
+    let status = fs::read_to_string(format!("/proc/{pid}/status"))
+        .with_context(|| format!("failed to read status of process {pid}"))?;
+    let rss_kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rss| rss.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .unwrap_or_default();
+    let mut usage = MemoryEntry::new();
+    usage.set_usage(rss_kb * 1024);
+    let mut memory = MemoryStat::new();
+    memory.set_rss(rss_kb * 1024);
+    memory.set_usage(usage);
+
+    let threads = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))
+        .and_then(|threads| threads.trim().parse::<u64>().ok())
+        .unwrap_or(1);
+    let mut pids = PidsStat::new();
+    pids.set_current(threads);
+
+    let mut metrics = Metrics::new();
+    metrics.set_cpu(cpu);
+    metrics.set_memory(memory);
+    metrics.set_pids(pids);
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_procfs_metrics() -> Result<()> {
+        // burn some cpu so the usage is not zero
+        let mut x = 0u64;
+        for i in 0..50_000_000u64 {
+            x = x.wrapping_add(i * i);
+        }
+        std::hint::black_box(x);
+
+        let metrics = procfs_metrics(std::process::id())?;
+        assert!(metrics.memory.usage.usage > 0);
+        assert_eq!(metrics.memory.rss, metrics.memory.usage.usage);
+        assert!(metrics.cpu.usage.total > 0);
+        assert_eq!(
+            metrics.cpu.usage.total,
+            metrics.cpu.usage.user + metrics.cpu.usage.kernel
+        );
+
+        assert!(procfs_metrics(u32::MAX).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_procfs_metrics_threads() -> Result<()> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Barrier;
+
+        let before = procfs_metrics(std::process::id())?.pids.current;
+        assert!(before >= 1);
+
+        let (started, stop) = (Barrier::new(5), AtomicBool::new(false));
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    started.wait();
+                    while !stop.load(Ordering::Relaxed) {
+                        std::hint::spin_loop();
+                    }
+                });
+            }
+            started.wait();
+            let metrics = procfs_metrics(std::process::id());
+            stop.store(true, Ordering::Relaxed);
+            assert!(metrics?.pids.current >= before + 4);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_pool_metrics() -> Result<()> {
+        use protobuf::{Message, UnknownValueRef};
+
+        let pool = PoolMetrics {
+            size: 4,
+            ready: 3,
+            busy: 1,
+            requests: 10,
+            ..Default::default()
+        };
+        let mut metrics = procfs_metrics(std::process::id())?;
+        add_pool_metrics(&mut metrics, &pool)?;
+
+        // the extension survives the encoding of the message, as an unknown field
+        let metrics = Metrics::parse_from_bytes(&metrics.write_to_bytes()?)?;
+        assert!(metrics.cpu.usage.total > 0);
+        match metrics
+            .special_fields
+            .unknown_fields()
+            .get(POOL_METRICS_FIELD)
+        {
+            Some(UnknownValueRef::LengthDelimited(bytes)) => {
+                assert_eq!(PoolMetrics::decode(bytes)?, pool)
+            }
+            field => panic!("unexpected pool metrics {field:?}"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroup_path() -> Result<()> {
+        use protobuf::{Message, UnknownValueRef};
+
+        let Some(cgroup) = cgroup_path(std::process::id()) else {
+            // not on cgroup v2
+            return Ok(());
+        };
+        assert!(cgroup.is_absolute());
+        assert!(cgroup_path(u32::MAX).is_none());
+
+        let mut metrics = procfs_metrics(std::process::id())?;
+        add_cgroup_path(&mut metrics, &cgroup);
+        let metrics = Metrics::parse_from_bytes(&metrics.write_to_bytes()?)?;
+        match metrics
+            .special_fields
+            .unknown_fields()
+            .get(CGROUP_PATH_FIELD)
+        {
+            Some(UnknownValueRef::LengthDelimited(bytes)) => {
+                assert_eq!(PathBuf::from(std::str::from_utf8(bytes)?), cgroup)
+            }
+            field => panic!("unexpected cgroup path {field:?}"),
+        }
+        Ok(())
+    }
+}
@@ -1,39 +1,40 @@
 use std::fs::File;
+use std::net::IpAddr;
 
 use anyhow::Result;
 use containerd_shim::error::Error as ShimError;
 use containerd_shim::{self as shim};
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::InterfaceFlags;
 use nix::sched::{setns, unshare, CloneFlags};
 use oci_spec::runtime;
 
+use crate::sandbox::oci;
+
 #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
 pub fn setup_namespaces(spec: &runtime::Spec) -> Result<()> {
-    let namespaces = spec
-        .linux()
-        .as_ref()
-        .unwrap()
-        .namespaces()
-        .as_ref()
-        .unwrap();
-    for ns in namespaces {
-        if ns.typ() == runtime::LinuxNamespaceType::Network {
-            if let Some(p) = ns.path() {
-                let f = File::open(p).map_err(|err| {
-                    ShimError::Other(format!(
-                        "could not open network namespace {}: {}",
-                        p.display(),
-                        err
-                    ))
-                })?;
-                setns(f, CloneFlags::CLONE_NEWNET).map_err(|err| {
-                    ShimError::Other(format!("could not set network namespace: {0}", err))
-                })?;
-            } else {
-                unshare(CloneFlags::CLONE_NEWNET).map_err(|err| {
-                    ShimError::Other(format!("could not unshare network namespace: {0}", err))
-                })?;
-            }
+    // The shim, and the engines of the containers of the pod, join the network namespace CNI set
+    // up for the pod, so that their traffic originates from the pod IP and obeys its NetworkPolicy.
+    match oci::network_namespace(spec) {
+        Some(Some(p)) => {
+            log::info!("joining network namespace {}", p.display());
+            let f = File::open(p).map_err(|err| {
+                ShimError::Other(format!(
+                    "could not open network namespace {}: {}",
+                    p.display(),
+                    err
+                ))
+            })?;
+            setns(f, CloneFlags::CLONE_NEWNET).map_err(|err| {
+                ShimError::Other(format!("could not set network namespace: {0}", err))
+            })?;
+        }
+        Some(None) => {
+            unshare(CloneFlags::CLONE_NEWNET).map_err(|err| {
+                ShimError::Other(format!("could not unshare network namespace: {0}", err))
+            })?;
         }
+        None => log::debug!("sharing the network namespace of the host"),
     }
 
     // Keep all mounts changes (such as for the rootfs) private to the shim
@@ -42,3 +43,38 @@ pub fn setup_namespaces(spec: &runtime::Spec) -> Result<()> {
         .map_err(|err| shim::Error::Other(format!("failed to unshare mount namespace: {}", err)))?;
     Ok(())
 }
+
+/// The fields of the pod of the `io.containerd.wasm.env-from-field.<NAME>` annotations the shim
+/// gets from the host: `spec.nodeName`, the hostname of the host, and `status.podIP`.
+pub fn host_field(field: &str) -> Option<String> {
+    match field {
+        "spec.nodeName" => nix::unistd::gethostname().ok()?.into_string().ok(),
+        "status.podIP" => pod_ip().map(|ip| ip.to_string()),
+        _ => None,
+    }
+}
+
+// The shim is in the network namespace of the pod, see `setup_namespaces`, so the pod IP is the
+// address of its interface, an IPv4 one for dual-stack pods like Kubernetes does
+fn pod_ip() -> Option<IpAddr> {
+    let addresses = getifaddrs()
+        .map_err(|err| log::warn!("failed to list the addresses of the pod: {err}"))
+        .ok()?;
+    let mut ips: Vec<IpAddr> = addresses
+        .filter(|addr| {
+            addr.flags.contains(InterfaceFlags::IFF_UP)
+                && !addr.flags.contains(InterfaceFlags::IFF_LOOPBACK)
+        })
+        .filter_map(|addr| {
+            let addr = addr.address?;
+            if let Some(addr) = addr.as_sockaddr_in() {
+                return Some(IpAddr::V4(addr.ip()));
+            }
+            let ip = addr.as_sockaddr_in6()?.ip();
+            // link-local addresses aren't reachable from other pods
+            (ip.segments()[0] & 0xffc0 != 0xfe80).then_some(IpAddr::V6(ip))
+        })
+        .collect();
+    ips.sort_by_key(IpAddr::is_ipv6);
+    ips.into_iter().next()
+}
@@ -1,13 +1,17 @@
 use std::fs::OpenOptions;
-use std::io::Result;
+use std::io::{self, ErrorKind, Result, Write};
 use std::os::fd::{IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
 use std::path::Path;
+use std::thread;
 
 use crossbeam::atomic::AtomicCell;
 pub use libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
 
 pub type StdioRawFd = RawFd;
 
+pub const NULL_DEVICE: &str = "/dev/null";
+
 pub struct StdioOwnedFd(AtomicCell<StdioRawFd>);
 
 impl Drop for StdioOwnedFd {
@@ -49,3 +53,14 @@ impl StdioOwnedFd {
         Self::try_from(OpenOptions::new().read(true).write(true).open(path)?)
     }
 }
+
+/// Returns the end of a socket whose output is copied to `dst`, until all its copies are closed.
+pub fn forward_to(mut dst: impl Write + Send + 'static) -> Result<StdioOwnedFd> {
+    let (writer, mut reader) = UnixStream::pair()?;
+    thread::spawn(move || match io::copy(&mut reader, &mut dst) {
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => {}
+        Err(err) => log::warn!("stopped forwarding the output of the container: {err}"),
+    });
+    StdioOwnedFd::try_from(writer)
+}
@@ -1,42 +1,407 @@
+use std::fs::{File, OpenOptions};
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio as ProcessStdio};
+use std::thread;
 use std::time::Duration;
 
+use anyhow::Context;
 use chrono::{DateTime, Utc};
+use oci_spec::image::Platform;
+use oci_spec::runtime::Spec;
 
-use crate::container::Engine;
-use crate::sandbox::{Error as SandboxError, Instance as SandboxInstance, InstanceConfig};
+use super::job::JobObject;
+use crate::container::{Engine, ErrorCode, Stdio, WasiContext};
+use crate::sandbox::instance_utils::Options;
+use crate::sandbox::sync::WaitableCell;
+use crate::sandbox::{
+    containerd, oci, Error as SandboxError, Instance as SandboxInstance, InstanceConfig,
+};
+use crate::sys::networking;
+use crate::sys::signals::{SIGINT, SIGKILL, SIGTERM};
 
-pub struct Instance<E: Engine>(PhantomData<E>);
+/// Environment variable with the bundle of the container a shim process is hosting
+const CONTAINER_PROCESS_ENV: &str = "RUNWASI_CONTAINER_PROCESS";
+/// Environment variables with the container, and the containerd it loads its wasm layers from
+const CONTAINER_ID_ENV: &str = "RUNWASI_CONTAINER_ID";
+const CONTAINERD_NAMESPACE_ENV: &str = "RUNWASI_CONTAINERD_NAMESPACE";
+const CONTAINERD_ADDRESS_ENV: &str = "RUNWASI_CONTAINERD_ADDRESS";
+
+/// Environment variables kept in the container process, needed by the Windows runtime
+const SYSTEM_ENV: &[&str] = &["SystemRoot", "windir"];
+
+/// A container running in a child process of the shim.
+///
+/// Windows has no `fork`, so the shim spawns its own binary with [`CONTAINER_PROCESS_ENV`]
+/// set to the bundle of the container, and the child runs the container from `shim_main`.
+/// The child can't inherit the wasm layers from the shim, so it loads them from containerd.
+/// The child is assigned to a job object that enforces the resources of the container
+/// and terminates the child when the shim exits.
+pub struct Instance<E: Engine> {
+    id: String,
+    namespace: String,
+    containerd_address: String,
+    bundle: PathBuf,
+    stdin: PathBuf,
+    stdout: PathBuf,
+    stderr: PathBuf,
+    job: JobObject,
+    exit_code: WaitableCell<(u32, DateTime<Utc>)>,
+    _engine: PhantomData<E>,
+}
+
+// Opens one of the named pipes created by containerd for the stdio of the container.
+// The pipe is opened for synchronous IO so the child process can use it as its stdio.
+fn open_stdio(path: &Path) -> anyhow::Result<ProcessStdio> {
+    if path.as_os_str().is_empty() {
+        return Ok(ProcessStdio::null());
+    }
+    let file: File = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open {path:?}"))?;
+    Ok(file.into())
+}
+
+// Reads a file of the container, through the mount with the longest destination containing it, or
+// else in the root of the container. The file can't be outside of the mount or the root, even
+// through a symlink.
+fn read_in_container(spec: &Spec, bundle: &Path, path: &Path) -> std::io::Result<String> {
+    let mount = spec
+        .mounts()
+        .iter()
+        .flatten()
+        .filter_map(|mount| {
+            let rest = path.strip_prefix(mount.destination()).ok()?;
+            Some((
+                mount.destination().components().count(),
+                mount.source().clone()?,
+                rest,
+            ))
+        })
+        .max_by_key(|(depth, _, _)| *depth);
+    let (base, rest) = match mount {
+        Some((_, source, rest)) => (source, rest),
+        None => {
+            let root = spec
+                .root()
+                .as_ref()
+                .map(|root| root.path().clone())
+                .unwrap_or_else(|| PathBuf::from("rootfs"));
+            (bundle.join(root), path.strip_prefix("/").unwrap_or(path))
+        }
+    };
+
+    let base = base.canonicalize()?;
+    let file = base.join(rest).canonicalize()?;
+    if !file.starts_with(&base) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{path:?} is outside of the container"),
+        ));
+    }
+    std::fs::read_to_string(file)
+}
+
+fn load_spec(bundle: &Path) -> anyhow::Result<Spec> {
+    let path = bundle.join("config.json");
+    Spec::load(&path).with_context(|| format!("failed to load spec from {path:?}"))
+}
 
 impl<E: Engine> SandboxInstance for Instance<E> {
     type Engine = E;
 
-    fn new(_id: String, _cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self, SandboxError> {
-        todo!();
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn new(id: String, cfg: Option<&InstanceConfig<Self::Engine>>) -> Result<Self, SandboxError> {
+        let cfg = cfg.context("missing configuration")?;
+        if cfg.get_terminal() {
+            return Err(SandboxError::InvalidArgument(
+                "terminal is not supported".to_string(),
+            ));
+        }
+        let bundle = cfg.get_bundle().to_path_buf();
+        let mut spec = load_spec(&bundle)?;
+        let options = Options::read(&bundle)?;
+        let stripped_env = oci::strip_env(&mut spec, &options.env_strip)?;
+        let resolved_fields = oci::resolve_env_from_fields(&mut spec, networking::host_field)?;
+        // the files of the environment are read when the container starts, and only kept in memory
+        oci::check_env_from_files(&spec)?;
+        let mounted_log = oci::mount_record_log(&mut spec, &bundle)?;
+        let mounted_coredump = oci::mount_coredump(&mut spec, &bundle)?;
+        if stripped_env || resolved_fields || mounted_log || mounted_coredump {
+            spec.save(bundle.join("config.json"))?;
+        }
+
+        let resources = spec
+            .windows()
+            .as_ref()
+            .and_then(|windows| windows.resources());
+        let job = JobObject::new(resources).context("failed to create job object")?;
+
+        Ok(Self {
+            id,
+            namespace: cfg.get_namespace(),
+            containerd_address: cfg.get_containerd_address(),
+            bundle,
+            stdin: cfg.get_stdin().to_path_buf(),
+            stdout: cfg.get_stdout().to_path_buf(),
+            stderr: cfg.get_stderr().to_path_buf(),
+            job,
+            exit_code: WaitableCell::new(),
+            _engine: PhantomData,
+        })
     }
 
     /// Start the instance
     /// The returned value should be a unique ID (such as a PID) for the instance.
     /// Nothing internally should be using this ID, but it is returned to containerd where a user may want to use it.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn start(&self) -> Result<u32, SandboxError> {
-        todo!();
+        log::info!("starting instance: {}", self.id);
+        // make sure we have an exit code by the time we finish (even if there's a panic)
+        let guard = self.exit_code.set_guard_with(|| (137, Utc::now()));
+
+        let mut spec = load_spec(&self.bundle)?;
+        let mounts = spec.clone();
+        oci::resolve_env_from_files(&mut spec, |path| {
+            read_in_container(&mounts, &self.bundle, path)
+        })?;
+        let env = spec
+            .process()
+            .as_ref()
+            .and_then(|p| p.env().as_ref())
+            .into_iter()
+            .flatten()
+            .filter_map(|var| var.split_once('='));
+        let system_env = SYSTEM_ENV
+            .iter()
+            .filter_map(|var| Some((*var, std::env::var_os(var)?)));
+
+        let mut child = Command::new(std::env::current_exe().context("failed to find the shim")?)
+            .env_clear()
+            .envs(system_env)
+            .envs(env)
+            .env(CONTAINER_PROCESS_ENV, &self.bundle)
+            .env(CONTAINER_ID_ENV, &self.id)
+            .env(CONTAINERD_NAMESPACE_ENV, &self.namespace)
+            .env(CONTAINERD_ADDRESS_ENV, &self.containerd_address)
+            .stdin(open_stdio(&self.stdin)?)
+            .stdout(open_stdio(&self.stdout)?)
+            .stderr(open_stdio(&self.stderr)?)
+            .spawn()
+            .context("failed to spawn container process")?;
+
+        if let Err(err) = self.job.assign(&child) {
+            let _ = child.kill();
+            return Err(anyhow::Error::from(err)
+                .context("failed to assign container process to job")
+                .into());
+        }
+
+        let pid = child.id();
+        let exit_code = self.exit_code.clone();
+        thread::spawn(move || {
+            // move the exit code guard into this thread
+            let _guard = guard;
+
+            // waits on the process handle, which is signaled when the process exits
+            let status = match child.wait() {
+                Ok(status) => status.code().unwrap_or(137),
+                Err(e) => {
+                    log::error!("wait failed: {e}");
+                    137
+                }
+            } as u32;
+            let _ = exit_code.set((status, Utc::now()));
+        });
+
+        Ok(pid)
     }
 
     /// Send a signal to the instance
-    fn kill(&self, _signal: u32) -> Result<(), SandboxError> {
-        todo!();
+    /// Windows has no signals, SIGKILL, SIGTERM and SIGINT terminate the processes of the container.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
+    fn kill(&self, signal: u32) -> Result<(), SandboxError> {
+        log::info!("sending signal {signal} to instance: {}", self.id);
+        match signal as i32 {
+            SIGKILL | SIGTERM | SIGINT => {}
+            _ => {
+                return Err(SandboxError::InvalidArgument(format!(
+                    "unsupported signal number: {signal}"
+                )))
+            }
+        }
+        self.job
+            .terminate(signal_exit_code(signal))
+            .with_context(|| format!("failed to terminate instance {}", self.id))?;
+        Ok(())
     }
 
     /// Delete any reference to the instance
     /// This is called after the instance has exited.
+    /// The job, and any process left in it, is closed when the instance is dropped.
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip_all, level = "Info"))]
     fn delete(&self) -> Result<(), SandboxError> {
-        todo!();
+        log::info!("deleting instance: {}", self.id);
+        Ok(())
+    }
+
+    /// Runs the container in the current process if it was spawned by [`Instance::start`].
+    fn run_container_process(engine: impl FnOnce() -> Self::Engine) -> Option<i32> {
+        let bundle = PathBuf::from(std::env::var_os(CONTAINER_PROCESS_ENV)?);
+        let [id, namespace, address] = [
+            CONTAINER_ID_ENV,
+            CONTAINERD_NAMESPACE_ENV,
+            CONTAINERD_ADDRESS_ENV,
+        ]
+        .map(|var| std::env::var(var).unwrap_or_default());
+        for var in [
+            CONTAINER_PROCESS_ENV,
+            CONTAINER_ID_ENV,
+            CONTAINERD_NAMESPACE_ENV,
+            CONTAINERD_ADDRESS_ENV,
+        ] {
+            std::env::remove_var(var);
+        }
+
+        let engine = engine();
+        let (modules, platform) = load_modules(&engine, &id, &namespace, &address)
+            .unwrap_or_else(|e| {
+                log::warn!("Error obtaining wasm layers for container {id}.  Will attempt to use files inside container image. Error: {e}");
+                (vec![], Platform::default())
+            });
+        let code = run_container(engine, &bundle, &modules, &platform).unwrap_or_else(|err| {
+            log::error!("error running container: {err:?}");
+            ErrorCode::of(&err).map_or(137, |code| code.exit_status())
+        });
+        Some(code)
     }
 
     /// Waits for the instance to finish and retunrs its exit code
     /// Returns None if the timeout is reached before the instance has finished.
     /// This is a blocking call.
-    fn wait_timeout(&self, _t: impl Into<Option<Duration>>) -> Option<(u32, DateTime<Utc>)> {
-        todo!();
+    #[cfg_attr(feature = "tracing", tracing::instrument(parent = tracing::Span::current(), skip(self, t), level = "Info"))]
+    fn wait_timeout(&self, t: impl Into<Option<Duration>>) -> Option<(u32, DateTime<Utc>)> {
+        self.exit_code.wait_timeout(t).copied()
+    }
+}
+
+// The exit code of a container terminated by `signal`, as for a process killed by a signal on unix.
+fn signal_exit_code(signal: u32) -> u32 {
+    128 + signal
+}
+
+// Loads the wasm layers of the container `id` from containerd.
+fn load_modules(
+    engine: &impl Engine,
+    id: &str,
+    namespace: &str,
+    address: &str,
+) -> Result<(Vec<oci::WasmLayer>, Platform), SandboxError> {
+    containerd::Client::connect(address, namespace)?.load_modules(id, engine)
+}
+
+// Runs the wasm container of `bundle` with the stdio of the current process.
+fn run_container(
+    engine: impl Engine,
+    bundle: &Path,
+    wasm_layers: &[oci::WasmLayer],
+    platform: &Platform,
+) -> anyhow::Result<i32> {
+    let mut spec = load_spec(bundle)?;
+    let root = spec
+        .root()
+        .as_ref()
+        .map(|root| bundle.join(root.path()))
+        .unwrap_or_else(|| bundle.join("rootfs"));
+    std::env::set_current_dir(&root).with_context(|| format!("failed to enter {root:?}"))?;
+
+    // the entrypoint is relative to the root of the container
+    if let Some(mut process) = spec.process().clone() {
+        if let Some(mut args) = process.args().clone() {
+            if let Some(arg0) = args.first_mut() {
+                *arg0 = arg0.trim_start_matches(['/', '\\']).to_string();
+            }
+            process.set_args(Some(args));
+        }
+        spec.set_process(Some(process));
+    }
+
+    let ctx = WasiContext {
+        spec: &spec,
+        wasm_layers,
+        platform,
+        pool_stats: None,
+        threads: None,
+        keyvalue: None,
+    };
+    engine.can_handle(&ctx)?;
+    engine.run(&ctx, Stdio::init_from_std())
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::image::{Descriptor, MediaType};
+    use oci_spec::runtime::{ProcessBuilder, RootBuilder, SpecBuilder};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::container::{RuntimeContext, Source};
+
+    // An engine exiting with the number of wasm layers of its container
+    #[derive(Clone, Default)]
+    struct LayersEngine;
+
+    impl Engine for LayersEngine {
+        fn name() -> &'static str {
+            "layers"
+        }
+        fn can_handle(&self, _ctx: &impl RuntimeContext) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn run_wasi(&self, ctx: &impl RuntimeContext, _stdio: Stdio) -> anyhow::Result<i32> {
+            match ctx.entrypoint().source {
+                Source::Oci(layers) => Ok(layers.len() as i32),
+                Source::File(_) => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_container_with_layers() -> anyhow::Result<()> {
+        let bundle = tempdir()?;
+        std::fs::create_dir(bundle.path().join("rootfs"))?;
+        SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
+            .build()?
+            .save(bundle.path().join("config.json"))?;
+        let layer = || oci::WasmLayer {
+            config: Descriptor::new(
+                MediaType::Other("application/vnd.w3c.wasm.module.v1+wasm".to_string()),
+                0,
+                "",
+            ),
+            layer: vec![].into(),
+        };
+
+        let cwd = std::env::current_dir()?;
+        let code = run_container(
+            LayersEngine,
+            bundle.path(),
+            &[layer(), layer()],
+            &Platform::default(),
+        );
+        std::env::set_current_dir(cwd)?;
+        assert_eq!(code?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signal_exit_code() {
+        assert_eq!(signal_exit_code(SIGKILL as u32), 137);
+        assert_eq!(signal_exit_code(SIGTERM as u32), 143);
+        assert_eq!(signal_exit_code(SIGINT as u32), 130);
     }
 }
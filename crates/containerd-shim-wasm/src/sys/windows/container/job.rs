@@ -0,0 +1,102 @@
+use std::io::{Error, Result};
+use std::mem::size_of;
+use std::os::windows::io::AsRawHandle;
+use std::process::Child;
+use std::ptr::null;
+
+use oci_spec::runtime::WindowsResources;
+use serde_json::Value;
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectCpuRateControlInformation,
+    JobObjectExtendedLimitInformation, SetInformationJobObject, TerminateJobObject,
+    JOBOBJECT_CPU_RATE_CONTROL_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_CPU_RATE_CONTROL_ENABLE, JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+    JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+
+/// A job object holding the process of a container.
+///
+/// The job applies the memory and cpu limits of the container to the process,
+/// and terminates it when the job is closed, e.g., if the shim exits.
+pub(crate) struct JobObject(HANDLE);
+
+fn check(res: i32) -> Result<()> {
+    match res {
+        0 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+impl JobObject {
+    pub fn new(resources: Option<WindowsResources>) -> Result<Self> {
+        // the windows resources of oci-spec have no getters, read them from their serialized form
+        let resources = resources
+            .and_then(|r| serde_json::to_value(r).ok())
+            .unwrap_or_default();
+
+        let handle = unsafe { CreateJobObjectW(null(), null()) };
+        if handle == 0 {
+            return Err(Error::last_os_error());
+        }
+        let job = Self(handle);
+
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let memory_limit = resources
+            .pointer("/memory/limit")
+            .and_then(Value::as_u64)
+            .filter(|limit| *limit > 0);
+        if let Some(limit) = memory_limit {
+            limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+            limits.JobMemoryLimit = limit as usize;
+        }
+        check(unsafe {
+            SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const _,
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        })?;
+
+        // the cpu maximum is the portion of the processor cycles the container can use,
+        // in units of 1/10000, which is also the unit of the job cpu rate
+        let cpu_maximum = resources
+            .pointer("/cpu/maximum")
+            .and_then(Value::as_u64)
+            .filter(|max| (1..10000).contains(max));
+        if let Some(maximum) = cpu_maximum {
+            let mut rate: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = unsafe { std::mem::zeroed() };
+            rate.ControlFlags =
+                JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+            rate.Anonymous.CpuRate = maximum as u32;
+            check(unsafe {
+                SetInformationJobObject(
+                    job.0,
+                    JobObjectCpuRateControlInformation,
+                    &rate as *const _ as *const _,
+                    size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as u32,
+                )
+            })?;
+        }
+
+        Ok(job)
+    }
+
+    /// Add the process to the job
+    pub fn assign(&self, child: &Child) -> Result<()> {
+        check(unsafe { AssignProcessToJobObject(self.0, child.as_raw_handle() as HANDLE) })
+    }
+
+    /// Terminate all the processes in the job with `exit_code`
+    pub fn terminate(&self, exit_code: u32) -> Result<()> {
+        check(unsafe { TerminateJobObject(self.0, exit_code) })
+    }
+}
+
+impl Drop for JobObject {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
@@ -1 +1,2 @@
 pub mod instance;
+mod job;
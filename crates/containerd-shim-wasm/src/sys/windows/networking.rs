@@ -2,3 +2,12 @@ pub fn setup_namespaces(spec: &oci_spec::runtime::Spec) -> anyhow::Result<()> {
     // noop for now
     Ok(())
 }
+
+/// The fields of the pod of the `io.containerd.wasm.env-from-field.<NAME>` annotations the shim
+/// gets from the host: `spec.nodeName`, the name of the computer.
+pub fn host_field(field: &str) -> Option<String> {
+    match field {
+        "spec.nodeName" => std::env::var("COMPUTERNAME").ok(),
+        _ => None,
+    }
+}
@@ -1,6 +1,6 @@
 use std::fs::OpenOptions;
 use std::io::ErrorKind::Other;
-use std::io::{Error, Result};
+use std::io::{Error, Result, Write};
 use std::os::windows::fs::OpenOptionsExt;
 use std::os::windows::prelude::{AsRawHandle, IntoRawHandle, OwnedHandle};
 use std::path::Path;
@@ -15,6 +15,8 @@ pub const STDIN_FILENO: StdioRawFd = 0;
 pub const STDOUT_FILENO: StdioRawFd = 1;
 pub const STDERR_FILENO: StdioRawFd = 2;
 
+pub const NULL_DEVICE: &str = "NUL";
+
 pub struct StdioOwnedFd(AtomicCell<StdioRawFd>);
 
 impl Drop for StdioOwnedFd {
@@ -67,3 +69,10 @@ impl StdioOwnedFd {
         Self::try_from(options.open(path)?)
     }
 }
+
+pub fn forward_to(_dst: impl Write + Send + 'static) -> Result<StdioOwnedFd> {
+    Err(Error::new(
+        Other,
+        "forwarding the output of the container is only supported on Linux",
+    ))
+}
@@ -1,9 +1,33 @@
 //! Testing utilities used across different modules
+//!
+//! With the `testing` feature, the engines implemented outside of this crate can use them for
+//! the integration tests of their [`Engine`](crate::container::Engine): a [`WasiTest`] creates
+//! the bundle of a container in a temporary directory, with its OCI spec, its rootfs and its
+//! stdio, and runs it in the process of the test, without containerd.
+//!
+//! ```ignore
+//! use containerd_shim_wasm::testing::{modules, EngineTest};
+//!
+//! #[test]
+//! fn test_hello_world() -> anyhow::Result<()> {
+//!     let (exit_code, stdout, _) = EngineTest::<MyEngine>::builder()?
+//!         .with_wasm(modules::HELLO_WORLD)?
+//!         .with_env("GREETING", "hello")?
+//!         .with_fifo_stdio()?
+//!         .build()?
+//!         .start()?
+//!         .wait(std::time::Duration::from_secs(10))?;
+//!     assert_eq!(exit_code, 0);
+//!     assert_eq!(stdout, "hello world\n");
+//!     Ok(())
+//! }
+//! ```
 
 use std::collections::HashMap;
 use std::fs::{self, create_dir, read, read_to_string, write, File};
 use std::marker::PhantomData;
 use std::ops::Add;
+use std::path::Path;
 use std::time::Duration;
 
 use anyhow::{bail, Result};
@@ -15,12 +39,21 @@ use crate::sys::signals::SIGKILL;
 
 pub const TEST_NAMESPACE: &str = "runwasi-test";
 
+/// A [`WasiTest`] of the container instance of an [`Engine`](crate::container::Engine)
+pub type EngineTest<E> = WasiTest<crate::container::Instance<E>>;
+
 pub struct WasiTestBuilder<WasiInstance: Instance>
 where
     WasiInstance::Engine: Default + Send + Sync + Clone,
 {
     container_name: String,
     tempdir: tempfile::TempDir,
+    start_fn: String,
+    args: Vec<String>,
+    env: Vec<String>,
+    annotations: HashMap<String, String>,
+    #[cfg(unix)]
+    fifo_stdio: bool,
     _phantom: PhantomData<WasiInstance>,
 }
 
@@ -30,6 +63,8 @@ where
 {
     instance: WasiInstance,
     tempdir: tempfile::TempDir,
+    #[cfg(unix)]
+    fifos: Option<(fifo::FifoReader, fifo::FifoReader)>,
 }
 
 impl<WasiInstance: Instance> WasiTestBuilder<WasiInstance>
@@ -60,6 +95,12 @@ where
         let builder = Self {
             container_name: "test".to_string(),
             tempdir,
+            start_fn: String::new(),
+            args: vec![],
+            env: vec![],
+            annotations: HashMap::new(),
+            #[cfg(unix)]
+            fifo_stdio: false,
             _phantom: Default::default(),
         }
         .with_wasm([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00])?
@@ -69,27 +110,62 @@ where
         Ok(builder)
     }
 
-    pub fn with_start_fn(self, start_fn: impl AsRef<str>) -> Result<Self> {
-        let dir = self.tempdir.path();
+    pub fn with_start_fn(mut self, start_fn: impl AsRef<str>) -> Result<Self> {
         let start_fn = start_fn.as_ref();
 
         log::info!("setting wasi test start_fn to {start_fn:?}");
 
-        let entrypoint = match start_fn {
-            "" => "/hello.wasm".to_string(),
-            s => "/hello.wasm#".to_string().add(s),
-        };
-        let spec = SpecBuilder::default()
-            .root(RootBuilder::default().path("rootfs").build()?)
-            .process(
-                ProcessBuilder::default()
-                    .cwd("/")
-                    .args([entrypoint])
-                    .build()?,
-            )
-            .build()?;
+        self.start_fn = start_fn.to_string();
 
-        spec.save(dir.join("config.json"))?;
+        Ok(self)
+    }
+
+    /// Sets the arguments of the module, after its entrypoint
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Result<Self> {
+        self.args = args.into_iter().map(Into::into).collect();
+
+        log::info!("setting wasi test args to {:?}", self.args);
+
+        Ok(self)
+    }
+
+    pub fn with_env(mut self, key: impl AsRef<str>, value: impl AsRef<str>) -> Result<Self> {
+        let (key, value) = (key.as_ref(), value.as_ref());
+
+        log::info!("setting wasi test env {key}={value}");
+
+        self.env.push(format!("{key}={value}"));
+
+        Ok(self)
+    }
+
+    pub fn with_annotation(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self> {
+        let (key, value) = (key.into(), value.into());
+
+        log::info!("setting wasi test annotation {key}={value}");
+
+        self.annotations.insert(key, value);
+
+        Ok(self)
+    }
+
+    /// Uses fifos for the stdout and the stderr of the container, like containerd, rather than
+    /// regular files
+    #[cfg(unix)]
+    pub fn with_fifo_stdio(mut self) -> Result<Self> {
+        let dir = self.tempdir.path();
+
+        log::info!("setting wasi test stdio to fifos");
+
+        for name in ["stdout", "stderr"] {
+            fs::remove_file(dir.join(name))?;
+            fifo::create(dir.join(name))?;
+        }
+        self.fifo_stdio = true;
 
         Ok(self)
     }
@@ -157,6 +233,41 @@ where
 
         log::info!("building wasi test");
 
+        let entrypoint = match self.start_fn.as_str() {
+            "" => "/hello.wasm".to_string(),
+            s => "/hello.wasm#".to_string().add(s),
+        };
+        let args: Vec<_> = std::iter::once(entrypoint).chain(self.args).collect();
+        let mut process = ProcessBuilder::default().cwd("/").args(args);
+        if !self.env.is_empty() {
+            process = process.env(self.env);
+        }
+        let mut spec = SpecBuilder::default();
+        if !self.annotations.is_empty() {
+            spec = spec.annotations(self.annotations);
+        }
+        let spec = spec
+            .root(
+                RootBuilder::default()
+                    .path("rootfs")
+                    .readonly(false)
+                    .build()?,
+            )
+            .process(process.build()?)
+            .build()?;
+
+        spec.save(dir.join("config.json"))?;
+
+        // the fifos are read before the instance opens them, so that it never blocks on them
+        #[cfg(unix)]
+        let fifos = match self.fifo_stdio {
+            true => Some((
+                fifo::FifoReader::open(dir.join("stdout"))?,
+                fifo::FifoReader::open(dir.join("stderr"))?,
+            )),
+            false => None,
+        };
+
         let mut cfg = InstanceConfig::new(
             WasiInstance::Engine::default(),
             TEST_NAMESPACE,
@@ -168,7 +279,12 @@ where
             .set_stdin(dir.join("stdin"));
 
         let instance = WasiInstance::new(self.container_name, Some(&cfg))?;
-        Ok(WasiTest { instance, tempdir })
+        Ok(WasiTest {
+            instance,
+            tempdir,
+            #[cfg(unix)]
+            fifos,
+        })
     }
 }
 
@@ -184,6 +300,11 @@ where
         &self.instance
     }
 
+    /// The bundle of the container, with its `config.json` and its `rootfs`
+    pub fn bundle(&self) -> &Path {
+        self.tempdir.path()
+    }
+
     pub fn start(&self) -> Result<&Self> {
         log::info!("starting wasi test");
         self.instance.start()?;
@@ -208,8 +329,13 @@ where
             }
         };
 
-        let stdout = read_to_string(dir.join("stdout"))?;
-        let stderr = read_to_string(dir.join("stderr"))?;
+        let (stdout, stderr) = match self.read_fifos()? {
+            Some(output) => output,
+            None => (
+                read_to_string(dir.join("stdout"))?,
+                read_to_string(dir.join("stderr"))?,
+            ),
+        };
 
         self.instance.delete()?;
 
@@ -217,6 +343,107 @@ where
 
         Ok((status, stdout, stderr))
     }
+
+    #[cfg(unix)]
+    fn read_fifos(&self) -> Result<Option<(String, String)>> {
+        let Some((stdout, stderr)) = &self.fifos else {
+            return Ok(None);
+        };
+        Ok(Some((stdout.finish()?, stderr.finish()?)))
+    }
+
+    #[cfg(not(unix))]
+    fn read_fifos(&self) -> Result<Option<(String, String)>> {
+        Ok(None)
+    }
+}
+
+#[cfg(unix)]
+mod fifo {
+    use std::ffi::CString;
+    use std::fs::{File, OpenOptions};
+    use std::io::{ErrorKind, Read};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use anyhow::{bail, Result};
+
+    pub fn create(path: impl AsRef<Path>) -> Result<()> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Collects the output written to a fifo, like the clients of containerd do
+    pub struct FifoReader {
+        done: Arc<AtomicBool>,
+        reader: Mutex<Option<JoinHandle<std::io::Result<Vec<u8>>>>>,
+    }
+
+    impl FifoReader {
+        pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+            // the fifo is read without blocking, so that there is no need to keep a writer open,
+            // and the reader can stop once the container has exited
+            let mut fifo = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path)?;
+            let done = Arc::new(AtomicBool::new(false));
+            let reader = thread::spawn({
+                let done = done.clone();
+                move || read_until_done(&mut fifo, &done)
+            });
+            Ok(Self {
+                done,
+                reader: Mutex::new(Some(reader)),
+            })
+        }
+
+        /// Returns the output written to the fifo, once the container has exited
+        pub fn finish(&self) -> Result<String> {
+            self.done.store(true, Ordering::SeqCst);
+            let Some(reader) = self.reader.lock().unwrap().take() else {
+                bail!("the output of the fifo was already read");
+            };
+            let output = reader.join().expect("fifo reader panicked")?;
+            Ok(String::from_utf8(output)?)
+        }
+    }
+
+    impl Drop for FifoReader {
+        fn drop(&mut self) {
+            self.done.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn read_until_done(fifo: &mut File, done: &AtomicBool) -> std::io::Result<Vec<u8>> {
+        let mut output = vec![];
+        let mut buf = [0; 4096];
+        loop {
+            // the flag is checked before reading, so that the output written before it was set
+            // is read entirely
+            let is_done = done.load(Ordering::SeqCst);
+            match fifo.read(&mut buf) {
+                Ok(n) if n > 0 => output.extend_from_slice(&buf[..n]),
+                // no writer, or nothing written yet
+                Ok(_) if is_done => return Ok(output),
+                Err(err) if err.kind() == ErrorKind::WouldBlock && is_done => return Ok(output),
+                Ok(_) => thread::sleep(Duration::from_millis(10)),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
 }
 
 pub mod oci_helpers {
@@ -508,3 +735,34 @@ pub mod oci_helpers {
         Ok(())
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_fifo_reader() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("stdout");
+        fifo::create(&path)?;
+
+        let reader = fifo::FifoReader::open(&path)?;
+
+        // like the instance, the writer opens the fifo for reading and writing
+        let mut writer = OpenOptions::new().read(true).write(true).open(&path)?;
+        writer.write_all(b"hello ")?;
+        writer.write_all(&[b'x'; 100_000])?;
+        writer.write_all(b" world")?;
+
+        let output = reader.finish()?;
+        assert!(output.starts_with("hello x"));
+        assert!(output.ends_with("x world"));
+        assert_eq!(output.len(), 100_012);
+
+        assert!(reader.finish().is_err());
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use containerd_shim_wasm::container::{Engine, Entrypoint, Instance, RuntimeContext, Stdio};
-use wasmedge_sdk::config::{ConfigBuilder, HostRegistrationConfigOptions};
-use wasmedge_sdk::plugin::PluginManager;
-use wasmedge_sdk::VmBuilder;
+use wasmedge_sdk::config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions};
+use wasmedge_sdk::{Vm, VmBuilder};
+
+use crate::plugins::{self, PluginOptions};
 
 pub type WasmEdgeInstance = Instance<WasmEdgeEngine>;
 
@@ -13,17 +14,24 @@ pub struct WasmEdgeEngine {
 
 impl Default for WasmEdgeEngine {
     fn default() -> Self {
-        let host_options = HostRegistrationConfigOptions::default();
-        let host_options = host_options.wasi(true);
-        let config = ConfigBuilder::default()
-            .with_host_registration_config(host_options)
-            .build()
-            .unwrap();
-        let vm = VmBuilder::new().with_config(config).build().unwrap();
+        let vm = new_vm(false).unwrap();
+        // the containers inherit the plugins loaded by the shim
+        plugins::load_host_plugins();
         Self { vm }
     }
 }
 
+/// Create a vm with WASI, and with the threads proposal, i.e., shared memories and
+/// `wasi::thread-spawn`, when `threads` is set.
+fn new_vm(threads: bool) -> Result<Vm> {
+    let common_options = CommonConfigOptions::default().threads(threads);
+    let host_options = HostRegistrationConfigOptions::default().wasi(true);
+    let config = ConfigBuilder::new(common_options)
+        .with_host_registration_config(host_options)
+        .build()?;
+    Ok(VmBuilder::new().with_config(config).build()?)
+}
+
 impl Engine for WasmEdgeEngine {
     fn name() -> &'static str {
         "wasmedge"
@@ -32,25 +40,36 @@ impl Engine for WasmEdgeEngine {
     fn run_wasi(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
         let args = ctx.args();
         let envs: Vec<_> = std::env::vars().map(|(k, v)| format!("{k}={v}")).collect();
+        let entrypoint = ctx.entrypoint();
+        ensure!(
+            entrypoint.func_args().is_empty(),
+            "the wasmedge engine does not support arguments for the entrypoint function"
+        );
         let Entrypoint {
-            source,
-            func,
-            arg0: _,
-            name,
-        } = ctx.entrypoint();
+            source, func, name, ..
+        } = entrypoint;
 
-        let mut vm = self.vm.clone();
+        // the threads of the guest are capped by the pids limit of the cgroup of the container
+        let mut vm = match ctx.wasi_threads()? {
+            true => new_vm(true).context("failed to create a vm with wasi-threads")?,
+            false => self.vm.clone(),
+        };
+        // the root is not preopened when the capability policy denies the filesystem
+        let preopens = match ctx.allows_capability("filesystem") {
+            true => vec!["/:/"],
+            false => vec![],
+        };
         vm.wasi_module_mut()
             .context("Not found wasi module")?
             .initialize(
                 Some(args.iter().map(String::as_str).collect()),
                 Some(envs.iter().map(String::as_str).collect()),
-                Some(vec!["/:/"]),
+                Some(preopens),
             );
 
         let mod_name = name.unwrap_or_else(|| "main".to_string());
 
-        PluginManager::load(None)?;
+        PluginOptions::from_annotations(ctx.annotations())?.load()?;
         let vm = vm.auto_detect_plugins()?;
 
         let wasm_bytes = source.as_bytes()?;
@@ -1,4 +1,5 @@
 pub mod instance;
+pub mod plugins;
 
 pub use instance::WasmEdgeInstance;
 
@@ -0,0 +1,156 @@
+//! WasmEdge plugins, e.g., `wasi_nn`, `wasmedge_image` or `rustls`.
+//!
+//! Without configuration, the shim loads the plugins from the default paths of WasmEdge, e.g.,
+//! `WASMEDGE_PLUGIN_PATH`. The plugins can instead be loaded from configured directories:
+//!  - `RUNWASI_WASMEDGE_PLUGIN_PATH`: a `:` separated list of directories of the host, loaded by
+//!    the shim before it creates its containers, so that all its containers can use them.
+//!  - `runwasi.io/wasmedge-plugin-path`: a `:` separated list of directories of the container,
+//!    e.g., plugins shipped in the image or mounted in a volume, loaded when the container starts.
+//!  - `runwasi.io/wasmedge-plugins`: a `,` separated list of the plugins the container needs,
+//!    e.g., `wasi_nn,rustls`. The container fails to start if one of them isn't loaded, rather
+//!    than failing when the guest calls its imports.
+//!
+//! All the modules of the loaded plugins are registered in the VM of the container.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+use anyhow::{bail, ensure, Context, Result};
+use containerd_shim_wasm::sandbox::node_config;
+use wasmedge_sdk::plugin::PluginManager;
+
+pub const PLUGIN_PATH_ENV: &str = "RUNWASI_WASMEDGE_PLUGIN_PATH";
+
+/// Annotation with the directories of the container the plugins are loaded from
+pub const PLUGIN_PATH_ANNOTATION: &str = "runwasi.io/wasmedge-plugin-path";
+
+/// Annotation with the names of the plugins needed by the container
+pub const PLUGINS_ANNOTATION: &str = "runwasi.io/wasmedge-plugins";
+
+/// The directories of the host the plugins are loaded from, from the environment of the shim
+pub fn host_paths() -> Result<Vec<PathBuf>> {
+    node_config::var(PLUGIN_PATH_ENV)
+        .map(|paths| parse_paths(PLUGIN_PATH_ENV, &paths))
+        .unwrap_or_else(|_| Ok(vec![]))
+}
+
+/// Loads the plugins of the shim, once, before it creates its containers.
+pub(crate) fn load_host_plugins() {
+    static LOAD: Once = Once::new();
+    LOAD.call_once(|| {
+        let load_all = || -> Result<()> {
+            let paths = host_paths()?;
+            if paths.is_empty() {
+                PluginManager::load(None)?;
+            }
+            load(&paths)
+        };
+        // the containers needing a plugin that failed to load fail to start
+        if let Err(err) = load_all() {
+            log::error!("failed to load the wasmedge plugins of the shim: {err:#}");
+        }
+    });
+}
+
+/// The plugins of a container
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PluginOptions {
+    paths: Vec<PathBuf>,
+    required: Vec<String>,
+}
+
+impl PluginOptions {
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let paths = annotations
+            .get(PLUGIN_PATH_ANNOTATION)
+            .map(|paths| parse_paths(&format!("{PLUGIN_PATH_ANNOTATION} annotation"), paths))
+            .transpose()?
+            .unwrap_or_default();
+        let required = annotations
+            .get(PLUGINS_ANNOTATION)
+            .map(|names| {
+                names
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self { paths, required })
+    }
+
+    /// Loads the plugins of the container, and checks that the plugins it needs are loaded.
+    pub(crate) fn load(&self) -> Result<()> {
+        load(&self.paths)?;
+        let loaded = PluginManager::names();
+        let missing: Vec<_> = self
+            .required
+            .iter()
+            .filter(|name| !loaded.contains(name))
+            .collect();
+        if !missing.is_empty() {
+            bail!("missing wasmedge plugins {missing:?}, the loaded plugins are {loaded:?}");
+        }
+        Ok(())
+    }
+}
+
+fn load(paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        PluginManager::load(Some(path.as_path()))
+            .with_context(|| format!("failed to load wasmedge plugins from {path:?}"))?;
+    }
+    Ok(())
+}
+
+fn parse_paths(source: &str, paths: &str) -> Result<Vec<PathBuf>> {
+    paths
+        .split(':')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            ensure!(
+                Path::new(path).is_absolute(),
+                "invalid {source} {paths:?}, the plugin directories must be absolute paths"
+            );
+            Ok(PathBuf::from(path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_options() -> Result<()> {
+        assert_eq!(
+            PluginOptions::from_annotations(&HashMap::new())?,
+            PluginOptions::default()
+        );
+
+        let options = PluginOptions::from_annotations(&HashMap::from([
+            (
+                PLUGIN_PATH_ANNOTATION.to_string(),
+                "/opt/wasmedge/plugin: /usr/lib/wasmedge".to_string(),
+            ),
+            (
+                PLUGINS_ANNOTATION.to_string(),
+                "wasi_nn, rustls,".to_string(),
+            ),
+        ]))?;
+        assert_eq!(
+            options,
+            PluginOptions {
+                paths: vec!["/opt/wasmedge/plugin".into(), "/usr/lib/wasmedge".into()],
+                required: vec!["wasi_nn".into(), "rustls".into()],
+            }
+        );
+
+        let relative = HashMap::from([(PLUGIN_PATH_ANNOTATION.to_string(), "plugin".to_string())]);
+        assert!(PluginOptions::from_annotations(&relative).is_err());
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
 use containerd_shim_wasm::container::{Engine, Entrypoint, Instance, RuntimeContext, Stdio};
-use wasmer::{Module, Store};
+use wasmer::{FunctionType, Module, Store, Type, Value};
 use wasmer_wasix::virtual_fs::host_fs::FileSystem;
-use wasmer_wasix::{WasiEnv, WasiError};
+use wasmer_wasix::{is_wasix_module, WasiEnv, WasiError};
+
+use crate::wasix::{self, WASIX_ANNOTATION};
 
 pub type WasmerInstance = Instance<WasmerEngine>;
 
@@ -19,12 +21,11 @@ impl Engine for WasmerEngine {
     fn run_wasi(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
         let args = ctx.args();
         let envs = std::env::vars();
+        let entrypoint = ctx.entrypoint();
+        let func_args = entrypoint.func_args().to_vec();
         let Entrypoint {
-            source,
-            func,
-            arg0: _,
-            name,
-        } = ctx.entrypoint();
+            source, func, name, ..
+        } = entrypoint;
 
         let mod_name = name.unwrap_or_else(|| "main".to_string());
 
@@ -34,32 +35,80 @@ impl Engine for WasmerEngine {
         let wasm_bytes = source.as_bytes()?;
         let module = Module::from_binary(&store, &wasm_bytes)?;
 
-        let runtime = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()?;
+        let wasix = wasix::enabled(ctx.annotations())?;
+        ensure!(
+            wasix || !is_wasix_module(&module),
+            "the module imports WASIX, set the {WASIX_ANNOTATION} annotation to \"true\" to run it"
+        );
+
+        let mut runtime = tokio::runtime::Builder::new_multi_thread();
+        if let Some(threads) = ctx.threads() {
+            runtime.worker_threads(threads.get());
+        }
+        let runtime = runtime.enable_all().build()?;
         let _guard = runtime.enter();
 
         log::info!("Creating `WasiEnv`...: args {args:?}, envs: {envs:?}");
-        let (instance, wasi_env) = WasiEnv::builder(mod_name)
+        let mut builder = WasiEnv::builder(mod_name)
             .args(&args[1..])
             .envs(envs)
-            .fs(Box::<FileSystem>::default())
-            .preopen_dir("/")?
-            .instantiate(module, &mut store)?;
+            .fs(Box::<FileSystem>::default());
+        // the root is not preopened when the capability policy denies the filesystem
+        if ctx.allows_capability("filesystem") {
+            builder = builder.preopen_dir("/")?;
+        }
+        if wasix {
+            builder = wasix::configure(
+                builder,
+                store.engine().clone(),
+                runtime.handle().clone(),
+                ctx.allows_capability("sockets"),
+            );
+        }
+        let (instance, wasi_env) = builder.instantiate(module, &mut store)?;
 
         log::info!("redirect stdio");
         stdio.redirect()?;
 
         log::info!("Running {func:?}");
         let start = instance.exports.get_function(&func)?;
+        let params = parse_params(&start.ty(&store), &func_args)
+            .with_context(|| format!("invalid arguments for {func:?}"))?;
         wasi_env.data(&store).thread.set_status_running();
-        let status = start.call(&mut store, &[]).map(|_| 0).or_else(|err| {
+        let status = start.call(&mut store, &params).map(|_| 0).or_else(|err| {
             match err.downcast_ref::<WasiError>() {
                 Some(WasiError::Exit(code)) => Ok(code.raw()),
                 _ => Err(err),
             }
         })?;
 
+        // stops the other threads of the guest, as when the main thread of a process exits
+        wasi_env.cleanup(&mut store, Some(status.into()));
+
         Ok(status)
     }
 }
+
+/// Parses the arguments of the entrypoint as the parameters of the function.
+fn parse_params(ty: &FunctionType, args: &[String]) -> Result<Vec<Value>> {
+    ensure!(
+        ty.params().len() == args.len(),
+        "expected {} arguments, got {}",
+        ty.params().len(),
+        args.len()
+    );
+    ty.params()
+        .iter()
+        .zip(args)
+        .map(|(ty, arg)| {
+            let val = match ty {
+                Type::I32 => Value::I32(arg.parse()?),
+                Type::I64 => Value::I64(arg.parse()?),
+                Type::F32 => Value::F32(arg.parse()?),
+                Type::F64 => Value::F64(arg.parse()?),
+                ty => bail!("unsupported parameter type {ty:?}"),
+            };
+            Ok(val)
+        })
+        .collect()
+}
@@ -1,4 +1,5 @@
 pub mod instance;
+pub mod wasix;
 
 pub use instance::WasmerInstance;
 
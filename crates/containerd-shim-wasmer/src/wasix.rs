@@ -0,0 +1,93 @@
+//! WASIX, the extensions of wasmer to WASI preview 1, e.g., threads, `fork` and `exec`, and
+//! sockets.
+//!
+//! Modules importing `wasix_32v1` or `wasix_64v1` only run with the `runwasi.io/wasix: "true"`
+//! annotation, so that workloads only get the additional host APIs when they ask for them.
+//! The threads of the guest are then spawned on the tokio runtime of the container, its sockets
+//! use the network namespace of the container, and `exec` runs the binaries in its file system.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::runtime::Handle;
+use wasmer::Engine;
+use wasmer_wasix::runtime::task_manager::tokio::TokioTaskManager;
+use wasmer_wasix::{LocalNetworking, PluggableRuntime, WasiEnvBuilder};
+
+/// Annotation enabling WASIX for the container, `true` or `false`
+pub const WASIX_ANNOTATION: &str = "runwasi.io/wasix";
+
+/// Whether the container can use WASIX, from its annotations
+pub fn enabled(annotations: &HashMap<String, String>) -> Result<bool> {
+    annotations
+        .get(WASIX_ANNOTATION)
+        .map(|enabled| {
+            enabled
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid {WASIX_ANNOTATION} annotation {enabled:?}"))
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Sets the WASIX runtime of the environment of the guest, with the sockets of the network
+/// namespace of the container when `networking` is allowed.
+pub(crate) fn configure(
+    builder: WasiEnvBuilder,
+    engine: Engine,
+    handle: Handle,
+    networking: bool,
+) -> WasiEnvBuilder {
+    let mut runtime = PluggableRuntime::new(Arc::new(TokioTaskManager::new(handle)));
+    if networking {
+        runtime.set_networking_implementation(LocalNetworking::default());
+    }
+    // the threads of the guest compile the module with the engine of the shim
+    runtime.set_engine(Some(engine));
+    builder.runtime(Arc::new(runtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{Module, Store};
+    use wasmer_wasix::is_wasix_module;
+
+    use super::*;
+
+    #[test]
+    fn test_enabled() -> Result<()> {
+        assert!(!enabled(&HashMap::new())?);
+
+        let annotations =
+            |enabled: &str| HashMap::from([(WASIX_ANNOTATION.to_string(), enabled.to_string())]);
+        assert!(enabled(&annotations("true"))?);
+        assert!(!enabled(&annotations("false"))?);
+        assert!(enabled(&annotations("yes")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_wasix_module() -> Result<()> {
+        let store = Store::default();
+        let wasix = Module::new(
+            &store,
+            r#"(module
+                (import "wasix_32v1" "thread_id" (func (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")))"#,
+        )?;
+        assert!(is_wasix_module(&wasix));
+
+        let wasi = Module::new(
+            &store,
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")))"#,
+        )?;
+        assert!(!is_wasix_module(&wasi));
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{bail, ensure, Context, Result};
+use containerd_shim_wasm::container::{Engine, Entrypoint, Instance, RuntimeContext, Stdio};
+use wasmi::core::{ValueType, F32, F64};
+use wasmi::{FuncType, Linker, Module, Store, Value};
+use wasmi_wasi::{ambient_authority, Dir, WasiCtx, WasiCtxBuilder};
+
+pub type WasmiInstance = Instance<WasmiEngine>;
+
+/// An engine interpreting the modules with [wasmi], for the nodes that can't run the code
+/// compiled by a JIT, e.g., with a W^X policy or on architectures without a compiler backend.
+///
+/// [wasmi]: https://github.com/wasmi-labs/wasmi
+#[derive(Clone, Default)]
+pub struct WasmiEngine {
+    engine: wasmi::Engine,
+}
+
+impl Engine for WasmiEngine {
+    fn name() -> &'static str {
+        "wasmi"
+    }
+
+    fn run_wasi(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
+        let args = ctx.args();
+        let envs: Vec<_> = std::env::vars().collect();
+        let entrypoint = ctx.entrypoint();
+        let func_args = entrypoint.func_args().to_vec();
+        let Entrypoint { source, func, .. } = entrypoint;
+
+        let wasm_bytes = source.as_bytes()?;
+        let module = Module::new(&self.engine, &wasm_bytes[..]).context("loading module")?;
+
+        log::info!("Creating `WasiCtx`...: args {args:?}, envs: {envs:?}");
+        let mut wasi_ctx = WasiCtxBuilder::new()
+            .args(args)?
+            .envs(&envs)?
+            .inherit_stdio();
+        // the root is not preopened when the capability policy denies the filesystem
+        if ctx.allows_capability("filesystem") {
+            wasi_ctx =
+                wasi_ctx.preopened_dir(Dir::open_ambient_dir("/", ambient_authority())?, "/")?;
+        }
+        let wasi_ctx = wasi_ctx.build();
+
+        let mut store = Store::new(&self.engine, wasi_ctx);
+        let mut linker = Linker::<WasiCtx>::new(&self.engine);
+        wasmi_wasi::add_to_linker(&mut linker, |ctx| ctx)?;
+
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+
+        log::info!("redirect stdio");
+        stdio.redirect()?;
+
+        log::info!("Running {func:?}");
+        let start = instance
+            .get_func(&store, &func)
+            .with_context(|| format!("module has no function {func:?}"))?;
+        let params = parse_params(&start.ty(&store), &func_args)
+            .with_context(|| format!("invalid arguments for {func:?}"))?;
+        let mut results = vec![Value::I32(0); start.ty(&store).results().len()];
+        let status = match start.call(&mut store, &params, &mut results) {
+            Ok(()) => 0,
+            Err(wasmi::Error::Trap(trap)) => match trap.i32_exit_status() {
+                Some(code) => code,
+                None => return Err(wasmi::Error::Trap(trap).into()),
+            },
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(status)
+    }
+
+    fn supported_imports() -> Option<&'static [&'static str]> {
+        // the linker only has the WASI preview 1 functions, and wasmi can't run components
+        Some(&["wasi_snapshot_preview1"])
+    }
+}
+
+/// Parses the arguments of the entrypoint as the parameters of the function.
+fn parse_params(ty: &FuncType, args: &[String]) -> Result<Vec<Value>> {
+    ensure!(
+        ty.params().len() == args.len(),
+        "expected {} arguments, got {}",
+        ty.params().len(),
+        args.len()
+    );
+    ty.params()
+        .iter()
+        .zip(args)
+        .map(|(ty, arg)| {
+            let val = match ty {
+                ValueType::I32 => Value::I32(arg.parse()?),
+                ValueType::I64 => Value::I64(arg.parse()?),
+                ValueType::F32 => Value::F32(F32::from(arg.parse::<f32>()?)),
+                ValueType::F64 => Value::F64(F64::from(arg.parse::<f64>()?)),
+                ty => bail!("unsupported parameter type {ty:?}"),
+            };
+            Ok(val)
+        })
+        .collect()
+}
@@ -0,0 +1,8 @@
+pub mod instance;
+
+pub use instance::WasmiInstance;
+
+#[cfg(unix)]
+#[cfg(test)]
+#[path = "tests.rs"]
+mod wasmi_tests;
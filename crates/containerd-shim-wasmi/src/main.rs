@@ -0,0 +1,6 @@
+use containerd_shim_wasm::sandbox::cli::{revision, shim_main, version};
+use containerd_shim_wasmi::WasmiInstance;
+
+fn main() {
+    shim_main::<WasmiInstance>("wasmi", version!(), revision!(), "v1", None);
+}
@@ -0,0 +1,61 @@
+//! Componentization of WASI preview 1 modules.
+//!
+//! When `RUNWASI_WASMTIME_P1_ADAPTER` is set to the path of a preview 1 component adapter,
+//! e.g., the `wasi_snapshot_preview1.command.wasm` published with each wasmtime release,
+//! the modules run by the shim are turned into components with the adapter when they
+//! are loaded, or precompiled. The adapter implements the preview 1 imports of the module
+//! on top of the preview 2 interfaces, so the module runs as a `wasi:cli/command` component,
+//! with the same `wasi:http` and `wasi:sockets` support as any other component.
+//!
+//! The adapter must match the version of wasmtime the shim is built with.
+//! Library modules linked into the entrypoint are not componentized.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::sandbox::node_config;
+use wit_component::ComponentEncoder;
+
+pub const P1_ADAPTER_ENV: &str = "RUNWASI_WASMTIME_P1_ADAPTER";
+
+/// Name of the module the preview 1 imports are from
+const P1_MODULE_NAME: &str = "wasi_snapshot_preview1";
+
+/// A preview 1 component adapter
+#[derive(Clone)]
+pub struct P1Adapter(Arc<Vec<u8>>);
+
+impl P1Adapter {
+    /// Loads the adapter from the environment of the shim, if one is configured
+    pub fn from_env() -> Result<Option<Self>> {
+        match node_config::var_os(P1_ADAPTER_ENV) {
+            Some(path) if !path.is_empty() => Self::load(Path::new(&path)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let adapter = std::fs::read(path)
+            .with_context(|| format!("failed to read preview 1 adapter {path:?}"))?;
+        Ok(Self::new(adapter))
+    }
+
+    pub fn new(adapter: Vec<u8>) -> Self {
+        Self(Arc::new(adapter))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Turns a preview 1 module into a component
+    pub fn componentize(&self, module: &[u8]) -> Result<Vec<u8>> {
+        ComponentEncoder::default()
+            .module(module)?
+            .adapter(P1_MODULE_NAME, &self.0)?
+            .validate(true)
+            .encode()
+            .context("failed to componentize module with the preview 1 adapter")
+    }
+}
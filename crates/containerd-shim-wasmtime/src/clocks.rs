@@ -0,0 +1,217 @@
+//! Virtual clocks of the guest.
+//!
+//! The clocks seen by the guest can be changed with annotations, e.g., for deterministic tests
+//! or to replay a workload at the time it originally ran:
+//!  - `runwasi.io/wall-clock-seconds`: pins the wall clock to a Unix timestamp, in seconds.
+//!    The wall clock then doesn't advance.
+//!  - `runwasi.io/wall-clock-offset-seconds`: shifts the wall clock by a number of seconds,
+//!    which can be negative. It applies to the pinned time too.
+//!  - `runwasi.io/monotonic-clock-resolution-ms`: throttles the monotonic clock to a resolution,
+//!    in milliseconds, e.g., to reduce the precision of timers available to the guest.
+//!    The monotonic clock keeps advancing, so that the guest can still sleep.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+/// Annotation with the Unix timestamp the wall clock is pinned to, in seconds
+pub const WALL_CLOCK_ANNOTATION: &str = "runwasi.io/wall-clock-seconds";
+
+/// Annotation with the offset applied to the wall clock, in seconds
+pub const WALL_CLOCK_OFFSET_ANNOTATION: &str = "runwasi.io/wall-clock-offset-seconds";
+
+/// Annotation with the resolution of the monotonic clock, in milliseconds
+pub const MONOTONIC_CLOCK_RESOLUTION_ANNOTATION: &str = "runwasi.io/monotonic-clock-resolution-ms";
+
+/// Resolution of the wall clock, as reported by the host clock on Linux
+const WALL_CLOCK_RESOLUTION: Duration = Duration::from_nanos(1);
+
+/// The clocks of the guest, the clocks of the host unless configured otherwise
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VirtualClocks {
+    pinned: Option<Duration>,
+    offset: i64,
+    resolution: Option<Duration>,
+}
+
+impl VirtualClocks {
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let parse = |key: &str| -> Result<Option<i64>> {
+            annotations
+                .get(key)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("invalid {key} annotation {value:?}"))
+                })
+                .transpose()
+        };
+
+        let pinned = parse(WALL_CLOCK_ANNOTATION)?
+            .map(|secs| {
+                u64::try_from(secs)
+                    .map(Duration::from_secs)
+                    .with_context(|| format!("invalid {WALL_CLOCK_ANNOTATION} annotation, the time is before the Unix epoch"))
+            })
+            .transpose()?;
+        let offset = parse(WALL_CLOCK_OFFSET_ANNOTATION)?.unwrap_or(0);
+        let resolution = parse(MONOTONIC_CLOCK_RESOLUTION_ANNOTATION)?
+            .map(|millis| match u64::try_from(millis) {
+                Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+                _ => anyhow::bail!(
+                    "invalid {MONOTONIC_CLOCK_RESOLUTION_ANNOTATION} annotation, the resolution must be positive"
+                ),
+            })
+            .transpose()?;
+
+        Ok(Self {
+            pinned,
+            offset,
+            resolution,
+        })
+    }
+
+    /// Sets the clocks of a WASI context, leaving the host clocks when they are not virtualized.
+    pub(crate) fn apply(&self, builder: &mut WasiCtxBuilder) {
+        if self.pinned.is_some() || self.offset != 0 {
+            builder.wall_clock(self.wall_clock());
+        }
+        if self.resolution.is_some() {
+            builder.monotonic_clock(self.monotonic_clock());
+        }
+    }
+
+    /// The wall clock of the guest, which is the clock of the host when it is not virtualized.
+    pub(crate) fn wall_clock(&self) -> impl HostWallClock {
+        VirtualWallClock {
+            pinned: self.pinned,
+            offset: self.offset,
+        }
+    }
+
+    /// The monotonic clock of the guest, which is the clock of the host when it is not virtualized.
+    pub(crate) fn monotonic_clock(&self) -> impl HostMonotonicClock {
+        VirtualMonotonicClock {
+            start: Instant::now(),
+            resolution: self.resolution.map_or(1, |r| r.as_nanos() as u64),
+        }
+    }
+}
+
+struct VirtualWallClock {
+    pinned: Option<Duration>,
+    offset: i64,
+}
+
+impl HostWallClock for VirtualWallClock {
+    fn resolution(&self) -> Duration {
+        WALL_CLOCK_RESOLUTION
+    }
+
+    fn now(&self) -> Duration {
+        let now = self.pinned.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+        });
+        let offset = Duration::from_secs(self.offset.unsigned_abs());
+        // times before the Unix epoch can't be represented
+        match self.offset < 0 {
+            true => now.saturating_sub(offset),
+            false => now.saturating_add(offset),
+        }
+    }
+}
+
+struct VirtualMonotonicClock {
+    start: Instant,
+    resolution: u64,
+}
+
+impl HostMonotonicClock for VirtualMonotonicClock {
+    fn resolution(&self) -> u64 {
+        self.resolution
+    }
+
+    fn now(&self) -> u64 {
+        let now = self.start.elapsed().as_nanos() as u64;
+        now - now % self.resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations(annotations: &[(&str, &str)]) -> HashMap<String, String> {
+        annotations
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_annotations() -> Result<()> {
+        assert_eq!(
+            VirtualClocks::from_annotations(&HashMap::new())?,
+            VirtualClocks::default()
+        );
+
+        let clocks = VirtualClocks::from_annotations(&annotations(&[
+            (WALL_CLOCK_ANNOTATION, "1704067200"),
+            (WALL_CLOCK_OFFSET_ANNOTATION, "-3600"),
+            (MONOTONIC_CLOCK_RESOLUTION_ANNOTATION, "10"),
+        ]))?;
+        assert_eq!(
+            clocks,
+            VirtualClocks {
+                pinned: Some(Duration::from_secs(1704067200)),
+                offset: -3600,
+                resolution: Some(Duration::from_millis(10)),
+            }
+        );
+
+        for invalid in [
+            (WALL_CLOCK_ANNOTATION, "-1"),
+            (WALL_CLOCK_ANNOTATION, "2024-01-01"),
+            (WALL_CLOCK_OFFSET_ANNOTATION, "1h"),
+            (MONOTONIC_CLOCK_RESOLUTION_ANNOTATION, "0"),
+        ] {
+            assert!(VirtualClocks::from_annotations(&annotations(&[invalid])).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_wall_clock() {
+        let pinned = VirtualWallClock {
+            pinned: Some(Duration::from_secs(1704067200)),
+            offset: -3600,
+        };
+        assert_eq!(pinned.now(), Duration::from_secs(1704067200 - 3600));
+        assert_eq!(pinned.now(), pinned.now());
+
+        let host = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let shifted = VirtualWallClock {
+            pinned: None,
+            offset: 86400,
+        };
+        assert!(shifted.now() >= host + Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn test_monotonic_clock() {
+        let clock = VirtualMonotonicClock {
+            start: Instant::now() - Duration::from_millis(25),
+            resolution: Duration::from_millis(10).as_nanos() as u64,
+        };
+        let now = clock.now();
+        assert_eq!(now % clock.resolution(), 0);
+        assert!(now >= Duration::from_millis(20).as_nanos() as u64);
+    }
+}
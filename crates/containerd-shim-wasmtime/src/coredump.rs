@@ -0,0 +1,39 @@
+//! Core dumps of the guests that trap.
+//!
+//! With the `runwasi.io/coredump-on-trap: "true"` annotation, a guest trapping while running its
+//! entrypoint gets a core dump in the [wasm core dump format], written next to the bundle of the
+//! container, whose path the shim then publishes in a `/tasks/coredumped` event.
+//! The dump can be loaded in debuggers supporting the format, e.g., `wasmgdb`, to inspect the
+//! stack and the locals of the failing frames.
+//!
+//! Exits and interruptions by the host, e.g., `proc_exit` or a deadline, are not traps and
+//! don't get a core dump.
+//!
+//! [wasm core dump format]: https://github.com/WebAssembly/tool-conventions/blob/main/Coredump.md
+
+use std::fs;
+
+use anyhow::Result;
+use wasmtime::{Store, Trap, WasmCoreDump};
+
+use crate::instance::WasiCtx;
+
+/// Writes the core dump of a failed call to the core dump file of the store, if the call trapped.
+pub(crate) fn write_on_trap<T>(store: &mut Store<WasiCtx>, name: &str, status: &Result<T>) {
+    let Err(err) = status else {
+        return;
+    };
+    let Some(path) = store.data().coredump.clone() else {
+        return;
+    };
+    if !err.is::<Trap>() {
+        return;
+    }
+    let Some(coredump) = err.downcast_ref::<WasmCoreDump>() else {
+        return;
+    };
+    match fs::write(&path, coredump.serialize(&mut *store, name)) {
+        Ok(()) => log::info!("guest trapped, wrote core dump to {path:?}"),
+        Err(err) => log::error!("failed to write core dump to {path:?}: {err}"),
+    }
+}
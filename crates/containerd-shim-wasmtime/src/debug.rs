@@ -0,0 +1,85 @@
+//! Debugging of the guests with a native debugger.
+//!
+//! wasmtime doesn't have an API to debug the guests it runs, e.g., to implement a gdb remote
+//! or DAP stub, but it can keep the DWARF of the guests in the code it compiles, so that gdb or
+//! lldb attached to the process of a container can set breakpoints in, and step through, the
+//! sources of the guest:
+//!  - `RUNWASI_WASMTIME_DEBUG`: set to `1` or `true` to compile the guests of the shim with their
+//!    debug info, and without optimizations. Precompiled layers are compiled again.
+//!  - `runwasi.io/wait-for-debugger: "true"`: stops the process of the container before it calls
+//!    its entrypoint, until a debugger attaches and continues it, e.g., with `gdb -p <pid>`.
+//!    This is only supported on Linux.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::sandbox::node_config;
+use wasmtime::{Config, OptLevel};
+
+use crate::instance::WasiCtx;
+
+pub const DEBUG_ENV: &str = "RUNWASI_WASMTIME_DEBUG";
+
+/// Annotation stopping the container before it calls its entrypoint, `true` or `false`
+pub const WAIT_FOR_DEBUGGER_ANNOTATION: &str = "runwasi.io/wait-for-debugger";
+
+/// Whether the guests are compiled for debugging, from the environment of the shim
+pub fn enabled() -> bool {
+    node_config::var(DEBUG_ENV)
+        .is_ok_and(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true"))
+}
+
+/// Configures the engine to emit the debug info of the guests.
+pub(crate) fn apply(config: &mut Config) {
+    config.debug_info(true).cranelift_opt_level(OptLevel::None);
+}
+
+pub(crate) fn wait_for_debugger(annotations: &HashMap<String, String>) -> Result<bool> {
+    annotations
+        .get(WAIT_FOR_DEBUGGER_ANNOTATION)
+        .map(|wait| {
+            wait.parse().with_context(|| {
+                format!("invalid {WAIT_FOR_DEBUGGER_ANNOTATION} annotation {wait:?}")
+            })
+        })
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Stops the process until a debugger continues it, if the container waits for one.
+pub(crate) fn wait(ctx: &WasiCtx) -> Result<()> {
+    if !ctx.wait_for_debugger {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        log::info!(
+            "waiting for a debugger to attach to process {} and continue it",
+            std::process::id()
+        );
+        signal_hook::low_level::raise(signal_hook::consts::SIGSTOP)
+            .context("failed to stop the process")?;
+        log::info!("continued by the debugger");
+    }
+    #[cfg(not(unix))]
+    log::warn!("{WAIT_FOR_DEBUGGER_ANNOTATION} is only supported on Linux, ignoring it");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_for_debugger() -> Result<()> {
+        assert!(!wait_for_debugger(&HashMap::new())?);
+
+        let annotations = |wait: &str| {
+            HashMap::from([(WAIT_FOR_DEBUGGER_ANNOTATION.to_string(), wait.to_string())])
+        };
+        assert!(wait_for_debugger(&annotations("true"))?);
+        assert!(!wait_for_debugger(&annotations("false"))?);
+        assert!(wait_for_debugger(&annotations("yes")).is_err());
+        Ok(())
+    }
+}
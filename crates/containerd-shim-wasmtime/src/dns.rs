@@ -0,0 +1,677 @@
+//! Host implementation of `wasi:sockets/ip-name-lookup` with the resolv.conf of the container.
+//!
+//! The name lookup of wasmtime uses the resolver of the libc, which can't load its NSS modules
+//! from the rootfs of a wasm image, and doesn't see the `/etc/resolv.conf` that the kubelet mounts
+//! in the container. Names are instead resolved by a stub resolver:
+//!  - the names of `/etc/hosts` are resolved first, e.g., `localhost` or the hostname of the pod,
+//!  - then the `nameserver`s of `/etc/resolv.conf` are queried over UDP, and over TCP when the
+//!    response is truncated, for the `A` and `AAAA` records of the name,
+//!  - relative names are expanded with the `search` domains, according to the `ndots` option,
+//!    so that in-cluster service names, e.g., `my-service` or `my-service.my-namespace`, resolve
+//!    as they would with glibc,
+//!  - the `timeout` and `attempts` options are honored.
+//!
+//! The files are read on every lookup, as glibc does when they change.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{mem, vec};
+
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::runtime::{poll_noop, spawn, AbortOnDropJoinHandle};
+use wasmtime_wasi::{async_trait, subscribe, Pollable, Subscribe};
+
+use crate::instance::WasiCtx;
+
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+const DNS_PORT: u16 = 53;
+const MAX_NAMESERVERS: usize = 3;
+const MAX_UDP_RESPONSE: usize = 4096;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NOERROR: u8 = 0;
+const RCODE_NXDOMAIN: u8 = 3;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit/dns",
+        world: "runwasi:dns/imports",
+        async: {
+            only_imports: [],
+        },
+        trappable_imports: true,
+        with: {
+            "wasi:io/poll": wasmtime_wasi::bindings::io::poll,
+            "wasi:sockets/network": wasmtime_wasi::bindings::sockets::network,
+            "wasi:sockets/ip-name-lookup/resolve-address-stream": super::ResolveAddressStream,
+        },
+    });
+}
+
+use bindings::wasi::sockets::ip_name_lookup::{self, ErrorCode, IpAddress, Network};
+
+/// Add the name lookup to `linker`, in place of the one of wasmtime.
+pub(crate) fn add_to_linker(linker: &mut Linker<WasiCtx>) -> Result<()> {
+    linker.allow_shadowing(true);
+    let result = ip_name_lookup::add_to_linker(linker, |ctx| ctx);
+    linker.allow_shadowing(false);
+    result
+}
+
+/// The resolver configuration of `/etc/resolv.conf`, with the defaults of glibc.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<SocketAddr>,
+    pub search: Vec<String>,
+    pub ndots: usize,
+    pub timeout: Duration,
+    pub attempts: usize,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        Self {
+            nameservers: vec![SocketAddr::from((Ipv4Addr::LOCALHOST, DNS_PORT))],
+            search: vec![],
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+        }
+    }
+}
+
+impl ResolvConf {
+    /// Loads the configuration of `path`, or the defaults when it doesn't exist.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        match std::fs::read_to_string(path.as_ref()) {
+            Ok(content) => Self::parse(&content),
+            Err(err) => {
+                log::debug!("using the default resolver configuration: {err}");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut conf = Self::default();
+        let mut nameservers = vec![];
+        for line in content.lines() {
+            let line = line.split(['#', ';']).next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("nameserver") => {
+                    // link-local addresses can have a zone, e.g., `fe80::1%eth0`, which isn't
+                    // supported
+                    let address = fields.next().and_then(|ns| IpAddr::from_str(ns).ok());
+                    if let Some(address) = address {
+                        nameservers.push(SocketAddr::new(address, DNS_PORT));
+                    }
+                }
+                // the last of `domain` and `search` wins
+                Some("domain") => conf.search = fields.take(1).map(absolute).collect(),
+                Some("search") => conf.search = fields.map(absolute).collect(),
+                Some("options") => {
+                    for option in fields {
+                        let (name, value) = option.split_once(':').unwrap_or((option, ""));
+                        let value = value.parse::<usize>();
+                        match (name, value) {
+                            ("ndots", Ok(ndots)) => conf.ndots = ndots.min(15),
+                            ("timeout", Ok(secs)) => {
+                                conf.timeout = Duration::from_secs(secs.clamp(1, 30) as u64)
+                            }
+                            ("attempts", Ok(attempts)) => conf.attempts = attempts.clamp(1, 5),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !nameservers.is_empty() {
+            nameservers.truncate(MAX_NAMESERVERS);
+            conf.nameservers = nameservers;
+        }
+        conf
+    }
+
+    /// The fully qualified names to query for `name`, in order.
+    /// A name ending with a dot is absolute and isn't expanded with the search domains. Other
+    /// names are queried as is before the search domains when they have at least `ndots` dots,
+    /// and after them otherwise.
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        if let Some(name) = name.strip_suffix('.') {
+            return vec![name.to_string()];
+        }
+        let expanded = self
+            .search
+            .iter()
+            .filter(|domain| !domain.is_empty())
+            .map(|domain| format!("{name}.{domain}"));
+        if name.matches('.').count() >= self.ndots {
+            std::iter::once(name.to_string()).chain(expanded).collect()
+        } else {
+            expanded.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+// search domains are stored without their trailing dot
+fn absolute(domain: &str) -> String {
+    domain.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Returns the addresses of `name` in the hosts file `content`.
+pub fn lookup_hosts(content: &str, name: &str) -> Vec<IpAddr> {
+    let name = name.trim_end_matches('.');
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let address = IpAddr::from_str(fields.next()?).ok()?;
+            fields
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+                .then_some(address)
+        })
+        .collect()
+}
+
+/// Resolves `host`, an IP address or a name, with the `/etc/hosts` and `/etc/resolv.conf` of the
+/// container.
+pub async fn lookup_host(host: &str) -> Result<Vec<IpAddr>, ErrorCode> {
+    // IPv6 addresses are between brackets in URLs
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(address) = IpAddr::from_str(literal) {
+        return Ok(vec![address]);
+    }
+    let conf = ResolvConf::load(RESOLV_CONF_PATH);
+    let hosts = std::fs::read_to_string(HOSTS_PATH).unwrap_or_default();
+    resolve(&conf, &hosts, host).await
+}
+
+/// Resolves `name` with the hosts file and the resolver configuration.
+pub async fn resolve(conf: &ResolvConf, hosts: &str, name: &str) -> Result<Vec<IpAddr>, ErrorCode> {
+    let addresses = lookup_hosts(hosts, name);
+    if !addresses.is_empty() {
+        return Ok(addresses);
+    }
+
+    let mut failed = false;
+    for candidate in conf.candidates(name) {
+        match lookup(conf, &candidate).await {
+            Ok(Some(addresses)) => return Ok(addresses),
+            Ok(None) => {}
+            Err(err) => {
+                log::debug!("failed to resolve {candidate}: {err:?}");
+                failed = true;
+            }
+        }
+    }
+    match failed {
+        true => Err(ErrorCode::TemporaryResolverFailure),
+        false => Err(ErrorCode::NameUnresolvable),
+    }
+}
+
+// Queries the nameservers for the addresses of a fully qualified name, `None` when it doesn't
+// exist or has no address.
+async fn lookup(conf: &ResolvConf, name: &str) -> Result<Option<Vec<IpAddr>>, ErrorCode> {
+    let queries = [TYPE_A, TYPE_AAAA]
+        .map(|qtype| encode_query(query_id(), name, qtype).map(|query| (qtype, query)));
+    let [a, aaaa] = queries;
+    let (a, aaaa) = (a?, aaaa?);
+
+    for _ in 0..conf.attempts {
+        for &server in &conf.nameservers {
+            let (a, aaaa) = tokio::join!(
+                exchange(server, &a.1, conf.timeout),
+                exchange(server, &aaaa.1, conf.timeout)
+            );
+            let (Some(a), Some(aaaa)) = (a, aaaa) else {
+                continue;
+            };
+            match (a.rcode, aaaa.rcode) {
+                (RCODE_NOERROR, RCODE_NOERROR) => {}
+                (RCODE_NXDOMAIN, _) | (_, RCODE_NXDOMAIN) => return Ok(None),
+                // e.g., a server failure, the next nameserver might know better
+                _ => continue,
+            }
+            let addresses: Vec<_> = a.addresses.into_iter().chain(aaaa.addresses).collect();
+            return Ok((!addresses.is_empty()).then_some(addresses));
+        }
+    }
+    Err(ErrorCode::TemporaryResolverFailure)
+}
+
+// Sends a query to `server` and waits for its response, `None` on timeout or error.
+async fn exchange(server: SocketAddr, query: &[u8], timeout: Duration) -> Option<Response> {
+    let exchange = async {
+        let response = exchange_udp(server, query).await?;
+        match response.truncated {
+            true => exchange_tcp(server, query).await,
+            false => Ok(response),
+        }
+    };
+    match tokio::time::timeout(timeout, exchange).await {
+        Ok(Ok(response)) => Some(response),
+        Ok(Err(err)) => {
+            log::debug!("failed to query {server}: {err}");
+            None
+        }
+        Err(_) => {
+            log::debug!("timeout querying {server}");
+            None
+        }
+    }
+}
+
+async fn exchange_udp(server: SocketAddr, query: &[u8]) -> std::io::Result<Response> {
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+    let mut buffer = vec![0; MAX_UDP_RESPONSE];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        // ignore the datagrams that aren't the response to the query
+        if let Some(response) = parse_response(query, &buffer[..len]) {
+            return Ok(response);
+        }
+    }
+}
+
+async fn exchange_tcp(server: SocketAddr, query: &[u8]) -> std::io::Result<Response> {
+    let mut stream = TcpStream::connect(server).await?;
+    stream.write_u16(query.len() as u16).await?;
+    stream.write_all(query).await?;
+    let len = stream.read_u16().await?;
+    let mut buffer = vec![0; len as usize];
+    stream.read_exact(&mut buffer).await?;
+    parse_response(query, &buffer)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid dns response"))
+}
+
+fn query_id() -> u16 {
+    RandomState::new().build_hasher().finish() as u16
+}
+
+/// Encodes a recursive query of the records of type `qtype` of `name`.
+fn encode_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>, ErrorCode> {
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // a standard query, with recursion desired
+    query.extend_from_slice(&0x0100u16.to_be_bytes());
+    // one question, no answer, authority or additional record
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    if query.len() - 12 > 255 {
+        return Err(ErrorCode::InvalidArgument);
+    }
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// The parts of a response the resolver cares about
+#[derive(Debug, Default, PartialEq)]
+struct Response {
+    rcode: u8,
+    truncated: bool,
+    addresses: Vec<IpAddr>,
+}
+
+/// Parses the response to `query`, `None` if `packet` isn't one.
+fn parse_response(query: &[u8], packet: &[u8]) -> Option<Response> {
+    let mut reader = Reader { packet, offset: 0 };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    let qdcount = reader.u16()?;
+    let ancount = reader.u16()?;
+    reader.skip(4)?;
+    // the id must match and the packet must be a response
+    if query.get(..2)? != id.to_be_bytes() || flags & 0x8000 == 0 {
+        return None;
+    }
+    let mut response = Response {
+        rcode: (flags & 0x000f) as u8,
+        truncated: flags & 0x0200 != 0,
+        addresses: vec![],
+    };
+    if response.truncated {
+        return Some(response);
+    }
+
+    for _ in 0..qdcount {
+        reader.skip_name()?;
+        reader.skip(4)?;
+    }
+    // the answers include the records of the target of the CNAMEs
+    for _ in 0..ancount {
+        reader.skip_name()?;
+        let rtype = reader.u16()?;
+        let class = reader.u16()?;
+        reader.skip(4)?;
+        let len = reader.u16()? as usize;
+        let data = reader.bytes(len)?;
+        match (rtype, class, len) {
+            (TYPE_A, CLASS_IN, 4) => {
+                let octets: [u8; 4] = data.try_into().ok()?;
+                response.addresses.push(IpAddr::from(octets));
+            }
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                let octets: [u8; 16] = data.try_into().ok()?;
+                response.addresses.push(IpAddr::from(octets));
+            }
+            _ => {}
+        }
+    }
+    Some(response)
+}
+
+struct Reader<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.packet.get(self.offset..self.offset + len)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // skips a name, which ends with an empty label or a compression pointer
+    fn skip_name(&mut self) -> Option<()> {
+        loop {
+            let len = self.bytes(1)?[0];
+            match len {
+                0 => return Some(()),
+                len if len & 0xc0 == 0xc0 => return self.skip(1),
+                len => self.skip(len as usize)?,
+            }
+        }
+    }
+}
+
+/// The resource of a pending or completed name lookup
+pub enum ResolveAddressStream {
+    Waiting(AbortOnDropJoinHandle<Result<Vec<IpAddress>, ErrorCode>>),
+    Done(Result<vec::IntoIter<IpAddress>, ErrorCode>),
+}
+
+#[async_trait]
+impl Subscribe for ResolveAddressStream {
+    async fn ready(&mut self) {
+        if let ResolveAddressStream::Waiting(future) = self {
+            *self = ResolveAddressStream::Done(future.await.map(|v| v.into_iter()));
+        }
+    }
+}
+
+impl ip_name_lookup::Host for WasiCtx {
+    fn resolve_addresses(
+        &mut self,
+        network: Resource<Network>,
+        name: String,
+    ) -> wasmtime::Result<Result<Resource<ResolveAddressStream>, ErrorCode>> {
+        if !self.resource_table.get(&network)?.allow_ip_name_lookup {
+            return Ok(Err(ErrorCode::PermanentResolverFailure));
+        }
+        let host = match parse(&name) {
+            Ok(host) => host,
+            Err(err) => return Ok(Err(err)),
+        };
+        let task = spawn(async move {
+            let addresses = match host {
+                url::Host::Ipv4(address) => vec![IpAddr::V4(address)],
+                url::Host::Ipv6(address) => vec![IpAddr::V6(address)],
+                url::Host::Domain(name) => lookup_host(&name).await?,
+            };
+            Ok(addresses.into_iter().map(to_ip_address).collect())
+        });
+        let stream = self
+            .resource_table
+            .push(ResolveAddressStream::Waiting(task))?;
+        Ok(Ok(stream))
+    }
+}
+
+impl ip_name_lookup::HostResolveAddressStream for WasiCtx {
+    fn resolve_next_address(
+        &mut self,
+        stream: Resource<ResolveAddressStream>,
+    ) -> wasmtime::Result<Result<Option<IpAddress>, ErrorCode>> {
+        let stream = self.resource_table.get_mut(&stream)?;
+        loop {
+            match stream {
+                ResolveAddressStream::Waiting(future) => match poll_noop(Pin::new(future)) {
+                    Some(result) => {
+                        *stream = ResolveAddressStream::Done(result.map(|v| v.into_iter()));
+                    }
+                    None => return Ok(Err(ErrorCode::WouldBlock)),
+                },
+                // the error is only reported once, the stream is then exhausted
+                ResolveAddressStream::Done(result @ Err(_)) => {
+                    let result = mem::replace(result, Ok(vec![].into_iter()));
+                    return Ok(result.map(|_| None));
+                }
+                ResolveAddressStream::Done(Ok(addresses)) => return Ok(Ok(addresses.next())),
+            }
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        stream: Resource<ResolveAddressStream>,
+    ) -> wasmtime::Result<Resource<Pollable>> {
+        subscribe(&mut self.resource_table, stream)
+    }
+
+    fn drop(&mut self, stream: Resource<ResolveAddressStream>) -> wasmtime::Result<()> {
+        self.resource_table.delete(stream)?;
+        Ok(())
+    }
+}
+
+// Validates a domain name or an IP address, and converts unicode domains to punycode, as
+// wasmtime does.
+fn parse(name: &str) -> Result<url::Host, ErrorCode> {
+    match url::Host::parse(name) {
+        Ok(host) => Ok(host),
+        // bare IPv6 addresses are only valid between brackets in a URL
+        Err(_) => match Ipv6Addr::from_str(name) {
+            Ok(address) => Ok(url::Host::Ipv6(address)),
+            Err(_) => Err(ErrorCode::InvalidArgument),
+        },
+    }
+}
+
+fn to_ip_address(address: IpAddr) -> IpAddress {
+    // IPv4-mapped addresses are returned as IPv4 addresses, as wasmtime does
+    let address = match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+        address => address,
+    };
+    match address {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            IpAddress::Ipv4((a, b, c, d))
+        }
+        IpAddr::V6(v6) => {
+            let [a, b, c, d, e, f, g, h] = v6.segments();
+            IpAddress::Ipv6((a, b, c, d, e, f, g, h))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POD_RESOLV_CONF: &str = "\
+search default.svc.cluster.local svc.cluster.local cluster.local
+nameserver 10.96.0.10
+options ndots:5 timeout:1 attempts:3 # comment
+";
+
+    #[test]
+    fn test_parse_resolv_conf() -> Result<()> {
+        let conf = ResolvConf::parse(POD_RESOLV_CONF);
+        assert_eq!(conf.nameservers, vec!["10.96.0.10:53".parse()?]);
+        assert_eq!(
+            conf.search,
+            vec![
+                "default.svc.cluster.local",
+                "svc.cluster.local",
+                "cluster.local"
+            ]
+        );
+        assert_eq!(conf.ndots, 5);
+        assert_eq!(conf.timeout, Duration::from_secs(1));
+        assert_eq!(conf.attempts, 3);
+
+        let conf = ResolvConf::parse("search example.com\ndomain corp.example.\n");
+        assert_eq!(conf.search, vec!["corp.example"]);
+        assert_eq!(conf.nameservers, ResolvConf::default().nameservers);
+        Ok(())
+    }
+
+    #[test]
+    fn test_candidates() {
+        let conf = ResolvConf::parse(POD_RESOLV_CONF);
+        assert_eq!(
+            conf.candidates("my-service"),
+            vec![
+                "my-service.default.svc.cluster.local",
+                "my-service.svc.cluster.local",
+                "my-service.cluster.local",
+                "my-service",
+            ]
+        );
+        assert_eq!(conf.candidates("example.com."), vec!["example.com"]);
+
+        let conf = ResolvConf::parse("search svc.cluster.local\noptions ndots:1\n");
+        assert_eq!(
+            conf.candidates("example.com"),
+            vec!["example.com", "example.com.svc.cluster.local"]
+        );
+    }
+
+    #[test]
+    fn test_lookup_hosts() {
+        let hosts = "127.0.0.1 localhost\n10.244.0.5\tmy-pod my-pod.default # comment\n";
+        assert_eq!(
+            lookup_hosts(hosts, "my-pod"),
+            vec![IpAddr::from([10, 244, 0, 5])]
+        );
+        assert!(lookup_hosts(hosts, "comment").is_empty());
+    }
+
+    #[test]
+    fn test_query_response() -> Result<()> {
+        let query = encode_query(0x1234, "my-service.default", TYPE_A)?;
+        assert_eq!(
+            &query[12..],
+            b"\x0amy-service\x07default\x00\x00\x01\x00\x01"
+        );
+        assert!(encode_query(1, "invalid..name", TYPE_A).is_err());
+
+        // a response with the question, a CNAME and an A record pointing to the question
+        let mut response = query.clone();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        response.extend_from_slice(b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x00\x3c\x00\x02\xc0\x0c");
+        response
+            .extend_from_slice(b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\x0a\x60\x00\x01");
+        assert_eq!(
+            parse_response(&query, &response),
+            Some(Response {
+                rcode: RCODE_NOERROR,
+                truncated: false,
+                addresses: vec![IpAddr::from([10, 96, 0, 1])],
+            })
+        );
+
+        // a response to another query
+        response[1] = 0;
+        assert_eq!(parse_response(&query, &response), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        let nameserver = server.local_addr()?;
+        std::thread::spawn(move || {
+            let mut buffer = [0; 512];
+            while let Ok((len, client)) = server.recv_from(&mut buffer) {
+                let mut response = buffer[..len].to_vec();
+                response[2] |= 0x80;
+                let qtype = response[len - 3];
+                let name_end = len - 4;
+                let known = buffer[12..name_end].starts_with(b"\x0amy-service\x07default\x03svc");
+                match (known, qtype as u16) {
+                    (true, TYPE_A) => {
+                        response[7] = 1;
+                        response.extend_from_slice(
+                            b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\x0a\x60\x00\x01",
+                        );
+                    }
+                    (true, _) => {}
+                    (false, _) => response[3] |= RCODE_NXDOMAIN,
+                }
+                let _ = server.send_to(&response, client);
+            }
+        });
+
+        let conf = ResolvConf {
+            nameservers: vec![nameserver],
+            search: vec!["default.svc.cluster.local".into()],
+            ndots: 5,
+            timeout: Duration::from_secs(1),
+            attempts: 1,
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let resolve = |name| runtime.block_on(resolve(&conf, "", name));
+        assert_eq!(resolve("my-service")?, vec![IpAddr::from([10, 96, 0, 1])]);
+        assert!(matches!(
+            resolve("unknown"),
+            Err(ErrorCode::NameUnresolvable)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Failures of the guests, published by the shim as `/tasks/trapped` and `/tasks/oom` events.
+//!
+//! A guest failing after the memory limit of the container refused to grow one of its memories
+//! is reported as out of memory, e.g., a Rust guest aborting on a failed allocation traps with
+//! `unreachable`. Other traps are reported with their code, e.g., `MemoryOutOfBounds`.
+//!
+//! Exits and interruptions by the host, e.g., `proc_exit` or a deadline, are not failures.
+//!
+//! Traps carry the wasm backtrace of the guest, symbolicated with the DWARF debug info of the
+//! module when it has some, and with the names of its name section otherwise.
+
+use anyhow::Result;
+use containerd_shim_wasm::container::{BacktraceFrame, GuestFailure};
+use wasmtime::{FrameInfo, Store, Trap, WasmBacktrace};
+
+use crate::instance::WasiCtx;
+use crate::limits::DeadlineExceeded;
+use crate::shutdown::ShutdownRequested;
+
+// The failure is sent to the shim in a single datagram, and a stack overflow has thousands of
+// frames, so only the innermost ones are kept
+const MAX_BACKTRACE_FRAMES: usize = 64;
+
+/// Adds the failure of the guest to the error of a failed call, for the shim to publish it.
+pub(crate) fn classify<T>(store: &Store<WasiCtx>, status: Result<T>) -> Result<T> {
+    status.map_err(|err| match failure(store, &err) {
+        Some(failure) => err.context(failure),
+        None => err,
+    })
+}
+
+fn failure(store: &Store<WasiCtx>, err: &anyhow::Error) -> Option<GuestFailure> {
+    if err.is::<wasmtime_wasi::I32Exit>()
+        || err.is::<DeadlineExceeded>()
+        || err.is::<ShutdownRequested>()
+    {
+        return None;
+    }
+    let trap = err.downcast_ref::<Trap>();
+    if store.data().limits.memory_exhausted() {
+        let message = match trap {
+            Some(trap) => format!("memory limit reached, then {trap}"),
+            None => format!("memory limit reached, then {err}"),
+        };
+        return Some(GuestFailure::OutOfMemory { message });
+    }
+    trap.map(|trap| GuestFailure::Trap {
+        code: format!("{trap:?}"),
+        message: trap.to_string(),
+        backtrace: backtrace(err),
+    })
+}
+
+fn backtrace(err: &anyhow::Error) -> Vec<BacktraceFrame> {
+    let Some(backtrace) = err.downcast_ref::<WasmBacktrace>() else {
+        return vec![];
+    };
+    backtrace
+        .frames()
+        .iter()
+        .take(MAX_BACKTRACE_FRAMES)
+        .map(frame)
+        .collect()
+}
+
+fn frame(frame: &FrameInfo) -> BacktraceFrame {
+    let symbol = frame.symbols().first();
+    let func_name = symbol
+        .and_then(|symbol| symbol.name())
+        .or_else(|| frame.func_name())
+        .map(demangle);
+    BacktraceFrame {
+        module: frame.module().name().map(str::to_string),
+        func_index: frame.func_index(),
+        func_name,
+        offset: frame.module_offset(),
+        file: symbol.and_then(|symbol| symbol.file()).map(str::to_string),
+        line: symbol.and_then(|symbol| symbol.line()),
+        column: symbol.and_then(|symbol| symbol.column()),
+    }
+}
+
+// Demangles the names of Rust guests, without their hash
+fn demangle(name: &str) -> String {
+    match rustc_demangle::try_demangle(name) {
+        Ok(name) => format!("{name:#}"),
+        Err(_) => name.to_string(),
+    }
+}
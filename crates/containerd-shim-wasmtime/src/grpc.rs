@@ -0,0 +1,204 @@
+//! gRPC calls over `wasi:http/outgoing-handler`.
+//!
+//! gRPC runs over HTTP/2 and returns the status of a call in the trailers of the response, while
+//! wasmtime sends the outgoing requests of components with HTTP/1.1. Requests with a gRPC content
+//! type, `application/grpc` or e.g. `application/grpc+proto`, are instead sent over HTTP/2:
+//!  - with TLS and ALPN for `https` URLs, validating the certificates as [`crate::tls`] does,
+//!  - with prior knowledge, i.e., h2c, for `http` URLs, as gRPC clients do.
+//!
+//! The guest frames the messages of the body, as gRPC libraries do, and the request gets the
+//! `te: trailers` header gRPC servers require. The bodies of the request and the response are
+//! streamed, so that client, server and bidirectional streaming calls work, and the trailers of
+//! the response, with the `grpc-status` of the call, are passed to the guest.
+//! Every call opens its own connection, resolved with the resolv.conf of the container.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use http_body_util::BodyExt;
+use hyper::client::conn::http2::{self, SendRequest};
+use hyper::header::{HeaderValue, CONTENT_TYPE, TE};
+use hyper::{Request, Version};
+use rustls::pki_types::ServerName;
+use rustls::RootCertStore;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use wasmtime_wasi::runtime::{spawn, AbortOnDropJoinHandle};
+use wasmtime_wasi_http::bindings::http::types::{DnsErrorPayload, ErrorCode};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::hyper_request_error;
+use wasmtime_wasi_http::io::TokioIo;
+use wasmtime_wasi_http::types::{
+    HostFutureIncomingResponse, IncomingResponse, OutgoingRequestConfig,
+};
+
+use crate::dns;
+use crate::tls::{self, Pins};
+
+/// Returns whether `request` is a gRPC call, from its content type.
+pub(crate) fn is_grpc<B>(request: &Request<B>) -> bool {
+    let Some(content_type) = request.headers().get(CONTENT_TYPE) else {
+        return false;
+    };
+    let content_type = content_type.to_str().unwrap_or_default();
+    let (media_type, _) = content_type.split_once(';').unwrap_or((content_type, ""));
+    let media_type = media_type.trim().to_ascii_lowercase();
+    media_type == "application/grpc" || media_type.starts_with("application/grpc+")
+}
+
+/// The gRPC client of a container
+#[derive(Clone)]
+pub(crate) struct GrpcClient {
+    tls: TlsConnector,
+}
+
+impl GrpcClient {
+    pub fn new(roots: Arc<RootCertStore>, pins: Pins) -> Result<Self> {
+        let mut config = tls::client_config(roots, pins)?;
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        Ok(Self {
+            tls: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    /// Sends the gRPC `request` in the background.
+    pub fn send_request(
+        &self,
+        request: Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HostFutureIncomingResponse {
+        let client = self.clone();
+        let handle = spawn(async move { Ok(client.send(request, config).await) });
+        HostFutureIncomingResponse::pending(handle)
+    }
+
+    async fn send(
+        &self,
+        mut request: Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> Result<IncomingResponse, ErrorCode> {
+        let host = request
+            .uri()
+            .host()
+            .ok_or(ErrorCode::HttpRequestUriInvalid)?
+            .to_string();
+        let port = request
+            .uri()
+            .port_u16()
+            .unwrap_or(if config.use_tls { 443 } else { 80 });
+        let stream = timeout(config.connect_timeout, connect(&host, port))
+            .await
+            .map_err(|_| ErrorCode::ConnectionTimeout)??;
+
+        let (mut sender, worker) = if config.use_tls {
+            let server_name =
+                ServerName::try_from(host.clone()).map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+            let stream = timeout(
+                config.connect_timeout,
+                self.tls.connect(server_name, stream),
+            )
+            .await
+            .map_err(|_| ErrorCode::ConnectionTimeout)?
+            .map_err(|err| {
+                log::debug!("tls handshake with {host} failed: {err}");
+                ErrorCode::TlsProtocolError
+            })?;
+            handshake(stream, &config).await?
+        } else {
+            handshake(stream, &config).await?
+        };
+
+        // unlike HTTP/1.1, the scheme and the authority of the URI are sent, as pseudo-headers
+        *request.version_mut() = Version::HTTP_2;
+        request
+            .headers_mut()
+            .insert(TE, HeaderValue::from_static("trailers"));
+
+        let response = timeout(config.first_byte_timeout, sender.send_request(request))
+            .await
+            .map_err(|_| ErrorCode::ConnectionReadTimeout)?
+            .map_err(hyper_request_error)?;
+        Ok(IncomingResponse {
+            resp: response.map(|body| body.map_err(hyper_request_error).boxed()),
+            worker: Some(worker),
+            between_bytes_timeout: config.between_bytes_timeout,
+        })
+    }
+}
+
+async fn connect(host: &str, port: u16) -> Result<TcpStream, ErrorCode> {
+    let addresses = dns::lookup_host(host).await.map_err(|err| {
+        log::debug!("failed to resolve {host}: {err:?}");
+        ErrorCode::DnsError(DnsErrorPayload {
+            rcode: Some("address not available".to_string()),
+            info_code: Some(0),
+        })
+    })?;
+    let addresses: Vec<_> = addresses
+        .into_iter()
+        .map(|address| SocketAddr::new(address, port))
+        .collect();
+    TcpStream::connect(addresses.as_slice())
+        .await
+        .map_err(|_| ErrorCode::ConnectionRefused)
+}
+
+// Starts an HTTP/2 connection over `stream`, served in the background until the response is
+// dropped.
+async fn handshake(
+    stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    config: &OutgoingRequestConfig,
+) -> Result<(SendRequest<HyperOutgoingBody>, AbortOnDropJoinHandle<()>), ErrorCode> {
+    let (sender, connection) = timeout(
+        config.connect_timeout,
+        http2::handshake(TokioExecutor, TokioIo::new(stream)),
+    )
+    .await
+    .map_err(|_| ErrorCode::ConnectionTimeout)?
+    .map_err(hyper_request_error)?;
+    let worker = spawn(async move {
+        if let Err(err) = connection.await {
+            log::debug!("grpc connection failed: {err}");
+        }
+    });
+    Ok((sender, worker))
+}
+
+/// Runs the streams of the HTTP/2 connections on the tokio runtime
+#[derive(Clone, Copy)]
+struct TokioExecutor;
+
+impl<F> hyper::rt::Executor<F> for TokioExecutor
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn execute(&self, future: F) {
+        tokio::task::spawn(future);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_grpc() {
+        let request = |content_type: &str| {
+            Request::builder()
+                .header(CONTENT_TYPE, content_type)
+                .body(())
+                .unwrap()
+        };
+        assert!(is_grpc(&request("application/grpc")));
+        assert!(is_grpc(&request("application/grpc+proto")));
+        assert!(is_grpc(&request("Application/gRPC; charset=utf-8")));
+        assert!(!is_grpc(&request("application/grpc-web")));
+        assert!(!is_grpc(&request("application/json")));
+        assert!(!is_grpc(&Request::new(())));
+    }
+}
@@ -0,0 +1,194 @@
+//! Egress policy for outbound `wasi:http/outgoing-handler` requests.
+//!
+//! The policy is configured per container with annotations holding
+//! comma separated lists of host patterns:
+//!  - `runwasi.io/wasi-http-allowed-hosts`: if set, only matching hosts can be reached.
+//!  - `runwasi.io/wasi-http-denied-hosts`: matching hosts can never be reached.
+//!
+//! A pattern is either `*`, a host name (`example.com`), or a wildcard for
+//! its subdomains (`*.example.com`), optionally followed by a port (`example.com:8080`).
+//! IPv6 addresses are written in brackets when followed by a port (`[2001:db8::1]:8080`).
+//! Hosts are matched case insensitively, without the trailing dot of a fully qualified name.
+
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+
+use anyhow::{bail, Result};
+
+/// Annotation with the hosts outbound requests are allowed to
+pub const ALLOWED_HOSTS_ANNOTATION: &str = "runwasi.io/wasi-http-allowed-hosts";
+
+/// Annotation with the hosts outbound requests are denied to
+pub const DENIED_HOSTS_ANNOTATION: &str = "runwasi.io/wasi-http-denied-hosts";
+
+#[derive(Clone, Debug, PartialEq)]
+struct HostPattern {
+    host: String,
+    port: Option<u16>,
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim();
+        let (host, port) = match pattern.rsplit_once(':') {
+            // a bare IPv6 address, without a port
+            _ if pattern.parse::<Ipv6Addr>().is_ok() => (pattern, None),
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                match port.parse() {
+                    Ok(port) => (host, Some(port)),
+                    Err(_) => bail!("invalid port in host pattern {pattern:?}"),
+                }
+            }
+            _ => (pattern, None),
+        };
+        let host = normalize_host(host);
+        if host.is_empty() {
+            bail!("empty host pattern");
+        }
+        Ok(Self { host, port })
+    }
+
+    fn matches(&self, host: &str, port: u16) -> bool {
+        if self.port.is_some_and(|p| p != port) {
+            return false;
+        }
+        match self.host.strip_prefix('*') {
+            Some("") => true,
+            Some(suffix) if suffix.starts_with('.') => host.ends_with(suffix),
+            _ => self.host == host,
+        }
+    }
+}
+
+/// Returns the form hosts are compared in: lowercase, without the trailing dot of a fully
+/// qualified name, and IPv6 addresses without their brackets, in their canonical form.
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let unbracketed = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    if let Ok(address) = unbracketed.parse::<Ipv6Addr>() {
+        return address.to_string();
+    }
+    host.strip_suffix('.').unwrap_or(host).to_lowercase()
+}
+
+/// Allow and deny lists applied to every outbound HTTP request of a container
+#[derive(Clone, Debug, Default)]
+pub struct HttpEgressPolicy {
+    allowed: Option<Vec<HostPattern>>,
+    denied: Vec<HostPattern>,
+}
+
+fn parse_patterns(list: &str) -> Result<Vec<HostPattern>> {
+    list.split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(HostPattern::parse)
+        .collect()
+}
+
+impl HttpEgressPolicy {
+    /// Builds the policy from the container annotations.
+    /// Without annotations every host is allowed.
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let allowed = annotations
+            .get(ALLOWED_HOSTS_ANNOTATION)
+            .map(|list| parse_patterns(list))
+            .transpose()?;
+        let denied = annotations
+            .get(DENIED_HOSTS_ANNOTATION)
+            .map(|list| parse_patterns(list))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Self { allowed, denied })
+    }
+
+    /// A policy denying every request, for the containers denied the `http` capability.
+    pub fn deny_all() -> Self {
+        Self {
+            allowed: Some(vec![]),
+            denied: vec![],
+        }
+    }
+
+    /// Returns true if a request to `host:port` is allowed.
+    pub fn is_allowed(&self, host: &str, port: u16) -> bool {
+        let host = normalize_host(host);
+        if self.denied.iter().any(|p| p.matches(&host, port)) {
+            return false;
+        }
+        match &self.allowed {
+            Some(allowed) => allowed.iter().any(|p| p.matches(&host, port)),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allowed: Option<&str>, denied: Option<&str>) -> HttpEgressPolicy {
+        let mut annotations = HashMap::new();
+        if let Some(allowed) = allowed {
+            annotations.insert(ALLOWED_HOSTS_ANNOTATION.to_string(), allowed.to_string());
+        }
+        if let Some(denied) = denied {
+            annotations.insert(DENIED_HOSTS_ANNOTATION.to_string(), denied.to_string());
+        }
+        HttpEgressPolicy::from_annotations(&annotations).unwrap()
+    }
+
+    #[test]
+    fn test_default_allows_all() {
+        let policy = policy(None, None);
+        assert!(policy.is_allowed("example.com", 443));
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let policy = policy(Some("example.com, *.wasm.dev:8080"), None);
+        assert!(policy.is_allowed("example.com", 443));
+        assert!(policy.is_allowed("EXAMPLE.com", 80));
+        assert!(policy.is_allowed("api.wasm.dev", 8080));
+        assert!(!policy.is_allowed("api.wasm.dev", 443));
+        assert!(!policy.is_allowed("wasm.dev", 8080));
+        assert!(!policy.is_allowed("api.example.com", 443));
+    }
+
+    #[test]
+    fn test_deny_list_wins() {
+        let policy = policy(Some("*"), Some("169.254.169.254,*.internal"));
+        assert!(policy.is_allowed("example.com", 443));
+        assert!(!policy.is_allowed("169.254.169.254", 80));
+        assert!(!policy.is_allowed("metadata.internal", 80));
+    }
+
+    #[test]
+    fn test_normalized_hosts() {
+        let allowed = policy(Some("Example.com., [2001:DB8::1]:8080, ::1"), None);
+        assert!(allowed.is_allowed("example.com", 443));
+        assert!(allowed.is_allowed("EXAMPLE.COM.", 443));
+        assert!(allowed.is_allowed("[2001:db8:0::1]", 8080));
+        assert!(!allowed.is_allowed("[2001:db8::1]", 443));
+        assert!(allowed.is_allowed("[::1]", 80));
+        assert!(allowed.is_allowed("0:0:0:0:0:0:0:1", 80));
+
+        let denied = policy(None, Some("*.internal"));
+        assert!(!denied.is_allowed("metadata.INTERNAL.", 80));
+
+        assert_eq!(normalize_host(" Example.COM. "), "example.com");
+        assert_eq!(normalize_host("[2001:DB8::0:1]"), "2001:db8::1");
+        assert_eq!(normalize_host("127.0.0.1"), "127.0.0.1");
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let annotations = HashMap::from([(
+            ALLOWED_HOSTS_ANNOTATION.to_string(),
+            "example.com:http".to_string(),
+        )]);
+        assert!(HttpEgressPolicy::from_annotations(&annotations).is_err());
+    }
+}
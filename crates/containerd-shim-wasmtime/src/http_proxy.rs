@@ -0,0 +1,214 @@
+//! Serving of components targeting the `wasi:http/proxy` world.
+//!
+//! When a component exports `wasi:http/incoming-handler`, the shim binds a
+//! TCP listener inside the container network namespace and dispatches every
+//! incoming request into a fresh instance of the component, or into the next ready instance of
+//! a pool with the `runwasi.io/concurrency` annotation, see [`crate::pool`].
+//! The listener binds the ports of the `runwasi.io/ports` annotation, if any.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use http_body_util::BodyExt;
+use hyper::server::conn::http1;
+use wasmtime::component::{Component, InstancePre};
+use wasmtime::Engine;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::io::TokioIo;
+use wasmtime_wasi_http::proxy::Proxy;
+use wasmtime_wasi_http::{hyper_request_error, WasiHttpView};
+
+use crate::instance::WasiCtx;
+use crate::pool::{InstancePool, PoolOptions};
+use crate::{limits, port_bridge};
+
+/// The interface a component has to export to be served over HTTP
+const INCOMING_HANDLER_INTERFACE: &str = "wasi:http/incoming-handler@0.2.0";
+
+/// Annotation used to configure the address the HTTP listener binds to, e.g. `0.0.0.0:8080`
+pub const HTTP_ADDRESS_ANNOTATION: &str = "runwasi.io/wasi-http-address";
+
+/// Environment variable used to configure the address the HTTP listener binds to,
+/// when the annotation is not set
+pub const HTTP_ADDRESS_ENV: &str = "WASI_HTTP_ADDRESS";
+
+const DEFAULT_HTTP_ADDRESS: &str = "0.0.0.0:8080";
+
+/// Returns true if the component exports `wasi:http/incoming-handler`
+pub(crate) fn is_http_proxy(engine: &Engine, component: &Component) -> bool {
+    component
+        .component_type()
+        .exports(engine)
+        .any(|(name, _)| name == INCOMING_HANDLER_INTERFACE)
+}
+
+/// Returns the address to listen on, looking first at the container annotations and then
+/// at its environment.
+pub(crate) fn listen_address(
+    annotations: &HashMap<String, String>,
+    envs: &[(String, String)],
+) -> Result<SocketAddr> {
+    let address = annotations
+        .get(HTTP_ADDRESS_ANNOTATION)
+        .or_else(|| {
+            envs.iter()
+                .find(|(k, _)| k == HTTP_ADDRESS_ENV)
+                .map(|(_, v)| v)
+        })
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_HTTP_ADDRESS);
+
+    address
+        .parse()
+        .with_context(|| format!("invalid wasi:http listen address {address:?}"))
+}
+
+/// Returns the addresses to listen on, the ports of the `runwasi.io/ports` annotation if there
+/// is one, or else the listen address.
+pub(crate) fn listen_addresses(
+    annotations: &HashMap<String, String>,
+    envs: &[(String, String)],
+) -> Result<Vec<SocketAddr>> {
+    match port_bridge::ports(annotations)? {
+        Some(ports) => Ok(port_bridge::addresses(&ports)),
+        None => Ok(vec![listen_address(annotations, envs)?]),
+    }
+}
+
+struct ProxyHandler<F> {
+    engine: Engine,
+    pre: InstancePre<WasiCtx>,
+    new_ctx: Arc<F>,
+    pool: Option<InstancePool>,
+}
+
+impl<F> ProxyHandler<F>
+where
+    F: Fn() -> Result<WasiCtx> + Send + Sync + 'static,
+{
+    async fn handle(
+        &self,
+        req: hyper::Request<hyper::body::Incoming>,
+    ) -> Result<hyper::Response<HyperOutgoingBody>> {
+        let req = req.map(|body| body.map_err(hyper_request_error).boxed());
+        if let Some(pool) = &self.pool {
+            return Ok(pool.handle(req).await??);
+        }
+
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
+        let mut store = limits::new_store(&self.engine, (self.new_ctx)()?);
+        let req = store.data_mut().new_incoming_request(req)?;
+        let out = store.data_mut().new_response_outparam(sender)?;
+        let (proxy, _instance) = Proxy::instantiate_pre(&mut store, &self.pre).await?;
+
+        let task = tokio::task::spawn(async move {
+            proxy
+                .wasi_http_incoming_handler()
+                .call_handle(&mut store, req, out)
+                .await
+        });
+
+        match receiver.await {
+            Ok(Ok(resp)) => Ok(resp),
+            Ok(Err(err)) => Err(err.into()),
+            Err(_) => {
+                let err = match task.await {
+                    Ok(res) => res.err(),
+                    Err(err) => Some(err.into()),
+                };
+                bail!("component never invoked `response-outparam::set`: {err:?}")
+            }
+        }
+    }
+}
+
+/// Serve the component on `addresses` until a listener fails.
+/// `new_ctx` is called to build the WASI context of every request, or of every instance of the
+/// pool with `pool`.
+pub(crate) async fn serve(
+    engine: Engine,
+    pre: InstancePre<WasiCtx>,
+    addresses: &[SocketAddr],
+    max_connections: usize,
+    pool: Option<PoolOptions>,
+    new_ctx: impl Fn() -> Result<WasiCtx> + Send + Sync + 'static,
+) -> Result<()> {
+    log::info!("serving wasi:http requests");
+
+    let new_ctx = Arc::new(new_ctx);
+    let pool = match pool {
+        Some(options) => {
+            let pool = InstancePool::new(engine.clone(), pre.clone(), options, new_ctx.clone());
+            Some(pool.await?)
+        }
+        None => None,
+    };
+    let handler = Arc::new(ProxyHandler {
+        engine,
+        pre,
+        new_ctx,
+        pool,
+    });
+
+    port_bridge::serve(addresses, max_connections, move |stream| {
+        let handler = handler.clone();
+        async move {
+            let service = hyper::service::service_fn(move |req| {
+                let handler = handler.clone();
+                async move { handler.handle(req).await }
+            });
+            if let Err(err) = http1::Builder::new()
+                .keep_alive(true)
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::error!("error serving wasi:http connection: {err:?}");
+            }
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_address() -> Result<()> {
+        let annotations = HashMap::from([(
+            HTTP_ADDRESS_ANNOTATION.to_string(),
+            "127.0.0.1:3000".to_string(),
+        )]);
+        let envs = vec![(HTTP_ADDRESS_ENV.to_string(), "127.0.0.1:4000".to_string())];
+
+        assert_eq!(
+            listen_address(&annotations, &envs)?,
+            "127.0.0.1:3000".parse()?
+        );
+        assert_eq!(
+            listen_address(&HashMap::new(), &envs)?,
+            "127.0.0.1:4000".parse()?
+        );
+        assert_eq!(
+            listen_address(&HashMap::new(), &[])?,
+            DEFAULT_HTTP_ADDRESS.parse()?
+        );
+
+        let envs = vec![(HTTP_ADDRESS_ENV.to_string(), "not an address".to_string())];
+        assert!(listen_address(&HashMap::new(), &envs).is_err());
+
+        let annotations = HashMap::from([(
+            port_bridge::PORTS_ANNOTATION.to_string(),
+            "80,8080".to_string(),
+        )]);
+        assert_eq!(
+            listen_addresses(&annotations, &envs)?,
+            vec!["0.0.0.0:80".parse()?, "0.0.0.0:8080".parse()?]
+        );
+
+        Ok(())
+    }
+}
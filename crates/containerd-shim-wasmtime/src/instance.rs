@@ -1,22 +1,59 @@
+use std::any::TypeId;
+use std::borrow::Cow;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
 use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use containerd_shim_wasm::container::{
-    Engine, Entrypoint, Instance, RuntimeContext, Stdio, WasmBinaryType,
+    Engine, Entrypoint, ErrorCode as TaskErrorCode, Instance, PrecompileTarget, RuntimeContext,
+    Source, Stdio, Volume, WasmBinaryType, WASI_THREADS_ANNOTATION,
 };
 use containerd_shim_wasm::sandbox::WasmLayer;
 use wasmtime::component::{self as wasmtime_component, Component, ResourceTable};
-use wasmtime::{Config, Module, Precompiled, Store};
+use wasmtime::{Config, Func, Module, Precompiled, Store, Val, WasmBacktraceDetails};
 use wasmtime_wasi::preview1::{self as wasi_preview1};
 use wasmtime_wasi::{self as wasi_preview2};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{
+    default_send_request, HostFutureIncomingResponse, OutgoingRequestConfig,
+};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
+
+use crate::adapter::P1Adapter;
+use crate::clocks::VirtualClocks;
+use crate::grpc::{self, GrpcClient};
+use crate::http_egress::HttpEgressPolicy;
+use crate::keyvalue::{self, KeyValueStore, ScopedStore};
+use crate::limits::{self, DeadlineExceeded, ResourceLimits, DEADLINE_EXCEEDED_EXIT_CODE};
+use crate::nn::{self, WasiNnCtx};
+use crate::pool::PoolOptions;
+use crate::pooling::PoolingOptions;
+use crate::port_bridge::{self, Connection};
+use crate::prewarm::{self, Prewarm};
+use crate::quota::{self, FsQuotas, FS_QUOTA_ANNOTATION};
+use crate::record::Recorder;
+use crate::shutdown::{self, ShutdownRequested, SHUTDOWN_EXPORT_ANNOTATION, SIGTERM_EXIT_CODE};
+use crate::socket_policy::SocketPolicy;
+use crate::threads::{self, WasiThreads};
+use crate::tls::{self, Pins, TlsClient};
+use crate::{coredump, debug, dns, failure, http_proxy, linking, params, reactor, scheduler};
 
 pub type WasmtimeInstance = Instance<WasmtimeEngine<DefaultConfig>>;
 
 #[derive(Clone)]
 pub struct WasmtimeEngine<T: WasiConfig> {
-    engine: wasmtime::Engine,
+    pub(crate) engine: wasmtime::Engine,
+    p1_adapter: Option<P1Adapter>,
+    prewarm: Option<Prewarm>,
+    nn_backends: nn::Backends,
+    tls_roots: Arc<rustls::RootCertStore>,
     config_type: PhantomData<T>,
 }
 
@@ -33,16 +70,120 @@ impl WasiConfig for DefaultConfig {
 
 pub trait WasiConfig: Clone + Sync + Send + 'static {
     fn new_config() -> Config;
+
+    /// Register additional host functions for wasm modules, e.g., a custom
+    /// set of imports provided by a downstream shim.
+    /// This is called after the WASI preview 1 functions have been added to the linker.
+    /// The default implementation doesn't add any function.
+    fn add_to_linker(_linker: &mut wasmtime::Linker<WasiCtx>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Register additional component model imports for wasm components, e.g., the
+    /// host implementation of a custom WIT world generated with `wasmtime::component::bindgen!`.
+    /// This is called after the WASI preview 2 and `wasi:http` interfaces have been added to the linker.
+    /// The default implementation doesn't add any import.
+    fn add_to_component_linker(_linker: &mut wasmtime_component::Linker<WasiCtx>) -> Result<()> {
+        Ok(())
+    }
+
+    /// The wasi-nn backends of the engine, e.g., for ONNX or OpenVINO graphs.
+    /// The `wasi_ephemeral_nn` functions are only added to the linker of modules when there
+    /// is at least one backend. The default implementation provides the backends of the
+    /// `onnx` and `openvino` cargo features.
+    fn nn_backends() -> Vec<Arc<dyn nn::Backend>> {
+        nn::backends()
+    }
+
+    /// The store of the `wasi:keyvalue` interfaces for the `keyvalue` runtime option of the
+    /// shim, e.g., `memory` or `redis://<host>`. The interfaces are only added to the linker of
+    /// the components of the containers with a store.
+    /// The default implementation supports the stores of the `keyvalue` module, and opens each
+    /// store once per process.
+    fn keyvalue_store(config: &str) -> Result<Arc<dyn KeyValueStore>> {
+        keyvalue::open(config)
+    }
+}
+
+/// Returns the wasmtime engine of `T`, created once per process.
+///
+/// The engine, and the code it compiles, is shared by all the instances of the process, e.g.,
+/// by the containers of a pod. Each instance runs in its own stores, with its own memories,
+/// tables, globals and resource limits, so the instances only share the compiled code.
+fn shared_engine<T: WasiConfig>() -> wasmtime::Engine {
+    static ENGINES: OnceLock<Mutex<HashMap<TypeId, wasmtime::Engine>>> = OnceLock::new();
+    ENGINES
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap()
+        .entry(TypeId::of::<T>())
+        .or_insert_with(new_engine::<T>)
+        .clone()
+}
+
+/// Creates the engine of `T` from the configuration of the shim.
+///
+/// An invalid time slice, pooling allocator or preview 1 adapter configuration is logged and
+/// replaced by its default, rather than failing every container the shim starts.
+fn new_engine<T: WasiConfig>() -> wasmtime::Engine {
+    let mut config = T::new_config();
+    config.async_support(true); // must be on
+    config.epoch_interruption(true); // used to enforce cpu limits
+    config.coredump_on_trap(true); // only written for the containers enabling them
+    config.wasm_threads(true); // shared memories are only linked for the containers enabling them
+    config.wasm_backtrace_details(WasmBacktraceDetails::Enable); // file and line of the traps
+    if debug::enabled() {
+        debug::apply(&mut config);
+    }
+    if let Err(err) = scheduler::init() {
+        log::warn!("invalid time slice configuration, using the default: {err:#}");
+    }
+    engine_with_pooling(config, PoolingOptions::from_env())
+}
+
+/// Creates an engine from `config` with the pooling allocator `pooling` configures, or with the
+/// on-demand allocator if the pooling configuration is invalid.
+fn engine_with_pooling(
+    config: Config,
+    pooling: Result<Option<PoolingOptions>>,
+) -> wasmtime::Engine {
+    let pooled = pooling.and_then(|pooling| {
+        let Some(pooling) = pooling else {
+            return Ok(None);
+        };
+        let mut config = config.clone();
+        pooling.apply(&mut config)?;
+        Ok(Some(wasmtime::Engine::new(&config)?))
+    });
+    match pooled {
+        Ok(Some(engine)) => return engine,
+        Ok(None) => {}
+        Err(err) => log::warn!(
+            "invalid pooling allocator configuration, using the on-demand allocator: {err:#}"
+        ),
+    }
+    // the remaining settings are fixed, so this only fails on hosts wasmtime doesn't support
+    wasmtime::Engine::new(&config)
+        .context("failed to create wasmtime engine")
+        .unwrap()
+}
+
+/// Returns the preview 1 adapter `adapter` loaded, or no adapter if it failed to load.
+fn p1_adapter_or_default(adapter: Result<Option<P1Adapter>>) -> Option<P1Adapter> {
+    adapter.unwrap_or_else(|err| {
+        log::warn!("invalid preview 1 adapter configuration, not using an adapter: {err:#}");
+        None
+    })
 }
 
 impl<T: WasiConfig> Default for WasmtimeEngine<T> {
     fn default() -> Self {
-        let mut config = T::new_config();
-        config.async_support(true); // must be on
         Self {
-            engine: wasmtime::Engine::new(&config)
-                .context("failed to create wasmtime engine")
-                .unwrap(),
+            engine: shared_engine::<T>(),
+            p1_adapter: p1_adapter_or_default(P1Adapter::from_env()),
+            prewarm: Prewarm::from_env(),
+            nn_backends: T::nn_backends().into(),
+            tls_roots: tls::node_roots(),
             config_type: PhantomData,
         }
     }
@@ -52,6 +193,18 @@ impl<T: WasiConfig> Default for WasmtimeEngine<T> {
 pub struct WasiCtx {
     pub(crate) wasi_preview2: wasi_preview2::WasiCtx,
     pub(crate) wasi_preview1: wasi_preview1::WasiP1Ctx,
+    pub(crate) wasi_http: WasiHttpCtx,
+    pub(crate) http_egress: HttpEgressPolicy,
+    pub(crate) limits: ResourceLimits,
+    pub(crate) shutdown_export: Option<String>,
+    pub(crate) nn: WasiNnCtx,
+    pub(crate) keyvalue: Option<ScopedStore>,
+    pub(crate) tls: Option<TlsClient>,
+    pub(crate) grpc: Option<GrpcClient>,
+    pub(crate) coredump: Option<PathBuf>,
+    pub(crate) wait_for_debugger: bool,
+    pub(crate) wasi_threads: Option<Arc<WasiThreads>>,
+    pub(crate) fs_quotas: FsQuotas,
     pub(crate) resource_table: ResourceTable,
 }
 
@@ -66,42 +219,110 @@ impl wasi_preview2::WasiView for WasiCtx {
     }
 }
 
+/// This impl is required to use the wasi:http interfaces.
+impl WasiHttpView for WasiCtx {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        &mut self.wasi_http
+    }
+
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.resource_table
+    }
+
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        let host = request.uri().host().unwrap_or_default();
+        let port = request
+            .uri()
+            .port_u16()
+            .unwrap_or(if config.use_tls { 443 } else { 80 });
+        if !self.http_egress.is_allowed(host, port) {
+            log::info!("outbound request to {host}:{port} denied by egress policy");
+            return Err(ErrorCode::HttpRequestDenied.into());
+        }
+        match &self.grpc {
+            Some(grpc) if grpc::is_grpc(&request) => Ok(grpc.send_request(request, config)),
+            _ => Ok(default_send_request(request, config)),
+        }
+    }
+}
+
 impl<T: WasiConfig> Engine for WasmtimeEngine<T> {
     fn name() -> &'static str {
         "wasmtime"
     }
 
     fn run_wasi(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
+        // the instances of the container share the workers of the runtime, see the `scheduler` module
+        let runtime = scheduler::runtime(ctx.threads())?;
+        let _guard = runtime.enter();
+
         log::info!("setting up wasi");
         let envs: Vec<_> = std::env::vars().collect();
-        let Entrypoint {
-            source,
-            func,
-            arg0: _,
-            name: _,
-        } = ctx.entrypoint();
+        let entrypoint = ctx.entrypoint();
+        let func_args = entrypoint.func_args().to_vec();
+        let Entrypoint { source, func, .. } = entrypoint;
 
-        log::info!("building wasi context");
-        let wasi_ctx = prepare_wasi_ctx(ctx, envs)?;
-        let store = Store::new(&self.engine, wasi_ctx);
+        let store = self.new_store(ctx, &envs)?;
+        let (source, libraries) = self.split_libraries(source)?;
 
         let wasm_bytes = &source.as_bytes()?;
+        let mapped_path = match &source {
+            Source::Oci([layer]) => layer.layer.mapped_path(),
+            _ => None,
+        };
 
-        let status = self.execute(wasm_bytes, store, func, stdio)?;
+        let invocation = Invocation {
+            ctx,
+            envs: &envs,
+            func,
+            func_args,
+            stdio,
+        };
+        let status = self.execute(
+            invocation,
+            wasm_bytes,
+            mapped_path.as_deref(),
+            libraries,
+            store,
+        );
 
-        let status = status.map(|_| 0).or_else(|err| {
-            match err.downcast_ref::<wasmtime_wasi::I32Exit>() {
-                Some(value) => Ok(value.process_exit_code()),
-                _ => Err(err),
-            }
-        })?;
+        exit_code(status)
+    }
 
-        Ok(status)
+    fn run_reactor(&self, ctx: &impl RuntimeContext, stdio: Stdio) -> Result<i32> {
+        let runtime = scheduler::runtime(ctx.threads())?;
+        let _guard = runtime.enter();
+
+        log::info!("setting up wasi reactor");
+        let envs: Vec<_> = std::env::vars().collect();
+        let store = self.new_store(ctx, &envs)?;
+        let (source, libraries) = self.split_libraries(ctx.entrypoint().source)?;
+
+        let bytes = source.as_bytes()?;
+        let bytes = match libraries.is_empty() {
+            true => self.prewarm(&bytes),
+            false => Cow::Borrowed(bytes.as_ref()),
+        };
+        let module = self
+            .load_module(&bytes)
+            .context("invalid reactor: only modules can run as reactors")?;
+
+        let input = BufReader::new(std::io::stdin());
+        let status = self.execute_reactor(module, libraries, store, stdio, input);
+        exit_code(status)
     }
 
     fn precompile(&self, layers: &[WasmLayer]) -> Result<Vec<Option<Vec<u8>>>> {
         let mut compiled_layers = Vec::<Option<Vec<u8>>>::with_capacity(layers.len());
 
+        // modules linked with libraries are run as modules, see `run_wasi`
+        let linked = layers.iter().any(linking::is_library);
+        let adapter = self.p1_adapter.as_ref().filter(|_| !linked);
+
         for layer in layers {
             if self.engine.detect_precompiled(&layer.layer).is_some() {
                 log::info!("Already precompiled");
@@ -109,7 +330,29 @@ impl<T: WasiConfig> Engine for WasmtimeEngine<T> {
                 continue;
             }
 
-            let compiled_layer = self.engine.precompile_module(&layer.layer)?;
+            let compiled_layer = match WasmBinaryType::from_bytes(&layer.layer) {
+                Some(WasmBinaryType::Module) => {
+                    let module = match linked {
+                        true => Cow::Borrowed(&layer.layer[..]),
+                        false => self.prewarm(&layer.layer),
+                    };
+                    match adapter {
+                        Some(adapter) => {
+                            let component = adapter.componentize(&module)?;
+                            self.engine.precompile_component(&component)?
+                        }
+                        _ => self.engine.precompile_module(&module)?,
+                    }
+                }
+                Some(WasmBinaryType::Component) => {
+                    self.engine.precompile_component(&layer.layer)?
+                }
+                None => {
+                    log::warn!("layer contains invalid wasm binary");
+                    compiled_layers.push(None);
+                    continue;
+                }
+            };
             compiled_layers.push(Some(compiled_layer));
         }
 
@@ -121,8 +364,69 @@ impl<T: WasiConfig> Engine for WasmtimeEngine<T> {
         self.engine
             .precompile_compatibility_hash()
             .hash(&mut hasher);
+        // modules are precompiled as components when there is an adapter
+        if let Some(adapter) = &self.p1_adapter {
+            adapter.as_bytes().hash(&mut hasher);
+        }
+        // and the modules exporting the initialization function are prewarmed
+        if let Some(prewarm) = &self.prewarm {
+            prewarm.export().hash(&mut hasher);
+        }
         Some(hasher.finish().to_string())
     }
+
+    fn precompile_target(&self) -> PrecompileTarget {
+        PrecompileTarget {
+            features: host_features(),
+            ..PrecompileTarget::host(env!("CARGO_PKG_VERSION"))
+        }
+    }
+}
+
+/// The features of the CPU wasmtime compiles for, the ones detected by cranelift on the host
+fn host_features() -> Vec<String> {
+    #[allow(unused_mut)]
+    let mut features: Vec<&str> = vec![];
+    #[cfg(target_arch = "x86_64")]
+    {
+        macro_rules! detect {
+            ($($feature:tt),*) => {
+                $(if std::is_x86_feature_detected!($feature) {
+                    features.push($feature);
+                })*
+            };
+        }
+        detect!(
+            "sse3",
+            "ssse3",
+            "sse4.1",
+            "sse4.2",
+            "popcnt",
+            "avx",
+            "avx2",
+            "fma",
+            "bmi1",
+            "bmi2",
+            "lzcnt",
+            "avx512bitalg",
+            "avx512dq",
+            "avx512f",
+            "avx512vl",
+            "avx512vbmi"
+        );
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        macro_rules! detect {
+            ($($feature:tt),*) => {
+                $(if std::arch::is_aarch64_feature_detected!($feature) {
+                    features.push($feature);
+                })*
+            };
+        }
+        detect!("lse", "paca", "fp16");
+    }
+    features.into_iter().map(str::to_string).collect()
 }
 
 impl<T: std::clone::Clone + Sync + WasiConfig + Send + 'static> WasmtimeEngine<T> {
@@ -130,38 +434,135 @@ impl<T: std::clone::Clone + Sync + WasiConfig + Send + 'static> WasmtimeEngine<T
     ///
     /// This function adds wasi_preview1 to the linker and can be utilized
     /// to execute a wasm module that uses wasi_preview1.
+    /// The `libraries` are instantiated first, and the exports of each one are
+    /// linked under its name.
+    /// The start function `func` is called with `func_args` as its parameters.
     fn execute_module(
         &self,
         module: Module,
+        libraries: Vec<(String, Module)>,
         mut store: Store<WasiCtx>,
         func: &String,
+        func_args: &[String],
         stdio: Stdio,
     ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
         log::debug!("execute module");
 
-        let mut module_linker = wasmtime::Linker::new(&self.engine);
+        let mut module_linker = self.module_linker()?;
+        quota::link(&mut module_linker, &store)?;
+        threads::link(&mut module_linker, &mut store, &module, &libraries)?;
 
-        log::debug!("init linker");
-        wasi_preview1::add_to_linker_async(&mut module_linker, |s: &mut WasiCtx| {
-            &mut s.wasi_preview1
-        })?;
+        if store.data().shutdown_export.is_some() {
+            shutdown::install_handler().context("failed to set SIGTERM handler")?;
+        }
 
         wasmtime_wasi::runtime::in_tokio(async move {
-            log::info!("instantiating instance");
-            let instance: wasmtime::Instance =
-                module_linker.instantiate_async(&mut store, &module).await?;
+            let instance =
+                instantiate_module(&mut module_linker, &mut store, &module, libraries).await?;
 
             log::info!("getting start function");
             let start_func = instance
                 .get_func(&mut store, func)
                 .context("module does not have a WASI start function")?;
+            let ty = start_func.ty(&store);
+            let params = params::parse_params(&ty, func_args)
+                .with_context(|| format!("invalid arguments for {func:?}"))?;
+            let mut results = vec![Val::I32(0); ty.results().len()];
 
             log::debug!("running start function {func:?}");
 
+            debug::wait(store.data())?;
             stdio.redirect()?;
 
-            let status = start_func.call_async(&mut store, &[], &mut []).await;
-            Ok(status)
+            let status =
+                call_or_shutdown(&mut store, &instance, start_func, &params, &mut results).await;
+            coredump::write_on_trap(&mut store, module.name().unwrap_or("main"), &status);
+            Ok(failure::classify(&store, status))
+        })
+    }
+
+    /// Execute a wasm module as a reactor.
+    ///
+    /// The module is initialized, and its exports are then invoked from `input`,
+    /// the stdin of the container, until it is killed, see the `reactor` module.
+    fn execute_reactor(
+        &self,
+        module: Module,
+        libraries: Vec<(String, Module)>,
+        mut store: Store<WasiCtx>,
+        stdio: Stdio,
+        input: impl BufRead + Send + 'static,
+    ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
+        log::debug!("execute reactor");
+
+        let mut module_linker = self.module_linker()?;
+        quota::link(&mut module_linker, &store)?;
+        threads::link(&mut module_linker, &mut store, &module, &libraries)?;
+
+        // the reactor waits for a SIGTERM even without a shutdown export
+        shutdown::install_handler().context("failed to set SIGTERM handler")?;
+
+        wasmtime_wasi::runtime::in_tokio(async move {
+            let instance =
+                instantiate_module(&mut module_linker, &mut store, &module, libraries).await?;
+
+            debug::wait(store.data())?;
+
+            stdio.redirect()?;
+
+            let init = reactor::INIT_EXPORTS
+                .iter()
+                .find_map(|name| instance.get_func(&mut store, name));
+            if let Some(init) = init {
+                log::info!("initializing reactor");
+                if let Err(err) = call_or_shutdown(&mut store, &instance, init, &[], &mut []).await
+                {
+                    return Ok(failure::classify(&store, Err(err)));
+                }
+            }
+
+            log::info!("servicing invocations");
+            let invocations = reactor::Invocations::spawn(input);
+            loop {
+                if shutdown::take_request() {
+                    log::info!("stopping reactor: shutdown requested");
+                    return Ok(
+                        shutdown_instance(&mut store, &instance, ShutdownRequested.into()).await,
+                    );
+                }
+
+                let Some(invocation) = invocations.next(reactor::POLL_INTERVAL) else {
+                    continue;
+                };
+                let Some(func) = instance.get_func(&mut store, &invocation.func) else {
+                    log::warn!("reactor does not export function {:?}", invocation.func);
+                    continue;
+                };
+                let ty = func.ty(&store);
+                let params = match params::parse_params(&ty, &invocation.args) {
+                    Ok(params) => params,
+                    Err(err) => {
+                        log::warn!("invalid invocation of {:?}: {err}", invocation.func);
+                        continue;
+                    }
+                };
+                let mut results = vec![Val::I32(0); ty.results().len()];
+
+                log::debug!("invoking function {:?}", invocation.func);
+                if let Err(err) =
+                    call_or_shutdown(&mut store, &instance, func, &params, &mut results).await
+                {
+                    return Ok(failure::classify(&store, Err(err)));
+                }
+                // the reader of the results may be gone, e.g., with a broken pipe
+                let results = params::format_results(&results);
+                if let Err(err) = writeln!(std::io::stdout().lock(), "{results}") {
+                    log::warn!(
+                        "failed to write the results of {:?}: {err}",
+                        invocation.func
+                    );
+                }
+            }
         })
     }
 
@@ -174,15 +575,16 @@ impl<T: std::clone::Clone + Sync + WasiConfig + Send + 'static> WasmtimeEngine<T
         component: Component,
         mut store: Store<WasiCtx>,
         func: String,
+        func_args: Vec<String>,
         stdio: Stdio,
     ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
-        log::debug!("loading wasm component");
+        log::debug!("execute component");
 
-        let mut linker = wasmtime_component::Linker::new(&self.engine);
+        if store.data_mut().shutdown_export.take().is_some() {
+            log::warn!("{SHUTDOWN_EXPORT_ANNOTATION} is not supported for components, ignoring it");
+        }
 
-        log::debug!("init linker");
-        wasi_preview2::add_to_linker_async(&mut linker)?;
-        log::debug!("done init linker");
+        let linker = self.component_linker(store.data().keyvalue.is_some())?;
 
         log::info!("instantiating component");
 
@@ -191,25 +593,28 @@ impl<T: std::clone::Clone + Sync + WasiConfig + Send + 'static> WasmtimeEngine<T
         // TODO: think about a better way to do this.
         wasmtime_wasi::runtime::in_tokio(async move {
             if func == "_start" {
-                let pre = linker.instantiate_pre(&component)?;
+                ensure!(func_args.is_empty(), COMMAND_ARGS_ERROR);
+                let pre = linker
+                    .instantiate_pre(&component)
+                    .context(TaskErrorCode::UnsupportedImport)?;
                 let (command, _instance) =
                     wasi_preview2::bindings::Command::instantiate_pre(&mut store, &pre).await?;
 
+                debug::wait(store.data())?;
+
                 stdio.redirect()?;
 
-                let status = command
-                    .wasi_cli_run()
-                    .call_run(&mut store)
-                    .await?
-                    .map_err(|_| {
-                        anyhow::anyhow!(
-                            "failed to run component targeting `wasi:cli/command` world"
-                        )
-                    });
+                let status = command.wasi_cli_run().call_run(&mut store).await;
+                coredump::write_on_trap(&mut store, "component", &status);
+                let status = failure::classify(&store, status)?.map_err(|_| {
+                    anyhow::anyhow!("failed to run component targeting `wasi:cli/command` world")
+                });
 
                 Ok(status)
             } else {
-                let pre = linker.instantiate_pre(&component)?;
+                let pre = linker
+                    .instantiate_pre(&component)
+                    .context(TaskErrorCode::UnsupportedImport)?;
 
                 let instance = pre.instantiate_async(&mut store).await?;
 
@@ -217,90 +622,1421 @@ impl<T: std::clone::Clone + Sync + WasiConfig + Send + 'static> WasmtimeEngine<T
                 let start_func = instance.get_func(&mut store, &func).context(format!(
                     "component does not have exported function {func:?}"
                 ))?;
+                let params = params::parse_component_params(&start_func.params(&store), &func_args)
+                    .with_context(|| format!("invalid arguments for {func:?}"))?;
+                let mut results =
+                    vec![wasmtime_component::Val::Bool(false); start_func.results(&store).len()];
 
                 log::debug!("running exported function {func:?} {start_func:?}");
 
+                debug::wait(store.data())?;
+
                 stdio.redirect()?;
 
-                let status = start_func.call_async(&mut store, &[], &mut []).await;
-                Ok(status)
+                let status = start_func
+                    .call_async(&mut store, &params, &mut results)
+                    .await;
+                coredump::write_on_trap(&mut store, "component", &status);
+                Ok(failure::classify(&store, status))
             }
         })
     }
 
+    /// Serve a wasm component targeting the `wasi:http/proxy` world.
+    ///
+    /// Every request is handled by a new instance of the component, with its own store, or by an
+    /// instance of the pool of the container, see the `pool` module.
+    fn serve_component(
+        &self,
+        ctx: &impl RuntimeContext,
+        envs: &[(String, String)],
+        component: Component,
+        stdio: Stdio,
+    ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
+        log::debug!("serve component");
+
+        let addresses = http_proxy::listen_addresses(ctx.annotations(), envs)?;
+        let max_connections = port_bridge::max_connections(ctx.annotations())?;
+
+        let pool = PoolOptions::from_ctx(ctx)?;
+
+        let pre = self
+            .component_linker(ctx.keyvalue().is_some())?
+            .instantiate_pre(&component)?;
+        let new_ctx = self.instance_ctx_factory(ctx, envs)?;
+
+        stdio.redirect()?;
+
+        let engine = self.engine.clone();
+        wasmtime_wasi::runtime::in_tokio(async move {
+            let new_ctx = move || new_ctx(None);
+            let status =
+                http_proxy::serve(engine, pre, &addresses, max_connections, pool, new_ctx).await;
+            Ok(status)
+        })
+    }
+
+    /// Serve the connections to the ports of a wasm component with its function `func`.
+    ///
+    /// Every connection is handled by a new instance of the component, with its own store,
+    /// see the `port_bridge` module.
+    fn bridge_component(
+        &self,
+        invocation: Invocation<'_, impl RuntimeContext>,
+        component: Component,
+        addresses: Vec<SocketAddr>,
+    ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
+        log::debug!("bridge component");
+
+        let Invocation {
+            ctx,
+            envs,
+            func,
+            func_args,
+            stdio,
+        } = invocation;
+        let max_connections = port_bridge::max_connections(ctx.annotations())?;
+
+        let pre = self
+            .component_linker(ctx.keyvalue().is_some())?
+            .instantiate_pre(&component)?;
+        let new_ctx = self.instance_ctx_factory(ctx, envs)?;
+
+        stdio.redirect()?;
+
+        let engine = self.engine.clone();
+        wasmtime_wasi::runtime::in_tokio(async move {
+            let status = port_bridge::serve(&addresses, max_connections, move |stream| {
+                let connection = Connection::new(stream);
+                let store =
+                    new_ctx(Some(&connection)).map(|wasi_ctx| limits::new_store(&engine, wasi_ctx));
+                let (pre, func, func_args) = (pre.clone(), func.clone(), func_args.clone());
+                async move {
+                    let status = match store {
+                        Ok(mut store) => call_component(&mut store, &pre, &func, &func_args).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = status {
+                        log::error!("error handling connection: {err:?}");
+                    }
+                }
+            })
+            .await;
+            Ok(status)
+        })
+    }
+
+    /// Serve the connections to the ports of a wasm module with its function `func`.
+    ///
+    /// Every connection is handled by a new instance of the module, and of its `libraries`,
+    /// with its own store, see the `port_bridge` module.
+    fn bridge_module(
+        &self,
+        invocation: Invocation<'_, impl RuntimeContext>,
+        module: Module,
+        libraries: Vec<(String, Module)>,
+        addresses: Vec<SocketAddr>,
+    ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
+        log::debug!("bridge module");
+
+        let Invocation {
+            ctx,
+            envs,
+            func,
+            func_args,
+            stdio,
+        } = invocation;
+        let max_connections = port_bridge::max_connections(ctx.annotations())?;
+
+        let new_ctx = self.instance_ctx_factory(ctx, envs)?;
+
+        stdio.redirect()?;
+
+        let this = self.clone();
+        wasmtime_wasi::runtime::in_tokio(async move {
+            let status = port_bridge::serve(&addresses, max_connections, move |stream| {
+                let connection = Connection::new(stream);
+                let store = new_ctx(Some(&connection))
+                    .map(|wasi_ctx| limits::new_store(&this.engine, wasi_ctx));
+                let linker = this.module_linker();
+                let (module, libraries) = (module.clone(), libraries.clone());
+                let (func, func_args) = (func.clone(), func_args.clone());
+                async move {
+                    let status = async {
+                        let (mut store, mut linker) = (store?, linker?);
+                        quota::link(&mut linker, &store)?;
+                        let instance =
+                            instantiate_module(&mut linker, &mut store, &module, libraries).await?;
+                        let start_func = instance
+                            .get_func(&mut store, &func)
+                            .with_context(|| format!("module does not export {func:?}"))?;
+                        let ty = start_func.ty(&store);
+                        let params = params::parse_params(&ty, &func_args)
+                            .with_context(|| format!("invalid arguments for {func:?}"))?;
+                        let mut results = vec![Val::I32(0); ty.results().len()];
+                        start_func
+                            .call_async(&mut store, &params, &mut results)
+                            .await
+                    };
+                    if let Err(err) = status.await {
+                        log::error!("error handling connection: {err:?}");
+                    }
+                }
+            })
+            .await;
+            Ok(status)
+        })
+    }
+
+    /// Create the WASI contexts of the instances handling the requests, or the connections, of a
+    /// container, with the policies and limits of its annotations.
+    fn instance_ctx_factory(
+        &self,
+        ctx: &impl RuntimeContext,
+        envs: &[(String, String)],
+    ) -> Result<impl Fn(Option<&Connection>) -> Result<WasiCtx> + Send + Sync + 'static> {
+        let args = ctx.args().to_vec();
+        let envs = envs.to_vec();
+        let preopens = preopens(ctx);
+        // the requests share the log, only the requests handled one at a time replay deterministically
+        let options = WasiCtxOptions::from_ctx(ctx)?;
+        let keyvalue = self.keyvalue_store(ctx)?;
+        let tls = self.tls_client(ctx, &options.sockets)?;
+        let grpc = self.grpc_client(ctx)?;
+        let fs_quotas = FsQuotas::from_annotations(ctx.annotations(), &preopens)?;
+        Ok(move |connection: Option<&Connection>| {
+            let mut wasi_ctx = prepare_wasi_ctx(&args, &envs, &preopens, &options, connection)?;
+            wasi_ctx.keyvalue.clone_from(&keyvalue);
+            wasi_ctx.tls.clone_from(&tls);
+            wasi_ctx.grpc = Some(grpc.clone());
+            wasi_ctx.fs_quotas = fs_quotas.clone();
+            Ok(wasi_ctx)
+        })
+    }
+
+    fn run_component(
+        &self,
+        invocation: Invocation<'_, impl RuntimeContext>,
+        component: Component,
+        mut store: Store<WasiCtx>,
+    ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
+        if store.data_mut().wasi_threads.take().is_some() {
+            log::warn!("{WASI_THREADS_ANNOTATION} is not supported for components, ignoring it");
+        }
+        if !std::mem::take(&mut store.data_mut().fs_quotas).is_empty() {
+            log::warn!("{FS_QUOTA_ANNOTATION} is not supported for components, ignoring it");
+        }
+        let command = invocation.func == "_start";
+        if command && http_proxy::is_http_proxy(&self.engine, &component) {
+            ensure!(invocation.func_args.is_empty(), COMMAND_ARGS_ERROR);
+            let Invocation {
+                ctx, envs, stdio, ..
+            } = invocation;
+            self.serve_component(ctx, envs, component, stdio)
+        } else if let Some(ports) = port_bridge::ports(invocation.ctx.annotations())? {
+            ensure!(
+                !command || invocation.func_args.is_empty(),
+                COMMAND_ARGS_ERROR
+            );
+            let addresses = port_bridge::addresses(&ports);
+            self.bridge_component(invocation, component, addresses)
+        } else {
+            let Invocation {
+                func,
+                func_args,
+                stdio,
+                ..
+            } = invocation;
+            self.execute_component(component, store, func, func_args, stdio)
+        }
+    }
+
+    fn run_module(
+        &self,
+        invocation: Invocation<'_, impl RuntimeContext>,
+        module: Module,
+        libraries: Vec<(String, Module)>,
+        store: Store<WasiCtx>,
+    ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
+        match port_bridge::ports(invocation.ctx.annotations())? {
+            Some(ports) => {
+                let addresses = port_bridge::addresses(&ports);
+                self.bridge_module(invocation, module, libraries, addresses)
+            }
+            None => {
+                let Invocation {
+                    func,
+                    func_args,
+                    stdio,
+                    ..
+                } = invocation;
+                self.execute_module(module, libraries, store, &func, &func_args, stdio)
+            }
+        }
+    }
+
+    /// Execute a wasm binary.
+    ///
+    /// When the binary is a precompiled artifact mapped from the content store,
+    /// `mapped_path` is used to deserialize it straight from the file, so that
+    /// the compiled code is shared with the other containers using the artifact.
     fn execute(
         &self,
+        invocation: Invocation<'_, impl RuntimeContext>,
         wasm_binary: &[u8],
+        mapped_path: Option<&Path>,
+        libraries: Vec<(String, Module)>,
         store: Store<WasiCtx>,
-        func: String,
-        stdio: Stdio,
     ) -> Result<std::prelude::v1::Result<(), anyhow::Error>, anyhow::Error> {
         match WasmBinaryType::from_bytes(wasm_binary) {
-            Some(WasmBinaryType::Module) => {
-                log::debug!("loading wasm module");
-                let module = Module::from_binary(&self.engine, wasm_binary)?;
-                self.execute_module(module, store, &func, stdio)
-            }
+            Some(WasmBinaryType::Module) => match &self.p1_adapter {
+                // modules with wasi-threads or quotas aren't componentized, see the `threads` and
+                // `quota` modules
+                Some(adapter)
+                    if libraries.is_empty()
+                        && store.data().wasi_threads.is_none()
+                        && store.data().fs_quotas.is_empty() =>
+                {
+                    log::debug!("componentizing wasm module");
+                    let component = adapter
+                        .componentize(&self.prewarm(wasm_binary))
+                        .and_then(|component| Component::from_binary(&self.engine, &component))
+                        .context(TaskErrorCode::CompileFailed)?;
+                    self.run_component(invocation, component, store)
+                }
+                _ => {
+                    log::debug!("loading wasm module");
+                    let wasm_binary = match libraries.is_empty() {
+                        true => self.prewarm(wasm_binary),
+                        false => Cow::Borrowed(wasm_binary),
+                    };
+                    let module = Module::from_binary(&self.engine, &wasm_binary)
+                        .context(TaskErrorCode::CompileFailed)?;
+                    self.run_module(invocation, module, libraries, store)
+                }
+            },
             Some(WasmBinaryType::Component) => {
-                let component = Component::from_binary(&self.engine, wasm_binary)?;
-                self.execute_component(component, store, func, stdio)
+                log::debug!("loading wasm component");
+                ensure!(libraries.is_empty(), COMPONENT_LIBRARIES_ERROR);
+                let component = Component::from_binary(&self.engine, wasm_binary)
+                    .context(TaskErrorCode::CompileFailed)?;
+                self.run_component(invocation, component, store)
             }
             None => match &self.engine.detect_precompiled(wasm_binary) {
                 Some(Precompiled::Module) => {
                     log::info!("using precompiled module");
-                    let module = unsafe { Module::deserialize(&self.engine, wasm_binary) }?;
-                    self.execute_module(module, store, &func, stdio)
+                    let module = match mapped_path {
+                        Some(path) => unsafe { Module::deserialize_file(&self.engine, path) }
+                            .or_else(|err| {
+                                log::warn!("failed to load mapped module {path:?}: {err}");
+                                unsafe { Module::deserialize(&self.engine, wasm_binary) }
+                            }),
+                        None => unsafe { Module::deserialize(&self.engine, wasm_binary) },
+                    }
+                    .context(TaskErrorCode::CompileFailed)?;
+                    self.run_module(invocation, module, libraries, store)
                 }
                 Some(Precompiled::Component) => {
                     log::info!("using precompiled component");
-                    let component = unsafe { Component::deserialize(&self.engine, wasm_binary) }?;
-                    self.execute_component(component, store, func, stdio)
-                }
-                None => {
-                    bail!("invalid precompiled module")
+                    ensure!(libraries.is_empty(), COMPONENT_LIBRARIES_ERROR);
+                    let component = match mapped_path {
+                        Some(path) => unsafe { Component::deserialize_file(&self.engine, path) }
+                            .or_else(|err| {
+                                log::warn!("failed to load mapped component {path:?}: {err}");
+                                unsafe { Component::deserialize(&self.engine, wasm_binary) }
+                            }),
+                        None => unsafe { Component::deserialize(&self.engine, wasm_binary) },
+                    }
+                    .context(TaskErrorCode::CompileFailed)?;
+                    self.run_component(invocation, component, store)
                 }
+                None => Err(anyhow::anyhow!(
+                    "not a module, a component or a precompiled artifact"
+                ))
+                .context(TaskErrorCode::InvalidWasm),
             },
         }
     }
 }
 
+impl<T: WasiConfig> WasmtimeEngine<T> {
+    /// Create the store of a container, enforcing the limits and policies of its annotations.
+    fn new_store(
+        &self,
+        ctx: &impl RuntimeContext,
+        envs: &[(String, String)],
+    ) -> Result<Store<WasiCtx>> {
+        log::info!("building wasi context");
+        let options = WasiCtxOptions::from_ctx(ctx)?;
+        let preopens = preopens(ctx);
+        let tls = self.tls_client(ctx, &options.sockets)?;
+        let grpc = self.grpc_client(ctx)?;
+        let mut wasi_ctx = prepare_wasi_ctx(ctx.args(), envs, &preopens, &options, None)?;
+        wasi_ctx.shutdown_export = ctx.annotations().get(SHUTDOWN_EXPORT_ANNOTATION).cloned();
+        if ctx.allows_capability("nn") {
+            wasi_ctx.nn = WasiNnCtx::new(self.nn_backends.clone(), ctx.annotations(), &preopens)?;
+        }
+        wasi_ctx.keyvalue = self.keyvalue_store(ctx)?;
+        wasi_ctx.tls = tls;
+        wasi_ctx.grpc = Some(grpc);
+        wasi_ctx.coredump = ctx.coredump_file();
+        wasi_ctx.wait_for_debugger = debug::wait_for_debugger(ctx.annotations())?;
+        wasi_ctx.fs_quotas = FsQuotas::from_annotations(ctx.annotations(), &preopens)?;
+        if ctx.wasi_threads()? {
+            let new_ctx = self.instance_ctx_factory(ctx, envs)?;
+            let threads = WasiThreads::new(move || new_ctx(None), ctx.max_threads());
+            wasi_ctx.wasi_threads = Some(Arc::new(threads));
+        }
+        Ok(limits::new_store(&self.engine, wasi_ctx))
+    }
+
+    /// The key-value store of a container, with the buckets scoped to the container, or `None`
+    /// when the shim has no store.
+    fn keyvalue_store(&self, ctx: &impl RuntimeContext) -> Result<Option<ScopedStore>> {
+        let Some(keyvalue) = ctx.keyvalue() else {
+            return Ok(None);
+        };
+        let store = T::keyvalue_store(&keyvalue.store)
+            .with_context(|| format!("invalid key-value store {:?}", keyvalue.store))?;
+        Ok(Some(ScopedStore::new(store, &keyvalue.scope)))
+    }
+
+    /// The TLS client of a container, with the trust store of the node and the pins of its
+    /// annotations, or `None` when the container can't use sockets.
+    fn tls_client(
+        &self,
+        ctx: &impl RuntimeContext,
+        sockets: &SocketPolicy,
+    ) -> Result<Option<TlsClient>> {
+        if !sockets.is_enabled() {
+            return Ok(None);
+        }
+        let pins = Pins::from_annotations(ctx.annotations())?;
+        let client = TlsClient::new(self.tls_roots.clone(), pins, sockets.clone())?;
+        Ok(Some(client))
+    }
+
+    /// The client of the gRPC calls of a container, with the trust store of the node and the pins
+    /// of its annotations.
+    fn grpc_client(&self, ctx: &impl RuntimeContext) -> Result<GrpcClient> {
+        let pins = Pins::from_annotations(ctx.annotations())?;
+        GrpcClient::new(self.tls_roots.clone(), pins)
+    }
+
+    /// Prewarm a module exporting the initialization function, see the `prewarm` module.
+    /// The module is returned as is when it can't be prewarmed.
+    fn prewarm<'a>(&self, module: &'a [u8]) -> Cow<'a, [u8]> {
+        let Some(prewarm) = self.prewarm.as_ref().filter(|p| p.applies_to(module)) else {
+            return Cow::Borrowed(module);
+        };
+        log::info!("prewarming module with {:?}", prewarm.export());
+        match self.initialize(module, prewarm) {
+            Ok(module) => Cow::Owned(module),
+            Err(err) => {
+                log::warn!("failed to prewarm module, running it as is: {err:#}");
+                Cow::Borrowed(module)
+            }
+        }
+    }
+
+    /// Run the initialization of a module in an isolated store, and snapshot its state.
+    fn initialize(&self, module: &[u8], prewarm: &Prewarm) -> Result<Vec<u8>> {
+        let (instrumented, state) = prewarm::instrument(module)?;
+        let instrumented = Module::from_binary(&self.engine, &instrumented)?;
+        let wasi_ctx = prepare_wasi_ctx(
+            &[],
+            &[],
+            &[],
+            &WasiCtxOptions {
+                http_egress: HttpEgressPolicy::deny_all(),
+                sockets: SocketPolicy::disabled(),
+                limits: ResourceLimits::default().with_max_exec(prewarm::MAX_INIT_TIME),
+                ..Default::default()
+            },
+            None,
+        )?;
+        let mut store = limits::new_store(&self.engine, wasi_ctx);
+        let module_linker = self.module_linker()?;
+        let snapshot = wasmtime_wasi::runtime::in_tokio(async {
+            let instance = module_linker
+                .instantiate_async(&mut store, &instrumented)
+                .await?;
+            let init = instance
+                .get_typed_func::<(), ()>(&mut store, prewarm.export())
+                .context("invalid initialization export")?;
+            init.call_async(&mut store, ()).await?;
+            prewarm::snapshot(&mut store, &instance, &state)
+        })?;
+        prewarm::restore(module, prewarm, &snapshot)
+    }
+
+    /// Split the library layers from the source of a container.
+    /// With several module layers, the layers other than the entrypoint are its libraries.
+    fn split_libraries<'a>(
+        &self,
+        source: Source<'a>,
+    ) -> Result<(Source<'a>, Vec<(String, Module)>)> {
+        match source {
+            Source::Oci(layers) if layers.len() > 1 => {
+                let (main, libraries) = linking::split_layers(layers)?;
+                let libraries = libraries
+                    .into_iter()
+                    .map(|(name, layer)| {
+                        let library = self
+                            .load_module(&layer.layer)
+                            .context("invalid library layer: only modules can be linked")?;
+                        Ok((name.to_string(), library))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((Source::Oci(std::slice::from_ref(main)), libraries))
+            }
+            source => Ok((source, vec![])),
+        }
+    }
+
+    /// Create a linker for modules, with the preview 1 and custom host functions.
+    fn module_linker(&self) -> Result<wasmtime::Linker<WasiCtx>> {
+        let mut module_linker = wasmtime::Linker::new(&self.engine);
+
+        log::debug!("init linker");
+        wasi_preview1::add_to_linker_async(&mut module_linker, |s: &mut WasiCtx| {
+            &mut s.wasi_preview1
+        })?;
+        if !self.nn_backends.is_empty() {
+            nn::add_to_linker(&mut module_linker, |s: &mut WasiCtx| &mut s.nn)?;
+        }
+        T::add_to_linker(&mut module_linker)?;
+        Ok(module_linker)
+    }
+
+    /// Create a linker for components, with the preview 2, `wasi:http` and custom imports, and the
+    /// `wasi:keyvalue` imports for the containers with a key-value store.
+    fn component_linker(&self, keyvalue: bool) -> Result<wasmtime_component::Linker<WasiCtx>> {
+        let mut linker = wasmtime_component::Linker::new(&self.engine);
+
+        log::debug!("init linker");
+        wasi_preview2::add_to_linker_async(&mut linker)?;
+        dns::add_to_linker(&mut linker)?;
+        wasmtime_wasi_http::proxy::add_only_http_to_linker(&mut linker)?;
+        if keyvalue {
+            keyvalue::add_to_linker(&mut linker)?;
+        }
+        tls::add_to_linker(&mut linker)?;
+        T::add_to_component_linker(&mut linker)?;
+        log::debug!("done init linker");
+        Ok(linker)
+    }
+
+    /// Load a module, which can be precompiled.
+    fn load_module(&self, bytes: &[u8]) -> Result<Module> {
+        match WasmBinaryType::from_bytes(bytes) {
+            Some(WasmBinaryType::Module) => Module::from_binary(&self.engine, bytes),
+            _ if self.engine.detect_precompiled(bytes) == Some(Precompiled::Module) => unsafe {
+                Module::deserialize(&self.engine, bytes)
+            },
+            _ => bail!("not a wasm module"),
+        }
+    }
+}
+
+/// Instantiate the `libraries` of a module, linking the exports of each one under its name,
+/// and then the module.
+async fn instantiate_module(
+    module_linker: &mut wasmtime::Linker<WasiCtx>,
+    store: &mut Store<WasiCtx>,
+    module: &Module,
+    libraries: Vec<(String, Module)>,
+) -> Result<wasmtime::Instance> {
+    for (name, library) in libraries {
+        log::info!("instantiating library {name:?}");
+        let instance = module_linker
+            .instantiate_async(&mut *store, &library)
+            .await?;
+        if let Some(init) = instance.get_func(&mut *store, "_initialize") {
+            init.call_async(&mut *store, &[], &mut []).await?;
+        }
+        module_linker.instance(&mut *store, &name, instance)?;
+    }
+
+    log::info!("instantiating instance");
+    // the module is linked first, so that a missing import is told apart from a trap
+    let pre = module_linker
+        .instantiate_pre(module)
+        .context(TaskErrorCode::UnsupportedImport)?;
+    pre.instantiate_async(&mut *store).await
+}
+
+/// Instantiate a component and call its function `func`, i.e., `wasi:cli/run` for `_start`.
+async fn call_component(
+    store: &mut Store<WasiCtx>,
+    pre: &wasmtime_component::InstancePre<WasiCtx>,
+    func: &str,
+    func_args: &[String],
+) -> Result<()> {
+    if func == "_start" {
+        let (command, _instance) =
+            wasi_preview2::bindings::Command::instantiate_pre(&mut *store, pre).await?;
+        return command
+            .wasi_cli_run()
+            .call_run(&mut *store)
+            .await?
+            .map_err(|_| anyhow::anyhow!("failed to run component"));
+    }
+    let instance = pre.instantiate_async(&mut *store).await?;
+    let func_ref = instance
+        .get_func(&mut *store, func)
+        .with_context(|| format!("component does not export {func:?}"))?;
+    let params = params::parse_component_params(&func_ref.params(&*store), func_args)
+        .with_context(|| format!("invalid arguments for {func:?}"))?;
+    let mut results = vec![wasmtime_component::Val::Bool(false); func_ref.results(&*store).len()];
+    func_ref
+        .call_async(&mut *store, &params, &mut results)
+        .await
+}
+
+/// Call `func`, and the shutdown export of the instance in its place if the call is
+/// interrupted by a SIGTERM.
+async fn call_or_shutdown(
+    store: &mut Store<WasiCtx>,
+    instance: &wasmtime::Instance,
+    func: Func,
+    params: &[Val],
+    results: &mut [Val],
+) -> Result<()> {
+    match func.call_async(&mut *store, params, results).await {
+        Err(err) if err.is::<ShutdownRequested>() => shutdown_instance(store, instance, err).await,
+        status => status,
+    }
+}
+
+/// Call the shutdown export of the instance, or return `err` if it doesn't have one.
+async fn shutdown_instance(
+    store: &mut Store<WasiCtx>,
+    instance: &wasmtime::Instance,
+    err: anyhow::Error,
+) -> Result<()> {
+    // the export is removed so that the shutdown itself isn't interrupted
+    let shutdown_func = store
+        .data_mut()
+        .shutdown_export
+        .take()
+        .and_then(|name| instance.get_func(&mut *store, &name));
+    match shutdown_func {
+        Some(shutdown_func) => {
+            log::info!("calling shutdown function");
+            shutdown_func.call_async(&mut *store, &[], &mut []).await
+        }
+        None => Err(err),
+    }
+}
+
+/// Map the result of running a container to its exit code.
+fn exit_code(status: Result<Result<()>>) -> Result<i32> {
+    // errors raised while running the wasm code, e.g. a trap, may be returned by either
+    // the outer or the inner result
+    status.and_then(|status| status).map(|_| 0).or_else(|err| {
+        if err.is::<DeadlineExceeded>() {
+            return Ok(DEADLINE_EXCEEDED_EXIT_CODE);
+        }
+        if err.is::<ShutdownRequested>() {
+            return Ok(SIGTERM_EXIT_CODE);
+        }
+        match err.downcast_ref::<wasmtime_wasi::I32Exit>() {
+            Some(value) => Ok(value.process_exit_code()),
+            _ => Err(err),
+        }
+    })
+}
+
+const COMMAND_ARGS_ERROR: &str =
+    "the entrypoint of commands has no parameters, select another exported function to pass arguments";
+
+const COMPONENT_LIBRARIES_ERROR: &str =
+    "library layers can only be linked with modules, compose the component instead";
+
+/// The function a container runs, with its arguments, and the context it runs in
+struct Invocation<'a, C: RuntimeContext> {
+    ctx: &'a C,
+    envs: &'a [(String, String)],
+    func: String,
+    func_args: Vec<String>,
+    stdio: Stdio,
+}
+
+/// The policies and limits the WASI contexts of a container are built with, see
+/// [`prepare_wasi_ctx`].
+#[derive(Clone, Default)]
+struct WasiCtxOptions {
+    http_egress: HttpEgressPolicy,
+    sockets: SocketPolicy,
+    clocks: VirtualClocks,
+    recorder: Option<Recorder>,
+    limits: ResourceLimits,
+}
+
+impl WasiCtxOptions {
+    /// The options of a container, from its annotations and its resources
+    fn from_ctx(ctx: &impl RuntimeContext) -> Result<Self> {
+        Ok(Self {
+            http_egress: http_egress_policy(ctx)?,
+            sockets: socket_policy(ctx)?,
+            clocks: VirtualClocks::from_annotations(ctx.annotations())?,
+            recorder: ctx.record_log()?.as_ref().map(Recorder::open).transpose()?,
+            limits: ResourceLimits::from_resources(ctx.resources())
+                .with_annotations(ctx.annotations())?
+                .with_stats(ctx.pool_stats()),
+        })
+    }
+}
+
 /// Prepare both wasi_preview1 and wasi_preview2 contexts.
+/// With a `connection`, its stdin and stdout are the ones of the connection.
 fn prepare_wasi_ctx(
-    ctx: &impl RuntimeContext,
-    envs: Vec<(String, String)>,
+    args: &[String],
+    envs: &[(String, String)],
+    preopens: &[Volume],
+    options: &WasiCtxOptions,
+    connection: Option<&Connection>,
 ) -> Result<WasiCtx, anyhow::Error> {
-    let mut wasi_preview1_builder = wasi_builder(ctx, &envs)?;
+    let mut wasi_preview1_builder = wasi_builder(args, envs, preopens, options, connection)?;
     let wasi_preview1_ctx = wasi_preview1_builder.build_p1();
 
-    let mut wasi_preview2_builder = wasi_builder(ctx, &envs)?;
+    let mut wasi_preview2_builder = wasi_builder(args, envs, preopens, options, connection)?;
     let wasi_preview2_ctx = wasi_preview2_builder.build();
     let wasi_data = WasiCtx {
         wasi_preview1: wasi_preview1_ctx,
         wasi_preview2: wasi_preview2_ctx,
+        wasi_http: WasiHttpCtx::new(),
+        http_egress: options.http_egress.clone(),
+        limits: options.limits.clone(),
+        shutdown_export: None,
+        nn: WasiNnCtx::default(),
+        keyvalue: None,
+        tls: None,
+        grpc: None,
+        coredump: None,
+        wait_for_debugger: false,
+        wasi_threads: None,
+        fs_quotas: FsQuotas::default(),
         resource_table: ResourceTable::default(),
     };
     Ok(wasi_data)
 }
 
 fn wasi_builder(
-    ctx: &impl RuntimeContext,
+    args: &[String],
     envs: &[(String, String)],
+    preopens: &[Volume],
+    options: &WasiCtxOptions,
+    connection: Option<&Connection>,
 ) -> Result<wasi_preview2::WasiCtxBuilder, anyhow::Error> {
-    // TODO: make this more configurable (e.g. allow the user to specify the
-    // preopened directories and their permissions)
-    // https://github.com/containerd/runwasi/issues/413
-    let file_perms = wasi_preview2::FilePerms::all();
-    let dir_perms = wasi_preview2::DirPerms::all();
-
+    let sockets_enabled = options.sockets.is_enabled();
+    let sockets = options.sockets.clone();
     let mut builder = wasi_preview2::WasiCtxBuilder::new();
     builder
-        .args(ctx.args())
+        .args(args)
         .envs(envs)
         .inherit_stdio()
-        .inherit_network()
-        .allow_tcp(true)
-        .allow_udp(true)
-        .allow_ip_name_lookup(true)
-        .preopened_dir("/", "/", dir_perms, file_perms)?;
+        .socket_addr_check(move |addr, addr_use| {
+            let allowed = sockets.is_allowed(&addr, addr_use);
+            Box::pin(async move { allowed })
+        })
+        .allow_tcp(sockets_enabled)
+        .allow_udp(sockets_enabled)
+        .allow_ip_name_lookup(sockets_enabled);
+    // the instance handling a connection of the port bridge reads and writes the connection
+    if let Some(connection) = connection {
+        builder
+            .stdin(connection.stdin())
+            .stdout(connection.stdout());
+    }
+    options.clocks.apply(&mut builder);
+    if let Some(recorder) = &options.recorder {
+        recorder.apply(&options.clocks, &mut builder);
+    }
+
+    // the directories are preopened with the permissions of their mount, guests resolve
+    // their paths in the longest matching preopen
+    for preopen in preopens {
+        let (dir_perms, file_perms) = match preopen.readonly {
+            true => (
+                wasi_preview2::DirPerms::READ,
+                wasi_preview2::FilePerms::READ,
+            ),
+            false => (
+                wasi_preview2::DirPerms::all(),
+                wasi_preview2::FilePerms::all(),
+            ),
+        };
+        let guest_path = preopen.guest_path.to_string_lossy();
+        builder
+            .preopened_dir(&preopen.host_path, &guest_path, dir_perms, file_perms)
+            .with_context(|| format!("failed to preopen {guest_path}"))?;
+    }
     Ok(builder)
 }
+
+/// The directories preopened for the guest: the root of the container, and its volumes.
+/// None are preopened when the capability policy denies the `filesystem` capability.
+fn preopens(ctx: &impl RuntimeContext) -> Vec<Volume> {
+    if !ctx.allows_capability("filesystem") {
+        return vec![];
+    }
+    let root = Volume {
+        host_path: "/".into(),
+        guest_path: "/".into(),
+        readonly: ctx.readonly_rootfs(),
+    };
+    std::iter::once(root).chain(ctx.volumes()).collect()
+}
+
+/// The HTTP egress policy of the container annotations, denying every request when the capability
+/// policy denies the `http` capability.
+fn http_egress_policy(ctx: &impl RuntimeContext) -> Result<HttpEgressPolicy> {
+    match ctx.allows_capability("http") {
+        true => HttpEgressPolicy::from_annotations(ctx.annotations()),
+        false => Ok(HttpEgressPolicy::deny_all()),
+    }
+}
+
+/// The socket policy of the container annotations, without sockets when the capability policy
+/// denies the `sockets` capability.
+fn socket_policy(ctx: &impl RuntimeContext) -> Result<SocketPolicy> {
+    match ctx.allows_capability("sockets") {
+        true => SocketPolicy::from_annotations(ctx.annotations()),
+        false => Ok(SocketPolicy::disabled()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    use containerd_shim_wasm::container::{GuestFailure, PoolMetrics, PoolStats};
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder};
+
+    use super::*;
+
+    static ANSWER: AtomicI32 = AtomicI32::new(0);
+
+    #[test]
+    fn test_invalid_pooling_falls_back() -> Result<()> {
+        let config = || {
+            let mut config = Config::new();
+            config.async_support(true);
+            config
+        };
+        let pooling = PoolingOptions {
+            max_instances: None,
+            max_memory_pages: Some(u64::MAX),
+            max_table_elements: None,
+        };
+        let engine = engine_with_pooling(config(), Ok(Some(pooling)));
+        Module::new(&engine, "(module)")?;
+        let engine = engine_with_pooling(config(), Err(anyhow::anyhow!("invalid value")));
+        Module::new(&engine, "(module)")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_p1_adapter_falls_back() {
+        let adapter = P1Adapter::load(Path::new("/nonexistent/adapter.wasm"));
+        assert!(p1_adapter_or_default(adapter.map(Some)).is_none());
+    }
+
+    #[test]
+    fn test_instance_stats() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let module = Module::new(
+            &engine.engine,
+            r#"(module
+                (memory 1)
+                (func (export "grow") (drop (memory.grow (i32.const 2)))))"#,
+        )?;
+        let stats = PoolStats::default();
+        let wasi_ctx = prepare_wasi_ctx(
+            &[],
+            &[],
+            &[],
+            &WasiCtxOptions {
+                limits: ResourceLimits::default().with_stats(Some(stats.clone())),
+                ..Default::default()
+            },
+            None,
+        )?;
+        let mut store = limits::new_store(&engine.engine, wasi_ctx);
+        assert_eq!(stats.metrics().instances, 1);
+
+        wasmtime_wasi::runtime::in_tokio(async {
+            let instance = wasmtime::Linker::new(&engine.engine)
+                .instantiate_async(&mut store, &module)
+                .await?;
+            let grow = instance.get_typed_func::<(), ()>(&mut store, "grow")?;
+            grow.call_async(&mut store, ()).await
+        })?;
+        assert_eq!(stats.metrics().memory, 3 * 65536);
+
+        // the store and its memory are no longer counted once it is dropped
+        drop(store);
+        assert_eq!(stats.metrics(), PoolMetrics::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_execution_deadline() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let module = Module::new(
+            &engine.engine,
+            r#"(module (func (export "_start") (loop (br 0))))"#,
+        )?;
+        let annotations = HashMap::from([(
+            limits::MAX_EXEC_SECONDS_ANNOTATION.to_string(),
+            "0.1".to_string(),
+        )]);
+        let wasi_ctx = prepare_wasi_ctx(
+            &[],
+            &[],
+            &[],
+            &WasiCtxOptions {
+                limits: ResourceLimits::default().with_annotations(&annotations)?,
+                ..Default::default()
+            },
+            None,
+        )?;
+        let store = limits::new_store(&engine.engine, wasi_ctx);
+
+        let err = engine
+            .execute_module(
+                module,
+                vec![],
+                store,
+                &"_start".to_string(),
+                &[],
+                Stdio::default(),
+            )?
+            .unwrap_err();
+        assert!(err.is::<DeadlineExceeded>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown_export() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let run = |wat: &str| -> Result<Result<()>> {
+            let module = Module::new(&engine.engine, wat)?;
+            let mut wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+            wasi_ctx.shutdown_export = Some("shutdown".to_string());
+            let store = limits::new_store(&engine.engine, wasi_ctx);
+            shutdown::request();
+            engine.execute_module(
+                module,
+                vec![],
+                store,
+                &"_start".to_string(),
+                &[],
+                Stdio::default(),
+            )
+        };
+
+        let err = run(r#"(module
+            (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+            (memory (export "memory") 1)
+            (func (export "_start") (loop (br 0)))
+            (func (export "shutdown") (call $exit (i32.const 3))))"#)?
+        .unwrap_err();
+        assert_eq!(err.downcast_ref::<wasmtime_wasi::I32Exit>().unwrap().0, 3);
+
+        // without the export, the module is interrupted
+        let err = run(r#"(module (func (export "_start") (loop (br 0))))"#)?.unwrap_err();
+        assert!(err.is::<ShutdownRequested>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_linked_libraries() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let library = Module::new(
+            &engine.engine,
+            r#"(module
+                (global $answer (mut i32) (i32.const 0))
+                (func (export "_initialize") (global.set $answer (i32.const 42)))
+                (func (export "answer") (result i32) (global.get $answer)))"#,
+        )?;
+        let module = Module::new(
+            &engine.engine,
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                (import "answers" "answer" (func $answer (result i32)))
+                (memory (export "memory") 1)
+                (func (export "_start") (call $exit (call $answer))))"#,
+        )?;
+        let wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+        let store = limits::new_store(&engine.engine, wasi_ctx);
+
+        let libraries = vec![("answers".to_string(), library)];
+        let err = engine
+            .execute_module(
+                module,
+                libraries,
+                store,
+                &"_start".to_string(),
+                &[],
+                Stdio::default(),
+            )?
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<wasmtime_wasi::I32Exit>().unwrap().0, 42);
+        Ok(())
+    }
+
+    // An adapter exporting `wasi:cli/run` on top of the `_start` of the module
+    fn fake_p1_adapter() -> Result<P1Adapter> {
+        let wit = r#"
+            package wasi:cli@0.2.0;
+            interface run { run: func() -> result; }
+            world adapter { export run; }
+        "#;
+        let mut resolve = wit_parser::Resolve::default();
+        let pkg = resolve.push(wit_parser::UnresolvedPackage::parse(
+            Path::new("adapter.wit"),
+            wit,
+        )?)?;
+        let world = resolve.select_world(pkg, Some("adapter"))?;
+
+        let mut adapter = wat::parse_str(
+            r#"(module
+                (import "__main_module__" "_start" (func $start))
+                (func (export "proc_exit") (param i32) unreachable)
+                (func (export "wasi:cli/run@0.2.0#run") (result i32)
+                    (call $start)
+                    (i32.const 0)))"#,
+        )?;
+        wit_component::embed_component_metadata(
+            &mut adapter,
+            &resolve,
+            world,
+            wit_component::StringEncoding::UTF8,
+        )?;
+        Ok(P1Adapter::new(adapter))
+    }
+
+    #[test]
+    fn test_prewarm() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig> {
+            prewarm: Some(Prewarm::new("wizer.initialize")),
+            ..Default::default()
+        };
+        let module = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "wizer.initialize") (i32.store (i32.const 8) (i32.const 1)))
+                (func (export "_start")
+                    (if (i32.eqz (i32.load (i32.const 8))) (then (call $exit (i32.const 1))))))"#,
+        )?;
+
+        let prewarmed = engine.prewarm(&module);
+        assert!(matches!(prewarmed, Cow::Owned(_)));
+        let module = Module::from_binary(&engine.engine, &prewarmed)?;
+        assert!(module.get_export("wizer.initialize").is_none());
+
+        let wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+        let store = limits::new_store(&engine.engine, wasi_ctx);
+        engine.execute_module(
+            module,
+            vec![],
+            store,
+            &"_start".to_string(),
+            &[],
+            Stdio::default(),
+        )??;
+
+        // modules that can't be snapshotted run as is
+        let module = wat::parse_str(
+            r#"(module (import "env" "memory" (memory 1)) (func (export "wizer.initialize")))"#,
+        )?;
+        assert!(matches!(engine.prewarm(&module), Cow::Borrowed(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_p1_adapter() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig> {
+            p1_adapter: Some(fake_p1_adapter()?),
+            ..Default::default()
+        };
+        let module = wat::parse_str(
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start"))
+                (func (export "exit") (call $exit (i32.const 1))))"#,
+        )?;
+
+        let component = engine.p1_adapter.as_ref().unwrap().componentize(&module)?;
+        assert!(matches!(
+            WasmBinaryType::from_bytes(&component),
+            Some(WasmBinaryType::Component)
+        ));
+        let component = Component::from_binary(&engine.engine, &component)?;
+
+        let wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+        let store = limits::new_store(&engine.engine, wasi_ctx);
+        engine.execute_component(
+            component,
+            store,
+            "_start".to_string(),
+            vec![],
+            Stdio::default(),
+        )??;
+        Ok(())
+    }
+
+    #[test]
+    fn test_entrypoint_args() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let run = |args: &[String]| -> Result<Result<()>> {
+            let module = Module::new(
+                &engine.engine,
+                r#"(module
+                    (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                    (memory (export "memory") 1)
+                    (func (export "exit") (param i32 i32)
+                        (call $exit (i32.add (local.get 0) (local.get 1)))))"#,
+            )?;
+            let wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+            let store = limits::new_store(&engine.engine, wasi_ctx);
+            engine.execute_module(
+                module,
+                vec![],
+                store,
+                &"exit".to_string(),
+                args,
+                Stdio::default(),
+            )
+        };
+
+        let err = run(&["40".into(), "2".into()])?.unwrap_err();
+        assert_eq!(err.downcast_ref::<wasmtime_wasi::I32Exit>().unwrap().0, 42);
+
+        assert!(run(&["40".into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reactor() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let module = Module::new(
+            &engine.engine,
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                (global $base (mut i32) (i32.const 0))
+                (memory (export "memory") 1)
+                (func (export "_initialize") (global.set $base (i32.const 40)))
+                (func (export "add") (param i32) (result i32)
+                    (global.set $base (i32.add (global.get $base) (local.get 0)))
+                    (global.get $base))
+                (func (export "exit") (call $exit (global.get $base))))"#,
+        )?;
+        let wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+        let store = limits::new_store(&engine.engine, wasi_ctx);
+
+        // the state of the instance is kept between invocations, invalid ones are skipped
+        let input = std::io::Cursor::new(
+            "add 1
+missing
+add one
+
+add 1
+exit
+",
+        );
+        let err = engine
+            .execute_reactor(module, vec![], store, Stdio::default(), input)?
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<wasmtime_wasi::I32Exit>().unwrap().0, 42);
+        Ok(())
+    }
+
+    #[derive(Clone)]
+    struct HostFunctionsConfig {}
+
+    impl WasiConfig for HostFunctionsConfig {
+        fn new_config() -> Config {
+            let mut config = wasmtime::Config::new();
+            config.parallel_compilation(false);
+            config
+        }
+
+        fn add_to_linker(linker: &mut wasmtime::Linker<WasiCtx>) -> Result<()> {
+            linker.func_wrap("host", "answer", |answer: i32| {
+                ANSWER.store(answer, Ordering::SeqCst);
+            })?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_virtual_clocks() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        // exits with the seconds of the wall clock
+        let module = Module::new(
+            &engine.engine,
+            r#"(module
+                (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 0)))
+                    (call $proc_exit (i32.wrap_i64 (i64.div_u (i64.load (i32.const 0)) (i64.const 1000000000))))))"#,
+        )?;
+        let annotations = HashMap::from([
+            (
+                crate::clocks::WALL_CLOCK_ANNOTATION.to_string(),
+                "100".to_string(),
+            ),
+            (
+                crate::clocks::WALL_CLOCK_OFFSET_ANNOTATION.to_string(),
+                "-58".to_string(),
+            ),
+        ]);
+        let wasi_ctx = prepare_wasi_ctx(
+            &[],
+            &[],
+            &[],
+            &WasiCtxOptions {
+                clocks: VirtualClocks::from_annotations(&annotations)?,
+                ..Default::default()
+            },
+            None,
+        )?;
+        let store = limits::new_store(&engine.engine, wasi_ctx);
+
+        let status = engine.execute_module(
+            module,
+            vec![],
+            store,
+            &"_start".to_string(),
+            &[],
+            Stdio::default(),
+        );
+        assert_eq!(exit_code(status)?, 42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_host_functions() -> Result<()> {
+        let engine = WasmtimeEngine::<HostFunctionsConfig>::default();
+        let module = Module::new(
+            &engine.engine,
+            r#"(module
+                (import "host" "answer" (func $answer (param i32)))
+                (func (export "_start") (call $answer (i32.const 42))))"#,
+        )?;
+        let wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+        let store = limits::new_store(&engine.engine, wasi_ctx);
+
+        engine.execute_module(
+            module,
+            vec![],
+            store,
+            &"_start".to_string(),
+            &[],
+            Stdio::default(),
+        )??;
+
+        assert_eq!(ANSWER.load(Ordering::SeqCst), 42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_coredump_on_trap() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let dir = tempfile::tempdir()?;
+        let run = |wat: &str| -> Result<Vec<u8>> {
+            let coredump = dir.path().join("wasm.coredump");
+            let module = Module::new(&engine.engine, wat)?;
+            let mut wasi_ctx = prepare_wasi_ctx(&[], &[], &[], &WasiCtxOptions::default(), None)?;
+            wasi_ctx.coredump = Some(coredump.clone());
+            std::fs::write(&coredump, b"")?;
+            let store = limits::new_store(&engine.engine, wasi_ctx);
+            let _ = engine.execute_module(
+                module,
+                vec![],
+                store,
+                &"_start".to_string(),
+                &[],
+                Stdio::default(),
+            );
+            Ok(std::fs::read(coredump)?)
+        };
+
+        let trapped = run(r#"(module
+            (memory (export "memory") 1)
+            (func (export "_start") unreachable))"#)?;
+        assert!(trapped.starts_with(b"\0asm"));
+
+        // exiting is not a trap
+        let exited = run(r#"(module
+            (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+            (func (export "_start") (call $proc_exit (i32.const 1))))"#)?;
+        assert!(exited.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_guest_failures() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        let run = |wat: &str, limits: ResourceLimits| -> Option<GuestFailure> {
+            let module = Module::new(&engine.engine, wat).unwrap();
+            let wasi_ctx = prepare_wasi_ctx(
+                &[],
+                &[],
+                &[],
+                &WasiCtxOptions {
+                    limits,
+                    ..Default::default()
+                },
+                None,
+            )
+            .unwrap();
+            let store = limits::new_store(&engine.engine, wasi_ctx);
+            let status = engine.execute_module(
+                module,
+                vec![],
+                store,
+                &"_start".to_string(),
+                &[],
+                Stdio::default(),
+            );
+            let err = exit_code(status).err()?;
+            err.downcast_ref::<GuestFailure>().cloned()
+        };
+
+        let failure = run(
+            r#"(module $hello (func $start (export "_start") unreachable))"#,
+            ResourceLimits::default(),
+        );
+        let Some(GuestFailure::Trap {
+            code,
+            message,
+            backtrace,
+        }) = failure
+        else {
+            panic!("expected a trap, got {failure:?}");
+        };
+        assert_eq!(code, "UnreachableCodeReached");
+        assert_eq!(
+            message,
+            "wasm trap: wasm `unreachable` instruction executed"
+        );
+        assert_eq!(backtrace.len(), 1);
+        assert_eq!(backtrace[0].module.as_deref(), Some("hello"));
+        assert_eq!(backtrace[0].func_name.as_deref(), Some("start"));
+        assert_eq!(backtrace[0].func_index, 0);
+
+        // the guest aborts once it can't grow its memory
+        let grow = r#"(module
+            (memory 1)
+            (func (export "_start")
+                (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1)) (then unreachable))))"#;
+        let limited = ResourceLimits::from_resources(Some(
+            &LinuxResourcesBuilder::default()
+                .memory(LinuxMemoryBuilder::default().limit(65536).build()?)
+                .build()?,
+        ));
+        let failure = run(grow, limited);
+        assert!(matches!(failure, Some(GuestFailure::OutOfMemory { .. })));
+        assert_eq!(run(grow, ResourceLimits::default()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_precompile_target() {
+        let target = WasmtimeEngine::<DefaultConfig>::default().precompile_target();
+        assert_eq!(target.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(target.target, PrecompileTarget::host("").target);
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            target.features.contains(&"avx2".to_string()),
+            std::is_x86_feature_detected!("avx2")
+        );
+    }
+
+    #[test]
+    fn test_shared_engine() -> Result<()> {
+        let engine = WasmtimeEngine::<DefaultConfig>::default();
+        assert!(wasmtime::Engine::same(
+            &engine.engine,
+            &WasmtimeEngine::<DefaultConfig>::default().engine
+        ));
+        // the engines of different configurations are not shared
+        assert!(!wasmtime::Engine::same(
+            &engine.engine,
+            &WasmtimeEngine::<HostFunctionsConfig>::default().engine
+        ));
+
+        let run = |module: &Module, limits: ResourceLimits| -> Result<i32> {
+            let wasi_ctx = prepare_wasi_ctx(
+                &[],
+                &[],
+                &[],
+                &WasiCtxOptions {
+                    limits,
+                    ..Default::default()
+                },
+                None,
+            )?;
+            let store = limits::new_store(&engine.engine, wasi_ctx);
+            exit_code(engine.execute_module(
+                module.clone(),
+                vec![],
+                store,
+                &"_start".to_string(),
+                &[],
+                Stdio::default(),
+            ))
+        };
+
+        // the instances of a module don't see the globals and memory of the other instances
+        let counter = Module::new(
+            &engine.engine,
+            r#"(module
+                (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+                (global $runs (mut i32) (i32.const 0))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (global.set $runs (i32.add (global.get $runs) (i32.const 1)))
+                    (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                    (call $exit (i32.add (global.get $runs) (i32.load (i32.const 0))))))"#,
+        )?;
+        assert_eq!(run(&counter, ResourceLimits::default())?, 2);
+        assert_eq!(run(&counter, ResourceLimits::default())?, 2);
+
+        // the deadline of an instance doesn't interrupt the other instances of the engine
+        let annotations = HashMap::from([(
+            limits::MAX_EXEC_SECONDS_ANNOTATION.to_string(),
+            "0.1".to_string(),
+        )]);
+        let spin = Module::new(
+            &engine.engine,
+            r#"(module
+                (global $i (mut i32) (i32.const 0))
+                (func (export "_start")
+                    (loop
+                        (global.set $i (i32.add (global.get $i) (i32.const 1)))
+                        (br_if 0 (i32.ne (global.get $i) (i32.const -1))))))"#,
+        )?;
+        let forever = Module::new(
+            &engine.engine,
+            r#"(module (func (export "_start") (loop (br 0))))"#,
+        )?;
+        std::thread::scope(|scope| -> Result<()> {
+            let limited = ResourceLimits::default().with_annotations(&annotations)?;
+            let interrupted = scope.spawn(|| run(&forever, limited));
+            assert_eq!(run(&spin, ResourceLimits::default())?, 0);
+            assert_eq!(interrupted.join().unwrap()?, DEADLINE_EXCEEDED_EXIT_CODE);
+            Ok(())
+        })
+    }
+}
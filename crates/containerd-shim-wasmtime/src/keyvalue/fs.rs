@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+
+use super::{parse_counter, KeyValueStore};
+
+/// A store keeping each bucket in a directory, with a file per key.
+/// The names of the files are the hex encoded keys, so that any key is a valid file name.
+/// The buckets scoped to a container are in the subdirectories of the scope, e.g.,
+/// `k8s.io/default/web/app/<bucket>`.
+pub struct FileStore {
+    root: PathBuf,
+    // serializes the increments, which read and write the value
+    lock: Mutex<()>,
+}
+
+impl FileStore {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root)
+            .with_context(|| format!("failed to create key-value store {root:?}"))?;
+        Ok(Self {
+            root,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn bucket(&self, bucket: &str) -> Result<PathBuf> {
+        let invalid = |name: &str| name.is_empty() || name.starts_with('.') || name.contains('\\');
+        if bucket.split('/').any(invalid) {
+            bail!("invalid bucket name {bucket:?}");
+        }
+        Ok(self.root.join(bucket))
+    }
+
+    fn path(&self, bucket: &str, key: &str) -> Result<PathBuf> {
+        let name = key.bytes().fold(String::new(), |mut name, b| {
+            let _ = write!(name, "{b:02x}");
+            name
+        });
+        // the empty key is stored as `-`, as there are no empty file names
+        let name = if name.is_empty() { "-".into() } else { name };
+        Ok(self.bucket(bucket)?.join(name))
+    }
+
+    fn write(&self, bucket: &str, key: &str, value: &[u8]) -> Result<()> {
+        let path = self.path(bucket, key)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        // written to a temporary file first, so that readers never see a partial value
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, value)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+fn decode_key(name: &str) -> Option<String> {
+    if name == "-" {
+        return Some(String::new());
+    }
+    let bytes = (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+impl KeyValueStore for FileStore {
+    fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path(bucket, key)?) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn set(&self, bucket: &str, key: &str, value: &[u8]) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        self.write(bucket, key, value)
+    }
+
+    fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        match fs::remove_file(self.path(bucket, key)?) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list_keys(&self, bucket: &str) -> Result<Vec<String>> {
+        let entries = match fs::read_dir(self.bucket(bucket)?) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut keys = vec![];
+        for entry in entries {
+            // the directories are the buckets of the scopes nested in the bucket's one
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name();
+            if let Some(key) = name.to_str().and_then(decode_key) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn increment(&self, bucket: &str, key: &str, delta: u64) -> Result<u64> {
+        let _lock = self.lock.lock().unwrap();
+        let value = match self.get(bucket, key)? {
+            Some(value) => parse_counter(&value)?,
+            None => 0,
+        } + delta;
+        self.write(bucket, key, value.to_string().as_bytes())?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::keyvalue::tests::check_store;
+
+    #[test]
+    fn test_file_store() -> Result<()> {
+        let dir = tempdir()?;
+        let store = FileStore::new(dir.path())?;
+        check_store(&store)?;
+
+        store.set("bucket", "", b"empty")?;
+        assert_eq!(store.get("bucket", "")?.as_deref(), Some(&b"empty"[..]));
+        assert!(store.set("../escape", "key", b"value").is_err());
+        assert!(store.set("scope/../escape", "key", b"value").is_err());
+        assert!(store.set("scope//bucket", "key", b"value").is_err());
+
+        // the content is kept across instances of the store
+        let store = FileStore::new(dir.path())?;
+        assert_eq!(store.get("other", "key")?.as_deref(), Some(&b"other"[..]));
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+use super::{parse_counter, KeyValueStore};
+
+/// A store keeping the buckets in memory
+#[derive(Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, HashMap<String, Vec<u8>>>>,
+}
+
+impl KeyValueStore for MemoryStore {
+    fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let buckets = self.buckets.lock().unwrap();
+        Ok(buckets.get(bucket).and_then(|b| b.get(key)).cloned())
+    }
+
+    fn set(&self, bucket: &str, key: &str, value: &[u8]) -> Result<()> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(bucket.to_string()).or_default();
+        bucket.insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get_mut(bucket) {
+            bucket.remove(key);
+        }
+        Ok(())
+    }
+
+    fn list_keys(&self, bucket: &str) -> Result<Vec<String>> {
+        let buckets = self.buckets.lock().unwrap();
+        Ok(buckets
+            .get(bucket)
+            .map(|b| b.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn increment(&self, bucket: &str, key: &str, delta: u64) -> Result<u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(bucket.to_string()).or_default();
+        let value = match bucket.get(key) {
+            Some(value) => parse_counter(value)?,
+            None => 0,
+        } + delta;
+        bucket.insert(key.to_string(), value.to_string().into_bytes());
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyvalue::tests::check_store;
+
+    #[test]
+    fn test_memory_store() -> Result<()> {
+        check_store(&MemoryStore::default())
+    }
+}
@@ -0,0 +1,322 @@
+//! Host implementation of `wasi:keyvalue`.
+//!
+//! Components importing the `store` and `atomics` interfaces of the [wasi:keyvalue] proposal
+//! get their buckets from the store of the `keyvalue` runtime option of the shim:
+//!  - `memory`: an in-memory store, whose content is lost when the shim exits.
+//!  - `file:<directory>`: a directory with a subdirectory per bucket and a file per key.
+//!  - `redis://[:<password>@]<host>[:<port>][/<db>]`: a Redis server, with a hash per bucket.
+//!
+//! The containers share the store, but not their buckets: the names of the buckets are prefixed
+//! with the scope of the container, e.g., `k8s.io/default/web/app/`.
+//! Without a store, the interfaces are not linked, and downstream shims can provide their own
+//! stores by implementing [`WasiConfig::keyvalue_store`].
+//! Atomic increments store the value as a decimal string, as Redis does.
+//! The stores do blocking I/O, so the operations run on the blocking threads of tokio.
+//!
+//! [wasi:keyvalue]: https://github.com/WebAssembly/wasi-keyvalue
+//! [`WasiConfig::keyvalue_store`]: crate::instance::WasiConfig::keyvalue_store
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{bail, Context, Result};
+use wasmtime::component::{Linker, Resource};
+
+use crate::instance::WasiCtx;
+
+mod fs;
+mod memory;
+mod redis;
+
+pub use fs::FileStore;
+pub use memory::MemoryStore;
+pub use redis::RedisStore;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit/keyvalue",
+        world: "wasi:keyvalue/imports",
+        async: true,
+        with: {
+            "wasi:keyvalue/store/bucket": super::Bucket,
+        },
+    });
+}
+
+use bindings::wasi::keyvalue::store::{self, KeyResponse};
+
+/// A backend of the key-value interfaces.
+/// Buckets are created when they are first written to.
+pub trait KeyValueStore: Send + Sync {
+    fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>>;
+
+    fn set(&self, bucket: &str, key: &str, value: &[u8]) -> Result<()>;
+
+    fn delete(&self, bucket: &str, key: &str) -> Result<()>;
+
+    fn exists(&self, bucket: &str, key: &str) -> Result<bool> {
+        Ok(self.get(bucket, key)?.is_some())
+    }
+
+    fn list_keys(&self, bucket: &str) -> Result<Vec<String>>;
+
+    /// Atomically adds `delta` to the value of `key`, missing keys count as `0`.
+    fn increment(&self, bucket: &str, key: &str, delta: u64) -> Result<u64>;
+}
+
+/// Opens the store of a `keyvalue` runtime option.
+/// The store is opened once per process, and shared by the containers using it.
+pub fn open(config: &str) -> Result<Arc<dyn KeyValueStore>> {
+    static STORES: OnceLock<Mutex<HashMap<String, Arc<dyn KeyValueStore>>>> = OnceLock::new();
+    let mut stores = STORES.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(store) = stores.get(config) {
+        return Ok(store.clone());
+    }
+    let store = parse_config(config)?;
+    stores.insert(config.to_string(), store.clone());
+    Ok(store)
+}
+
+fn parse_config(config: &str) -> Result<Arc<dyn KeyValueStore>> {
+    if config == "memory" {
+        return Ok(Arc::new(MemoryStore::default()));
+    }
+    if let Some(dir) = config.strip_prefix("file:") {
+        return Ok(Arc::new(FileStore::new(dir)?));
+    }
+    if config.starts_with("redis://") {
+        return Ok(Arc::new(RedisStore::new(config)?));
+    }
+    bail!("invalid key-value store {config:?}, expected `memory`, `file:<directory>` or `redis://<host>`")
+}
+
+/// Parses a value written by `increment`
+fn parse_counter(value: &[u8]) -> Result<u64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .context("value is not a counter")
+}
+
+/// The store of a container, whose buckets are prefixed with the scope of the container.
+#[derive(Clone)]
+pub(crate) struct ScopedStore {
+    store: Arc<dyn KeyValueStore>,
+    scope: String,
+}
+
+impl ScopedStore {
+    pub(crate) fn new(store: Arc<dyn KeyValueStore>, scope: &str) -> Self {
+        let scope = scope.trim_end_matches('/').to_string();
+        Self { store, scope }
+    }
+
+    /// Returns the name of the bucket `identifier` of the container in the store.
+    /// The identifiers can't contain `/`, so that the bucket of a container is never in the scope
+    /// of another one.
+    fn bucket(&self, identifier: &str) -> Option<String> {
+        if identifier.is_empty() || identifier.contains('/') {
+            return None;
+        }
+        Some(format!("{}/{identifier}", self.scope))
+    }
+}
+
+/// A bucket opened by the guest
+pub struct Bucket {
+    name: String,
+}
+
+impl WasiCtx {
+    fn keyvalue_store(&self) -> Result<&ScopedStore, store::Error> {
+        self.keyvalue.as_ref().ok_or(store::Error::NoSuchStore)
+    }
+
+    fn bucket_name(&self, bucket: &Resource<Bucket>) -> Result<String, store::Error> {
+        let bucket = self
+            .resource_table
+            .get(bucket)
+            .map_err(|err| store::Error::Other(err.to_string()))?;
+        Ok(bucket.name.clone())
+    }
+
+    /// Runs `f` with the store and the name of `bucket` on a blocking thread, so that a slow
+    /// store doesn't block the other tasks of the runtime, e.g., the requests of other instances.
+    /// The returned future doesn't borrow the context.
+    fn with_bucket<R: Send + 'static>(
+        &self,
+        bucket: &Resource<Bucket>,
+        f: impl FnOnce(&dyn KeyValueStore, &str) -> Result<R> + Send + 'static,
+    ) -> impl Future<Output = Result<R, store::Error>> + Send + 'static {
+        let target = self
+            .bucket_name(bucket)
+            .and_then(|name| Ok((self.keyvalue_store()?.store.clone(), name)));
+        async move {
+            let (store, name) = target?;
+            tokio::task::spawn_blocking(move || {
+                f(store.as_ref(), &name).map_err(|err| {
+                    log::debug!("key-value operation on bucket {name:?} failed: {err:#}");
+                    store::Error::Other(format!("{err:#}"))
+                })
+            })
+            .await
+            .map_err(|err| store::Error::Other(err.to_string()))?
+        }
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl store::Host for WasiCtx {
+    async fn open(&mut self, identifier: String) -> Result<Resource<Bucket>, store::Error> {
+        let name = self
+            .keyvalue_store()?
+            .bucket(&identifier)
+            .ok_or(store::Error::NoSuchStore)?;
+        self.resource_table
+            .push(Bucket { name })
+            .map_err(|err| store::Error::Other(err.to_string()))
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl store::HostBucket for WasiCtx {
+    async fn get(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> Result<Option<Vec<u8>>, store::Error> {
+        self.with_bucket(&bucket, move |store, bucket| store.get(bucket, &key))
+            .await
+    }
+
+    async fn set(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        value: Vec<u8>,
+    ) -> Result<(), store::Error> {
+        self.with_bucket(&bucket, move |store, bucket| {
+            store.set(bucket, &key, &value)
+        })
+        .await
+    }
+
+    async fn delete(&mut self, bucket: Resource<Bucket>, key: String) -> Result<(), store::Error> {
+        self.with_bucket(&bucket, move |store, bucket| store.delete(bucket, &key))
+            .await
+    }
+
+    async fn exists(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> Result<bool, store::Error> {
+        self.with_bucket(&bucket, move |store, bucket| store.exists(bucket, &key))
+            .await
+    }
+
+    // every key is returned in a single page
+    async fn list_keys(
+        &mut self,
+        bucket: Resource<Bucket>,
+        _cursor: Option<u64>,
+    ) -> Result<KeyResponse, store::Error> {
+        let keys = self
+            .with_bucket(&bucket, |store, bucket| store.list_keys(bucket))
+            .await?;
+        Ok(KeyResponse { keys, cursor: None })
+    }
+
+    fn drop(&mut self, bucket: Resource<Bucket>) -> wasmtime::Result<()> {
+        self.resource_table.delete(bucket)?;
+        Ok(())
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl bindings::wasi::keyvalue::atomics::Host for WasiCtx {
+    async fn increment(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        delta: u64,
+    ) -> Result<u64, store::Error> {
+        self.with_bucket(&bucket, move |store, bucket| {
+            store.increment(bucket, &key, delta)
+        })
+        .await
+    }
+}
+
+/// Adds the `wasi:keyvalue` interfaces to the linker of components.
+pub fn add_to_linker(linker: &mut Linker<WasiCtx>) -> Result<()> {
+    bindings::Imports::add_to_linker(linker, |ctx| ctx)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Checks the behavior of a store, shared by the tests of each backend.
+    pub(crate) fn check_store(store: &dyn KeyValueStore) -> Result<()> {
+        assert_eq!(store.get("bucket", "key")?, None);
+        assert!(!store.exists("bucket", "key")?);
+
+        store.set("bucket", "key", b"value")?;
+        store.set("bucket", "a/b", b"")?;
+        store.set("other", "key", b"other")?;
+        assert_eq!(store.get("bucket", "key")?.as_deref(), Some(&b"value"[..]));
+        assert_eq!(store.get("bucket", "a/b")?.as_deref(), Some(&b""[..]));
+        assert!(store.exists("bucket", "key")?);
+
+        let mut keys = store.list_keys("bucket")?;
+        keys.sort();
+        assert_eq!(keys, ["a/b", "key"]);
+
+        store.delete("bucket", "key")?;
+        store.delete("bucket", "missing")?;
+        assert_eq!(store.get("bucket", "key")?, None);
+        assert_eq!(store.get("other", "key")?.as_deref(), Some(&b"other"[..]));
+
+        assert_eq!(store.increment("bucket", "counter", 2)?, 2);
+        assert_eq!(store.increment("bucket", "counter", 3)?, 5);
+        assert_eq!(store.get("bucket", "counter")?.as_deref(), Some(&b"5"[..]));
+        assert!(store.increment("other", "key", 1).is_err());
+
+        // the buckets of the containers are prefixed with their scope
+        store.set("k8s.io/default/web/app/bucket", "key", b"scoped")?;
+        assert_eq!(
+            store
+                .get("k8s.io/default/web/app/bucket", "key")?
+                .as_deref(),
+            Some(&b"scoped"[..])
+        );
+        assert_eq!(store.get("bucket", "key")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoped_store() {
+        let store = ScopedStore::new(Arc::new(MemoryStore::default()), "k8s.io/default/web/app/");
+        assert_eq!(
+            store.bucket("cache").as_deref(),
+            Some("k8s.io/default/web/app/cache")
+        );
+        assert_eq!(store.bucket("../other"), None);
+        assert_eq!(store.bucket(""), None);
+    }
+
+    #[test]
+    fn test_open() -> Result<()> {
+        assert!(Arc::ptr_eq(&open("memory")?, &open("memory")?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert!(parse_config("memory").is_ok());
+        assert!(parse_config("redis://localhost:6379/1").is_ok());
+        assert!(parse_config("etcd://localhost").is_err());
+    }
+}
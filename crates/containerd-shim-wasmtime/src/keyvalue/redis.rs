@@ -0,0 +1,375 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use super::KeyValueStore;
+
+const DEFAULT_PORT: u16 = 6379;
+
+// Time to connect to the server, and to send a command or receive a reply, after which the
+// connection is dropped and the operation fails
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// The largest value a Redis server stores
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+// The replies of the store are arrays of values, so deeper replies are invalid
+const MAX_REPLY_DEPTH: usize = 2;
+
+/// A store keeping each bucket in a hash of a Redis server.
+///
+/// The store talks the RESP protocol over a single connection, which is opened on the first
+/// operation and reopened after an error. The operations time out after 5 seconds.
+pub struct RedisStore {
+    address: String,
+    password: Option<String>,
+    db: Option<u32>,
+    conn: Mutex<Option<Connection>>,
+}
+
+/// A reply of the server
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status,
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl RedisStore {
+    /// Creates a store for a `redis://[:<password>@]<host>[:<port>][/<db>]` url
+    pub fn new(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("redis://")
+            .with_context(|| format!("invalid redis url {url:?}"))?;
+        let (password, rest) = match rest.rsplit_once('@') {
+            Some((userinfo, rest)) => {
+                let password = userinfo.split_once(':').map_or(userinfo, |(_, p)| p);
+                (Some(password.to_string()), rest)
+            }
+            None => (None, rest),
+        };
+        let (host, db) = match rest.split_once('/') {
+            Some((host, "")) => (host, None),
+            Some((host, db)) => (
+                host,
+                Some(
+                    db.parse()
+                        .with_context(|| format!("invalid redis db {db:?}"))?,
+                ),
+            ),
+            None => (rest, None),
+        };
+        if host.is_empty() {
+            bail!("invalid redis url {url:?}, missing host");
+        }
+        let address = match host.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+            _ => format!("{host}:{DEFAULT_PORT}"),
+        };
+        Ok(Self {
+            address,
+            password,
+            db,
+            conn: Mutex::new(None),
+        })
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        let stream = connect_timeout(&self.address)
+            .with_context(|| format!("failed to connect to redis at {}", self.address))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut conn = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        if let Some(password) = &self.password {
+            conn.call(&[b"AUTH", password.as_bytes()])?;
+        }
+        if let Some(db) = self.db {
+            conn.call(&[b"SELECT", db.to_string().as_bytes()])?;
+        }
+        Ok(conn)
+    }
+
+    fn call(&self, args: &[&[u8]]) -> Result<Reply> {
+        let mut conn = self.conn.lock().unwrap();
+        let reply = match conn.as_mut() {
+            Some(conn) => conn.call(args),
+            None => self.connect().and_then(|c| conn.insert(c).call(args)),
+        };
+        // errors of the server leave the connection usable, errors of the connection don't
+        if reply.as_ref().is_err_and(|err| !err.is::<ServerError>()) {
+            *conn = None;
+        }
+        reply
+    }
+}
+
+fn connect_timeout(address: &str) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::ErrorKind::NotFound.into()))
+}
+
+/// An error reply of the server
+#[derive(Debug)]
+struct ServerError(String);
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "redis error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn call(&mut self, args: &[&[u8]]) -> Result<Reply> {
+        let mut command = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            command.extend_from_slice(arg);
+            command.extend_from_slice(b"\r\n");
+        }
+        self.writer.write_all(&command)?;
+        read_reply(&mut self.reader)
+    }
+}
+
+fn read_reply(reader: &mut impl BufRead) -> Result<Reply> {
+    read_nested_reply(reader, 0)
+}
+
+fn read_nested_reply(reader: &mut impl BufRead, depth: usize) -> Result<Reply> {
+    ensure!(depth < MAX_REPLY_DEPTH, "redis reply is nested too deeply");
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        bail!("redis connection closed");
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, value) = line.split_at(line.len().min(1));
+    let reply = match kind {
+        "+" => Reply::Status,
+        "-" => return Err(ServerError(value.to_string()).into()),
+        ":" => Reply::Integer(value.parse()?),
+        "$" => match value.parse::<i64>()? {
+            len if len < 0 => Reply::Bulk(None),
+            len => {
+                let len = usize::try_from(len)?;
+                ensure!(
+                    len <= MAX_BULK_LEN,
+                    "redis reply of {len} bytes is too large"
+                );
+                // the buffer grows with the data received rather than with the announced length
+                let mut data = vec![];
+                reader
+                    .by_ref()
+                    .take(len as u64 + 2)
+                    .read_to_end(&mut data)?;
+                ensure!(
+                    data.len() == len + 2 && data.ends_with(b"\r\n"),
+                    "truncated redis reply"
+                );
+                data.truncate(len);
+                Reply::Bulk(Some(data))
+            }
+        },
+        "*" => match value.parse::<i64>()? {
+            len if len < 0 => Reply::Array(vec![]),
+            len => Reply::Array(
+                (0..len)
+                    .map(|_| read_nested_reply(reader, depth + 1))
+                    .collect::<Result<_>>()?,
+            ),
+        },
+        _ => bail!("invalid redis reply {line:?}"),
+    };
+    Ok(reply)
+}
+
+fn unexpected(reply: Reply) -> anyhow::Error {
+    anyhow::anyhow!("unexpected redis reply {reply:?}")
+}
+
+impl KeyValueStore for RedisStore {
+    fn get(&self, bucket: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.call(&[b"HGET", bucket.as_bytes(), key.as_bytes()])? {
+            Reply::Bulk(value) => Ok(value),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn set(&self, bucket: &str, key: &str, value: &[u8]) -> Result<()> {
+        self.call(&[b"HSET", bucket.as_bytes(), key.as_bytes(), value])?;
+        Ok(())
+    }
+
+    fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        self.call(&[b"HDEL", bucket.as_bytes(), key.as_bytes()])?;
+        Ok(())
+    }
+
+    fn exists(&self, bucket: &str, key: &str) -> Result<bool> {
+        match self.call(&[b"HEXISTS", bucket.as_bytes(), key.as_bytes()])? {
+            Reply::Integer(exists) => Ok(exists == 1),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn list_keys(&self, bucket: &str) -> Result<Vec<String>> {
+        match self.call(&[b"HKEYS", bucket.as_bytes()])? {
+            Reply::Array(keys) => keys
+                .into_iter()
+                .map(|key| match key {
+                    Reply::Bulk(Some(key)) => Ok(String::from_utf8(key)?),
+                    reply => Err(unexpected(reply)),
+                })
+                .collect(),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn increment(&self, bucket: &str, key: &str, delta: u64) -> Result<u64> {
+        let delta = delta.to_string();
+        match self.call(&[
+            b"HINCRBY",
+            bucket.as_bytes(),
+            key.as_bytes(),
+            delta.as_bytes(),
+        ])? {
+            Reply::Integer(value) => Ok(value.try_into()?),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::keyvalue::tests::check_store;
+
+    // A server implementing the hash commands of the store, on a single connection
+    fn fake_server() -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut hashes: HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>> = HashMap::new();
+            while let Ok(Reply::Array(args)) = read_reply(&mut reader) {
+                let args: Vec<Vec<u8>> = args
+                    .into_iter()
+                    .map(|arg| match arg {
+                        Reply::Bulk(Some(arg)) => arg,
+                        arg => panic!("unexpected argument {arg:?}"),
+                    })
+                    .collect();
+                let hash = hashes.entry(args[1].clone()).or_default();
+                let bulk = |value: &[u8]| {
+                    [format!("${}\r\n", value.len()).as_bytes(), value, b"\r\n"].concat()
+                };
+                let reply = match args[0].as_slice() {
+                    b"SELECT" => b"+OK\r\n".to_vec(),
+                    b"HGET" => match hash.get(&args[2]) {
+                        Some(value) => bulk(value),
+                        None => b"$-1\r\n".to_vec(),
+                    },
+                    b"HSET" => {
+                        hash.insert(args[2].clone(), args[3].clone());
+                        b":1\r\n".to_vec()
+                    }
+                    b"HDEL" => format!(":{}\r\n", hash.remove(&args[2]).is_some() as u8).into(),
+                    b"HEXISTS" => format!(":{}\r\n", hash.contains_key(&args[2]) as u8).into(),
+                    b"HKEYS" => {
+                        let mut reply = format!("*{}\r\n", hash.len()).into_bytes();
+                        hash.keys().for_each(|key| reply.extend(bulk(key)));
+                        reply
+                    }
+                    b"HINCRBY" => {
+                        let value = hash.get(&args[2]).map_or(Some(0), |value| {
+                            String::from_utf8_lossy(value).parse::<i64>().ok()
+                        });
+                        match value {
+                            Some(value) => {
+                                let delta: i64 = String::from_utf8_lossy(&args[3]).parse().unwrap();
+                                let value = value + delta;
+                                hash.insert(args[2].clone(), value.to_string().into_bytes());
+                                format!(":{value}\r\n").into()
+                            }
+                            None => b"-ERR hash value is not an integer\r\n".to_vec(),
+                        }
+                    }
+                    command => panic!("unexpected command {command:?}"),
+                };
+                writer.write_all(&reply).unwrap();
+            }
+        });
+        Ok(address)
+    }
+
+    #[test]
+    fn test_redis_store() -> Result<()> {
+        let address = fake_server()?;
+        let store = RedisStore::new(&format!("redis://{address}/1"))?;
+        check_store(&store)
+    }
+
+    #[test]
+    fn test_redis_url() -> Result<()> {
+        let store = RedisStore::new("redis://:secret@redis.default.svc/2")?;
+        assert_eq!(store.address, "redis.default.svc:6379");
+        assert_eq!(store.password.as_deref(), Some("secret"));
+        assert_eq!(store.db, Some(2));
+
+        let store = RedisStore::new("redis://localhost:6380")?;
+        assert_eq!(store.address, "localhost:6380");
+        assert_eq!(store.password, None);
+        assert_eq!(store.db, None);
+
+        assert!(RedisStore::new("redis://").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_reply() -> Result<()> {
+        let mut reply = Cursor::new("*3\r\n$5\r\nhello\r\n$-1\r\n:42\r\n");
+        assert_eq!(
+            read_reply(&mut reply)?,
+            Reply::Array(vec![
+                Reply::Bulk(Some(b"hello".to_vec())),
+                Reply::Bulk(None),
+                Reply::Integer(42),
+            ])
+        );
+        assert_eq!(read_reply(&mut Cursor::new("+OK\r\n"))?, Reply::Status);
+        assert!(read_reply(&mut Cursor::new("-ERR unknown command\r\n"))
+            .is_err_and(|err| err.is::<ServerError>()));
+
+        // the lengths of the replies are checked before reading them
+        assert!(read_reply(&mut Cursor::new("$1000000000000\r\n")).is_err());
+        assert!(read_reply(&mut Cursor::new("$5\r\nhel")).is_err());
+        assert!(read_reply(&mut Cursor::new("*1\r\n*1\r\n:1\r\n")).is_err());
+        Ok(())
+    }
+}
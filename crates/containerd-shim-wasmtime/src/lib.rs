@@ -1,4 +1,30 @@
+pub mod adapter;
+pub mod clocks;
+pub mod coredump;
+pub mod debug;
+pub mod dns;
+pub mod failure;
+pub mod grpc;
+pub mod http_egress;
+pub mod http_proxy;
 pub mod instance;
+pub mod keyvalue;
+pub mod limits;
+pub mod linking;
+pub mod nn;
+pub mod params;
+pub mod pool;
+pub mod pooling;
+pub mod port_bridge;
+pub mod prewarm;
+pub mod quota;
+pub mod reactor;
+pub mod record;
+pub mod scheduler;
+pub mod shutdown;
+pub mod socket_policy;
+pub mod threads;
+pub mod tls;
 
 pub use instance::WasmtimeInstance;
 
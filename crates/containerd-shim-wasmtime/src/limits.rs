@@ -0,0 +1,398 @@
+//! Resource limits of the container enforced by the wasm engine.
+//!
+//! The limits are taken from the `linux.resources` field of the OCI spec, so they
+//! are honored even when the cgroup of the container is not enforced:
+//!  - `memory.limit` caps the total size of the linear memories of an instance.
+//!    Growing a memory over the limit fails, as it would with a `memory.grow` instruction
+//!    on a memory that reached its maximum size.
+//!  - `cpu.quota` and `cpu.period` are enforced with epoch interruption: an instance that used
+//!    its quota in the current period is paused until the next period starts.
+//!
+//! Every store is counted in the [`PoolStats`] of the container, with the size of its linear
+//! memories, which the shim reports in the stats of the task.
+//!
+//! The `runwasi.io/max-exec-seconds` annotation additionally sets a deadline on the execution
+//! of an instance. An instance still running wasm code after the deadline is terminated, and
+//! the container exits with [`DEADLINE_EXCEEDED_EXIT_CODE`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::container::{InstanceStats, PoolStats};
+use oci_spec::runtime::LinuxResources;
+use wasmtime::{Engine, Store, UpdateDeadline};
+
+use crate::instance::WasiCtx;
+use crate::scheduler;
+use crate::shutdown::{self, ShutdownRequested};
+
+/// Annotation with the maximum duration of the execution of an instance, in seconds
+pub const MAX_EXEC_SECONDS_ANNOTATION: &str = "runwasi.io/max-exec-seconds";
+
+/// Exit code of a container terminated because it exceeded its execution deadline,
+/// the same used by the `timeout` command
+pub const DEADLINE_EXCEEDED_EXIT_CODE: i32 = 124;
+
+/// Error returned when an instance is terminated because it exceeded its execution deadline
+#[derive(Debug)]
+pub struct DeadlineExceeded;
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution deadline exceeded")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Interval at which the engine epoch is incremented
+pub(crate) const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Default CPU period, as used by the CFS scheduler
+const DEFAULT_CPU_PERIOD: Duration = Duration::from_millis(100);
+
+/// Resource limits applied to every store of a container
+#[derive(Clone, Debug, Default)]
+pub struct ResourceLimits {
+    memory_limit: Option<usize>,
+    memory_used: usize,
+    memory_exhausted: bool,
+    cpu: Option<Arc<CpuThrottle>>,
+    max_exec: Option<Duration>,
+    deadline: Option<Instant>,
+    stats: Option<PoolStats>,
+    // the counters of the store the limits apply to, set when the store is created
+    instance: Option<Arc<InstanceStats>>,
+}
+
+impl ResourceLimits {
+    /// Returns the limits configured in the resources of the OCI spec.
+    pub fn from_resources(resources: Option<&LinuxResources>) -> Self {
+        let memory_limit = resources
+            .and_then(|r| r.memory().as_ref())
+            .and_then(|m| m.limit())
+            .and_then(|limit| usize::try_from(limit).ok())
+            .filter(|limit| *limit > 0);
+
+        let cpu = resources.and_then(|r| r.cpu().as_ref()).and_then(|cpu| {
+            let quota = cpu.quota().filter(|q| *q > 0)?;
+            let period = cpu
+                .period()
+                .filter(|p| *p > 0)
+                .map(Duration::from_micros)
+                .unwrap_or(DEFAULT_CPU_PERIOD);
+            Some(Arc::new(CpuThrottle::new(
+                Duration::from_micros(quota as u64),
+                period,
+            )))
+        });
+
+        Self {
+            memory_limit,
+            memory_used: 0,
+            memory_exhausted: false,
+            cpu,
+            max_exec: None,
+            deadline: None,
+            stats: None,
+            instance: None,
+        }
+    }
+
+    /// Whether the memory limit refused to grow a memory of the store
+    pub fn memory_exhausted(&self) -> bool {
+        self.memory_exhausted
+    }
+
+    /// Sets the execution deadline from the `runwasi.io/max-exec-seconds` annotation, if present.
+    pub fn with_annotations(mut self, annotations: &HashMap<String, String>) -> Result<Self> {
+        if let Some(seconds) = annotations.get(MAX_EXEC_SECONDS_ANNOTATION) {
+            let seconds: f64 = seconds
+                .trim()
+                .parse()
+                .ok()
+                .filter(|s: &f64| s.is_finite() && *s > 0.0)
+                .with_context(|| {
+                    format!("invalid value {seconds:?} for {MAX_EXEC_SECONDS_ANNOTATION}")
+                })?;
+            self.max_exec = Some(Duration::from_secs_f64(seconds));
+        }
+        Ok(self)
+    }
+
+    /// Sets the execution deadline of the stores.
+    pub fn with_max_exec(mut self, max_exec: Duration) -> Self {
+        self.max_exec = Some(max_exec);
+        self
+    }
+
+    /// Sets the counters the stores are counted in.
+    pub fn with_stats(mut self, stats: Option<PoolStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Starts the execution deadline again, e.g., when an instance handles a new request.
+    pub fn restart_deadline(&mut self) {
+        self.deadline = self.max_exec.map(|max_exec| Instant::now() + max_exec);
+    }
+}
+
+impl wasmtime::ResourceLimiter for ResourceLimits {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        let used = self.memory_used + (desired - current);
+        if self.memory_limit.is_some_and(|limit| used > limit) {
+            log::info!("memory limit reached, failed to grow memory to {desired} bytes");
+            self.memory_exhausted = true;
+            return Ok(false);
+        }
+        self.memory_used = used;
+        if let Some(instance) = &self.instance {
+            instance.memory_grown((desired - current) as u64);
+        }
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        _desired: u32,
+        _maximum: Option<u32>,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Pauses the execution once the CPU quota of the current period is used.
+/// The throttle is shared by all the stores of a container.
+#[derive(Debug)]
+struct CpuThrottle {
+    quota: Duration,
+    period: Duration,
+    // start of the current period and the time spent running in it
+    usage: Mutex<(Instant, Duration)>,
+}
+
+impl CpuThrottle {
+    fn new(quota: Duration, period: Duration) -> Self {
+        Self {
+            quota,
+            period,
+            usage: Mutex::new((Instant::now(), Duration::ZERO)),
+        }
+    }
+
+    // called on every epoch tick while wasm code is running
+    fn tick(&self) {
+        let pause = self.account(Instant::now());
+        if !pause.is_zero() {
+            // the worker of the store runs the other tasks of the runtime in the meantime
+            scheduler::block_in_place(|| std::thread::sleep(pause));
+        }
+    }
+
+    // Accounts a tick at `now`, and returns how long the store has to pause for.
+    // Once the quota is used, the next period starts in the future, and all the stores of the
+    // container pause until then.
+    fn account(&self, now: Instant) -> Duration {
+        let mut usage = self.usage.lock().unwrap();
+        let (start, used) = &mut *usage;
+        if now < *start {
+            return *start - now;
+        }
+        if now - *start >= self.period {
+            *start = now;
+            *used = Duration::ZERO;
+        }
+        *used += EPOCH_TICK;
+        if *used < self.quota {
+            return Duration::ZERO;
+        }
+        *start += self.period;
+        *used = Duration::ZERO;
+        start.saturating_duration_since(now)
+    }
+}
+
+// increments the epoch of the engine on a background thread, shared by all the engines of the process
+fn start_epoch_ticker(engine: &Engine) {
+    static ENGINES: Mutex<Vec<Engine>> = Mutex::new(Vec::new());
+    static TICKER: OnceLock<()> = OnceLock::new();
+
+    let mut engines = ENGINES.lock().unwrap();
+    if !engines.iter().any(|e| Engine::same(e, engine)) {
+        engines.push(engine.clone());
+    }
+    drop(engines);
+
+    TICKER.get_or_init(|| {
+        std::thread::spawn(|| loop {
+            std::thread::sleep(EPOCH_TICK);
+            ENGINES
+                .lock()
+                .unwrap()
+                .iter()
+                .for_each(Engine::increment_epoch);
+        });
+    });
+}
+
+/// Creates a store for `ctx` that enforces its resource limits.
+/// When `ctx` has a shutdown export, the store is also interrupted on shutdown requests.
+/// The store yields to the other tasks of its runtime every time slice, see the `scheduler`
+/// module. The engine must have epoch interruption enabled.
+/// The execution deadline, if any, starts when the store is created.
+pub(crate) fn new_store(engine: &Engine, mut ctx: WasiCtx) -> Store<WasiCtx> {
+    let cpu = ctx.limits.cpu.clone();
+    ctx.limits.restart_deadline();
+    ctx.limits.instance = ctx
+        .limits
+        .stats
+        .as_ref()
+        .map(|stats| Arc::new(stats.instance_created()));
+    let mut store = Store::new(engine, ctx);
+    store.limiter(|ctx| &mut ctx.limits);
+
+    start_epoch_ticker(engine);
+    let time_slice = scheduler::time_slice();
+    let mut ticks = 0;
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |ctx| {
+        if ctx
+            .data()
+            .limits
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            log::info!("terminating instance: execution deadline exceeded");
+            return Err(DeadlineExceeded.into());
+        }
+        if ctx.data().shutdown_export.is_some() && shutdown::take_request() {
+            log::info!("interrupting instance: shutdown requested");
+            return Err(ShutdownRequested.into());
+        }
+        if let Some(cpu) = &cpu {
+            cpu.tick();
+        }
+        ticks += 1;
+        if ticks < time_slice {
+            return Ok(UpdateDeadline::Continue(1));
+        }
+        ticks = 0;
+        // yield to let other tasks, e.g., concurrent wasi:http requests, make progress
+        Ok(UpdateDeadline::Yield(1))
+    });
+    store
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{LinuxCpuBuilder, LinuxMemoryBuilder, LinuxResourcesBuilder};
+    use wasmtime::ResourceLimiter;
+
+    use super::*;
+
+    #[test]
+    fn test_no_limits() {
+        let limits = ResourceLimits::from_resources(None);
+        assert_eq!(limits.memory_limit, None);
+        assert!(limits.cpu.is_none());
+    }
+
+    #[test]
+    fn test_limits_from_resources() -> Result<()> {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(1024 * 1024).build()?)
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .quota(50_000)
+                    .period(100_000_u64)
+                    .build()?,
+            )
+            .build()?;
+        let limits = ResourceLimits::from_resources(Some(&resources));
+        assert_eq!(limits.memory_limit, Some(1024 * 1024));
+
+        let cpu = limits.cpu.unwrap();
+        assert_eq!(cpu.quota, Duration::from_millis(50));
+        assert_eq!(cpu.period, Duration::from_millis(100));
+
+        // an unlimited memory
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(-1).build()?)
+            .build()?;
+        assert_eq!(
+            ResourceLimits::from_resources(Some(&resources)).memory_limit,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_exec_annotation() -> Result<()> {
+        let annotations =
+            HashMap::from([(MAX_EXEC_SECONDS_ANNOTATION.to_string(), "1.5".to_string())]);
+        let limits = ResourceLimits::default().with_annotations(&annotations)?;
+        assert_eq!(limits.max_exec, Some(Duration::from_millis(1500)));
+
+        for value in ["0", "-1", "soon", "inf"] {
+            let annotations =
+                HashMap::from([(MAX_EXEC_SECONDS_ANNOTATION.to_string(), value.to_string())]);
+            assert!(ResourceLimits::default()
+                .with_annotations(&annotations)
+                .is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpu_throttle() {
+        let cpu = CpuThrottle::new(2 * EPOCH_TICK, Duration::from_millis(100));
+        let start = cpu.usage.lock().unwrap().0;
+
+        assert_eq!(cpu.account(start), Duration::ZERO);
+        // the quota is used, the store pauses until the next period
+        assert_eq!(
+            cpu.account(start + EPOCH_TICK),
+            Duration::from_millis(100) - EPOCH_TICK
+        );
+        // and so do the other stores of the container
+        assert_eq!(
+            cpu.account(start + 4 * EPOCH_TICK),
+            Duration::from_millis(100) - 4 * EPOCH_TICK
+        );
+        assert_eq!(
+            cpu.account(start + Duration::from_millis(100)),
+            Duration::ZERO
+        );
+        // a new period starts after a period without ticks
+        assert_eq!(
+            cpu.account(start + Duration::from_millis(250)),
+            Duration::ZERO
+        );
+        assert_eq!(cpu.usage.lock().unwrap().1, EPOCH_TICK);
+    }
+
+    #[test]
+    fn test_memory_limit_is_shared_by_memories() -> Result<()> {
+        let mut limits = ResourceLimits {
+            memory_limit: Some(3 * 65536),
+            ..Default::default()
+        };
+        assert!(limits.memory_growing(0, 65536, None)?);
+        assert!(limits.memory_growing(0, 65536, None)?);
+        assert!(!limits.memory_exhausted());
+        assert!(!limits.memory_growing(65536, 3 * 65536, None)?);
+        assert!(limits.memory_exhausted());
+        assert!(limits.memory_growing(65536, 2 * 65536, None)?);
+        Ok(())
+    }
+}
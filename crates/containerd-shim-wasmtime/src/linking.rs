@@ -0,0 +1,95 @@
+//! Linking of the module layers of an image.
+//!
+//! An image can ship libraries as additional module layers, with the
+//! `runwasi.io/module-name` annotation on their descriptor naming the module they implement.
+//! The libraries are instantiated in the order of the layers, and their exports satisfy the
+//! imports from that module name of the following layers, e.g., a layer annotated with
+//! `runwasi.io/module-name: libc` provides the `(import "libc" ...)` of the entrypoint.
+//! Libraries following the WASI reactor convention are initialized with their `_initialize`
+//! export before the next layer is instantiated.
+//!
+//! The one layer without the annotation is the entrypoint module of the container.
+
+use anyhow::{bail, Result};
+use containerd_shim_wasm::sandbox::WasmLayer;
+
+/// Layer annotation with the name under which the exports of a library module are linked
+pub const MODULE_NAME_ANNOTATION: &str = "runwasi.io/module-name";
+
+/// Returns whether the layer is a library of the entrypoint module
+pub(crate) fn is_library(layer: &WasmLayer) -> bool {
+    layer
+        .config
+        .annotations()
+        .as_ref()
+        .is_some_and(|a| a.contains_key(MODULE_NAME_ANNOTATION))
+}
+
+/// Splits the layers of an image into the entrypoint and its libraries, in linking order.
+pub(crate) fn split_layers(layers: &[WasmLayer]) -> Result<(&WasmLayer, Vec<(&str, &WasmLayer)>)> {
+    let mut main = None;
+    let mut libraries = vec![];
+    for layer in layers {
+        let name = layer
+            .config
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(MODULE_NAME_ANNOTATION));
+        match name {
+            Some(name) => libraries.push((name.as_str(), layer)),
+            None if main.is_none() => main = Some(layer),
+            None => bail!(
+                "only one layer can be the entrypoint, annotate the others with {MODULE_NAME_ANNOTATION}"
+            ),
+        }
+    }
+    match main {
+        Some(main) => Ok((main, libraries)),
+        None => bail!(
+            "no entrypoint layer, all the layers have the {MODULE_NAME_ANNOTATION} annotation"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use oci_spec::image::{DescriptorBuilder, MediaType};
+
+    use super::*;
+
+    fn layer(name: Option<&str>) -> WasmLayer {
+        let mut config = DescriptorBuilder::default()
+            .media_type(MediaType::Other(
+                "application/vnd.wasm.content.layer.v1+wasm".into(),
+            ))
+            .digest("sha256:000")
+            .size(0)
+            .build()
+            .unwrap();
+        if let Some(name) = name {
+            config.set_annotations(Some(HashMap::from([(
+                MODULE_NAME_ANNOTATION.to_string(),
+                name.to_string(),
+            )])));
+        }
+        WasmLayer {
+            config,
+            layer: vec![].into(),
+        }
+    }
+
+    #[test]
+    fn test_split_layers() -> Result<()> {
+        let layers = [layer(Some("a")), layer(None), layer(Some("b"))];
+        let (main, libraries) = split_layers(&layers)?;
+        assert!(std::ptr::eq(main, &layers[1]));
+        let names: Vec<_> = libraries.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["a", "b"]);
+
+        assert!(split_layers(&[layer(None), layer(None)]).is_err());
+        assert!(split_layers(&[layer(Some("a"))]).is_err());
+        Ok(())
+    }
+}
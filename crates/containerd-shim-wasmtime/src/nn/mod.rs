@@ -0,0 +1,708 @@
+//! Host support for wasi-nn.
+//!
+//! Modules importing the `wasi_ephemeral_nn` functions of the [wasi-nn] proposal can run
+//! inference on the host, through the backends of the engine, one per graph encoding,
+//! e.g., ONNX or OpenVINO. The shim provides the backends of its cargo features:
+//!  - `openvino`: OpenVINO graphs, a `model.xml` and its `model.bin` weights, run with the
+//!    OpenVINO libraries found at runtime, e.g., in `OPENVINO_INSTALL_DIR`.
+//!  - `onnx`: ONNX graphs, a `model.onnx`, run with the ONNX Runtime library of
+//!    `ORT_DYLIB_PATH`, or `libonnxruntime.so` in the search path of the dynamic linker.
+//!
+//! Downstream shims can provide other backends by implementing [`WasiConfig::nn_backends`].
+//! The imports are only linked when the engine has at least one backend.
+//!
+//! Models are usually too large to be embedded in the module, they can instead be mounted in
+//! the container, e.g., from a Kubernetes volume, and listed in the `runwasi.io/wasi-nn-graphs`
+//! annotation as comma separated `<encoding>::<directory>` pairs:
+//!
+//! ```yaml
+//! runwasi.io/wasi-nn-graphs: "onnx::/models/mobilenet, openvino::/models/resnet"
+//! ```
+//!
+//! The guest loads them with `load_by_name`, by the path of their directory, and the backend
+//! loads the graph from the directory when it is first requested. The directories must be
+//! absolute paths in a volume of the container.
+//!
+//! [wasi-nn]: https://github.com/WebAssembly/wasi-nn
+//! [`WasiConfig::nn_backends`]: crate::instance::WasiConfig::nn_backends
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use containerd_shim_wasm::container::Volume;
+use wasmtime::{AsContextMut, Caller, Extern, Linker};
+
+/// Annotation with the graphs that can be loaded by name, as `<encoding>::<directory>` pairs
+pub const NN_GRAPHS_ANNOTATION: &str = "runwasi.io/wasi-nn-graphs";
+
+/// Name of the module the wasi-nn functions are imported from
+const NN_MODULE_NAME: &str = "wasi_ephemeral_nn";
+
+/// Maximum number of graphs a guest can load
+const MAX_GRAPHS: usize = 64;
+
+/// Maximum number of execution contexts a guest can create
+const MAX_CONTEXTS: usize = 256;
+
+#[cfg(feature = "onnx")]
+mod onnx;
+#[cfg(feature = "openvino")]
+mod openvino;
+
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxBackend;
+#[cfg(feature = "openvino")]
+pub use openvino::OpenvinoBackend;
+
+/// The encoding of a graph, which selects the backend that loads it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GraphEncoding {
+    Openvino,
+    Onnx,
+    Tensorflow,
+    Pytorch,
+    Tensorflowlite,
+}
+
+impl GraphEncoding {
+    fn from_raw(raw: u32) -> Option<Self> {
+        Some(match raw {
+            0 => Self::Openvino,
+            1 => Self::Onnx,
+            2 => Self::Tensorflow,
+            3 => Self::Pytorch,
+            4 => Self::Tensorflowlite,
+            _ => return None,
+        })
+    }
+}
+
+impl FromStr for GraphEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(encoding: &str) -> Result<Self> {
+        Ok(match encoding {
+            "openvino" => Self::Openvino,
+            "onnx" => Self::Onnx,
+            "tensorflow" => Self::Tensorflow,
+            "pytorch" => Self::Pytorch,
+            "tensorflowlite" => Self::Tensorflowlite,
+            _ => bail!("unknown graph encoding {encoding:?}"),
+        })
+    }
+}
+
+/// The device a graph is run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionTarget {
+    Cpu,
+    Gpu,
+    Tpu,
+}
+
+impl ExecutionTarget {
+    fn from_raw(raw: u32) -> Option<Self> {
+        Some(match raw {
+            0 => Self::Cpu,
+            1 => Self::Gpu,
+            2 => Self::Tpu,
+            _ => return None,
+        })
+    }
+}
+
+/// The type of the elements of a tensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TensorType {
+    F16,
+    F32,
+    F64,
+    U8,
+    I32,
+    I64,
+}
+
+impl TensorType {
+    fn from_raw(raw: u8) -> Option<Self> {
+        Some(match raw {
+            0 => Self::F16,
+            1 => Self::F32,
+            2 => Self::F64,
+            3 => Self::U8,
+            4 => Self::I32,
+            5 => Self::I64,
+            _ => return None,
+        })
+    }
+}
+
+/// An input of a graph, its data is the little endian bytes of its elements
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    pub dimensions: Vec<u32>,
+    pub ty: TensorType,
+    pub data: Vec<u8>,
+}
+
+/// A wasi-nn backend, loading the graphs of one encoding.
+pub trait Backend: Send + Sync {
+    fn encoding(&self) -> GraphEncoding;
+
+    /// Loads a graph from the builders passed by the guest, e.g., the model and its weights.
+    fn load(&self, builders: &[&[u8]], target: ExecutionTarget) -> Result<Box<dyn Graph>>;
+
+    /// Loads a graph from a directory of the `runwasi.io/wasi-nn-graphs` annotation.
+    fn load_from_dir(&self, dir: &Path, target: ExecutionTarget) -> Result<Box<dyn Graph>>;
+}
+
+/// A loaded graph.
+pub trait Graph: Send + Sync {
+    fn init_execution_context(&self) -> Result<Box<dyn ExecutionContext>>;
+}
+
+/// An inference session of a graph.
+pub trait ExecutionContext: Send {
+    fn set_input(&mut self, index: u32, tensor: Tensor) -> Result<()>;
+
+    fn compute(&mut self) -> Result<()>;
+
+    /// Returns the data of an output tensor.
+    fn get_output(&mut self, index: u32) -> Result<Vec<u8>>;
+}
+
+/// The backends of an engine
+pub type Backends = Arc<[Arc<dyn Backend>]>;
+
+/// Returns the backends of the cargo features of the shim.
+pub fn backends() -> Vec<Arc<dyn Backend>> {
+    vec![
+        #[cfg(feature = "openvino")]
+        Arc::new(OpenvinoBackend::default()),
+        #[cfg(feature = "onnx")]
+        Arc::new(OnnxBackend),
+    ]
+}
+
+/// The wasi-nn state of a store: the graphs the guest has loaded, and their execution contexts.
+#[derive(Default)]
+pub struct WasiNnCtx {
+    backends: Option<Backends>,
+    named: HashMap<String, (GraphEncoding, PathBuf)>,
+    loaded: HashMap<String, u32>,
+    graphs: Vec<Arc<dyn Graph>>,
+    contexts: Vec<Box<dyn ExecutionContext>>,
+}
+
+impl WasiNnCtx {
+    /// Creates the state of a container, with the graphs of its annotations, whose directories
+    /// are resolved through the `preopens` of the container.
+    pub fn new(
+        backends: Backends,
+        annotations: &HashMap<String, String>,
+        preopens: &[Volume],
+    ) -> Result<Self> {
+        let mut named = HashMap::new();
+        let graphs = annotations.get(NN_GRAPHS_ANNOTATION).map(String::as_str);
+        for graph in graphs.unwrap_or_default().split(',').map(str::trim) {
+            if graph.is_empty() {
+                continue;
+            }
+            let (encoding, dir) = graph.split_once("::").with_context(|| {
+                format!("invalid {NN_GRAPHS_ANNOTATION} annotation {graph:?}, expected `<encoding>::<directory>`")
+            })?;
+            let encoding = encoding.parse()?;
+            named.insert(dir.to_string(), (encoding, host_path(preopens, dir)?));
+        }
+        Ok(Self {
+            backends: Some(backends),
+            named,
+            ..Default::default()
+        })
+    }
+
+    fn backend(&self, encoding: GraphEncoding) -> Result<&Arc<dyn Backend>, NnErrno> {
+        self.backends
+            .iter()
+            .flat_map(|backends| backends.iter())
+            .find(|backend| backend.encoding() == encoding)
+            .ok_or(NnErrno::InvalidEncoding)
+    }
+
+    fn push_graph(&mut self, graph: Box<dyn Graph>) -> u32 {
+        self.graphs.push(graph.into());
+        self.graphs.len() as u32 - 1
+    }
+
+    fn check_graphs(&self) -> Result<(), NnErrno> {
+        match self.graphs.len() < MAX_GRAPHS {
+            true => Ok(()),
+            false => Err(NnErrno::TooLarge),
+        }
+    }
+
+    fn load(
+        &mut self,
+        builders: &[&[u8]],
+        encoding: GraphEncoding,
+        target: ExecutionTarget,
+    ) -> Result<u32, NnErrno> {
+        self.check_graphs()?;
+        let graph = self
+            .backend(encoding)?
+            .load(builders, target)
+            .map_err(runtime_error)?;
+        Ok(self.push_graph(graph))
+    }
+
+    fn load_by_name(&mut self, name: &str) -> Result<u32, NnErrno> {
+        if let Some(graph) = self.loaded.get(name) {
+            return Ok(*graph);
+        }
+        let (encoding, dir) = self.named.get(name).ok_or(NnErrno::NotFound)?;
+        self.check_graphs()?;
+        log::info!("loading wasi-nn graph {name:?}");
+        let graph = self
+            .backend(*encoding)?
+            .load_from_dir(dir, ExecutionTarget::Cpu)
+            .map_err(runtime_error)?;
+        let graph = self.push_graph(graph);
+        self.loaded.insert(name.to_string(), graph);
+        Ok(graph)
+    }
+
+    fn init_execution_context(&mut self, graph: u32) -> Result<u32, NnErrno> {
+        if self.contexts.len() >= MAX_CONTEXTS {
+            return Err(NnErrno::TooLarge);
+        }
+        let graph = self
+            .graphs
+            .get(graph as usize)
+            .ok_or(NnErrno::InvalidArgument)?;
+        let context = graph.init_execution_context().map_err(runtime_error)?;
+        self.contexts.push(context);
+        Ok(self.contexts.len() as u32 - 1)
+    }
+
+    fn context(&mut self, context: u32) -> Result<&mut Box<dyn ExecutionContext>, NnErrno> {
+        self.contexts
+            .get_mut(context as usize)
+            .ok_or(NnErrno::InvalidArgument)
+    }
+}
+
+// Path on the host of a graph directory, in the preopen with the longest guest path containing it.
+// The directory must be a normalized absolute path, so that it can't escape the preopen.
+fn host_path(preopens: &[Volume], dir: &str) -> Result<PathBuf> {
+    let dir = Path::new(dir);
+    let normalized = dir
+        .components()
+        .all(|component| matches!(component, Component::RootDir | Component::Normal(_)));
+    if !dir.is_absolute() || !normalized {
+        bail!("wasi-nn graph directory {dir:?} must be a normalized absolute path");
+    }
+    preopens
+        .iter()
+        .filter_map(|volume| Some((volume, dir.strip_prefix(&volume.guest_path).ok()?)))
+        .max_by_key(|(volume, _)| volume.guest_path.components().count())
+        .map(|(volume, rest)| volume.host_path.join(rest))
+        .with_context(|| format!("wasi-nn graph directory {dir:?} is not in the container"))
+}
+
+/// Errors returned to the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NnErrno {
+    InvalidArgument = 1,
+    InvalidEncoding = 2,
+    MissingMemory = 3,
+    RuntimeError = 5,
+    TooLarge = 7,
+    NotFound = 8,
+}
+
+impl fmt::Display for NnErrno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+fn runtime_error(err: anyhow::Error) -> NnErrno {
+    log::warn!("wasi-nn backend error: {err:#}");
+    NnErrno::RuntimeError
+}
+
+fn errno(result: Result<(), NnErrno>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(errno) => errno as i32,
+    }
+}
+
+// Accessors of the guest memory, failing with `InvalidArgument` when out of bounds.
+fn bytes(memory: &[u8], ptr: u32, len: u32) -> Result<&[u8], NnErrno> {
+    memory
+        .get(range(ptr, len as usize)?)
+        .ok_or(NnErrno::InvalidArgument)
+}
+
+fn bytes_mut(memory: &mut [u8], ptr: u32, len: usize) -> Result<&mut [u8], NnErrno> {
+    memory
+        .get_mut(range(ptr, len)?)
+        .ok_or(NnErrno::InvalidArgument)
+}
+
+fn range(ptr: u32, len: usize) -> Result<std::ops::Range<usize>, NnErrno> {
+    let start = ptr as usize;
+    let end = start.checked_add(len).ok_or(NnErrno::InvalidArgument)?;
+    Ok(start..end)
+}
+
+// Pointer to the field at `offset` of the struct at `ptr`.
+fn field(ptr: u32, offset: u32) -> Result<u32, NnErrno> {
+    ptr.checked_add(offset).ok_or(NnErrno::InvalidArgument)
+}
+
+fn read_u32(memory: &[u8], ptr: u32) -> Result<u32, NnErrno> {
+    let bytes = bytes(memory, ptr, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn write_u32(memory: &mut [u8], ptr: u32, value: u32) -> Result<(), NnErrno> {
+    bytes_mut(memory, ptr, 4)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+// Reads a `list` of the witx ABI, a pointer and a length.
+fn read_list(memory: &[u8], ptr: u32, elem_size: u32) -> Result<&[u8], NnErrno> {
+    let list = read_u32(memory, ptr)?;
+    let len = read_u32(memory, field(ptr, 4)?)?;
+    bytes(
+        memory,
+        list,
+        len.checked_mul(elem_size).ok_or(NnErrno::TooLarge)?,
+    )
+}
+
+fn read_tensor(memory: &[u8], ptr: u32) -> Result<Tensor, NnErrno> {
+    let dimensions = read_list(memory, ptr, 4)?
+        .chunks_exact(4)
+        .map(|dim| u32::from_le_bytes(dim.try_into().unwrap()))
+        .collect();
+    let ty = bytes(memory, field(ptr, 8)?, 1)?[0];
+    let ty = TensorType::from_raw(ty).ok_or(NnErrno::InvalidArgument)?;
+    let data = read_list(memory, field(ptr, 12)?, 1)?.to_vec();
+    Ok(Tensor {
+        dimensions,
+        ty,
+        data,
+    })
+}
+
+// Calls `f` with the memory of the guest and its wasi-nn state.
+fn with_memory<T, R>(
+    caller: &mut Caller<'_, T>,
+    get: fn(&mut T) -> &mut WasiNnCtx,
+    f: impl FnOnce(&mut [u8], &mut WasiNnCtx) -> Result<R, NnErrno>,
+) -> Result<R, NnErrno> {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return Err(NnErrno::MissingMemory);
+    };
+    let (memory, data) = memory.data_and_store_mut(caller.as_context_mut());
+    f(memory, get(data))
+}
+
+/// Adds the `wasi_ephemeral_nn` functions to the linker of modules.
+pub fn add_to_linker<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut WasiNnCtx,
+) -> Result<()> {
+    linker.func_wrap(
+        NN_MODULE_NAME,
+        "load",
+        move |mut caller: Caller<'_, T>,
+              builders: u32,
+              builders_len: u32,
+              encoding: u32,
+              target: u32,
+              graph: u32| {
+            errno(with_memory(&mut caller, get, |memory, nn| {
+                let encoding = GraphEncoding::from_raw(encoding).ok_or(NnErrno::InvalidEncoding)?;
+                let target = ExecutionTarget::from_raw(target).ok_or(NnErrno::InvalidArgument)?;
+                let builders = (0..builders_len)
+                    .map(|i| {
+                        let offset = i.checked_mul(8).ok_or(NnErrno::InvalidArgument)?;
+                        read_list(memory, field(builders, offset)?, 1)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let handle = nn.load(&builders, encoding, target)?;
+                write_u32(memory, graph, handle)
+            }))
+        },
+    )?;
+    linker.func_wrap(
+        NN_MODULE_NAME,
+        "load_by_name",
+        move |mut caller: Caller<'_, T>, name: u32, name_len: u32, graph: u32| {
+            errno(with_memory(&mut caller, get, |memory, nn| {
+                let name = std::str::from_utf8(bytes(memory, name, name_len)?)
+                    .map_err(|_| NnErrno::InvalidArgument)?
+                    .to_string();
+                let handle = nn.load_by_name(&name)?;
+                write_u32(memory, graph, handle)
+            }))
+        },
+    )?;
+    linker.func_wrap(
+        NN_MODULE_NAME,
+        "init_execution_context",
+        move |mut caller: Caller<'_, T>, graph: u32, context: u32| {
+            errno(with_memory(&mut caller, get, |memory, nn| {
+                let handle = nn.init_execution_context(graph)?;
+                write_u32(memory, context, handle)
+            }))
+        },
+    )?;
+    linker.func_wrap(
+        NN_MODULE_NAME,
+        "set_input",
+        move |mut caller: Caller<'_, T>, context: u32, index: u32, tensor: u32| {
+            errno(with_memory(&mut caller, get, |memory, nn| {
+                let tensor = read_tensor(memory, tensor)?;
+                nn.context(context)?
+                    .set_input(index, tensor)
+                    .map_err(runtime_error)
+            }))
+        },
+    )?;
+    linker.func_wrap(
+        NN_MODULE_NAME,
+        "compute",
+        move |mut caller: Caller<'_, T>, context: u32| {
+            let nn = get(caller.data_mut());
+            errno(
+                nn.context(context)
+                    .and_then(|context| context.compute().map_err(runtime_error)),
+            )
+        },
+    )?;
+    linker.func_wrap(
+        NN_MODULE_NAME,
+        "get_output",
+        move |mut caller: Caller<'_, T>,
+              context: u32,
+              index: u32,
+              buf: u32,
+              buf_len: u32,
+              written: u32| {
+            errno(with_memory(&mut caller, get, |memory, nn| {
+                let output = nn
+                    .context(context)?
+                    .get_output(index)
+                    .map_err(runtime_error)?;
+                if output.len() > buf_len as usize {
+                    return Err(NnErrno::TooLarge);
+                }
+                bytes_mut(memory, buf, output.len())?.copy_from_slice(&output);
+                write_u32(memory, written, output.len() as u32)
+            }))
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use wasmtime::{Engine, Module, Store};
+
+    use super::*;
+
+    // A backend whose graphs add the bias read from the `bias` file of their directory to
+    // each byte of their input.
+    struct AddBackend;
+
+    struct AddGraph(u8);
+
+    struct AddContext(u8, Vec<u8>);
+
+    impl Backend for AddBackend {
+        fn encoding(&self) -> GraphEncoding {
+            GraphEncoding::Onnx
+        }
+
+        fn load(&self, builders: &[&[u8]], _: ExecutionTarget) -> Result<Box<dyn Graph>> {
+            Ok(Box::new(AddGraph(builders[0][0])))
+        }
+
+        fn load_from_dir(&self, dir: &Path, _: ExecutionTarget) -> Result<Box<dyn Graph>> {
+            Ok(Box::new(AddGraph(std::fs::read(dir.join("bias"))?[0])))
+        }
+    }
+
+    impl Graph for AddGraph {
+        fn init_execution_context(&self) -> Result<Box<dyn ExecutionContext>> {
+            Ok(Box::new(AddContext(self.0, vec![])))
+        }
+    }
+
+    impl ExecutionContext for AddContext {
+        fn set_input(&mut self, _: u32, tensor: Tensor) -> Result<()> {
+            self.1 = tensor.data;
+            Ok(())
+        }
+
+        fn compute(&mut self) -> Result<()> {
+            let bias = self.0;
+            self.1.iter_mut().for_each(|byte| *byte += bias);
+            Ok(())
+        }
+
+        fn get_output(&mut self, _: u32) -> Result<Vec<u8>> {
+            Ok(self.1.clone())
+        }
+    }
+
+    // Loads the graph named "/models/add", runs it on [1, 2, 3], and returns the errno of
+    // the first call that fails, or the sum of the output
+    const MODULE: &str = r#"(module
+        (import "wasi_ephemeral_nn" "load_by_name" (func $load_by_name (param i32 i32 i32) (result i32)))
+        (import "wasi_ephemeral_nn" "init_execution_context" (func $init (param i32 i32) (result i32)))
+        (import "wasi_ephemeral_nn" "set_input" (func $set_input (param i32 i32 i32) (result i32)))
+        (import "wasi_ephemeral_nn" "compute" (func $compute (param i32) (result i32)))
+        (import "wasi_ephemeral_nn" "get_output" (func $get_output (param i32 i32 i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        ;; name of the graph
+        (data (i32.const 0) "/models/add")
+        ;; dimensions: [3]
+        (data (i32.const 16) "\03\00\00\00")
+        ;; tensor: dimensions (16, 1), type u8, data (56, 3)
+        (data (i32.const 32) "\10\00\00\00\01\00\00\00\03\00\00\00\38\00\00\00\03\00\00\00")
+        (data (i32.const 56) "\01\02\03")
+        (func (export "run") (result i32)
+            (local $errno i32)
+            (local.set $errno (call $load_by_name (i32.const 0) (i32.const 11) (i32.const 64)))
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (local.set $errno (call $init (i32.load (i32.const 64)) (i32.const 68)))
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (local.set $errno (call $set_input (i32.load (i32.const 68)) (i32.const 0) (i32.const 32)))
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (local.set $errno (call $compute (i32.load (i32.const 68))))
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (local.set $errno (call $get_output (i32.load (i32.const 68)) (i32.const 0) (i32.const 80) (i32.const 16) (i32.const 72)))
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (i32.add
+                (i32.add (i32.load8_u (i32.const 80)) (i32.load8_u (i32.const 81)))
+                (i32.load8_u (i32.const 82)))
+        )
+    )"#;
+
+    fn run(nn: WasiNnCtx) -> Result<i32> {
+        let engine = Engine::default();
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker, |nn: &mut WasiNnCtx| nn)?;
+        let module = Module::new(&engine, MODULE)?;
+        let mut store = Store::new(&engine, nn);
+        let instance = linker.instantiate(&mut store, &module)?;
+        let run = instance.get_typed_func::<(), i32>(&mut store, "run")?;
+        run.call(&mut store, ())
+    }
+
+    #[test]
+    fn test_load_by_name() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::create_dir(dir.path().join("add"))?;
+        std::fs::write(dir.path().join("add/bias"), [10])?;
+
+        let backends: Backends = Arc::new([Arc::new(AddBackend) as Arc<dyn Backend>]);
+        let preopens = [Volume {
+            host_path: dir.path().to_path_buf(),
+            guest_path: "/models".into(),
+            readonly: true,
+        }];
+        let annotations = HashMap::from([(
+            NN_GRAPHS_ANNOTATION.to_string(),
+            "onnx::/models/add".to_string(),
+        )]);
+
+        let nn = WasiNnCtx::new(backends.clone(), &annotations, &preopens)?;
+        assert_eq!(run(nn)?, 11 + 12 + 13);
+
+        // graphs that aren't in the annotation can't be loaded
+        let nn = WasiNnCtx::new(backends.clone(), &HashMap::new(), &preopens)?;
+        assert_eq!(run(nn)?, NnErrno::NotFound as i32);
+
+        // nor those without a backend for their encoding
+        let annotations = HashMap::from([(
+            NN_GRAPHS_ANNOTATION.to_string(),
+            "openvino::/models/add".to_string(),
+        )]);
+        let nn = WasiNnCtx::new(backends.clone(), &annotations, &preopens)?;
+        assert_eq!(run(nn)?, NnErrno::InvalidEncoding as i32);
+
+        let annotations =
+            HashMap::from([(NN_GRAPHS_ANNOTATION.to_string(), "/models/add".to_string())]);
+        assert!(WasiNnCtx::new(backends, &annotations, &preopens).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_path() -> Result<()> {
+        let preopens = [Volume {
+            host_path: "/var/lib/models".into(),
+            guest_path: "/models".into(),
+            readonly: true,
+        }];
+        assert_eq!(
+            host_path(&preopens, "/models/add")?,
+            Path::new("/var/lib/models/add")
+        );
+        assert!(host_path(&preopens, "/models/../etc").is_err());
+        assert!(host_path(&preopens, "models/add").is_err());
+        assert!(host_path(&preopens, "/data/add").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_bounds() {
+        let mut memory = [0u8; 16];
+        assert_eq!(bytes(&memory, 8, 8), Ok(&[0u8; 8][..]));
+        assert_eq!(bytes(&memory, 12, 8), Err(NnErrno::InvalidArgument));
+        assert_eq!(bytes(&memory, u32::MAX, 2), Err(NnErrno::InvalidArgument));
+        assert_eq!(
+            write_u32(&mut memory, u32::MAX - 1, 1),
+            Err(NnErrno::InvalidArgument)
+        );
+        assert_eq!(
+            read_list(&memory, u32::MAX - 3, 1),
+            Err(NnErrno::InvalidArgument)
+        );
+        assert_eq!(
+            read_tensor(&memory, u32::MAX - 7).map(|_| ()),
+            Err(NnErrno::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_limits() -> Result<()> {
+        let backends: Backends = Arc::new([Arc::new(AddBackend) as Arc<dyn Backend>]);
+        let mut nn = WasiNnCtx::new(backends, &HashMap::new(), &[])?;
+        let builders: [&[u8]; 1] = [&[1]];
+        for _ in 0..MAX_GRAPHS {
+            assert!(nn
+                .load(&builders, GraphEncoding::Onnx, ExecutionTarget::Cpu)
+                .is_ok());
+        }
+        assert_eq!(
+            nn.load(&builders, GraphEncoding::Onnx, ExecutionTarget::Cpu),
+            Err(NnErrno::TooLarge)
+        );
+        for _ in 0..MAX_CONTEXTS {
+            assert!(nn.init_execution_context(0).is_ok());
+        }
+        assert_eq!(nn.init_execution_context(0), Err(NnErrno::TooLarge));
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! The ONNX backend, whose graphs are an ONNX model, run with ONNX Runtime.
+
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use ort::{DynValue, PrimitiveTensorElementType, Session, TensorElementType, Value, ValueType};
+
+use super::{Backend, ExecutionContext, ExecutionTarget, Graph, GraphEncoding, Tensor, TensorType};
+
+/// Loads ONNX graphs with the ONNX Runtime library of `ORT_DYLIB_PATH`, or `libonnxruntime.so`
+/// in the search path of the dynamic linker. The graphs run on the CPU.
+pub struct OnnxBackend;
+
+impl Backend for OnnxBackend {
+    fn encoding(&self) -> GraphEncoding {
+        GraphEncoding::Onnx
+    }
+
+    fn load(&self, builders: &[&[u8]], _target: ExecutionTarget) -> Result<Box<dyn Graph>> {
+        let [model] = builders else {
+            bail!("expected the model, got {} builders", builders.len());
+        };
+        let session = Session::builder()
+            .context("failed to load ONNX Runtime")?
+            .commit_from_memory(model)?;
+        Ok(Box::new(OnnxGraph(Arc::new(session))))
+    }
+
+    fn load_from_dir(&self, dir: &Path, target: ExecutionTarget) -> Result<Box<dyn Graph>> {
+        let model = std::fs::read(dir.join("model.onnx"))?;
+        self.load(&[&model], target)
+    }
+}
+
+struct OnnxGraph(Arc<Session>);
+
+impl Graph for OnnxGraph {
+    fn init_execution_context(&self) -> Result<Box<dyn ExecutionContext>> {
+        Ok(Box::new(OnnxExecutionContext {
+            session: self.0.clone(),
+            inputs: self.0.inputs.iter().map(|_| None).collect(),
+            outputs: self.0.outputs.iter().map(|_| None).collect(),
+        }))
+    }
+}
+
+struct OnnxExecutionContext {
+    session: Arc<Session>,
+    inputs: Vec<Option<DynValue>>,
+    outputs: Vec<Option<Vec<u8>>>,
+}
+
+impl ExecutionContext for OnnxExecutionContext {
+    fn set_input(&mut self, index: u32, tensor: Tensor) -> Result<()> {
+        let input = self
+            .inputs
+            .get_mut(index as usize)
+            .with_context(|| format!("the graph has no input {index}"))?;
+        let dimensions: Vec<i64> = tensor.dimensions.iter().map(|dim| *dim as i64).collect();
+        let value = match tensor.ty {
+            TensorType::F32 => value(dimensions, &tensor.data, f32::from_le_bytes)?,
+            TensorType::F64 => value(dimensions, &tensor.data, f64::from_le_bytes)?,
+            TensorType::U8 => value(dimensions, &tensor.data, u8::from_le_bytes)?,
+            TensorType::I32 => value(dimensions, &tensor.data, i32::from_le_bytes)?,
+            TensorType::I64 => value(dimensions, &tensor.data, i64::from_le_bytes)?,
+            TensorType::F16 => bail!("f16 tensors are not supported by the ONNX backend"),
+        };
+        *input = Some(value);
+        Ok(())
+    }
+
+    fn compute(&mut self) -> Result<()> {
+        let session = &self.session;
+        let inputs = session
+            .inputs
+            .iter()
+            .zip(&self.inputs)
+            .map(|(input, value)| match value {
+                Some(value) => Ok((input.name.clone(), value.view())),
+                None => bail!("missing input {:?}", input.name),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let outputs = session.run(inputs)?;
+        for (index, output) in session.outputs.iter().enumerate() {
+            let output = &outputs[output.name.as_str()];
+            let ty = match output.dtype()? {
+                ValueType::Tensor { ty, .. } => ty,
+                ty => bail!("{ty:?} outputs are not supported by the ONNX backend"),
+            };
+            let bytes = match ty {
+                TensorElementType::Float32 => bytes(output, f32::to_le_bytes)?,
+                TensorElementType::Float64 => bytes(output, f64::to_le_bytes)?,
+                TensorElementType::Uint8 => bytes(output, u8::to_le_bytes)?,
+                TensorElementType::Int32 => bytes(output, i32::to_le_bytes)?,
+                TensorElementType::Int64 => bytes(output, i64::to_le_bytes)?,
+                ty => bail!("{ty:?} outputs are not supported by the ONNX backend"),
+            };
+            self.outputs[index] = Some(bytes);
+        }
+        Ok(())
+    }
+
+    fn get_output(&mut self, index: u32) -> Result<Vec<u8>> {
+        self.outputs
+            .get(index as usize)
+            .cloned()
+            .flatten()
+            .with_context(|| format!("no output {index}, the graph must be computed first"))
+    }
+}
+
+// Decodes the little endian elements of a tensor.
+fn value<T: PrimitiveTensorElementType + Clone + Debug + 'static, const N: usize>(
+    dimensions: Vec<i64>,
+    data: &[u8],
+    from_le_bytes: fn([u8; N]) -> T,
+) -> Result<DynValue> {
+    if data.len() % N != 0 {
+        bail!("the tensor data isn't a whole number of {N} bytes elements");
+    }
+    let elements: Vec<T> = data
+        .chunks_exact(N)
+        .map(|bytes| from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    Ok(Value::from_array((dimensions, elements))?.into_dyn())
+}
+
+// Encodes the elements of an output as little endian bytes.
+fn bytes<T: PrimitiveTensorElementType + Copy, const N: usize>(
+    output: &DynValue,
+    to_le_bytes: fn(T) -> [u8; N],
+) -> Result<Vec<u8>> {
+    let (_, elements) = output.try_extract_raw_tensor::<T>()?;
+    Ok(elements
+        .iter()
+        .flat_map(|element| to_le_bytes(*element))
+        .collect())
+}
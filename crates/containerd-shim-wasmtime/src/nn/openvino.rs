@@ -0,0 +1,107 @@
+//! The OpenVINO backend, whose graphs are an OpenVINO IR model and its weights.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use openvino::{Core, DeviceType, ElementType, InferRequest, Shape};
+
+use super::{Backend, ExecutionContext, ExecutionTarget, Graph, GraphEncoding, Tensor, TensorType};
+
+/// Loads OpenVINO graphs with the OpenVINO libraries found at runtime.
+/// The libraries are only loaded with the first graph, so that the shim runs without them.
+#[derive(Default)]
+pub struct OpenvinoBackend(Mutex<Option<Core>>);
+
+impl Backend for OpenvinoBackend {
+    fn encoding(&self) -> GraphEncoding {
+        GraphEncoding::Openvino
+    }
+
+    fn load(&self, builders: &[&[u8]], target: ExecutionTarget) -> Result<Box<dyn Graph>> {
+        let [xml, weights] = builders else {
+            bail!(
+                "expected the model and its weights, got {} builders",
+                builders.len()
+            );
+        };
+        let mut core = self.0.lock().unwrap();
+        let core = match core.as_mut() {
+            Some(core) => core,
+            None => core.insert(Core::new().context("failed to load OpenVINO")?),
+        };
+
+        let shape = Shape::new(&[weights.len() as i64])?;
+        let mut weights_tensor = openvino::Tensor::new(ElementType::U8, &shape)?;
+        weights_tensor.get_raw_data_mut()?.copy_from_slice(weights);
+        let model = core.read_model_from_buffer(xml, Some(&weights_tensor))?;
+        let compiled = core.compile_model(&model, device(target))?;
+        Ok(Box::new(OpenvinoGraph(Mutex::new(compiled))))
+    }
+
+    fn load_from_dir(&self, dir: &Path, target: ExecutionTarget) -> Result<Box<dyn Graph>> {
+        let xml = std::fs::read(dir.join("model.xml"))?;
+        let weights = std::fs::read(dir.join("model.bin"))?;
+        self.load(&[&xml, &weights], target)
+    }
+}
+
+struct OpenvinoGraph(Mutex<openvino::CompiledModel>);
+
+impl Graph for OpenvinoGraph {
+    fn init_execution_context(&self) -> Result<Box<dyn ExecutionContext>> {
+        let request = self.0.lock().unwrap().create_infer_request()?;
+        Ok(Box::new(OpenvinoExecutionContext(request)))
+    }
+}
+
+struct OpenvinoExecutionContext(InferRequest);
+
+impl ExecutionContext for OpenvinoExecutionContext {
+    fn set_input(&mut self, index: u32, tensor: Tensor) -> Result<()> {
+        let dimensions: Vec<i64> = tensor.dimensions.iter().map(|dim| *dim as i64).collect();
+        let mut input = openvino::Tensor::new(element_type(tensor.ty), &Shape::new(&dimensions)?)?;
+        let data = input.get_raw_data_mut()?;
+        if data.len() != tensor.data.len() {
+            bail!(
+                "expected {} bytes for the dimensions {:?}, got {}",
+                data.len(),
+                tensor.dimensions,
+                tensor.data.len()
+            );
+        }
+        data.copy_from_slice(&tensor.data);
+        self.0.set_input_tensor_by_index(index as usize, &input)?;
+        Ok(())
+    }
+
+    fn compute(&mut self) -> Result<()> {
+        self.0.infer()?;
+        Ok(())
+    }
+
+    fn get_output(&mut self, index: u32) -> Result<Vec<u8>> {
+        let output = self.0.get_output_tensor_by_index(index as usize)?;
+        Ok(output.get_raw_data()?.to_vec())
+    }
+}
+
+fn device(target: ExecutionTarget) -> DeviceType<'static> {
+    match target {
+        ExecutionTarget::Cpu => DeviceType::CPU,
+        ExecutionTarget::Gpu => DeviceType::GPU,
+        // OpenVINO runs the workloads of accelerators on their NPU device
+        ExecutionTarget::Tpu => DeviceType::NPU,
+    }
+}
+
+fn element_type(ty: TensorType) -> ElementType {
+    match ty {
+        TensorType::F16 => ElementType::F16,
+        TensorType::F32 => ElementType::F32,
+        TensorType::F64 => ElementType::F64,
+        TensorType::U8 => ElementType::U8,
+        TensorType::I32 => ElementType::I32,
+        TensorType::I64 => ElementType::I64,
+    }
+}
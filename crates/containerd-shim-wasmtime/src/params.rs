@@ -0,0 +1,134 @@
+//! Parameters of exported functions.
+//!
+//! The functions called by the shim, the entrypoint selected with the
+//! `io.containerd.wasm.entrypoint` annotation or the invocations of a reactor, get their
+//! arguments as strings, which are parsed according to the signature of the function.
+//!
+//! Module functions can have `i32`, `i64`, `f32` and `f64` parameters.
+//! Component functions can also have `bool`, `char` and `string` parameters.
+
+use anyhow::{bail, ensure, Context, Result};
+use wasmtime::component::{Type, Val as ComponentVal};
+use wasmtime::{FuncType, Val, ValType};
+
+/// Parses the arguments of a module function as its parameters.
+pub(crate) fn parse_params(ty: &FuncType, args: &[String]) -> Result<Vec<Val>> {
+    ensure!(
+        ty.params().len() == args.len(),
+        "expected {} arguments, got {}",
+        ty.params().len(),
+        args.len()
+    );
+    ty.params()
+        .zip(args)
+        .map(|(ty, arg)| {
+            let invalid = || format!("invalid {ty} argument {arg:?}");
+            let val = match ty {
+                ValType::I32 => Val::I32(arg.parse().with_context(invalid)?),
+                ValType::I64 => Val::I64(arg.parse().with_context(invalid)?),
+                ValType::F32 => Val::F32(arg.parse::<f32>().with_context(invalid)?.to_bits()),
+                ValType::F64 => Val::F64(arg.parse::<f64>().with_context(invalid)?.to_bits()),
+                ty => bail!("unsupported parameter type {ty}"),
+            };
+            Ok(val)
+        })
+        .collect()
+}
+
+/// Parses the arguments of a component function as its parameters.
+pub(crate) fn parse_component_params(
+    params: &[Type],
+    args: &[String],
+) -> Result<Vec<ComponentVal>> {
+    ensure!(
+        params.len() == args.len(),
+        "expected {} arguments, got {}",
+        params.len(),
+        args.len()
+    );
+    params
+        .iter()
+        .zip(args)
+        .map(|(ty, arg)| {
+            let invalid = || format!("invalid {ty:?} argument {arg:?}");
+            let val = match ty {
+                Type::Bool => ComponentVal::Bool(arg.parse().with_context(invalid)?),
+                Type::S8 => ComponentVal::S8(arg.parse().with_context(invalid)?),
+                Type::U8 => ComponentVal::U8(arg.parse().with_context(invalid)?),
+                Type::S16 => ComponentVal::S16(arg.parse().with_context(invalid)?),
+                Type::U16 => ComponentVal::U16(arg.parse().with_context(invalid)?),
+                Type::S32 => ComponentVal::S32(arg.parse().with_context(invalid)?),
+                Type::U32 => ComponentVal::U32(arg.parse().with_context(invalid)?),
+                Type::S64 => ComponentVal::S64(arg.parse().with_context(invalid)?),
+                Type::U64 => ComponentVal::U64(arg.parse().with_context(invalid)?),
+                Type::Float32 => ComponentVal::Float32(arg.parse().with_context(invalid)?),
+                Type::Float64 => ComponentVal::Float64(arg.parse().with_context(invalid)?),
+                Type::Char => ComponentVal::Char(arg.parse().with_context(invalid)?),
+                Type::String => ComponentVal::String(arg.clone()),
+                ty => bail!("unsupported parameter type {ty:?}"),
+            };
+            Ok(val)
+        })
+        .collect()
+}
+
+/// Formats the results of a module function, separated by spaces.
+pub(crate) fn format_results(results: &[Val]) -> String {
+    results
+        .iter()
+        .map(|val| match val {
+            Val::I32(val) => val.to_string(),
+            Val::I64(val) => val.to_string(),
+            Val::F32(val) => f32::from_bits(*val).to_string(),
+            Val::F64(val) => f64::from_bits(*val).to_string(),
+            val => format!("{val:?}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::Engine;
+
+    use super::*;
+
+    #[test]
+    fn test_params_and_results() -> Result<()> {
+        let engine = Engine::default();
+        let ty = FuncType::new(&engine, [ValType::I32, ValType::F64], [ValType::I64]);
+
+        let params = parse_params(&ty, &["-3".into(), "0.5".into()])?;
+        assert_eq!(params[0].unwrap_i32(), -3);
+        assert_eq!(params[1].unwrap_f64(), 0.5);
+
+        assert!(parse_params(&ty, &["1".into()]).is_err());
+        assert!(parse_params(&ty, &["one".into(), "0.5".into()]).is_err());
+
+        assert_eq!(
+            format_results(&[Val::I64(42), Val::F32(1.5f32.to_bits())]),
+            "42 1.5"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_component_params() -> Result<()> {
+        let params = parse_component_params(
+            &[Type::String, Type::U32, Type::Bool],
+            &["/index.html".into(), "8080".into(), "true".into()],
+        )?;
+        assert_eq!(
+            params,
+            [
+                ComponentVal::String("/index.html".into()),
+                ComponentVal::U32(8080),
+                ComponentVal::Bool(true)
+            ]
+        );
+
+        assert!(parse_component_params(&[Type::U32], &["-1".into()]).is_err());
+        assert!(parse_component_params(&[Type::String], &[]).is_err());
+        Ok(())
+    }
+}
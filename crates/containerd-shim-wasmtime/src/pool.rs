@@ -0,0 +1,207 @@
+//! Pool of ready instances of a component targeting the `wasi:http/proxy` world.
+//!
+//! With the `runwasi.io/concurrency` annotation, e.g. `8`, the requests served by a component are
+//! load balanced across a pool of as many instances, instantiated when the container starts,
+//! rather than handled by a fresh instance each. Every instance handles one request at a time, and
+//! the requests wait for the next ready instance, so that the instances keep their state, e.g.,
+//! their caches, from one request to the next. An instance failing to handle a request, e.g.,
+//! trapping, is replaced by a new one.
+//! The deadline of the `runwasi.io/max-exec-seconds` annotation applies to every request.
+//!
+//! The pool updates the [`PoolStats`] of the container, which the shim reports in the stats of
+//! the task.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::container::{PoolStats, RuntimeContext};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use wasmtime::component::InstancePre;
+use wasmtime::{Engine, Store};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::{HyperIncomingBody, HyperOutgoingBody};
+use wasmtime_wasi_http::proxy::Proxy;
+use wasmtime_wasi_http::WasiHttpView;
+
+use crate::instance::WasiCtx;
+use crate::limits;
+
+/// Annotation used to declare the number of instances serving the requests, e.g. `8`
+pub const CONCURRENCY_ANNOTATION: &str = "runwasi.io/concurrency";
+
+/// Time to wait before instantiating again an instance that failed to be replaced
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+type Response = Result<hyper::Response<HyperOutgoingBody>, ErrorCode>;
+
+/// The size of the pool of a container and the counters it updates
+pub(crate) struct PoolOptions {
+    pub size: usize,
+    pub stats: PoolStats,
+}
+
+impl PoolOptions {
+    /// Returns the options of the `runwasi.io/concurrency` annotation, or `None` without one.
+    pub fn from_ctx(ctx: &impl RuntimeContext) -> Result<Option<Self>> {
+        let Some(size) = concurrency(ctx.annotations())? else {
+            return Ok(None);
+        };
+        let stats = ctx.pool_stats().unwrap_or_default();
+        Ok(Some(Self { size, stats }))
+    }
+}
+
+fn concurrency(annotations: &HashMap<String, String>) -> Result<Option<usize>> {
+    let Some(concurrency) = annotations.get(CONCURRENCY_ANNOTATION) else {
+        return Ok(None);
+    };
+    let size = concurrency
+        .trim()
+        .parse()
+        .ok()
+        .filter(|size| *size > 0)
+        .with_context(|| {
+            format!("invalid value {concurrency:?} for {CONCURRENCY_ANNOTATION}, expected a positive number")
+        })?;
+    Ok(Some(size))
+}
+
+/// A request waiting for an instance
+struct Job {
+    request: hyper::Request<HyperIncomingBody>,
+    response: oneshot::Sender<Response>,
+}
+
+/// The instances of a component serving its requests
+pub(crate) struct InstancePool {
+    jobs: mpsc::UnboundedSender<Job>,
+    stats: PoolStats,
+}
+
+impl InstancePool {
+    /// Instantiates the instances of the pool, and starts serving the requests with them.
+    /// `new_ctx` is called to build the WASI context of every instance.
+    pub async fn new<F>(
+        engine: Engine,
+        pre: InstancePre<WasiCtx>,
+        options: PoolOptions,
+        new_ctx: Arc<F>,
+    ) -> Result<Self>
+    where
+        F: Fn() -> Result<WasiCtx> + Send + Sync + 'static,
+    {
+        let PoolOptions { size, stats } = options;
+        log::info!("serving requests with a pool of {size} instances");
+        stats.set_size(size as u64);
+
+        let (jobs, receiver) = mpsc::unbounded_channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..size {
+            let worker = Worker {
+                engine: engine.clone(),
+                pre: pre.clone(),
+                new_ctx: new_ctx.clone(),
+                jobs: receiver.clone(),
+                stats: stats.clone(),
+            };
+            // the instances are ready before the first request is accepted
+            let instance = worker.instantiate().await?;
+            tokio::task::spawn(worker.run(instance));
+        }
+        Ok(Self { jobs, stats })
+    }
+
+    /// Handles `request` with the next ready instance.
+    pub async fn handle(&self, request: hyper::Request<HyperIncomingBody>) -> Result<Response> {
+        let (response, receiver) = oneshot::channel();
+        self.stats.request_queued();
+        self.jobs
+            .send(Job { request, response })
+            .ok()
+            .context("the instance pool stopped")?;
+        receiver
+            .await
+            .ok()
+            .context("component never invoked `response-outparam::set`")
+    }
+}
+
+struct Worker<F> {
+    engine: Engine,
+    pre: InstancePre<WasiCtx>,
+    new_ctx: Arc<F>,
+    jobs: Arc<Mutex<mpsc::UnboundedReceiver<Job>>>,
+    stats: PoolStats,
+}
+
+impl<F> Worker<F>
+where
+    F: Fn() -> Result<WasiCtx> + Send + Sync + 'static,
+{
+    async fn instantiate(&self) -> Result<(Store<WasiCtx>, Proxy)> {
+        let mut store = limits::new_store(&self.engine, (self.new_ctx)()?);
+        let (proxy, _instance) = Proxy::instantiate_pre(&mut store, &self.pre).await?;
+        self.stats.instance_ready();
+        Ok((store, proxy))
+    }
+
+    // Handles the requests with the instance, and replaces it when it fails, until the pool is
+    // dropped.
+    async fn run(self, mut instance: (Store<WasiCtx>, Proxy)) {
+        loop {
+            let Some(job) = self.jobs.lock().await.recv().await else {
+                return;
+            };
+            self.stats.request_started();
+            let (store, proxy) = &mut instance;
+            let status = handle(store, proxy, job).await;
+            self.stats.request_finished(status.is_err());
+            match status {
+                Ok(()) => self.stats.instance_ready(),
+                Err(err) => {
+                    log::error!(
+                        "error handling wasi:http request, replacing the instance: {err:?}"
+                    );
+                    instance = loop {
+                        match self.instantiate().await {
+                            Ok(instance) => break instance,
+                            Err(err) => {
+                                log::error!("failed to replace the instance: {err:?}");
+                                tokio::time::sleep(RETRY_DELAY).await;
+                            }
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+async fn handle(store: &mut Store<WasiCtx>, proxy: &Proxy, job: Job) -> Result<()> {
+    let request = store.data_mut().new_incoming_request(job.request)?;
+    let response = store.data_mut().new_response_outparam(job.response)?;
+    store.data_mut().limits.restart_deadline();
+    proxy
+        .wasi_http_incoming_handler()
+        .call_handle(&mut *store, request, response)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrency() -> Result<()> {
+        let annotations = |concurrency: &str| {
+            HashMap::from([(CONCURRENCY_ANNOTATION.to_string(), concurrency.to_string())])
+        };
+        assert_eq!(concurrency(&annotations("8"))?, Some(8));
+        assert_eq!(concurrency(&HashMap::new())?, None);
+        assert!(concurrency(&annotations("0")).is_err());
+        assert!(concurrency(&annotations("many")).is_err());
+        Ok(())
+    }
+}
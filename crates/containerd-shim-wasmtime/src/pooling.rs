@@ -0,0 +1,149 @@
+//! Configuration of wasmtime's pooling instance allocator.
+//!
+//! The pooling allocator reserves the memories, tables and stacks of a fixed number
+//! of instances when the engine is created, and reuses them for every new instance.
+//! This makes instantiation much cheaper, e.g., for `wasi:http` components that get a
+//! new instance per request, at the cost of reserving the virtual memory up front.
+//!
+//! The allocator is configured through the environment of the shim:
+//!  - `RUNWASI_WASMTIME_POOLING`: set to `1` or `true` to enable the pooling allocator.
+//!  - `RUNWASI_WASMTIME_POOLING_MAX_INSTANCES`: maximum number of concurrent instances.
+//!  - `RUNWASI_WASMTIME_POOLING_MAX_MEMORY_PAGES`: maximum size of a linear memory, in 64KiB pages.
+//!  - `RUNWASI_WASMTIME_POOLING_MAX_TABLE_ELEMENTS`: maximum number of elements of a table.
+//!
+//! Limits that are not set use wasmtime's defaults.
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::sandbox::node_config;
+use wasmtime::{Config, InstanceAllocationStrategy, PoolingAllocationConfig};
+
+pub const POOLING_ENV: &str = "RUNWASI_WASMTIME_POOLING";
+pub const POOLING_MAX_INSTANCES_ENV: &str = "RUNWASI_WASMTIME_POOLING_MAX_INSTANCES";
+pub const POOLING_MAX_MEMORY_PAGES_ENV: &str = "RUNWASI_WASMTIME_POOLING_MAX_MEMORY_PAGES";
+pub const POOLING_MAX_TABLE_ELEMENTS_ENV: &str = "RUNWASI_WASMTIME_POOLING_MAX_TABLE_ELEMENTS";
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// Tunables of the pooling instance allocator
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolingOptions {
+    pub max_instances: Option<u32>,
+    pub max_memory_pages: Option<u64>,
+    pub max_table_elements: Option<u32>,
+}
+
+fn parse<T: std::str::FromStr>(
+    var: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<Option<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    lookup(var)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid value {value:?} for {var}"))
+        })
+        .transpose()
+}
+
+impl PoolingOptions {
+    /// Returns the pooling options from the environment of the shim,
+    /// or `None` if the pooling allocator is not enabled.
+    pub fn from_env() -> Result<Option<Self>> {
+        Self::from_lookup(|var| node_config::var(var).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Option<Self>> {
+        let enabled = lookup(POOLING_ENV)
+            .is_some_and(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true"));
+        if !enabled {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            max_instances: parse(POOLING_MAX_INSTANCES_ENV, &lookup)?,
+            max_memory_pages: parse(POOLING_MAX_MEMORY_PAGES_ENV, &lookup)?,
+            max_table_elements: parse(POOLING_MAX_TABLE_ELEMENTS_ENV, &lookup)?,
+        }))
+    }
+
+    /// Configures `config` to use the pooling instance allocator.
+    pub fn apply(&self, config: &mut Config) -> Result<()> {
+        let mut pooling = PoolingAllocationConfig::default();
+        if let Some(max_instances) = self.max_instances {
+            pooling
+                .total_core_instances(max_instances)
+                .total_component_instances(max_instances)
+                .total_memories(max_instances)
+                .total_tables(max_instances)
+                .total_stacks(max_instances);
+        }
+        if let Some(pages) = self.max_memory_pages {
+            let bytes = pages
+                .checked_mul(WASM_PAGE_SIZE)
+                .and_then(|bytes| usize::try_from(bytes).ok())
+                .with_context(|| format!("{POOLING_MAX_MEMORY_PAGES_ENV} is too large"))?;
+            pooling.max_memory_size(bytes);
+        }
+        if let Some(elements) = self.max_table_elements {
+            pooling.table_elements(elements);
+        }
+        log::info!("using the pooling instance allocator: {self:?}");
+        config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn options(vars: &[(&str, &str)]) -> Result<Option<PoolingOptions>> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        PoolingOptions::from_lookup(|var| vars.get(var).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn test_disabled_by_default() -> Result<()> {
+        assert_eq!(options(&[])?, None);
+        assert_eq!(options(&[(POOLING_ENV, "0")])?, None);
+        assert_eq!(options(&[(POOLING_MAX_INSTANCES_ENV, "10")])?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pooling_options() -> Result<()> {
+        let opts = options(&[
+            (POOLING_ENV, "true"),
+            (POOLING_MAX_INSTANCES_ENV, "10"),
+            (POOLING_MAX_MEMORY_PAGES_ENV, "160"),
+            (POOLING_MAX_TABLE_ELEMENTS_ENV, "1000"),
+        ])?
+        .unwrap();
+        assert_eq!(
+            opts,
+            PoolingOptions {
+                max_instances: Some(10),
+                max_memory_pages: Some(160),
+                max_table_elements: Some(1000),
+            }
+        );
+
+        let mut config = Config::new();
+        config.async_support(true);
+        opts.apply(&mut config)?;
+        wasmtime::Engine::new(&config)?;
+
+        assert_eq!(options(&[(POOLING_ENV, "1")])?, Some(Default::default()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_pooling_options() {
+        assert!(options(&[(POOLING_ENV, "1"), (POOLING_MAX_INSTANCES_ENV, "many")]).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! Port bridge for workloads that can't open sockets themselves.
+//!
+//! With the `runwasi.io/ports` annotation, e.g. `8080,9090`, the shim listens on the declared
+//! ports of the pod, so that a Service or an Ingress can route traffic to the container:
+//! * a component exporting `wasi:http/incoming-handler` is served over HTTP on every port, see
+//!   [`crate::http_proxy`],
+//! * any other module or component gets its entrypoint function, e.g., `_start` or the export
+//!   selected with `app.wasm#handle`, called in a fresh instance for every connection, with the
+//!   connection as its stdin and stdout, as `inetd` does.
+//!   The connection is closed when the function returns.
+//!
+//! The shim handles up to `runwasi.io/max-connections` connections at a time, 1024 by default,
+//! and waits for one to close before accepting the next one.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, MutexGuard, Semaphore};
+use tokio::task::JoinSet;
+use wasmtime_wasi::pipe::{AsyncReadStream, AsyncWriteStream};
+use wasmtime_wasi::{
+    async_trait, HostInputStream, HostOutputStream, StdinStream, StdoutStream, StreamError,
+    StreamResult, Subscribe,
+};
+
+/// Annotation used to declare the ports the shim listens on, e.g. `8080,9090`
+pub const PORTS_ANNOTATION: &str = "runwasi.io/ports";
+
+/// Annotation used to set the number of connections handled at a time, e.g. `100`
+pub const MAX_CONNECTIONS_ANNOTATION: &str = "runwasi.io/max-connections";
+
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Bytes of the responses buffered before the guest waits for the client
+const WRITE_BUDGET: usize = 64 * 1024;
+
+/// Returns the ports of the `runwasi.io/ports` annotation, or `None` without one
+pub(crate) fn ports(annotations: &HashMap<String, String>) -> Result<Option<Vec<u16>>> {
+    let Some(ports) = annotations.get(PORTS_ANNOTATION) else {
+        return Ok(None);
+    };
+    let ports = ports
+        .split(',')
+        .map(str::trim)
+        .filter(|port| !port.is_empty())
+        .map(|port| {
+            port.parse()
+                .with_context(|| format!("invalid port {port:?} in {PORTS_ANNOTATION}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(ports))
+}
+
+/// Returns the number of connections handled at a time, from the `runwasi.io/max-connections`
+/// annotation
+pub(crate) fn max_connections(annotations: &HashMap<String, String>) -> Result<usize> {
+    let Some(max) = annotations.get(MAX_CONNECTIONS_ANNOTATION) else {
+        return Ok(DEFAULT_MAX_CONNECTIONS);
+    };
+    max.trim()
+        .parse()
+        .ok()
+        .filter(|max| *max > 0)
+        .with_context(|| format!("invalid value {max:?} for {MAX_CONNECTIONS_ANNOTATION}"))
+}
+
+/// The addresses of the ports on every interface of the pod
+pub(crate) fn addresses(ports: &[u16]) -> Vec<SocketAddr> {
+    ports
+        .iter()
+        .map(|port| SocketAddr::from((Ipv4Addr::UNSPECIFIED, *port)))
+        .collect()
+}
+
+/// Listen on `addresses` and call `handle` for every connection, until a listener fails.
+/// At most `max_connections` connections are handled at a time, across all the addresses.
+pub(crate) async fn serve<F, Fut>(
+    addresses: &[SocketAddr],
+    max_connections: usize,
+    handle: F,
+) -> Result<()>
+where
+    F: Fn(TcpStream) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let handle = Arc::new(handle);
+    let permits = Arc::new(Semaphore::new(max_connections));
+    let mut listeners = JoinSet::new();
+    for address in addresses {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("failed to listen on {address}"))?;
+        log::info!("listening on {address}");
+        let (handle, permits) = (handle.clone(), permits.clone());
+        listeners.spawn(async move {
+            loop {
+                // the semaphore is never closed
+                let permit = permits.clone().acquire_owned().await.unwrap();
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let connection = handle(stream);
+                        drop(tokio::task::spawn(async move {
+                            connection.await;
+                            drop(permit);
+                        }))
+                    }
+                    Err(err) => break Err(err),
+                }
+            }
+        });
+    }
+    match listeners.join_next().await {
+        Some(status) => Ok(status??),
+        None => Ok(()),
+    }
+}
+
+/// The stdin and the stdout of the instance handling a connection
+pub(crate) struct Connection {
+    stdin: Shared<AsyncReadStream>,
+    stdout: Shared<AsyncWriteStream>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self {
+            stdin: Shared::new(AsyncReadStream::new(reader)),
+            stdout: Shared::new(AsyncWriteStream::new(WRITE_BUDGET, writer)),
+        }
+    }
+
+    pub fn stdin(&self) -> impl StdinStream {
+        self.stdin.clone()
+    }
+
+    pub fn stdout(&self) -> impl StdoutStream {
+        self.stdout.clone()
+    }
+}
+
+// A stream of a connection, shared by the preview 1 and preview 2 contexts of the instance.
+// The lock is held while waiting for the stream to be ready, unlike with `AsyncStdoutStream`,
+// which misses the wakeups of its writer, so that a flush never completes.
+struct Shared<T>(Arc<Mutex<T>>);
+
+impl<T> Shared<T> {
+    fn new(stream: T) -> Self {
+        Self(Arc::new(Mutex::new(stream)))
+    }
+
+    fn lock(&self) -> StreamResult<MutexGuard<'_, T>> {
+        self.0
+            .try_lock()
+            .map_err(|_| StreamError::trap("concurrent use of the stream of the connection"))
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+#[async_trait]
+impl<T: Subscribe> Subscribe for Shared<T> {
+    async fn ready(&mut self) {
+        self.0.lock().await.ready().await
+    }
+}
+
+impl<T: HostInputStream> HostInputStream for Shared<T> {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        self.lock()?.read(size)
+    }
+}
+
+impl<T: HostOutputStream> HostOutputStream for Shared<T> {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.lock()?.write(bytes)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.lock()?.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.lock()?.check_write()
+    }
+}
+
+impl<T: HostInputStream> StdinStream for Shared<T> {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+impl<T: HostOutputStream> StdoutStream for Shared<T> {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ports() -> Result<()> {
+        let annotations = HashMap::from([(PORTS_ANNOTATION.to_string(), "8080, 9090".to_string())]);
+        assert_eq!(ports(&annotations)?, Some(vec![8080, 9090]));
+        assert_eq!(
+            addresses(&[8080]),
+            vec!["0.0.0.0:8080".parse::<SocketAddr>()?]
+        );
+        assert_eq!(ports(&HashMap::new())?, None);
+
+        let annotations = HashMap::from([(PORTS_ANNOTATION.to_string(), "http".to_string())]);
+        assert!(ports(&annotations).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_connections() -> Result<()> {
+        let annotations =
+            |max: &str| HashMap::from([(MAX_CONNECTIONS_ANNOTATION.to_string(), max.to_string())]);
+        assert_eq!(max_connections(&HashMap::new())?, DEFAULT_MAX_CONNECTIONS);
+        assert_eq!(max_connections(&annotations("100"))?, 100);
+        assert!(max_connections(&annotations("0")).is_err());
+        assert!(max_connections(&annotations("many")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        use std::io::Read;
+
+        use tokio::io::AsyncWriteExt;
+
+        let address = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .build()?;
+            // a single connection at a time, the next one is accepted once the first is closed
+            runtime.block_on(serve(&[address], 1, |mut stream| async move {
+                let _ = stream.write_all(b"hello").await;
+            }))
+        });
+
+        for _ in 0..2 {
+            let mut response = String::new();
+            let mut stream = loop {
+                match std::net::TcpStream::connect(address) {
+                    Ok(stream) => break stream,
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                }
+            };
+            stream.read_to_string(&mut response)?;
+            assert_eq!(response, "hello");
+        }
+        Ok(())
+    }
+}
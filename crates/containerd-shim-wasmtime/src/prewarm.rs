@@ -0,0 +1,473 @@
+//! Prewarming of modules, from a snapshot of their initialized state.
+//!
+//! With `RUNWASI_WASMTIME_PREWARM` set to the name of an initialization export, e.g.,
+//! `wizer.initialize` as with [Wizer], the modules exporting this function are instantiated once
+//! and the function is called, to run the initialization of the guest up to this marker, e.g.,
+//! parsing its configuration or filling its caches. The memories and the mutable globals of the
+//! instance are then snapshotted into a new module:
+//!  - the memories start with the size and the content of the snapshot, as data segments,
+//!  - the mutable globals start with the value of the snapshot,
+//!  - the start function and the initialization export are removed.
+//!
+//! The modules are prewarmed when they are precompiled, on image pull, so the containers of the
+//! image start from the snapshot, with copy-on-write mappings of its memories, without running the
+//! initialization again. Modules that aren't precompiled are prewarmed when they are loaded.
+//!
+//! The initialization runs without arguments, environment, preopened directories or network, as
+//! the snapshot is shared by all the containers of the image. Modules importing their memories, or
+//! with passive data segments, can't be snapshotted and run as is. Tables, imported globals and
+//! globals of reference types aren't snapshotted, so the initialization must not modify them.
+//!
+//! [Wizer]: https://github.com/bytecodealliance/wizer
+
+use std::ops::Range;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use containerd_shim_wasm::sandbox::node_config;
+use wasm_encoder::{
+    ConstExpr, DataCountSection, DataSection, Encode, ExportKind, ExportSection, GlobalType,
+    MemorySection, MemoryType, RawSection, SectionId, ValType,
+};
+use wasmparser::{DataKind, ExternalKind, Parser, Payload, TypeRef};
+use wasmtime::{AsContextMut, Instance, Val};
+
+/// Environment variable with the name of the initialization export
+pub const PREWARM_ENV: &str = "RUNWASI_WASMTIME_PREWARM";
+
+/// Time the initialization of a module can run for
+pub const MAX_INIT_TIME: Duration = Duration::from_secs(60);
+
+/// Prefix of the exports added to read the state of the instance
+const STATE_EXPORT_PREFIX: &str = "__runwasi_prewarm";
+
+/// Zero bytes between two runs of data that are merged into the same data segment
+const MAX_SEGMENT_GAP: usize = 64;
+
+/// The initialization export of the modules to prewarm
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prewarm {
+    export: String,
+}
+
+impl Prewarm {
+    /// Loads the initialization export from the environment of the shim, if one is configured.
+    pub fn from_env() -> Option<Self> {
+        node_config::var(PREWARM_ENV)
+            .ok()
+            .filter(|export| !export.is_empty())
+            .map(Self::new)
+    }
+
+    pub fn new(export: impl Into<String>) -> Self {
+        Self {
+            export: export.into(),
+        }
+    }
+
+    pub fn export(&self) -> &str {
+        &self.export
+    }
+
+    /// Returns whether `module` exports the initialization function, and is prewarmed.
+    pub fn applies_to(&self, module: &[u8]) -> bool {
+        Parser::new(0)
+            .parse_all(module)
+            .any(|payload| match payload {
+                Ok(Payload::ExportSection(exports)) => exports.into_iter().any(|export| {
+                    export.is_ok_and(|e| e.name == self.export && e.kind == ExternalKind::Func)
+                }),
+                _ => false,
+            })
+    }
+}
+
+/// The defined memories and mutable globals of a module, exported by [`instrument`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct State {
+    memories: Vec<u32>,
+    globals: Vec<u32>,
+}
+
+/// The content of the defined memories and the value of the mutable globals of an instance
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    memories: Vec<(u64, Vec<u8>)>,
+    globals: Vec<(u32, Val)>,
+}
+
+/// Returns `module` with its memories and mutable globals exported, and the state it exports.
+pub fn instrument(module: &[u8]) -> Result<(Vec<u8>, State)> {
+    let mut state = State::default();
+    let mut imported_globals = 0;
+    let mut sections = vec![];
+
+    for payload in Parser::new(0).parse_all(module) {
+        let payload = payload?;
+        match &payload {
+            Payload::ImportSection(imports) => {
+                for import in imports.clone() {
+                    match import?.ty {
+                        TypeRef::Memory(_) => {
+                            bail!("modules importing memories can't be prewarmed")
+                        }
+                        TypeRef::Global(_) => imported_globals += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Payload::MemorySection(memories) => state.memories = (0..memories.count()).collect(),
+            Payload::GlobalSection(globals) => {
+                for (i, global) in globals.clone().into_iter().enumerate() {
+                    let global = global?;
+                    if global.ty.mutable && to_val_type(global.ty.content_type).is_some() {
+                        state.globals.push(imported_globals + i as u32);
+                    }
+                }
+            }
+            Payload::DataSection(data) => {
+                for segment in data.clone() {
+                    if let DataKind::Passive = segment?.kind {
+                        bail!("modules with passive data segments can't be prewarmed");
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        match &payload {
+            Payload::ExportSection(exports) => {
+                let mut section = copy_exports(exports, |_| true)?;
+                for memory in &state.memories {
+                    section.export(&memory_export(*memory), ExportKind::Memory, *memory);
+                }
+                for global in &state.globals {
+                    section.export(&global_export(*global), ExportKind::Global, *global);
+                }
+                sections.push(Section::Exports(section));
+            }
+            payload => sections.extend(payload.as_section().map(Section::raw)),
+        }
+    }
+    Ok((encode(module, &sections), state))
+}
+
+/// Reads the state exported by an instance of an instrumented module.
+pub fn snapshot(
+    mut store: impl AsContextMut,
+    instance: &Instance,
+    state: &State,
+) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::default();
+    for index in &state.memories {
+        let memory = instance
+            .get_memory(&mut store, &memory_export(*index))
+            .context("missing memory export")?;
+        let pages = memory.size(&store);
+        let data = memory.data(&store).to_vec();
+        snapshot.memories.push((pages, data));
+    }
+    for index in &state.globals {
+        let global = instance
+            .get_global(&mut store, &global_export(*index))
+            .context("missing global export")?;
+        snapshot.globals.push((*index, global.get(&mut store)));
+    }
+    Ok(snapshot)
+}
+
+/// Returns `module` starting from `snapshot`, without its start function and the initialization
+/// export of `prewarm`.
+pub fn restore(module: &[u8], prewarm: &Prewarm, snapshot: &Snapshot) -> Result<Vec<u8>> {
+    let mut imported_globals = 0;
+    let mut memory64 = vec![];
+    let mut sections = vec![];
+
+    for payload in Parser::new(0).parse_all(module) {
+        let payload = payload?;
+        match &payload {
+            Payload::ImportSection(imports) => {
+                for import in imports.clone() {
+                    if let TypeRef::Global(_) = import?.ty {
+                        imported_globals += 1;
+                    }
+                }
+                sections.extend(payload.as_section().map(Section::raw));
+            }
+            Payload::MemorySection(memories) => {
+                let mut section = MemorySection::new();
+                for (memory, (pages, _)) in memories.clone().into_iter().zip(&snapshot.memories) {
+                    let memory = memory?;
+                    memory64.push(memory.memory64);
+                    section.memory(MemoryType {
+                        minimum: *pages,
+                        maximum: memory.maximum,
+                        memory64: memory.memory64,
+                        shared: memory.shared,
+                        page_size_log2: memory.page_size_log2,
+                    });
+                }
+                sections.push(Section::Memories(section));
+            }
+            Payload::GlobalSection(globals) => {
+                let mut section = vec![];
+                globals.count().encode(&mut section);
+                let mut items = globals.clone().into_iter_with_offsets().peekable();
+                let mut index = imported_globals;
+                while let Some(item) = items.next() {
+                    let (offset, global) = item?;
+                    let value = snapshot.globals.iter().find(|(g, _)| *g == index);
+                    match value.and_then(|(_, value)| to_const_expr(value)) {
+                        Some((val_type, init)) => {
+                            let ty = GlobalType {
+                                val_type,
+                                mutable: true,
+                                shared: global.ty.shared,
+                            };
+                            ty.encode(&mut section);
+                            init.encode(&mut section);
+                        }
+                        // the globals that aren't snapshotted are copied as is
+                        None => {
+                            let end = match items.peek() {
+                                Some(Ok((next, _))) => *next,
+                                _ => globals.range().end,
+                            };
+                            section.extend_from_slice(&module[offset..end]);
+                        }
+                    }
+                    index += 1;
+                }
+                sections.push(Section::Bytes(SectionId::Global, section));
+            }
+            Payload::ExportSection(exports) => {
+                let section = copy_exports(exports, |name| name != prewarm.export())?;
+                sections.push(Section::Exports(section));
+            }
+            Payload::StartSection { .. } => {}
+            Payload::DataCountSection { .. } => sections.push(Section::DataCount),
+            Payload::DataSection(_) => sections.push(Section::Data),
+            payload => sections.extend(payload.as_section().map(Section::raw)),
+        }
+    }
+
+    // the data segments of the snapshot replace the ones of the module
+    let mut segments = DataSection::new();
+    for (index, (_, memory)) in snapshot.memories.iter().enumerate() {
+        for range in data_ranges(memory) {
+            let offset = match memory64[index] {
+                true => ConstExpr::i64_const(range.start as i64),
+                false => ConstExpr::i32_const(range.start as i32),
+            };
+            segments.active(index as u32, &offset, memory[range].iter().copied());
+        }
+    }
+    if !sections.iter().any(|s| matches!(s, Section::Data)) && !segments.is_empty() {
+        sections.push(Section::Data);
+    }
+    let count = segments.len();
+    for section in &mut sections {
+        match section {
+            Section::DataCount => *section = Section::DataCountOf(count),
+            Section::Data => *section = Section::Segments(segments.clone()),
+            _ => {}
+        }
+    }
+    Ok(encode(module, &sections))
+}
+
+/// A section of the rewritten module
+enum Section {
+    Raw(u8, Range<usize>),
+    Bytes(SectionId, Vec<u8>),
+    Memories(MemorySection),
+    Exports(ExportSection),
+    Segments(DataSection),
+    DataCountOf(u32),
+    // placeholders for the sections of the snapshot
+    Data,
+    DataCount,
+}
+
+impl Section {
+    fn raw((id, range): (u8, Range<usize>)) -> Self {
+        Self::Raw(id, range)
+    }
+}
+
+fn encode(module: &[u8], sections: &[Section]) -> Vec<u8> {
+    let mut encoded = wasm_encoder::Module::new();
+    for section in sections {
+        match section {
+            Section::Raw(id, range) => encoded.section(&RawSection {
+                id: *id,
+                data: &module[range.clone()],
+            }),
+            Section::Bytes(id, data) => encoded.section(&RawSection {
+                id: *id as u8,
+                data,
+            }),
+            Section::Memories(section) => encoded.section(section),
+            Section::Exports(section) => encoded.section(section),
+            Section::Segments(section) => encoded.section(section),
+            Section::DataCountOf(count) => encoded.section(&DataCountSection { count: *count }),
+            Section::Data | Section::DataCount => &mut encoded,
+        };
+    }
+    encoded.finish()
+}
+
+fn copy_exports(
+    exports: &wasmparser::ExportSectionReader,
+    filter: impl Fn(&str) -> bool,
+) -> Result<ExportSection> {
+    let mut section = ExportSection::new();
+    for export in exports.clone() {
+        let export = export?;
+        if filter(export.name) {
+            section.export(export.name, to_export_kind(export.kind), export.index);
+        }
+    }
+    Ok(section)
+}
+
+/// The ranges of `memory` with non-zero bytes, merging the ranges separated by few zeros
+fn data_ranges(memory: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
+    let mut offset = 0;
+    while let Some(start) = memory[offset..].iter().position(|b| *b != 0) {
+        let start = offset + start;
+        let end = memory[start..]
+            .iter()
+            .position(|b| *b == 0)
+            .map_or(memory.len(), |len| start + len);
+        match ranges.last_mut() {
+            Some(last) if start - last.end <= MAX_SEGMENT_GAP => last.end = end,
+            _ => ranges.push(start..end),
+        }
+        offset = end;
+    }
+    ranges
+}
+
+fn memory_export(index: u32) -> String {
+    format!("{STATE_EXPORT_PREFIX}_memory_{index}")
+}
+
+fn global_export(index: u32) -> String {
+    format!("{STATE_EXPORT_PREFIX}_global_{index}")
+}
+
+fn to_val_type(ty: wasmparser::ValType) -> Option<ValType> {
+    match ty {
+        wasmparser::ValType::I32 => Some(ValType::I32),
+        wasmparser::ValType::I64 => Some(ValType::I64),
+        wasmparser::ValType::F32 => Some(ValType::F32),
+        wasmparser::ValType::F64 => Some(ValType::F64),
+        wasmparser::ValType::V128 => Some(ValType::V128),
+        wasmparser::ValType::Ref(_) => None,
+    }
+}
+
+fn to_const_expr(value: &Val) -> Option<(ValType, ConstExpr)> {
+    match value {
+        Val::I32(v) => Some((ValType::I32, ConstExpr::i32_const(*v))),
+        Val::I64(v) => Some((ValType::I64, ConstExpr::i64_const(*v))),
+        Val::F32(v) => Some((ValType::F32, ConstExpr::f32_const(f32::from_bits(*v)))),
+        Val::F64(v) => Some((ValType::F64, ConstExpr::f64_const(f64::from_bits(*v)))),
+        Val::V128(v) => Some((ValType::V128, ConstExpr::v128_const(v.as_u128() as i128))),
+        _ => None,
+    }
+}
+
+fn to_export_kind(kind: ExternalKind) -> ExportKind {
+    match kind {
+        ExternalKind::Func => ExportKind::Func,
+        ExternalKind::Table => ExportKind::Table,
+        ExternalKind::Memory => ExportKind::Memory,
+        ExternalKind::Global => ExportKind::Global,
+        ExternalKind::Tag => ExportKind::Tag,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmtime::{Engine, Module, Store};
+
+    use super::*;
+
+    const MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $counter (export "counter") (mut i32) (i32.const 0))
+            (global $ratio (export "ratio") (mut f64) (f64.const 0))
+            (global $version (export "version") i32 (i32.const 7))
+            (data (i32.const 16) "hello")
+            (func $start (global.set $counter (i32.const 1)))
+            (func (export "init")
+                (drop (memory.grow (i32.const 1)))
+                (i32.store (i32.const 65536) (i32.const 42))
+                (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+                (global.set $ratio (f64.const 0.5)))
+            (start $start)
+        )
+    "#;
+
+    #[test]
+    fn test_data_ranges() {
+        let mut memory = vec![0; 1024];
+        memory[10..13].copy_from_slice(b"abc");
+        memory[20..22].copy_from_slice(b"de");
+        memory[500] = 1;
+        assert_eq!(data_ranges(&memory), vec![10..22, 500..501]);
+        assert!(data_ranges(&[0; 16]).is_empty());
+    }
+
+    #[test]
+    fn test_prewarm() -> Result<()> {
+        let engine = Engine::default();
+        let prewarm = Prewarm::new("init");
+        let module = wat::parse_str(MODULE)?;
+        assert!(prewarm.applies_to(&module));
+        assert!(!Prewarm::new("_start").applies_to(&module));
+
+        let (instrumented, state) = instrument(&module)?;
+        let instrumented = Module::new(&engine, instrumented)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &instrumented, &[])?;
+        let init = instance.get_typed_func::<(), ()>(&mut store, "init")?;
+        init.call(&mut store, ())?;
+        let snapshot = snapshot(&mut store, &instance, &state)?;
+
+        let prewarmed = restore(&module, &prewarm, &snapshot)?;
+        let prewarmed = Module::new(&engine, prewarmed)?;
+        assert!(prewarmed.get_export("init").is_none());
+
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &prewarmed, &[])?;
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        assert_eq!(memory.size(&store), 2);
+        assert_eq!(&memory.data(&store)[16..21], b"hello");
+        assert_eq!(&memory.data(&store)[65536..65540], &42i32.to_le_bytes());
+        // the start function isn't run again
+        let counter = instance.get_global(&mut store, "counter").unwrap();
+        assert_eq!(counter.get(&mut store).i32(), Some(2));
+        let ratio = instance.get_global(&mut store, "ratio").unwrap();
+        assert_eq!(ratio.get(&mut store).f64(), Some(0.5));
+        let version = instance.get_global(&mut store, "version").unwrap();
+        assert_eq!(version.get(&mut store).i32(), Some(7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_modules() -> Result<()> {
+        let module = wat::parse_str(
+            r#"(module (import "env" "memory" (memory 1)) (func (export "init")))"#,
+        )?;
+        assert!(instrument(&module).is_err());
+
+        let module = wat::parse_str(r#"(module (memory 1) (data "x") (func (export "init")))"#)?;
+        assert!(instrument(&module).is_err());
+        Ok(())
+    }
+}
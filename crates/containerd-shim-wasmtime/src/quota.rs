@@ -0,0 +1,726 @@
+//! Write quotas of the directories preopened for modules, with the `runwasi.io/fs-quota`
+//! annotation.
+//!
+//! A guest writing to a volume shared with the other containers of the pod, e.g., an `emptyDir`,
+//! can fill it for all of them. The annotation caps the bytes and the inodes of the files in
+//! the preopened directories of the guest, as comma separated `<directory>=<bytes>[/<inodes>]`,
+//! where the size can have a `K`, `M`, `G`, `Ki`, `Mi` or `Gi` suffix:
+//!
+//! ```yaml
+//! runwasi.io/fs-quota: "/data=64Mi/1000, /cache=1Gi, /tmp=/100"
+//! ```
+//!
+//! The preview 1 functions creating and writing files are wrapped to charge the bytes they add
+//! to a file, and the files and directories they create, to the quota of the preopen of their
+//! descriptor, before calling the ones of wasmtime. They fail with `EDQUOT` when the quota is
+//! exceeded, as with a disk quota.
+//!
+//! The usage of a directory is counted on the host when the container starts, then estimated
+//! from the writes of the guest. When a write would exceed the quota, the usage is counted again,
+//! so that the files removed or truncated since, by the guest or by the other containers, are
+//! freed.
+//!
+//! Components write through the streams of `wasi:filesystem`, which can't be charged, so the
+//! annotation is ignored for them, and the modules with quotas aren't componentized by the
+//! preview 1 adapter.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use containerd_shim_wasm::container::Volume;
+use wasmtime::{Caller, Extern, Linker, Store};
+use wasmtime_wasi::preview1::wasi_snapshot_preview1::{self as p1, WasiSnapshotPreview1};
+use wasmtime_wasi::preview1::{types, WasiP1Ctx};
+use wiggle::{GuestMemory, GuestPtr};
+
+use crate::instance::WasiCtx;
+
+/// Annotation with the quotas of the preopened directories, as `<directory>=<bytes>[/<inodes>]`
+pub const FS_QUOTA_ANNOTATION: &str = "runwasi.io/fs-quota";
+
+const P1_MODULE_NAME: &str = "wasi_snapshot_preview1";
+
+// The errnos of preview 1
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_DQUOT: i32 = 19;
+const ERRNO_NOENT: i32 = 44;
+
+// The `oflags` of `path_open`
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_EXCL: i32 = 4;
+
+// The descriptor of the first preopen, after stdin, stdout and stderr
+const FIRST_PREOPEN_FD: u32 = 3;
+
+/// The quota of a directory, in bytes and in inodes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+}
+
+impl FromStr for Quota {
+    type Err = anyhow::Error;
+
+    fn from_str(quota: &str) -> Result<Self> {
+        let (bytes, inodes) = match quota.split_once('/') {
+            Some((bytes, inodes)) => (bytes.trim(), Some(inodes.trim())),
+            None => (quota.trim(), None),
+        };
+        let quota = Self {
+            bytes: (!bytes.is_empty()).then(|| parse_size(bytes)).transpose()?,
+            inodes: inodes
+                .map(|inodes| inodes.parse().context("invalid number of inodes"))
+                .transpose()?,
+        };
+        if quota == Self::default() {
+            bail!("empty quota");
+        }
+        Ok(quota)
+    }
+}
+
+// Parses a size in bytes, with an optional decimal or binary suffix, e.g., `64Mi`
+fn parse_size(size: &str) -> Result<u64> {
+    let units = [
+        ("Ki", 1 << 10),
+        ("Mi", 1 << 20),
+        ("Gi", 1 << 30),
+        ("K", 1_000),
+        ("M", 1_000_000),
+        ("G", 1_000_000_000),
+    ];
+    let (number, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| Some((size.strip_suffix(suffix)?, *unit)))
+        .unwrap_or((size, 1));
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("invalid size {size:?}"))?;
+    number
+        .checked_mul(unit)
+        .with_context(|| format!("size {size:?} is too large"))
+}
+
+/// The usage of a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Usage {
+    bytes: u64,
+    inodes: u64,
+}
+
+impl Usage {
+    // Counts the files and directories under `dir`, and the sizes of the files, without
+    // following symlinks
+    fn count(dir: &Path) -> Self {
+        let mut usage = Self::default();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                usage.inodes += 1;
+                match metadata.is_dir() {
+                    true => dirs.push(entry.path()),
+                    false => usage.bytes += metadata.len(),
+                }
+            }
+        }
+        usage
+    }
+
+    fn add(self, bytes: u64, inodes: u64) -> Self {
+        Self {
+            bytes: self.bytes.saturating_add(bytes),
+            inodes: self.inodes.saturating_add(inodes),
+        }
+    }
+
+    fn within(&self, quota: &Quota) -> bool {
+        quota.bytes.map_or(true, |bytes| self.bytes <= bytes)
+            && quota.inodes.map_or(true, |inodes| self.inodes <= inodes)
+    }
+}
+
+/// The quota of a preopened directory, shared by the instances of the container
+#[derive(Debug)]
+struct DirQuota {
+    dir: PathBuf,
+    quota: Quota,
+    usage: Mutex<Usage>,
+}
+
+impl DirQuota {
+    // Charges `bytes` and `inodes` to the usage of the directory, or returns `false` when they
+    // exceed its quota
+    fn charge(&self, bytes: u64, inodes: u64) -> bool {
+        let mut usage = self.usage.lock().unwrap();
+        let mut charged = usage.add(bytes, inodes);
+        if !charged.within(&self.quota) {
+            // the estimate doesn't know about the files removed or truncated since
+            *usage = Usage::count(&self.dir);
+            charged = usage.add(bytes, inodes);
+            if !charged.within(&self.quota) {
+                log::debug!("quota of {:?} exceeded: {charged:?}", self.dir);
+                return false;
+            }
+        }
+        *usage = charged;
+        true
+    }
+
+    fn recount(&self) {
+        *self.usage.lock().unwrap() = Usage::count(&self.dir);
+    }
+}
+
+/// The quotas of the preopened directories of an instance, with the preopen of each descriptor
+/// opened in them
+#[derive(Debug, Clone, Default)]
+pub struct FsQuotas {
+    dirs: Vec<Option<Arc<DirQuota>>>,
+    fds: HashMap<u32, usize>,
+}
+
+impl FsQuotas {
+    /// The quotas of the `runwasi.io/fs-quota` annotation for the `preopens` of a container,
+    /// whose usage is counted on the host.
+    pub fn from_annotations(
+        annotations: &HashMap<String, String>,
+        preopens: &[Volume],
+    ) -> Result<Self> {
+        let Some(quotas) = annotations.get(FS_QUOTA_ANNOTATION) else {
+            return Ok(Self::default());
+        };
+        let mut dirs = vec![None; preopens.len()];
+        for entry in quotas.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (dir, quota) = entry.split_once('=').with_context(|| {
+                format!("invalid {FS_QUOTA_ANNOTATION} {entry:?}, expected `<directory>=<bytes>[/<inodes>]`")
+            })?;
+            let quota: Quota = quota
+                .parse()
+                .with_context(|| format!("invalid {FS_QUOTA_ANNOTATION} {entry:?}"))?;
+            let index = preopens
+                .iter()
+                .position(|preopen| preopen.guest_path == Path::new(dir.trim()))
+                .with_context(|| {
+                    format!("{dir:?} of {FS_QUOTA_ANNOTATION} is not a preopened directory")
+                })?;
+            let host_dir = &preopens[index].host_path;
+            let usage = Usage::count(host_dir);
+            log::info!("quota of {dir}: {quota:?}, using {usage:?}");
+            dirs[index] = Some(Arc::new(DirQuota {
+                dir: host_dir.clone(),
+                quota,
+                usage: Mutex::new(usage),
+            }));
+        }
+        let fds = (0..preopens.len())
+            .map(|index| (FIRST_PREOPEN_FD + index as u32, index))
+            .collect();
+        Ok(Self { dirs, fds })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirs.iter().all(Option::is_none)
+    }
+
+    fn dir(&self, fd: i32) -> Option<&DirQuota> {
+        let index = *self.fds.get(&(fd as u32))?;
+        self.dirs.get(index)?.as_deref()
+    }
+
+    fn charge(&self, fd: i32, bytes: u64, inodes: u64) -> bool {
+        self.dir(fd).map_or(true, |dir| dir.charge(bytes, inodes))
+    }
+
+    // `fd` was opened in the directory of `dirfd`
+    fn opened(&mut self, dirfd: i32, fd: u32) {
+        match self.fds.get(&(dirfd as u32)).copied() {
+            Some(index) => self.fds.insert(fd, index),
+            None => self.fds.remove(&fd),
+        };
+    }
+
+    fn renumbered(&mut self, from: i32, to: i32) {
+        match self.fds.remove(&(from as u32)) {
+            Some(index) => self.fds.insert(to as u32, index),
+            None => self.fds.remove(&(to as u32)),
+        };
+    }
+
+    fn closed(&mut self, fd: i32) {
+        self.fds.remove(&(fd as u32));
+    }
+
+    // Files were moved or linked from the directory of `from` to the one of `to`, which gets
+    // files it didn't write
+    fn moved(&self, from: i32, to: i32) {
+        if self.fds.get(&(from as u32)) != self.fds.get(&(to as u32)) {
+            if let Some(dir) = self.dir(to) {
+                dir.recount();
+            }
+        }
+    }
+}
+
+/// Wraps the preview 1 functions writing files in `linker` to enforce the quotas of the store
+/// of the container, when it has some.
+pub(crate) fn link(linker: &mut Linker<WasiCtx>, store: &Store<WasiCtx>) -> Result<()> {
+    if store.data().fs_quotas.is_empty() {
+        return Ok(());
+    }
+    log::info!("enforcing the quotas of the preopened directories");
+    add_to_linker(linker, |ctx| (&mut ctx.wasi_preview1, &mut ctx.fs_quotas))
+}
+
+/// Adds the preview 1 functions enforcing the quotas to `linker`, in place of the ones of
+/// wasmtime, which they call.
+pub fn add_to_linker<T: Send + 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> (&mut WasiP1Ctx, &mut FsQuotas),
+) -> Result<()> {
+    linker.allow_shadowing(true);
+    let result = add_wrappers(linker, get);
+    linker.allow_shadowing(false);
+    result
+}
+
+fn add_wrappers<T: Send + 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> (&mut WasiP1Ctx, &mut FsQuotas),
+) -> Result<()> {
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "fd_write",
+        move |mut caller: Caller<'_, T>, (fd, iovs, iovs_len, nwritten): (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                if quotas.dir(fd).is_some() {
+                    let len = write_len(&memory, iovs, iovs_len);
+                    let growth = growth(p1, &mut memory, fd, None, len).await;
+                    if !quotas.charge(fd, growth, 0) {
+                        return Ok(ERRNO_DQUOT);
+                    }
+                }
+                p1::fd_write(p1, &mut memory, fd, iovs, iovs_len, nwritten).await
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "fd_pwrite",
+        move |mut caller: Caller<'_, T>,
+              (fd, iovs, iovs_len, offset, nwritten): (i32, i32, i32, i64, i32)| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                if quotas.dir(fd).is_some() {
+                    let len = write_len(&memory, iovs, iovs_len);
+                    let growth = growth(p1, &mut memory, fd, Some(offset as u64), len).await;
+                    if !quotas.charge(fd, growth, 0) {
+                        return Ok(ERRNO_DQUOT);
+                    }
+                }
+                p1::fd_pwrite(p1, &mut memory, fd, iovs, iovs_len, offset, nwritten).await
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "fd_filestat_set_size",
+        move |mut caller: Caller<'_, T>, (fd, size): (i32, i64)| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                if quotas.dir(fd).is_some() {
+                    let growth = growth(p1, &mut memory, fd, Some(0), size as u64).await;
+                    if !quotas.charge(fd, growth, 0) {
+                        return Ok(ERRNO_DQUOT);
+                    }
+                }
+                p1::fd_filestat_set_size(p1, &mut memory, fd, size).await
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "path_open",
+        move |mut caller: Caller<'_, T>,
+              (dirfd, dirflags, path, path_len, oflags, base, inheriting, fdflags, opened): (
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+            i64,
+            i64,
+            i32,
+            i32,
+        )| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                // a full directory can't have new files, but its files can still be opened
+                let full = oflags & OFLAGS_CREAT != 0 && !quotas.charge(dirfd, 0, 1);
+                if full && oflags & OFLAGS_EXCL != 0 {
+                    return Ok(ERRNO_DQUOT);
+                }
+                let oflags = if full { oflags & !OFLAGS_CREAT } else { oflags };
+                let errno = p1::path_open(
+                    p1,
+                    &mut memory,
+                    dirfd,
+                    dirflags,
+                    path,
+                    path_len,
+                    oflags,
+                    base,
+                    inheriting,
+                    fdflags,
+                    opened,
+                )
+                .await?;
+                match errno {
+                    ERRNO_SUCCESS => {
+                        if let Ok(fd) = memory.read(GuestPtr::<u32>::new(opened as u32)) {
+                            quotas.opened(dirfd, fd);
+                        }
+                    }
+                    ERRNO_NOENT if full => return Ok(ERRNO_DQUOT),
+                    _ => {}
+                }
+                Ok(errno)
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "path_create_directory",
+        move |mut caller: Caller<'_, T>, (fd, path, path_len): (i32, i32, i32)| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                if !quotas.charge(fd, 0, 1) {
+                    return Ok(ERRNO_DQUOT);
+                }
+                p1::path_create_directory(p1, &mut memory, fd, path, path_len).await
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "path_symlink",
+        move |mut caller: Caller<'_, T>,
+              (old_path, old_path_len, fd, new_path, new_path_len): (i32, i32, i32, i32, i32)| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                if !quotas.charge(fd, 0, 1) {
+                    return Ok(ERRNO_DQUOT);
+                }
+                p1::path_symlink(
+                    p1,
+                    &mut memory,
+                    old_path,
+                    old_path_len,
+                    fd,
+                    new_path,
+                    new_path_len,
+                )
+                .await
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "path_rename",
+        move |mut caller: Caller<'_, T>,
+              (fd, old_path, old_path_len, new_fd, new_path, new_path_len): (
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+        )| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                let errno = p1::path_rename(
+                    p1,
+                    &mut memory,
+                    fd,
+                    old_path,
+                    old_path_len,
+                    new_fd,
+                    new_path,
+                    new_path_len,
+                )
+                .await?;
+                if errno == ERRNO_SUCCESS {
+                    quotas.moved(fd, new_fd);
+                }
+                Ok(errno)
+            })
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "path_link",
+        move |mut caller: Caller<'_, T>,
+              (old_fd, old_flags, old_path, old_path_len, new_fd, new_path, new_path_len): (
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+        )| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                let errno = p1::path_link(
+                    p1,
+                    &mut memory,
+                    old_fd,
+                    old_flags,
+                    old_path,
+                    old_path_len,
+                    new_fd,
+                    new_path,
+                    new_path_len,
+                )
+                .await?;
+                if errno == ERRNO_SUCCESS {
+                    quotas.moved(old_fd, new_fd);
+                }
+                Ok(errno)
+            })
+        },
+    )?;
+    linker.func_wrap(
+        P1_MODULE_NAME,
+        "fd_renumber",
+        move |mut caller: Caller<'_, T>, from: i32, to: i32| {
+            let export = caller.get_export("memory");
+            let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+            let errno = p1::fd_renumber(p1, &mut memory, from, to)?;
+            if errno == ERRNO_SUCCESS {
+                quotas.renumbered(from, to);
+            }
+            Ok(errno)
+        },
+    )?;
+    linker.func_wrap_async(
+        P1_MODULE_NAME,
+        "fd_close",
+        move |mut caller: Caller<'_, T>, (fd,): (i32,)| {
+            Box::new(async move {
+                let export = caller.get_export("memory");
+                let (mut memory, p1, quotas) = guest(&mut caller, &export, get)?;
+                let errno = p1::fd_close(p1, &mut memory, fd).await?;
+                if errno == ERRNO_SUCCESS {
+                    quotas.closed(fd);
+                }
+                Ok(errno)
+            })
+        },
+    )?;
+    Ok(())
+}
+
+// The memory of the guest, with its preview 1 context and quotas, as the preview 1 functions of
+// wasmtime get them
+fn guest<'a, T>(
+    caller: &'a mut Caller<'_, T>,
+    export: &'a Option<Extern>,
+    get: fn(&mut T) -> (&mut WasiP1Ctx, &mut FsQuotas),
+) -> Result<(GuestMemory<'a>, &'a mut WasiP1Ctx, &'a mut FsQuotas)> {
+    let (memory, data) = match export {
+        Some(Extern::Memory(memory)) => {
+            let (memory, data) = memory.data_and_store_mut(caller);
+            (GuestMemory::Unshared(memory), data)
+        }
+        Some(Extern::SharedMemory(memory)) => {
+            (GuestMemory::Shared(memory.data()), caller.data_mut())
+        }
+        _ => bail!("missing required memory export"),
+    };
+    let (p1, quotas) = get(data);
+    Ok((memory, p1, quotas))
+}
+
+// The number of bytes of the iovecs of a write, up to the first one outside of the memory
+fn write_len(memory: &GuestMemory<'_>, iovs: i32, iovs_len: i32) -> u64 {
+    (0..iovs_len as u32)
+        .map_while(|i| {
+            let len = (iovs as u32).checked_add(i.checked_mul(8)?.checked_add(4)?)?;
+            memory.read(GuestPtr::<u32>::new(len)).ok()
+        })
+        .map(u64::from)
+        .sum()
+}
+
+// The number of bytes a write of `len` bytes at `offset`, or at the position of the descriptor,
+// adds to its file
+async fn growth(
+    p1: &mut WasiP1Ctx,
+    memory: &mut GuestMemory<'_>,
+    fd: i32,
+    offset: Option<u64>,
+    len: u64,
+) -> u64 {
+    let fd = types::Fd::from(fd as u32);
+    let Ok(stat) = p1.fd_filestat_get(memory, fd).await else {
+        return len;
+    };
+    let offset = match offset {
+        Some(offset) => offset,
+        None => match p1.fd_fdstat_get(memory, fd).await {
+            Ok(fdstat) if fdstat.fs_flags.contains(types::Fdflags::APPEND) => stat.size,
+            _ => p1.fd_tell(memory, fd).unwrap_or(stat.size),
+        },
+    };
+    offset.saturating_add(len).saturating_sub(stat.size)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use wasmtime::{Config, Engine, Module};
+    use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder};
+
+    use super::*;
+
+    struct Ctx {
+        p1: WasiP1Ctx,
+        quotas: FsQuotas,
+    }
+
+    // Opens `file`, or `other` at `0x10`, in the preopen, and writes 8 bytes to it
+    const MODULE: &str = r#"(module
+        (import "wasi_snapshot_preview1" "path_open"
+            (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_create_directory"
+            (func $mkdir (param i32 i32 i32) (result i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0x00) "file")
+        (data (i32.const 0x10) "other")
+        (data (i32.const 0x20) "dir")
+        (data (i32.const 0x40) "\60\00\00\00\08\00\00\00")
+        (data (i32.const 0x60) "12345678")
+        (func (export "open") (param $name i32) (param $oflags i32) (result i32)
+            (call $path_open (i32.const 3) (i32.const 0) (local.get $name)
+                (select (i32.const 4) (i32.const 5) (i32.eqz (local.get $name)))
+                (local.get $oflags) (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) (i32.const 0x30)))
+        (func (export "write") (result i32)
+            (call $fd_write (i32.load (i32.const 0x30)) (i32.const 0x40) (i32.const 1) (i32.const 0x34)))
+        (func (export "close") (result i32)
+            (call $fd_close (i32.load (i32.const 0x30))))
+        (func (export "mkdir") (result i32)
+            (call $mkdir (i32.const 3) (i32.const 0x20) (i32.const 3))))"#;
+
+    #[test]
+    fn test_parse_quota() -> Result<()> {
+        let quota: Quota = "64Mi/1000".parse()?;
+        assert_eq!(quota.bytes, Some(64 << 20));
+        assert_eq!(quota.inodes, Some(1000));
+        assert_eq!("2K".parse::<Quota>()?.bytes, Some(2000));
+        assert_eq!("/10".parse::<Quota>()?.bytes, None);
+        assert!("".parse::<Quota>().is_err());
+        assert!("64MB".parse::<Quota>().is_err());
+        assert!("1/many".parse::<Quota>().is_err());
+
+        let dir = tempdir()?;
+        let preopens = [
+            Volume {
+                host_path: "/".into(),
+                guest_path: "/".into(),
+                readonly: true,
+            },
+            Volume {
+                host_path: dir.path().into(),
+                guest_path: "/data".into(),
+                readonly: false,
+            },
+        ];
+        let annotations = |quota: &str| HashMap::from([(FS_QUOTA_ANNOTATION.into(), quota.into())]);
+        let quotas = FsQuotas::from_annotations(&annotations("/data=1Ki/10"), &preopens)?;
+        assert!(quotas.dir(3).is_none());
+        assert_eq!(quotas.dir(4).unwrap().quota.bytes, Some(1024));
+        assert!(FsQuotas::from_annotations(&annotations("/cache=1Ki"), &preopens).is_err());
+        assert!(FsQuotas::from_annotations(&HashMap::new(), &preopens)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_quota() -> Result<()> {
+        let dir = tempdir()?;
+        let preopens = [Volume {
+            host_path: dir.path().into(),
+            guest_path: "/data".into(),
+            readonly: false,
+        }];
+        let annotations = HashMap::from([(FS_QUOTA_ANNOTATION.into(), "/data=12/2".into())]);
+        let quotas = FsQuotas::from_annotations(&annotations, &preopens)?;
+        let p1 = WasiCtxBuilder::new()
+            .preopened_dir(dir.path(), "/data", DirPerms::all(), FilePerms::all())?
+            .build_p1();
+
+        let mut config = Config::new();
+        config.async_support(true);
+        let engine = Engine::new(&config)?;
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::preview1::add_to_linker_async(&mut linker, |ctx: &mut Ctx| &mut ctx.p1)?;
+        add_to_linker(&mut linker, |ctx: &mut Ctx| (&mut ctx.p1, &mut ctx.quotas))?;
+        let module = Module::new(&engine, MODULE)?;
+        let mut store = Store::new(&engine, Ctx { p1, quotas });
+
+        wasmtime_wasi::runtime::in_tokio(async {
+            let instance = linker.instantiate_async(&mut store, &module).await?;
+            let open = instance.get_typed_func::<(i32, i32), i32>(&mut store, "open")?;
+            let write = instance.get_typed_func::<(), i32>(&mut store, "write")?;
+            let close = instance.get_typed_func::<(), i32>(&mut store, "close")?;
+            let mkdir = instance.get_typed_func::<(), i32>(&mut store, "mkdir")?;
+
+            // the first 8 bytes fit in the quota, but not the next ones
+            assert_eq!(open.call_async(&mut store, (0, OFLAGS_CREAT)).await?, 0);
+            assert_eq!(write.call_async(&mut store, ()).await?, 0);
+            assert_eq!(write.call_async(&mut store, ()).await?, ERRNO_DQUOT);
+            assert_eq!(std::fs::metadata(dir.path().join("file"))?.len(), 8);
+
+            // overwriting the file doesn't add bytes
+            assert_eq!(close.call_async(&mut store, ()).await?, 0);
+            assert_eq!(open.call_async(&mut store, (0, 0)).await?, 0);
+            assert_eq!(write.call_async(&mut store, ()).await?, 0);
+
+            // the directory fits in the inodes, but not another file, while the file can still
+            // be opened
+            assert_eq!(mkdir.call_async(&mut store, ()).await?, 0);
+            assert_eq!(
+                open.call_async(&mut store, (0x10, OFLAGS_CREAT)).await?,
+                ERRNO_DQUOT
+            );
+            assert_eq!(open.call_async(&mut store, (0, OFLAGS_CREAT)).await?, 0);
+            assert!(!dir.path().join("other").exists());
+
+            // the files removed on the host are freed
+            std::fs::remove_dir(dir.path().join("dir"))?;
+            assert_eq!(open.call_async(&mut store, (0x10, OFLAGS_CREAT)).await?, 0);
+            Ok(())
+        })
+    }
+}
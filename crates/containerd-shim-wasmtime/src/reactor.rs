@@ -0,0 +1,112 @@
+//! Reactor modules.
+//!
+//! With the `runwasi.io/execution-mode: reactor` annotation, the module is not run through its
+//! `_start` export. It is instantiated and initialized with its `_initialize` export, or `init`,
+//! and the instance is then kept alive to service invocations until the container is killed.
+//!
+//! Invocations are read from the stdin of the container, one per line: the name of an export
+//! followed by its arguments separated by spaces, e.g., `add 1 2`. The results of each
+//! invocation are written to the stdout of the container, on one line.
+//! Only exports with `i32`, `i64`, `f32` and `f64` parameters and results can be invoked,
+//! see [`crate::params`].
+//!
+//! The container exits when an invocation calls `proc_exit` or traps. On SIGTERM, the reactor
+//! stops and calls the `runwasi.io/shutdown-export` function if there is one, see [`crate::shutdown`].
+
+use std::io::BufRead;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Exports called to initialize a reactor, the first one the module has is used
+pub(crate) const INIT_EXPORTS: &[&str] = &["_initialize", "init"];
+
+/// Interval at which the reactor checks for a shutdown request while waiting for invocations
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A call to an export of the reactor
+#[derive(Debug, PartialEq)]
+pub(crate) struct Invocation {
+    pub func: String,
+    pub args: Vec<String>,
+}
+
+impl Invocation {
+    /// Parses an invocation, returns `None` for a blank line
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace().map(str::to_string);
+        let func = words.next()?;
+        Some(Self {
+            func,
+            args: words.collect(),
+        })
+    }
+}
+
+/// Invocations read from a stream on a background thread.
+pub(crate) struct Invocations(mpsc::Receiver<Invocation>);
+
+impl Invocations {
+    pub fn spawn(reader: impl BufRead + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        log::debug!("stopped reading invocations: {err}");
+                        break;
+                    }
+                };
+                let Some(invocation) = Invocation::parse(&line) else {
+                    continue;
+                };
+                if tx.send(invocation).is_err() {
+                    break;
+                }
+            }
+        });
+        Self(rx)
+    }
+
+    /// Waits for the next invocation for at most `timeout`.
+    /// Once the stream is closed, the reactor keeps running without invocations.
+    pub fn next(&self, timeout: Duration) -> Option<Invocation> {
+        match self.0.recv_timeout(timeout) {
+            Ok(invocation) => Some(invocation),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(timeout);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_invocations() {
+        let invocations = Invocations::spawn(Cursor::new("add 1 2\n\n  tick \n"));
+        let next = || invocations.next(Duration::from_secs(5));
+        assert_eq!(
+            next(),
+            Some(Invocation {
+                func: "add".into(),
+                args: vec!["1".into(), "2".into()],
+            })
+        );
+        assert_eq!(
+            next(),
+            Some(Invocation {
+                func: "tick".into(),
+                args: vec![],
+            })
+        );
+        assert_eq!(invocations.next(Duration::from_millis(10)), None);
+    }
+}
@@ -0,0 +1,329 @@
+//! Record and replay of the nondeterministic results of WASI calls.
+//!
+//! With the `runwasi.io/record-mode: record` annotation, the results the guest gets from the host
+//! rather than from its own inputs, the readings of the wall and monotonic clocks and the random
+//! bytes, are written to the record log of the container.
+//! With `runwasi.io/record-mode: replay`, they are read back from the log in the same order,
+//! so that a run can be reproduced, e.g., to debug a failure with a copy of the log of the
+//! failed container.
+//!
+//! The file system, the network and stdin are not recorded: a replayed container needs the same
+//! files and inputs as the recorded one.
+//! When the guest diverges from the log, e.g., reading a clock where the log has random bytes,
+//! the replay stops and the following calls get their results from the host again.
+//!
+//! The log has an event per line, `wall <nanos>`, `monotonic <nanos>` or `random <hex>`.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use containerd_shim_wasm::container::{RecordLog, RecordMode};
+use rand_core::RngCore;
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+use crate::clocks::VirtualClocks;
+
+/// An event of the record log
+#[derive(Clone, Debug, PartialEq)]
+enum Event {
+    Wall(u64),
+    Monotonic(u64),
+    Random(Vec<u8>),
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Wall(nanos) => write!(f, "wall {nanos}"),
+            Event::Monotonic(nanos) => write!(f, "monotonic {nanos}"),
+            Event::Random(bytes) => {
+                let hex = bytes.iter().fold(String::new(), |mut hex, b| {
+                    let _ = write!(hex, "{b:02x}");
+                    hex
+                });
+                write!(f, "random {hex}")
+            }
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let (kind, value) = line.split_once(' ').unwrap_or((line, ""));
+        let event = match kind {
+            "wall" => Event::Wall(value.parse()?),
+            "monotonic" => Event::Monotonic(value.parse()?),
+            "random" => Event::Random(
+                (0..value.len())
+                    .step_by(2)
+                    .map(|i| {
+                        value
+                            .get(i..i + 2)
+                            .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    })
+                    .collect::<Option<_>>()
+                    .context("invalid random bytes")?,
+            ),
+            _ => bail!("unknown event {kind:?}"),
+        };
+        Ok(event)
+    }
+}
+
+enum State {
+    Record(LineWriter<File>),
+    Replay(VecDeque<Event>),
+    // the log is no longer recorded or replayed after a failure or a divergence
+    Host,
+}
+
+/// The record log of a container, shared by the clocks and random generators of its WASI contexts.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<State>>);
+
+impl Recorder {
+    /// Opens the log for recording, truncating it, or loads the events to replay.
+    pub fn open(log: &RecordLog) -> Result<Self> {
+        let path = &log.path;
+        let state = match log.mode {
+            RecordMode::Record => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open record log {path:?}"))?;
+                State::Record(LineWriter::new(file))
+            }
+            RecordMode::Replay => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("failed to read record log {path:?}"))?;
+                let events = content
+                    .lines()
+                    .enumerate()
+                    .map(|(n, line)| {
+                        line.parse()
+                            .with_context(|| format!("invalid record log line {}", n + 1))
+                    })
+                    .collect::<Result<_>>()?;
+                State::Replay(events)
+            }
+        };
+        Ok(Self(Arc::new(Mutex::new(state))))
+    }
+
+    /// Sets the clocks and random generators of a WASI context to the recorded ones.
+    pub(crate) fn apply(&self, clocks: &VirtualClocks, builder: &mut WasiCtxBuilder) {
+        let mut seed = [0; 16];
+        let mut rng = self.rng(wasmtime_wasi::thread_rng());
+        rng.fill_bytes(&mut seed);
+        builder
+            .wall_clock(RecordedWallClock {
+                clock: clocks.wall_clock(),
+                recorder: self.clone(),
+            })
+            .monotonic_clock(RecordedMonotonicClock {
+                clock: clocks.monotonic_clock(),
+                recorder: self.clone(),
+            })
+            .secure_random(self.rng(wasmtime_wasi::thread_rng()))
+            .insecure_random(rng)
+            .insecure_random_seed(u128::from_le_bytes(seed));
+    }
+
+    fn rng<R: RngCore + Send>(&self, rng: R) -> RecordedRng<R> {
+        RecordedRng {
+            rng,
+            recorder: self.clone(),
+        }
+    }
+
+    /// Returns the next result of the log when replaying, the host result otherwise.
+    fn next<T>(
+        &self,
+        host: impl FnOnce() -> T,
+        encode: impl FnOnce(&T) -> Event,
+        decode: impl FnOnce(Event) -> Option<T>,
+    ) -> T {
+        let mut state = self.0.lock().unwrap();
+        match &mut *state {
+            State::Record(log) => {
+                let value = host();
+                if let Err(err) = writeln!(log, "{}", encode(&value)) {
+                    log::warn!("failed to write the record log, stopped recording: {err}");
+                    *state = State::Host;
+                }
+                value
+            }
+            State::Replay(events) => {
+                let event = events.pop_front();
+                match event.clone().and_then(decode) {
+                    Some(value) => value,
+                    None => {
+                        log::warn!("the guest diverged from the record log at {event:?}, stopped replaying");
+                        *state = State::Host;
+                        host()
+                    }
+                }
+            }
+            State::Host => host(),
+        }
+    }
+}
+
+struct RecordedWallClock<C> {
+    clock: C,
+    recorder: Recorder,
+}
+
+impl<C: HostWallClock> HostWallClock for RecordedWallClock<C> {
+    fn resolution(&self) -> Duration {
+        self.clock.resolution()
+    }
+
+    fn now(&self) -> Duration {
+        self.recorder.next(
+            || self.clock.now(),
+            |now| Event::Wall(now.as_nanos() as u64),
+            |event| match event {
+                Event::Wall(nanos) => Some(Duration::from_nanos(nanos)),
+                _ => None,
+            },
+        )
+    }
+}
+
+struct RecordedMonotonicClock<C> {
+    clock: C,
+    recorder: Recorder,
+}
+
+impl<C: HostMonotonicClock> HostMonotonicClock for RecordedMonotonicClock<C> {
+    fn resolution(&self) -> u64 {
+        self.clock.resolution()
+    }
+
+    fn now(&self) -> u64 {
+        self.recorder.next(
+            || self.clock.now(),
+            |now| Event::Monotonic(*now),
+            |event| match event {
+                Event::Monotonic(nanos) => Some(nanos),
+                _ => None,
+            },
+        )
+    }
+}
+
+struct RecordedRng<R> {
+    rng: R,
+    recorder: Recorder,
+}
+
+impl<R: RngCore> RngCore for RecordedRng<R> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let len = dest.len();
+        let rng = &mut self.rng;
+        let bytes = self.recorder.next(
+            || {
+                let mut bytes = vec![0; len];
+                rng.fill_bytes(&mut bytes);
+                bytes
+            },
+            |bytes| Event::Random(bytes.clone()),
+            |event| match event {
+                Event::Random(bytes) if bytes.len() == len => Some(bytes),
+                _ => None,
+            },
+        );
+        dest.copy_from_slice(&bytes);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn readings(recorder: &Recorder) -> (Duration, u64, [u8; 8]) {
+        let wall = RecordedWallClock {
+            clock: VirtualClocks::default().wall_clock(),
+            recorder: recorder.clone(),
+        };
+        let monotonic = RecordedMonotonicClock {
+            clock: VirtualClocks::default().monotonic_clock(),
+            recorder: recorder.clone(),
+        };
+        let mut random = [0; 8];
+        recorder
+            .rng(wasmtime_wasi::thread_rng())
+            .fill_bytes(&mut random);
+        (wall.now(), monotonic.now(), random)
+    }
+
+    #[test]
+    fn test_record_and_replay() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("record.log");
+        let recorded = readings(&Recorder::open(&RecordLog {
+            mode: RecordMode::Record,
+            path: path.clone(),
+        })?);
+        assert_eq!(fs::read_to_string(&path)?.lines().count(), 3);
+
+        let replay = Recorder::open(&RecordLog {
+            mode: RecordMode::Replay,
+            path,
+        })?;
+        assert_eq!(readings(&replay), recorded);
+
+        // the log is exhausted, the results come from the host again
+        let mut random = [0; 8];
+        replay
+            .rng(wasmtime_wasi::thread_rng())
+            .fill_bytes(&mut random);
+        assert!(matches!(*replay.0.lock().unwrap(), State::Host));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_event() -> Result<()> {
+        for event in [
+            Event::Wall(1704067200000000000),
+            Event::Monotonic(42),
+            Event::Random(vec![0, 1, 0xfe, 0xff]),
+            Event::Random(vec![]),
+        ] {
+            assert_eq!(event.to_string().parse::<Event>()?, event);
+        }
+        assert!("random 0".parse::<Event>().is_err());
+        assert!("stdin 42".parse::<Event>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Cooperative scheduling of the instances of a container on a small pool of threads.
+//!
+//! The instances of a container, e.g., the instances handling its `wasi:http` requests, the
+//! connections to its ports or the instances of its pool, run as tasks of one tokio runtime,
+//! with the workers of the `threads` runtime option of the shim, or a worker per CPU the
+//! container can run on. An instance waiting in a host call, e.g., reading a socket, doesn't
+//! hold a worker, so hundreds of mostly-idle instances can share a few threads.
+//!
+//! An instance running wasm code yields its worker to the other tasks at the end of every time
+//! slice, with epoch interruption, so that a busy instance doesn't starve the others, and an
+//! instance paused by its CPU quota hands its worker over to the other tasks while it sleeps.
+//!  - `RUNWASI_WASMTIME_TIME_SLICE_MS`: the time slice, in milliseconds, rounded to the 10ms
+//!    ticks of the epoch. The default is a single tick.
+
+use std::num::NonZeroUsize;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use containerd_shim_wasm::sandbox::node_config;
+use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
+
+use crate::limits::EPOCH_TICK;
+
+pub const TIME_SLICE_ENV: &str = "RUNWASI_WASMTIME_TIME_SLICE_MS";
+
+// The time slice of the process, in epoch ticks, read when the engine is created in the shim
+static TIME_SLICE: OnceLock<u64> = OnceLock::new();
+
+/// Reads the time slice from the environment of the shim.
+/// This is called when the engine is created, so that the containers started by the shim
+/// inherit it.
+pub(crate) fn init() -> Result<()> {
+    let ticks = time_slice_from_lookup(|var| node_config::var(var).ok())?;
+    let _ = TIME_SLICE.set(ticks);
+    Ok(())
+}
+
+/// The number of epoch ticks an instance runs wasm code for before yielding its worker
+pub(crate) fn time_slice() -> u64 {
+    TIME_SLICE.get().copied().unwrap_or(1)
+}
+
+fn time_slice_from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<u64> {
+    let Some(millis) = lookup(TIME_SLICE_ENV) else {
+        return Ok(1);
+    };
+    let millis: u64 = millis
+        .trim()
+        .parse()
+        .ok()
+        .filter(|millis| *millis > 0)
+        .with_context(|| format!("invalid value {millis:?} for {TIME_SLICE_ENV}"))?;
+    Ok((millis / EPOCH_TICK.as_millis() as u64).max(1))
+}
+
+/// Builds the runtime the instances of a container run on, with `threads` workers, or a worker
+/// per CPU the container can run on.
+pub(crate) fn runtime(threads: Option<NonZeroUsize>) -> Result<Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = threads {
+        builder.worker_threads(threads.get());
+    }
+    builder
+        .thread_name("wasm-worker")
+        .enable_all()
+        .build()
+        .context("failed to start the runtime of the instances")
+}
+
+/// Runs `f`, which blocks the thread, without blocking the other tasks of the worker of the
+/// current task, if any.
+pub(crate) fn block_in_place<R>(f: impl FnOnce() -> R) -> R {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn time_slice_of(vars: &[(&str, &str)]) -> Result<u64> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        time_slice_from_lookup(|var| vars.get(var).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn test_time_slice() -> Result<()> {
+        assert_eq!(time_slice_of(&[])?, 1);
+        assert_eq!(time_slice_of(&[(TIME_SLICE_ENV, "50")])?, 5);
+        // rounded down to the ticks of the epoch, and at least one tick
+        assert_eq!(time_slice_of(&[(TIME_SLICE_ENV, "25")])?, 2);
+        assert_eq!(time_slice_of(&[(TIME_SLICE_ENV, "1")])?, 1);
+        assert!(time_slice_of(&[(TIME_SLICE_ENV, "0")]).is_err());
+        assert!(time_slice_of(&[(TIME_SLICE_ENV, "fast")]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_in_place() -> Result<()> {
+        // a task blocking its only worker doesn't block the other tasks
+        let runtime = runtime(NonZeroUsize::new(1))?;
+        let released = Arc::new(AtomicBool::new(false));
+        runtime.block_on(async {
+            let blocked = tokio::spawn({
+                let released = released.clone();
+                async move {
+                    block_in_place(|| {
+                        while !released.load(Ordering::SeqCst) {
+                            std::thread::sleep(Duration::from_millis(1));
+                        }
+                    })
+                }
+            });
+            tokio::spawn({
+                let released = released.clone();
+                async move { released.store(true, Ordering::SeqCst) }
+            })
+            .await?;
+            blocked.await?;
+            anyhow::Ok(())
+        })?;
+
+        // outside of a runtime, the function is just called
+        assert_eq!(block_in_place(|| 42), 42);
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Graceful shutdown of wasm modules.
+//!
+//! The `runwasi.io/shutdown-export` annotation names a function exported by the module
+//! that is called when the container receives a SIGTERM. The entrypoint is interrupted the
+//! next time it runs wasm code, and the export is called in its place. The container exits
+//! with `0` when the export returns, or with the code it passes to `proc_exit`.
+//! If the module doesn't have the export, the container exits with [`SIGTERM_EXIT_CODE`].
+//!
+//! The shim kills the container if it is still running once its grace period is over.
+//!
+//! Components can't be re-entered once interrupted, so the annotation is ignored for
+//! components, which are given the grace period to exit on their own.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Result;
+
+/// Annotation with the name of the function called when the container receives a SIGTERM
+pub const SHUTDOWN_EXPORT_ANNOTATION: &str = "runwasi.io/shutdown-export";
+
+/// Exit code of a module interrupted by a SIGTERM that has no shutdown export,
+/// the same as a process terminated by the signal
+pub const SIGTERM_EXIT_CODE: i32 = 128 + 15;
+
+/// Error returned when the entrypoint of an instance is interrupted by a SIGTERM
+#[derive(Debug)]
+pub struct ShutdownRequested;
+
+impl fmt::Display for ShutdownRequested {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "shutdown requested")
+    }
+}
+
+impl std::error::Error for ShutdownRequested {}
+
+fn requested() -> &'static Arc<AtomicBool> {
+    static REQUESTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    REQUESTED.get_or_init(Default::default)
+}
+
+/// Sets a handler for SIGTERM that requests the shutdown of the instance.
+pub(crate) fn install_handler() -> Result<()> {
+    static HANDLER: OnceLock<()> = OnceLock::new();
+    if HANDLER.get().is_none() {
+        signal_hook::flag::register(signal_hook::consts::SIGTERM, requested().clone())?;
+        let _ = HANDLER.set(());
+    }
+    Ok(())
+}
+
+/// Requests the shutdown of the instance, as a SIGTERM would.
+#[cfg(test)]
+pub(crate) fn request() {
+    requested().store(true, Ordering::SeqCst);
+}
+
+/// Returns whether a shutdown was requested, and clears the request.
+pub(crate) fn take_request() -> bool {
+    requested().swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,240 @@
+//! Network policy for `wasi:sockets` TCP and UDP sockets.
+//!
+//! The `runwasi.io/wasi-sockets-allowed` annotation holds a comma separated
+//! list of the destinations a container can connect or send datagrams to.
+//! Each entry is a CIDR, optionally followed by a port or a port range, e.g.:
+//!  - `10.0.0.0/8`: any port on the 10.0.0.0/8 network
+//!  - `192.168.1.10:443`: port 443 on a single address
+//!  - `0.0.0.0/0:8000-8999`: a port range on any IPv4 address
+//!  - `[fd00::/8]:53`: port 53 on an IPv6 network
+//!
+//! Without the annotation every destination is allowed.
+//! Binding sockets is not restricted, as the container network namespace already scopes it.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use wasmtime_wasi::SocketAddrUse;
+
+/// Annotation with the destinations sockets are allowed to reach
+pub const SOCKETS_ALLOWED_ANNOTATION: &str = "runwasi.io/wasi-sockets-allowed";
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    network: IpAddr,
+    prefix: u8,
+    ports: RangeInclusive<u16>,
+}
+
+fn parse_ports(ports: &str) -> Result<RangeInclusive<u16>> {
+    let (start, end) = ports.split_once('-').unwrap_or((ports, ports));
+    let start = start
+        .parse()
+        .with_context(|| format!("invalid port {start:?}"))?;
+    let end = end
+        .parse()
+        .with_context(|| format!("invalid port {end:?}"))?;
+    if start > end {
+        bail!("invalid port range {ports:?}");
+    }
+    Ok(start..=end)
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        let (cidr, ports) = match rule.strip_prefix('[') {
+            // IPv6 networks with a port are wrapped in brackets
+            Some(rest) => match rest.split_once(']') {
+                Some((cidr, "")) => (cidr, None),
+                Some((cidr, ports)) => match ports.strip_prefix(':') {
+                    Some(ports) => (cidr, Some(ports)),
+                    None => bail!("invalid socket rule {rule:?}"),
+                },
+                None => bail!("invalid socket rule {rule:?}"),
+            },
+            None if rule.matches(':').count() == 1 => {
+                let (cidr, ports) = rule.split_once(':').unwrap();
+                (cidr, Some(ports))
+            }
+            None => (rule, None),
+        };
+
+        let (network, prefix) = cidr.split_once('/').unwrap_or((cidr, ""));
+        let network: IpAddr = network
+            .parse()
+            .with_context(|| format!("invalid address in socket rule {rule:?}"))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max_prefix,
+            prefix => prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .with_context(|| format!("invalid prefix length in socket rule {rule:?}"))?,
+        };
+        let ports = match ports {
+            Some(ports) => parse_ports(ports)?,
+            None => 0..=u16::MAX,
+        };
+
+        Ok(Self {
+            network,
+            prefix,
+            ports,
+        })
+    }
+
+    fn matches(&self, addr: &SocketAddr) -> bool {
+        if !self.ports.contains(&addr.port()) {
+            return false;
+        }
+        let ip = match (self.network, addr.ip()) {
+            // match IPv4-mapped IPv6 addresses against IPv4 rules
+            (IpAddr::V4(_), IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+                Some(ip) => IpAddr::V4(ip),
+                None => return false,
+            },
+            (_, ip) => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(network).into(),
+                u32::from(ip).into(),
+                self.prefix,
+                32,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(network.into(), ip.into(), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: u128, ip: u128, prefix: u8, bits: u8) -> bool {
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    (network >> shift) == (ip >> shift)
+}
+
+/// Destinations the sockets of a container are allowed to reach
+#[derive(Clone, Debug, Default)]
+pub struct SocketPolicy {
+    allowed: Option<Arc<Vec<Rule>>>,
+    disabled: bool,
+}
+
+impl SocketPolicy {
+    /// Builds the policy from the container annotations.
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let allowed = annotations
+            .get(SOCKETS_ALLOWED_ANNOTATION)
+            .map(|rules| {
+                rules
+                    .split(',')
+                    .filter(|r| !r.trim().is_empty())
+                    .map(Rule::parse)
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?
+            .map(Arc::new);
+        Ok(Self {
+            allowed,
+            disabled: false,
+        })
+    }
+
+    /// A policy without sockets, for the containers denied the `sockets` capability.
+    pub fn disabled() -> Self {
+        Self {
+            allowed: None,
+            disabled: true,
+        }
+    }
+
+    /// Returns false if the container can't use sockets at all.
+    pub fn is_enabled(&self) -> bool {
+        !self.disabled
+    }
+
+    /// Returns true if the socket address can be used.
+    pub fn is_allowed(&self, addr: &SocketAddr, addr_use: SocketAddrUse) -> bool {
+        let allowed = match (&self.allowed, addr_use) {
+            _ if self.disabled => false,
+            (None, _) | (_, SocketAddrUse::TcpBind | SocketAddrUse::UdpBind) => true,
+            (Some(rules), _) => rules.iter().any(|r| r.matches(addr)),
+        };
+        if !allowed {
+            log::info!("socket address {addr} denied by network policy");
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(rules: &str) -> SocketPolicy {
+        let annotations =
+            HashMap::from([(SOCKETS_ALLOWED_ANNOTATION.to_string(), rules.to_string())]);
+        SocketPolicy::from_annotations(&annotations).unwrap()
+    }
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_default_allows_all() {
+        let policy = SocketPolicy::default();
+        assert!(policy.is_allowed(&addr("1.2.3.4:80"), SocketAddrUse::TcpConnect));
+    }
+
+    #[test]
+    fn test_cidr_and_ports() {
+        let policy = policy("10.0.0.0/8, 192.168.1.10:443, 0.0.0.0/0:8000-8999, [fd00::/8]:53");
+        let connect = SocketAddrUse::TcpConnect;
+
+        assert!(policy.is_allowed(&addr("10.1.2.3:22"), connect));
+        assert!(policy.is_allowed(&addr("192.168.1.10:443"), connect));
+        assert!(!policy.is_allowed(&addr("192.168.1.10:80"), connect));
+        assert!(!policy.is_allowed(&addr("192.168.1.11:443"), connect));
+        assert!(policy.is_allowed(&addr("8.8.8.8:8080"), connect));
+        assert!(!policy.is_allowed(&addr("8.8.8.8:9000"), connect));
+        assert!(policy.is_allowed(&addr("[fd12::1]:53"), SocketAddrUse::UdpOutgoingDatagram));
+        assert!(!policy.is_allowed(&addr("[fe80::1]:53"), SocketAddrUse::UdpConnect));
+        assert!(policy.is_allowed(&addr("[::ffff:10.0.0.1]:80"), connect));
+    }
+
+    #[test]
+    fn test_bind_is_not_restricted() {
+        let policy = policy("10.0.0.0/8");
+        assert!(policy.is_allowed(&addr("0.0.0.0:8080"), SocketAddrUse::TcpBind));
+        assert!(policy.is_allowed(&addr("0.0.0.0:8080"), SocketAddrUse::UdpBind));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        for rules in [
+            "10.0.0.0/33",
+            "10.0.0.0:http",
+            "not-an-ip",
+            "[fd00::/8:53",
+            "10.0.0.1:90-80",
+        ] {
+            let annotations =
+                HashMap::from([(SOCKETS_ALLOWED_ANNOTATION.to_string(), rules.to_string())]);
+            assert!(
+                SocketPolicy::from_annotations(&annotations).is_err(),
+                "{rules:?} should be invalid"
+            );
+        }
+    }
+}
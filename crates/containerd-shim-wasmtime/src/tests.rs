@@ -257,3 +257,29 @@ fn test_wasip2_component() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+// Test that components are precompiled as components and not as core modules
+#[test]
+fn test_precompile_component() -> anyhow::Result<()> {
+    use containerd_shim_wasm::container::Engine;
+    use containerd_shim_wasm::sandbox::WasmLayer;
+    use oci_spec::image::{Descriptor, MediaType};
+
+    let engine = WasmtimeEngine::<WasiTestConfig>::default();
+    let layer = WasmLayer {
+        config: Descriptor::new(MediaType::Other("".to_string()), 0, ""),
+        layer: COMPONENT_HELLO_WORLD.bytes.to_vec().into(),
+    };
+
+    let compiled = engine.precompile(&[layer])?;
+    let compiled = compiled[0]
+        .as_ref()
+        .expect("component should be precompiled");
+
+    assert!(matches!(
+        engine.engine.detect_precompiled(compiled),
+        Some(wasmtime::Precompiled::Component)
+    ));
+
+    Ok(())
+}
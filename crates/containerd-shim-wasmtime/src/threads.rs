@@ -0,0 +1,210 @@
+//! wasi-threads for wasm modules, with the `runwasi.io/wasi-threads` annotation.
+//!
+//! The shim creates the shared memories imported by the module, and links its
+//! `wasi::thread-spawn` import. A spawned thread runs the `wasi_thread_start` export of a new
+//! instance of the module, on its own OS thread and in its own store, importing the same shared
+//! memories. The instance of a thread has its own WASI context, with the arguments, environment,
+//! preopens and policies of the container, so the files a thread opens aren't visible to the
+//! others.
+//!
+//! The guest runs at most `linux.resources.pids.limit` threads, its main thread included, and
+//! `thread-spawn` returns a negative id past the limit. A thread that traps, or calls
+//! `proc_exit`, exits the container, as with the wasmtime CLI.
+//!
+//! Components can't import shared memories, so the annotation is ignored for components.
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use anyhow::{ensure, Context, Result};
+use wasmtime::{Caller, ExternType, InstancePre, Linker, Module, SharedMemory, Store};
+
+use crate::instance::WasiCtx;
+use crate::limits;
+
+/// The export of the module running a spawned thread, with its id and start argument
+const THREAD_START_EXPORT: &str = "wasi_thread_start";
+
+// The ids of the threads are positive and under 2^29, see the wasi-threads proposal
+const MAX_THREAD_ID: i32 = 0x1FFF_FFFF;
+
+const LIBRARIES_ERROR: &str = "wasi-threads is not supported for modules linked with libraries";
+
+/// The threads spawned by the guest of a container
+pub(crate) struct WasiThreads {
+    new_ctx: Box<dyn Fn() -> Result<WasiCtx> + Send + Sync>,
+    slots: Slots,
+    pre: OnceLock<InstancePre<WasiCtx>>,
+}
+
+impl WasiThreads {
+    /// `new_ctx` builds the WASI context of every spawned thread.
+    pub fn new(
+        new_ctx: impl Fn() -> Result<WasiCtx> + Send + Sync + 'static,
+        max_threads: Option<NonZeroUsize>,
+    ) -> Self {
+        Self {
+            new_ctx: Box::new(new_ctx),
+            slots: Slots::new(max_threads),
+            pre: OnceLock::new(),
+        }
+    }
+
+    // Starts a thread running `wasi_thread_start`, and returns its id
+    fn spawn(self: &Arc<Self>, start_arg: i32) -> Result<i32> {
+        let pre = self
+            .pre
+            .get()
+            .context("wasi-threads is not linked")?
+            .clone();
+        let slot = self
+            .slots
+            .acquire()
+            .context("the guest reached its maximum number of threads")?;
+        let mut wasi_ctx = (self.new_ctx)()?;
+        wasi_ctx.wasi_threads = Some(self.clone());
+
+        let tid = slot.tid;
+        thread::Builder::new()
+            .name(format!("wasi-thread-{tid}"))
+            .spawn(move || {
+                let status = run(pre, wasi_ctx, tid, start_arg);
+                drop(slot);
+                let Err(err) = status else {
+                    return;
+                };
+                let code = match err.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                    Some(exit) => exit.process_exit_code(),
+                    None => {
+                        log::error!("wasi thread {tid} failed: {err:?}");
+                        1
+                    }
+                };
+                std::process::exit(code);
+            })?;
+        Ok(tid)
+    }
+}
+
+fn run(pre: InstancePre<WasiCtx>, wasi_ctx: WasiCtx, tid: i32, start_arg: i32) -> Result<()> {
+    let mut store = limits::new_store(pre.module().engine(), wasi_ctx);
+    wasmtime_wasi::runtime::in_tokio(async move {
+        let instance = pre.instantiate_async(&mut store).await?;
+        let start = instance
+            .get_typed_func::<(i32, i32), ()>(&mut store, THREAD_START_EXPORT)
+            .with_context(|| format!("module does not export {THREAD_START_EXPORT:?}"))?;
+        start.call_async(&mut store, (tid, start_arg)).await
+    })
+}
+
+/// Links the shared memories imported by `module`, and `wasi::thread-spawn`, when the store
+/// of the container has wasi-threads.
+pub(crate) fn link(
+    linker: &mut Linker<WasiCtx>,
+    store: &mut Store<WasiCtx>,
+    module: &Module,
+    libraries: &[(String, Module)],
+) -> Result<()> {
+    let Some(threads) = store.data().wasi_threads.clone() else {
+        return Ok(());
+    };
+    ensure!(libraries.is_empty(), LIBRARIES_ERROR);
+
+    log::info!("linking wasi-threads");
+    for import in module.imports() {
+        if let ExternType::Memory(ty) = import.ty() {
+            if ty.is_shared() {
+                let memory = SharedMemory::new(module.engine(), ty)?;
+                linker.define(&mut *store, import.module(), import.name(), memory)?;
+            }
+        }
+    }
+    linker.func_wrap(
+        "wasi",
+        "thread-spawn",
+        |caller: Caller<'_, WasiCtx>, start_arg: i32| -> i32 {
+            let Some(threads) = caller.data().wasi_threads.clone() else {
+                return -1;
+            };
+            threads.spawn(start_arg).unwrap_or_else(|err| {
+                log::warn!("failed to spawn wasi thread: {err:#}");
+                -1
+            })
+        },
+    )?;
+    let _ = threads.pre.set(linker.instantiate_pre(module)?);
+    Ok(())
+}
+
+// The ids of the spawned threads, with the number of threads alive
+struct Slots {
+    live: Arc<AtomicUsize>,
+    max_live: usize,
+    next_tid: AtomicI32,
+}
+
+// A thread alive, until it's dropped
+struct Slot {
+    live: Arc<AtomicUsize>,
+    tid: i32,
+}
+
+impl Slots {
+    fn new(max_threads: Option<NonZeroUsize>) -> Self {
+        Self {
+            live: Arc::default(),
+            // the main thread of the guest is one of the threads
+            max_live: max_threads.map_or(usize::MAX, |max| max.get() - 1),
+            next_tid: AtomicI32::new(1),
+        }
+    }
+
+    fn acquire(&self) -> Option<Slot> {
+        self.live
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                (live < self.max_live).then_some(live + 1)
+            })
+            .ok()?;
+        let tid = self
+            .next_tid
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |tid| {
+                Some(if tid >= MAX_THREAD_ID { 1 } else { tid + 1 })
+            })
+            .unwrap();
+        Some(Slot {
+            live: self.live.clone(),
+            tid,
+        })
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.live.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots() {
+        let slots = Slots::new(NonZeroUsize::new(3));
+        let first = slots.acquire().unwrap();
+        let second = slots.acquire().unwrap();
+        assert_eq!((first.tid, second.tid), (1, 2));
+
+        // the main thread and two spawned threads are alive
+        assert!(slots.acquire().is_none());
+        drop(first);
+        assert_eq!(slots.acquire().map(|slot| slot.tid), Some(3));
+
+        let slots = Slots::new(None);
+        slots.next_tid.store(MAX_THREAD_ID, Ordering::SeqCst);
+        assert_eq!(slots.acquire().map(|slot| slot.tid), Some(MAX_THREAD_ID));
+        assert_eq!(slots.acquire().map(|slot| slot.tid), Some(1));
+    }
+}
@@ -0,0 +1,359 @@
+//! Host implementation of the `runwasi:tls/client` interface.
+//!
+//! Components can open TLS connections without bundling a TLS stack, by importing
+//! `runwasi:tls/client` and calling `connect` with a host and a port: the host resolves the name
+//! with the resolv.conf of the container, opens the connection, subject to the socket policy of
+//! the container, and performs the handshake. The guest reads and writes the plaintext of the
+//! connection through the streams it gets back.
+//!
+//! Certificates are validated with the trust store of the node, loaded by the shim at startup
+//! from `SSL_CERT_FILE` or the CA bundle of the distribution, as the rootfs of a wasm image has
+//! none. The `runwasi.io/tls-pins` annotation further pins the certificates of some hosts, with
+//! a comma separated list of `<host>=sha256:<fingerprint>` entries, where the fingerprint is the
+//! SHA-256 digest of the DER encoded leaf certificate, e.g., as printed by
+//! `openssl x509 -noout -fingerprint -sha256`. A host with several pins accepts any of them, e.g.,
+//! while rotating its certificate.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::pipe::{AsyncReadStream, AsyncWriteStream};
+use wasmtime_wasi::{InputStream, OutputStream, SocketAddrUse};
+
+use crate::dns;
+use crate::http_egress::normalize_host;
+use crate::instance::WasiCtx;
+use crate::socket_policy::SocketPolicy;
+
+/// Annotation with the certificate pins of the hosts, e.g. `api.example.com=sha256:<fingerprint>`
+pub const TLS_PINS_ANNOTATION: &str = "runwasi.io/tls-pins";
+
+/// The CA bundles of the common distributions, after `SSL_CERT_FILE`
+const CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// Bytes written by the guest buffered before it waits for the connection
+const WRITE_BUDGET: usize = 64 * 1024;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit/tls",
+        world: "runwasi:tls/imports",
+        async: {
+            only_imports: ["connect"],
+        },
+        trappable_imports: true,
+        with: {
+            "wasi:io/error": wasmtime_wasi::bindings::io::error,
+            "wasi:io/poll": wasmtime_wasi::bindings::io::poll,
+            "wasi:io/streams": wasmtime_wasi::bindings::io::streams,
+        },
+    });
+}
+
+use bindings::runwasi::tls::client;
+
+/// Add the `runwasi:tls/client` interface to `linker`.
+pub(crate) fn add_to_linker(linker: &mut Linker<WasiCtx>) -> Result<()> {
+    client::add_to_linker(linker, |ctx| ctx)
+}
+
+/// Loads the trust store of the node, empty when it has no CA bundle.
+pub fn node_roots() -> Arc<RootCertStore> {
+    let bundle = std::env::var_os("SSL_CERT_FILE")
+        .map(Into::into)
+        .into_iter()
+        .chain(CA_BUNDLES.iter().map(Into::into))
+        .find(|path: &std::path::PathBuf| path.is_file());
+    let Some(bundle) = bundle else {
+        log::warn!("no CA bundle found, TLS connections of the guests will fail");
+        return Arc::new(RootCertStore::empty());
+    };
+    match load_roots(&bundle) {
+        Ok(roots) => {
+            log::debug!("loaded {} trust anchors from {bundle:?}", roots.len());
+            Arc::new(roots)
+        }
+        Err(err) => {
+            log::warn!("failed to load the CA bundle {bundle:?}: {err:#}");
+            Arc::new(RootCertStore::empty())
+        }
+    }
+}
+
+fn load_roots(bundle: &Path) -> Result<RootCertStore> {
+    let mut reader = BufReader::new(File::open(bundle)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    let mut roots = RootCertStore::empty();
+    let (_, ignored) = roots.add_parsable_certificates(certs);
+    if ignored > 0 {
+        log::debug!("ignored {ignored} invalid certificates of {bundle:?}");
+    }
+    Ok(roots)
+}
+
+/// Certificate pins of the hosts, by host name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pins(HashMap<String, Vec<String>>);
+
+impl Pins {
+    /// Parses the pins of the `runwasi.io/tls-pins` annotation.
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let mut pins = HashMap::<_, Vec<_>>::new();
+        let Some(entries) = annotations.get(TLS_PINS_ANNOTATION) else {
+            return Ok(Self(pins));
+        };
+        for entry in entries.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((host, pin)) = entry.split_once('=') else {
+                bail!(
+                    "invalid pin {entry:?} in {TLS_PINS_ANNOTATION}, expected <host>=sha256:<hex>"
+                );
+            };
+            let fingerprint = pin
+                .strip_prefix("sha256:")
+                .and_then(parse_fingerprint)
+                .with_context(|| format!("invalid fingerprint {pin:?} for {host}"))?;
+            pins.entry(normalize_host(host))
+                .or_default()
+                .push(fingerprint);
+        }
+        Ok(Self(pins))
+    }
+
+    /// Returns whether the leaf certificate `cert` of `host` is accepted.
+    pub fn accepts(&self, host: &str, cert: &[u8]) -> bool {
+        match self.0.get(&normalize_host(host)) {
+            Some(fingerprints) => fingerprints.contains(&sha256::digest(cert)),
+            None => true,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// a hex encoded digest, with or without colons between the bytes, normalized to lowercase
+fn parse_fingerprint(fingerprint: &str) -> Option<String> {
+    let digits: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let valid = digits.len() == 64 && digits.chars().all(|c| c.is_ascii_hexdigit());
+    valid.then_some(digits)
+}
+
+/// Validates the certificates with the trust store, and then with the pins of the host
+#[derive(Debug)]
+struct PinningVerifier {
+    webpki: Arc<WebPkiServerVerifier>,
+    pins: Pins,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(address) => std::net::IpAddr::from(*address).to_string(),
+            _ => String::new(),
+        };
+        if !self.pins.accepts(&host, end_entity) {
+            log::info!("certificate of {host} doesn't match its pins");
+            return Err(rustls::Error::General(format!(
+                "the certificate of {host} doesn't match the pins of {TLS_PINS_ANNOTATION}"
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+/// The configuration of the TLS clients of a container, validating the certificates with `roots`
+/// and `pins`.
+pub(crate) fn client_config(roots: Arc<RootCertStore>, pins: Pins) -> Result<ClientConfig> {
+    // without trust anchors, every handshake fails before the pins are checked
+    let config = match pins.is_empty() || roots.is_empty() {
+        true => ClientConfig::builder().with_root_certificates(roots),
+        false => {
+            let webpki = WebPkiServerVerifier::builder(roots)
+                .build()
+                .context("invalid trust store")?;
+            ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinningVerifier { webpki, pins }))
+        }
+    };
+    Ok(config.with_no_client_auth())
+}
+
+/// The TLS client of a container
+#[derive(Clone)]
+pub(crate) struct TlsClient {
+    connector: TlsConnector,
+    sockets: SocketPolicy,
+}
+
+impl TlsClient {
+    pub fn new(roots: Arc<RootCertStore>, pins: Pins, sockets: SocketPolicy) -> Result<Self> {
+        let config = client_config(roots, pins)?;
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            sockets,
+        })
+    }
+
+    /// Opens a TLS connection to `port` of `host`.
+    pub async fn connect(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
+        let server_name = ServerName::try_from(host.to_string())
+            .with_context(|| format!("invalid server name {host:?}"))?;
+        let addresses = dns::lookup_host(host)
+            .await
+            .map_err(|err| anyhow::anyhow!("failed to resolve {host}: {err:?}"))?;
+
+        let mut last_error = None;
+        for address in addresses {
+            let address = SocketAddr::new(address, port);
+            if !self.sockets.is_allowed(&address, SocketAddrUse::TcpConnect) {
+                last_error = Some(anyhow::anyhow!("connection to {address} denied by policy"));
+                continue;
+            }
+            match TcpStream::connect(address).await {
+                Ok(stream) => {
+                    let stream = self
+                        .connector
+                        .connect(server_name, stream)
+                        .await
+                        .with_context(|| format!("tls handshake with {host} failed"))?;
+                    return Ok(stream);
+                }
+                Err(err) => {
+                    last_error = Some(
+                        anyhow::Error::new(err).context(format!("failed to connect to {address}")),
+                    )
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no address for {host}")))
+    }
+}
+
+#[wasmtime_wasi::async_trait]
+impl client::Host for WasiCtx {
+    async fn connect(
+        &mut self,
+        host: String,
+        port: u16,
+    ) -> wasmtime::Result<Result<(Resource<InputStream>, Resource<OutputStream>), String>> {
+        let Some(tls) = self.tls.clone() else {
+            return Ok(Err("tls connections are not allowed".to_string()));
+        };
+        let stream = match tls.connect(&host, port).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::debug!("failed to open a tls connection to {host}:{port}: {err:#}");
+                return Ok(Err(format!("{err:#}")));
+            }
+        };
+        let (reader, writer) = tokio::io::split(stream);
+        let input = InputStream::Host(Box::new(AsyncReadStream::new(reader)));
+        let output: OutputStream = Box::new(AsyncWriteStream::new(WRITE_BUDGET, writer));
+        let input = self.resource_table.push(input)?;
+        let output = self.resource_table.push(output)?;
+        Ok(Ok((input, output)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str =
+        "5D:41:40:2A:BC:4B:2A:76:B9:71:9D:91:10:17:C5:92:5D:41:40:2A:BC:4B:2A:76:B9:71:9D:91:10:17:C5:92";
+
+    #[test]
+    fn test_pins() -> Result<()> {
+        let cert = b"certificate";
+        let digest = sha256::digest(cert.as_slice());
+        let annotations = HashMap::from([(
+            TLS_PINS_ANNOTATION.to_string(),
+            format!("API.example.com=sha256:{FINGERPRINT}, api.example.com=sha256:{digest}"),
+        )]);
+        let pins = Pins::from_annotations(&annotations)?;
+        assert!(pins.accepts("api.example.com", cert));
+        assert!(!pins.accepts("api.example.com", b"another certificate"));
+        assert!(pins.accepts("example.com", b"another certificate"));
+        // the hosts are normalized, so that another spelling of a host can't skip its pins
+        assert!(!pins.accepts("API.Example.com.", b"another certificate"));
+
+        let annotations = HashMap::from([(
+            TLS_PINS_ANNOTATION.to_string(),
+            format!("[2001:DB8::1]=sha256:{digest}"),
+        )]);
+        let pins = Pins::from_annotations(&annotations)?;
+        assert!(pins.accepts("2001:db8:0::1", cert));
+        assert!(!pins.accepts("2001:db8::1", b"another certificate"));
+
+        assert_eq!(Pins::from_annotations(&HashMap::new())?, Pins::default());
+        for invalid in ["api.example.com", "api.example.com=sha1:00", "a=sha256:zz"] {
+            let annotations = HashMap::from([(TLS_PINS_ANNOTATION.to_string(), invalid.into())]);
+            assert!(Pins::from_annotations(&annotations).is_err());
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
 use anyhow::Context;
 use clap::Parser;
 use oci_spec::image::{self as spec, Arch, ImageConfiguration};
-use oci_tar_builder::Builder;
+use oci_tar_builder::{push, Builder, Compression, OciConfig};
 use oci_wasm::WasmConfig;
 use sha256::{digest, try_digest};
 
@@ -27,18 +28,47 @@ pub async fn main() {
         return;
     }
 
-    if args.module.is_empty() && args.components.is_none() {
-        println!("Must supply module or components");
+    if args.module.is_empty() && args.components.is_none() && args.script.is_empty() {
+        println!("Must supply module, components or script");
         return;
     }
 
+    if args.index && (args.as_artifact || args.module.is_empty()) {
+        println!("An image index is built from modules, and not as an artifact");
+        return;
+    }
+
+    if args.as_artifact && !args.script.is_empty() {
+        println!("Mutually exclusive flags: as-artifact and script");
+        return;
+    }
+
+    let image = format!("{}/{}:{}", args.repo, args.name, args.tag);
+    let (push, insecure) = (args.push, args.insecure);
     if args.as_artifact {
-        generate_wasm_artifact(args, out_dir).await.unwrap();
+        generate_wasm_artifact(args, out_dir.clone()).await.unwrap();
+    } else if args.index {
+        generate_wasi_index(args, out_dir.clone()).unwrap();
     } else {
-        generate_wasi_image(args, out_dir).unwrap();
+        generate_wasi_image(args, out_dir.clone()).unwrap();
+    }
+
+    // the tar file is removed when the build fails
+    if push && out_dir.is_file() {
+        push_image(&out_dir, &image, insecure).await.unwrap();
     }
 }
 
+async fn push_image(out_dir: &Path, image: &str, insecure: bool) -> Result<(), anyhow::Error> {
+    println!("Pushing {} to {image}", out_dir.display());
+    let reference: oci_distribution::Reference = image.parse()?;
+    let auth = push::registry_auth(reference.registry())?;
+    let f = File::open(out_dir)?;
+    push::push(f, image, &auth, insecure).await?;
+    println!("Successfully pushed {image}");
+    Ok(())
+}
+
 async fn generate_wasm_artifact(args: Args, out_dir: PathBuf) -> Result<(), anyhow::Error> {
     println!("Generating wasm artifact");
 
@@ -71,6 +101,7 @@ async fn generate_wasm_artifact(args: Args, out_dir: PathBuf) -> Result<(), anyh
 
     let module_path = PathBuf::from(path);
     builder.add_layer_with_media_type(&module_path, oci_wasm::WASM_LAYER_MEDIA_TYPE.to_string());
+    add_referrers(&mut builder, &args.referrer)?;
 
     println!("Creating oci tar file {}", out_dir.clone().display());
     let f = File::create(out_dir.clone())?;
@@ -94,7 +125,18 @@ fn generate_wasi_image(args: Args, out_dir: PathBuf) -> Result<(), anyhow::Error
     let entry_point = args.name.clone() + ".wasm";
 
     let mut builder = Builder::<ImageConfiguration>::default();
+    if let Some(compression) = args.compression {
+        builder.set_compression(compression);
+    }
     let mut layer_digests = Vec::new();
+    let mut native_digests = Vec::new();
+    for native_path in args.native.iter() {
+        let native_path = PathBuf::from(native_path);
+        builder.add_layer_with_media_type(&native_path, spec::MediaType::ImageLayer.to_string());
+        native_digests
+            .push(try_digest(&native_path).context("failed to calculate digest for rootfs")?);
+    }
+
     for module_path in args.module.iter() {
         let module_path = PathBuf::from(module_path);
         builder.add_layer_with_media_type(
@@ -106,6 +148,15 @@ fn generate_wasi_image(args: Args, out_dir: PathBuf) -> Result<(), anyhow::Error
             .push(try_digest(&module_path).context("failed to calculate digest for module")?);
     }
 
+    for script_path in args.script.iter() {
+        let script_path = PathBuf::from(script_path);
+        let media_type = oci_tar_builder::script_media_type(&script_path)
+            .with_context(|| format!("unknown script type: {script_path:?}"))?;
+        builder.add_layer_with_media_type(&script_path, media_type.to_string());
+        layer_digests
+            .push(try_digest(&script_path).context("failed to calculate digest for script")?);
+    }
+
     for layer_config in args.layer.iter() {
         //split string on equals sign
         let layer_options: Vec<&str> = layer_config.split('=').collect();
@@ -145,30 +196,23 @@ fn generate_wasi_image(args: Args, out_dir: PathBuf) -> Result<(), anyhow::Error
         }
     }
 
-    // Need each config to be unique since we don't have layers to make them unique in the rootfs
-    // https://github.com/opencontainers/image-spec/pull/1173
-    let unique_id = digest(layer_digests.join(""));
-    let mut labels: HashMap<String, String> = HashMap::new();
-    labels.insert("containerd.runwasi.layers".to_string(), unique_id);
-
-    let config = spec::ConfigBuilder::default()
-        .entrypoint(vec![entry_point])
-        .labels(labels)
-        .build()?;
-
-    let conf = spec::ImageConfigurationBuilder::default()
-        .config(config)
-        .os("wasip1")
-        .architecture(Arch::Wasm)
-        .rootfs(spec::RootFsBuilder::default().diff_ids(vec![]).build()?)
-        .build()
-        .context("failed to build image configuration")?;
+    layer_digests.extend(native_digests.iter().cloned());
+    let mut conf = image_config(entry_point, &layer_digests, "wasip1")?;
+    if !native_digests.is_empty() {
+        dual_format_config(
+            &mut conf,
+            &native_digests,
+            args.native_entrypoint.clone(),
+            &args.native_arch,
+        )?;
+    }
 
     builder.add_config(
         conf,
         format!("{}/{}:{}", args.repo, args.name, args.tag),
         spec::MediaType::ImageConfig,
     );
+    add_referrers(&mut builder, &args.referrer)?;
 
     println!("Creating oci tar file {}", out_dir.clone().display());
     let f = File::create(out_dir.clone()).unwrap();
@@ -187,6 +231,155 @@ fn generate_wasi_image(args: Args, out_dir: PathBuf) -> Result<(), anyhow::Error
     Ok(())
 }
 
+fn add_referrers<C: OciConfig>(
+    builder: &mut Builder<C>,
+    referrers: &[String],
+) -> Result<(), anyhow::Error> {
+    for referrer in referrers.iter() {
+        let (artifact_type, path) = referrer
+            .split_once('=')
+            .with_context(|| format!("referrer {referrer:?} is not <artifact-type>=<path>"))?;
+        builder.add_referrer(&PathBuf::from(path), artifact_type.to_string());
+    }
+    Ok(())
+}
+
+fn generate_wasi_index(args: Args, out_dir: PathBuf) -> Result<(), anyhow::Error> {
+    println!("Generating wasm oci image index");
+    let entry_point = args.name.clone() + ".wasm";
+
+    // the scripts and layers are shared by the variants
+    let mut shared_layers = Vec::new();
+    for script_path in args.script.iter() {
+        let script_path = PathBuf::from(script_path);
+        let media_type = oci_tar_builder::script_media_type(&script_path)
+            .with_context(|| format!("unknown script type: {script_path:?}"))?;
+        shared_layers.push((script_path, media_type.to_string()));
+    }
+    for layer_config in args.layer.iter() {
+        let layer_options: Vec<&str> = layer_config.split('=').collect();
+        let layer_type = layer_options.first().unwrap();
+        let layer_path = PathBuf::from(layer_options.last().unwrap());
+        shared_layers.push((layer_path, layer_type.to_string()));
+    }
+
+    let mut builder = Builder::<ImageConfiguration>::default();
+    if let Some(compression) = args.compression {
+        builder.set_compression(compression);
+    }
+    for module in args.module.iter() {
+        // a module is either `<path>`, for all the engines, or `<engine>=<path>`
+        let (engine, module_path) = match module.split_once('=') {
+            Some((engine, path)) => (Some(engine), PathBuf::from(path)),
+            None => (None, PathBuf::from(module)),
+        };
+        let os = if is_component(&module_path)? {
+            "wasip2"
+        } else {
+            "wasip1"
+        };
+
+        let mut layers = vec![(
+            module_path,
+            oci_tar_builder::WASM_LAYER_MEDIA_TYPE.to_string(),
+        )];
+        layers.extend(shared_layers.iter().cloned());
+        let layer_digests = layers
+            .iter()
+            .map(|(path, _)| try_digest(path).context("failed to calculate digest for layer"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut annotations = HashMap::new();
+        if let Some(engine) = engine {
+            annotations.insert(
+                oci_tar_builder::ENGINE_ANNOTATION.to_string(),
+                engine.to_string(),
+            );
+        }
+        builder.add_variant(
+            image_config(entry_point.clone(), &layer_digests, os)?,
+            spec::MediaType::ImageConfig,
+            layers,
+            annotations,
+        );
+    }
+    add_referrers(&mut builder, &args.referrer)?;
+
+    println!("Creating oci tar file {}", out_dir.clone().display());
+    let f = File::create(out_dir.clone())?;
+    let name = format!("{}/{}:{}", args.repo, args.name, args.tag);
+    match builder.build_index(name, f) {
+        Ok(_) => println!("Successfully created oci tar file {}", out_dir.display()),
+        Err(e) => {
+            print!(
+                "Building oci tar file {} failed: {:?}",
+                out_dir.display(),
+                e
+            );
+            fs::remove_file(out_dir).unwrap_or(print!("Failed to remove temporary file"));
+        }
+    }
+
+    Ok(())
+}
+
+fn image_config(
+    entry_point: String,
+    layer_digests: &[String],
+    os: &str,
+) -> Result<ImageConfiguration, anyhow::Error> {
+    // Need each config to be unique since we don't have layers to make them unique in the rootfs
+    // https://github.com/opencontainers/image-spec/pull/1173
+    let unique_id = digest(layer_digests.join(""));
+    let mut labels: HashMap<String, String> = HashMap::new();
+    labels.insert("containerd.runwasi.layers".to_string(), unique_id);
+
+    let config = spec::ConfigBuilder::default()
+        .entrypoint(vec![entry_point])
+        .labels(labels)
+        .build()?;
+
+    spec::ImageConfigurationBuilder::default()
+        .config(config)
+        .os(os)
+        .architecture(Arch::Wasm)
+        .rootfs(spec::RootFsBuilder::default().diff_ids(vec![]).build()?)
+        .build()
+        .context("failed to build image configuration")
+}
+
+// Makes the config of a wasm image the one of a dual-format image, run by runc on the native
+// rootfs: containerd unpacks the rootfs layers listed in the `diff_ids`, and skips the wasm layers
+fn dual_format_config(
+    config: &mut ImageConfiguration,
+    native_digests: &[String],
+    entrypoint: Option<String>,
+    arch: &str,
+) -> Result<(), anyhow::Error> {
+    let diff_ids = native_digests
+        .iter()
+        .map(|digest| format!("sha256:{digest}"))
+        .collect::<Vec<_>>();
+    config.set_os(spec::Os::Linux);
+    config.set_architecture(Arch::from(arch));
+    config.set_rootfs(spec::RootFsBuilder::default().diff_ids(diff_ids).build()?);
+    if let Some(entrypoint) = entrypoint {
+        let mut container_config = config.config().clone().unwrap_or_default();
+        container_config.set_entrypoint(Some(vec![entrypoint]));
+        config.set_config(Some(container_config));
+    }
+    Ok(())
+}
+
+// components have the layer 1 in the header of the binary, modules the layer 0
+fn is_component(path: &PathBuf) -> Result<bool, anyhow::Error> {
+    let mut header = [0u8; 8];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .with_context(|| format!("failed to read wasm header of {path:?}"))?;
+    Ok(header[..4] == *b"\0asm" && header[6..] == [1, 0])
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -208,9 +401,70 @@ struct Args {
     #[arg(short, long)]
     layer: Vec<String>,
 
+    /// An artifact attached to the image as a referrer, as `<artifact-type>=<path>`
+    #[arg(long)]
+    referrer: Vec<String>,
+
+    /// A script run by the js or python shim, with the media type of its extension
+    #[arg(long)]
+    script: Vec<String>,
+
     #[arg(short, long)]
     components: Option<String>,
 
     #[arg(short, long)]
     as_artifact: bool,
+
+    /// Compress the layers of the image, the layers of wasm artifacts are not compressed
+    #[arg(long, value_enum, conflicts_with = "as_artifact")]
+    compression: Option<Compression>,
+
+    /// Push the image to the registry of its repo, with the credentials of the
+    /// OCI_REGISTRY_USERNAME and OCI_REGISTRY_PASSWORD environment variables or of the docker config
+    #[arg(long)]
+    push: bool,
+
+    /// Push the image over plain HTTP, e.g., to a local registry
+    #[arg(long, requires = "push")]
+    insecure: bool,
+
+    /// Build an image index with a variant per module, given as `[<engine>=]<path>`
+    #[arg(long)]
+    index: bool,
+
+    /// A tar archive of a native linux rootfs, making a dual-format image that runs under runc,
+    /// while the shim prefers its wasm layers
+    #[arg(long, conflicts_with_all = ["as_artifact", "index"])]
+    native: Vec<String>,
+
+    /// The entrypoint of the native rootfs, run by runc
+    #[arg(long, requires = "native")]
+    native_entrypoint: Option<String>,
+
+    /// The architecture of the native rootfs, e.g., `arm64`
+    #[arg(long, default_value = "amd64")]
+    native_arch: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dual_format_config() -> Result<(), anyhow::Error> {
+        let native = vec!["1234".to_string()];
+        let mut config = image_config("app.wasm".to_string(), &native, "wasip1")?;
+        dual_format_config(&mut config, &native, Some("/app".to_string()), "arm64")?;
+
+        // runc runs the entrypoint on the rootfs of the native layers
+        assert_eq!(config.os(), &spec::Os::Linux);
+        assert_eq!(config.architecture(), &Arch::ARM64);
+        assert_eq!(config.rootfs().diff_ids(), &["sha256:1234".to_string()]);
+        let entrypoint = config
+            .config()
+            .as_ref()
+            .and_then(|c| c.entrypoint().clone());
+        assert_eq!(entrypoint, Some(vec!["/app".to_string()]));
+        Ok(())
+    }
 }
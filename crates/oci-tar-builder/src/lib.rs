@@ -1,24 +1,39 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::metadata;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Error, Result};
 use indexmap::IndexMap;
 use log::{debug, warn};
 use oci_spec::image::{
-    DescriptorBuilder, ImageConfiguration, ImageIndexBuilder, ImageManifestBuilder, MediaType,
-    PlatformBuilder, SCHEMA_VERSION,
+    Descriptor, DescriptorBuilder, ImageConfiguration, ImageIndexBuilder, ImageManifestBuilder,
+    MediaType, PlatformBuilder, SCHEMA_VERSION,
 };
 use oci_wasm::{WasmConfig, WASM_ARCHITECTURE};
 use serde::Serialize;
 use sha256::{digest, try_digest};
+
+pub mod push;
+
 #[derive(Debug)]
 pub struct Builder<C: OciConfig> {
     configs: Vec<(C, String, MediaType)>,
     layers: Vec<(PathBuf, String)>,
+    referrers: Vec<(PathBuf, String)>,
+    variants: Vec<Variant<C>>,
+    compression: Option<Compression>,
 }
 
+/// A variant of an image index: its config, the media type of the config, its layers and the
+/// annotations of its manifest
+type Variant<C> = (
+    C,
+    MediaType,
+    Vec<(PathBuf, String)>,
+    HashMap<String, String>,
+);
+
 pub trait OciConfig {
     fn os(&self) -> String;
     fn architecture(&self) -> String;
@@ -67,6 +82,9 @@ impl Default for Builder<WasmConfig> {
         Self {
             configs: Vec::new(),
             layers: Vec::new(),
+            referrers: Vec::new(),
+            variants: Vec::new(),
+            compression: None,
         }
     }
 }
@@ -76,6 +94,9 @@ impl Default for Builder<ImageConfiguration> {
         Self {
             configs: Vec::new(),
             layers: Vec::new(),
+            referrers: Vec::new(),
+            variants: Vec::new(),
+            compression: None,
         }
     }
 }
@@ -107,6 +128,64 @@ struct DockerManifest {
 pub const WASM_LAYER_MEDIA_TYPE: &str =
     "application/vnd.bytecodealliance.wasm.component.layer.v0+wasm";
 
+/// Media type of the JavaScript layers run by the js shim
+pub const JS_LAYER_MEDIA_TYPE: &str = "application/javascript";
+
+/// Media type of the Python layers run by the python shim
+pub const PYTHON_LAYER_MEDIA_TYPE: &str = "text/x-python";
+
+/// Annotation of the referrers index with the digest of the manifest it refers to, as in the
+/// tag schema of registries without the referrers API, e.g., `sha256-<digest>`
+pub(crate) const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// Annotation of the manifests of an image index with the engine of the shim they are built for,
+/// e.g., with layers precompiled by the engine
+pub const ENGINE_ANNOTATION: &str = "runwasi.io/engine";
+
+/// Annotation with the name of the image, used by containerd when it is imported
+const IMAGE_NAME_ANNOTATION: &str = "io.containerd.image.name";
+
+/// Annotation with the file name of the artifact of a referrer
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// Compression of the layers, recorded as a `+gzip` or `+zstd` suffix of their media type, e.g.,
+/// `application/vnd.bytecodealliance.wasm.component.layer.v0+wasm+zstd`
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    fn compress(&self, content: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(content)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Zstd => Ok(zstd::encode_all(content, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+        }
+    }
+}
+
+/// Returns the media type of the layer of a script, from the extension of its file.
+pub fn script_media_type(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()? {
+        "js" | "mjs" => Some(JS_LAYER_MEDIA_TYPE),
+        "py" => Some(PYTHON_LAYER_MEDIA_TYPE),
+        _ => None,
+    }
+}
+
 impl<C: OciConfig> Builder<C> {
     pub fn add_config(&mut self, config: C, name: String, media_type: MediaType) -> &mut Self {
         self.configs.push((config, name, media_type));
@@ -123,6 +202,34 @@ impl<C: OciConfig> Builder<C> {
         self
     }
 
+    /// Attaches an artifact, e.g., an SBOM, a signature or a precompiled variant, to the image
+    /// manifests, as a referrer with a `subject` and the `artifactType` of the artifact.
+    pub fn add_referrer(&mut self, artifact: &PathBuf, artifact_type: String) -> &mut Self {
+        self.referrers.push((artifact.to_owned(), artifact_type));
+        self
+    }
+
+    /// Compresses the layers of the image with `compression`.
+    pub fn set_compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Adds a variant of the image to the image index built by `build_index`, e.g., a component
+    /// or a core module, or layers precompiled by an engine, with the annotations selecting it,
+    /// e.g., `ENGINE_ANNOTATION`, next to the platform of its config.
+    pub fn add_variant(
+        &mut self,
+        config: C,
+        media_type: MediaType,
+        layers: Vec<(PathBuf, String)>,
+        annotations: HashMap<String, String>,
+    ) -> &mut Self {
+        self.variants
+            .push((config, media_type, layers, annotations));
+        self
+    }
+
     pub fn build<W: Write>(&mut self, w: W) -> Result<(), Error> {
         let mut tb = tar::Builder::new(w);
         let mut manifests = Vec::new();
@@ -139,32 +246,11 @@ impl<C: OciConfig> Builder<C> {
             layers: Vec::new(),
         };
 
+        let mut blobs = HashSet::new();
         for layer in self.layers.iter() {
-            let dgst = try_digest(layer.0.as_path()).context("failed to digest layer")?;
-            let meta = metadata(layer.0.clone()).context("could not get layer metadata")?;
-            let oci_digest = "sha256:".to_owned() + &dgst;
-
-            let mut media_type = MediaType::ImageLayer;
-            if !layer.1.is_empty() {
-                media_type = MediaType::Other(layer.1.clone());
-            }
-            let desc = DescriptorBuilder::default()
-                // TODO: check file headers to determine mediatype? Could also just require it to be passed in on add_layer
-                .media_type(media_type)
-                .digest(&oci_digest)
-                .size(meta.len() as i64)
-                .build()
-                .context("failed to build descriptor")?;
-            layer_digests.insert(oci_digest, desc);
-
-            let mut th = tar::Header::new_gnu();
-            th.set_mode(0o444);
-            th.set_size(meta.len());
-            let p = "blobs/sha256/".to_owned() + &dgst;
-            th.set_path(&p).context("could not set path for layer")?;
-            th.set_cksum();
-            let f = std::fs::File::open(layer.0.clone()).context("could not open layer")?;
-            tb.append(&th, f)?;
+            let desc = append_layer(&mut tb, &layer.0, &layer.1, self.compression, &mut blobs)?;
+            let p = "blobs/sha256/".to_owned() + desc.digest().trim_start_matches("sha256:");
+            layer_digests.insert(desc.digest().clone(), desc);
 
             mfst.layers.push(p.to_string());
         }
@@ -206,13 +292,10 @@ impl<C: OciConfig> Builder<C> {
             let mut annotations = HashMap::new();
             if config.1.contains(':') {
                 let split = config.1.split(':').collect::<Vec<&str>>()[1];
-                annotations.insert(
-                    "org.opencontainers.image.ref.name".to_string(),
-                    split.to_string(),
-                );
+                annotations.insert(REF_NAME_ANNOTATION.to_string(), split.to_string());
             }
             mfst.repo_tags.push(config.1.clone());
-            annotations.insert("io.containerd.image.name".to_string(), config.1.clone());
+            annotations.insert(IMAGE_NAME_ANNOTATION.to_string(), config.1.clone());
 
             let manifest = ImageManifestBuilder::default()
                 .schema_version(SCHEMA_VERSION)
@@ -253,6 +336,86 @@ impl<C: OciConfig> Builder<C> {
                 .context("failed to build descriptor")?;
 
             manifests.push(desc);
+
+            if !self.referrers.is_empty() {
+                let subject = DescriptorBuilder::default()
+                    .media_type(MediaType::ImageManifest)
+                    .size(b.len() as i64)
+                    .digest("sha256:".to_owned() + &dgst)
+                    .build()
+                    .context("failed to build descriptor")?;
+                manifests.push(self.build_referrers(&mut tb, subject)?);
+            }
+        }
+
+        append_layout(&mut tb, manifests)?;
+
+        let mfst_data =
+            serde_json::to_string(&vec![&mfst]).context("failed to serialize manifest")?;
+        let mut th = tar::Header::new_gnu();
+        th.set_path("manifest.json")?;
+        th.set_mode(0o644);
+        th.set_size(mfst_data.as_bytes().len() as u64);
+        th.set_cksum();
+        tb.append(&th, mfst_data.as_bytes())?;
+
+        tb.finish()?;
+
+        Ok(())
+    }
+
+    /// Builds an image index with the variants of the image, each with its own manifest, named
+    /// `name`. The shims select the variant of their engine when they load the image.
+    pub fn build_index<W: Write>(&mut self, name: String, w: W) -> Result<(), Error> {
+        if self.variants.is_empty() {
+            anyhow::bail!("an image index needs at least one variant");
+        }
+
+        let mut tb = tar::Builder::new(w);
+        let mut blobs = HashSet::new();
+        let mut manifests = Vec::new();
+        for (config, media_type, layers, annotations) in self.variants.iter() {
+            let mut layer_descs = Vec::new();
+            for (path, layer_type) in layers.iter() {
+                let desc = append_layer(&mut tb, path, layer_type, self.compression, &mut blobs)?;
+                layer_descs.push(desc);
+            }
+
+            let b = config.to_string();
+            let dgst = append_blob(&mut tb, b.as_bytes()).context("could not append config")?;
+            let config_desc = DescriptorBuilder::default()
+                .media_type(media_type.clone())
+                .size(b.len() as i64)
+                .digest("sha256:".to_owned() + &dgst)
+                .build()
+                .context("failed to build descriptor")?;
+
+            let manifest = ImageManifestBuilder::default()
+                .schema_version(SCHEMA_VERSION)
+                .media_type(MediaType::ImageManifest)
+                .layers(layer_descs)
+                .config(config_desc)
+                .build()
+                .context("failed to build manifest")?
+                .to_string()
+                .context("failed to serialize manifest")?;
+            let dgst =
+                append_blob(&mut tb, manifest.as_bytes()).context("could not append manifest")?;
+
+            let platform = PlatformBuilder::default()
+                .os(config.os().as_str())
+                .architecture(config.architecture().as_str())
+                .build()
+                .context("failed to build platform")?;
+            let desc = DescriptorBuilder::default()
+                .media_type(MediaType::ImageManifest)
+                .size(manifest.len() as i64)
+                .platform(platform)
+                .annotations(annotations.clone())
+                .digest("sha256:".to_owned() + &dgst)
+                .build()
+                .context("failed to build descriptor")?;
+            manifests.push(desc);
         }
 
         let idx = ImageIndexBuilder::default()
@@ -260,43 +423,396 @@ impl<C: OciConfig> Builder<C> {
             .media_type(MediaType::ImageIndex)
             .manifests(manifests)
             .build()
-            .context("failed to build index")?;
+            .context("failed to build index")?
+            .to_string()
+            .context("failed to serialize index")?;
+        let dgst = append_blob(&mut tb, idx.as_bytes()).context("could not append index")?;
+
+        let mut annotations = HashMap::new();
+        if let Some((_, tag)) = name.rsplit_once(':') {
+            annotations.insert(REF_NAME_ANNOTATION.to_string(), tag.to_string());
+        }
+        annotations.insert(IMAGE_NAME_ANNOTATION.to_string(), name);
+        let subject = DescriptorBuilder::default()
+            .media_type(MediaType::ImageIndex)
+            .size(idx.len() as i64)
+            .digest("sha256:".to_owned() + &dgst)
+            .build()
+            .context("failed to build descriptor")?;
+        let mut desc = subject.clone();
+        desc.set_annotations(Some(annotations));
 
-        let s = idx.to_string().context("failed to serialize index")?;
-        let b = s.as_bytes();
+        let mut manifests = vec![desc];
+        if !self.referrers.is_empty() {
+            manifests.push(self.build_referrers(&mut tb, subject)?);
+        }
+        append_layout(&mut tb, manifests)?;
 
-        let mut th = tar::Header::new_gnu();
-        th.set_path("index.json")
-            .context("could not set path to index.json")?;
-        th.set_size(b.len() as u64);
-        th.set_mode(0o644);
-        th.set_cksum();
+        tb.finish()?;
 
-        tb.append(&th, b)?;
+        Ok(())
+    }
 
-        let layout = serde_json::to_string(&OciLayout::default())
-            .context("failed to serialize oci-layout")?;
-        let b = layout.as_bytes();
+    /// Writes the referrers of the manifest `subject`, and their index, returning the descriptor
+    /// of the index, annotated with the tag of the referrers tag schema.
+    fn build_referrers<W: Write>(
+        &self,
+        tb: &mut tar::Builder<W>,
+        subject: Descriptor,
+    ) -> Result<Descriptor, Error> {
+        // the artifacts have no config, their config is the empty descriptor
+        let empty = b"{}";
+        let empty_dgst = append_blob(tb, empty).context("could not append empty config")?;
+        let config = DescriptorBuilder::default()
+            .media_type(MediaType::EmptyJSON)
+            .size(empty.len() as i64)
+            .digest("sha256:".to_owned() + &empty_dgst)
+            .build()
+            .context("failed to build descriptor")?;
 
-        let mut th = tar::Header::new_gnu();
-        th.set_path("oci-layout")
-            .context("could not set path for oci-layout file")?;
-        th.set_size(b.len() as u64);
-        th.set_mode(0o644);
-        th.set_cksum();
-        tb.append(&th, b)?;
+        let mut referrers = Vec::new();
+        for (path, artifact_type) in self.referrers.iter() {
+            let artifact = std::fs::read(path).context("could not read referrer")?;
+            let dgst = append_blob(tb, &artifact).context("could not append referrer")?;
 
-        let mfst_data =
-            serde_json::to_string(&vec![&mfst]).context("failed to serialize manifest")?;
-        let mut th = tar::Header::new_gnu();
-        th.set_path("manifest.json")?;
-        th.set_mode(0o644);
-        th.set_size(mfst_data.as_bytes().len() as u64);
-        th.set_cksum();
-        tb.append(&th, mfst_data.as_bytes())?;
+            let mut annotations = HashMap::new();
+            if let Some(name) = path.file_name() {
+                annotations.insert(
+                    TITLE_ANNOTATION.to_string(),
+                    name.to_string_lossy().into_owned(),
+                );
+            }
 
-        tb.finish()?;
+            let layer = DescriptorBuilder::default()
+                .media_type(MediaType::Other(artifact_type.clone()))
+                .size(artifact.len() as i64)
+                .digest("sha256:".to_owned() + &dgst)
+                .annotations(annotations.clone())
+                .build()
+                .context("failed to build descriptor")?;
+
+            let manifest = ImageManifestBuilder::default()
+                .schema_version(SCHEMA_VERSION)
+                .media_type(MediaType::ImageManifest)
+                .artifact_type(MediaType::Other(artifact_type.clone()))
+                .config(config.clone())
+                .layers(vec![layer])
+                .subject(subject.clone())
+                .annotations(annotations.clone())
+                .build()
+                .context("failed to build referrer manifest")?
+                .to_string()
+                .context("failed to serialize referrer manifest")?;
+            let dgst = append_blob(tb, manifest.as_bytes())
+                .context("could not append referrer manifest")?;
+
+            let desc = DescriptorBuilder::default()
+                .media_type(MediaType::ImageManifest)
+                .artifact_type(MediaType::Other(artifact_type.clone()))
+                .size(manifest.len() as i64)
+                .digest("sha256:".to_owned() + &dgst)
+                .annotations(annotations)
+                .build()
+                .context("failed to build descriptor")?;
+            referrers.push(desc);
+        }
+
+        let idx = ImageIndexBuilder::default()
+            .schema_version(SCHEMA_VERSION)
+            .media_type(MediaType::ImageIndex)
+            .manifests(referrers)
+            .build()
+            .context("failed to build referrers index")?
+            .to_string()
+            .context("failed to serialize referrers index")?;
+        let dgst = append_blob(tb, idx.as_bytes()).context("could not append referrers index")?;
+
+        let tag = subject.digest().replacen(':', "-", 1);
+        DescriptorBuilder::default()
+            .media_type(MediaType::ImageIndex)
+            .size(idx.len() as i64)
+            .digest("sha256:".to_owned() + &dgst)
+            .annotations(HashMap::from([(REF_NAME_ANNOTATION.to_string(), tag)]))
+            .build()
+            .context("failed to build descriptor")
+    }
+}
+
+/// Appends the `index.json` with the `manifests` of the image, and the `oci-layout` file.
+fn append_layout<W: Write>(
+    tb: &mut tar::Builder<W>,
+    manifests: Vec<Descriptor>,
+) -> Result<(), Error> {
+    let idx = ImageIndexBuilder::default()
+        .schema_version(SCHEMA_VERSION)
+        .media_type(MediaType::ImageIndex)
+        .manifests(manifests)
+        .build()
+        .context("failed to build index")?;
+
+    let s = idx.to_string().context("failed to serialize index")?;
+    let b = s.as_bytes();
+
+    let mut th = tar::Header::new_gnu();
+    th.set_path("index.json")
+        .context("could not set path to index.json")?;
+    th.set_size(b.len() as u64);
+    th.set_mode(0o644);
+    th.set_cksum();
+
+    tb.append(&th, b)?;
+
+    let layout =
+        serde_json::to_string(&OciLayout::default()).context("failed to serialize oci-layout")?;
+    let b = layout.as_bytes();
+
+    let mut th = tar::Header::new_gnu();
+    th.set_path("oci-layout")
+        .context("could not set path for oci-layout file")?;
+    th.set_size(b.len() as u64);
+    th.set_mode(0o644);
+    th.set_cksum();
+    tb.append(&th, b)?;
+    Ok(())
+}
+
+/// Appends a layer to `blobs/sha256`, compressed with `compression`, returning its descriptor.
+/// The layers whose digest is in `blobs` were already appended and are not appended again.
+fn append_layer<W: Write>(
+    tb: &mut tar::Builder<W>,
+    path: &Path,
+    media_type: &str,
+    compression: Option<Compression>,
+    blobs: &mut HashSet<String>,
+) -> Result<Descriptor, Error> {
+    let (media_type, dgst, size) = match compression {
+        None => {
+            let dgst = try_digest(path).context("failed to digest layer")?;
+            let meta = metadata(path).context("could not get layer metadata")?;
+            if blobs.insert(dgst.clone()) {
+                let mut th = tar::Header::new_gnu();
+                th.set_mode(0o444);
+                th.set_size(meta.len());
+                th.set_path("blobs/sha256/".to_owned() + &dgst)
+                    .context("could not set path for layer")?;
+                th.set_cksum();
+                let f = std::fs::File::open(path).context("could not open layer")?;
+                tb.append(&th, f)?;
+            }
+            let media_type = match media_type {
+                "" => MediaType::ImageLayer,
+                media_type => MediaType::Other(media_type.to_string()),
+            };
+            (media_type, dgst, meta.len())
+        }
+        Some(compression) => {
+            let content = std::fs::read(path).context("could not read layer")?;
+            let content = compression
+                .compress(&content)
+                .context("failed to compress layer")?;
+            let dgst = digest(content.as_slice());
+            if blobs.insert(dgst.clone()) {
+                append_blob(tb, &content).context("could not append layer")?;
+            }
+            let media_type = match (media_type, compression) {
+                ("", Compression::Gzip) => MediaType::ImageLayerGzip,
+                ("", Compression::Zstd) => MediaType::ImageLayerZstd,
+                (media_type, _) => {
+                    MediaType::Other(format!("{media_type}+{}", compression.suffix()))
+                }
+            };
+            (media_type, dgst, content.len() as u64)
+        }
+    };
+
+    DescriptorBuilder::default()
+        // TODO: check file headers to determine mediatype? Could also just require it to be passed in on add_layer
+        .media_type(media_type)
+        .digest("sha256:".to_owned() + &dgst)
+        .size(size as i64)
+        .build()
+        .context("failed to build descriptor")
+}
+
+/// Appends a blob to `blobs/sha256`, returning its digest.
+fn append_blob<W: Write>(tb: &mut tar::Builder<W>, b: &[u8]) -> Result<String, Error> {
+    let dgst = digest(b);
+    let mut th = tar::Header::new_gnu();
+    th.set_mode(0o444);
+    th.set_size(b.len() as u64);
+    th.set_path("blobs/sha256/".to_owned() + &dgst)
+        .context("could not set path for blob")?;
+    th.set_cksum();
+    tb.append(&th, b)?;
+    Ok(dgst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use oci_spec::image::{ImageIndex, ImageManifest};
+
+    use super::*;
+
+    fn read_blobs(tar: &[u8]) -> Result<HashMap<String, Vec<u8>>> {
+        let mut blobs = HashMap::new();
+        for entry in tar::Archive::new(tar).entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            blobs.insert(path, content);
+        }
+        Ok(blobs)
+    }
+
+    #[test]
+    fn test_referrers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let module = dir.path().join("app.wasm");
+        std::fs::write(&module, b"\0asm")?;
+        let sbom = dir.path().join("sbom.spdx.json");
+        std::fs::write(&sbom, b"{}")?;
+
+        let mut tar = Vec::new();
+        Builder::<ImageConfiguration>::default()
+            .add_config(
+                ImageConfiguration::default(),
+                "ghcr.io/containerd/runwasi/app:latest".to_string(),
+                MediaType::ImageConfig,
+            )
+            .add_layer_with_media_type(&module, WASM_LAYER_MEDIA_TYPE.to_string())
+            .add_referrer(&sbom, "application/spdx+json".to_string())
+            .build(&mut tar)?;
+        let blobs = read_blobs(&tar)?;
+        let blob = |desc: &Descriptor| {
+            let dgst = desc.digest().trim_start_matches("sha256:");
+            blobs[&format!("blobs/sha256/{dgst}")].as_slice()
+        };
+
+        let index = ImageIndex::from_reader(blobs["index.json"].as_slice())?;
+        let [image, referrers] = index.manifests().as_slice() else {
+            panic!("expected the image and its referrers index");
+        };
+        assert_eq!(image.media_type(), &MediaType::ImageManifest);
+        assert_eq!(referrers.media_type(), &MediaType::ImageIndex);
+        let tag = referrers.annotations().as_ref().unwrap()[REF_NAME_ANNOTATION].clone();
+        assert_eq!(tag, image.digest().replace(':', "-"));
+
+        let referrers = ImageIndex::from_reader(blob(referrers))?;
+        let [referrer] = referrers.manifests().as_slice() else {
+            panic!("expected a single referrer");
+        };
+        let artifact_type = MediaType::Other("application/spdx+json".to_string());
+        assert_eq!(referrer.artifact_type().as_ref(), Some(&artifact_type));
+
+        let manifest = ImageManifest::from_reader(blob(referrer))?;
+        assert_eq!(manifest.artifact_type().as_ref(), Some(&artifact_type));
+        assert_eq!(manifest.config().media_type(), &MediaType::EmptyJSON);
+        assert_eq!(
+            manifest.subject().as_ref().unwrap().digest(),
+            image.digest()
+        );
+        assert_eq!(blob(&manifest.layers()[0]), b"{}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let module = dir.path().join("app.wasm");
+        std::fs::write(&module, b"\0asm\x01\0\0\0")?;
+        let precompiled = dir.path().join("app.cwasm");
+        std::fs::write(&precompiled, b"precompiled")?;
+
+        let variant = |layer: &PathBuf| vec![(layer.clone(), WASM_LAYER_MEDIA_TYPE.to_string())];
+        let mut tar = Vec::new();
+        Builder::<ImageConfiguration>::default()
+            .add_variant(
+                ImageConfiguration::default(),
+                MediaType::ImageConfig,
+                variant(&precompiled),
+                HashMap::from([(ENGINE_ANNOTATION.to_string(), "wasmtime".to_string())]),
+            )
+            .add_variant(
+                ImageConfiguration::default(),
+                MediaType::ImageConfig,
+                variant(&module),
+                HashMap::new(),
+            )
+            .build_index(
+                "ghcr.io/containerd/runwasi/app:latest".to_string(),
+                &mut tar,
+            )?;
+        let blobs = read_blobs(&tar)?;
+        let blob = |desc: &Descriptor| {
+            let dgst = desc.digest().trim_start_matches("sha256:");
+            blobs[&format!("blobs/sha256/{dgst}")].as_slice()
+        };
+
+        let index = ImageIndex::from_reader(blobs["index.json"].as_slice())?;
+        let [image] = index.manifests().as_slice() else {
+            panic!("expected a single image");
+        };
+        assert_eq!(image.media_type(), &MediaType::ImageIndex);
+        let annotations = image.annotations().as_ref().unwrap();
+        assert_eq!(annotations[REF_NAME_ANNOTATION], "latest");
+        assert_eq!(
+            annotations[IMAGE_NAME_ANNOTATION],
+            "ghcr.io/containerd/runwasi/app:latest"
+        );
+
+        let variants = ImageIndex::from_reader(blob(image))?;
+        let [wasmtime, any] = variants.manifests().as_slice() else {
+            panic!("expected two variants");
+        };
+        let engine = |desc: &Descriptor| {
+            desc.annotations()
+                .as_ref()
+                .and_then(|a| a.get(ENGINE_ANNOTATION).cloned())
+        };
+        assert_eq!(engine(wasmtime).as_deref(), Some("wasmtime"));
+        assert_eq!(engine(any), None);
+
+        let manifest = ImageManifest::from_reader(blob(wasmtime))?;
+        assert_eq!(blob(&manifest.layers()[0]), b"precompiled");
+        let manifest = ImageManifest::from_reader(blob(any))?;
+        assert_eq!(blob(&manifest.layers()[0]), b"\0asm\x01\0\0\0");
+        Ok(())
+    }
 
+    #[test]
+    fn test_compression() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let module = dir.path().join("app.wasm");
+        std::fs::write(&module, b"\0asm\x01\0\0\0")?;
+
+        let mut tar = Vec::new();
+        Builder::<ImageConfiguration>::default()
+            .add_config(
+                ImageConfiguration::default(),
+                "ghcr.io/containerd/runwasi/app:latest".to_string(),
+                MediaType::ImageConfig,
+            )
+            .add_layer_with_media_type(&module, WASM_LAYER_MEDIA_TYPE.to_string())
+            .set_compression(Compression::Zstd)
+            .build(&mut tar)?;
+        let blobs = read_blobs(&tar)?;
+
+        let index = ImageIndex::from_reader(blobs["index.json"].as_slice())?;
+        let dgst = index.manifests()[0].digest().trim_start_matches("sha256:");
+        let manifest =
+            ImageManifest::from_reader(blobs[&format!("blobs/sha256/{dgst}")].as_slice())?;
+        let layer = &manifest.layers()[0];
+        assert_eq!(
+            layer.media_type().to_string(),
+            format!("{WASM_LAYER_MEDIA_TYPE}+zstd")
+        );
+
+        let dgst = layer.digest().trim_start_matches("sha256:");
+        let content = zstd::decode_all(blobs[&format!("blobs/sha256/{dgst}")].as_slice())?;
+        assert_eq!(content, b"\0asm\x01\0\0\0");
         Ok(())
     }
 }
@@ -0,0 +1,270 @@
+//! Pushes the OCI tar archives built by the `Builder` to a registry, without importing them with
+//! `ctr` or `regctl` first.
+//!
+//! The blobs are uploaded in chunks, falling back to a single upload for the registries that
+//! don't support chunked uploads. The images are pushed before the indexes referencing them, and
+//! the manifests of `index.json` are tagged with their `org.opencontainers.image.ref.name`.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use base64::Engine as _;
+use oci_distribution::client::{ClientConfig, ClientProtocol};
+use oci_distribution::secrets::RegistryAuth;
+use oci_distribution::{Client, Reference, RegistryOperation};
+use oci_spec::image::{Descriptor, ImageIndex, ImageManifest, MediaType};
+
+use crate::REF_NAME_ANNOTATION;
+
+/// Environment variables with the credentials of the registry, e.g., a token as the password,
+/// taking precedence over the docker config
+pub const REGISTRY_USERNAME_ENV: &str = "OCI_REGISTRY_USERNAME";
+pub const REGISTRY_PASSWORD_ENV: &str = "OCI_REGISTRY_PASSWORD";
+
+/// The key of Docker Hub in the `auths` of the docker config
+const DOCKER_HUB_AUTH_KEY: &str = "https://index.docker.io/v1/";
+
+/// Returns the credentials of `registry`, from the environment or else from the `auths` of the
+/// docker config, e.g., `~/.docker/config.json`, falling back to anonymous access.
+pub fn registry_auth(registry: &str) -> Result<RegistryAuth> {
+    if let (Ok(username), Ok(password)) = (
+        std::env::var(REGISTRY_USERNAME_ENV),
+        std::env::var(REGISTRY_PASSWORD_ENV),
+    ) {
+        return Ok(RegistryAuth::Basic(username, password));
+    }
+
+    let Some(path) = docker_config_path() else {
+        return Ok(RegistryAuth::Anonymous);
+    };
+    let config = match std::fs::read(&path) {
+        Ok(config) => config,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(RegistryAuth::Anonymous)
+        }
+        Err(err) => return Err(err).with_context(|| format!("failed to read {path:?}")),
+    };
+    let config: DockerConfig =
+        serde_json::from_slice(&config).with_context(|| format!("failed to parse {path:?}"))?;
+    docker_config_auth(&config, registry)
+}
+
+#[derive(serde::Deserialize, Default)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+}
+
+#[derive(serde::Deserialize)]
+struct DockerAuth {
+    auth: Option<String>,
+}
+
+fn docker_config_path() -> Option<PathBuf> {
+    match std::env::var_os("DOCKER_CONFIG") {
+        Some(dir) => Some(PathBuf::from(dir).join("config.json")),
+        None => Some(PathBuf::from(std::env::var_os("HOME")?).join(".docker/config.json")),
+    }
+}
+
+fn docker_config_auth(config: &DockerConfig, registry: &str) -> Result<RegistryAuth> {
+    let key = match registry {
+        "docker.io" | "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB_AUTH_KEY,
+        registry => registry,
+    };
+    // the keys are either the registry, or the url of the registry
+    let auth = config.auths.iter().find_map(|(k, auth)| {
+        let host = k
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        let host = host.split('/').next().unwrap_or_default();
+        (k == key || host == key).then_some(auth)
+    });
+    let Some(auth) = auth.and_then(|auth| auth.auth.as_ref()) else {
+        log::debug!("no credentials for {registry} in the docker config");
+        return Ok(RegistryAuth::Anonymous);
+    };
+
+    let auth = base64::engine::general_purpose::STANDARD
+        .decode(auth)
+        .context("invalid auth in the docker config")?;
+    let auth = String::from_utf8(auth).context("invalid auth in the docker config")?;
+    let (username, password) = auth
+        .split_once(':')
+        .context("invalid auth in the docker config, expected <username>:<password>")?;
+    Ok(RegistryAuth::Basic(
+        username.to_string(),
+        password.to_string(),
+    ))
+}
+
+/// Pushes the OCI tar `archive` to the repository of `image`, e.g.,
+/// `ghcr.io/containerd/runwasi/wasi-demo-oci:latest`. The registry is accessed over plain HTTP
+/// when `insecure` is set, e.g., for a local registry.
+pub async fn push(
+    archive: impl Read,
+    image: &str,
+    auth: &RegistryAuth,
+    insecure: bool,
+) -> Result<()> {
+    let blobs = read_archive(archive)?;
+    let index = blobs
+        .get("index.json")
+        .context("the archive has no index.json")?;
+    let index = ImageIndex::from_reader(index.as_slice()).context("invalid index.json")?;
+
+    let image: Reference = image.parse().context("invalid image reference")?;
+    let protocol = if insecure {
+        ClientProtocol::Http
+    } else {
+        ClientProtocol::Https
+    };
+    let client = Client::new(ClientConfig {
+        protocol,
+        ..Default::default()
+    });
+    client
+        .auth(&image, auth, RegistryOperation::Push)
+        .await
+        .context("failed to authenticate to the registry")?;
+
+    let pusher = Pusher {
+        client: &client,
+        image: &image,
+        blobs: &blobs,
+    };
+    for descriptor in index.manifests() {
+        let tag = descriptor
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(REF_NAME_ANNOTATION));
+        let reference = match tag {
+            Some(tag) => Reference::with_tag(
+                image.registry().to_string(),
+                image.repository().to_string(),
+                tag.clone(),
+            ),
+            None => pusher.reference(descriptor),
+        };
+        pusher.push_manifest(descriptor, &reference).await?;
+        println!("Pushed {reference}");
+    }
+    Ok(())
+}
+
+struct Pusher<'a> {
+    client: &'a Client,
+    image: &'a Reference,
+    blobs: &'a HashMap<String, Vec<u8>>,
+}
+
+impl Pusher<'_> {
+    fn reference(&self, descriptor: &Descriptor) -> Reference {
+        Reference::with_digest(
+            self.image.registry().to_string(),
+            self.image.repository().to_string(),
+            descriptor.digest().clone(),
+        )
+    }
+
+    fn blob(&self, descriptor: &Descriptor) -> Result<&[u8]> {
+        let path = format!("blobs/{}", descriptor.digest().replacen(':', "/", 1));
+        self.blobs
+            .get(&path)
+            .map(Vec::as_slice)
+            .with_context(|| format!("the archive has no blob {}", descriptor.digest()))
+    }
+
+    async fn push_blob(&self, descriptor: &Descriptor) -> Result<()> {
+        let data = self.blob(descriptor)?;
+        self.client
+            .push_blob(self.image, data, descriptor.digest())
+            .await
+            .with_context(|| format!("failed to push blob {}", descriptor.digest()))?;
+        Ok(())
+    }
+
+    // pushes the content of a manifest, or of an index, before the manifest itself
+    async fn push_manifest(&self, descriptor: &Descriptor, reference: &Reference) -> Result<()> {
+        let data = self.blob(descriptor)?;
+        match descriptor.media_type() {
+            MediaType::ImageIndex => {
+                let index = ImageIndex::from_reader(data)?;
+                for manifest in index.manifests() {
+                    Box::pin(self.push_manifest(manifest, &self.reference(manifest))).await?;
+                }
+            }
+            _ => {
+                let manifest = ImageManifest::from_reader(data)?;
+                self.push_blob(manifest.config()).await?;
+                for layer in manifest.layers() {
+                    self.push_blob(layer).await?;
+                }
+            }
+        }
+
+        let content_type = descriptor
+            .media_type()
+            .to_string()
+            .parse()
+            .context("invalid media type")?;
+        self.client
+            .push_manifest_raw(reference, data.to_vec(), content_type)
+            .await
+            .with_context(|| format!("failed to push manifest {}", descriptor.digest()))?;
+        Ok(())
+    }
+}
+
+fn read_archive(archive: impl Read) -> Result<HashMap<String, Vec<u8>>> {
+    let mut blobs = HashMap::new();
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        blobs.insert(path, content);
+    }
+    Ok(blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_config_auth() -> Result<()> {
+        let config: DockerConfig = serde_json::from_str(
+            r#"{
+                "auths": {
+                    "https://index.docker.io/v1/": { "auth": "dXNlcjpodWI=" },
+                    "ghcr.io": { "auth": "dXNlcjp0b2tlbg==" },
+                    "https://localhost:5000": {}
+                }
+            }"#,
+        )?;
+
+        let basic = |username: &str, password: &str| {
+            RegistryAuth::Basic(username.to_string(), password.to_string())
+        };
+        assert_eq!(
+            docker_config_auth(&config, "ghcr.io")?,
+            basic("user", "token")
+        );
+        assert_eq!(
+            docker_config_auth(&config, "docker.io")?,
+            basic("user", "hub")
+        );
+        assert_eq!(
+            docker_config_auth(&config, "localhost:5000")?,
+            RegistryAuth::Anonymous
+        );
+        assert_eq!(
+            docker_config_auth(&config, "quay.io")?,
+            RegistryAuth::Anonymous
+        );
+        Ok(())
+    }
+}
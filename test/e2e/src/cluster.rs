@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// A cluster with the shims installed, with the `wasm` runtime handler of containerd
+pub enum Cluster {
+    /// A kind cluster, whose node image has the shims installed, e.g., built from
+    /// `test/k8s/Dockerfile`
+    Kind {
+        kind: PathBuf,
+        name: String,
+        node_image: String,
+    },
+    /// A k3s cluster on this host, whose containerd is configured by `test/k3s/bootstrap.sh`
+    K3s { k3s: PathBuf },
+}
+
+impl Cluster {
+    /// Creates the cluster for `runtime`, e.g., `wasmtime`, and imports the images of
+    /// `image_archive` in it.
+    pub fn create(&self, runtime: &str, image_archive: &str) -> Result<()> {
+        match self {
+            Self::Kind {
+                kind,
+                name,
+                node_image,
+            } => {
+                // a cluster left by a previous run would have the shims of its node image
+                run(Command::new(kind).args(["delete", "cluster", "--name", name]))?;
+                run(Command::new(kind)
+                    .args(["create", "cluster", "--name", name, "--image", node_image]))?;
+                run(Command::new(kind).args([
+                    "load",
+                    "image-archive",
+                    "--name",
+                    name,
+                    image_archive,
+                ]))
+            }
+            Self::K3s { .. } => {
+                run(Command::new("test/k3s/bootstrap.sh").args([runtime, image_archive]))
+            }
+        }
+    }
+
+    pub fn delete(&self) -> Result<()> {
+        match self {
+            Self::Kind { kind, name, .. } => {
+                run(Command::new(kind).args(["delete", "cluster", "--name", name]))
+            }
+            // the k3s installation is removed with `make bin/k3s/clean`
+            Self::K3s { .. } => Ok(()),
+        }
+    }
+
+    pub fn kubectl(&self) -> Command {
+        match self {
+            Self::Kind { name, .. } => {
+                let mut cmd = Command::new("kubectl");
+                cmd.arg(format!("--context=kind-{name}"));
+                cmd
+            }
+            Self::K3s { k3s } => {
+                let mut cmd = Command::new(k3s);
+                cmd.arg("kubectl");
+                cmd
+            }
+        }
+    }
+
+    // crictl on the node of the cluster, which talks to its containerd
+    pub fn crictl(&self) -> Command {
+        match self {
+            Self::Kind { name, .. } => {
+                let mut cmd = Command::new("docker");
+                cmd.args(["exec", &format!("{name}-control-plane"), "crictl"]);
+                cmd
+            }
+            Self::K3s { k3s } => {
+                let mut cmd = Command::new(k3s);
+                cmd.arg("crictl");
+                cmd
+            }
+        }
+    }
+
+    pub fn apply(&self, manifest: &str) -> Result<()> {
+        self.kubectl_stdin(["apply", "-f", "-"], manifest)
+    }
+
+    /// Deletes the resources of `manifest`, waiting for their pods to be deleted
+    pub fn delete_manifest(&self, manifest: &str) -> Result<()> {
+        self.kubectl_stdin(
+            [
+                "delete",
+                "--ignore-not-found",
+                "--cascade=foreground",
+                "-f",
+                "-",
+            ],
+            manifest,
+        )
+    }
+
+    fn kubectl_stdin<const N: usize>(&self, args: [&str; N], stdin: &str) -> Result<()> {
+        use std::io::Write;
+
+        let mut child = self
+            .kubectl()
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("failed to run kubectl")?;
+        child.stdin.take().unwrap().write_all(stdin.as_bytes())?;
+        if !child.wait()?.success() {
+            bail!("kubectl {} failed", args.join(" "));
+        }
+        Ok(())
+    }
+
+    /// Waits for `resource` to have `condition`, e.g., `condition=Complete`
+    pub fn wait(&self, resource: &str, condition: &str, timeout_secs: u64) -> Result<()> {
+        run(self.kubectl().args([
+            "wait",
+            resource,
+            &format!("--for={condition}"),
+            &format!("--timeout={timeout_secs}s"),
+        ]))
+    }
+
+    pub fn get_json(&self, args: &[&str]) -> Result<Value> {
+        let output = output(self.kubectl().args(["get", "-o", "json"]).args(args))?;
+        Ok(serde_json::from_str(&output)?)
+    }
+
+    pub fn logs(&self, args: &[&str]) -> Result<String> {
+        output(self.kubectl().arg("logs").args(args))
+    }
+}
+
+pub fn run(cmd: &mut Command) -> Result<()> {
+    let status = cmd
+        .status()
+        .with_context(|| format!("failed to run {cmd:?}"))?;
+    if !status.success() {
+        bail!("{cmd:?} failed with {status}");
+    }
+    Ok(())
+}
+
+pub fn output(cmd: &mut Command) -> Result<String> {
+    let output = cmd
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("failed to run {cmd:?}"))?;
+    if !output.status.success() {
+        bail!("{cmd:?} failed with {}", output.status);
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -0,0 +1,96 @@
+//! End-to-end tests of the shims on a Kubernetes cluster.
+//!
+//! The runner creates a kind or k3s cluster with the shim of a runtime as the `wasm` runtime
+//! handler of containerd, installs the `wasm` RuntimeClass, deploys sample workloads, a job, a
+//! deployment and a service, and checks their logs, exit codes and metrics, like the
+//! conformance tests of Kubernetes. See `make test/k8s`.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{Parser, ValueEnum};
+
+use crate::cluster::Cluster;
+
+mod cluster;
+mod suite;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Provider {
+    Kind,
+    K3s,
+}
+
+/// Runs the end-to-end tests of a shim on a kind or k3s cluster.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// The cluster to run the tests on
+    #[arg(short, long, value_enum, default_value_t = Provider::Kind)]
+    provider: Provider,
+
+    /// The runtime of the shim, e.g., `wasmtime`
+    #[arg(short, long, default_value = "wasmtime")]
+    runtime: String,
+
+    /// The archive of the images of the workloads, e.g., `dist/img.tar`
+    #[arg(short, long, default_value = "dist/img.tar")]
+    image_archive: String,
+
+    /// The node image of the kind cluster, with the shims installed
+    #[arg(long, required_if_eq("provider", "kind"))]
+    node_image: Option<String>,
+
+    /// The name of the kind cluster
+    #[arg(long, default_value = "containerd-wasm")]
+    cluster_name: String,
+
+    /// The kind binary
+    #[arg(long, default_value = "bin/kind")]
+    kind: PathBuf,
+
+    /// The k3s binary
+    #[arg(long, default_value = "bin/k3s")]
+    k3s: PathBuf,
+
+    /// Runs the checks whose name contains this filter only
+    #[arg(short, long)]
+    filter: Option<String>,
+
+    /// Runs the checks on the existing cluster, rather than creating one
+    #[arg(long)]
+    no_create: bool,
+
+    /// Keeps the cluster after the checks, to debug them
+    #[arg(long)]
+    keep: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let cluster = match args.provider {
+        Provider::Kind => Cluster::Kind {
+            kind: args.kind,
+            name: args.cluster_name,
+            node_image: args.node_image.unwrap_or_default(),
+        },
+        Provider::K3s => Cluster::K3s { k3s: args.k3s },
+    };
+
+    if !args.no_create {
+        cluster.create(&args.runtime, &args.image_archive)?;
+    }
+
+    let failed = suite::run(&cluster, args.filter.as_deref());
+
+    if !args.keep {
+        cluster.delete()?;
+    }
+
+    if !failed.is_empty() {
+        bail!("{} checks failed: {}", failed.len(), failed.join(", "));
+    }
+    eprintln!("all checks passed with {}", args.runtime);
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use serde_json::Value;
+
+use crate::cluster::{output, Cluster};
+
+const RUNTIME_CLASS: &str = include_str!("../manifests/runtimeclass.yaml");
+const JOB: &str = include_str!("../manifests/job.yaml");
+const DEPLOYMENT: &str = include_str!("../manifests/deployment.yaml");
+const CLIENT: &str = include_str!("../manifests/client.yaml");
+
+// The number of replicas of `manifests/deployment.yaml`
+const REPLICAS: usize = 2;
+
+/// A check of the suite, run against the cluster
+pub struct Check {
+    pub name: &'static str,
+    run: fn(&Cluster) -> Result<()>,
+}
+
+/// The checks of the suite, in the order they run in, as each can depend on the previous ones
+pub fn checks() -> Vec<Check> {
+    vec![
+        Check {
+            name: "runtime class is installed",
+            run: runtime_class,
+        },
+        Check {
+            name: "job completes with its output in the logs",
+            run: job_completes,
+        },
+        Check {
+            name: "job fails with the exit code of the module",
+            run: job_exit_code,
+        },
+        Check {
+            name: "deployment becomes available and stays available",
+            run: deployment_available,
+        },
+        Check {
+            name: "service routes to the pods of the deployment",
+            run: service_routes,
+        },
+        Check {
+            name: "metrics of the wasm containers are reported",
+            run: container_metrics,
+        },
+        Check {
+            name: "deployment and jobs are deleted",
+            run: workloads_deleted,
+        },
+    ]
+}
+
+/// Runs the checks whose name contains `filter`, and returns the names of the failed ones
+pub fn run(cluster: &Cluster, filter: Option<&str>) -> Vec<&'static str> {
+    let mut failed = vec![];
+    for check in checks() {
+        if filter.is_some_and(|filter| !check.name.contains(filter)) {
+            continue;
+        }
+        eprintln!("[RUN] {}", check.name);
+        match (check.run)(cluster) {
+            Ok(()) => eprintln!("[PASS] {}", check.name),
+            Err(err) => {
+                eprintln!("[FAIL] {}: {err:#}", check.name);
+                failed.push(check.name);
+            }
+        }
+    }
+    failed
+}
+
+fn runtime_class(cluster: &Cluster) -> Result<()> {
+    cluster.apply(RUNTIME_CLASS)?;
+    let class = cluster.get_json(&["runtimeclass", "wasm"])?;
+    ensure!(
+        class["handler"] == "wasm",
+        "unexpected handler {}",
+        class["handler"]
+    );
+    Ok(())
+}
+
+fn job_completes(cluster: &Cluster) -> Result<()> {
+    cluster.apply(JOB)?;
+    cluster.wait("job/wasi-echo", "condition=Complete", 90)?;
+
+    let logs = cluster.logs(&["job/wasi-echo"])?;
+    ensure!(
+        logs.contains("hello from runwasi"),
+        "unexpected logs {logs:?}"
+    );
+
+    let pods = cluster.get_json(&["pods", "-l", "job-name=wasi-echo"])?;
+    ensure!(
+        exit_codes(&pods, "demo") == [0],
+        "unexpected exit codes {:?}",
+        exit_codes(&pods, "demo")
+    );
+    Ok(())
+}
+
+fn job_exit_code(cluster: &Cluster) -> Result<()> {
+    cluster.wait("job/wasi-exit", "condition=Failed", 90)?;
+
+    let pods = cluster.get_json(&["pods", "-l", "job-name=wasi-exit"])?;
+    ensure!(
+        exit_codes(&pods, "demo") == [3],
+        "unexpected exit codes {:?}",
+        exit_codes(&pods, "demo")
+    );
+    Ok(())
+}
+
+fn deployment_available(cluster: &Cluster) -> Result<()> {
+    cluster.apply(DEPLOYMENT)?;
+    cluster.wait("deployment/wasi-demo", "condition=Available=True", 90)?;
+
+    // verify that we are still running after some time
+    std::thread::sleep(Duration::from_secs(5));
+    cluster.wait("deployment/wasi-demo", "condition=Available=True", 5)?;
+
+    let pods = cluster.get_json(&["pods", "-l", "app=wasi-demo"])?;
+    let restarts = restart_counts(&pods, "demo");
+    ensure!(
+        restarts.len() == REPLICAS && restarts.iter().all(|n| *n == 0),
+        "unexpected restarts {restarts:?}"
+    );
+
+    let logs = cluster.logs(&["deployment/wasi-demo", "-c", "demo"])?;
+    ensure!(
+        logs.contains("This is a song that never ends."),
+        "unexpected logs {logs:?}"
+    );
+    Ok(())
+}
+
+fn service_routes(cluster: &Cluster) -> Result<()> {
+    let endpoints = cluster.get_json(&["endpoints", "wasi-demo"])?;
+    ensure!(
+        endpoint_addresses(&endpoints) == REPLICAS,
+        "expected {REPLICAS} endpoints, got {}",
+        endpoint_addresses(&endpoints)
+    );
+
+    cluster.delete_manifest(CLIENT)?;
+    cluster.apply(CLIENT)?;
+    cluster.wait("job/wasi-demo-client", "condition=Complete", 90)?;
+    let logs = cluster.logs(&["job/wasi-demo-client"])?;
+    ensure!(logs.contains("nginx"), "unexpected response {logs:?}");
+    Ok(())
+}
+
+fn container_metrics(cluster: &Cluster) -> Result<()> {
+    let stats = output(cluster.crictl().args([
+        "stats",
+        "-o",
+        "json",
+        "--label",
+        "io.kubernetes.container.name=demo",
+    ]))?;
+    let stats: Value = serde_json::from_str(&stats)?;
+    let memory = working_set_bytes(&stats).context("no stats of the wasm containers")?;
+    ensure!(
+        memory.len() >= REPLICAS,
+        "expected the stats of {REPLICAS} containers, got {}",
+        memory.len()
+    );
+    if let Some(zero) = memory.iter().position(|bytes| *bytes == 0) {
+        bail!("no memory reported for container {zero}");
+    }
+    Ok(())
+}
+
+fn workloads_deleted(cluster: &Cluster) -> Result<()> {
+    cluster.delete_manifest(CLIENT)?;
+    cluster.delete_manifest(JOB)?;
+    cluster.delete_manifest(DEPLOYMENT)?;
+
+    let pods = cluster.get_json(&["pods"])?;
+    let left = pods["items"].as_array().map_or(0, Vec::len);
+    ensure!(left == 0, "{left} pods left after the deletion");
+    Ok(())
+}
+
+// The exit codes of the terminated `container` of `pods`
+fn exit_codes(pods: &Value, container: &str) -> Vec<i64> {
+    container_statuses(pods, container)
+        .filter_map(|status| status["state"]["terminated"]["exitCode"].as_i64())
+        .collect()
+}
+
+fn restart_counts(pods: &Value, container: &str) -> Vec<i64> {
+    container_statuses(pods, container)
+        .filter_map(|status| status["restartCount"].as_i64())
+        .collect()
+}
+
+fn container_statuses<'a>(
+    pods: &'a Value,
+    container: &'a str,
+) -> impl Iterator<Item = &'a Value> + 'a {
+    pods["items"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|pod| pod["status"]["containerStatuses"].as_array())
+        .flatten()
+        .filter(move |status| status["name"] == container)
+}
+
+fn endpoint_addresses(endpoints: &Value) -> usize {
+    endpoints["subsets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|subset| subset["addresses"].as_array())
+        .map(Vec::len)
+        .sum()
+}
+
+// The working set of the containers of the output of `crictl stats -o json`, whose integers
+// are strings
+fn working_set_bytes(stats: &Value) -> Option<Vec<u64>> {
+    stats["stats"]
+        .as_array()?
+        .iter()
+        .map(|stats| {
+            let value = &stats["memory"]["workingSetBytes"]["value"];
+            value
+                .as_u64()
+                .or_else(|| value.as_str().and_then(|value| value.parse().ok()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_container_statuses() {
+        let pods = json!({
+            "items": [
+                {"status": {"containerStatuses": [
+                    {"name": "demo", "restartCount": 0, "state": {"terminated": {"exitCode": 3}}},
+                    {"name": "nginx", "restartCount": 1, "state": {"running": {}}},
+                ]}},
+                {"status": {"containerStatuses": [
+                    {"name": "demo", "restartCount": 2, "state": {"running": {}}},
+                ]}},
+                {"status": {}},
+            ]
+        });
+        assert_eq!(exit_codes(&pods, "demo"), [3]);
+        assert_eq!(exit_codes(&pods, "nginx"), Vec::<i64>::new());
+        assert_eq!(restart_counts(&pods, "demo"), [0, 2]);
+        assert_eq!(exit_codes(&json!({}), "demo"), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn test_endpoint_addresses() {
+        let endpoints = json!({
+            "subsets": [
+                {"addresses": [{"ip": "10.244.0.5"}, {"ip": "10.244.0.6"}]},
+                {"notReadyAddresses": [{"ip": "10.244.0.7"}]},
+            ]
+        });
+        assert_eq!(endpoint_addresses(&endpoints), 2);
+        assert_eq!(endpoint_addresses(&json!({})), 0);
+    }
+
+    #[test]
+    fn test_working_set_bytes() {
+        let stats = json!({
+            "stats": [
+                {"memory": {"workingSetBytes": {"value": "1048576"}}},
+                {"memory": {"workingSetBytes": {"value": 2048}}},
+            ]
+        });
+        assert_eq!(working_set_bytes(&stats), Some(vec![1048576, 2048]));
+        assert_eq!(working_set_bytes(&json!({"stats": [{"memory": {}}]})), None);
+        assert_eq!(working_set_bytes(&json!({})), None);
+    }
+}